@@ -0,0 +1,86 @@
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use payments_engine::{AsyncPaymentsEngine, PaymentsEngine, Transaction, TransactionType, amount};
+
+// clients submitting at once, each from a task of its own, as concurrent server requests do
+const CLIENTS: u32 = 64;
+const DEPOSITS: u32 = 1_000;
+const WORKER_THREADS: usize = 8;
+
+#[derive(Clone, Copy)]
+enum Mode {
+    // one engine on one task, as the servers run by default
+    SingleTask,
+    // --actors: an engine per client on a task of its own
+    Actors,
+    // --sharded: an engine per client in a concurrent map
+    Sharded,
+}
+
+impl Mode {
+    fn name(self) -> &'static str {
+        match self {
+            Mode::SingleTask => "single-task",
+            Mode::Actors => "actors",
+            Mode::Sharded => "sharded",
+        }
+    }
+
+    fn spawn(self) -> AsyncPaymentsEngine {
+        match self {
+            Mode::SingleTask => AsyncPaymentsEngine::spawn(PaymentsEngine::new()),
+            Mode::Actors => AsyncPaymentsEngine::spawn_per_account(PaymentsEngine::new).unwrap(),
+            Mode::Sharded => AsyncPaymentsEngine::spawn_sharded(PaymentsEngine::new).unwrap(),
+        }
+    }
+}
+
+const MODES: [Mode; 3] = [Mode::SingleTask, Mode::Actors, Mode::Sharded];
+
+// every client deposits `DEPOSITS` times at once, each waiting for one to be applied before
+// sending the next
+async fn run(engine: AsyncPaymentsEngine) {
+    let tasks: Vec<_> = (0..CLIENTS)
+        .map(|client| {
+            let engine = engine.clone();
+            tokio::spawn(async move {
+                for i in 0..DEPOSITS {
+                    let tx = Transaction {
+                        tx_type: TransactionType::Deposit,
+                        account_id: client as u16,
+                        tx_id: client * DEPOSITS + i,
+                        amount: Some(amount!(1)),
+                        currency: None,
+                        timestamp: None,
+                        reason: None,
+                        effective: None,
+                    };
+                    engine.process(black_box(tx)).await.unwrap();
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+}
+
+fn contention(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(WORKER_THREADS)
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("contention");
+    group.throughput(Throughput::Elements((CLIENTS * DEPOSITS).into()));
+    group.sample_size(20);
+    for mode in MODES {
+        group.bench_function(BenchmarkId::from_parameter(mode.name()), |b| {
+            b.iter(|| runtime.block_on(async { run(mode.spawn()).await }))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, contention);
+criterion_main!(benches);
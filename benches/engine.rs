@@ -0,0 +1,203 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "mmap")]
+use std::time::Duration;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group};
+use payments_engine::{PaymentsEngine, Transaction};
+
+const ROWS: u32 = 10_000;
+const CLIENTS: u32 = 1_000;
+// rows of the file the two read paths go over, large enough for reading it to show in a run
+#[cfg(feature = "mmap")]
+const FILE_ROWS: u32 = 500_000;
+
+// counts live and peak heap bytes, so every workload can report what it allocates on top of the
+// timings--peak memory grows with the stored transactions, which a pure throughput bench misses
+struct CountingAllocator;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(live, Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[derive(Clone, Copy)]
+enum Workload {
+    // mostly deposits, with the odd withdrawal
+    DepositHeavy,
+    // every deposit is disputed, resolved and disputed again
+    DisputeHeavy,
+    // deposits, withdrawals, disputes and their outcomes interleaved
+    Mixed,
+}
+
+impl Workload {
+    fn name(self) -> &'static str {
+        match self {
+            Workload::DepositHeavy => "deposit-heavy",
+            Workload::DisputeHeavy => "dispute-heavy",
+            Workload::Mixed => "mixed",
+        }
+    }
+
+    // rows come in cycles that each belong to one client, so disputes find their client's tx
+    fn cycle(self) -> u32 {
+        match self {
+            Workload::DepositHeavy => 1,
+            Workload::DisputeHeavy => 4,
+            Workload::Mixed => 6,
+        }
+    }
+
+    // a deterministic csv input of `rows` rows spread over `CLIENTS` clients
+    fn input(self, rows: u32) -> String {
+        let mut csv = String::from("type,client,tx,amount\n");
+        for row in 0..rows {
+            let client = row / self.cycle() % CLIENTS + 1;
+            let tx = row + 1;
+            let line = match self {
+                Workload::DepositHeavy if row % 10 == 9 => {
+                    format!("withdrawal,{},{},1.5", client, tx)
+                }
+                Workload::DepositHeavy => format!("deposit,{},{},{}.25", client, tx, row % 97),
+                Workload::DisputeHeavy => match row % 4 {
+                    0 => format!("deposit,{},{},{}.5", client, tx, row % 89 + 1),
+                    1 => format!("dispute,{},{},", client, tx - 1),
+                    2 => format!("resolve,{},{},", client, tx - 2),
+                    // disputing again once resolved is rejected
+                    _ => format!("dispute,{},{},", client, tx - 3),
+                },
+                Workload::Mixed => match row % 6 {
+                    0 | 1 => format!("deposit,{},{},{}.75", client, tx, row % 53 + 10),
+                    2 => format!("withdrawal,{},{},2.5", client, tx),
+                    3 => format!("dispute,{},{},", client, tx - 3),
+                    // the occasional chargeback, locking the client
+                    4 if row / 6 % 10 == 0 => format!("chargeback,{},{},", client, tx - 4),
+                    4 => format!("resolve,{},{},", client, tx - 4),
+                    _ => format!("deposit,{},{},1.0001", client, tx),
+                },
+            };
+            csv.push_str(&line);
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+const WORKLOADS: [Workload; 3] = [
+    Workload::DepositHeavy,
+    Workload::DisputeHeavy,
+    Workload::Mixed,
+];
+
+// the CLI's hot path: deserialize each csv row and apply it, skipping failures
+fn run(input: impl Read) -> PaymentsEngine {
+    let mut engine = PaymentsEngine::new();
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input);
+    for tx in rdr.deserialize::<Transaction>() {
+        let _ = engine.process_tx(&tx.unwrap());
+    }
+    engine
+}
+
+fn throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process");
+    group.throughput(Throughput::Elements(ROWS.into()));
+    for workload in WORKLOADS {
+        let input = workload.input(ROWS);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(workload.name()),
+            &input,
+            |b, input| b.iter(|| run(black_box(input.as_bytes()))),
+        );
+    }
+    group.finish();
+}
+
+// the same mixed file read as `--mmap` and the default buffered reads do, parsed and applied the
+// same way, so the cost of each read path can be compared over a whole run
+#[cfg(feature = "mmap")]
+fn read_paths(c: &mut Criterion) {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    let path = std::env::temp_dir().join(format!("payments-bench-{}.csv", std::process::id()));
+    std::fs::write(&path, Workload::Mixed.input(FILE_ROWS)).unwrap();
+
+    let mut group = c.benchmark_group("read");
+    group.throughput(Throughput::Elements(FILE_ROWS.into()));
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(15));
+    group.bench_function("buffered", |b| {
+        b.iter(|| run(BufReader::new(File::open(&path).unwrap())))
+    });
+    group.bench_function("mmap", |b| {
+        b.iter(|| {
+            let file = File::open(&path).unwrap();
+            // SAFETY: nothing else touches the file while the bench reads it
+            let mapped = unsafe { memmap2::Mmap::map(&file).unwrap() };
+            #[cfg(unix)]
+            mapped.advise(memmap2::Advice::Sequential).unwrap();
+            run(&mapped[..])
+        })
+    });
+    group.finish();
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+// one untimed run per workload, with the heap it needed on top of the generated input
+fn report_memory() {
+    for workload in WORKLOADS {
+        let input = workload.input(ROWS);
+        let baseline = LIVE.load(Ordering::Relaxed);
+        PEAK.store(baseline, Ordering::Relaxed);
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+
+        let engine = run(input.as_bytes());
+
+        println!(
+            "memory {}: peak {} bytes, retained {} bytes, {} allocations",
+            workload.name(),
+            PEAK.load(Ordering::Relaxed) - baseline,
+            LIVE.load(Ordering::Relaxed) - baseline,
+            ALLOCATIONS.load(Ordering::Relaxed) - allocations
+        );
+        drop(engine);
+    }
+}
+
+criterion_group!(benches, throughput);
+#[cfg(feature = "mmap")]
+criterion_group!(read, read_paths);
+
+fn main() {
+    report_memory();
+    benches();
+    #[cfg(feature = "mmap")]
+    read();
+    Criterion::default().configure_from_args().final_summary();
+}
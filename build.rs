@@ -0,0 +1,16 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // only the gRPC service needs generated code--default builds skip protoc entirely
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/payments.proto");
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("vendored protoc is available");
+        // SAFETY: build scripts are single-threaded
+        unsafe { std::env::set_var("PROTOC", protoc) };
+        tonic_prost_build::configure()
+            .build_client(false)
+            .compile_protos(&["proto/payments.proto"], &["proto"])
+            .expect("failed to compile proto/payments.proto");
+    }
+}
@@ -0,0 +1,46 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use payments_engine::{PaymentsEngine, PrecisionPolicy, RoundingMode, RowParser};
+
+// arbitrary bytes through the CLI's csv reader setup and row parser (its byte-level fast path
+// falling back to serde, then the precision policy), with the first byte choosing the policy:
+// nothing may panic, and every balance must stay consistent whatever was rejected
+fuzz_target!(|data: &[u8]| {
+    let Some((&policy, data)) = data.split_first() else {
+        return;
+    };
+    let precision = match policy % 3 {
+        0 => PrecisionPolicy::Reject,
+        1 => PrecisionPolicy::Truncate,
+        _ => PrecisionPolicy::Round(RoundingMode::HalfEven),
+    };
+    let mut engine = PaymentsEngine::new();
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(data);
+    let Ok(headers) = rdr.headers().cloned() else {
+        return;
+    };
+    let parser = RowParser::new(&headers, precision);
+
+    let mut record = csv::ByteRecord::new();
+    loop {
+        match rdr.read_byte_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            // skipped like the CLI does
+            Err(_) => continue,
+        }
+        if let Ok(tx) = parser.parse(&record) {
+            let _ = engine.process_tx(&tx);
+        }
+    }
+
+    for account in engine.accounts() {
+        for balance in account.balances.values() {
+            assert_eq!(balance.total, balance.available + balance.held);
+            assert!(!balance.held.is_sign_negative());
+        }
+    }
+});
@@ -1,141 +1,670 @@
-use rust_decimal::Decimal;
-use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::amount::Amount;
+use serde::{Deserialize, Serialize};
 
 use crate::error::{Error, Result};
+use crate::ledger::{LedgerAccount, Posting, Postings};
 
-#[derive(Debug, Deserialize)]
+/// A single client's balances, one [`Balance`] per currency, and its [`AccountStatus`], which
+/// applies across all currencies.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Account {
     pub id: u16,
-    pub available: Decimal,
-    pub held: Decimal,
-    pub total: Decimal,
-    pub locked: bool,
+    pub balances: BTreeMap<String, Balance>,
+    pub status: AccountStatus,
+    /// Why the account was frozen or locked, while it is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_reason: Option<String>,
+}
+
+/// What an account currently accepts, ordered from least to most restrictive.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    /// Accepts every transaction.
+    #[default]
+    Active,
+    /// Accepts everything except withdrawals, e.g. during a compliance review.
+    Frozen,
+    /// Set by a chargeback; accepts nothing until unlocked.
+    Locked,
+    /// Accepts nothing, ever again.
+    Closed,
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Frozen => "frozen",
+            AccountStatus::Locked => "locked",
+            AccountStatus::Closed => "closed",
+        };
+
+        f.write_str(name)
+    }
+}
+
+// what a transaction does to an account, which decides whether its status permits it
+#[derive(Clone, Copy)]
+enum Operation {
+    // deposits and clears bring funds in
+    Inbound,
+    // withdrawals and refunds take funds out
+    Outbound,
+    // disputes, resolves, chargebacks, captures and voids are driven by the card network, not the
+    // client
+    Dispute,
+    // adjustments and reversals are corrections made by operations staff
+    Correction,
+}
+
+/// The funds a client holds in a single currency.
+///
+/// `available` and `held` are the client's accounts in the double-entry [`Ledger`](crate::Ledger):
+/// the operations on [`Account`] change them only through balanced [`Posting`]s, which they
+/// return. `total` is always `available + held`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Balance {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+}
+
+/// Bounds on the amount of a single deposit, provisional deposit or withdrawal, to keep
+/// obviously bogus rows from reaching the balances. Both are inclusive; unset bounds don't apply.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AmountLimits {
+    /// E.g. the smallest unit, `0.0001`, to refuse zero amounts.
+    pub min: Option<Amount>,
+    pub max: Option<Amount>,
+}
+
+impl AmountLimits {
+    /// Fails with [`Error::AmountBelowMinimum`] or [`Error::AmountAboveMaximum`] if `amount` is
+    /// out of bounds.
+    pub fn validate(&self, amount: Amount) -> Result<()> {
+        if self.min.is_some_and(|min| amount < min) {
+            return Err(Error::AmountBelowMinimum(
+                "Amount is below the configured minimum.",
+            ));
+        }
+        if self.max.is_some_and(|max| amount > max) {
+            return Err(Error::AmountAboveMaximum(
+                "Amount is above the configured maximum.",
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl Account {
     pub fn new(id: u16) -> Self {
         Self {
             id,
-            available: Decimal::ZERO,
-            held: Decimal::ZERO,
-            total: Decimal::ZERO,
-            locked: false,
+            balances: BTreeMap::new(),
+            status: AccountStatus::Active,
+            status_reason: None,
         }
     }
 
-    pub fn deposit(&mut self, amount: Decimal) -> Result<()> {
-        self.check_lock()?;
-        self.validate_deposit_amount(amount)?;
+    /// Whether a chargeback has locked the account.
+    pub fn is_locked(&self) -> bool {
+        self.status == AccountStatus::Locked
+    }
 
-        let new_available = self
-            .available
-            .checked_add(amount)
-            .ok_or_else(|| Error::TransactionError("Overflow Error: invalid deposit tx amount."))?;
-        let new_total = self
-            .total
-            .checked_add(amount)
-            .ok_or_else(|| Error::TransactionError("Overflow Error: invalid deposit tx amount."))?;
+    /// Balance held in `currency`; zero if the client has never transacted in it.
+    pub fn balance(&self, currency: &str) -> Balance {
+        self.balances.get(currency).copied().unwrap_or_default()
+    }
 
-        self.available = new_available;
-        self.total = new_total;
+    pub fn deposit(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.check_status(Operation::Inbound)?;
+        self.balance_mut(currency).deposit(amount)
+    }
 
-        Ok(())
+    pub fn provisional_deposit(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.check_status(Operation::Inbound)?;
+        self.balance_mut(currency).provisional_deposit(amount)
     }
 
-    pub fn withdrawal(&mut self, amount: Decimal) -> Result<()> {
-        self.check_lock()?;
-        self.validate_withdrawal_amount(amount)?;
+    pub fn clear(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.check_status(Operation::Inbound)?;
+        self.balance_mut(currency).clear(amount)
+    }
 
-        // theoretically all underflows should NEVER happen bc we always check for sufficient funds
-        let new_available = self.available.checked_sub(amount).ok_or_else(|| {
-            Error::TransactionError("Underflow Error: invalid withdrawal tx amount.")
-        })?;
-        let new_total = self.total.checked_sub(amount).ok_or_else(|| {
-            Error::TransactionError("Underflow Error: invalid withdrawal tx amount.")
-        })?;
+    pub fn withdrawal(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.check_status(Operation::Outbound)?;
+        self.balance_mut(currency).withdrawal(amount)
+    }
 
-        self.available = new_available;
-        self.total = new_total;
+    pub fn refund(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.check_status(Operation::Outbound)?;
+        self.balance_mut(currency).refund(amount)
+    }
 
-        Ok(())
+    /// Places a hold of `amount` on available funds, moving it to `held`.
+    pub fn authorize(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.check_status(Operation::Outbound)?;
+        self.balance_mut(currency).authorize(amount)
     }
 
-    pub fn dispute(&mut self, amount: Decimal) -> Result<()> {
-        self.check_lock()?;
-        self.validate_dispute_amount(amount)?;
+    /// Settles a hold: `captured` is debited from `held` and `total` for good, while `released`
+    /// goes back to `available`.
+    pub fn capture(
+        &mut self,
+        currency: &str,
+        captured: Amount,
+        released: Amount,
+    ) -> Result<Postings> {
+        self.check_status(Operation::Dispute)?;
+        self.balance_mut(currency).settle_hold(captured, released)
+    }
 
-        let new_available = self.available.checked_sub(amount).ok_or_else(|| {
-            Error::TransactionError("Underflow Error: invalid dispute tx amount.")
-        })?;
-        let new_held = self
-            .held
-            .checked_add(amount)
-            .ok_or_else(|| Error::TransactionError("Overflow Error: invalid dispute tx amount."))?;
+    /// Releases a hold of `amount` back to `available`.
+    pub fn void(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.check_status(Operation::Dispute)?;
+        self.balance_mut(currency).settle_hold(Amount::ZERO, amount)
+    }
 
-        self.available = new_available;
-        self.held = new_held;
+    // fees ride on a tx that already passed the status checks, and reach the fee account whatever
+    // its status
+    pub(crate) fn pay_fee(&mut self, currency: &str, fee: Amount) -> Result<Postings> {
+        self.balance_mut(currency).withdrawal(fee)
+    }
+
+    pub(crate) fn receive_fee(&mut self, currency: &str, fee: Amount) -> Result<Postings> {
+        self.balance_mut(currency).deposit(fee)
+    }
+
+    // interest is earned on funds the engine already holds, whatever the account's status
+    // negative interest takes back what a backdated debit's funds earned in the meantime
+    pub(crate) fn credit_interest(&mut self, currency: &str, interest: Amount) -> Result<Postings> {
+        match interest < Amount::ZERO {
+            true => self.balance_mut(currency).adjust(interest),
+            false => self.balance_mut(currency).deposit(interest),
+        }
+    }
+
+    // an expired hold is released whatever the account's status, since the client asked for
+    // nothing
+    pub(crate) fn release_hold(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.balance_mut(currency).settle_hold(Amount::ZERO, amount)
+    }
+
+    /// Adds the signed `amount` to `available` and `total`, without checking for funds, so a
+    /// correction may leave `available` negative. Allowed on frozen and locked accounts.
+    pub fn adjust(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.check_status(Operation::Correction)?;
+        self.balance_mut(currency).adjust(amount)
+    }
+
+    pub fn dispute(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.check_status(Operation::Dispute)?;
+        self.balance_mut(currency).dispute(amount)
+    }
+
+    pub fn resolve(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.check_status(Operation::Dispute)?;
+        self.balance_mut(currency).resolve(amount)
+    }
+
+    pub fn chargeback(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        let postings = self.chargeback_funds(currency, amount)?;
+        self.lock("chargeback"); // lock account after successful chargeback
+
+        Ok(postings)
+    }
+
+    pub fn dispute_withdrawal(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.check_status(Operation::Dispute)?;
+        self.balance_mut(currency).dispute_withdrawal(amount)
+    }
+
+    pub fn resolve_withdrawal(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.check_status(Operation::Dispute)?;
+        self.balance_mut(currency).resolve_withdrawal(amount)
+    }
+
+    pub fn chargeback_withdrawal(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        let postings = self.chargeback_withdrawal_funds(currency, amount)?;
+        self.lock("chargeback"); // lock account after successful chargeback
+
+        Ok(postings)
+    }
+
+    // the balance side of a chargeback alone--the engine decides about the lock per its policy
+    pub(crate) fn chargeback_funds(&mut self, currency: &str, amount: Amount) -> Result<Postings> {
+        self.check_status(Operation::Dispute)?;
+        self.balance_mut(currency).chargeback(amount)
+    }
+
+    pub(crate) fn chargeback_withdrawal_funds(
+        &mut self,
+        currency: &str,
+        amount: Amount,
+    ) -> Result<Postings> {
+        self.check_status(Operation::Dispute)?;
+        self.balance_mut(currency).chargeback_withdrawal(amount)
+    }
+
+    /// Stops withdrawals until the account is unlocked, recording `reason`.
+    pub fn freeze(&mut self, reason: &str) -> Result<()> {
+        self.check_open()?;
+        if self.status != AccountStatus::Active {
+            return Err(Error::AccountError("Only active accounts can be frozen."));
+        }
+        self.status = AccountStatus::Frozen;
+        self.status_reason = Some(reason.to_owned());
 
         Ok(())
     }
 
-    pub fn resolve(&mut self, amount: Decimal) -> Result<()> {
-        self.check_lock()?;
-        self.validate_resolve_amount(amount)?;
+    /// Lifts a freeze, or a lock left by a chargeback (an administrative decision).
+    pub fn unlock(&mut self) -> Result<()> {
+        self.check_open()?;
+        if self.status == AccountStatus::Active {
+            return Err(Error::AccountError("Account is not locked or frozen."));
+        }
+        self.status = AccountStatus::Active;
+        self.status_reason = None;
 
-        let new_held = self.held.checked_sub(amount).ok_or_else(|| {
-            Error::TransactionError("Underflow Error: invalid resolve tx amount.")
-        })?;
-        let new_available = self
-            .available
-            .checked_add(amount)
-            .ok_or_else(|| Error::TransactionError("Overflow Error: invalid resolve tx amount."))?;
+        Ok(())
+    }
 
-        self.held = new_held;
-        self.available = new_available;
+    /// Closes the account for good. Only allowed once every balance is zero, so no funds are
+    /// stranded.
+    pub fn close(&mut self) -> Result<()> {
+        self.check_open()?;
+        if self
+            .balances
+            .values()
+            .any(|balance| *balance != Balance::default())
+        {
+            return Err(Error::AccountError(
+                "Account must have a zero balance to be closed.",
+            ));
+        }
+        self.status = AccountStatus::Closed;
+        self.status_reason = None;
 
         Ok(())
     }
 
-    pub fn chargeback(&mut self, amount: Decimal) -> Result<()> {
-        self.check_lock()?;
-        self.validate_chargeback_amount(amount)?;
+    // fold another account's balances into this one, currency by currency, when consolidating
+    // duplicate clients--the more restrictive status (and its reason) carries over to the merged
+    // account, and closed accounts can't take part at all
+    pub fn merge(&mut self, other: &Account) -> Result<()> {
+        self.check_open()?;
+        other.check_open()?;
+        let mut balances = self.balances.clone();
+        for (currency, balance) in &other.balances {
+            balances
+                .entry(currency.clone())
+                .or_default()
+                .merge(balance)?;
+        }
 
-        let new_held = self.held.checked_sub(amount).ok_or_else(|| {
-            Error::TransactionError("Underflow Error: invalid chargeback tx amount.")
-        })?;
-        let new_total = self.total.checked_sub(amount).ok_or_else(|| {
-            Error::TransactionError("Underflow Error: invalid chargeback tx amount.")
-        })?;
+        self.balances = balances;
+        if other.status > self.status {
+            self.status = other.status;
+            self.status_reason = other.status_reason.clone();
+        }
 
-        self.held = new_held;
-        self.total = new_total;
-        self.locked = true; // lock account after successful chargeback
+        Ok(())
+    }
+
+    // a currency's balance springs into existence on first use, like the account itself
+    fn balance_mut(&mut self, currency: &str) -> &mut Balance {
+        self.balances.entry(currency.to_owned()).or_default()
+    }
+
+    // take `postings` back out of the balance in `currency`, when the operation that posted them
+    // failed
+    pub(crate) fn unpost(&mut self, currency: &str, postings: &[Posting]) {
+        let balance = self.balance_mut(currency);
+        *balance = balance.unposted(postings);
+    }
+
+    fn check_open(&self) -> Result<()> {
+        if self.status == AccountStatus::Closed {
+            return Err(Error::AccountClosed(
+                "Account is closed. No further transactions are accepted.",
+            ));
+        }
 
         Ok(())
     }
 
-    fn check_lock(&self) -> Result<()> {
-        if self.locked {
-            return Err(Error::AccountError(
+    pub(crate) fn lock(&mut self, reason: &str) {
+        self.status = AccountStatus::Locked;
+        self.status_reason = Some(reason.to_owned());
+    }
+
+    fn check_status(&self, operation: Operation) -> Result<()> {
+        match (self.status, operation) {
+            (AccountStatus::Active, _) => Ok(()),
+            (AccountStatus::Closed, _) => self.check_open(),
+            (_, Operation::Correction) => Ok(()),
+            (AccountStatus::Frozen, Operation::Outbound) => Err(Error::AccountLocked(
+                "Account is frozen. Withdrawals are currently unavailable.",
+            )),
+            (AccountStatus::Frozen, Operation::Inbound | Operation::Dispute) => Ok(()),
+            (AccountStatus::Locked, _) => Err(Error::AccountLocked(
                 "Account is locked. All transactions are currently unavailable.",
+            )),
+        }
+    }
+
+    // ensure the transaction account ID matches the account ID for disputes, resolves, and
+    // chargebacks
+    pub(crate) fn validate_tx_account_id(&self, tx_account_id: u16) -> Result<()> {
+        if self.id != tx_account_id {
+            return Err(Error::TransactionError(
+                "Transaction account ID does not match account.",
             ));
         }
 
         Ok(())
     }
+}
 
-    fn validate_deposit_amount(&self, amount: Decimal) -> Result<()> {
+impl Balance {
+    pub(crate) fn deposit(&mut self, amount: Amount) -> Result<Postings> {
+        self.validate_deposit_amount(amount)?;
+
+        self.post(
+            Postings::one(Posting::new(
+                LedgerAccount::Suspense,
+                LedgerAccount::Available,
+                amount,
+            )),
+            "Overflow Error: invalid deposit tx amount.",
+        )
+    }
+
+    pub(crate) fn authorize(&mut self, amount: Amount) -> Result<Postings> {
         Self::check_negative_amount(amount)?;
+        if self.available < amount {
+            return Err(Error::InsufficientFunds(
+                "Insufficient funds to complete authorization.",
+            ));
+        }
+
+        self.post(
+            Postings::one(Posting::new(
+                LedgerAccount::Available,
+                LedgerAccount::Held,
+                amount,
+            )),
+            "Overflow Error: invalid authorize tx amount.",
+        )
+    }
+
+    // the whole hold leaves `held`, split between what is captured and what is released
+    pub(crate) fn settle_hold(&mut self, captured: Amount, released: Amount) -> Result<Postings> {
+        let hold = captured
+            .checked_add(released)
+            .ok_or(Error::TransactionError(
+                "Overflow Error: invalid capture tx amount.",
+            ))?;
+        // ensure the account has enough held funds
+        if self.held < hold {
+            return Err(Error::InsufficientFunds(
+                "Insufficient funds to complete capture/void transaction.",
+            ));
+        }
+
+        self.post(
+            Postings::two(
+                Posting::new(LedgerAccount::Held, LedgerAccount::Suspense, captured),
+                Posting::new(LedgerAccount::Held, LedgerAccount::Available, released),
+            ),
+            "Overflow Error: invalid capture/void tx amount.",
+        )
+    }
+
+    pub(crate) fn adjust(&mut self, amount: Amount) -> Result<Postings> {
+        self.post(
+            Postings::one(Posting::new(
+                LedgerAccount::Suspense,
+                LedgerAccount::Available,
+                amount,
+            )),
+            "Overflow Error: invalid adjustment tx amount.",
+        )
+    }
+
+    // provisional deposits (e.g. check/ACH) count towards the total but stay held until cleared
+    pub(crate) fn provisional_deposit(&mut self, amount: Amount) -> Result<Postings> {
+        self.validate_deposit_amount(amount)?;
+
+        self.post(
+            Postings::one(Posting::new(
+                LedgerAccount::Suspense,
+                LedgerAccount::Held,
+                amount,
+            )),
+            "Overflow Error: invalid provisional deposit tx amount.",
+        )
+    }
+
+    pub(crate) fn clear(&mut self, amount: Amount) -> Result<Postings> {
+        self.validate_clear_amount(amount)?;
+
+        self.post(
+            Postings::one(Posting::new(
+                LedgerAccount::Held,
+                LedgerAccount::Available,
+                amount,
+            )),
+            "Overflow Error: invalid clear tx amount.",
+        )
+    }
+
+    pub(crate) fn withdrawal(&mut self, amount: Amount) -> Result<Postings> {
+        self.validate_withdrawal_amount(amount)?;
+
+        // theoretically this should NEVER fail bc we always check for sufficient funds
+        self.post(
+            Postings::one(Posting::new(
+                LedgerAccount::Available,
+                LedgerAccount::Suspense,
+                amount,
+            )),
+            "Underflow Error: invalid withdrawal tx amount.",
+        )
+    }
+
+    // a refund takes funds out like a withdrawal, with its own error for the missing funds
+    pub(crate) fn refund(&mut self, amount: Amount) -> Result<Postings> {
+        if self.available < amount {
+            return Err(Error::InsufficientFunds(
+                "Insufficient funds to complete refund transaction.",
+            ));
+        }
+        self.withdrawal(amount)
+    }
+
+    // the hold is placed even when the funds were already withdrawn, driving `available`
+    // negative--`total` stays `available + held` either way
+    pub(crate) fn dispute(&mut self, amount: Amount) -> Result<Postings> {
+        self.post(
+            Postings::one(Posting::new(
+                LedgerAccount::Available,
+                LedgerAccount::Held,
+                amount,
+            )),
+            "Overflow Error: invalid dispute tx amount.",
+        )
+    }
+
+    pub(crate) fn resolve(&mut self, amount: Amount) -> Result<Postings> {
+        self.validate_resolve_amount(amount)?;
+
+        self.post(
+            Postings::one(Posting::new(
+                LedgerAccount::Held,
+                LedgerAccount::Available,
+                amount,
+            )),
+            "Overflow Error: invalid resolve tx amount.",
+        )
+    }
+
+    // the charged back funds are written off to the engine's chargeback loss
+    pub(crate) fn chargeback(&mut self, amount: Amount) -> Result<Postings> {
+        self.validate_chargeback_amount(amount)?;
+
+        self.post(
+            Postings::one(Posting::new(
+                LedgerAccount::Held,
+                LedgerAccount::ChargebackLoss,
+                amount,
+            )),
+            "Underflow Error: invalid chargeback tx amount.",
+        )
+    }
+
+    // a disputed withdrawal provisionally credits the withdrawn funds back as held
+    pub(crate) fn dispute_withdrawal(&mut self, amount: Amount) -> Result<Postings> {
+        self.post(
+            Postings::one(Posting::new(
+                LedgerAccount::Suspense,
+                LedgerAccount::Held,
+                amount,
+            )),
+            "Overflow Error: invalid dispute tx amount.",
+        )
+    }
+
+    // resolving a withdrawal dispute upholds the withdrawal, dropping the provisional credit
+    pub(crate) fn resolve_withdrawal(&mut self, amount: Amount) -> Result<Postings> {
+        self.validate_resolve_withdrawal_amount(amount)?;
+
+        self.post(
+            Postings::one(Posting::new(
+                LedgerAccount::Held,
+                LedgerAccount::Suspense,
+                amount,
+            )),
+            "Underflow Error: invalid resolve tx amount.",
+        )
+    }
+
+    // charging back a withdrawal returns the withdrawn funds to the client
+    pub(crate) fn chargeback_withdrawal(&mut self, amount: Amount) -> Result<Postings> {
+        self.validate_chargeback_withdrawal_amount(amount)?;
+
+        self.post(
+            Postings::one(Posting::new(
+                LedgerAccount::Held,
+                LedgerAccount::Available,
+                amount,
+            )),
+            "Overflow Error: invalid chargeback tx amount.",
+        )
+    }
+
+    // every balance change goes through here: the client's legs of balanced postings move
+    // `available` and `held`, and `total` follows from them, so no operation can get them out of
+    // step. Worked out in full first, so a failure changes nothing
+    fn post(&mut self, postings: Postings, error: &'static str) -> Result<Postings> {
+        let mut available = self.available;
+        let mut held = self.held;
+        for posting in postings.iter() {
+            for (account, credit) in [(posting.debit, false), (posting.credit, true)] {
+                let balance = match account {
+                    LedgerAccount::Available => &mut available,
+                    LedgerAccount::Held => &mut held,
+                    LedgerAccount::Suspense | LedgerAccount::ChargebackLoss => continue,
+                };
+                let posted = match credit {
+                    true => balance.checked_add(posting.amount),
+                    false => balance.checked_sub(posting.amount),
+                };
+                *balance = posted.ok_or(Error::TransactionError(error))?;
+            }
+        }
+        let total = available
+            .checked_add(held)
+            .ok_or(Error::TransactionError(error))?;
+
+        self.available = available;
+        self.held = held;
+        self.total = total;
+
+        Ok(postings)
+    }
+
+    // the balance as it was before `postings` were posted to it. They were posted without
+    // overflowing, so undoing them can't overflow either
+    pub(crate) fn unposted(&self, postings: &[Posting]) -> Balance {
+        let mut available = self.available;
+        let mut held = self.held;
+        for posting in postings {
+            for (account, credit) in [(posting.debit, false), (posting.credit, true)] {
+                let balance = match account {
+                    LedgerAccount::Available => &mut available,
+                    LedgerAccount::Held => &mut held,
+                    LedgerAccount::Suspense | LedgerAccount::ChargebackLoss => continue,
+                };
+                match credit {
+                    true => *balance -= posting.amount,
+                    false => *balance += posting.amount,
+                }
+            }
+        }
+
+        Balance {
+            available,
+            held,
+            total: available + held,
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: &Balance) -> Result<()> {
+        let new_available =
+            self.available
+                .checked_add(other.available)
+                .ok_or(Error::AccountError(
+                    "Overflow Error: invalid account merge.",
+                ))?;
+        let new_held = self
+            .held
+            .checked_add(other.held)
+            .ok_or(Error::AccountError(
+                "Overflow Error: invalid account merge.",
+            ))?;
+        let new_total = self
+            .total
+            .checked_add(other.total)
+            .ok_or(Error::AccountError(
+                "Overflow Error: invalid account merge.",
+            ))?;
+
+        self.available = new_available;
+        self.held = new_held;
+        self.total = new_total;
 
         Ok(())
     }
 
-    fn validate_withdrawal_amount(&self, amount: Decimal) -> Result<()> {
+    fn validate_deposit_amount(&self, amount: Amount) -> Result<()> {
+        Self::check_negative_amount(amount)?;
+
+        Ok(())
+    }
+
+    fn validate_withdrawal_amount(&self, amount: Amount) -> Result<()> {
         Self::check_negative_amount(amount)?;
 
         // ensure the account has enough available/total funds
         if self.available < amount || self.total < amount {
-            return Err(Error::AccountError(
+            return Err(Error::InsufficientFunds(
                 "Insufficient funds to complete withdrawal transaction.",
             ));
         }
@@ -143,21 +672,33 @@ impl Account {
         Ok(())
     }
 
-    fn validate_dispute_amount(&self, amount: Decimal) -> Result<()> {
-        // ensure the account has enough available funds
-        if self.available < amount {
-            return Err(Error::AccountError(
-                "Insufficient funds to complete dispute transaction.",
+    fn validate_resolve_amount(&self, amount: Amount) -> Result<()> {
+        // ensure the account has enough held funds
+        if self.held < amount {
+            return Err(Error::InsufficientFunds(
+                "Insufficient funds to complete resolve transaction.",
             ));
         }
 
         Ok(())
     }
 
-    fn validate_resolve_amount(&self, amount: Decimal) -> Result<()> {
+    fn validate_clear_amount(&self, amount: Amount) -> Result<()> {
         // ensure the account has enough held funds
         if self.held < amount {
-            return Err(Error::AccountError(
+            return Err(Error::InsufficientFunds(
+                "Insufficient funds to complete clear transaction.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn validate_resolve_withdrawal_amount(&self, amount: Amount) -> Result<()> {
+        // ensure the account has enough held funds--`total` may go negative along with
+        // `available` when the disputed funds were already withdrawn
+        if self.held < amount {
+            return Err(Error::InsufficientFunds(
                 "Insufficient funds to complete resolve transaction.",
             ));
         }
@@ -165,10 +706,10 @@ impl Account {
         Ok(())
     }
 
-    fn validate_chargeback_amount(&self, amount: Decimal) -> Result<()> {
-        // ensure the account has enough held/total funds
-        if self.held < amount || self.total < amount {
-            return Err(Error::AccountError(
+    fn validate_chargeback_withdrawal_amount(&self, amount: Amount) -> Result<()> {
+        // ensure the account has enough held funds
+        if self.held < amount {
+            return Err(Error::InsufficientFunds(
                 "Insufficient funds to complete chargeback transaction.",
             ));
         }
@@ -176,12 +717,12 @@ impl Account {
         Ok(())
     }
 
-    // ensure the transaction account ID matches the account ID for disputes, resolves, and
-    // chargebacks
-    pub fn validate_tx_account_id(&self, tx_account_id: u16) -> Result<()> {
-        if self.id != tx_account_id {
-            return Err(Error::TransactionError(
-                "Transaction account ID does not match account.",
+    fn validate_chargeback_amount(&self, amount: Amount) -> Result<()> {
+        // ensure the account has enough held funds--`total` may go negative along with
+        // `available` when the disputed funds were already withdrawn
+        if self.held < amount {
+            return Err(Error::InsufficientFunds(
+                "Insufficient funds to complete chargeback transaction.",
             ));
         }
 
@@ -189,7 +730,7 @@ impl Account {
     }
 
     // ensure that deposit/withdrawal amounts are not negative
-    fn check_negative_amount(amount: Decimal) -> Result<()> {
+    fn check_negative_amount(amount: Amount) -> Result<()> {
         if amount.is_sign_negative() {
             return Err(Error::TransactionError(
                 "Deposit/withdrawal amounts must be greater than zero.",
@@ -203,24 +744,27 @@ impl Account {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rust_decimal::{Decimal, dec};
+    use crate::amount;
+    use crate::amount::Amount;
+
+    const USD: &str = "USD";
 
     #[test]
     fn test_deposit_success() {
         let mut account = Account::new(1);
-        account.deposit(dec!(100)).unwrap();
+        account.deposit(USD, amount!(100)).unwrap();
 
-        assert_eq!(account.available, dec!(100));
-        assert_eq!(account.total, dec!(100));
-        assert_eq!(account.held, dec!(0));
-        assert!(!account.locked);
+        assert_eq!(account.balance(USD).available, amount!(100));
+        assert_eq!(account.balance(USD).total, amount!(100));
+        assert_eq!(account.balance(USD).held, amount!(0));
+        assert!(!account.is_locked());
     }
 
     #[test]
     fn test_deposit_failure_overflow() {
         let mut account = Account::new(1);
-        account.deposit(Decimal::ONE).unwrap();
-        let result = account.deposit(Decimal::MAX);
+        account.deposit(USD, Amount::ONE).unwrap();
+        let result = account.deposit(USD, Amount::MAX);
 
         assert!(result.is_err());
     }
@@ -228,8 +772,8 @@ mod tests {
     #[test]
     fn test_deposit_failure_locked_account() {
         let mut account = Account::new(1);
-        account.locked = true;
-        let result = account.deposit(dec!(100));
+        account.lock("test");
+        let result = account.deposit(USD, amount!(100));
 
         assert!(result.is_err());
     }
@@ -237,76 +781,258 @@ mod tests {
     #[test]
     fn test_withdrawal_success() {
         let mut account = Account::new(1);
-        account.deposit(dec!(100)).unwrap();
-        account.withdrawal(dec!(40)).unwrap();
+        account.deposit(USD, amount!(100)).unwrap();
+        account.withdrawal(USD, amount!(40)).unwrap();
 
-        assert_eq!(account.available, dec!(60));
-        assert_eq!(account.total, dec!(60));
+        assert_eq!(account.balance(USD).available, amount!(60));
+        assert_eq!(account.balance(USD).total, amount!(60));
     }
 
     #[test]
     fn test_withdrawal_failure_insufficient_funds() {
         let mut account = Account::new(1);
-        account.deposit(dec!(50)).unwrap();
-        let result = account.withdrawal(dec!(60));
+        account.deposit(USD, amount!(50)).unwrap();
+        let result = account.withdrawal(USD, amount!(60));
 
         assert!(result.is_err());
-        assert_eq!(account.available, dec!(50));
+        assert_eq!(account.balance(USD).available, amount!(50));
     }
 
     #[test]
     fn test_withdrawal_failure_locked_account() {
         let mut account = Account::new(1);
-        account.deposit(dec!(100)).unwrap();
-        account.locked = true;
-        let result = account.withdrawal(dec!(10));
+        account.deposit(USD, amount!(100)).unwrap();
+        account.lock("test");
+        let result = account.withdrawal(USD, amount!(10));
 
         assert!(result.is_err());
     }
 
     #[test]
-    fn test_dispute_success() {
+    fn test_refund_success() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(100)).unwrap();
+        account.refund(USD, amount!(25)).unwrap();
+
+        assert_eq!(account.balance(USD).available, amount!(75));
+        assert_eq!(account.balance(USD).total, amount!(75));
+    }
+
+    #[test]
+    fn test_refund_failure_insufficient_funds() {
         let mut account = Account::new(1);
-        account.deposit(dec!(100)).unwrap();
-        account.dispute(dec!(60)).unwrap();
+        account.deposit(USD, amount!(100)).unwrap();
+        account.dispute(USD, amount!(80)).unwrap();
+        let result = account.refund(USD, amount!(30));
 
-        assert_eq!(account.available, dec!(40));
-        assert_eq!(account.held, dec!(60));
-        assert_eq!(account.total, dec!(100));
+        assert!(matches!(result, Err(Error::InsufficientFunds(_))));
+        assert_eq!(account.balance(USD).available, amount!(20));
     }
 
     #[test]
-    fn test_dispute_failure_insufficient_available_funds() {
+    fn test_authorize_success() {
         let mut account = Account::new(1);
-        account.deposit(dec!(60)).unwrap();
+        account.deposit(USD, amount!(100)).unwrap();
+        account.authorize(USD, amount!(30)).unwrap();
 
-        let result = account.dispute(dec!(80));
+        assert_eq!(account.balance(USD).available, amount!(70));
+        assert_eq!(account.balance(USD).held, amount!(30));
+        assert_eq!(account.balance(USD).total, amount!(100));
+    }
+
+    #[test]
+    fn test_authorize_failure_frozen_account() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(100)).unwrap();
+        account.freeze("test").unwrap();
+        let result = account.authorize(USD, amount!(30));
+
+        assert!(matches!(result, Err(Error::AccountLocked(_))));
+        assert_eq!(account.balance(USD).held, amount!(0));
+    }
+
+    #[test]
+    fn test_capture_success() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(100)).unwrap();
+        account.authorize(USD, amount!(30)).unwrap();
+        let postings = account.capture(USD, amount!(20), amount!(10)).unwrap();
+
+        assert_eq!(account.balance(USD).available, amount!(80));
+        assert_eq!(account.balance(USD).held, amount!(0));
+        assert_eq!(account.balance(USD).total, amount!(80));
+        assert_eq!(
+            *postings,
+            [
+                Posting::new(LedgerAccount::Held, LedgerAccount::Suspense, amount!(20)),
+                Posting::new(LedgerAccount::Held, LedgerAccount::Available, amount!(10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_void_failure_insufficient_held_funds() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(100)).unwrap();
+        let result = account.void(USD, amount!(10));
+
+        assert!(matches!(result, Err(Error::InsufficientFunds(_))));
+        assert_eq!(account.balance(USD).available, amount!(100));
+    }
+
+    #[test]
+    fn test_adjust_success_locked_account() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(10)).unwrap();
+        account.lock("test");
+        account.adjust(USD, amount!(-15)).unwrap();
+
+        assert_eq!(account.balance(USD).available, amount!(-5));
+        assert_eq!(account.balance(USD).total, amount!(-5));
+    }
+
+    #[test]
+    fn test_adjust_failure_closed_account() {
+        let mut account = Account::new(1);
+        account.close().unwrap();
+        let result = account.adjust(USD, amount!(5));
 
         assert!(result.is_err());
+        assert_eq!(account.balance(USD).total, amount!(0));
+    }
+
+    #[test]
+    fn test_dispute_success() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(100)).unwrap();
+        account.dispute(USD, amount!(60)).unwrap();
+
+        assert_eq!(account.balance(USD).available, amount!(40));
+        assert_eq!(account.balance(USD).held, amount!(60));
+        assert_eq!(account.balance(USD).total, amount!(100));
+    }
+
+    #[test]
+    fn test_dispute_success_negative_available() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(60)).unwrap();
+
+        account.dispute(USD, amount!(80)).unwrap();
+
+        assert_eq!(account.balance(USD).available, amount!(-20));
+        assert_eq!(account.balance(USD).held, amount!(80));
+        assert_eq!(account.balance(USD).total, amount!(60));
+
+        account.chargeback(USD, amount!(80)).unwrap();
+
+        assert_eq!(account.balance(USD).available, amount!(-20));
+        assert_eq!(account.balance(USD).total, amount!(-20));
     }
 
     #[test]
     fn test_resolve_success() {
         let mut account = Account::new(1);
-        account.deposit(dec!(100)).unwrap();
-        account.dispute(dec!(60)).unwrap();
+        account.deposit(USD, amount!(100)).unwrap();
+        account.dispute(USD, amount!(60)).unwrap();
 
-        assert_eq!(account.available, dec!(40));
+        assert_eq!(account.balance(USD).available, amount!(40));
 
-        account.resolve(dec!(60)).unwrap();
+        account.resolve(USD, amount!(60)).unwrap();
 
-        assert_eq!(account.available, dec!(100));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.total, dec!(100));
+        assert_eq!(account.balance(USD).available, amount!(100));
+        assert_eq!(account.balance(USD).held, amount!(0));
+        assert_eq!(account.balance(USD).total, amount!(100));
     }
 
     #[test]
     fn test_resolve_failure_insufficient_held_funds() {
         let mut account = Account::new(1);
-        account.deposit(dec!(100)).unwrap();
-        account.dispute(dec!(60)).unwrap();
+        account.deposit(USD, amount!(100)).unwrap();
+        account.dispute(USD, amount!(60)).unwrap();
+
+        let result = account.resolve(USD, amount!(80));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_dispute_withdrawal_success() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(100)).unwrap();
+        account.withdrawal(USD, amount!(40)).unwrap();
+        account.dispute_withdrawal(USD, amount!(40)).unwrap();
+
+        assert_eq!(account.balance(USD).available, amount!(60));
+        assert_eq!(account.balance(USD).held, amount!(40));
+        assert_eq!(account.balance(USD).total, amount!(100));
+    }
+
+    #[test]
+    fn test_resolve_withdrawal_success() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(100)).unwrap();
+        account.withdrawal(USD, amount!(40)).unwrap();
+        account.dispute_withdrawal(USD, amount!(40)).unwrap();
+        account.resolve_withdrawal(USD, amount!(40)).unwrap();
+
+        assert_eq!(account.balance(USD).available, amount!(60));
+        assert_eq!(account.balance(USD).held, amount!(0));
+        assert_eq!(account.balance(USD).total, amount!(60));
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_chargeback_withdrawal_success() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(100)).unwrap();
+        account.withdrawal(USD, amount!(40)).unwrap();
+        account.dispute_withdrawal(USD, amount!(40)).unwrap();
+        account.chargeback_withdrawal(USD, amount!(40)).unwrap();
+
+        assert_eq!(account.balance(USD).available, amount!(100));
+        assert_eq!(account.balance(USD).held, amount!(0));
+        assert_eq!(account.balance(USD).total, amount!(100));
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_chargeback_withdrawal_failure_insufficient_held_funds() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(100)).unwrap();
+
+        let result = account.chargeback_withdrawal(USD, amount!(40));
+
+        assert!(result.is_err());
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_provisional_deposit_success() {
+        let mut account = Account::new(1);
+        account.provisional_deposit(USD, amount!(100)).unwrap();
+
+        assert_eq!(account.balance(USD).available, amount!(0));
+        assert_eq!(account.balance(USD).held, amount!(100));
+        assert_eq!(account.balance(USD).total, amount!(100));
+    }
+
+    #[test]
+    fn test_clear_success() {
+        let mut account = Account::new(1);
+        account.provisional_deposit(USD, amount!(100)).unwrap();
+        account.clear(USD, amount!(100)).unwrap();
+
+        assert_eq!(account.balance(USD).available, amount!(100));
+        assert_eq!(account.balance(USD).held, amount!(0));
+        assert_eq!(account.balance(USD).total, amount!(100));
+    }
+
+    #[test]
+    fn test_clear_failure_insufficient_held_funds() {
+        let mut account = Account::new(1);
+        account.provisional_deposit(USD, amount!(50)).unwrap();
 
-        let result = account.resolve(dec!(80));
+        let result = account.clear(USD, amount!(80));
 
         assert!(result.is_err());
     }
@@ -314,49 +1040,252 @@ mod tests {
     #[test]
     fn test_chargeback_success() {
         let mut account = Account::new(1);
-        account.deposit(dec!(100)).unwrap();
-        account.dispute(dec!(60)).unwrap();
+        account.deposit(USD, amount!(100)).unwrap();
+        account.dispute(USD, amount!(60)).unwrap();
 
-        assert_eq!(account.total, dec!(100));
-        assert!(!account.locked);
+        assert_eq!(account.balance(USD).total, amount!(100));
+        assert!(!account.is_locked());
 
-        account.chargeback(dec!(60)).unwrap();
+        account.chargeback(USD, amount!(60)).unwrap();
 
-        assert_eq!(account.total, dec!(40));
-        assert_eq!(account.held, dec!(0));
-        assert!(account.locked);
+        assert_eq!(account.balance(USD).total, amount!(40));
+        assert_eq!(account.balance(USD).held, amount!(0));
+        assert!(account.is_locked());
     }
 
     #[test]
     fn test_chargeback_failure_insufficient_held_funds() {
         let mut account = Account::new(1);
-        account.deposit(dec!(100)).unwrap();
-        account.dispute(dec!(60)).unwrap();
+        account.deposit(USD, amount!(100)).unwrap();
+        account.dispute(USD, amount!(60)).unwrap();
+
+        let result = account.chargeback(USD, amount!(80));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_merge_success() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(100)).unwrap();
+        let mut other = Account::new(2);
+        other.deposit(USD, amount!(50)).unwrap();
+        other.dispute(USD, amount!(20)).unwrap();
+
+        account.merge(&other).unwrap();
+
+        assert_eq!(account.id, 1);
+        assert_eq!(account.balance(USD).available, amount!(130));
+        assert_eq!(account.balance(USD).held, amount!(20));
+        assert_eq!(account.balance(USD).total, amount!(150));
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_merge_carries_lock() {
+        let mut account = Account::new(1);
+        let mut other = Account::new(2);
+        other.lock("test");
 
-        let result = account.chargeback(dec!(80));
+        account.merge(&other).unwrap();
+
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_merge_failure_overflow() {
+        let mut account = Account::new(1);
+        account.deposit(USD, Amount::MAX).unwrap();
+        let mut other = Account::new(2);
+        other.deposit(USD, Amount::ONE).unwrap();
+
+        let result = account.merge(&other);
 
         assert!(result.is_err());
+        assert_eq!(account.balance(USD).total, Amount::MAX);
+    }
+
+    #[test]
+    fn test_currencies_are_independent() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(100)).unwrap();
+        account.deposit("EUR", amount!(10)).unwrap();
+
+        let result = account.withdrawal("EUR", amount!(50));
+
+        assert!(matches!(result, Err(Error::InsufficientFunds(_))));
+        assert_eq!(account.balance(USD).available, amount!(100));
+        assert_eq!(account.balance("EUR").available, amount!(10));
+        assert_eq!(account.balance("GBP"), Balance::default());
     }
 
     #[test]
-    fn test_check_lock() {
+    fn test_chargeback_locks_all_currencies() {
         let mut account = Account::new(1);
-        assert!(account.check_lock().is_ok());
+        account.deposit(USD, amount!(100)).unwrap();
+        account.deposit("EUR", amount!(10)).unwrap();
+        account.dispute("EUR", amount!(10)).unwrap();
+        account.chargeback("EUR", amount!(10)).unwrap();
 
-        account.locked = true;
-        assert!(account.check_lock().is_err());
+        let result = account.withdrawal(USD, amount!(1));
+
+        assert!(matches!(result, Err(Error::AccountLocked(_))));
+    }
+
+    #[test]
+    fn test_merge_keeps_currencies_apart() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(100)).unwrap();
+        let mut other = Account::new(2);
+        other.deposit(USD, amount!(5)).unwrap();
+        other.deposit("EUR", amount!(7)).unwrap();
+
+        account.merge(&other).unwrap();
+
+        assert_eq!(account.balance(USD).total, amount!(105));
+        assert_eq!(account.balance("EUR").total, amount!(7));
+    }
+
+    #[test]
+    fn test_unlock_success() {
+        let mut account = Account::new(1);
+        account.lock("test");
+
+        account.unlock().unwrap();
+
+        assert!(!account.is_locked());
+        assert!(account.deposit(USD, amount!(1)).is_ok());
+    }
+
+    #[test]
+    fn test_unlock_failure_not_locked() {
+        let mut account = Account::new(1);
+
+        assert!(matches!(account.unlock(), Err(Error::AccountError(_))));
+    }
+
+    #[test]
+    fn test_close_success() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(10)).unwrap();
+        account.withdrawal(USD, amount!(10)).unwrap();
+
+        account.close().unwrap();
+
+        assert_eq!(account.status, AccountStatus::Closed);
+        assert!(matches!(
+            account.deposit(USD, amount!(1)),
+            Err(Error::AccountClosed(_))
+        ));
+        assert!(matches!(account.unlock(), Err(Error::AccountClosed(_))));
+    }
+
+    #[test]
+    fn test_close_failure_funds_remaining() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(10)).unwrap();
+
+        assert!(matches!(account.close(), Err(Error::AccountError(_))));
+        assert_eq!(account.status, AccountStatus::Active);
+    }
+
+    #[test]
+    fn test_merge_failure_closed_account() {
+        let mut account = Account::new(1);
+        let mut other = Account::new(2);
+        other.close().unwrap();
+
+        assert!(matches!(
+            account.merge(&other),
+            Err(Error::AccountClosed(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_status() {
+        let mut account = Account::new(1);
+        assert!(account.check_status(Operation::Outbound).is_ok());
+
+        account.freeze("review").unwrap();
+        assert!(account.check_status(Operation::Inbound).is_ok());
+        assert!(account.check_status(Operation::Dispute).is_ok());
+        assert!(account.check_status(Operation::Outbound).is_err());
+
+        account.lock("test");
+        assert!(account.check_status(Operation::Inbound).is_err());
+        assert!(account.check_status(Operation::Dispute).is_err());
+    }
+
+    #[test]
+    fn test_freeze_allows_deposits_only() {
+        let mut account = Account::new(1);
+        account.deposit(USD, amount!(10)).unwrap();
+
+        account.freeze("review").unwrap();
+
+        assert!(account.deposit(USD, amount!(5)).is_ok());
+        assert!(matches!(
+            account.withdrawal(USD, amount!(1)),
+            Err(Error::AccountLocked(_))
+        ));
+        assert_eq!(account.status_reason.as_deref(), Some("review"));
+        account.unlock().unwrap();
+        assert!(account.withdrawal(USD, amount!(1)).is_ok());
+        assert_eq!(account.status_reason, None);
+    }
+
+    #[test]
+    fn test_merge_keeps_more_restrictive_status() {
+        let mut account = Account::new(1);
+        account.freeze("review").unwrap();
+        let mut other = Account::new(2);
+        other.lock("chargeback");
+
+        account.merge(&other).unwrap();
+
+        assert!(account.is_locked());
+        assert_eq!(account.status_reason.as_deref(), Some("chargeback"));
+    }
+
+    #[test]
+    fn test_amount_limits_success() {
+        let limits = AmountLimits {
+            min: Some(amount!(0.0001)),
+            max: Some(amount!(1000)),
+        };
+
+        assert!(limits.validate(amount!(0.0001)).is_ok());
+        assert!(limits.validate(amount!(1000)).is_ok());
+        assert!(AmountLimits::default().validate(amount!(0)).is_ok());
+    }
+
+    #[test]
+    fn test_amount_limits_failure() {
+        let limits = AmountLimits {
+            min: Some(amount!(0.0001)),
+            max: Some(amount!(1000)),
+        };
+
+        assert!(matches!(
+            limits.validate(amount!(0)),
+            Err(Error::AmountBelowMinimum(_))
+        ));
+        assert!(matches!(
+            limits.validate(amount!(1000.0001)),
+            Err(Error::AmountAboveMaximum(_))
+        ));
     }
 
     #[test]
     fn test_check_negative_amount_success() {
-        let result = Account::check_negative_amount(dec!(1));
+        let result = Balance::check_negative_amount(amount!(1));
 
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_check_negative_amount_failure() {
-        let result = Account::check_negative_amount(dec!(-1));
+        let result = Balance::check_negative_amount(amount!(-1));
 
         assert!(result.is_err());
     }
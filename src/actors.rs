@@ -0,0 +1,454 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{RwLock, RwLockReadGuard, mpsc, oneshot};
+
+use crate::{
+    account::Account,
+    async_engine::{AsyncPaymentsEngine, Command},
+    engine::{DuplicatePolicy, PaymentsEngine},
+    error::{Error, ErrorContext, Result},
+    hash::HashMap,
+    transaction::Transaction,
+};
+
+// bounded so a flooded client gets backpressure instead of queueing without limit
+const MAILBOX_BUFFER: usize = 64;
+
+// the client each deposit or withdrawal tx id belongs to, shared by the router, which claims ids
+// as it routes them, and the actors, which give them back if the tx isn't applied
+type Owners = Arc<Mutex<HashMap<u32, u16>>>;
+
+enum Message {
+    Process {
+        tx: Transaction,
+        // whether the router claimed the tx id for this tx
+        claimed: bool,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    Account(u16, oneshot::Sender<Option<Account>>),
+    Accounts(oneshot::Sender<Vec<Account>>),
+    Merge(u16, u16, oneshot::Sender<Result<()>>),
+    EvictExpired(u64, oneshot::Sender<Result<usize>>),
+    // hand the engine over to be merged into another actor's, and stop
+    Surrender(oneshot::Sender<PaymentsEngine>),
+    // take over a surrendered engine, then merge `source` into `target`. An engine that can't be
+    // taken over is sent back with the error
+    Absorb {
+        engine: Box<PaymentsEngine>,
+        source: u16,
+        target: u16,
+        reply: oneshot::Sender<(Result<()>, Option<PaymentsEngine>)>,
+    },
+}
+
+// dispatches commands to an actor per client, each owning an engine of its own, on the caller's
+// task so that nothing but the actors themselves is in the way of different clients. Clients
+// merged into another share its actor from then on
+pub(crate) struct Router {
+    factory: Box<dyn Fn() -> PaymentsEngine + Send + Sync>,
+    // the engine built to check the factory's configuration, handed to the first actor
+    spare: Mutex<Option<PaymentsEngine>>,
+    duplicate_policy: DuplicatePolicy,
+    // held for reading while a command is handed to an actor, and for writing while actors are
+    // started or merged, so no command is sent to an actor that's being merged away
+    routes: RwLock<Routes>,
+    owners: Owners,
+}
+
+#[derive(Default)]
+struct Routes {
+    clients: HashMap<u16, usize>,
+    // by index, `None` once merged away
+    actors: Vec<Option<mpsc::Sender<Message>>>,
+}
+
+impl Routes {
+    fn mailbox(&self, client: u16) -> Option<&mpsc::Sender<Message>> {
+        self.actors[*self.clients.get(&client)?].as_ref()
+    }
+}
+
+impl Router {
+    pub(crate) fn new<F>(factory: F) -> Result<Self>
+    where
+        F: Fn() -> PaymentsEngine + Send + Sync + 'static,
+    {
+        let engine = factory();
+        if engine.charges_fees() {
+            return Err(Error::ConfigError(
+                "an engine per account can't charge fees".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            factory: Box::new(factory),
+            duplicate_policy: engine.duplicate_policy(),
+            spare: Mutex::new(Some(engine)),
+            routes: RwLock::default(),
+            owners: Owners::default(),
+        })
+    }
+
+    // a dropped message drops its reply, which tells the caller the engine has stopped
+    pub(crate) async fn dispatch(&self, command: Command) {
+        match command {
+            Command::Process(tx, reply) => self.process(tx, reply).await,
+            Command::Account(id, reply) => {
+                let routes = self.routes.read().await;
+                match routes.mailbox(id) {
+                    Some(mailbox) => {
+                        let _ = mailbox.send(Message::Account(id, reply)).await;
+                    }
+                    None => {
+                        let _ = reply.send(None);
+                    }
+                }
+            }
+            Command::Accounts(reply) => {
+                let routes = self.routes.read().await;
+                let mut accounts = Vec::new();
+                for mailbox in routes.actors.iter().flatten() {
+                    let Ok(mut batch) = ask(mailbox, Message::Accounts).await else {
+                        return;
+                    };
+                    accounts.append(&mut batch);
+                }
+                let _ = reply.send(accounts);
+            }
+            Command::Merge(source, target, reply) => {
+                let _ = reply.send(self.merge(source, target).await);
+            }
+            Command::EvictExpired(now, reply) => {
+                let routes = self.routes.read().await;
+                let mut evicted = 0;
+                for mailbox in routes.actors.iter().flatten() {
+                    match ask(mailbox, |reply| Message::EvictExpired(now, reply)).await {
+                        Ok(Ok(count)) => evicted += count,
+                        Ok(Err(e)) => {
+                            let _ = reply.send(Err(e));
+                            return;
+                        }
+                        Err(_) => return,
+                    }
+                }
+                let _ = reply.send(Ok(evicted));
+            }
+        }
+    }
+
+    async fn process(&self, tx: Transaction, reply: oneshot::Sender<Result<()>>) {
+        let routes = self.route(tx.account_id).await;
+        let actor = routes.clients[&tx.account_id];
+        let claimed = match claim(&self.owners, &routes.clients, &tx) {
+            Claim::Claimed => true,
+            Claim::Unclaimed => false,
+            Claim::Taken => {
+                let _ = reply.send(taken(self.duplicate_policy, &tx));
+                return;
+            }
+        };
+        if let Some(mailbox) = &routes.actors[actor] {
+            let _ = mailbox.send(Message::Process { tx, claimed, reply }).await;
+        }
+    }
+
+    // the routes, with an actor for `client` started on first sight
+    async fn route(&self, client: u16) -> RwLockReadGuard<'_, Routes> {
+        let routes = self.routes.read().await;
+        if routes.clients.contains_key(&client) {
+            return routes;
+        }
+        drop(routes);
+        let mut routes = self.routes.write().await;
+        self.start_client(&mut routes, client);
+        routes.downgrade()
+    }
+
+    fn start_client(&self, routes: &mut Routes, client: u16) -> usize {
+        if let Some(&actor) = routes.clients.get(&client) {
+            return actor;
+        }
+        let spare = self
+            .spare
+            .lock()
+            .expect("no actor panics holding the lock")
+            .take();
+        let engine = spare.unwrap_or_else(&self.factory);
+        routes.actors.push(Some(self.start(engine)));
+        routes.clients.insert(client, routes.actors.len() - 1);
+        routes.actors.len() - 1
+    }
+
+    fn start(&self, engine: PaymentsEngine) -> mpsc::Sender<Message> {
+        let (mailbox, receiver) = mpsc::channel(MAILBOX_BUFFER);
+        tokio::spawn(act(engine, receiver, self.owners.clone()));
+        mailbox
+    }
+
+    // merge `source` into `target`, handing the source's engine over to the target's actor first
+    // if they have one each
+    async fn merge(&self, source: u16, target: u16) -> Result<()> {
+        let mut routes = self.routes.write().await;
+        let target_actor = self.start_client(&mut routes, target);
+        let target_mailbox = routes.actors[target_actor]
+            .clone()
+            .ok_or_else(AsyncPaymentsEngine::stopped)?;
+        let source_actor = match routes.clients.get(&source) {
+            Some(&actor) if actor != target_actor => actor,
+            // the target's engine refuses an unknown source or a merge into itself
+            _ => {
+                return ask(&target_mailbox, |reply| {
+                    Message::Merge(source, target, reply)
+                })
+                .await?;
+            }
+        };
+        let Some(source_mailbox) = routes.actors[source_actor].take() else {
+            return Err(AsyncPaymentsEngine::stopped());
+        };
+        let engine = ask(&source_mailbox, Message::Surrender).await?;
+        let (result, refused) = ask(&target_mailbox, |reply| Message::Absorb {
+            engine: Box::new(engine),
+            source,
+            target,
+            reply,
+        })
+        .await?;
+        match refused {
+            Some(engine) => routes.actors[source_actor] = Some(self.start(engine)),
+            None => {
+                for actor in routes.clients.values_mut() {
+                    if *actor == source_actor {
+                        *actor = target_actor;
+                    }
+                }
+            }
+        }
+
+        result
+    }
+}
+
+// what routing a tx to its client's actor made of its tx id
+pub(crate) enum Claim {
+    // the tx stores no record of its own, or its actor's engine already holds the id
+    Unclaimed,
+    // the id was free and now belongs to the tx's client
+    Claimed,
+    // another actor's engine holds the id, so this one wouldn't see the duplicate
+    Taken,
+}
+
+// claims `tx`'s id for its client, whose actor `clients` must already map
+pub(crate) fn claim(
+    owners: &Mutex<HashMap<u32, u16>>,
+    clients: &HashMap<u16, usize>,
+    tx: &Transaction,
+) -> Claim {
+    if !PaymentsEngine::creates_record(tx.tx_type) {
+        return Claim::Unclaimed;
+    }
+    let actor = clients[&tx.account_id];
+    let mut owners = owners.lock().expect("no actor panics holding the lock");
+    match owners.get(&tx.tx_id) {
+        Some(owner) if clients.get(owner) != Some(&actor) => Claim::Taken,
+        Some(_) => Claim::Unclaimed,
+        None => {
+            owners.insert(tx.tx_id, tx.account_id);
+            Claim::Claimed
+        }
+    }
+}
+
+// the reply to a tx whose id was `Taken`
+pub(crate) fn taken(policy: DuplicatePolicy, tx: &Transaction) -> Result<()> {
+    match policy {
+        DuplicatePolicy::Reject => {
+            Err(Error::DuplicateTransaction(tx.tx_id).with_context(ErrorContext::for_tx(tx)))
+        }
+        DuplicatePolicy::Skip => Ok(()),
+    }
+}
+
+// applies `tx` on its actor, giving a `claimed` tx id back for another client to use if the tx
+// wasn't stored after all
+pub(crate) fn apply(
+    engine: &mut PaymentsEngine,
+    tx: &Transaction,
+    claimed: bool,
+    owners: &Mutex<HashMap<u32, u16>>,
+) -> Result<()> {
+    let result = engine.process_tx(tx);
+    if claimed && !engine.has_transaction(tx.tx_id).unwrap_or(true) {
+        owners
+            .lock()
+            .expect("no actor panics holding the lock")
+            .remove(&tx.tx_id);
+    }
+    result
+}
+
+async fn ask<T>(
+    mailbox: &mpsc::Sender<Message>,
+    message: impl FnOnce(oneshot::Sender<T>) -> Message,
+) -> Result<T> {
+    let (reply, response) = oneshot::channel();
+    let _ = mailbox.send(message(reply)).await;
+
+    response.await.map_err(|_| AsyncPaymentsEngine::stopped())
+}
+
+// owns one client's engine (or several clients', once merged); a dropped reply just means the
+// caller stopped waiting, so it's ignored
+async fn act(mut engine: PaymentsEngine, mut mailbox: mpsc::Receiver<Message>, owners: Owners) {
+    while let Some(message) = mailbox.recv().await {
+        match message {
+            Message::Process { tx, claimed, reply } => {
+                let _ = reply.send(apply(&mut engine, &tx, claimed, &owners));
+            }
+            Message::Account(id, reply) => {
+                let _ = reply.send(engine.account(id).cloned());
+            }
+            Message::Accounts(reply) => {
+                let _ = reply.send(engine.accounts().cloned().collect());
+            }
+            Message::Merge(source, target, reply) => {
+                let _ = reply.send(engine.merge_accounts(source, target));
+            }
+            Message::EvictExpired(now, reply) => {
+                let _ = reply.send(engine.evict_expired(now));
+            }
+            Message::Surrender(reply) => {
+                let _ = reply.send(engine);
+                return;
+            }
+            Message::Absorb {
+                engine: other,
+                source,
+                target,
+                reply,
+            } => {
+                let _ = reply.send(match engine.check_merge(&other) {
+                    Ok(()) => (
+                        engine
+                            .merge_state(*other)
+                            .and_then(|()| engine.merge_accounts(source, target)),
+                        None,
+                    ),
+                    Err(e) => (Err(e), Some(*other)),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::amount::Amount;
+    use crate::testing::TxBuilder;
+    use crate::transaction::DEFAULT_CURRENCY;
+
+    async fn total(engine: &AsyncPaymentsEngine, client: u16) -> Amount {
+        engine
+            .account(client)
+            .await
+            .unwrap()
+            .unwrap()
+            .balance(DEFAULT_CURRENCY)
+            .total
+    }
+
+    #[tokio::test]
+    async fn test_spawn_per_account_success() {
+        let engine = AsyncPaymentsEngine::spawn_per_account(PaymentsEngine::new).unwrap();
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(10)).build(),
+            TxBuilder::deposit(2, 2, amount!(20)).build(),
+            TxBuilder::withdrawal(1, 3, amount!(4)).build(),
+            TxBuilder::dispute(2, 2).build(),
+        ] {
+            engine.process(tx).await.unwrap();
+        }
+
+        assert_eq!(total(&engine, 1).await, amount!(6));
+        assert_eq!(
+            engine
+                .account(2)
+                .await
+                .unwrap()
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .held,
+            amount!(20)
+        );
+        assert_eq!(engine.accounts().await.unwrap().len(), 2);
+        assert!(engine.account(3).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_per_account_keeps_tx_ids_unique() {
+        let engine = AsyncPaymentsEngine::spawn_per_account(PaymentsEngine::new).unwrap();
+        engine
+            .process(TxBuilder::deposit(1, 1, amount!(10)).build())
+            .await
+            .unwrap();
+        // a failed withdrawal leaves its tx id free
+        assert!(
+            engine
+                .process(TxBuilder::withdrawal(1, 2, amount!(50)).build())
+                .await
+                .is_err()
+        );
+
+        let result = engine
+            .process(TxBuilder::deposit(2, 1, amount!(5)).build())
+            .await;
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::DuplicateTransaction(1)
+        ));
+        engine
+            .process(TxBuilder::deposit(2, 2, amount!(5)).build())
+            .await
+            .unwrap();
+        assert_eq!(total(&engine, 2).await, amount!(5));
+    }
+
+    #[tokio::test]
+    async fn test_merge_accounts_across_actors() {
+        let engine = AsyncPaymentsEngine::spawn_per_account(PaymentsEngine::new).unwrap();
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(10)).build(),
+            TxBuilder::deposit(2, 2, amount!(20)).build(),
+        ] {
+            engine.process(tx).await.unwrap();
+        }
+
+        engine.merge_accounts(2, 1).await.unwrap();
+
+        assert_eq!(total(&engine, 1).await, amount!(30));
+        assert!(engine.account(2).await.unwrap().is_none());
+        // the source's transactions went with it
+        engine
+            .process(TxBuilder::dispute(1, 2).build())
+            .await
+            .unwrap();
+        assert!(engine.merge_accounts(3, 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_per_account_failure_fee_schedule() {
+        let schedule = crate::FeeSchedule::from_toml("fee_account = 999\n").unwrap();
+
+        let result = AsyncPaymentsEngine::spawn_per_account(move || {
+            PaymentsEngine::builder()
+                .fee_schedule(schedule.clone())
+                .build()
+        });
+
+        assert!(matches!(result.err().unwrap(), Error::ConfigError(_)));
+    }
+}
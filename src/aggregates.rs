@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use payments_engine::{Amount, Event, TransactionType};
+
+// one client's activity in one currency over a window of the stream, from `start` up to `end`
+// (seconds since the Unix epoch)
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientAggregate {
+    pub start: u64,
+    pub end: u64,
+    pub client: u16,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub currency: String,
+    // deposits credited, provisional ones included
+    pub deposits: Amount,
+    pub deposit_count: u64,
+    pub withdrawals: Amount,
+    pub withdrawal_count: u64,
+    pub disputes: u64,
+    pub chargebacks: u64,
+    // the signed sum of money credited to and debited from the client by the window's
+    // transactions; holds and releases, which move money within the account, don't count
+    pub net_flow: Amount,
+}
+
+// per-client totals of the events seen since the window started, over windows of `length`
+// seconds following each other
+#[derive(Debug)]
+pub struct WindowAggregates {
+    start: u64,
+    length: u64,
+    clients: BTreeMap<(u16, String), ClientAggregate>,
+}
+
+impl WindowAggregates {
+    pub fn new(start: u64, length: u64) -> Self {
+        Self {
+            start,
+            length,
+            clients: BTreeMap::new(),
+        }
+    }
+
+    // when the current window closes
+    pub fn end(&self) -> u64 {
+        self.start + self.length
+    }
+
+    pub fn add(&mut self, event: &Event) {
+        match event {
+            Event::Deposited {
+                client,
+                currency,
+                amount,
+                ..
+            }
+            | Event::ProvisionalDeposited {
+                client,
+                currency,
+                amount,
+                ..
+            } => {
+                let aggregate = self.client(*client, currency);
+                aggregate.deposits += *amount;
+                aggregate.deposit_count += 1;
+                aggregate.net_flow += *amount;
+            }
+            Event::WithdrawalApplied {
+                client,
+                currency,
+                amount,
+                ..
+            } => {
+                let aggregate = self.client(*client, currency);
+                aggregate.withdrawals += *amount;
+                aggregate.withdrawal_count += 1;
+                aggregate.net_flow -= *amount;
+            }
+            Event::DisputeOpened {
+                client, currency, ..
+            } => self.client(*client, currency).disputes += 1,
+            Event::ChargebackApplied {
+                client,
+                tx_type,
+                currency,
+                amount,
+                ..
+            } => {
+                let aggregate = self.client(*client, currency);
+                aggregate.chargebacks += 1;
+                // a charged-back withdrawal is paid back to the client
+                match tx_type {
+                    TransactionType::Withdrawal => aggregate.net_flow += *amount,
+                    _ => aggregate.net_flow -= *amount,
+                }
+            }
+            Event::Captured {
+                client,
+                currency,
+                amount,
+                ..
+            }
+            | Event::Refunded {
+                client,
+                currency,
+                amount,
+                ..
+            } => self.client(*client, currency).net_flow -= *amount,
+            Event::InterestAccrued {
+                client,
+                currency,
+                amount,
+                ..
+            }
+            | Event::Adjusted {
+                client,
+                currency,
+                amount,
+                ..
+            }
+            | Event::Reversed {
+                client,
+                currency,
+                amount,
+                ..
+            }
+            | Event::CustomApplied {
+                client,
+                currency,
+                amount,
+                ..
+            } => self.client(*client, currency).net_flow += *amount,
+            Event::FeeCharged {
+                client,
+                currency,
+                amount,
+                fee_account,
+                ..
+            } => {
+                self.client(*client, currency).net_flow -= *amount;
+                self.client(*fee_account, currency).net_flow += *amount;
+            }
+            _ => {}
+        }
+    }
+
+    // the totals of every client with activity in the window, which closes, leaving the next
+    // one starting where it ended
+    pub fn close(&mut self) -> Vec<ClientAggregate> {
+        let clients = std::mem::take(&mut self.clients);
+        let end = self.end();
+        let start = std::mem::replace(&mut self.start, end);
+        clients
+            .into_values()
+            .map(|aggregate| ClientAggregate {
+                start,
+                end,
+                ..aggregate
+            })
+            .collect()
+    }
+
+    fn client(&mut self, client: u16, currency: &str) -> &mut ClientAggregate {
+        self.clients
+            .entry((client, currency.to_string()))
+            .or_insert_with(|| ClientAggregate {
+                client,
+                currency: currency.to_string(),
+                ..ClientAggregate::default()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::amount;
+
+    #[test]
+    fn test_close() {
+        let mut window = WindowAggregates::new(60, 60);
+        for event in [
+            Event::Deposited {
+                client: 1,
+                tx: 1,
+                currency: String::new(),
+                amount: amount!(100),
+                timestamp: None,
+            },
+            Event::Deposited {
+                client: 1,
+                tx: 2,
+                currency: String::new(),
+                amount: amount!(50),
+                timestamp: None,
+            },
+            Event::WithdrawalApplied {
+                client: 1,
+                tx: 3,
+                currency: String::new(),
+                amount: amount!(30),
+                timestamp: None,
+            },
+            Event::DisputeOpened {
+                client: 1,
+                tx: 2,
+                tx_type: TransactionType::Deposit,
+                currency: String::new(),
+                amount: amount!(50),
+            },
+            Event::ChargebackApplied {
+                client: 1,
+                tx: 2,
+                tx_type: TransactionType::Deposit,
+                currency: String::new(),
+                amount: amount!(50),
+            },
+            Event::FeeCharged {
+                client: 1,
+                tx: 3,
+                currency: String::new(),
+                amount: amount!(1),
+                fee_account: 9,
+            },
+            Event::AccountLocked {
+                client: 1,
+                reason: "chargeback of tx 2".to_string(),
+            },
+        ] {
+            window.add(&event);
+        }
+
+        assert_eq!(
+            window.close(),
+            [
+                ClientAggregate {
+                    start: 60,
+                    end: 120,
+                    client: 1,
+                    currency: String::new(),
+                    deposits: amount!(150),
+                    deposit_count: 2,
+                    withdrawals: amount!(30),
+                    withdrawal_count: 1,
+                    disputes: 1,
+                    chargebacks: 1,
+                    net_flow: amount!(69),
+                },
+                ClientAggregate {
+                    start: 60,
+                    end: 120,
+                    client: 9,
+                    net_flow: amount!(1),
+                    ..ClientAggregate::default()
+                },
+            ]
+        );
+        // the next window starts empty where this one ended
+        window.add(&Event::Deposited {
+            client: 2,
+            tx: 4,
+            currency: "EUR".to_string(),
+            amount: amount!(5),
+            timestamp: None,
+        });
+        let next = window.close();
+        assert_eq!(next.len(), 1);
+        assert_eq!((next[0].start, next[0].client), (120, 2));
+        assert_eq!(
+            serde_json::to_string(&next[0]).unwrap(),
+            r#"{"start":120,"end":180,"client":2,"currency":"EUR","deposits":"5","deposit_count":1,"withdrawals":"0","withdrawal_count":0,"disputes":0,"chargebacks":0,"net_flow":"5"}"#
+        );
+    }
+}
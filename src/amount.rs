@@ -0,0 +1,342 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::error::{Error, Result};
+
+/// Decimal places amounts are kept to. Input amounts with more are brought in line by a
+/// [`PrecisionPolicy`].
+pub const AMOUNT_DP: u32 = 4;
+
+/// A monetary amount.
+///
+/// By default this is [`Decimal`], exact at any scale. With the `fixed-point` feature it is an
+/// i64 count of minor units at 4 decimal places instead, which is half the size and faster, but
+/// refuses amounts with more decimal places or beyond about ±922 trillion.
+#[cfg(not(feature = "fixed-point"))]
+pub type Amount = Decimal;
+
+#[cfg(feature = "fixed-point")]
+pub use fixed::Amount;
+
+/// Conversions between [`Amount`] and [`Decimal`], whichever representation `Amount` uses, e.g.
+/// for formatting or for interfaces that deal in decimals.
+pub trait AmountExt: Sized {
+    fn to_decimal(self) -> Decimal;
+
+    /// Fails with [`Error::TransactionError`](crate::Error::TransactionError) if `value` can't
+    /// be represented.
+    fn from_decimal(value: Decimal) -> Result<Self>;
+}
+
+#[cfg(not(feature = "fixed-point"))]
+impl AmountExt for Decimal {
+    fn to_decimal(self) -> Decimal {
+        self
+    }
+
+    fn from_decimal(value: Decimal) -> Result<Self> {
+        Ok(value)
+    }
+}
+
+/// What happens to input amounts with more than [`AMOUNT_DP`] decimal places.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrecisionPolicy {
+    /// Fail the transaction.
+    Reject,
+    /// Drop the extra places, rounding toward zero.
+    Truncate,
+    /// Round to [`AMOUNT_DP`] places.
+    Round(RoundingMode),
+}
+
+/// Rounds half to even, the way account balances are rounded for output.
+impl Default for PrecisionPolicy {
+    fn default() -> Self {
+        PrecisionPolicy::Round(RoundingMode::HalfEven)
+    }
+}
+
+impl PrecisionPolicy {
+    /// `amount` with at most [`AMOUNT_DP`] decimal places. Fails with
+    /// [`Error::TransactionError`] under [`PrecisionPolicy::Reject`] if it has more.
+    pub fn apply(self, amount: Decimal) -> Result<Decimal> {
+        // trailing zeros don't count as extra precision
+        if amount.normalize().scale() <= AMOUNT_DP {
+            return Ok(amount);
+        }
+        let strategy = match self {
+            PrecisionPolicy::Reject => {
+                return Err(Error::TransactionError(
+                    "Amount has more than 4 decimal places.",
+                ));
+            }
+            PrecisionPolicy::Truncate => RoundingStrategy::ToZero,
+            PrecisionPolicy::Round(mode) => mode.strategy(),
+        };
+
+        Ok(amount.round_dp_with_strategy(AMOUNT_DP, strategy))
+    }
+}
+
+/// How [`PrecisionPolicy::Round`] rounds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Halfway cases go to the even neighbour (banker's rounding).
+    #[default]
+    HalfEven,
+    /// Halfway cases go away from zero.
+    HalfUp,
+    /// Halfway cases go toward zero.
+    HalfDown,
+    /// Always toward positive infinity.
+    Ceiling,
+    /// Always toward negative infinity.
+    Floor,
+}
+
+impl RoundingMode {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            RoundingMode::HalfEven => RoundingStrategy::MidpointNearestEven,
+            RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::HalfDown => RoundingStrategy::MidpointTowardZero,
+            RoundingMode::Ceiling => RoundingStrategy::ToPositiveInfinity,
+            RoundingMode::Floor => RoundingStrategy::ToNegativeInfinity,
+        }
+    }
+}
+
+/// An [`Amount`] literal, written like a [`rust_decimal::dec!`] literal: `amount!(10.5)`.
+///
+/// Panics if the literal can't be represented.
+#[macro_export]
+macro_rules! amount {
+    ($($literal:tt)+) => {
+        <$crate::Amount as $crate::AmountExt>::from_decimal(::rust_decimal::dec!($($literal)+))
+            .expect("amount literal out of range")
+    };
+}
+
+#[cfg(feature = "fixed-point")]
+mod fixed {
+    use std::fmt;
+    use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+    use std::str::FromStr;
+
+    use rust_decimal::{Decimal, prelude::ToPrimitive};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::AmountExt;
+    use crate::error::{Error, Result};
+
+    const SCALE: u32 = super::AMOUNT_DP;
+    const UNITS_PER_ONE: i64 = 10_000;
+
+    /// A monetary amount as an i64 count of minor units (1/10,000ths).
+    ///
+    /// Arithmetic panics on overflow like [`Decimal`]'s; use
+    /// [`checked_add`](Self::checked_add)/[`checked_sub`](Self::checked_sub) where overflow is
+    /// possible.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct Amount(i64);
+
+    impl Amount {
+        pub const ZERO: Amount = Amount(0);
+        pub const ONE: Amount = Amount(UNITS_PER_ONE);
+        pub const MAX: Amount = Amount(i64::MAX);
+        pub const MIN: Amount = Amount(i64::MIN);
+
+        /// `num * 10^-scale`, like [`Decimal::new`]. Panics if `scale` is above 4 or the
+        /// amount is out of range.
+        pub fn new(num: i64, scale: u32) -> Self {
+            assert!(scale <= SCALE, "amounts have at most 4 decimal places");
+            Amount(
+                num.checked_mul(10i64.pow(SCALE - scale))
+                    .expect("amount out of range"),
+            )
+        }
+
+        pub fn from_minor_units(units: i64) -> Self {
+            Amount(units)
+        }
+
+        pub fn minor_units(self) -> i64 {
+            self.0
+        }
+
+        pub fn checked_add(self, other: Amount) -> Option<Amount> {
+            self.0.checked_add(other.0).map(Amount)
+        }
+
+        pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+            self.0.checked_sub(other.0).map(Amount)
+        }
+
+        pub fn is_sign_negative(self) -> bool {
+            self.0 < 0
+        }
+    }
+
+    impl AmountExt for Amount {
+        fn to_decimal(self) -> Decimal {
+            Decimal::new(self.0, SCALE)
+        }
+
+        fn from_decimal(value: Decimal) -> Result<Self> {
+            let units = value
+                .checked_mul(Decimal::from(UNITS_PER_ONE))
+                .ok_or(Error::TransactionError("Amount is out of range."))?;
+            if !units.fract().is_zero() {
+                return Err(Error::TransactionError(
+                    "Amount has more than 4 decimal places.",
+                ));
+            }
+
+            units
+                .to_i64()
+                .map(Amount)
+                .ok_or(Error::TransactionError("Amount is out of range."))
+        }
+    }
+
+    impl Add for Amount {
+        type Output = Amount;
+
+        fn add(self, other: Amount) -> Amount {
+            self.checked_add(other).expect("amount overflow")
+        }
+    }
+
+    impl Sub for Amount {
+        type Output = Amount;
+
+        fn sub(self, other: Amount) -> Amount {
+            self.checked_sub(other).expect("amount overflow")
+        }
+    }
+
+    impl AddAssign for Amount {
+        fn add_assign(&mut self, other: Amount) {
+            *self = *self + other;
+        }
+    }
+
+    impl SubAssign for Amount {
+        fn sub_assign(&mut self, other: Amount) {
+            *self = *self - other;
+        }
+    }
+
+    impl Neg for Amount {
+        type Output = Amount;
+
+        fn neg(self) -> Amount {
+            Amount(self.0.checked_neg().expect("amount overflow"))
+        }
+    }
+
+    // trailing zeros are dropped, so `10.5` prints as written rather than as `10.5000`
+    impl fmt::Display for Amount {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            fmt::Display::fmt(&self.to_decimal().normalize(), f)
+        }
+    }
+
+    impl FromStr for Amount {
+        type Err = Error;
+
+        fn from_str(s: &str) -> Result<Self> {
+            let value = Decimal::from_str(s)
+                .map_err(|_| Error::TransactionError("Invalid transaction amount."))?;
+            Amount::from_decimal(value)
+        }
+    }
+
+    // the same representation as `Decimal`, so persisted state and events read either way
+    impl Serialize for Amount {
+        fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            Serialize::serialize(&self.to_decimal().normalize(), serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Amount {
+        fn deserialize<D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> std::result::Result<Self, D::Error> {
+            let value = <Decimal as Deserialize>::deserialize(deserializer)?;
+            Amount::from_decimal(value).map_err(serde::de::Error::custom)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use rust_decimal::dec;
+
+        #[test]
+        fn test_from_decimal_success() {
+            assert_eq!(
+                Amount::from_decimal(dec!(10.0001)).unwrap().minor_units(),
+                100_001
+            );
+            assert_eq!(
+                Amount::from_decimal(dec!(-2.5)).unwrap(),
+                Amount::new(-25, 1)
+            );
+        }
+
+        #[test]
+        fn test_from_decimal_failure_precision() {
+            assert!(matches!(
+                Amount::from_decimal(dec!(0.00001)),
+                Err(Error::TransactionError(_))
+            ));
+            assert!(matches!(
+                Amount::from_decimal(dec!(1000000000000000)),
+                Err(Error::TransactionError(_))
+            ));
+        }
+
+        #[test]
+        fn test_serde_round_trip() {
+            let amount: Amount = serde_json::from_str("\"10.50\"").unwrap();
+
+            assert_eq!(amount.to_string(), "10.5");
+            assert_eq!(serde_json::to_string(&amount).unwrap(), "\"10.5\"");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_precision_policy_success() {
+        let round = |mode| PrecisionPolicy::Round(mode).apply(dec!(-1.00005)).unwrap();
+
+        assert_eq!(round(RoundingMode::HalfEven), dec!(-1.0000));
+        assert_eq!(round(RoundingMode::HalfUp), dec!(-1.0001));
+        assert_eq!(round(RoundingMode::HalfDown), dec!(-1.0000));
+        assert_eq!(round(RoundingMode::Ceiling), dec!(-1.0000));
+        assert_eq!(round(RoundingMode::Floor), dec!(-1.0001));
+        assert_eq!(
+            PrecisionPolicy::Truncate.apply(dec!(2.99999)).unwrap(),
+            dec!(2.9999)
+        );
+        // amounts within the limit pass through unchanged, trailing zeros and all
+        assert_eq!(
+            PrecisionPolicy::Reject.apply(dec!(1.500000)).unwrap(),
+            dec!(1.500000)
+        );
+    }
+
+    #[test]
+    fn test_precision_policy_failure_reject() {
+        assert!(matches!(
+            PrecisionPolicy::Reject.apply(dec!(0.00001)),
+            Err(Error::TransactionError(_))
+        ));
+    }
+}
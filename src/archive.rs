@@ -0,0 +1,54 @@
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::{
+    error::{Error, Result},
+    transaction::TxRecord,
+};
+
+/// Where the engine writes the transactions it evicts, before dropping them from the store.
+pub trait ArchiveSink {
+    /// Archives the record of tx `tx`. An error here fails the eviction, and the record stays
+    /// in the store.
+    fn archive(&mut self, tx: u32, record: &TxRecord) -> Result<()>;
+
+    /// Pushes out any buffered records.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// an evicted transaction as written to the archive
+#[derive(Serialize)]
+struct ArchivedTx<'a> {
+    tx: u32,
+    #[serde(flatten)]
+    record: &'a TxRecord,
+}
+
+/// Writes archived transactions as JSON lines, each record flattened next to its `tx` id.
+pub struct JsonlArchive<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonlArchive<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> ArchiveSink for JsonlArchive<W> {
+    fn archive(&mut self, tx: u32, record: &TxRecord) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, &ArchivedTx { tx, record })
+            .map_err(std::io::Error::other)
+            .and_then(|()| writeln!(self.writer))
+            .map_err(|e| Error::StoreError(format!("failed to archive tx {}: {}", tx, e)))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .map_err(|e| Error::StoreError(format!("failed to flush archive: {}", e)))
+    }
+}
@@ -0,0 +1,405 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use arrow_array::{
+    Array, ArrayRef, BooleanArray, Decimal128Array, RecordBatch, StringArray, UInt16Array,
+    cast::AsArray,
+    types::{
+        Decimal128Type, Int8Type, Int16Type, Int32Type, Int64Type, UInt8Type, UInt16Type,
+        UInt32Type, UInt64Type,
+    },
+};
+use arrow_schema::{DataType, Field, Schema};
+use rust_decimal::Decimal;
+use serde::{
+    Deserialize,
+    de::{IntoDeserializer, value::StrDeserializer},
+};
+
+use crate::account::Balance;
+use crate::amount::{AMOUNT_DP, AmountExt};
+use crate::engine::PaymentsEngine;
+use crate::error::{Error, ErrorContext, Result};
+use crate::transaction::{DEFAULT_CURRENCY, Transaction, TransactionRow, TransactionType};
+
+// what a column holds, as far as reading it is concerned
+#[derive(Clone, Copy)]
+enum Kind {
+    String,
+    Integer,
+    Amount,
+}
+
+impl Kind {
+    fn accepts(self, data_type: &DataType) -> bool {
+        let string = matches!(
+            data_type,
+            DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View
+        );
+        match self {
+            Kind::String => string,
+            Kind::Integer => data_type.is_integer(),
+            // floats are refused: they can't hold most amounts exactly
+            Kind::Amount => string || matches!(data_type, DataType::Decimal128(..)),
+        }
+    }
+}
+
+// the columns of an input batch, checked against what each must hold up front so rows can't
+// fail on a column's type
+struct Columns<'a> {
+    tx_type: &'a dyn Array,
+    client: &'a dyn Array,
+    tx: &'a dyn Array,
+    amount: Option<&'a dyn Array>,
+    currency: Option<&'a dyn Array>,
+    timestamp: Option<&'a dyn Array>,
+    reason: Option<&'a dyn Array>,
+    effective: Option<&'a dyn Array>,
+}
+
+impl<'a> Columns<'a> {
+    fn for_batch(batch: &'a RecordBatch) -> Result<Self> {
+        let column = |name: &str, kind: Kind| -> Result<Option<&'a dyn Array>> {
+            let Some(column) = batch.column_by_name(name) else {
+                return Ok(None);
+            };
+            if !kind.accepts(column.data_type()) {
+                return Err(Error::SchemaError(format!(
+                    "column `{}` has unsupported type {}",
+                    name,
+                    column.data_type()
+                )));
+            }
+            Ok(Some(column.as_ref()))
+        };
+        let required = |name: &str, kind: Kind| -> Result<&'a dyn Array> {
+            column(name, kind)?
+                .ok_or_else(|| Error::SchemaError(format!("missing required column `{}`", name)))
+        };
+
+        Ok(Columns {
+            tx_type: required("type", Kind::String)?,
+            client: required("client", Kind::Integer)?,
+            tx: required("tx", Kind::Integer)?,
+            amount: column("amount", Kind::Amount)?,
+            currency: column("currency", Kind::String)?,
+            timestamp: column("timestamp", Kind::Integer)?,
+            reason: column("reason", Kind::String)?,
+            effective: column("effective", Kind::Integer)?,
+        })
+    }
+
+    fn transaction(&self, row: usize) -> Result<Transaction> {
+        let tx_type = string(self.tx_type, row)
+            .and_then(|name| {
+                let name: StrDeserializer<'_, serde::de::value::Error> = name.into_deserializer();
+                TransactionType::deserialize(name).ok()
+            })
+            .ok_or(Error::TransactionError("Invalid transaction type."))?;
+        let account_id = integer(self.client, row)
+            .and_then(|client| u16::try_from(client).ok())
+            .ok_or(Error::TransactionError("Invalid client id."))?;
+        let tx_id = integer(self.tx, row)
+            .and_then(|tx| u32::try_from(tx).ok())
+            .ok_or(Error::TransactionError("Invalid tx id."))?;
+        let amount = match self.amount {
+            Some(amount) => decimal(amount, row)?,
+            None => None,
+        };
+        let timestamp = match self.timestamp.and_then(|timestamp| integer(timestamp, row)) {
+            Some(timestamp) => Some(
+                u64::try_from(timestamp)
+                    .map_err(|_| Error::TransactionError("Invalid timestamp."))?,
+            ),
+            None => None,
+        };
+        let effective = match self.effective.and_then(|effective| integer(effective, row)) {
+            Some(effective) => Some(
+                u64::try_from(effective)
+                    .map_err(|_| Error::TransactionError("Invalid effective date."))?,
+            ),
+            None => None,
+        };
+        let text = |column: Option<&dyn Array>| {
+            column
+                .and_then(|column| string(column, row))
+                .map(str::to_string)
+        };
+
+        TransactionRow {
+            tx_type,
+            account_id,
+            tx_id,
+            amount,
+            currency: text(self.currency),
+            timestamp,
+            reason: text(self.reason),
+            effective,
+        }
+        .try_into()
+    }
+}
+
+fn string(column: &dyn Array, row: usize) -> Option<&str> {
+    if column.is_null(row) {
+        return None;
+    }
+    match column.data_type() {
+        DataType::Utf8 => Some(column.as_string::<i32>().value(row)),
+        DataType::LargeUtf8 => Some(column.as_string::<i64>().value(row)),
+        DataType::Utf8View => Some(column.as_string_view().value(row)),
+        _ => None,
+    }
+}
+
+fn integer(column: &dyn Array, row: usize) -> Option<i128> {
+    if column.is_null(row) {
+        return None;
+    }
+    Some(match column.data_type() {
+        DataType::Int8 => column.as_primitive::<Int8Type>().value(row).into(),
+        DataType::Int16 => column.as_primitive::<Int16Type>().value(row).into(),
+        DataType::Int32 => column.as_primitive::<Int32Type>().value(row).into(),
+        DataType::Int64 => column.as_primitive::<Int64Type>().value(row).into(),
+        DataType::UInt8 => column.as_primitive::<UInt8Type>().value(row).into(),
+        DataType::UInt16 => column.as_primitive::<UInt16Type>().value(row).into(),
+        DataType::UInt32 => column.as_primitive::<UInt32Type>().value(row).into(),
+        DataType::UInt64 => column.as_primitive::<UInt64Type>().value(row).into(),
+        _ => return None,
+    })
+}
+
+fn decimal(column: &dyn Array, row: usize) -> Result<Option<Decimal>> {
+    if column.is_null(row) {
+        return Ok(None);
+    }
+    let amount = match column.data_type() {
+        DataType::Decimal128(_, scale) => u32::try_from(*scale).ok().and_then(|scale| {
+            let value = column.as_primitive::<Decimal128Type>().value(row);
+            Decimal::try_from_i128_with_scale(value, scale).ok()
+        }),
+        _ => string(column, row).and_then(|amount| Decimal::from_str(amount).ok()),
+    };
+
+    amount
+        .map(Some)
+        .ok_or(Error::TransactionError("Invalid amount."))
+}
+
+// an amount as a `Decimal128` at the engine's scale
+fn minor_units(amount: Decimal) -> i128 {
+    let mut amount = amount;
+    amount.rescale(AMOUNT_DP);
+    amount.mantissa()
+}
+
+impl PaymentsEngine {
+    /// Applies each row of an Arrow `batch` in order, like [`process_tx`](Self::process_tx),
+    /// returning the rows that failed. Each failure's [`context`](Error::context) has the
+    /// 1-based row within the batch as its `line`.
+    ///
+    /// Columns are found by name, as in the CSV input: `type`, `client` and `tx` are required,
+    /// and `amount`, `currency`, `timestamp` and `reason` are optional. Strings can be `Utf8`,
+    /// `LargeUtf8` or `Utf8View` and ids any integer type. Amounts are `Decimal128` or strings;
+    /// floats are refused.
+    ///
+    /// Fails without applying anything with [`Error::SchemaError`] when a column is missing or
+    /// of the wrong type. Stops at an [`Error::StoreError`], [`Error::EventError`] or
+    /// [`Error::AuditError`], as those mean state or its log can no longer be trusted.
+    pub fn process_record_batch(&mut self, batch: &RecordBatch) -> Result<Vec<Error>> {
+        let columns = Columns::for_batch(batch)?;
+        let mut rejected = Vec::new();
+        for row in 0..batch.num_rows() {
+            let Err(error) = columns.transaction(row).and_then(|tx| self.process_tx(&tx)) else {
+                continue;
+            };
+            let error = error.with_context(ErrorContext {
+                line: Some(row as u64 + 1),
+                ..ErrorContext::default()
+            });
+            if let Error::StoreError(_) | Error::EventError(_) | Error::AuditError(_) = error.root()
+            {
+                return Err(error);
+            }
+            rejected.push(error);
+        }
+
+        Ok(rejected)
+    }
+
+    /// The accounts as an Arrow batch, a row per client and currency (a client with no
+    /// balances gets a zero row in the default currency): `client` (`UInt16`), `currency`
+    /// (`Utf8`, empty for the default), `available`, `held` and `total` (`Decimal128(38, 4)`),
+    /// `locked` (`Boolean`) and `status` (`Utf8`).
+    pub fn accounts_as_record_batch(&self) -> Result<RecordBatch> {
+        let mut clients = Vec::new();
+        let mut currencies = Vec::new();
+        let mut available = Vec::new();
+        let mut held = Vec::new();
+        let mut total = Vec::new();
+        let mut locked = Vec::new();
+        let mut statuses = Vec::new();
+        for account in self.accounts() {
+            // an account that never held funds still gets a (zero) row
+            let zero = [(DEFAULT_CURRENCY, Balance::default())];
+            let balances: Vec<_> = match account.balances.is_empty() {
+                true => zero.to_vec(),
+                false => account
+                    .balances
+                    .iter()
+                    .map(|(currency, balance)| (currency.as_str(), *balance))
+                    .collect(),
+            };
+            for (currency, balance) in balances {
+                clients.push(account.id);
+                currencies.push(currency);
+                available.push(minor_units(balance.available.to_decimal()));
+                held.push(minor_units(balance.held.to_decimal()));
+                total.push(minor_units(balance.total.to_decimal()));
+                locked.push(account.is_locked());
+                statuses.push(account.status.to_string());
+            }
+        }
+
+        let amount = |values: Vec<i128>| -> Result<ArrayRef> {
+            let array = Decimal128Array::from(values)
+                .with_precision_and_scale(38, AMOUNT_DP as i8)
+                .map_err(|e| Error::SchemaError(e.to_string()))?;
+            Ok(Arc::new(array))
+        };
+        let amount_type = DataType::Decimal128(38, AMOUNT_DP as i8);
+        let schema = Schema::new(vec![
+            Field::new("client", DataType::UInt16, false),
+            Field::new("currency", DataType::Utf8, false),
+            Field::new("available", amount_type.clone(), false),
+            Field::new("held", amount_type.clone(), false),
+            Field::new("total", amount_type, false),
+            Field::new("locked", DataType::Boolean, false),
+            Field::new("status", DataType::Utf8, false),
+        ]);
+
+        RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(UInt16Array::from(clients)),
+                Arc::new(StringArray::from(currencies)),
+                amount(available)?,
+                amount(held)?,
+                amount(total)?,
+                Arc::new(BooleanArray::from(locked)),
+                Arc::new(StringArray::from(statuses)),
+            ],
+        )
+        .map_err(|e| Error::SchemaError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use arrow_array::{Int64Array, UInt32Array};
+
+    fn batch(columns: Vec<(&str, ArrayRef)>) -> RecordBatch {
+        RecordBatch::try_from_iter(columns).unwrap()
+    }
+
+    #[test]
+    fn test_process_record_batch_success() {
+        let mut engine = PaymentsEngine::new();
+        let input = batch(vec![
+            (
+                "type",
+                Arc::new(StringArray::from(vec![
+                    "deposit",
+                    "deposit",
+                    "withdrawal",
+                    "refill",
+                    "withdrawal",
+                ])),
+            ),
+            (
+                "client",
+                Arc::new(Int64Array::from(vec![1, 2, 1, 1, 70_000])),
+            ),
+            ("tx", Arc::new(UInt32Array::from(vec![1, 2, 3, 4, 5]))),
+            (
+                "amount",
+                Arc::new(
+                    Decimal128Array::from(vec![Some(10_500), Some(3_000), Some(2_250), None, None])
+                        .with_precision_and_scale(10, 3)
+                        .unwrap(),
+                ),
+            ),
+        ]);
+
+        let rejected = engine.process_record_batch(&input).unwrap();
+
+        assert_eq!(rejected.len(), 2);
+        assert_eq!(rejected[0].context().unwrap().line, Some(4));
+        assert!(matches!(rejected[0].root(), Error::TransactionError(_)));
+        assert_eq!(rejected[1].context().unwrap().line, Some(5));
+        assert_eq!(
+            engine
+                .account(1)
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .available,
+            amount!(8.25)
+        );
+
+        let output = engine.accounts_as_record_batch().unwrap();
+        assert_eq!(output.num_rows(), 2);
+        let clients = output
+            .column_by_name("client")
+            .unwrap()
+            .as_primitive::<UInt16Type>();
+        let row = clients
+            .values()
+            .iter()
+            .position(|&client| client == 1)
+            .unwrap();
+        let total = output
+            .column_by_name("total")
+            .unwrap()
+            .as_primitive::<Decimal128Type>();
+        assert_eq!(total.value(row), 82_500);
+        assert_eq!(
+            output
+                .column_by_name("status")
+                .unwrap()
+                .as_string::<i32>()
+                .value(row),
+            "active"
+        );
+    }
+
+    #[test]
+    fn test_process_record_batch_failure_schema() {
+        let mut engine = PaymentsEngine::new();
+        let missing = batch(vec![(
+            "type",
+            Arc::new(StringArray::from(vec!["deposit"])) as ArrayRef,
+        )]);
+        let float = batch(vec![
+            ("type", Arc::new(StringArray::from(vec!["deposit"]))),
+            ("client", Arc::new(Int64Array::from(vec![1]))),
+            ("tx", Arc::new(Int64Array::from(vec![1]))),
+            (
+                "amount",
+                Arc::new(arrow_array::Float64Array::from(vec![1.5])),
+            ),
+        ]);
+
+        assert!(matches!(
+            engine.process_record_batch(&missing),
+            Err(Error::SchemaError(_))
+        ));
+        assert!(matches!(
+            engine.process_record_batch(&float),
+            Err(Error::SchemaError(_))
+        ));
+        assert_eq!(engine.accounts().count(), 0);
+    }
+}
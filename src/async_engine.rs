@@ -0,0 +1,355 @@
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::{Stream, StreamExt};
+
+#[cfg(feature = "concurrent-map")]
+use crate::sharded::Shards;
+use crate::{
+    account::Account,
+    actors::Router,
+    engine::PaymentsEngine,
+    error::{Error, Result},
+    transaction::Transaction,
+};
+
+// bounded so a fast producer gets backpressure instead of queueing without limit
+const COMMAND_BUFFER: usize = 1024;
+
+pub(crate) enum Command {
+    Process(Transaction, oneshot::Sender<Result<()>>),
+    Account(u16, oneshot::Sender<Option<Account>>),
+    Accounts(oneshot::Sender<Vec<Account>>),
+    Merge(u16, u16, oneshot::Sender<Result<()>>),
+    EvictExpired(u64, oneshot::Sender<Result<usize>>),
+}
+
+/// Cloneable handle to a [`PaymentsEngine`] running on its own tokio task.
+///
+/// Every handle feeds the same engine, and transactions are applied in the order the task
+/// receives them. The task stops once all handles are dropped.
+///
+/// [`spawn_per_account`](Self::spawn_per_account) runs an engine per client instead, so that
+/// transactions of different clients are applied concurrently, and with the `concurrent-map`
+/// feature [`spawn_sharded`](Self::spawn_sharded) does so without a task per client.
+#[derive(Clone)]
+pub struct AsyncPaymentsEngine {
+    inner: Inner,
+}
+
+#[derive(Clone)]
+enum Inner {
+    Engine(mpsc::Sender<Command>),
+    Actors(Arc<Router>),
+    #[cfg(feature = "concurrent-map")]
+    Sharded(Arc<Shards>),
+}
+
+impl AsyncPaymentsEngine {
+    /// Moves `engine` onto a new task on the current tokio runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside a tokio runtime.
+    pub fn spawn(engine: PaymentsEngine) -> Self {
+        let (commands, receiver) = mpsc::channel(COMMAND_BUFFER);
+        tokio::spawn(run(engine, receiver));
+
+        Self {
+            inner: Inner::Engine(commands),
+        }
+    }
+
+    /// Runs an engine per client instead, each on its own task (an actor) with its own mailbox,
+    /// built by `factory` when the client is first seen. Handles route transactions to them by
+    /// client, so each client's are applied in order while different clients' are applied
+    /// concurrently. The actors stop once all handles are dropped.
+    ///
+    /// Each engine only sees its own client's transactions, so:
+    /// - the handles keep tx ids unique across clients, refusing a deposit or withdrawal whose tx
+    ///   id another client's is using, or is still being applied with, as the duplicate policy
+    ///   says
+    /// - a dispute, resolve, chargeback and the like naming another client's tx fails with
+    ///   [`Error::UnknownTransaction`], whatever the account mismatch policy
+    /// - interest is accrued by each engine from the first timestamp it sees
+    ///
+    /// [`merge_accounts`](Self::merge_accounts) hands the source client's engine over to the
+    /// target's. Engines with a fee schedule are refused with [`Error::ConfigError`], as fees
+    /// are credited to a fee account of their own.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside a tokio runtime.
+    pub fn spawn_per_account<F>(factory: F) -> Result<Self>
+    where
+        F: Fn() -> PaymentsEngine + Send + Sync + 'static,
+    {
+        Ok(Self {
+            inner: Inner::Actors(Arc::new(Router::new(factory)?)),
+        })
+    }
+
+    /// Keeps an engine per client like [`spawn_per_account`](Self::spawn_per_account), with the
+    /// same caveats, but in a concurrent map instead of on tasks of their own. Each call is
+    /// applied on the caller's task under its client's engine lock, so calls for different
+    /// clients don't wait on each other or on a hop to another task. Engines live until all
+    /// handles are dropped.
+    #[cfg(feature = "concurrent-map")]
+    pub fn spawn_sharded<F>(factory: F) -> Result<Self>
+    where
+        F: Fn() -> PaymentsEngine + Send + Sync + 'static,
+    {
+        Ok(Self {
+            inner: Inner::Sharded(Arc::new(Shards::new(factory)?)),
+        })
+    }
+
+    /// Applies a single transaction, with the same semantics as [`PaymentsEngine::process_tx`].
+    pub async fn process(&self, tx: Transaction) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Process(tx, reply)).await?;
+
+        response.await.map_err(|_| Self::stopped())?
+    }
+
+    /// Applies every transaction from `stream` in order, handing failed ones to `on_error` and
+    /// carrying on, like the CLI does.
+    ///
+    /// Only fails if the engine task has stopped, or on an [`Error::EngineError`], which leaves
+    /// the engine unable to carry on.
+    pub async fn process_stream<S, F>(&self, stream: S, mut on_error: F) -> Result<()>
+    where
+        S: Stream<Item = Transaction>,
+        F: FnMut(&Transaction, Error),
+    {
+        let mut stream = std::pin::pin!(stream);
+        while let Some(tx) = stream.next().await {
+            let (reply, response) = oneshot::channel();
+            self.send(Command::Process(tx.clone(), reply)).await?;
+            match response.await.map_err(|_| Self::stopped())? {
+                Ok(()) => {}
+                // engine errors arrive with the failed transaction's context
+                Err(e) if matches!(e.root(), Error::EngineError(_)) => return Err(e),
+                Err(e) => on_error(&tx, e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns a copy of a client's account, if it has been seen.
+    pub async fn account(&self, id: u16) -> Result<Option<Account>> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Account(id, reply)).await?;
+
+        response.await.map_err(|_| Self::stopped())
+    }
+
+    /// Returns a copy of all client accounts, in no particular order.
+    pub async fn accounts(&self) -> Result<Vec<Account>> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Accounts(reply)).await?;
+
+        response.await.map_err(|_| Self::stopped())
+    }
+
+    /// Merges client `source` into `target`, with the same semantics as
+    /// [`PaymentsEngine::merge_accounts`].
+    pub async fn merge_accounts(&self, source: u16, target: u16) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Merge(source, target, reply)).await?;
+
+        response.await.map_err(|_| Self::stopped())?
+    }
+
+    /// Evicts the transactions that can no longer be disputed by `now`, with the same semantics
+    /// as [`PaymentsEngine::evict_expired`], from every engine. Returns the number evicted.
+    ///
+    /// A long-running server calls this periodically so its transaction index doesn't grow
+    /// without bound.
+    pub async fn evict_expired(&self, now: u64) -> Result<usize> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::EvictExpired(now, reply)).await?;
+
+        response.await.map_err(|_| Self::stopped())?
+    }
+
+    async fn send(&self, command: Command) -> Result<()> {
+        match &self.inner {
+            Inner::Engine(commands) => commands.send(command).await.map_err(|_| Self::stopped()),
+            Inner::Actors(router) => {
+                router.dispatch(command).await;
+                Ok(())
+            }
+            #[cfg(feature = "concurrent-map")]
+            Inner::Sharded(shards) => {
+                shards.dispatch(command);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn stopped() -> Error {
+        Error::EngineError("Engine task has stopped.")
+    }
+}
+
+// owns the engine; a dropped reply just means the caller stopped waiting, so it's ignored
+async fn run(mut engine: PaymentsEngine, mut receiver: mpsc::Receiver<Command>) {
+    while let Some(command) = receiver.recv().await {
+        match command {
+            Command::Process(tx, reply) => {
+                let _ = reply.send(engine.process_tx(&tx));
+            }
+            Command::Account(id, reply) => {
+                let _ = reply.send(engine.account(id).cloned());
+            }
+            Command::Accounts(reply) => {
+                let _ = reply.send(engine.accounts().cloned().collect());
+            }
+            Command::Merge(source, target, reply) => {
+                let _ = reply.send(engine.merge_accounts(source, target));
+            }
+            Command::EvictExpired(now, reply) => {
+                let _ = reply.send(engine.evict_expired(now));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::amount::Amount;
+    use crate::testing::TxBuilder;
+    use crate::transaction::DEFAULT_CURRENCY;
+
+    #[tokio::test]
+    async fn test_process_success() {
+        let engine = AsyncPaymentsEngine::spawn(PaymentsEngine::new());
+
+        engine
+            .process(TxBuilder::deposit(1, 1, amount!(10)).build())
+            .await
+            .unwrap();
+
+        let account = engine.account(1).await.unwrap().unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(10));
+        assert_eq!(engine.accounts().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_process_failure_insufficient_funds() {
+        let engine = AsyncPaymentsEngine::spawn(PaymentsEngine::new());
+
+        let result = engine
+            .process(TxBuilder::withdrawal(1, 1, amount!(10)).build())
+            .await;
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::InsufficientFunds(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_continues_past_failures() {
+        let engine = AsyncPaymentsEngine::spawn(PaymentsEngine::new());
+        let txs = tokio_stream::iter(vec![
+            TxBuilder::deposit(1, 1, amount!(10)).build(),
+            TxBuilder::withdrawal(1, 2, amount!(50)).build(),
+            TxBuilder::deposit(1, 3, amount!(5)).build(),
+        ]);
+        let mut failed = Vec::new();
+
+        engine
+            .process_stream(txs, |tx, _| failed.push(tx.tx_id))
+            .await
+            .unwrap();
+
+        assert_eq!(failed, vec![2]);
+        assert_eq!(
+            engine
+                .account(1)
+                .await
+                .unwrap()
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .total,
+            amount!(15)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_failure_engine_error() {
+        let engine = AsyncPaymentsEngine::spawn(PaymentsEngine::new());
+        // the second deposit overflows the ledger, which fails with an engine error
+        let txs = tokio_stream::iter(vec![
+            TxBuilder::deposit(1, 1, Amount::MAX).build(),
+            TxBuilder::deposit(2, 2, amount!(1)).build(),
+            TxBuilder::deposit(1, 3, amount!(5)).build(),
+        ]);
+        let mut failed = Vec::new();
+
+        let result = engine
+            .process_stream(txs, |tx, _| failed.push(tx.tx_id))
+            .await;
+
+        let error = result.unwrap_err();
+        assert!(matches!(error, Error::WithContext { .. }));
+        assert!(matches!(error.root(), Error::EngineError(_)));
+        // the stream stopped there
+        assert!(failed.is_empty());
+        assert_eq!(
+            engine
+                .account(1)
+                .await
+                .unwrap()
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .total,
+            Amount::MAX
+        );
+    }
+
+    #[tokio::test]
+    async fn test_evict_expired_from_every_engine() {
+        const DAY: u64 = 86_400;
+        let factory = || {
+            PaymentsEngine::builder()
+                .dispute_window(std::time::Duration::from_secs(DAY))
+                .eviction_policy(crate::engine::EvictionPolicy::Settled)
+                .build()
+        };
+        let engines = [
+            AsyncPaymentsEngine::spawn(factory()),
+            AsyncPaymentsEngine::spawn_per_account(factory).unwrap(),
+        ];
+        for engine in engines {
+            for (client, tx_id, timestamp) in [(1, 1, 0), (2, 2, 0), (1, 3, 2 * DAY)] {
+                engine
+                    .process(
+                        TxBuilder::deposit(client, tx_id, amount!(10))
+                            .timestamp(timestamp)
+                            .build(),
+                    )
+                    .await
+                    .unwrap();
+            }
+
+            assert_eq!(engine.evict_expired(2 * DAY).await.unwrap(), 2);
+
+            // too old to dispute, unlike the recent one
+            let result = engine
+                .process(TxBuilder::dispute(1, 1).timestamp(2 * DAY).build())
+                .await;
+            assert!(result.is_err());
+            engine
+                .process(TxBuilder::dispute(1, 3).timestamp(2 * DAY).build())
+                .await
+                .unwrap();
+            assert_eq!(engine.evict_expired(2 * DAY).await.unwrap(), 0);
+        }
+    }
+}
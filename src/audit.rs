@@ -0,0 +1,217 @@
+use std::io::Write;
+use std::sync::mpsc::Sender;
+
+use crate::amount::Amount;
+use serde::Serialize;
+
+use crate::{
+    account::Balance,
+    error::{Error, Result},
+    ledger::Postings,
+};
+
+/// One balance mutation the engine applied: the client's balance in `currency` before and after
+/// `operation`. Emitted to the engine's [`AuditSink`] once the operation has fully succeeded.
+///
+/// `operation` is the tx type that made the change (`deposit`, `withdrawal`, `dispute`, ...), or
+/// one of `fee` (the fee paid by the client), `fee_income` (the fee received by the fee account),
+/// `interest`, `hold_expiry`, `seed` and `merge`. Merges record both the emptied source and the
+/// target. `tx` is missing for changes that aren't tied to a tx id. `postings` are the ledger
+/// postings the change made, none for a merge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub client: u16,
+    pub tx: Option<u32>,
+    pub operation: &'static str,
+    pub currency: String,
+    pub amount: Amount,
+    pub before: Balance,
+    pub after: Balance,
+    pub postings: Postings,
+}
+
+// a record as written, with the balances flattened so the JSONL and CSV layouts match
+#[derive(Serialize)]
+struct AuditRow<'a> {
+    client: u16,
+    tx: Option<u32>,
+    operation: &'a str,
+    currency: &'a str,
+    amount: Amount,
+    available_before: Amount,
+    held_before: Amount,
+    total_before: Amount,
+    available_after: Amount,
+    held_after: Amount,
+    total_after: Amount,
+}
+
+impl<'a> From<&'a AuditRecord> for AuditRow<'a> {
+    fn from(record: &'a AuditRecord) -> Self {
+        Self {
+            client: record.client,
+            tx: record.tx,
+            operation: record.operation,
+            currency: &record.currency,
+            amount: record.amount,
+            available_before: record.before.available,
+            held_before: record.before.held,
+            total_before: record.before.total,
+            available_after: record.after.available,
+            held_after: record.after.held,
+            total_after: record.after.total,
+        }
+    }
+}
+
+/// Where the engine writes its [`AuditRecord`]s.
+pub trait AuditSink {
+    /// Delivers one record. An error here fails the operation that made the change, although
+    /// the change itself has already been applied.
+    fn audit(&mut self, record: &AuditRecord) -> Result<()>;
+
+    /// Pushes out any buffered records.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes audit records as JSON lines.
+pub struct JsonlAuditSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonlAuditSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> AuditSink for JsonlAuditSink<W> {
+    fn audit(&mut self, record: &AuditRecord) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, &AuditRow::from(record))
+            .map_err(|e| Error::AuditError(e.to_string()))?;
+        writeln!(self.writer).map_err(|e| Error::AuditError(e.to_string()))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .map_err(|e| Error::AuditError(e.to_string()))
+    }
+}
+
+/// Writes audit records as CSV, with a header row.
+pub struct CsvAuditSink<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> CsvAuditSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+        }
+    }
+}
+
+impl<W: Write> AuditSink for CsvAuditSink<W> {
+    fn audit(&mut self, record: &AuditRecord) -> Result<()> {
+        self.writer
+            .serialize(AuditRow::from(record))
+            .map_err(|e| Error::AuditError(e.to_string()))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .map_err(|e| Error::AuditError(e.to_string()))
+    }
+}
+
+/// Delivers every record to each of the sinks in turn, e.g. to keep an audit log and a GL
+/// journal. Stops at the first sink that fails.
+impl AuditSink for Vec<Box<dyn AuditSink + Send>> {
+    fn audit(&mut self, record: &AuditRecord) -> Result<()> {
+        self.iter_mut().try_for_each(|sink| sink.audit(record))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.iter_mut().try_for_each(|sink| sink.flush())
+    }
+}
+
+/// Sends audit records to a channel, for consumers in the same process.
+impl AuditSink for Sender<AuditRecord> {
+    fn audit(&mut self, record: &AuditRecord) -> Result<()> {
+        self.send(record.clone())
+            .map_err(|_| Error::AuditError("audit receiver has disconnected".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::ledger::{LedgerAccount, Posting};
+
+    fn record() -> AuditRecord {
+        AuditRecord {
+            client: 1,
+            tx: Some(2),
+            operation: "withdrawal",
+            currency: String::new(),
+            amount: amount!(1.5),
+            before: Balance {
+                available: amount!(10),
+                held: amount!(2),
+                total: amount!(12),
+            },
+            after: Balance {
+                available: amount!(8.5),
+                held: amount!(2),
+                total: amount!(10.5),
+            },
+            postings: Postings::one(Posting::new(
+                LedgerAccount::Available,
+                LedgerAccount::Suspense,
+                amount!(1.5),
+            )),
+        }
+    }
+
+    #[test]
+    fn test_jsonl_audit_sink() {
+        let mut sink = JsonlAuditSink::new(Vec::new());
+
+        sink.audit(&record()).unwrap();
+
+        let line: serde_json::Value = serde_json::from_slice(&sink.writer).unwrap();
+        assert_eq!(line["client"], 1);
+        assert_eq!(line["tx"], 2);
+        assert_eq!(line["operation"], "withdrawal");
+        assert_eq!(line["available_before"], "10");
+        assert_eq!(line["total_after"], "10.5");
+    }
+
+    #[test]
+    fn test_csv_audit_sink() {
+        let mut sink = CsvAuditSink::new(Vec::new());
+
+        sink.audit(&record()).unwrap();
+        sink.audit(&AuditRecord {
+            tx: None,
+            operation: "interest",
+            ..record()
+        })
+        .unwrap();
+
+        let out = String::from_utf8(sink.writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            out,
+            "client,tx,operation,currency,amount,available_before,held_before,total_before,\
+             available_after,held_after,total_after\n\
+             1,2,withdrawal,,1.5,10,2,12,8.5,2,10.5\n\
+             1,,interest,,1.5,10,2,12,8.5,2,10.5\n"
+        );
+    }
+}
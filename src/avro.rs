@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use avro_schema::schema::{BytesLogical, FixedLogical, LongLogical, Schema};
+use payments_engine::{Error, Result, Transaction};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::{Map, Number, Value};
+
+// first byte of a message in the schema registry's wire format, ahead of the big-endian schema id
+const MAGIC_BYTE: u8 = 0;
+// how long a schema lookup may take before the consumer gives up on the registry
+const REGISTRY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+struct RegisteredSchema {
+    schema: String,
+}
+
+// decodes Avro transactions framed in the schema registry wire format, fetching each writer
+// schema from a Confluent-compatible registry the first time its id is seen
+pub struct SchemaRegistry {
+    url: String,
+    agent: ureq::Agent,
+    schemas: HashMap<u32, Schema>,
+}
+
+impl SchemaRegistry {
+    pub fn new(url: &str) -> Self {
+        SchemaRegistry {
+            url: url.trim_end_matches('/').to_string(),
+            agent: ureq::Agent::config_builder()
+                .timeout_global(Some(REGISTRY_TIMEOUT))
+                .build()
+                .into(),
+            schemas: HashMap::new(),
+        }
+    }
+
+    // the transaction in `payload`. A payload that isn't framed Avro, or whose record doesn't
+    // map to a transaction, fails with a `SchemaError`; failing to reach the registry is an
+    // `Io` error, as the message may well decode once it is back
+    pub fn transaction(&mut self, payload: &[u8]) -> Result<Transaction> {
+        let [MAGIC_BYTE, a, b, c, d, datum @ ..] = payload else {
+            return Err(Error::SchemaError(
+                "message is not in the schema registry wire format".to_string(),
+            ));
+        };
+        let schema = self.schema(u32::from_be_bytes([*a, *b, *c, *d]))?;
+        let mut value = decode(schema, &mut &datum[..])?;
+        // enum symbols are conventionally upper case, transaction types aren't
+        if let Some(Value::String(tx_type)) = value.get_mut("type") {
+            *tx_type = tx_type.to_lowercase();
+        }
+
+        serde_json::from_value(value).map_err(|e| Error::SchemaError(e.to_string()))
+    }
+
+    fn schema(&mut self, id: u32) -> Result<&Schema> {
+        if !self.schemas.contains_key(&id) {
+            let body = self
+                .agent
+                .get(format!("{}/schemas/ids/{}", self.url, id))
+                .call()
+                .and_then(|mut response| response.body_mut().read_to_string())
+                .map_err(std::io::Error::other)?;
+            let schema = serde_json::from_str::<RegisteredSchema>(&body)
+                .and_then(|registered| serde_json::from_str(&registered.schema))
+                .map_err(|e| Error::SchemaError(format!("schema {}: {}", id, e)))?;
+            self.schemas.insert(id, schema);
+        }
+
+        Ok(&self.schemas[&id])
+    }
+}
+
+// decode one datum of `schema` off the front of `datum` into its JSON equivalent: records become
+// objects, enums their symbol, unions their branch, decimals strings and timestamps seconds since
+// the epoch, as `Transaction` is deserialized from
+fn decode(schema: &Schema, datum: &mut &[u8]) -> Result<Value> {
+    Ok(match schema {
+        Schema::Null => Value::Null,
+        Schema::Boolean => Value::Bool(take(datum, 1)?[0] != 0),
+        Schema::Int(_) => long(datum)?.into(),
+        Schema::Long(logical) => match logical {
+            Some(LongLogical::TimestampMillis | LongLogical::LocalTimestampMillis) => {
+                (long(datum)? / 1_000).into()
+            }
+            Some(LongLogical::TimestampMicros | LongLogical::LocalTimestampMicros) => {
+                (long(datum)? / 1_000_000).into()
+            }
+            _ => long(datum)?.into(),
+        },
+        Schema::Float => float(f32::from_le_bytes(take(datum, 4)?.try_into().unwrap()).into())?,
+        Schema::Double => float(f64::from_le_bytes(take(datum, 8)?.try_into().unwrap()))?,
+        Schema::Bytes(logical) => {
+            let len = length(datum)?;
+            let bytes = take(datum, len)?;
+            match logical {
+                Some(BytesLogical::Decimal(_, scale)) => decimal(bytes, *scale)?,
+                None => Value::String(hex::encode(bytes)),
+            }
+        }
+        Schema::String(_) => {
+            let len = length(datum)?;
+            let text = std::str::from_utf8(take(datum, len)?)
+                .map_err(|_| invalid("string is not valid UTF-8"))?;
+            Value::String(text.to_string())
+        }
+        Schema::Record(record) => {
+            let mut fields = Map::new();
+            for field in &record.fields {
+                fields.insert(field.name.clone(), decode(&field.schema, datum)?);
+            }
+            Value::Object(fields)
+        }
+        Schema::Enum(symbols) => {
+            let index = length(datum)?;
+            let symbol = symbols
+                .symbols
+                .get(index)
+                .ok_or_else(|| invalid("enum index out of range"))?;
+            Value::String(symbol.clone())
+        }
+        Schema::Array(items) => {
+            let mut values = Vec::new();
+            while let Some(count) = block(datum)? {
+                for _ in 0..count {
+                    values.push(decode(items, datum)?);
+                }
+            }
+            Value::Array(values)
+        }
+        Schema::Map(values) => {
+            let mut entries = Map::new();
+            while let Some(count) = block(datum)? {
+                for _ in 0..count {
+                    let key = decode(&Schema::String(None), datum)?;
+                    let key = key.as_str().unwrap_or_default().to_string();
+                    entries.insert(key, decode(values, datum)?);
+                }
+            }
+            Value::Object(entries)
+        }
+        Schema::Union(branches) => {
+            let index = length(datum)?;
+            let branch = branches
+                .get(index)
+                .ok_or_else(|| invalid("union index out of range"))?;
+            decode(branch, datum)?
+        }
+        Schema::Fixed(fixed) => {
+            let bytes = take(datum, fixed.size)?;
+            match fixed.logical {
+                Some(FixedLogical::Decimal(_, scale)) => decimal(bytes, scale)?,
+                _ => Value::String(hex::encode(bytes)),
+            }
+        }
+    })
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::SchemaError(format!("invalid Avro datum: {}", reason))
+}
+
+fn take<'a>(datum: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if datum.len() < len {
+        return Err(invalid("truncated"));
+    }
+    let (bytes, rest) = datum.split_at(len);
+    *datum = rest;
+    Ok(bytes)
+}
+
+// a zigzag varint, as ints and longs are both written
+fn long(datum: &mut &[u8]) -> Result<i64> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = take(datum, 1)?[0];
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+        }
+    }
+    Err(invalid("varint is too long"))
+}
+
+// a length or index, which can't be negative
+fn length(datum: &mut &[u8]) -> Result<usize> {
+    usize::try_from(long(datum)?).map_err(|_| invalid("negative length"))
+}
+
+// the item count of the next block of an array or map, `None` at the terminating empty block. A
+// negative count is followed by the block's size in bytes, which isn't needed here
+fn block(datum: &mut &[u8]) -> Result<Option<u64>> {
+    let count = long(datum)?;
+    if count < 0 {
+        long(datum)?;
+    }
+    Ok((count != 0).then_some(count.unsigned_abs()))
+}
+
+fn float(value: f64) -> Result<Value> {
+    Number::from_f64(value)
+        .map(Value::Number)
+        .ok_or_else(|| invalid("floating point value is not finite"))
+}
+
+// a decimal logical value: the unscaled number as big-endian two's complement
+fn decimal(bytes: &[u8], scale: usize) -> Result<Value> {
+    if bytes.len() > 16 {
+        return Err(invalid("decimal is out of range"));
+    }
+    let fill = match bytes.first() {
+        Some(first) if first & 0x80 != 0 => 0xff,
+        _ => 0,
+    };
+    let mut unscaled = [fill; 16];
+    unscaled[16 - bytes.len()..].copy_from_slice(bytes);
+    let value = u32::try_from(scale)
+        .ok()
+        .and_then(|scale| {
+            Decimal::try_from_i128_with_scale(i128::from_be_bytes(unscaled), scale).ok()
+        })
+        .ok_or_else(|| invalid("decimal is out of range"))?;
+
+    Ok(Value::String(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::{TransactionType, amount};
+
+    const SCHEMA: &str = r#"{
+        "type": "record",
+        "name": "Transaction",
+        "fields": [
+            {"name": "type", "type": {"type": "enum", "name": "TxType", "symbols": ["DEPOSIT", "WITHDRAWAL"]}},
+            {"name": "client", "type": "int"},
+            {"name": "tx", "type": "long"},
+            {"name": "amount", "type": ["null", {"type": "bytes", "logicalType": "decimal", "precision": 18, "scale": 2}]},
+            {"name": "timestamp", "type": {"type": "long", "logicalType": "timestamp-millis"}},
+            {"name": "tags", "type": {"type": "array", "items": "string"}}
+        ]
+    }"#;
+
+    fn zigzag(value: i64) -> Vec<u8> {
+        let mut value = ((value << 1) ^ (value >> 63)) as u64;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    fn registry() -> SchemaRegistry {
+        let mut registry = SchemaRegistry::new("http://localhost:8081/");
+        registry
+            .schemas
+            .insert(7, serde_json::from_str(SCHEMA).unwrap());
+        registry
+    }
+
+    fn message(amount: &[u8]) -> Vec<u8> {
+        let mut message = vec![MAGIC_BYTE, 0, 0, 0, 7];
+        message.extend(zigzag(1)); // WITHDRAWAL
+        message.extend(zigzag(3));
+        message.extend(zigzag(70_000));
+        message.extend(zigzag(1));
+        message.extend(zigzag(amount.len() as i64));
+        message.extend(amount);
+        message.extend(zigzag(1_700_000_000_500));
+        message.extend(zigzag(1));
+        message.extend(zigzag(2));
+        message.extend(b"eu");
+        message.extend(zigzag(0));
+        message
+    }
+
+    #[test]
+    fn test_transaction_success() {
+        let mut registry = registry();
+
+        // 1050 at scale 2, and -2.5 as 0xff06
+        let tx = registry.transaction(&message(&[0x04, 0x1a])).unwrap();
+        let negative = registry.transaction(&message(&[0xff, 0x06])).unwrap();
+
+        assert_eq!(tx.tx_type, TransactionType::Withdrawal);
+        assert_eq!(tx.account_id, 3);
+        assert_eq!(tx.tx_id, 70_000);
+        assert_eq!(tx.amount, Some(amount!(10.5)));
+        assert_eq!(tx.timestamp, Some(1_700_000_000));
+        assert_eq!(negative.amount, Some(amount!(-2.5)));
+    }
+
+    #[test]
+    fn test_transaction_failure_invalid_payload() {
+        let mut registry = registry();
+        let mut truncated = message(&[0x04, 0x1a]);
+        truncated.truncate(12);
+
+        for payload in [
+            &b"{\"type\":\"deposit\"}"[..],
+            &[MAGIC_BYTE, 0, 0],
+            &truncated,
+        ] {
+            assert!(matches!(
+                registry.transaction(payload),
+                Err(Error::SchemaError(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,131 @@
+// bits kept per tx id: with `PROBES` probes, about 1% of absent ids pass a full layer
+const BITS_PER_ID: usize = 10;
+const PROBES: u32 = 7;
+// blocks of the first layer (128 KiB), each layer after that having twice as many as the last
+const INITIAL_BLOCKS: usize = 1 << 11;
+
+// one cache line of bits, which all of an id's probes into a layer fall in
+type Block = [u64; 8];
+
+// a blocked bloom filter over tx ids: `may_contain` is never wrong about an id that was added,
+// and wrong about a small share of those that weren't, so stores can skip looking up ids that
+// are definitely missing. Keeping an id's probes to one block costs a little accuracy but makes a
+// lookup one cache miss per layer rather than one per probe.
+//
+// It grows without knowing how many ids are coming by adding a layer twice the size of the last
+// once that one is full, rather than rebuilding from every stored id. An id passes if it passes
+// any layer, so each layer adds its ~1% of false positives: about 10% after ten doublings, which
+// is past 100M ids
+#[derive(Default)]
+pub(crate) struct TxFilter {
+    layers: Vec<Layer>,
+}
+
+struct Layer {
+    // a power of two of them, so ids can be masked into range
+    blocks: Vec<Block>,
+    ids: usize,
+}
+
+impl Layer {
+    fn is_full(&self) -> bool {
+        self.ids >= self.blocks.len() * 512 / BITS_PER_ID
+    }
+
+    // the block `tx_id` falls in and the bits it sets there
+    fn probe(&self, tx_id: u32) -> (usize, Block) {
+        let hash = mix(tx_id as u64);
+        let block = (hash as usize) & (self.blocks.len() - 1);
+        // each probe takes 9 bits of a second hash to pick one of the block's 512 bits
+        let mut bits = mix(hash);
+        let mut mask = Block::default();
+        for _ in 0..PROBES {
+            let bit = (bits & 511) as usize;
+            mask[bit / 64] |= 1 << (bit % 64);
+            bits >>= 9;
+        }
+        (block, mask)
+    }
+}
+
+impl TxFilter {
+    pub(crate) fn insert(&mut self, tx_id: u32) {
+        let blocks = match self.layers.last() {
+            None => INITIAL_BLOCKS,
+            Some(layer) if layer.is_full() => layer.blocks.len() * 2,
+            Some(_) => 0,
+        };
+        if blocks > 0 {
+            self.layers.push(Layer {
+                blocks: vec![Block::default(); blocks],
+                ids: 0,
+            });
+        }
+        let layer = self.layers.last_mut().expect("a layer was just made");
+        let (block, mask) = layer.probe(tx_id);
+        for (word, bits) in layer.blocks[block].iter_mut().zip(mask) {
+            *word |= bits;
+        }
+        layer.ids += 1;
+    }
+
+    pub(crate) fn may_contain(&self, tx_id: u32) -> bool {
+        // newest first: the biggest layer holds about half of the ids
+        self.layers.iter().rev().any(|layer| {
+            let (block, mask) = layer.probe(tx_id);
+            layer.blocks[block]
+                .iter()
+                .zip(mask)
+                .all(|(word, bits)| word & bits == bits)
+        })
+    }
+}
+
+// the splitmix64 finalizer, spreading consecutive ids across the whole range
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_may_contain() {
+        let mut filter = TxFilter::default();
+        assert!(!filter.may_contain(0));
+        for tx_id in (0..50_000).map(|i| i * 3) {
+            filter.insert(tx_id);
+        }
+
+        assert!((0..50_000).all(|i| filter.may_contain(i * 3)));
+        let false_positives = (0..50_000)
+            .filter(|i| filter.may_contain(i * 3 + 1))
+            .count();
+        // half full, so well under the 1% a full layer lets through
+        assert!(false_positives < 50, "{}", false_positives);
+    }
+
+    #[test]
+    fn test_insert_adds_layers() {
+        let mut filter = TxFilter::default();
+        let ids = (3 * INITIAL_BLOCKS * 512 / BITS_PER_ID) as u32;
+        for tx_id in 0..ids {
+            filter.insert(tx_id);
+        }
+
+        assert_eq!(filter.layers.len(), 2);
+        assert!((0..ids).all(|tx_id| filter.may_contain(tx_id)));
+        let false_positives = (ids..ids * 2)
+            .filter(|&tx_id| filter.may_contain(tx_id))
+            .count();
+        assert!(
+            false_positives < ids as usize * 3 / 100,
+            "{}",
+            false_positives
+        );
+    }
+}
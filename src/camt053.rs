@@ -0,0 +1,238 @@
+use std::io::Write;
+
+use payments_engine::{AmountExt, DEFAULT_CURRENCY, Result};
+use rust_decimal::Decimal;
+
+use crate::{
+    dates,
+    ofx::{Period, Statement, escape},
+    output::fixed_dp,
+};
+
+const NAMESPACE: &str = "urn:iso:std:iso:20022:tech:xsd:camt.053.001.08";
+
+// an ISO 20022 camt.053 bank-to-customer statement message with a statement per currency. Each
+// entry is booked, and refers to its tx id, so the `iso20022` input reads the statement back
+// into the transactions that moved the total
+pub fn write<W: Write>(
+    out: &mut W,
+    client: u16,
+    statements: &[Statement],
+    period: Period,
+    default_currency: &str,
+    now: u64,
+) -> Result<()> {
+    let created = dates::iso(now);
+    let from = dates::iso(period.start);
+    let to = dates::iso(period.end - 1);
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<Document xmlns="{}">"#, NAMESPACE)?;
+    writeln!(out, "<BkToCstmrStmt>")?;
+    writeln!(
+        out,
+        "<GrpHdr><MsgId>{}-{}</MsgId><CreDtTm>{}</CreDtTm></GrpHdr>",
+        client,
+        dates::compact(period.start),
+        created
+    )?;
+    for statement in statements {
+        // a client's balances in other currencies are told apart as accounts of their own, as in
+        // the OFX statements
+        let (currency, account) = match statement.currency {
+            DEFAULT_CURRENCY => (escape(default_currency), client.to_string()),
+            currency => (escape(currency), format!("{}-{}", client, escape(currency))),
+        };
+        writeln!(out, "<Stmt>")?;
+        writeln!(out, "<Id>{}-{}</Id>", account, dates::compact(period.start))?;
+        writeln!(out, "<CreDtTm>{}</CreDtTm>", created)?;
+        writeln!(
+            out,
+            "<FrToDt><FrDtTm>{}</FrDtTm><ToDtTm>{}</ToDtTm></FrToDt>",
+            from, to
+        )?;
+        writeln!(
+            out,
+            "<Acct><Id><Othr><Id>{}</Id></Othr></Id><Ccy>{}</Ccy></Acct>",
+            account, currency
+        )?;
+        for (code, balance, at) in [
+            ("OPBD", statement.opening.total, period.start),
+            ("CLBD", statement.closing.total, period.end - 1),
+            ("CLAV", statement.closing.available, period.end - 1),
+        ] {
+            let balance = balance.to_decimal();
+            writeln!(
+                out,
+                r#"<Bal><Tp><CdOrPrtry><Cd>{}</Cd></CdOrPrtry></Tp><Amt Ccy="{}">{}</Amt><CdtDbtInd>{}</CdtDbtInd><Dt><Dt>{}</Dt></Dt></Bal>"#,
+                code,
+                currency,
+                fixed_dp(balance.abs()),
+                indicator(balance),
+                &dates::iso(at)[..10]
+            )?;
+        }
+        for (index, entry, change) in &statement.lines {
+            // stable across exports of overlapping periods, as the OFX entry ids are
+            let id = match entry.tx {
+                Some(tx) => format!("{}-{}", tx, entry.operation),
+                None => format!("{}-{}", entry.operation, index),
+            };
+            writeln!(out, "<Ntry>")?;
+            if let Some(tx) = entry.tx {
+                writeln!(out, "<NtryRef>{}</NtryRef>", tx)?;
+            }
+            writeln!(
+                out,
+                r#"<Amt Ccy="{}">{}</Amt>"#,
+                currency,
+                fixed_dp(change.abs())
+            )?;
+            writeln!(out, "<CdtDbtInd>{}</CdtDbtInd>", indicator(*change))?;
+            writeln!(out, "<Sts><Cd>BOOK</Cd></Sts>")?;
+            writeln!(
+                out,
+                "<BookgDt><DtTm>{}</DtTm></BookgDt>",
+                dates::iso(entry.timestamp.unwrap_or(period.start))
+            )?;
+            if let Some(effective) = entry.effective {
+                writeln!(out, "<ValDt><DtTm>{}</DtTm></ValDt>", dates::iso(effective))?;
+            }
+            writeln!(out, "<AcctSvcrRef>{}</AcctSvcrRef>", id)?;
+            writeln!(
+                out,
+                "<BkTxCd><Prtry><Cd>{}</Cd></Prtry></BkTxCd>",
+                entry.operation
+            )?;
+            writeln!(out, "<AddtlNtryInf>{}</AddtlNtryInf>", entry.operation)?;
+            writeln!(out, "</Ntry>")?;
+        }
+        writeln!(out, "</Stmt>")?;
+    }
+    writeln!(out, "</BkToCstmrStmt>")?;
+    writeln!(out, "</Document>")?;
+
+    Ok(())
+}
+
+// amounts are written unsigned, with whether they credit or debit the account
+fn indicator(amount: Decimal) -> &'static str {
+    match amount.is_sign_negative() && !amount.is_zero() {
+        true => "DBIT",
+        false => "CRDT",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ofx::statements;
+    use payments_engine::testing::TxBuilder;
+    use payments_engine::{PaymentsEngine, TransactionType, amount};
+
+    const DAY: u64 = dates::SECS_PER_DAY;
+
+    fn engine() -> PaymentsEngine {
+        let mut engine = PaymentsEngine::builder().track_history(true).build();
+        for (tx_type, tx_id, timestamp) in [
+            (TransactionType::Deposit, 1, 0),
+            (TransactionType::Deposit, 2, DAY),
+            (TransactionType::Withdrawal, 3, DAY + 60),
+            (TransactionType::Withdrawal, 4, DAY + 120),
+        ] {
+            engine
+                .process_tx(
+                    &TxBuilder::new(tx_type, 1, tx_id)
+                        .amount(amount!(10))
+                        .timestamp(timestamp)
+                        .build(),
+                )
+                .unwrap();
+        }
+        engine
+    }
+
+    #[test]
+    fn test_write() {
+        let engine = engine();
+        let period = Period {
+            start: DAY,
+            end: 2 * DAY,
+        };
+        let statements = statements(engine.history(1), period);
+        let mut out = Vec::new();
+
+        write(&mut out, 1, &statements, period, "USD", 0).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("<Acct><Id><Othr><Id>1</Id></Othr></Id><Ccy>USD</Ccy></Acct>"));
+        assert!(out.contains(
+            "<FrToDt><FrDtTm>1970-01-02T00:00:00</FrDtTm><ToDtTm>1970-01-02T23:59:59</ToDtTm></FrToDt>"
+        ));
+        assert!(out.contains(
+            r#"<Cd>OPBD</Cd></CdOrPrtry></Tp><Amt Ccy="USD">10.0000</Amt><CdtDbtInd>CRDT</CdtDbtInd><Dt><Dt>1970-01-02</Dt>"#
+        ));
+        // the withdrawals emptied the account
+        assert!(out.contains(
+            r#"<Cd>CLBD</Cd></CdOrPrtry></Tp><Amt Ccy="USD">0.0000</Amt><CdtDbtInd>CRDT</CdtDbtInd>"#
+        ));
+        assert!(out.contains(
+            "<NtryRef>3</NtryRef>\n<Amt Ccy=\"USD\">10.0000</Amt>\n<CdtDbtInd>DBIT</CdtDbtInd>\n<Sts><Cd>BOOK</Cd></Sts>\n<BookgDt><DtTm>1970-01-02T00:01:00</DtTm></BookgDt>\n<AcctSvcrRef>3-withdrawal</AcctSvcrRef>"
+        ));
+    }
+
+    #[cfg(feature = "iso20022")]
+    #[test]
+    fn test_write_reads_back_as_iso20022_input() {
+        use std::collections::HashMap;
+
+        let engine = engine();
+        let period = Period {
+            start: DAY,
+            end: 2 * DAY,
+        };
+        let mut out = Vec::new();
+        write(
+            &mut out,
+            1,
+            &statements(engine.history(1), period),
+            period,
+            "USD",
+            0,
+        )
+        .unwrap();
+
+        let rows =
+            crate::iso20022::parse(out.as_slice(), &HashMap::from([("1".to_string(), 1)])).unwrap();
+
+        let rows: Vec<_> = rows
+            .into_iter()
+            .map(|(_, row)| {
+                let row = row.unwrap();
+                (row.tx_type, row.tx_id, row.amount, row.timestamp)
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (
+                    TransactionType::Deposit,
+                    2,
+                    Some(Decimal::from(10)),
+                    Some(DAY)
+                ),
+                (
+                    TransactionType::Withdrawal,
+                    3,
+                    Some(Decimal::from(10)),
+                    Some(DAY)
+                ),
+                (
+                    TransactionType::Withdrawal,
+                    4,
+                    Some(Decimal::from(10)),
+                    Some(DAY)
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use payments_engine::{Error, PaymentsEngine, PaymentsEngineBuilder, Result};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::crypt;
+
+// bump whenever the position's layout changes so old checkpoints are refused rather than misread
+const CHECKPOINT_VERSION: u32 = 3;
+
+// how far a run has read each of its inputs, by path, so a resumed run picks up every input where
+// it left off, whatever order the inputs come in
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Position {
+    version: u32,
+    inputs: BTreeMap<String, Offset>,
+    outputs: Outputs,
+}
+
+// how far an input has been read: every row before `records` has been handled, or every row
+// once it's `done`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Offset {
+    records: u64,
+    // offset into the input as read (after decompression) of the next row, and its line, for a
+    // resumed run to skip to. Zero for inputs that aren't read as CSV
+    byte: u64,
+    line: u64,
+    done: bool,
+}
+
+// how long the files of rejected rows were when the checkpoint was saved, for a resumed run to
+// append to them from there
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Outputs {
+    pub quarantine: Option<u64>,
+    pub rejects: Option<u64>,
+}
+
+// saves how far each input has been read along with the engine state to a checkpoint file, every
+// `interval` rows and at the end of every input, and on resume passes over the inputs it read to
+// their end and skips to where it left off in the one it was part way through, or passes over the
+// rows it covers where that input can't be skipped through.
+//
+// The file is the position as a JSON line followed by the engine snapshot, written to a sibling
+// temp file and renamed over the last one so a crash never leaves a torn checkpoint behind
+pub struct Checkpointer {
+    path: PathBuf,
+    interval: u64,
+    position: Position,
+    // the path of the input being read, and how far it has been read
+    input: String,
+    offset: Offset,
+    // rows handled since the last checkpoint
+    since: u64,
+    // where the checkpoint being resumed from left off in the input being read, until the run
+    // has caught up to it
+    resume_at: Option<Offset>,
+    // whether the run started from a checkpoint, for the outputs it saved
+    resumed: bool,
+}
+
+impl Checkpointer {
+    pub fn new(path: &Path, interval: u64) -> Self {
+        Checkpointer {
+            path: path.to_path_buf(),
+            interval: interval.max(1),
+            position: Position {
+                version: CHECKPOINT_VERSION,
+                ..Position::default()
+            },
+            input: String::new(),
+            offset: Offset::default(),
+            since: 0,
+            resume_at: None,
+            resumed: false,
+        }
+    }
+
+    // pick up from the checkpoint at `path`, restoring the engine state it saved through
+    // `builder`. Without a checkpoint there yet, the run starts from the beginning
+    pub fn resume(
+        path: &Path,
+        interval: u64,
+        builder: PaymentsEngineBuilder,
+    ) -> Result<(Self, PaymentsEngine)> {
+        let mut checkpointer = Self::new(path, interval);
+        let Some((position, state)) = load::<Position>(path)? else {
+            tracing::info!(path = %path.display(), "no checkpoint yet, starting from the beginning");
+            return Ok((checkpointer, builder.build()));
+        };
+        if position.version != CHECKPOINT_VERSION {
+            return Err(Error::SnapshotError(format!(
+                "checkpoint version {} is not supported (expected {})",
+                position.version, CHECKPOINT_VERSION
+            )));
+        }
+        tracing::info!(
+            done = position
+                .inputs
+                .values()
+                .filter(|offset| offset.done)
+                .count(),
+            "resuming from checkpoint"
+        );
+        checkpointer.position = position;
+        checkpointer.resumed = true;
+
+        Ok((checkpointer, builder.restore(state)?))
+    }
+
+    // start reading the input at `path`, returning whether any of it is left to process: none of
+    // an input the checkpoint being resumed from had read to its end
+    pub fn start_input(&mut self, path: &Path) -> bool {
+        let path = path.display().to_string();
+        let saved = self.position.inputs.get(&path).copied();
+        if saved.is_some_and(|offset| offset.done) {
+            return false;
+        }
+        self.resume_at = saved.filter(|offset| offset.records > 0);
+        self.input = path;
+        self.offset = Offset::default();
+
+        true
+    }
+
+    // how long the files of rejected rows were at the checkpoint being resumed from, if any
+    pub fn resumed_outputs(&self) -> Outputs {
+        match self.resumed {
+            true => self.position.outputs,
+            false => Outputs::default(),
+        }
+    }
+
+    // whether the checkpoint being resumed from left off part way through the input being read,
+    // at a byte offset `seek` can skip to
+    pub fn seeks(&self) -> bool {
+        self.resume_at.is_some_and(|resume_at| resume_at.byte > 0)
+    }
+
+    // skip `rdr` straight to the row the checkpoint being resumed from left off at, once its
+    // headers are read, rather than parsing every row before it. Fails if the input has changed
+    // so that the offset no longer starts a row
+    pub fn seek<R: Read>(&mut self, rdr: &mut csv::Reader<Resumable<R>>) -> Result<()> {
+        if !self.seeks() {
+            return Ok(());
+        }
+        let Some(resume_at) = self.resume_at.take() else {
+            return Ok(());
+        };
+        let mut next = csv::Position::new();
+        // the header row counts as a record
+        next.set_byte(resume_at.byte)
+            .set_line(resume_at.line)
+            .set_record(resume_at.records + 1);
+        let fail = |reason: String| {
+            Error::ConfigError(format!(
+                "can't resume input `{}` at byte {}: {}",
+                self.input, resume_at.byte, reason
+            ))
+        };
+        rdr.seek(next).map_err(|e| fail(e.to_string()))?;
+        if !rdr.get_ref().at_line_start() {
+            return Err(fail("it no longer starts a row there".to_string()));
+        }
+        self.offset.records = resume_at.records;
+
+        Ok(())
+    }
+
+    // count a row read, returning whether it was already handled before the checkpoint being
+    // resumed from and is to be passed over
+    pub fn read(&mut self) -> bool {
+        self.offset.records += 1;
+        if let Some(resume_at) = &self.resume_at {
+            if self.offset.records <= resume_at.records {
+                return true;
+            }
+            self.resume_at = None;
+        }
+        self.since += 1;
+        false
+    }
+
+    // whether a checkpoint is due, `interval` rows having been handled since the last one
+    pub fn due(&self) -> bool {
+        self.since >= self.interval
+    }
+
+    // save a checkpoint with the next row at `next` (unknown for inputs that aren't read as
+    // CSV), and the files of rejected rows as long as `outputs` says
+    pub fn save(
+        &mut self,
+        engine: &mut PaymentsEngine,
+        next: Option<&csv::Position>,
+        outputs: Outputs,
+    ) -> Result<()> {
+        self.offset.byte = next.map_or(0, csv::Position::byte);
+        self.offset.line = next.map_or(0, csv::Position::line);
+        self.position.inputs.insert(self.input.clone(), self.offset);
+        self.position.outputs = outputs;
+        self.write(engine)
+    }
+
+    // save a checkpoint past the input just read to its end
+    pub fn end_input(&mut self, engine: &mut PaymentsEngine, outputs: Outputs) -> Result<()> {
+        self.resume_at = None;
+        self.offset = Offset {
+            done: true,
+            ..Offset::default()
+        };
+        self.position
+            .inputs
+            .insert(std::mem::take(&mut self.input), self.offset);
+        self.position.outputs = outputs;
+        self.write(engine)
+    }
+
+    fn write(&mut self, engine: &mut PaymentsEngine) -> Result<()> {
+        save(&self.path, &self.position, engine)?;
+        self.since = 0;
+        tracing::debug!(
+            input = %self.input,
+            records = self.offset.records,
+            "saved checkpoint"
+        );
+
+        Ok(())
+    }
+}
+
+// an input that can seek forward, by reading past what it skips, and back over what it read
+// before its first seek if told to keep it, which is only as far as the csv reader reads ahead
+// for the headers. That's all a resumed run needs to skip to where its checkpoint left off
+pub struct Resumable<R> {
+    inner: R,
+    // bytes read from `inner` so far
+    read: u64,
+    // everything read before the first seek, to seek back over
+    kept: Option<Vec<u8>>,
+    // kept bytes to serve again after seeking back over them
+    replay: Cursor<Vec<u8>>,
+    // whether the seek landed just past a newline
+    at_line_start: bool,
+}
+
+impl<R: Read> Resumable<R> {
+    // `keep` is whether the input will be seeked, without which reading it keeps nothing
+    pub fn new(inner: R, keep: bool) -> Self {
+        Resumable {
+            inner,
+            read: 0,
+            kept: keep.then(Vec::new),
+            replay: Cursor::default(),
+            at_line_start: false,
+        }
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.at_line_start
+    }
+}
+
+impl<R: Read> Read for Resumable<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.replay.read(buf)?;
+        if n > 0 {
+            return Ok(n);
+        }
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        if let Some(kept) = &mut self.kept {
+            kept.extend_from_slice(&buf[..n]);
+        }
+
+        Ok(n)
+    }
+}
+
+impl<R: Read> Seek for Resumable<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (SeekFrom::Start(to), Some(mut kept)) = (pos, self.kept.take()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "an input can only be seeked once, from its start",
+            ));
+        };
+        if to <= self.read {
+            // `kept` holds everything read so far
+            let to = to as usize;
+            self.at_line_start = to > 0 && kept[to - 1] == b'\n';
+            self.replay = Cursor::new(kept.split_off(to));
+        } else {
+            let skip = to - self.read - 1;
+            let skipped = io::copy(&mut (&mut self.inner).take(skip), &mut io::sink())?;
+            let mut last = [0];
+            if skipped < skip || self.inner.read(&mut last)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the input ends before it",
+                ));
+            }
+            self.read = to;
+            self.at_line_start = last[0] == b'\n';
+        }
+
+        Ok(to)
+    }
+}
+
+// write `header` as a JSON line followed by the engine state to `path`, through a sibling temp
+// file renamed over the last one so a crash never leaves a torn checkpoint behind. The engine's
+// events up to here go out first
+pub fn save<T: Serialize>(path: &Path, header: &T, engine: &mut PaymentsEngine) -> Result<()> {
+    engine.flush_events()?;
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut writer = BufWriter::new(crypt::Writer::new(File::create(&tmp_path)?)?);
+    serde_json::to_writer(&mut writer, header).map_err(|e| Error::SnapshotError(e.to_string()))?;
+    writeln!(writer)?;
+    engine.snapshot(&mut writer)?;
+    writer.flush()?;
+    writer.get_ref().get_ref().sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+// read back the header `save` wrote to `path`, along with a reader of the engine state after it
+// to restore; `None` if nothing has been saved there yet
+pub fn load<T: DeserializeOwned>(
+    path: &Path,
+) -> Result<Option<(T, BufReader<crypt::Reader<File>>)>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut reader = BufReader::new(crypt::Reader::new(file)?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let header = serde_json::from_str(&line)
+        .map_err(|e| Error::SnapshotError(format!("invalid checkpoint: {}", e)))?;
+
+    Ok(Some((header, reader)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::testing::TxBuilder;
+    use payments_engine::{DEFAULT_CURRENCY, amount};
+
+    #[test]
+    fn test_resume() {
+        let path = std::env::temp_dir().join(format!(
+            "payments-engine-checkpoint-{}.json",
+            std::process::id()
+        ));
+        let mut engine = PaymentsEngine::new();
+        let mut checkpointer = Checkpointer::new(&path, 2);
+        assert!(checkpointer.start_input(Path::new("a.csv")));
+        checkpointer
+            .end_input(&mut engine, Outputs::default())
+            .unwrap();
+        assert!(checkpointer.start_input(Path::new("b.csv")));
+        // the checkpoint after the second row, then an interruption after the third
+        for tx_id in 1..=3 {
+            if checkpointer.due() {
+                checkpointer
+                    .save(&mut engine, None, Outputs::default())
+                    .unwrap();
+            }
+            assert!(!checkpointer.read());
+            engine
+                .process_tx(&TxBuilder::deposit(1, tx_id, amount!(1)).build())
+                .unwrap();
+        }
+
+        let (mut resumed, mut engine) =
+            Checkpointer::resume(&path, 2, PaymentsEngine::builder()).unwrap();
+
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).total,
+            amount!(2)
+        );
+        // each input picks up where it left off, in whatever order they come
+        assert!(resumed.start_input(Path::new("b.csv")));
+        let skipped: Vec<_> = (1..=3).map(|_| resumed.read()).collect();
+        assert_eq!(skipped, [true, true, false]);
+        engine
+            .process_tx(&TxBuilder::deposit(1, 3, amount!(1)).build())
+            .unwrap();
+        resumed.end_input(&mut engine, Outputs::default()).unwrap();
+        assert!(resumed.start_input(Path::new("c.csv")));
+        assert!(!resumed.read());
+        assert!(!resumed.start_input(Path::new("a.csv")));
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).total,
+            amount!(3)
+        );
+
+        // the inputs read to their end stay done in later checkpoints
+        let (mut resumed, _) = Checkpointer::resume(&path, 2, PaymentsEngine::builder()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(!resumed.start_input(Path::new("a.csv")));
+        assert!(!resumed.start_input(Path::new("b.csv")));
+        assert!(resumed.start_input(Path::new("c.csv")));
+    }
+
+    #[test]
+    fn test_resumable_seek() {
+        let input = "a,b\n1,2\n3,4\n5,6\n";
+        let next = |rdr: &mut csv::Reader<Resumable<&[u8]>>| {
+            let record = rdr.records().next().unwrap().unwrap();
+            (record[0].to_string(), record.position().unwrap().line())
+        };
+        let seek = |byte, line| {
+            let mut rdr = csv::Reader::from_reader(Resumable::new(input.as_bytes(), true));
+            rdr.headers().unwrap();
+            let mut position = csv::Position::new();
+            position.set_byte(byte).set_line(line);
+            rdr.seek(position).map(|()| rdr)
+        };
+
+        // back over what was read for the headers, and on a row boundary
+        let mut rdr = seek(8, 3).unwrap();
+        assert!(rdr.get_ref().at_line_start());
+        assert_eq!(next(&mut rdr), ("3".to_string(), 3));
+        // not on a row boundary
+        assert!(!seek(9, 3).unwrap().get_ref().at_line_start());
+        // only once
+        assert!(rdr.seek(csv::Position::new()).is_err());
+
+        // forward, past what was read, by reading through it
+        let mut long = "a,b\n".to_string();
+        for i in 0..10_000 {
+            long.push_str(&format!("{},{}\n", i % 10, i));
+        }
+        let byte = long.len() as u64 - "9,9999\n".len() as u64;
+        let mut rdr = csv::Reader::from_reader(Resumable::new(long.as_bytes(), true));
+        rdr.headers().unwrap();
+        let mut position = csv::Position::new();
+        position.set_byte(byte).set_line(10_001);
+        rdr.seek(position).unwrap();
+        assert!(rdr.get_ref().at_line_start());
+        let record = rdr.records().next().unwrap().unwrap();
+        assert_eq!(&record[1], "9999");
+        assert_eq!(record.position().unwrap().line(), 10_001);
+        // past the end
+        let mut rdr = csv::Reader::from_reader(Resumable::new(long.as_bytes(), true));
+        let mut position = csv::Position::new();
+        position.set_byte(long.len() as u64 + 1);
+        assert!(rdr.seek(position).is_err());
+    }
+
+    #[test]
+    fn test_resume_without_checkpoint() {
+        let path = std::env::temp_dir().join("payments-engine-no-such-checkpoint.json");
+
+        let (mut resumed, engine) =
+            Checkpointer::resume(&path, 2, PaymentsEngine::builder()).unwrap();
+
+        assert_eq!(engine.accounts().count(), 0);
+        assert!(resumed.start_input(Path::new("a.csv")));
+        assert!(!resumed.read());
+    }
+}
@@ -0,0 +1,340 @@
+use std::io::{BufRead, BufReader, Write};
+
+use serde::Deserialize;
+use serde_json::json;
+
+use payments_engine::{Account, Amount, Error, Result};
+
+use crate::output::{self, OutputFormat};
+
+// what to ask the server started by `serve`
+#[derive(Clone, clap::Subcommand)]
+pub enum ClientCommand {
+    /// Print one client's balances
+    Account {
+        /// Client to print
+        client: u16,
+    },
+    /// Print every account's balances
+    Accounts,
+    /// Submit one transaction
+    Submit {
+        /// Transaction type, as in the CSV input; the server checks it
+        #[arg(value_name = "TYPE")]
+        tx_type: String,
+
+        /// Client the transaction is for
+        client: u16,
+
+        /// Transaction id
+        tx: u32,
+
+        /// Amount, for the types that take one
+        amount: Option<Amount>,
+
+        /// Currency of the amount
+        #[arg(long, value_name = "CODE")]
+        currency: Option<String>,
+
+        /// Seconds since the Unix epoch
+        #[arg(long, value_name = "SECS")]
+        timestamp: Option<u64>,
+
+        /// Reason recorded with admin operations and corrections
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Print the server's events as JSON lines as they happen, until interrupted
+    Tail,
+}
+
+// the body of a failed request
+#[derive(Deserialize)]
+struct ErrorBody {
+    code: String,
+    error: String,
+}
+
+// the server at `url`, as the caller presenting `token`, if any
+pub struct Client {
+    url: String,
+    token: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl Client {
+    pub fn new(url: &str, token: Option<String>) -> Self {
+        Client {
+            url: url.trim_end_matches('/').to_string(),
+            token,
+            // failed requests carry an error body worth showing
+            agent: ureq::Agent::config_builder()
+                .http_status_as_error(false)
+                .build()
+                .into(),
+        }
+    }
+
+    // run `command`, writing what it prints to `out` in `format`
+    pub fn run<W: Write>(
+        &self,
+        command: ClientCommand,
+        mut out: W,
+        format: OutputFormat,
+    ) -> Result<()> {
+        match command {
+            ClientCommand::Account { client } => {
+                let body = self.get(&format!("/accounts/{}", client))?;
+                let account: Account = parse(&body)?;
+                output::write_account_list([&account], out, format)
+            }
+            ClientCommand::Accounts => {
+                let accounts: Vec<Account> = parse(&self.get("/accounts")?)?;
+                output::write_account_list(&accounts, out, format)
+            }
+            ClientCommand::Submit {
+                tx_type,
+                client,
+                tx,
+                amount,
+                currency,
+                timestamp,
+                reason,
+            } => {
+                let body = tx_body(tx_type, client, tx, amount, currency, timestamp, reason);
+                let mut request = self.agent.post(format!("{}/transactions", self.url));
+                if let Some(token) = &self.token {
+                    request = request.header("authorization", format!("Bearer {}", token));
+                }
+                let response = request
+                    .content_type("application/json")
+                    .send(body.to_string())
+                    .map_err(request_failed)?;
+                check(response).map(drop)
+            }
+            ClientCommand::Tail => {
+                let response = self.request("/events")?;
+                for line in BufReader::new(response.into_body().into_reader()).lines() {
+                    writeln!(out, "{}", line?)?;
+                    out.flush()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn get(&self, path: &str) -> Result<String> {
+        self.request(path)?
+            .into_body()
+            .read_to_string()
+            .map_err(request_failed)
+    }
+
+    // a successful GET of `path`
+    fn request(&self, path: &str) -> Result<ureq::http::Response<ureq::Body>> {
+        let mut request = self.agent.get(format!("{}{}", self.url, path));
+        if let Some(token) = &self.token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        check(request.call().map_err(request_failed)?)
+    }
+}
+
+// the JSON transaction `POST /transactions` takes
+fn tx_body(
+    tx_type: String,
+    client: u16,
+    tx: u32,
+    amount: Option<Amount>,
+    currency: Option<String>,
+    timestamp: Option<u64>,
+    reason: Option<String>,
+) -> serde_json::Value {
+    let mut body = json!({ "type": tx_type, "client": client, "tx": tx });
+    for (key, value) in [
+        ("amount", amount.map(|amount| json!(amount))),
+        ("currency", currency.map(Into::into)),
+        ("timestamp", timestamp.map(Into::into)),
+        ("reason", reason.map(Into::into)),
+    ] {
+        if let Some(value) = value {
+            body[key] = value;
+        }
+    }
+    body
+}
+
+// `response` if it succeeded, or an error with what the server said was wrong
+fn check(response: ureq::http::Response<ureq::Body>) -> Result<ureq::http::Response<ureq::Body>> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.into_body().read_to_string().unwrap_or_default();
+    let message = match serde_json::from_str::<ErrorBody>(&body) {
+        Ok(ErrorBody { code, error }) => format!("{} ({})", error, code),
+        Err(_) if status == ureq::http::StatusCode::NOT_FOUND => "not found".to_string(),
+        Err(_) => body,
+    };
+
+    Err(Error::Io(std::io::Error::other(format!(
+        "server answered {}: {}",
+        status.as_u16(),
+        message
+    ))))
+}
+
+fn parse<'a, T: Deserialize<'a>>(body: &'a str) -> Result<T> {
+    serde_json::from_str(body).map_err(|e| {
+        Error::Io(std::io::Error::other(format!(
+            "unexpected server response: {}",
+            e
+        )))
+    })
+}
+
+fn request_failed(e: ureq::Error) -> Error {
+    Error::Io(std::io::Error::other(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+    use payments_engine::amount;
+
+    // answer one request on a local port with `status` and `body`, returning the server's URL
+    // and the request it got
+    fn serve_once(
+        status: &'static str,
+        body: &'static str,
+    ) -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // the head, and the body by its content-length
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length:")?
+                                .trim()
+                                .parse()
+                                .ok()
+                        })
+                        .unwrap_or(0);
+                    if body.len() >= length || n == 0 {
+                        break;
+                    }
+                }
+            }
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+
+        (url, server)
+    }
+
+    #[test]
+    fn test_tx_body() {
+        assert_eq!(
+            tx_body(
+                "deposit".to_string(),
+                1,
+                2,
+                Some(amount!(10.5)),
+                Some("EUR".to_string()),
+                None,
+                None
+            )
+            .to_string(),
+            r#"{"amount":"10.5","client":1,"currency":"EUR","tx":2,"type":"deposit"}"#
+        );
+        assert_eq!(
+            tx_body(
+                "unlock".to_string(),
+                1,
+                3,
+                None,
+                None,
+                None,
+                Some("reviewed".to_string())
+            )
+            .to_string(),
+            r#"{"client":1,"reason":"reviewed","tx":3,"type":"unlock"}"#
+        );
+    }
+
+    #[test]
+    fn test_account_success() {
+        let (url, server) = serve_once(
+            "200 OK",
+            r#"{"id":7,"balances":{"":{"available":"1.5","held":"0","total":"1.5"}},"status":"active"}"#,
+        );
+        let mut out = Vec::new();
+
+        Client::new(&url, Some("secret".to_string()))
+            .run(
+                ClientCommand::Account { client: 7 },
+                &mut out,
+                OutputFormat::Csv,
+            )
+            .unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /accounts/7 "));
+        assert!(request.contains("authorization: Bearer secret"));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked,status\n7,1.5000,0.0000,1.5000,false,active\n"
+        );
+    }
+
+    #[test]
+    fn test_submit_failure_shows_server_error() {
+        let (url, server) = serve_once(
+            "422 Unprocessable Entity",
+            r#"{"category":"insufficient-funds","code":"insufficient-funds","error":"Insufficient funds."}"#,
+        );
+
+        let error = Client::new(&url, None)
+            .run(
+                ClientCommand::Submit {
+                    tx_type: "withdrawal".to_string(),
+                    client: 1,
+                    tx: 1,
+                    amount: Some(amount!(5)),
+                    currency: None,
+                    timestamp: None,
+                    reason: None,
+                },
+                Vec::new(),
+                OutputFormat::Csv,
+            )
+            .unwrap_err();
+
+        assert!(server.join().unwrap().starts_with("POST /transactions "));
+        assert!(
+            error
+                .to_string()
+                .contains("server answered 422: Insufficient funds. (insufficient-funds)")
+        );
+    }
+}
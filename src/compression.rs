@@ -0,0 +1,109 @@
+use std::io::Read;
+use std::path::Path;
+
+#[cfg(not(feature = "compression"))]
+use payments_engine::Error;
+use payments_engine::Result;
+
+// how an input file is compressed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    // by extension: `.gz` is gzip, `.zst` is zstd, anything else (and stdin) is uncompressed
+    #[default]
+    Auto,
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    // the compression `path` is read with, `Auto` resolved by its extension
+    pub fn of(self, path: &Path) -> Compression {
+        if self != Compression::Auto {
+            return self;
+        }
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+// wrap `reader` so it streams out the decompressed input
+#[cfg(feature = "compression")]
+pub fn decompress<'a, R: Read + 'a>(
+    reader: R,
+    compression: Compression,
+) -> Result<Box<dyn Read + 'a>> {
+    match compression {
+        Compression::Auto | Compression::None => Ok(Box::new(reader)),
+        // a gzip file can be several members back to back, as `cat a.gz b.gz` makes
+        Compression::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(reader))),
+        Compression::Zstd => Ok(Box::new(zstd::Decoder::new(reader)?)),
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+pub fn decompress<'a, R: Read + 'a>(
+    reader: R,
+    compression: Compression,
+) -> Result<Box<dyn Read + 'a>> {
+    match compression {
+        Compression::Auto | Compression::None => Ok(Box::new(reader)),
+        Compression::Gzip | Compression::Zstd => Err(Error::ConfigError(
+            "compressed input requires building with the `compression` feature".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_of() {
+        assert_eq!(
+            Compression::Auto.of(Path::new("tx.csv.gz")),
+            Compression::Gzip
+        );
+        assert_eq!(Compression::Auto.of(Path::new("tx.zst")), Compression::Zstd);
+        assert_eq!(Compression::Auto.of(Path::new("tx.csv")), Compression::None);
+        assert_eq!(Compression::Auto.of(Path::new("-")), Compression::None);
+        assert_eq!(Compression::Gzip.of(Path::new("tx.csv")), Compression::Gzip);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_decompress() {
+        use std::io::Write;
+
+        const CSV: &str = "type,client,tx,amount\ndeposit,1,1,10\n";
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(CSV.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(CSV.as_bytes(), 0).unwrap();
+
+        for (compressed, compression) in [
+            (gzip, Compression::Gzip),
+            (zstd, Compression::Zstd),
+            (CSV.as_bytes().to_vec(), Compression::None),
+        ] {
+            let mut out = String::new();
+            decompress(compressed.as_slice(), compression)
+                .unwrap()
+                .read_to_string(&mut out)
+                .unwrap();
+            assert_eq!(out, CSV);
+        }
+    }
+
+    #[cfg(not(feature = "compression"))]
+    #[test]
+    fn test_decompress_failure_without_feature() {
+        assert!(matches!(
+            decompress(&b""[..], Compression::Gzip),
+            Err(Error::ConfigError(_))
+        ));
+    }
+}
@@ -0,0 +1,283 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{ArgMatches, parser::ValueSource};
+use payments_engine::{Amount, Error, ErrorCategory, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::{
+    AccountMismatchMode, Cli, DuplicateMode, ErrorPolicyMode, LockMode, NegativeAvailableMode,
+    PendingOverflowMode, PrecisionMode, RoundingModeArg, parse_error_action, parse_interest_rate,
+    policy::ErrorAction,
+};
+
+// engine behavior read from a `--config` TOML file, keyed like the options it stands in for and
+// taking the same values. Lists take one entry per repetition of the option
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct EngineConfig {
+    duplicates: Option<DuplicateMode>,
+    error_policy: Option<ErrorPolicyMode>,
+    #[serde(default)]
+    on_error: Vec<String>,
+    precision: Option<PrecisionMode>,
+    rounding_mode: Option<RoundingModeArg>,
+    min_amount: Option<Amount>,
+    max_amount: Option<Amount>,
+    lock_policy: Option<LockMode>,
+    account_mismatch: Option<AccountMismatchMode>,
+    negative_available: Option<NegativeAvailableMode>,
+    dispute_window: Option<u64>,
+    hold_expiry: Option<u64>,
+    clearing_period: Option<u64>,
+    #[serde(default)]
+    interest_rate: Vec<String>,
+    fee_schedule: Option<PathBuf>,
+    withdrawal_limits: Option<PathBuf>,
+    balance_thresholds: Option<PathBuf>,
+    risk_rules: Option<PathBuf>,
+    pending_disputes: Option<usize>,
+    pending_dispute_max_age: Option<u64>,
+    pending_overflow: Option<PendingOverflowMode>,
+    expected_accounts: Option<usize>,
+    evict_settled: Option<bool>,
+}
+
+impl EngineConfig {
+    // read the config at `path`. The files it names are taken relative to its directory
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut config: EngineConfig = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| Error::ConfigError(format!("{}: {}", path.display(), e)))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for file in [
+            &mut config.fee_schedule,
+            &mut config.withdrawal_limits,
+            &mut config.balance_thresholds,
+            &mut config.risk_rules,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *file = dir.join(&*file);
+        }
+
+        Ok(config)
+    }
+
+    // fill in the options `matches` didn't get on the command line. Repeatable options keep the
+    // config's entries ahead of the command line's, so the command line wins where both set the
+    // same category or currency
+    pub fn apply(self, cli: &mut Cli, matches: &ArgMatches) -> Result<()> {
+        let mut error_actions = self.error_actions()?;
+        error_actions.append(&mut cli.error_actions);
+        cli.error_actions = error_actions;
+        let mut interest_rates = self.interest_rates()?;
+        interest_rates.append(&mut cli.interest_rates);
+        cli.interest_rates = interest_rates;
+        self.fill(cli, |id| {
+            matches.value_source(id) == Some(ValueSource::CommandLine)
+        });
+
+        Ok(())
+    }
+
+    // set every engine policy the config has on `cli`, over whatever it was given, for a second
+    // engine configured like `cli` except where the config says otherwise. Its interest rates go
+    // after `cli`'s, so they win for the same currency. Options about reading rows rather than
+    // applying them are refused, as the second engine gets the rows `cli`'s engine got
+    pub fn overlay(self, cli: &mut Cli) -> Result<()> {
+        for (key, set) in [
+            ("error-policy", self.error_policy.is_some()),
+            ("on-error", !self.on_error.is_empty()),
+            ("precision", self.precision.is_some()),
+            ("rounding-mode", self.rounding_mode.is_some()),
+        ] {
+            if set {
+                return Err(Error::ConfigError(format!(
+                    "`{}` doesn't apply to a shadow engine",
+                    key
+                )));
+            }
+        }
+        let mut interest_rates = self.interest_rates()?;
+        cli.interest_rates.append(&mut interest_rates);
+        self.fill(cli, |_| false);
+
+        Ok(())
+    }
+
+    fn error_actions(&self) -> Result<Vec<(ErrorCategory, ErrorAction)>> {
+        self.on_error
+            .iter()
+            .map(|entry| parse_error_action(entry))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| Error::ConfigError(format!("on-error: {}", e)))
+    }
+
+    fn interest_rates(&self) -> Result<Vec<(Option<String>, Decimal)>> {
+        self.interest_rate
+            .iter()
+            .map(|entry| parse_interest_rate(entry))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| Error::ConfigError(format!("interest-rate: {}", e)))
+    }
+
+    // set the single-valued options the config has on `cli`, except those `given` already
+    fn fill(self, cli: &mut Cli, given: impl Fn(&str) -> bool) {
+        macro_rules! fill {
+            ($($field:ident),* $(,)?) => {$(
+                if let Some(value) = self.$field
+                    && !given(stringify!($field))
+                {
+                    cli.$field = value.into();
+                }
+            )*};
+        }
+        fill!(
+            duplicates,
+            error_policy,
+            precision,
+            rounding_mode,
+            min_amount,
+            max_amount,
+            lock_policy,
+            account_mismatch,
+            negative_available,
+            dispute_window,
+            hold_expiry,
+            clearing_period,
+            fee_schedule,
+            withdrawal_limits,
+            balance_thresholds,
+            risk_rules,
+            pending_disputes,
+            pending_dispute_max_age,
+            pending_overflow,
+            expected_accounts,
+            evict_settled,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+    use payments_engine::amount;
+
+    fn cli(args: &[&str], config: &str) -> Result<Cli> {
+        let matches = Cli::command()
+            .try_get_matches_from([&["payments-engine"], args].concat())
+            .unwrap();
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        toml::from_str::<EngineConfig>(config)
+            .map_err(|e| Error::ConfigError(e.to_string()))?
+            .apply(&mut cli, &matches)?;
+        Ok(cli)
+    }
+
+    #[test]
+    fn test_apply() {
+        let config = r#"
+            duplicates = "skip"
+            precision = "reject"
+            lock-policy = "never"
+            max-amount = "500"
+            pending-disputes = 10
+            on-error = ["duplicate=quarantine", "parse=abort"]
+        "#;
+
+        let cli = cli(
+            &["--precision", "truncate", "--on-error", "parse=warn"],
+            config,
+        )
+        .unwrap();
+
+        assert!(matches!(cli.duplicates, DuplicateMode::Skip));
+        assert!(matches!(cli.precision, PrecisionMode::Truncate));
+        assert!(matches!(cli.lock_policy, LockMode::Never));
+        assert_eq!(cli.max_amount, Some(amount!(500)));
+        assert_eq!(cli.pending_disputes, 10);
+        // later entries win, and the command line's come last
+        assert!(matches!(
+            cli.error_actions[..],
+            [
+                (ErrorCategory::Duplicate, ErrorAction::Quarantine),
+                (ErrorCategory::Parse, ErrorAction::Abort),
+                (ErrorCategory::Parse, ErrorAction::Warn),
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_apply_failure() {
+        for config in [
+            "duplicates = \"maybe\"",
+            "max-withdrawal = \"5\"",
+            "on-error = [\"parse\"]",
+            "interest-rate = [\"EUR=lots\"]",
+        ] {
+            assert!(
+                matches!(cli(&[], config), Err(Error::ConfigError(_))),
+                "{}",
+                config
+            );
+        }
+    }
+
+    #[test]
+    fn test_overlay() {
+        let matches = Cli::command()
+            .try_get_matches_from([
+                "payments-engine",
+                "--negative-available",
+                "allow",
+                "--max-amount",
+                "100",
+                "--interest-rate",
+                "2",
+            ])
+            .unwrap();
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        let config: EngineConfig =
+            toml::from_str("negative-available = \"reject\"\ninterest-rate = [\"3\"]").unwrap();
+
+        config.overlay(&mut cli).unwrap();
+
+        // the config wins even over the command line
+        assert!(matches!(
+            cli.negative_available,
+            NegativeAvailableMode::Reject
+        ));
+        assert_eq!(cli.max_amount, Some(amount!(100)));
+        assert_eq!(
+            cli.interest_rates,
+            [(None, Decimal::from(2)), (None, Decimal::from(3))]
+        );
+        let config: EngineConfig = toml::from_str("precision = \"reject\"").unwrap();
+        assert!(matches!(
+            config.overlay(&mut cli),
+            Err(Error::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_resolves_files() {
+        let dir =
+            std::env::temp_dir().join(format!("payments-engine-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("engine.toml");
+        fs::write(
+            &path,
+            "fee-schedule = \"fees.toml\"\nrisk-rules = \"/etc/risk.toml\"\n",
+        )
+        .unwrap();
+
+        let config = EngineConfig::load(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(config.fee_schedule, Some(dir.join("fees.toml")));
+        assert_eq!(config.risk_rules, Some(PathBuf::from("/etc/risk.toml")));
+    }
+}
@@ -0,0 +1,448 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use aes_gcm::{
+    Aes256Gcm, Key, KeyInit,
+    aead::{Aead, AeadCore, OsRng, Payload},
+};
+use sha2::{Digest, Sha256};
+
+use payments_engine::{Error, Result};
+
+// what an encrypted file starts with, ahead of the id of the key it's encrypted with
+const MAGIC: &[u8; 8] = b"PEENC\0\0\x01";
+
+const KEY_ID_BYTES: usize = 8;
+const NONCE_BYTES: usize = 12;
+const TAG_BYTES: usize = 16;
+const HEADER_BYTES: usize = MAGIC.len() + KEY_ID_BYTES;
+
+// the most plaintext sealed into one frame
+const FRAME_BYTES: usize = 64 * 1024;
+
+type KeyId = [u8; KEY_ID_BYTES];
+
+// the keys state files, the WAL, and the event and audit logs are encrypted at rest with. Files
+// are written with the current key, and read with whichever of the keys they name, so a key is
+// rotated by making it current and keeping the old one to read with until `reencrypt` has moved
+// every file over
+pub struct Keyring {
+    current: Option<(KeyId, Aes256Gcm)>,
+    keys: Vec<(KeyId, Aes256Gcm)>,
+}
+
+impl Keyring {
+    // `current` to write with (and read), and `older` keys only to read with
+    pub fn new(current: Option<[u8; 32]>, older: &[[u8; 32]]) -> Self {
+        let entry = |key: &[u8; 32]| {
+            (
+                key_id(key),
+                Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+            )
+        };
+        Self {
+            current: current.as_ref().map(entry),
+            keys: current.iter().chain(older).map(entry).collect(),
+        }
+    }
+
+    fn key(&self, id: &KeyId) -> Option<&Aes256Gcm> {
+        self.keys
+            .iter()
+            .find(|(key_id, _)| key_id == id)
+            .map(|(_, cipher)| cipher)
+    }
+}
+
+// the keyring files are written and read with, set once at startup from the command line. It's
+// process-wide since every file holding engine state goes through it, from wherever it's opened
+static KEYRING: OnceLock<Keyring> = OnceLock::new();
+
+pub fn init(keyring: Keyring) {
+    let _ = KEYRING.set(keyring);
+}
+
+// an AES-256 key read from `path`: 32 raw bytes, or 64 hex digits (e.g. `openssl rand -hex 32`)
+pub fn read_key(path: &Path) -> Result<[u8; 32]> {
+    let contents = fs::read(path)?;
+    let hex = std::str::from_utf8(&contents)
+        .ok()
+        .and_then(|text| hex::decode(text.trim()).ok());
+    hex.as_deref().unwrap_or(&contents).try_into().map_err(|_| {
+        Error::ConfigError(format!(
+            "{} is not a 256-bit key (32 bytes, or 64 hex digits)",
+            path.display()
+        ))
+    })
+}
+
+// the first bytes of the key's SHA-256, naming it in the files it encrypts without giving it away
+fn key_id(key: &[u8; 32]) -> KeyId {
+    let digest = Sha256::digest(key);
+    let mut id = [0; KEY_ID_BYTES];
+    id.copy_from_slice(&digest[..KEY_ID_BYTES]);
+    id
+}
+
+// a frame's position in its file, authenticated with it so frames can't be reordered or dropped
+// from the middle of a file
+fn frame_aad(index: u64) -> [u8; 8] {
+    index.to_le_bytes()
+}
+
+// writes through to `inner` as is without a current key. With one, it writes a header naming the
+// key and then the data as frames sealed with AES-256-GCM: a little-endian u32 length, a random
+// nonce, and the ciphertext with its tag. A frame is sealed every FRAME_BYTES and on every flush,
+// so a flushed write is on its way to disk in full, and a crash can only tear the last frame
+pub struct Writer<W: Write> {
+    inner: W,
+    sealing: Option<Sealing>,
+}
+
+struct Sealing {
+    cipher: &'static Aes256Gcm,
+    frame: u64,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W) -> io::Result<Self> {
+        Self::with_keyring(inner, KEYRING.get())
+    }
+
+    fn with_keyring(mut inner: W, keyring: Option<&'static Keyring>) -> io::Result<Self> {
+        let sealing = match keyring.and_then(|keyring| keyring.current.as_ref()) {
+            Some((id, cipher)) => {
+                inner.write_all(MAGIC)?;
+                inner.write_all(id)?;
+                Some(Sealing {
+                    cipher,
+                    frame: 0,
+                    buffer: Vec::new(),
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self { inner, sealing })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    // seal what's buffered, at most FRAME_BYTES at a time
+    fn seal(&mut self) -> io::Result<()> {
+        let Some(sealing) = &mut self.sealing else {
+            return Ok(());
+        };
+        for plaintext in sealing.buffer.chunks(FRAME_BYTES) {
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let ciphertext = sealing
+                .cipher
+                .encrypt(
+                    &nonce,
+                    Payload {
+                        msg: plaintext,
+                        aad: &frame_aad(sealing.frame),
+                    },
+                )
+                .map_err(|_| io::Error::other("failed to encrypt"))?;
+            let mut frame = Vec::with_capacity(4 + NONCE_BYTES + ciphertext.len());
+            frame.extend_from_slice(&(ciphertext.len() as u32).to_le_bytes());
+            frame.extend_from_slice(&nonce);
+            frame.extend_from_slice(&ciphertext);
+            // a frame in a single write, so it's torn at worst and never interleaved
+            self.inner.write_all(&frame)?;
+            sealing.frame += 1;
+        }
+        sealing.buffer.clear();
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(sealing) = &mut self.sealing else {
+            return self.inner.write(buf);
+        };
+        sealing.buffer.extend_from_slice(buf);
+        if sealing.buffer.len() >= FRAME_BYTES {
+            self.seal()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.seal()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for Writer<W> {
+    // like a BufWriter, what's buffered is written out on a best-effort basis
+    fn drop(&mut self) {
+        let _ = self.seal();
+    }
+}
+
+// reads a file `Writer` wrote, decrypting it with the keyring's key it names, or as is if it
+// isn't encrypted, so files written before encryption was turned on can still be read
+pub struct Reader<R: Read> {
+    inner: R,
+    opening: Option<Opening>,
+    // plaintext read but not yet handed out
+    pending: Vec<u8>,
+    at: usize,
+}
+
+struct Opening {
+    cipher: &'static Aes256Gcm,
+    frame: u64,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R) -> io::Result<Self> {
+        Self::with_keyring(inner, KEYRING.get())
+    }
+
+    fn with_keyring(mut inner: R, keyring: Option<&'static Keyring>) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(HEADER_BYTES);
+        (&mut inner)
+            .take(HEADER_BYTES as u64)
+            .read_to_end(&mut header)?;
+        if !header.starts_with(MAGIC) {
+            return Ok(Self {
+                inner,
+                opening: None,
+                pending: header,
+                at: 0,
+            });
+        }
+        let id: KeyId = header[MAGIC.len()..]
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::UnexpectedEof, "truncated header"))?;
+        let cipher = keyring
+            .and_then(|keyring| keyring.key(&id))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "encrypted with key {} which isn't among the --encryption-key or --decryption-key keys",
+                        hex::encode(id)
+                    ),
+                )
+            })?;
+
+        Ok(Self {
+            inner,
+            opening: Some(Opening { cipher, frame: 0 }),
+            pending: Vec::new(),
+            at: 0,
+        })
+    }
+
+    // the next frame's plaintext, or `false` at the end of the file
+    fn open_frame(&mut self) -> io::Result<bool> {
+        let Some(opening) = &mut self.opening else {
+            return Ok(false);
+        };
+        let mut length = [0; 4];
+        match self.inner.read(&mut length[..1])? {
+            0 => return Ok(false),
+            _ => self.inner.read_exact(&mut length[1..])?,
+        }
+        let length = u32::from_le_bytes(length) as usize;
+        if !(TAG_BYTES..=FRAME_BYTES + TAG_BYTES).contains(&length) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame {} has an invalid length", opening.frame),
+            ));
+        }
+        let mut frame = vec![0; NONCE_BYTES + length];
+        self.inner.read_exact(&mut frame)?;
+        let (nonce, ciphertext) = frame.split_at(NONCE_BYTES);
+        self.pending = opening
+            .cipher
+            .decrypt(
+                nonce.into(),
+                Payload {
+                    msg: ciphertext,
+                    aad: &frame_aad(opening.frame),
+                },
+            )
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("frame {} fails authentication", opening.frame),
+                )
+            })?;
+        self.at = 0;
+        opening.frame += 1;
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.at == self.pending.len() {
+            if self.opening.is_none() {
+                return self.inner.read(buf);
+            }
+            if !self.open_frame()? {
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.pending.len() - self.at);
+        buf[..len].copy_from_slice(&self.pending[self.at..self.at + len]);
+        self.at += len;
+
+        Ok(len)
+    }
+}
+
+// how much of an encrypted file is whole frames, dropping a frame torn by a crash mid-write;
+// `None` if the file isn't encrypted
+pub fn whole_frames(contents: &[u8]) -> Option<usize> {
+    if !contents.starts_with(MAGIC) {
+        return None;
+    }
+    let mut end = HEADER_BYTES;
+    while let Some(length) = contents.get(end..end + 4) {
+        let length = u32::from_le_bytes(length.try_into().expect("4 bytes")) as usize;
+        let next = end + 4 + NONCE_BYTES + length;
+        if next > contents.len() {
+            break;
+        }
+        end = next;
+    }
+
+    Some(end.min(contents.len()))
+}
+
+// rewrites the file at `path` under the current key, through a sibling temp file renamed over it
+pub fn reencrypt(path: &Path) -> Result<()> {
+    if KEYRING
+        .get()
+        .is_none_or(|keyring| keyring.current.is_none())
+    {
+        return Err(Error::ConfigError(
+            "reencrypt needs --encryption-key".to_string(),
+        ));
+    }
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut reader = Reader::new(fs::File::open(path)?)?;
+    let mut writer = Writer::new(fs::File::create(&tmp_path)?)?;
+    io::copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyring(current: u8, older: &[u8]) -> &'static Keyring {
+        let older: Vec<_> = older.iter().map(|byte| [*byte; 32]).collect();
+        Box::leak(Box::new(Keyring::new(Some([current; 32]), &older)))
+    }
+
+    fn seal(keyring: &'static Keyring, writes: &[&[u8]]) -> Vec<u8> {
+        let mut writer = Writer::with_keyring(Vec::new(), Some(keyring)).unwrap();
+        for write in writes {
+            writer.write_all(write).unwrap();
+            writer.flush().unwrap();
+        }
+        writer.get_ref().clone()
+    }
+
+    fn open(keyring: Option<&'static Keyring>, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+        Reader::with_keyring(sealed, keyring)?.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn test_round_trip_success() {
+        let keyring = keyring(1, &[]);
+        let large = vec![b'x'; FRAME_BYTES + 10];
+
+        let sealed = seal(keyring, &[b"first\n", b"second\n", &large]);
+
+        assert!(sealed.starts_with(MAGIC));
+        assert!(!sealed.windows(6).any(|window| window == b"first\n"));
+        let mut expected = b"first\nsecond\n".to_vec();
+        expected.extend_from_slice(&large);
+        assert_eq!(open(Some(keyring), &sealed).unwrap(), expected);
+        // plaintext files read as they are
+        assert_eq!(open(Some(keyring), b"a,b\n").unwrap(), b"a,b\n");
+        assert_eq!(open(None, b"").unwrap(), b"");
+    }
+
+    #[test]
+    fn test_round_trip_after_rotation_success() {
+        let sealed = seal(keyring(1, &[]), &[b"under the old key"]);
+
+        // the old key only reads, while new files are written with the new one
+        let rotated = keyring(2, &[1]);
+        assert_eq!(open(Some(rotated), &sealed).unwrap(), b"under the old key");
+        let resealed = seal(rotated, &[b"under the new key"]);
+        assert!(open(Some(keyring(1, &[])), &resealed).is_err());
+    }
+
+    #[test]
+    fn test_open_failure_tampered_or_reordered() {
+        let keyring = keyring(3, &[]);
+        let sealed = seal(keyring, &[b"one", b"two"]);
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            open(Some(keyring), &tampered).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // each frame is 4 + 12 + 3 + 16 bytes
+        let frame = 4 + NONCE_BYTES + 3 + TAG_BYTES;
+        let mut swapped = sealed[..HEADER_BYTES].to_vec();
+        swapped.extend_from_slice(&sealed[HEADER_BYTES + frame..]);
+        swapped.extend_from_slice(&sealed[HEADER_BYTES..HEADER_BYTES + frame]);
+        assert!(open(Some(keyring), &swapped).is_err());
+        assert!(open(None, &sealed).is_err());
+    }
+
+    #[test]
+    fn test_whole_frames() {
+        let sealed = seal(keyring(4, &[]), &[b"one", b"two"]);
+
+        assert_eq!(whole_frames(&sealed), Some(sealed.len()));
+        assert_eq!(
+            whole_frames(&sealed[..sealed.len() - 5]),
+            Some(HEADER_BYTES + 4 + NONCE_BYTES + 3 + TAG_BYTES)
+        );
+        assert_eq!(whole_frames(b"plain\n"), None);
+    }
+
+    #[test]
+    fn test_read_key() {
+        let path = std::env::temp_dir().join(format!("payments-engine-key-{}", std::process::id()));
+
+        fs::write(&path, format!("{}\n", "ab".repeat(32))).unwrap();
+        assert_eq!(read_key(&path).unwrap(), [0xab; 32]);
+        fs::write(&path, [7; 32]).unwrap();
+        assert_eq!(read_key(&path).unwrap(), [7; 32]);
+        fs::write(&path, "too short").unwrap();
+        assert!(matches!(read_key(&path), Err(Error::ConfigError(_))));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
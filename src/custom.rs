@@ -0,0 +1,288 @@
+use serde::Deserialize;
+
+use crate::{
+    account::Account,
+    amount::Amount,
+    error::{Error, Result},
+    transaction::DEFAULT_CURRENCY,
+};
+
+/// Applies transactions of a type of a deployment's own, such as a "bonus" or a "levy",
+/// registered under its name with [`custom_type`](crate::PaymentsEngineBuilder::custom_type) and
+/// processed with [`process_custom`](crate::PaymentsEngine::process_custom).
+pub trait CustomHandler {
+    /// Applies `tx` through `account`, its client's account. Returning an error refuses the
+    /// transaction, leaving the account as it was.
+    fn apply(&mut self, tx: &CustomTransaction, account: &mut AccountHandle) -> Result<()>;
+}
+
+/// A transaction of a custom type, read from the same columns as a
+/// [`Transaction`](crate::Transaction) with the type's name as is.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CustomTransaction {
+    #[serde(rename = "type")]
+    pub tx_type: String,
+    #[serde(rename = "client")]
+    pub account_id: u16,
+    #[serde(rename = "tx")]
+    pub tx_id: u32,
+    pub amount: Option<Amount>,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl CustomTransaction {
+    /// The tx's currency, or [`DEFAULT_CURRENCY`] when none is given.
+    pub fn currency_code(&self) -> &str {
+        self.currency.as_deref().unwrap_or(DEFAULT_CURRENCY)
+    }
+}
+
+/// A client's account as a [`CustomHandler`] sees it.
+///
+/// Funds only move by crediting and debiting, checked against the account as deposits and
+/// withdrawals are. The engine books each credit and debit once the handler succeeds, posting it
+/// to the ledger and emitting an [`Event::CustomApplied`](crate::Event::CustomApplied) for it.
+pub struct AccountHandle {
+    account: Account,
+    changes: Vec<(String, Amount)>,
+}
+
+impl AccountHandle {
+    pub(crate) fn new(account: Account) -> Self {
+        Self {
+            account,
+            changes: Vec::new(),
+        }
+    }
+
+    /// The account, with the credits and debits made so far.
+    pub fn account(&self) -> &Account {
+        &self.account
+    }
+
+    /// Credits `amount` to `available`, failing as a deposit would, e.g. on a locked account.
+    pub fn credit(&mut self, currency: &str, amount: Amount) -> Result<()> {
+        self.account.deposit(currency, amount)?;
+        self.changes.push((currency.to_owned(), amount));
+        Ok(())
+    }
+
+    /// Debits `amount` from `available`, failing as a withdrawal would, e.g. without the funds.
+    pub fn debit(&mut self, currency: &str, amount: Amount) -> Result<()> {
+        let change = negate(amount)?;
+        self.account.withdrawal(currency, amount)?;
+        self.changes.push((currency.to_owned(), change));
+        Ok(())
+    }
+
+    // the signed changes made, in order
+    pub(crate) fn into_changes(self) -> Vec<(String, Amount)> {
+        self.changes
+    }
+}
+
+// the other way round, for turning debits into changes and back; fails rather than overflowing
+// on the one amount that has no opposite
+pub(crate) fn negate(amount: Amount) -> Result<Amount> {
+    Amount::ZERO
+        .checked_sub(amount)
+        .ok_or(Error::TransactionError("Amount is out of range."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::amount::PrecisionPolicy;
+    use crate::engine::PaymentsEngine;
+    use crate::error::Error;
+    use crate::rows::{Row, RowParser};
+    use crate::testing::TxBuilder;
+
+    // credits the amount, plus 1 more for clients with a balance already
+    struct Bonus;
+
+    impl CustomHandler for Bonus {
+        fn apply(&mut self, tx: &CustomTransaction, account: &mut AccountHandle) -> Result<()> {
+            let amount = tx.amount.unwrap_or_default();
+            account.credit(tx.currency_code(), amount)?;
+            if account.account().balance(tx.currency_code()).total > amount {
+                account.credit(tx.currency_code(), amount!(1))?;
+            }
+            Ok(())
+        }
+    }
+
+    // debits the amount twice, so a levy the client can only half pay is refused part way
+    struct DoubleLevy;
+
+    impl CustomHandler for DoubleLevy {
+        fn apply(&mut self, tx: &CustomTransaction, account: &mut AccountHandle) -> Result<()> {
+            let amount = tx.amount.unwrap_or_default();
+            account.debit(tx.currency_code(), amount)?;
+            account.debit(tx.currency_code(), amount)
+        }
+    }
+
+    fn custom(tx_type: &str, tx_id: u32, amount: Amount) -> CustomTransaction {
+        CustomTransaction {
+            tx_type: tx_type.to_string(),
+            account_id: 1,
+            tx_id,
+            amount: Some(amount),
+            currency: None,
+            timestamp: None,
+            reason: None,
+        }
+    }
+
+    fn engine() -> PaymentsEngine {
+        let mut engine = PaymentsEngine::builder()
+            .custom_type("bonus", Box::new(Bonus))
+            .custom_type("levy", Box::new(DoubleLevy))
+            .build();
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(10)).build())
+            .unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_process_custom_success() {
+        let mut engine = engine();
+
+        engine
+            .process_custom(&custom("bonus", 2, amount!(5)))
+            .unwrap();
+        engine
+            .process_custom(&custom("levy", 3, amount!(1)))
+            .unwrap();
+
+        let balance = engine.account(1).unwrap().balance(DEFAULT_CURRENCY);
+        assert_eq!(balance.available, amount!(14));
+        assert_eq!(balance.total, amount!(14));
+    }
+
+    #[test]
+    fn test_process_custom_failure_refused_part_way() {
+        let mut engine = engine();
+
+        let result = engine.process_custom(&custom("levy", 2, amount!(6)));
+
+        let error = result.unwrap_err();
+        assert!(matches!(error.root(), Error::InsufficientFunds(_)));
+        assert_eq!(error.context().unwrap().tx_id, Some(2));
+        // the first debit was undone with the second
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).total,
+            amount!(10)
+        );
+    }
+
+    #[test]
+    fn test_process_custom_failure_unknown_or_duplicate() {
+        let mut engine = engine();
+
+        assert!(matches!(
+            engine
+                .process_custom(&custom("rebate", 2, amount!(1)))
+                .unwrap_err()
+                .root(),
+            Error::TransactionError(_)
+        ));
+        assert!(matches!(
+            engine
+                .process_custom(&custom("bonus", 1, amount!(1)))
+                .unwrap_err()
+                .root(),
+            Error::DuplicateTransaction(1)
+        ));
+        // a custom tx's own id is taken once it's applied
+        engine
+            .process_custom(&custom("bonus", 2, amount!(5)))
+            .unwrap();
+        assert!(matches!(
+            engine
+                .process_custom(&custom("bonus", 2, amount!(5)))
+                .unwrap_err()
+                .root(),
+            Error::DuplicateTransaction(2)
+        ));
+        assert!(matches!(
+            engine
+                .process_tx(&TxBuilder::deposit(1, 2, amount!(5)).build())
+                .unwrap_err()
+                .root(),
+            Error::DuplicateTransaction(2)
+        ));
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).total,
+            amount!(16)
+        );
+    }
+
+    #[test]
+    fn test_process_custom_failure_opens_no_account() {
+        let mut engine = engine();
+
+        // the client's balance takes it, but the ledger's total of client funds can't
+        let result = engine.process_custom(&CustomTransaction {
+            account_id: 2,
+            ..custom("bonus", 2, Amount::MAX)
+        });
+
+        assert!(result.is_err());
+        assert!(engine.account(2).is_none());
+        assert_eq!(engine.accounts().count(), 1);
+    }
+
+    #[cfg(feature = "fixed-point")]
+    #[test]
+    fn test_negate_failure_without_opposite() {
+        assert_eq!(negate(amount!(5)).unwrap(), amount!(-5));
+        assert!(matches!(
+            negate(Amount::MIN),
+            Err(Error::TransactionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_process_rows_of_either_kind() {
+        let mut engine = engine();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader("type,client,tx,amount\ndeposit,1,2,5\nbonus,1,3,2\n".as_bytes());
+        let parser = RowParser::new(
+            &reader.headers().unwrap().clone(),
+            PrecisionPolicy::default(),
+        );
+
+        for record in reader.byte_records() {
+            match parser.parse_row(&record.unwrap()).unwrap() {
+                Row::Transaction(tx) => engine.process_tx(&tx).unwrap(),
+                Row::Custom(tx) => engine.process_custom(&tx).unwrap(),
+            }
+        }
+
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).total,
+            amount!(18)
+        );
+    }
+
+    #[test]
+    fn test_custom_transaction_deserializes_from_row() {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader("type,client,tx,amount\nbonus,1,2,5\n".as_bytes());
+
+        let tx: CustomTransaction = reader.deserialize().next().unwrap().unwrap();
+
+        assert_eq!(tx, custom("bonus", 2, amount!(5)));
+    }
+}
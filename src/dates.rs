@@ -0,0 +1,120 @@
+// calendar dates in UTC, as input files and statements give them, to and from seconds since the
+// Unix epoch
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+// seconds since the epoch by the wall clock
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// seconds since the epoch at the start of a `YYYY-MM-DD` date (or date-time, whose time is
+// dropped)
+pub fn midnight(date: &str) -> Option<u64> {
+    let mut parts = date.get(..10)?.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // days from civil, counting years from March so the leap day comes last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    u64::try_from(days).ok()?.checked_mul(SECS_PER_DAY)
+}
+
+// `secs` since the epoch as `YYYYMMDDHHMMSS`
+pub fn compact(secs: u64) -> String {
+    let (year, month, day, time) = civil(secs);
+
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+// `secs` since the epoch as an ISO 8601 `YYYY-MM-DDTHH:MM:SS` date-time
+pub fn iso(secs: u64) -> String {
+    let (year, month, day, time) = civil(secs);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+// the year, month and day of `secs` since the epoch, and the seconds into that day
+fn civil(secs: u64) -> (i64, i64, i64, u64) {
+    // civil from days, the inverse of `midnight`
+    let days = (secs / SECS_PER_DAY) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, secs % SECS_PER_DAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_midnight() {
+        assert_eq!(midnight("1970-01-01"), Some(0));
+        assert_eq!(midnight("2024-02-29T10:00:00"), Some(1_709_164_800));
+        assert_eq!(midnight("2024-03-01"), Some(1_709_251_200));
+        for date in [
+            "2024-13-01",
+            "2024-01-00",
+            "1969-12-31",
+            "24-1-1",
+            "yesterday",
+        ] {
+            assert_eq!(midnight(date), None, "{}", date);
+        }
+    }
+
+    #[test]
+    fn test_compact() {
+        assert_eq!(compact(0), "19700101000000");
+        assert_eq!(compact(1_709_164_800 + 3_723), "20240229010203");
+        assert_eq!(compact(midnight("2100-03-01").unwrap()), "21000301000000");
+    }
+
+    #[test]
+    fn test_iso() {
+        assert_eq!(iso(0), "1970-01-01T00:00:00");
+        assert_eq!(iso(1_709_164_800 + 3_723), "2024-02-29T01:02:03");
+    }
+}
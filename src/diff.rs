@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use payments_engine::{
+    AccountStatus, AmountExt, Balance, DEFAULT_CURRENCY, PaymentsEngine, Result,
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    output::{OutputFormat, fixed_dp},
+    seed,
+};
+
+// how a (client, currency) row differs between the two states, most notable first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    // only in the new state
+    Added,
+    // only in the old state
+    Removed,
+    // locked in the new state but not the old
+    Locked,
+    // any other status change
+    Status,
+    Balance,
+}
+
+// one differing row of the report. Balances are new minus old, a missing side counting as zero
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct DiffRow {
+    client: u16,
+    currency: String,
+    change: Change,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    old_status: Option<AccountStatus>,
+    new_status: Option<AccountStatus>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Row {
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    status: AccountStatus,
+}
+
+// read an account state from `path`: a snapshot saved by --save-state (a JSON object) or an
+// accounts csv as written by a run
+pub fn load(path: &Path) -> Result<PaymentsEngine> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let snapshot = reader
+        .fill_buf()?
+        .iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        == Some(&b'{');
+    if snapshot {
+        return PaymentsEngine::restore(reader);
+    }
+    let mut engine = PaymentsEngine::new();
+    seed::load(&mut engine, reader)?;
+
+    Ok(engine)
+}
+
+// write a row for every client and currency whose balances or status differ between `old` and
+// `new` to `writer`, in client order, and return whether there were none
+pub fn run<W: Write>(
+    old: &PaymentsEngine,
+    new: &PaymentsEngine,
+    mut writer: W,
+    format: OutputFormat,
+) -> Result<bool> {
+    let rows = changes(old, new);
+
+    match format {
+        OutputFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(&mut writer);
+            for row in &rows {
+                csv_writer.serialize(row)?;
+            }
+            csv_writer.flush()?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut writer, &rows).map_err(std::io::Error::other)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Jsonl => {
+            for row in &rows {
+                serde_json::to_writer(&mut writer, row).map_err(std::io::Error::other)?;
+                writeln!(writer)?;
+            }
+        }
+    }
+    writer.flush()?;
+
+    Ok(rows.is_empty())
+}
+
+// every client and currency whose balances or status differ between `old` and `new`, in client
+// order
+pub fn changes(old: &PaymentsEngine, new: &PaymentsEngine) -> Vec<DiffRow> {
+    diff(&rows(old), &rows(new))
+}
+
+// balances at the precision they're written with, so a snapshot and the csv of the same state
+// compare equal. An account that never held funds counts as a zero row, as in the output
+fn rows(engine: &PaymentsEngine) -> BTreeMap<(u16, String), Row> {
+    let mut rows = BTreeMap::new();
+    for account in engine.accounts() {
+        let mut balances: Vec<_> = account.balances.iter().collect();
+        let zero = Balance::default();
+        let default_currency = DEFAULT_CURRENCY.to_string();
+        if balances.is_empty() {
+            balances.push((&default_currency, &zero));
+        }
+        for (currency, balance) in balances {
+            rows.insert(
+                (account.id, currency.clone()),
+                Row {
+                    available: fixed_dp(balance.available.to_decimal()),
+                    held: fixed_dp(balance.held.to_decimal()),
+                    total: fixed_dp(balance.total.to_decimal()),
+                    status: account.status,
+                },
+            );
+        }
+    }
+    rows
+}
+
+fn diff(old: &BTreeMap<(u16, String), Row>, new: &BTreeMap<(u16, String), Row>) -> Vec<DiffRow> {
+    let mut keys: Vec<_> = old.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter_map(|key| {
+            let (before, after) = (old.get(key), new.get(key));
+            let change = match (before, after) {
+                (None, _) => Change::Added,
+                (_, None) => Change::Removed,
+                (Some(before), Some(after)) if before == after => return None,
+                (Some(before), Some(after)) if before.status == after.status => Change::Balance,
+                (Some(before), Some(after))
+                    if after.status == AccountStatus::Locked
+                        && before.status != AccountStatus::Locked =>
+                {
+                    Change::Locked
+                }
+                _ => Change::Status,
+            };
+            let delta = |field: fn(&Row) -> Decimal| {
+                fixed_dp(after.map_or(Decimal::ZERO, field) - before.map_or(Decimal::ZERO, field))
+            };
+            Some(DiffRow {
+                client: key.0,
+                currency: key.1.clone(),
+                change,
+                available: delta(|row| row.available),
+                held: delta(|row| row.held),
+                total: delta(|row| row.total),
+                old_status: before.map(|row| row.status),
+                new_status: after.map(|row| row.status),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine(accounts: &str) -> PaymentsEngine {
+        let mut engine = PaymentsEngine::new();
+        seed::load(&mut engine, accounts.as_bytes()).unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_run_success() {
+        let old = engine(
+            "client,currency,available,held,total,locked\n\
+             1,USD,10,0,10,false\n\
+             2,USD,5,5,10,false\n\
+             3,USD,1,0,1,false\n",
+        );
+        let new = engine(
+            "client,currency,available,held,total,locked\n\
+             1,USD,10.00,0,10,false\n\
+             2,USD,5,0,5,true\n\
+             4,EUR,2.5,0,2.5,false\n",
+        );
+        let mut report = Vec::new();
+
+        assert!(!run(&old, &new, &mut report, OutputFormat::Csv).unwrap());
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,currency,change,available,held,total,old_status,new_status\n\
+             2,USD,locked,0.0000,-5.0000,-5.0000,active,locked\n\
+             3,USD,removed,-1.0000,0.0000,-1.0000,active,\n\
+             4,EUR,added,2.5000,0.0000,2.5000,,active\n"
+        );
+    }
+
+    #[test]
+    fn test_run_identical() {
+        let accounts = "client,available,held,total,locked\n1,7.5,2.5,10,false\n";
+        let mut report = Vec::new();
+
+        assert!(
+            run(
+                &engine(accounts),
+                &engine(accounts),
+                &mut report,
+                OutputFormat::Csv
+            )
+            .unwrap()
+        );
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn test_load_snapshot_matches_csv() {
+        let dir = std::env::temp_dir().join(format!("payments-engine-diff-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv_path = dir.join("accounts.csv");
+        let snapshot_path = dir.join("state.json");
+        std::fs::write(
+            &csv_path,
+            "client,available,held,total,locked\n1,7.5,2.5,10,false\n",
+        )
+        .unwrap();
+        let engine = load(&csv_path).unwrap();
+        engine
+            .snapshot(File::create(&snapshot_path).unwrap())
+            .unwrap();
+
+        let snapshot = load(&snapshot_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(run(&engine, &snapshot, std::io::sink(), OutputFormat::Csv).unwrap());
+    }
+}
@@ -1,204 +1,5912 @@
-use std::collections::HashMap;
+use std::io::{BufRead, Read, Write};
+use std::time::Duration;
+
+use crate::amount::Amount;
+use rust_decimal::Decimal;
 
 use crate::{
-    account::Account,
-    error::Result,
-    transaction::{Transaction, TransactionType, TxRecord},
+    account::{Account, AccountStatus, AmountLimits, Balance},
+    archive::{ArchiveSink, JsonlArchive},
+    audit::{AuditRecord, AuditSink},
+    custom::{self, AccountHandle, CustomHandler, CustomTransaction},
+    error::{Error, ErrorContext, Result},
+    events::{Event, EventSink},
+    fees::FeeSchedule,
+    hash::HashMap,
+    history::HistoryEntry,
+    interest::{InterestRates, SECS_PER_DAY},
+    ledger::{Ledger, LedgerAccount, Posting, Postings},
+    limits::WithdrawalLimits,
+    middleware::{Middleware, Next},
+    observer::{self, EngineObserver},
+    pending::{PendingBuffer, PendingDisputes},
+    risk::{RiskAction, RiskRules},
+    snapshot,
+    store::TxStore,
+    thresholds::BalanceThresholds,
+    transaction::{DisputeStatus, Transaction, TransactionType, TxRecord},
 };
 
+/// What to do with a deposit/withdrawal whose tx id has already been applied.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Refuse the duplicate with [`Error::DuplicateTransaction`], leaving state unchanged.
+    #[default]
+    Reject,
+    /// Ignore the duplicate and report success, so re-processed input is idempotent.
+    Skip,
+}
+
+/// What to do with a dispute, resolve, chargeback or clear whose referenced transaction belongs
+/// to another client.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AccountMismatchPolicy {
+    /// Refuse it with [`Error::TransactionError`], leaving state unchanged.
+    #[default]
+    Reject,
+    /// Ignore it and report success, leaving state unchanged.
+    Ignore,
+}
+
+/// Whether a chargeback locks the client's account.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LockPolicy {
+    /// Lock the account, so it accepts nothing until unlocked.
+    #[default]
+    OnChargeback,
+    /// Apply the chargeback to the balances only, leaving the account's status alone.
+    Never,
+}
+
+/// Which stored transactions are evicted from the [`TxStore`] once disputes and refunds can no
+/// longer reference them, so the store grows with the transactions still open rather than with
+/// all of them. An evicted transaction still counts as applied, so its tx id is still refused as
+/// a duplicate, but anything referencing it fails with [`Error::TransactionError`]. Evicted ids
+/// are kept as runs of consecutive ids, which stay few while ids settle roughly in the order they
+/// were issued.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Keep every transaction.
+    #[default]
+    Keep,
+    /// Evict a transaction once it has been charged back or reversed, or resolved with none of
+    /// its amount left to dispute or refund, and deposits and withdrawals past the dispute window
+    /// on [`PaymentsEngine::evict_expired`]. Reversals of a resolved transaction fail once it is
+    /// evicted.
+    Settled,
+}
+
+/// Whether a dispute may place its hold when the client no longer has the disputed funds
+/// available (e.g. a deposit that was already withdrawn).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NegativeAvailablePolicy {
+    /// Place the hold anyway, driving `available` negative (`total` stays `available + held`).
+    #[default]
+    Allow,
+    /// Refuse the dispute with [`Error::InsufficientFunds`], leaving state unchanged.
+    Reject,
+}
+
+/// Configures a [`PaymentsEngine`]'s behavior policies. Anything not set keeps its default.
+///
+/// ```
+/// use payments_engine::{AccountMismatchPolicy, DuplicatePolicy, PaymentsEngine};
+///
+/// let engine = PaymentsEngine::builder()
+///     .duplicate_policy(DuplicatePolicy::Skip)
+///     .account_mismatch_policy(AccountMismatchPolicy::Ignore)
+///     .build();
+/// ```
+#[derive(Default)]
+pub struct PaymentsEngineBuilder {
+    duplicate_policy: DuplicatePolicy,
+    account_mismatch_policy: AccountMismatchPolicy,
+    lock_policy: LockPolicy,
+    negative_available_policy: NegativeAvailablePolicy,
+    amount_limits: AmountLimits,
+    withdrawal_limits: Option<WithdrawalLimits>,
+    dispute_window: Option<Duration>,
+    hold_expiry: Option<Duration>,
+    clearing_period: Option<Duration>,
+    fee_schedule: Option<FeeSchedule>,
+    interest_rates: Option<InterestRates>,
+    risk_rules: Option<RiskRules>,
+    balance_thresholds: Option<BalanceThresholds>,
+    pending_disputes: Option<PendingDisputes>,
+    tx_store: TxStore,
+    eviction_policy: EvictionPolicy,
+    archive: Option<Box<dyn ArchiveSink + Send>>,
+    event_sink: Option<Box<dyn EventSink + Send>>,
+    audit_sink: Option<Box<dyn AuditSink + Send>>,
+    observers: Vec<Box<dyn EngineObserver + Send>>,
+    middleware: Vec<Box<dyn Middleware + Send>>,
+    custom_types: Vec<(&'static str, Box<dyn CustomHandler + Send>)>,
+    track_history: bool,
+    expected_accounts: usize,
+}
+
+impl PaymentsEngineBuilder {
+    /// Sets how transactions reusing an already applied tx id are handled.
+    pub fn duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
+
+    /// Sets how transactions referencing another client's transaction are handled.
+    pub fn account_mismatch_policy(mut self, policy: AccountMismatchPolicy) -> Self {
+        self.account_mismatch_policy = policy;
+        self
+    }
+
+    /// Sets whether chargebacks lock the account.
+    pub fn lock_policy(mut self, policy: LockPolicy) -> Self {
+        self.lock_policy = policy;
+        self
+    }
+
+    /// Sets whether disputes may drive `available` negative.
+    pub fn negative_available_policy(mut self, policy: NegativeAvailablePolicy) -> Self {
+        self.negative_available_policy = policy;
+        self
+    }
+
+    /// Sets the bounds on deposit and withdrawal amounts (none by default).
+    pub fn amount_limits(mut self, limits: AmountLimits) -> Self {
+        self.amount_limits = limits;
+        self
+    }
+
+    /// Caps withdrawals and authorizations by `limits`, refusing those that would go over with
+    /// [`Error::LimitExceeded`] (no caps by default).
+    pub fn withdrawal_limits(mut self, limits: WithdrawalLimits) -> Self {
+        self.withdrawal_limits = Some(limits);
+        self
+    }
+
+    /// Refuses disputes that come more than `window` after the disputed transaction with
+    /// [`Error::DisputeWindowExpired`] (no limit by default), going by their
+    /// [`timestamp`](Transaction::timestamp)s. Disputes without one are refused with
+    /// [`Error::TransactionError`], while transactions without one can always be disputed.
+    pub fn dispute_window(mut self, window: Duration) -> Self {
+        self.dispute_window = Some(window);
+        self
+    }
+
+    /// Lets authorization holds expire `lifetime` after the authorization, to be released by
+    /// [`PaymentsEngine::expire_holds`] (holds never expire by default). Only applies to
+    /// authorizations with a [`timestamp`](Transaction::timestamp).
+    pub fn hold_expiry(mut self, lifetime: Duration) -> Self {
+        self.hold_expiry = Some(lifetime);
+        self
+    }
+
+    /// Lets provisional deposits clear on their own `period` after they were made, to be cleared
+    /// by [`PaymentsEngine::clear_due`] (they wait for a `clear` by default). Only applies to
+    /// provisional deposits with a [`timestamp`](Transaction::timestamp).
+    pub fn clearing_period(mut self, period: Duration) -> Self {
+        self.clearing_period = Some(period);
+        self
+    }
+
+    /// Charges fees on deposits and withdrawals by `schedule` (no fees by default).
+    pub fn fee_schedule(mut self, schedule: FeeSchedule) -> Self {
+        self.fee_schedule = Some(schedule);
+        self
+    }
+
+    /// Pays daily interest on `available` balances at `rates` (none by default). See
+    /// [`PaymentsEngine::accrue_interest`].
+    pub fn interest_rates(mut self, rates: InterestRates) -> Self {
+        self.interest_rates = Some(rates);
+        self
+    }
+
+    /// Assesses deposits and withdrawals by `rules` before applying them, flagging, holding or
+    /// refusing them with [`Error::RiskRejected`] (no rules by default). Flagged and held
+    /// transactions are reported as [`Event::RiskFlagged`].
+    pub fn risk_rules(mut self, rules: RiskRules) -> Self {
+        self.risk_rules = Some(rules);
+        self
+    }
+
+    /// Reports balance changes crossing `thresholds` as [`Event::BalanceThresholdCrossed`] (none
+    /// by default).
+    pub fn balance_thresholds(mut self, thresholds: BalanceThresholds) -> Self {
+        self.balance_thresholds = Some(thresholds);
+        self
+    }
+
+    /// Buffers disputes of transactions not seen yet instead of refusing them (off by default).
+    pub fn pending_disputes(mut self, config: PendingDisputes) -> Self {
+        self.pending_disputes = Some(config);
+        self
+    }
+
+    /// Sets where stored transactions are kept (in memory by default).
+    pub fn tx_store(mut self, store: TxStore) -> Self {
+        self.tx_store = store;
+        self
+    }
+
+    /// Sets which stored transactions are evicted once settled (none by default).
+    pub fn eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.eviction_policy = policy;
+        self
+    }
+
+    /// Writes every evicted transaction to `writer` as a JSON line, its record flattened next to
+    /// its `tx` id, before it is dropped from the store (evicted transactions are discarded by
+    /// default).
+    pub fn archive(self, writer: Box<dyn Write + Send>) -> Self {
+        self.archive_sink(Box::new(JsonlArchive::new(writer)))
+    }
+
+    /// Archives every evicted transaction to `sink` instead, e.g. to columnar files for cold
+    /// storage.
+    pub fn archive_sink(mut self, sink: Box<dyn ArchiveSink + Send>) -> Self {
+        self.archive = Some(sink);
+        self
+    }
+
+    /// Emits an [`Event`] to `sink` for every state change (none by default).
+    pub fn event_sink(mut self, sink: Box<dyn EventSink + Send>) -> Self {
+        self.event_sink = Some(sink);
+        self
+    }
+
+    /// Writes an [`AuditRecord`] to `sink` for every balance mutation, with the balance before
+    /// and after it (none by default).
+    pub fn audit_sink(mut self, sink: Box<dyn AuditSink + Send>) -> Self {
+        self.audit_sink = Some(sink);
+        self
+    }
+
+    /// Registers `observer` to be called back as transactions are processed. Observers are called
+    /// in the order they were registered.
+    pub fn observer(mut self, observer: Box<dyn EngineObserver + Send>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Wraps [`process_tx`](PaymentsEngine::process_tx) in `middleware`. The first registered
+    /// is the outermost layer.
+    pub fn middleware(mut self, middleware: Box<dyn Middleware + Send>) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Registers `handler` to apply transactions of the custom type `name`, given to
+    /// [`process_custom`](PaymentsEngine::process_custom). A later registration of the same name
+    /// replaces an earlier one.
+    pub fn custom_type(
+        mut self,
+        name: &'static str,
+        handler: Box<dyn CustomHandler + Send>,
+    ) -> Self {
+        self.custom_types
+            .retain(|(registered, _)| *registered != name);
+        self.custom_types.push((name, handler));
+        self
+    }
+
+    /// Keeps every client's balance changes for [`PaymentsEngine::history`] (off by default).
+    /// The history grows with every change applied and is kept in memory only, so it isn't
+    /// saved in snapshots.
+    pub fn track_history(mut self, track: bool) -> Self {
+        self.track_history = track;
+        self
+    }
+
+    /// Reserves room for `count` accounts up front, sparing the account map from regrowing as
+    /// clients are first seen.
+    pub fn expected_accounts(mut self, count: usize) -> Self {
+        self.expected_accounts = count;
+        self
+    }
+
+    /// Creates an engine with no accounts.
+    pub fn build(self) -> PaymentsEngine {
+        PaymentsEngine {
+            accounts: HashMap::with_capacity_and_hasher(self.expected_accounts, Default::default()),
+            transactions: self.tx_store,
+            eviction_policy: self.eviction_policy,
+            archive: self.archive,
+            duplicate_policy: self.duplicate_policy,
+            account_mismatch_policy: self.account_mismatch_policy,
+            lock_policy: self.lock_policy,
+            negative_available_policy: self.negative_available_policy,
+            amount_limits: self.amount_limits,
+            withdrawal_limits: self.withdrawal_limits,
+            dispute_window: self.dispute_window,
+            hold_expiry: self.hold_expiry,
+            clearing_period: self.clearing_period,
+            fee_schedule: self.fee_schedule,
+            interest_rates: self.interest_rates,
+            interest_accrued_to: None,
+            risk_rules: self.risk_rules,
+            balance_thresholds: self.balance_thresholds,
+            ledger: Ledger::default(),
+            pending: PendingBuffer::new(self.pending_disputes),
+            event_sink: self.event_sink,
+            pending_events: Vec::new(),
+            audit_sink: self.audit_sink,
+            pending_audit: Vec::new(),
+            pending_postings: Vec::new(),
+            observers: self.observers,
+            middleware: self.middleware,
+            custom_types: self.custom_types,
+            history: self.track_history.then(HashMap::default),
+            effective: None,
+        }
+    }
+
+    /// Creates an engine from a snapshot written by [`PaymentsEngine::snapshot`], copying its
+    /// stored transactions into the configured store. Fails like [`PaymentsEngine::restore`].
+    pub fn restore<R: Read>(self, reader: R) -> Result<PaymentsEngine> {
+        let restored = snapshot::read(reader)?;
+        let mut engine = self.build();
+        for (tx_id, tx_info) in restored.transactions {
+            engine.transactions.insert(tx_id, tx_info)?;
+        }
+        for (first, last) in restored.evicted {
+            engine.transactions.mark_evicted(first, last);
+        }
+        engine.accounts.extend(restored.accounts);
+        engine.interest_accrued_to = restored.interest_accrued_to;
+        engine.ledger = restored.ledger;
+        // kept only if this engine is configured to look back on them
+        if let Some(limits) = &mut engine.withdrawal_limits {
+            limits.restore_recent(restored.recent_withdrawals);
+        }
+        if let Some(rules) = &mut engine.risk_rules {
+            rules.restore_activity(restored.risk_activity);
+        }
+
+        Ok(engine)
+    }
+}
+
+// interest owed on a backdated tx for the days from its effective date to the interest clock,
+// negative for a debit
+struct BackdatedInterest {
+    amount: Amount,
+    rate: Decimal,
+    from: u64,
+    to: u64,
+}
+
+/// Routes transactions to client accounts and keeps the account/transaction state.
+#[derive(Default)]
 pub struct PaymentsEngine {
-    pub accounts: HashMap<u16, Account>,
-    pub transactions: HashMap<u32, TxRecord>,
+    accounts: HashMap<u16, Account>,
+    transactions: TxStore,
+    eviction_policy: EvictionPolicy,
+    // where evicted transactions are written, if anywhere
+    archive: Option<Box<dyn ArchiveSink + Send>>,
+    duplicate_policy: DuplicatePolicy,
+    account_mismatch_policy: AccountMismatchPolicy,
+    lock_policy: LockPolicy,
+    negative_available_policy: NegativeAvailablePolicy,
+    amount_limits: AmountLimits,
+    withdrawal_limits: Option<WithdrawalLimits>,
+    dispute_window: Option<Duration>,
+    hold_expiry: Option<Duration>,
+    clearing_period: Option<Duration>,
+    fee_schedule: Option<FeeSchedule>,
+    interest_rates: Option<InterestRates>,
+    // the time interest has been paid up to, set by the first accrual
+    interest_accrued_to: Option<u64>,
+    risk_rules: Option<RiskRules>,
+    balance_thresholds: Option<BalanceThresholds>,
+    // the engine's side of the double-entry ledger the balances are posted to
+    ledger: Ledger,
+    pending: PendingBuffer,
+    event_sink: Option<Box<dyn EventSink + Send>>,
+    // events of the operation in progress, emitted only once it has fully succeeded
+    pending_events: Vec<Event>,
+    audit_sink: Option<Box<dyn AuditSink + Send>>,
+    // audit records of the operation in progress, written along with its events
+    pending_audit: Vec<AuditRecord>,
+    // balance changes of the operation in progress, taken back if a later step of it fails
+    pending_postings: Vec<(u16, String, Postings)>,
+    observers: Vec<Box<dyn EngineObserver + Send>>,
+    middleware: Vec<Box<dyn Middleware + Send>>,
+    custom_types: Vec<(&'static str, Box<dyn CustomHandler + Send>)>,
+    // every client's balance changes in the order they were applied, when tracked
+    history: Option<HashMap<u16, Vec<HistoryEntry>>>,
+    // the effective date of the transaction in progress, kept with its history
+    effective: Option<u64>,
 }
 
 impl PaymentsEngine {
+    /// Creates an engine with no accounts and the default policies.
     pub fn new() -> Self {
-        Self {
-            accounts: HashMap::new(),
-            transactions: HashMap::new(),
+        Self::default()
+    }
+
+    /// Starts configuring an engine's policies.
+    pub fn builder() -> PaymentsEngineBuilder {
+        PaymentsEngineBuilder::default()
+    }
+
+    /// Sets how transactions reusing an already applied tx id are handled.
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
+
+    /// Sets where stored transactions are kept (in memory by default), copying over any the
+    /// engine already holds (e.g. after [`restore`](Self::restore)).
+    pub fn with_tx_store(mut self, mut store: TxStore) -> Result<Self> {
+        for record in self.transactions.records() {
+            let (tx_id, tx_info) = record?;
+            store.insert(tx_id, tx_info)?;
         }
+        for (first, last) in self.transactions.evicted() {
+            store.mark_evicted(first, last);
+        }
+        self.transactions = store;
+
+        Ok(self)
     }
 
+    /// Applies a single transaction, creating the client's account on first sight.
+    ///
+    /// Transactions must be supplied in input order. On error the transaction is not applied and
+    /// engine state is left unchanged--except for [`Error::StoreError`], after which the engine
+    /// state can no longer be trusted, and [`Error::EventError`] and [`Error::AuditError`],
+    /// where the transaction was applied but its events or audit records were not all delivered.
+    /// Errors carry the transaction as their [`context`](Error::context).
+    ///
+    /// Each call runs in a `tx` [`tracing`] span at debug level carrying the tx id, client and
+    /// type.
+    ///
+    /// With [`pending_disputes`](PaymentsEngineBuilder::pending_disputes), a dispute of an
+    /// unseen tx is parked and succeeds; it is applied once the tx is, or else ends up among the
+    /// [dead letters](Self::take_dead_letters).
+    ///
+    /// With [`interest_rates`](PaymentsEngineBuilder::interest_rates), interest is first
+    /// [accrued](Self::accrue_interest) up to a timestamped transaction's `timestamp`.
+    ///
+    /// With [`middleware`](PaymentsEngineBuilder::middleware), the transaction goes through it
+    /// first, and is only applied as it reaches the engine.
     pub fn process_tx(&mut self, tx: &Transaction) -> Result<()> {
-        match tx.tx_type {
-            TransactionType::Deposit => self.process_deposit(&tx),
-            TransactionType::Withdrawal => self.process_withdrawal(&tx),
-            TransactionType::Dispute => self.process_dispute(&tx),
-            TransactionType::Resolve => self.process_resolve(&tx),
-            TransactionType::Chargeback => self.process_chargeback(&tx),
+        if self.middleware.is_empty() {
+            return self.apply_tx(tx);
         }
+        let mut middleware = std::mem::take(&mut self.middleware);
+        let result = Next::new(&mut middleware, self).run(tx);
+        self.middleware = middleware;
+        result
     }
 
-    fn process_deposit(&mut self, tx: &Transaction) -> Result<()> {
-        let account = self
-            .accounts
-            .entry(tx.account_id)
-            .or_insert(Account::new(tx.account_id));
-        let tx_info = TxRecord::try_from(tx)?;
-
-        account.deposit(tx_info.amount)?;
-        self.transactions.insert(tx.tx_id, tx_info);
+    // `process_tx` once past any middleware
+    pub(crate) fn apply_tx(&mut self, tx: &Transaction) -> Result<()> {
+        if let Some(now) = tx.timestamp {
+            self.accrue_interest(now)
+                .map_err(|e| e.with_context(ErrorContext::for_tx(tx)))?;
+        }
+        self.pending.tick();
+        self.process(tx)
+    }
 
-        Ok(())
+    /// Applies a transaction of a type registered with
+    /// [`custom_type`](PaymentsEngineBuilder::custom_type), through its handler.
+    /// [`RowParser::parse_row`](crate::RowParser::parse_row) reads them from CSV rows.
+    ///
+    /// Fails with [`Error::TransactionError`] for a type that isn't registered, and refuses a tx
+    /// id already in use, by any transaction or an earlier custom one, as
+    /// [`duplicate_policy`](PaymentsEngineBuilder::duplicate_policy) says. On error, including
+    /// the handler's own, the transaction is not applied. Custom transactions aren't stored, only
+    /// their tx id is kept, as an evicted transaction's is, so they can't be disputed or
+    /// reversed. They don't go through [`middleware`](PaymentsEngineBuilder::middleware). As with
+    /// `process_tx`, interest is first accrued up to the transaction's `timestamp`.
+    pub fn process_custom(&mut self, tx: &CustomTransaction) -> Result<()> {
+        let context = ErrorContext {
+            tx_id: Some(tx.tx_id),
+            account_id: Some(tx.account_id),
+            ..ErrorContext::default()
+        };
+        if let Some(now) = tx.timestamp {
+            self.accrue_interest(now)
+                .map_err(|e| e.with_context(context.clone()))?;
+        }
+        let result = match self.apply_custom(tx) {
+            Ok(()) => self.publish_events(),
+            Err(e) => {
+                self.roll_back();
+                Err(e)
+            }
+        };
+        result.map_err(|e| e.with_context(context))
     }
 
-    fn process_withdrawal(&mut self, tx: &Transaction) -> Result<()> {
+    fn apply_custom(&mut self, tx: &CustomTransaction) -> Result<()> {
+        let Some((operation, handler)) = self
+            .custom_types
+            .iter_mut()
+            .find(|(name, _)| *name == tx.tx_type)
+        else {
+            return Err(Error::TransactionError("Unknown transaction type."));
+        };
+        let operation = *operation;
+        if self.transactions.contains(tx.tx_id)? {
+            return match self.duplicate_policy {
+                DuplicatePolicy::Reject => Err(Error::DuplicateTransaction(tx.tx_id)),
+                DuplicatePolicy::Skip => Ok(()),
+            };
+        }
+        // the handler works on a copy, so that a refusal leaves the account as it was
         let account = self
             .accounts
-            .entry(tx.account_id)
-            .or_insert(Account::new(tx.account_id));
-        let tx_info = TxRecord::try_from(tx)?;
+            .get(&tx.account_id)
+            .cloned()
+            .unwrap_or_else(|| Account::new(tx.account_id));
+        let mut handle = AccountHandle::new(account);
+        handler.apply(tx, &mut handle)?;
+
+        let opened = !self.accounts.contains_key(&tx.account_id);
+        let result = self.book_custom(tx, operation, handle.into_changes());
+        match result {
+            // the tx id stays in use, as an evicted tx's would, so it can't be applied again
+            Ok(()) => self.transactions.mark_evicted(tx.tx_id, tx.tx_id),
+            // a roll back only takes back the postings, so drop the account the tx opened
+            Err(_) if opened => {
+                self.accounts.remove(&tx.account_id);
+            }
+            Err(_) => {}
+        }
+
+        result
+    }
 
-        account.withdrawal(tx_info.amount)?;
-        self.transactions.insert(tx.tx_id, tx_info.into());
+    fn book_custom(
+        &mut self,
+        tx: &CustomTransaction,
+        operation: &'static str,
+        changes: Vec<(String, Amount)>,
+    ) -> Result<()> {
+        for (currency, amount) in changes {
+            let account = self
+                .accounts
+                .entry(tx.account_id)
+                .or_insert(Account::new(tx.account_id));
+            let postings = match amount < Amount::ZERO {
+                true => account.withdrawal(&currency, custom::negate(amount)?)?,
+                false => account.deposit(&currency, amount)?,
+            };
+            self.post(
+                tx.account_id,
+                Some(tx.tx_id),
+                tx.timestamp,
+                operation,
+                &currency,
+                amount,
+                &postings,
+            )?;
+            self.record(Event::CustomApplied {
+                client: tx.account_id,
+                tx: tx.tx_id,
+                tx_type: operation.to_owned(),
+                currency,
+                amount,
+                timestamp: tx.timestamp,
+            });
+        }
 
         Ok(())
     }
 
-    fn process_dispute(&mut self, tx: &Transaction) -> Result<()> {
-        let account = self
-            .accounts
-            .entry(tx.account_id)
-            .or_insert(Account::new(tx.account_id));
-        match self.transactions.get(&tx.tx_id) {
-            Some(tx_info) => {
-                // ensure tx belongs to the same account
-                account.validate_tx_account_id(tx_info.account_id)?;
-                account.dispute(tx_info.amount)?;
+    /// Takes the disputes given up on since the last call--expired, evicted, flushed, or failed
+    /// once their tx arrived--as errors carrying the dispute as their [`context`](Error::context).
+    pub fn take_dead_letters(&mut self) -> Vec<Error> {
+        self.pending.take_dead_letters()
+    }
 
-                Ok(())
+    /// Returns whether any dispute is held back waiting for its tx. Held disputes aren't part of
+    /// a [`snapshot`](Self::snapshot).
+    pub fn has_pending_disputes(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Gives up on every dispute still pending, e.g. at the end of the input, turning them into
+    /// dead letters.
+    pub fn flush_pending_disputes(&mut self) {
+        self.pending.flush();
+    }
+
+    fn process(&mut self, tx: &Transaction) -> Result<()> {
+        let _span = tracing::debug_span!(
+            "tx",
+            tx_id = tx.tx_id,
+            client = tx.account_id,
+            tx_type = %tx.tx_type
+        )
+        .entered();
+        let result = match self.apply(tx) {
+            Ok(()) => self.publish_events(),
+            Err(e) => {
+                self.roll_back();
+                Err(e)
+            }
+        };
+        let result = match result {
+            Err(Error::UnknownTransaction(tx_id)) if self.transactions.is_evicted(tx_id) => Err(
+                Error::TransactionError("Transaction has been evicted as settled."),
+            ),
+            Err(Error::UnknownTransaction(_))
+                if tx.tx_type == TransactionType::Dispute && self.pending.park(tx) =>
+            {
+                tracing::debug!("parked until its tx arrives");
+                return Ok(());
+            }
+            result => result,
+        };
+        match &result {
+            Ok(()) => {
+                tracing::debug!("applied");
+                for observer in &mut self.observers {
+                    observer.on_tx_applied(tx);
+                }
+            }
+            Err(e) => {
+                tracing::debug!(error = %e, "rejected");
+                for observer in &mut self.observers {
+                    observer.on_tx_rejected(tx, e);
+                }
+            }
+        }
+        if result.is_ok() && Self::creates_record(tx.tx_type) {
+            for dispute in self.pending.take(tx.tx_id) {
+                if let Err(e) = self.process(&dispute) {
+                    self.pending.dead_letter(e);
+                }
             }
-            // tx not found--ignore
-            None => Ok(()),
         }
+        result.map_err(|e| e.with_context(ErrorContext::for_tx(tx)))
     }
 
-    fn process_resolve(&mut self, tx: &Transaction) -> Result<()> {
-        let account = self
-            .accounts
-            .entry(tx.account_id)
-            .or_insert(Account::new(tx.account_id));
-        match self.transactions.get(&tx.tx_id) {
-            Some(tx_info) => {
-                // ensure tx belongs to the same account
-                account.validate_tx_account_id(tx_info.account_id)?;
-                account.resolve(tx_info.amount)?;
+    /// Releases every open authorization whose hold has expired by `now` (seconds since the Unix
+    /// epoch) back to `available`, as a void would, whatever the account's status. Returns the
+    /// tx ids of the released authorizations in ascending order. Does nothing without a
+    /// [`hold_expiry`](PaymentsEngineBuilder::hold_expiry). On error no hold is released.
+    pub fn expire_holds(&mut self, now: u64) -> Result<Vec<u32>> {
+        let Some(lifetime) = self.hold_expiry else {
+            return Ok(Vec::new());
+        };
+        let mut expired = Vec::new();
+        for record in self.transactions.records() {
+            let (tx_id, tx_info) = record?;
+            if tx_info.tx_type == TransactionType::Authorize
+                && let Some(authorized_at) = tx_info.timestamp
+                && now.saturating_sub(authorized_at) >= lifetime.as_secs()
+            {
+                expired.push((tx_id, tx_info));
+            }
+        }
+        expired.sort_unstable_by_key(|(tx_id, _)| *tx_id);
 
-                Ok(())
+        let mut released = Vec::with_capacity(expired.len());
+        for (tx_id, tx_info) in &expired {
+            if let Err(e) = self.expire_hold(*tx_id, tx_info.clone(), now) {
+                self.roll_back_batch(expired.into_iter().take(released.len()))?;
+                return Err(e);
             }
-            // tx not found--ignore
-            None => Ok(()),
+            released.push(*tx_id);
         }
+        self.publish_events()?;
+
+        Ok(released)
     }
 
-    fn process_chargeback(&mut self, tx: &Transaction) -> Result<()> {
-        let account = self
+    fn expire_hold(&mut self, tx_id: u32, mut tx_info: TxRecord, now: u64) -> Result<()> {
+        let amount = tx_info.disputable;
+        let postings = self
             .accounts
-            .entry(tx.account_id)
-            .or_insert(Account::new(tx.account_id));
-        match self.transactions.get(&tx.tx_id) {
-            Some(tx_info) => {
-                // ensure tx belongs to the same account
-                account.validate_tx_account_id(tx_info.account_id)?;
-                account.chargeback(tx_info.amount)?;
+            .get_mut(&tx_info.account_id)
+            .ok_or(Error::AccountError("Account does not exist."))?
+            .release_hold(&tx_info.currency, amount)?;
+        self.post(
+            tx_info.account_id,
+            Some(tx_id),
+            Some(now),
+            "hold_expiry",
+            &tx_info.currency,
+            amount,
+            &postings,
+        )?;
+        self.record(Event::HoldExpired {
+            client: tx_info.account_id,
+            tx: tx_id,
+            currency: tx_info.currency.clone(),
+            amount,
+        });
+        tx_info.tx_type = TransactionType::Void;
+        tx_info.disputable = Amount::ZERO;
+        self.transactions.insert(tx_id, tx_info)
+    }
 
-                Ok(())
+    /// Clears every provisional deposit whose clearing period has ended by `now` (seconds since
+    /// the Unix epoch), moving its funds from `held` to `available` as a `clear` would. Deposits
+    /// of accounts that refuse a `clear`, such as locked ones, are left held. Returns the tx ids
+    /// of the cleared deposits in ascending order. Does nothing without a
+    /// [`clearing_period`](PaymentsEngineBuilder::clearing_period). On error no deposit is
+    /// cleared.
+    pub fn clear_due(&mut self, now: u64) -> Result<Vec<u32>> {
+        let Some(period) = self.clearing_period else {
+            return Ok(Vec::new());
+        };
+        let mut due = Vec::new();
+        for record in self.transactions.records() {
+            let (tx_id, tx_info) = record?;
+            if tx_info.tx_type == TransactionType::Provisional
+                && let Some(deposited_at) = tx_info.timestamp
+                && now.saturating_sub(deposited_at) >= period.as_secs()
+            {
+                due.push((tx_id, tx_info));
             }
-            // tx not found--ignore
-            None => Ok(()),
         }
+        due.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+
+        let mut cleared = Vec::with_capacity(due.len());
+        for (tx_id, tx_info) in &due {
+            match self.apply_clear(*tx_id, tx_info.clone(), Some(now), "clearing_period") {
+                Ok(()) => cleared.push(*tx_id),
+                Err(Error::AccountLocked(_) | Error::AccountClosed(_)) => {}
+                Err(e) => {
+                    self.roll_back_batch(
+                        due.into_iter()
+                            .filter(|(tx_id, _)| cleared.binary_search(tx_id).is_ok()),
+                    )?;
+                    return Err(e);
+                }
+            }
+        }
+        self.publish_events()?;
+
+        Ok(cleared)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::transaction::{Transaction, TransactionType};
-    use rust_decimal::{Decimal, dec};
+    /// Evicts every deposit and withdrawal that can no longer be disputed by `now` (seconds since
+    /// the Unix epoch), being past the [`dispute_window`](PaymentsEngineBuilder::dispute_window)
+    /// and not under dispute, archiving them like settled ones. Refunds and reversals of them
+    /// fail from then on, and the archive is flushed. Returns the number evicted. Does
+    /// nothing without both a dispute window and the [`EvictionPolicy::Settled`] policy, or for
+    /// transactions without a [`timestamp`](Transaction::timestamp).
+    pub fn evict_expired(&mut self, now: u64) -> Result<usize> {
+        let Some(window) = self
+            .dispute_window
+            .filter(|_| self.eviction_policy == EvictionPolicy::Settled)
+        else {
+            return Ok(0);
+        };
+        let mut expired = Vec::new();
+        for record in self.transactions.records() {
+            let (tx_id, tx_info) = record?;
+            if matches!(
+                tx_info.tx_type,
+                TransactionType::Deposit | TransactionType::Withdrawal
+            ) && tx_info.dispute_status != DisputeStatus::Disputed
+                && let Some(at) = tx_info.timestamp
+                && now.saturating_sub(at) > window.as_secs()
+            {
+                expired.push((tx_id, tx_info));
+            }
+        }
+        expired.sort_unstable_by_key(|(tx_id, _)| *tx_id);
 
-    fn new_tx(
-        tx_type: TransactionType,
-        account_id: u16,
-        tx_id: u32,
-        amount: Option<Decimal>,
-    ) -> Transaction {
-        Transaction {
-            tx_type,
-            account_id,
-            tx_id,
+        for (tx_id, tx_info) in &expired {
+            self.evict(*tx_id, tx_info)?;
+        }
+        if let Some(archive) = &mut self.archive {
+            archive.flush()?;
+        }
+
+        Ok(expired.len())
+    }
+
+    /// Credits interest on every positive `available` balance for the whole days since the last
+    /// accrual up to `now` (seconds since the Unix epoch), compounding daily at the
+    /// [`interest_rates`](PaymentsEngineBuilder::interest_rates). Each credit is a synthetic
+    /// deposit without a tx id, so it can't be disputed. The first call only starts the clock,
+    /// and a `now` before the last accrual does nothing. Returns the number of balances credited.
+    /// On error nothing is credited.
+    pub fn accrue_interest(&mut self, now: u64) -> Result<usize> {
+        let Some(rates) = &self.interest_rates else {
+            return Ok(0);
+        };
+        let Some(from) = self.interest_accrued_to else {
+            self.interest_accrued_to = Some(now);
+            return Ok(0);
+        };
+        let days = now.saturating_sub(from) / SECS_PER_DAY;
+        if days == 0 {
+            return Ok(0);
+        }
+        let to = from + days * SECS_PER_DAY;
+
+        // worked out in full before anything is credited, so a failure changes nothing
+        let mut credits = Vec::new();
+        for account in self.accounts.values() {
+            for (currency, balance) in &account.balances {
+                if balance.available <= Amount::ZERO {
+                    continue;
+                }
+                let interest = rates.interest(currency, balance.available, days)?;
+                if let Some(rate) = rates.rate(currency)
+                    && interest > Amount::ZERO
+                {
+                    credits.push((account.id, currency.clone(), interest, rate));
+                }
+            }
+        }
+        credits.sort_unstable_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+
+        let credited = credits.len();
+        for (client, currency, amount, rate) in credits {
+            // a credit failing takes back those made before it
+            if let Err(e) = self.credit_interest(client, &currency, amount, to) {
+                self.roll_back();
+                return Err(e);
+            }
+            self.record(Event::InterestAccrued {
+                client,
+                currency,
+                amount,
+                rate,
+                from,
+                to,
+            });
+        }
+        self.interest_accrued_to = Some(to);
+        self.publish_events()?;
+
+        Ok(credited)
+    }
+
+    fn credit_interest(
+        &mut self,
+        client: u16,
+        currency: &str,
+        amount: Amount,
+        to: u64,
+    ) -> Result<()> {
+        let postings = self
+            .accounts
+            .get_mut(&client)
+            .ok_or(Error::AccountError("Account does not exist."))?
+            .credit_interest(currency, amount)?;
+        self.post(
+            client,
+            None,
+            Some(to),
+            "interest",
+            currency,
             amount,
+            &postings,
+        )
+    }
+
+    /// `client`'s balance changes in the order they were applied, each with the balance it
+    /// resulted in. Empty unless the engine was built with
+    /// [`track_history`](PaymentsEngineBuilder::track_history).
+    pub fn history(&self, client: u16) -> &[HistoryEntry] {
+        self.history
+            .as_ref()
+            .and_then(|history| history.get(&client))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// The stored transaction `tx_id`, as later disputes, refunds and the like will see it.
+    pub fn transaction(&self, tx_id: u32) -> Result<Option<TxRecord>> {
+        self.transactions.get(tx_id)
+    }
+
+    /// Every stored transaction with its tx id, in no particular order.
+    pub fn transactions(&self) -> impl Iterator<Item = Result<(u32, TxRecord)>> + '_ {
+        self.transactions.records()
+    }
+
+    /// The engine's side of the double-entry ledger every balance change is posted to.
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// Checks that the ledger balances: in every currency, the clients' `available` and `held`
+    /// plus the engine's suspense and chargeback loss sum to zero. Fails with
+    /// [`Error::EngineError`] if a balance changed without a matching posting.
+    pub fn check_ledger(&self) -> Result<()> {
+        self.ledger.check(self.accounts.values())
+    }
+
+    #[cfg(feature = "tokio")]
+    // whether `tx_id` counts as applied, evicted or not
+    pub(crate) fn has_transaction(&self, tx_id: u32) -> Result<bool> {
+        self.transactions.contains(tx_id)
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    #[cfg(feature = "tokio")]
+    // fees are credited to the schedule's fee account, whichever client paid them
+    pub(crate) fn charges_fees(&self) -> bool {
+        self.fee_schedule.is_some()
+    }
+
+    /// Flushes the event and audit sinks and the archive, if any.
+    pub fn flush_events(&mut self) -> Result<()> {
+        if let Some(archive) = &mut self.archive {
+            archive.flush()?;
+        }
+        if let Some(sink) = &mut self.event_sink {
+            sink.flush()?;
+        }
+        match &mut self.audit_sink {
+            Some(sink) => sink.flush(),
+            None => Ok(()),
         }
     }
 
-    fn new_engine_with_deposit(account_id: u16, tx_id: u32, amount: Decimal) -> PaymentsEngine {
-        let mut engine = PaymentsEngine::new();
-        let deposit = new_tx(TransactionType::Deposit, account_id, tx_id, Some(amount));
-        engine.process_tx(&deposit).unwrap();
+    fn apply(&mut self, tx: &Transaction) -> Result<()> {
+        // a stored tx id means this deposit/withdrawal was already applied--never apply it twice
+        if Self::creates_record(tx.tx_type) && self.transactions.contains(tx.tx_id)? {
+            return match self.duplicate_policy {
+                DuplicatePolicy::Reject => Err(Error::DuplicateTransaction(tx.tx_id)),
+                DuplicatePolicy::Skip => Ok(()),
+            };
+        }
 
-        engine
+        if let (Some(effective), Some(timestamp)) = (tx.effective, tx.timestamp)
+            && effective > timestamp
+        {
+            return Err(Error::TransactionError(
+                "Effective date is after the transaction's timestamp.",
+            ));
+        }
+
+        if let Some(limits) = &self.withdrawal_limits {
+            limits.check(tx)?;
+        }
+        // the limits and risk rules look back only on what was actually applied
+        let held = self.assess_risk(tx)?;
+        let applied = held.as_ref().unwrap_or(tx);
+        let backdated = self.backdated_interest(applied)?;
+        self.effective = tx.effective;
+        let result = self.dispatch(applied);
+        self.effective = None;
+        let result = match (result, backdated) {
+            (Ok(()), Some(interest)) => self.pay_backdated_interest(applied, interest),
+            (result, _) => result,
+        };
+        if result.is_ok() {
+            if let Some(limits) = &mut self.withdrawal_limits {
+                limits.record(tx);
+            }
+            if let Some(rules) = &mut self.risk_rules {
+                rules.record(tx);
+            }
+        }
+        result
     }
 
-    #[test]
-    fn test_deposit_success() {
-        let mut engine = PaymentsEngine::new();
-        let deposit_tx = new_tx(TransactionType::Deposit, 1, 1, Some(dec!(100)));
+    // the interest a backdated deposit, withdrawal or adjustment would have earned, or cost,
+    // had it been applied on its effective date, over the days interest has already been paid
+    // for since. Worked out before the tx is applied, so a failure changes nothing
+    fn backdated_interest(&self, tx: &Transaction) -> Result<Option<BackdatedInterest>> {
+        let (Some(from), Some(rates), Some(to), Some(amount)) = (
+            tx.effective,
+            &self.interest_rates,
+            self.interest_accrued_to,
+            tx.amount,
+        ) else {
+            return Ok(None);
+        };
+        let change = match tx.tx_type {
+            TransactionType::Deposit | TransactionType::Adjustment => amount,
+            TransactionType::Withdrawal => -amount,
+            _ => return Ok(None),
+        };
+        let days = to.saturating_sub(from) / SECS_PER_DAY;
+        let currency = tx.currency_code();
+        let Some(rate) = rates.rate(currency).filter(|_| days > 0) else {
+            return Ok(None);
+        };
+        let interest = match change < Amount::ZERO {
+            true => -rates.interest(currency, -change, days)?,
+            false => rates.interest(currency, change, days)?,
+        };
 
-        engine.process_tx(&deposit_tx).unwrap();
+        Ok((interest != Amount::ZERO).then_some(BackdatedInterest {
+            amount: interest,
+            rate,
+            from,
+            to,
+        }))
+    }
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(100));
-        assert_eq!(account.held, dec!(0));
+    // credit (or take back) the interest a backdated tx missed out on, as accrued over the days
+    // from its effective date
+    fn pay_backdated_interest(
+        &mut self,
+        tx: &Transaction,
+        interest: BackdatedInterest,
+    ) -> Result<()> {
+        let currency = tx.currency_code();
+        let postings = self
+            .accounts
+            .get_mut(&tx.account_id)
+            .ok_or(Error::AccountError("Account does not exist."))?
+            .credit_interest(currency, interest.amount)?;
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "interest",
+            currency,
+            interest.amount,
+            &postings,
+        )?;
+        self.record(Event::InterestAccrued {
+            client: tx.account_id,
+            currency: currency.to_owned(),
+            amount: interest.amount,
+            rate: interest.rate,
+            from: interest.from,
+            to: interest.to,
+        });
+
+        Ok(())
     }
 
-    #[test]
-    fn test_withdrawal_success() {
-        let mut engine = new_engine_with_deposit(1, 1, dec!(100));
-        let withdrawal_tx = new_tx(TransactionType::Withdrawal, 1, 2, Some(dec!(60)));
+    // check `tx` against the risk rules, refusing it or flagging it as the strictest rule it trips
+    // says. A held deposit or withdrawal comes back as the provisional deposit or authorization
+    // to apply in its place
+    fn assess_risk(&mut self, tx: &Transaction) -> Result<Option<Transaction>> {
+        let Some(rule) = self.risk_rules.as_ref().and_then(|rules| rules.assess(tx)) else {
+            return Ok(None);
+        };
+        let (rule, action) = (rule.name(), rule.action());
+        if action == RiskAction::Reject {
+            return Err(Error::RiskRejected(format!("tripped the {} rule", rule)));
+        }
+        self.record(Event::RiskFlagged {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            rule: rule.to_string(),
+            action,
+        });
 
-        engine.process_tx(&withdrawal_tx).unwrap();
+        Ok((action == RiskAction::Hold).then(|| Transaction {
+            tx_type: match tx.tx_type {
+                TransactionType::Deposit => TransactionType::Provisional,
+                _ => TransactionType::Authorize,
+            },
+            ..tx.clone()
+        }))
+    }
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(40));
+    fn dispatch(&mut self, tx: &Transaction) -> Result<()> {
+        match tx.tx_type {
+            TransactionType::Deposit => self.process_deposit(tx),
+            TransactionType::Provisional => self.process_provisional(tx),
+            TransactionType::Clear => self.process_clear(tx),
+            TransactionType::Withdrawal => self.process_withdrawal(tx),
+            TransactionType::Refund => self.process_refund(tx),
+            TransactionType::Adjustment => self.process_adjustment(tx),
+            TransactionType::Reversal => self.process_reversal(tx),
+            TransactionType::Authorize => self.process_authorize(tx),
+            TransactionType::Capture => self.process_capture(tx),
+            TransactionType::Void => self.process_void(tx),
+            TransactionType::Dispute => self.process_dispute(tx),
+            TransactionType::Resolve => self.process_resolve(tx),
+            TransactionType::Chargeback => self.process_chargeback(tx),
+            TransactionType::Freeze => self.process_admin(tx, |account| account.freeze("admin")),
+            TransactionType::Unlock => self.process_admin(tx, Account::unlock),
+            TransactionType::Close => self.process_admin(tx, Account::close),
+        }
     }
 
-    #[test]
-    fn test_dispute_success() {
-        let mut engine = new_engine_with_deposit(1, 1, dec!(100));
-        let dispute_tx = new_tx(TransactionType::Dispute, 1, 1, None);
+    /// Returns the current state of a client's account, if it has been seen.
+    pub fn account(&self, id: u16) -> Option<&Account> {
+        self.accounts.get(&id)
+    }
 
-        engine.process_tx(&dispute_tx).unwrap();
+    /// Iterates over all client accounts, in no particular order.
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(0));
-        assert_eq!(account.held, dec!(100));
+    /// Writes the full engine state (accounts and stored transactions, including dispute status,
+    /// and how far interest has been accrued) to `writer` as versioned JSON, so a later run can
+    /// [`restore`](Self::restore) it.
+    ///
+    /// Policies are configuration rather than state and are not included, but the recent
+    /// withdrawals and activity that withdrawal limits and risk rules look back on are.
+    pub fn snapshot<W: Write>(&self, writer: W) -> Result<()> {
+        snapshot::write(
+            writer,
+            &self.accounts,
+            &self.transactions,
+            self.interest_accrued_to,
+            &self.ledger,
+            self.withdrawal_limits
+                .as_ref()
+                .map(WithdrawalLimits::recent)
+                .unwrap_or_default(),
+            self.risk_rules.as_ref().map(RiskRules::activity),
+        )
     }
 
-    #[test]
-    fn test_resolve_success() {
-        let mut engine = new_engine_with_deposit(1, 1, dec!(100));
-        let dispute_tx = new_tx(TransactionType::Dispute, 1, 1, None);
-        let resolve_tx = new_tx(TransactionType::Resolve, 1, 1, None);
+    /// Creates an engine from a snapshot written by [`snapshot`](Self::snapshot).
+    ///
+    /// Snapshots of another version, or whose contents are inconsistent, are refused with
+    /// [`Error::SnapshotError`].
+    pub fn restore<R: Read>(reader: R) -> Result<Self> {
+        Self::builder().restore(reader)
+    }
 
-        engine.process_tx(&dispute_tx).unwrap();
-        engine.process_tx(&resolve_tx).unwrap();
+    /// Merges the state of `other` into this engine, e.g. of runs over inputs partitioned by
+    /// client: its accounts, stored transactions and engine ledger balances are added to this
+    /// engine's, along with the recent activity its withdrawal limits and risk rules look back on
+    /// where this engine has them too. Policies are left as this engine's.
+    ///
+    /// Fails with [`Error::StateConflict`], merging nothing, if both engines hold the same client
+    /// or the same tx id, or have accrued interest up to different times.
+    pub fn merge_state(&mut self, other: PaymentsEngine) -> Result<()> {
+        self.check_merge(&other)?;
+        let evicted = other.transactions.evicted();
+        let ledger = self.ledger.combine(&other.ledger)?;
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(100));
-        assert_eq!(account.held, dec!(0));
+        for record in other.transactions.records() {
+            let (tx_id, tx_info) = record?;
+            self.transactions.insert(tx_id, tx_info)?;
+        }
+        for (first, last) in evicted {
+            self.transactions.mark_evicted(first, last);
+        }
+        self.accounts.extend(other.accounts);
+        self.interest_accrued_to = self.interest_accrued_to.or(other.interest_accrued_to);
+        self.ledger = ledger;
+        // the clients are distinct, so their recent activity is too
+        if let (Some(limits), Some(theirs)) =
+            (&mut self.withdrawal_limits, &other.withdrawal_limits)
+        {
+            limits.restore_recent(theirs.recent());
+        }
+        if let (Some(rules), Some(theirs)) = (&mut self.risk_rules, other.risk_rules) {
+            rules.restore_activity(theirs.activity().clone());
+        }
+
+        Ok(())
     }
 
-    #[test]
-    fn test_chargeback_success() {
-        let mut engine = new_engine_with_deposit(1, 1, dec!(100));
-        let dispute_tx = new_tx(TransactionType::Dispute, 1, 1, None);
-        let chargeback_tx = &new_tx(TransactionType::Chargeback, 1, 1, None);
+    // whether `other` could be merged into this engine by `merge_state`, which fails just as this
+    // does but gives up `other` either way
+    pub(crate) fn check_merge(&self, other: &PaymentsEngine) -> Result<()> {
+        if let Some(client) = other
+            .accounts
+            .keys()
+            .filter(|client| self.accounts.contains_key(client))
+            .min()
+        {
+            return Err(Error::StateConflict(format!(
+                "client {} is in both states",
+                client
+            )));
+        }
+        for record in other.transactions.records() {
+            let (tx_id, _) = record?;
+            if self.transactions.contains(tx_id)? {
+                return Err(Error::StateConflict(format!(
+                    "tx {} is in both states",
+                    tx_id
+                )));
+            }
+        }
+        for (first, last) in other.transactions.evicted() {
+            if let Some(tx_id) = self.transactions.first_in(first, last)? {
+                return Err(Error::StateConflict(format!(
+                    "tx {} is in both states",
+                    tx_id
+                )));
+            }
+        }
+        if let (Some(ours), Some(theirs)) = (self.interest_accrued_to, other.interest_accrued_to)
+            && ours != theirs
+        {
+            return Err(Error::StateConflict(format!(
+                "interest was accrued to {} in one state and {} in the other",
+                ours, theirs
+            )));
+        }
+        self.ledger.combine(&other.ledger)?;
 
-        engine.process_tx(&dispute_tx).unwrap();
-        engine.process_tx(&chargeback_tx).unwrap();
+        Ok(())
+    }
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(0));
-        assert_eq!(account.held, dec!(0));
-        assert!(account.locked);
+    /// Rebuilds an engine from an event log written by a [`JsonlSink`](crate::JsonlSink), applying
+    /// every event's state change in order. Accounts, balances, statuses and stored transactions
+    /// (with their dispute state) come out as they were when the events were emitted.
+    ///
+    /// Fails on the first malformed line ([`Error::EventError`]) or event that doesn't fit the
+    /// state rebuilt so far (e.g. a dispute of an unknown tx), with the line as its
+    /// [`context`](Error::context).
+    pub fn replay<R: BufRead>(reader: R) -> Result<Self> {
+        let mut engine = Self::default();
+        engine.replay_onto(reader)?;
+
+        Ok(engine)
+    }
+
+    /// Applies the events of a log on top of the current state, as [`replay`](Self::replay)
+    /// does from scratch, e.g. the events written after a [`snapshot`](Self::snapshot) of the
+    /// state replayed from earlier ones.
+    pub fn replay_onto<R: BufRead>(&mut self, reader: R) -> Result<()> {
+        for (index, line) in reader.lines().enumerate() {
+            let line_no = Some(index as u64 + 1);
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            serde_json::from_str(&line)
+                .map_err(|e| Error::EventError(e.to_string()))
+                .and_then(|event| self.apply_event(event))
+                .map_err(|e| e.with_context(ErrorContext::for_line(line_no)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Seeds a client's opening balance in `currency`, e.g. from the previous run's closing
+    /// balances, creating the account if needed. The account takes the most restrictive status
+    /// seeded for any of its currencies.
+    ///
+    /// Refused if the balance is inconsistent (total is not available + held) or the client
+    /// already has a balance in that currency. Seeded held funds have no stored transaction, so
+    /// they can't be resolved or charged back.
+    pub fn seed_balance(
+        &mut self,
+        account_id: u16,
+        currency: &str,
+        balance: Balance,
+        status: AccountStatus,
+    ) -> Result<()> {
+        match balance.available.checked_add(balance.held) {
+            Some(total) if total == balance.total => {}
+            Some(_) => {
+                return Err(Error::AccountError(
+                    "Seeded total does not equal available + held.",
+                ));
+            }
+            None => {
+                return Err(Error::AccountError(
+                    "Overflow Error: seeded available + held is out of range.",
+                ));
+            }
+        }
+        let account = self
+            .accounts
+            .entry(account_id)
+            .or_insert(Account::new(account_id));
+        if account.balances.contains_key(currency) {
+            return Err(Error::AccountError(
+                "Account already has a balance in this currency.",
+            ));
+        }
+        account.balances.insert(currency.to_string(), balance);
+        let previous_status = account.status;
+        account.status = account.status.max(status);
+        // the opening balance comes from outside the engine, like a deposit
+        let posted = self.post(
+            account_id,
+            None,
+            None,
+            "seed",
+            currency,
+            balance.total,
+            &Postings::two(
+                Posting::new(
+                    LedgerAccount::Suspense,
+                    LedgerAccount::Available,
+                    balance.available,
+                ),
+                Posting::new(LedgerAccount::Suspense, LedgerAccount::Held, balance.held),
+            ),
+        );
+        if let Err(e) = posted {
+            // leave no trace of the balance, so seeding it isn't refused as a duplicate later
+            if let Some(account) = self.accounts.get_mut(&account_id) {
+                account.balances.remove(currency);
+                account.status = previous_status;
+            }
+            return Err(e);
+        }
+        self.record(Event::BalanceSeeded {
+            client: account_id,
+            currency: currency.to_string(),
+            available: balance.available,
+            held: balance.held,
+            total: balance.total,
+            status,
+        });
+
+        self.publish_events()
+    }
+
+    /// Merges client `source_id` into `target_id` (administrative consolidation of duplicate
+    /// clients).
+    ///
+    /// Balances are combined into the target account, a lock on either account carries over, and
+    /// stored transactions are reassigned so later disputes resolve against the target.
+    pub fn merge_accounts(&mut self, source_id: u16, target_id: u16) -> Result<()> {
+        if source_id == target_id {
+            return Err(Error::AccountError("Cannot merge an account into itself."));
+        }
+        let source = self
+            .accounts
+            .remove(&source_id)
+            .ok_or(Error::AccountError("Merge source account does not exist."))?;
+        let target = self
+            .accounts
+            .entry(target_id)
+            .or_insert(Account::new(target_id));
+
+        let target_before = self.audit_sink.is_some().then(|| target.balances.clone());
+
+        if let Err(e) = target.merge(&source) {
+            self.accounts.insert(source_id, source);
+            return Err(e);
+        }
+
+        if let Some(target_before) = target_before {
+            for (currency, balance) in &source.balances {
+                self.pending_audit.push(AuditRecord {
+                    client: source_id,
+                    tx: None,
+                    operation: "merge",
+                    currency: currency.clone(),
+                    amount: balance.total,
+                    before: *balance,
+                    after: Balance::default(),
+                    postings: Postings::none(),
+                });
+                self.pending_audit.push(AuditRecord {
+                    client: target_id,
+                    tx: None,
+                    operation: "merge",
+                    currency: currency.clone(),
+                    amount: balance.total,
+                    before: target_before.get(currency).copied().unwrap_or_default(),
+                    after: target.balance(currency),
+                    postings: Postings::none(),
+                });
+            }
+        }
+        // the source's own history ends with it; the target's records what it took over
+        if let Some(history) = &mut self.history {
+            history.remove(&source_id);
+            let entries = history.entry(target_id).or_default();
+            for (currency, balance) in &source.balances {
+                entries.push(HistoryEntry {
+                    operation: "merge",
+                    tx: None,
+                    timestamp: None,
+                    effective: None,
+                    currency: currency.clone(),
+                    amount: balance.total,
+                    balance: target.balance(currency),
+                });
+            }
+        }
+        self.transactions.reassign_account(source_id, target_id)?;
+        self.record(Event::AccountsMerged {
+            source: source_id,
+            target: target_id,
+        });
+
+        self.publish_events()
+    }
+
+    fn process_deposit(&mut self, tx: &Transaction) -> Result<()> {
+        let account = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id));
+        let tx_info = TxRecord::try_from(tx)?;
+        let amount = tx_info.amount;
+        self.amount_limits.validate(amount)?;
+        let fee = Self::fee(
+            self.fee_schedule.as_ref(),
+            tx,
+            account,
+            &tx_info.currency,
+            amount,
+        )?;
+
+        let postings = account.deposit(&tx_info.currency, amount)?;
+
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "deposit",
+            &tx_info.currency,
+            amount,
+            &postings,
+        )?;
+        self.record(Event::Deposited {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            currency: tx_info.currency.clone(),
+            amount,
+            timestamp: tx.timestamp,
+        });
+        self.charge_fee(tx, &tx_info.currency, fee)?;
+        self.transactions.insert(tx.tx_id, tx_info)?;
+
+        Ok(())
+    }
+
+    fn process_provisional(&mut self, tx: &Transaction) -> Result<()> {
+        let account = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id));
+        let tx_info = TxRecord::try_from(tx)?;
+        let amount = tx_info.amount;
+        self.amount_limits.validate(amount)?;
+
+        let postings = account.provisional_deposit(&tx_info.currency, amount)?;
+
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "provisional",
+            &tx_info.currency,
+            amount,
+            &postings,
+        )?;
+        self.record(Event::ProvisionalDeposited {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            currency: tx_info.currency.clone(),
+            amount,
+            timestamp: tx.timestamp,
+        });
+        self.transactions.insert(tx.tx_id, tx_info)?;
+
+        Ok(())
+    }
+
+    fn process_clear(&mut self, tx: &Transaction) -> Result<()> {
+        let account = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id));
+        // tx not found--surfaced so callers can decide whether to ignore it
+        let Some(tx_info) = self.transactions.get(tx.tx_id)? else {
+            return Err(Error::UnknownTransaction(tx.tx_id));
+        };
+        // ensure tx belongs to the same account
+        if !Self::check_account(self.account_mismatch_policy, account, &tx_info)? {
+            return Ok(());
+        }
+        Self::check_currency(tx, &tx_info)?;
+        if !matches!(tx_info.tx_type, TransactionType::Provisional) {
+            return Err(Error::TransactionError(
+                "Only uncleared provisional deposits can be cleared.",
+            ));
+        }
+
+        self.apply_clear(tx.tx_id, tx_info, tx.timestamp, "clear")
+    }
+
+    // move the funds of the uncleared provisional deposit `tx_id` from held to available, by a
+    // `clear` or at the end of its clearing period
+    fn apply_clear(
+        &mut self,
+        tx_id: u32,
+        mut tx_info: TxRecord,
+        timestamp: Option<u64>,
+        operation: &'static str,
+    ) -> Result<()> {
+        let client = tx_info.account_id;
+        // uncleared deposits can't be disputed, so all of the amount is still disputable
+        let amount = tx_info.disputable;
+        let postings = self
+            .accounts
+            .get_mut(&client)
+            .ok_or(Error::AccountError("Account does not exist."))?
+            .clear(&tx_info.currency, amount)?;
+        self.post(
+            client,
+            Some(tx_id),
+            timestamp,
+            operation,
+            &tx_info.currency,
+            amount,
+            &postings,
+        )?;
+        self.record(Event::DepositCleared {
+            client,
+            tx: tx_id,
+            currency: tx_info.currency.clone(),
+            amount,
+        });
+        // once cleared the funds behave like an ordinary deposit
+        tx_info.tx_type = TransactionType::Deposit;
+        self.transactions.insert(tx_id, tx_info)?;
+
+        Ok(())
+    }
+
+    fn process_withdrawal(&mut self, tx: &Transaction) -> Result<()> {
+        let account = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id));
+        let tx_info = TxRecord::try_from(tx)?;
+        let amount = tx_info.amount;
+        self.amount_limits.validate(amount)?;
+        let fee = Self::fee(
+            self.fee_schedule.as_ref(),
+            tx,
+            account,
+            &tx_info.currency,
+            amount,
+        )?;
+
+        let postings = account.withdrawal(&tx_info.currency, amount)?;
+
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "withdrawal",
+            &tx_info.currency,
+            amount,
+            &postings,
+        )?;
+        self.record(Event::WithdrawalApplied {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            currency: tx_info.currency.clone(),
+            amount,
+            timestamp: tx.timestamp,
+        });
+        self.charge_fee(tx, &tx_info.currency, fee)?;
+        self.transactions.insert(tx.tx_id, tx_info)?;
+
+        Ok(())
+    }
+
+    // an authorization holds funds under its own tx id until a capture or void settles it
+    fn process_authorize(&mut self, tx: &Transaction) -> Result<()> {
+        let account = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id));
+        let tx_info = TxRecord::try_from(tx)?;
+        let amount = tx_info.amount;
+        self.amount_limits.validate(amount)?;
+
+        let postings = account.authorize(&tx_info.currency, amount)?;
+
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "authorize",
+            &tx_info.currency,
+            amount,
+            &postings,
+        )?;
+        self.record(Event::Authorized {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            currency: tx_info.currency.clone(),
+            amount,
+            timestamp: tx.timestamp,
+        });
+        self.transactions.insert(tx.tx_id, tx_info)?;
+
+        Ok(())
+    }
+
+    // capturing debits the row's amount (all of the hold without one) and releases the rest, after
+    // which the authorization behaves like an ordinary withdrawal of the captured amount
+    fn process_capture(&mut self, tx: &Transaction) -> Result<()> {
+        let account = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id));
+        // tx not found--surfaced so callers can decide whether to ignore it
+        let Some(mut tx_info) = self.transactions.get(tx.tx_id)? else {
+            return Err(Error::UnknownTransaction(tx.tx_id));
+        };
+        // ensure tx belongs to the same account
+        if !Self::check_account(self.account_mismatch_policy, account, &tx_info)? {
+            return Ok(());
+        }
+        Self::check_currency(tx, &tx_info)?;
+        Self::check_open_authorization(&tx_info)?;
+        // an open authorization can't be disputed, so its disputable amount is the whole hold
+        let hold = tx_info.disputable;
+        let captured = match tx.amount {
+            None => hold,
+            Some(amount) if amount <= Amount::ZERO => {
+                return Err(Error::TransactionError(
+                    "Capture amount must be greater than zero.",
+                ));
+            }
+            Some(amount) if amount > hold => {
+                return Err(Error::TransactionError(
+                    "Capture amount exceeds the authorized amount.",
+                ));
+            }
+            Some(amount) => amount,
+        };
+        let released = hold - captured;
+
+        let postings = account.capture(&tx_info.currency, captured, released)?;
+
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "capture",
+            &tx_info.currency,
+            captured,
+            &postings,
+        )?;
+        self.record(Event::Captured {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            currency: tx_info.currency.clone(),
+            amount: captured,
+            released,
+        });
+        tx_info.tx_type = TransactionType::Withdrawal;
+        tx_info.disputable = captured;
+        self.transactions.insert(tx.tx_id, tx_info)?;
+
+        Ok(())
+    }
+
+    fn process_void(&mut self, tx: &Transaction) -> Result<()> {
+        let account = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id));
+        // tx not found--surfaced so callers can decide whether to ignore it
+        let Some(mut tx_info) = self.transactions.get(tx.tx_id)? else {
+            return Err(Error::UnknownTransaction(tx.tx_id));
+        };
+        // ensure tx belongs to the same account
+        if !Self::check_account(self.account_mismatch_policy, account, &tx_info)? {
+            return Ok(());
+        }
+        Self::check_currency(tx, &tx_info)?;
+        Self::check_open_authorization(&tx_info)?;
+        let amount = tx_info.disputable;
+
+        let postings = account.void(&tx_info.currency, amount)?;
+
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "void",
+            &tx_info.currency,
+            amount,
+            &postings,
+        )?;
+        self.record(Event::Voided {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            currency: tx_info.currency.clone(),
+            amount,
+        });
+        // a voided authorization is kept so its tx id isn't reused, but nothing can act on it
+        tx_info.tx_type = TransactionType::Void;
+        tx_info.disputable = Amount::ZERO;
+        self.transactions.insert(tx.tx_id, tx_info)?;
+
+        Ok(())
+    }
+
+    // a refund pays back part or all of a deposit outside the dispute flow, so nothing is held
+    // and the account isn't locked
+    fn process_refund(&mut self, tx: &Transaction) -> Result<()> {
+        let account = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id));
+        // tx not found--surfaced so callers can decide whether to ignore it
+        let Some(mut tx_info) = self.transactions.get(tx.tx_id)? else {
+            return Err(Error::UnknownTransaction(tx.tx_id));
+        };
+        // ensure tx belongs to the same account
+        if !Self::check_account(self.account_mismatch_policy, account, &tx_info)? {
+            return Ok(());
+        }
+        Self::check_currency(tx, &tx_info)?;
+        Self::check_cleared(&tx_info)?;
+        let amount = Self::refund_amount(tx, &tx_info)?;
+
+        let postings = account.refund(&tx_info.currency, amount)?;
+
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "refund",
+            &tx_info.currency,
+            amount,
+            &postings,
+        )?;
+        self.record(Event::Refunded {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            currency: tx_info.currency.clone(),
+            amount,
+        });
+        Self::apply_refund(&mut tx_info, amount);
+        self.store_settled(tx.tx_id, tx_info)?;
+
+        Ok(())
+    }
+
+    // an adjustment corrects a balance by its signed amount. It is stored under its own tx id so
+    // it can be reversed, but it can't be disputed or refunded
+    fn process_adjustment(&mut self, tx: &Transaction) -> Result<()> {
+        let Some(reason) = tx.reason.clone().filter(|reason| !reason.is_empty()) else {
+            return Err(Error::TransactionError("Adjustment requires a reason."));
+        };
+        let account = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id));
+        let tx_info = TxRecord::try_from(tx)?;
+        let amount = tx_info.amount;
+        Self::check_correction_funds(
+            self.negative_available_policy,
+            account,
+            &tx_info.currency,
+            amount,
+        )?;
+
+        let postings = account.adjust(&tx_info.currency, amount)?;
+
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "adjustment",
+            &tx_info.currency,
+            amount,
+            &postings,
+        )?;
+        self.record(Event::Adjusted {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            currency: tx_info.currency.clone(),
+            amount,
+            reason,
+            timestamp: tx.timestamp,
+        });
+        self.transactions.insert(tx.tx_id, tx_info)?;
+
+        Ok(())
+    }
+
+    // a reversal undoes a deposit, withdrawal or adjustment as a whole, leaving it final like a
+    // chargeback but without locking the account
+    fn process_reversal(&mut self, tx: &Transaction) -> Result<()> {
+        let account = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id));
+        // tx not found--surfaced so callers can decide whether to ignore it
+        let Some(mut tx_info) = self.transactions.get(tx.tx_id)? else {
+            return Err(Error::UnknownTransaction(tx.tx_id));
+        };
+        // ensure tx belongs to the same account
+        if !Self::check_account(self.account_mismatch_policy, account, &tx_info)? {
+            return Ok(());
+        }
+        Self::check_currency(tx, &tx_info)?;
+        Self::check_cleared(&tx_info)?;
+        let change = Self::reversal_change(&tx_info)?;
+        Self::check_correction_funds(
+            self.negative_available_policy,
+            account,
+            &tx_info.currency,
+            change,
+        )?;
+
+        let postings = account.adjust(&tx_info.currency, change)?;
+
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "reversal",
+            &tx_info.currency,
+            change,
+            &postings,
+        )?;
+        self.record(Event::Reversed {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            tx_type: tx_info.tx_type,
+            currency: tx_info.currency.clone(),
+            amount: change,
+            reason: tx.reason.clone(),
+        });
+        tx_info.dispute_status = DisputeStatus::Reversed;
+        tx_info.disputable = Amount::ZERO;
+        self.store_settled(tx.tx_id, tx_info)?;
+
+        Ok(())
+    }
+
+    fn process_dispute(&mut self, tx: &Transaction) -> Result<()> {
+        let account = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id));
+        // tx not found--surfaced so callers can decide whether to ignore it
+        let Some(mut tx_info) = self.transactions.get(tx.tx_id)? else {
+            return Err(Error::UnknownTransaction(tx.tx_id));
+        };
+        // ensure tx belongs to the same account
+        if !Self::check_account(self.account_mismatch_policy, account, &tx_info)? {
+            return Ok(());
+        }
+        Self::check_currency(tx, &tx_info)?;
+        Self::check_cleared(&tx_info)?;
+        Self::check_dispute_window(self.dispute_window, tx, &tx_info)?;
+        let amount = Self::dispute_amount(tx, &tx_info)?;
+        let postings = match tx_info.tx_type {
+            TransactionType::Withdrawal => account.dispute_withdrawal(&tx_info.currency, amount)?,
+            _ => {
+                if self.negative_available_policy == NegativeAvailablePolicy::Reject
+                    && account.balance(&tx_info.currency).available < amount
+                {
+                    return Err(Error::InsufficientFunds(
+                        "Insufficient funds to complete dispute transaction.",
+                    ));
+                }
+                account.dispute(&tx_info.currency, amount)?
+            }
+        };
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "dispute",
+            &tx_info.currency,
+            amount,
+            &postings,
+        )?;
+        self.record(Event::DisputeOpened {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            tx_type: tx_info.tx_type,
+            currency: tx_info.currency.clone(),
+            amount,
+        });
+        tx_info.dispute_status = DisputeStatus::Disputed;
+        tx_info.disputable -= amount;
+        tx_info.disputed = amount;
+        self.transactions.insert(tx.tx_id, tx_info)?;
+
+        Ok(())
+    }
+
+    fn process_resolve(&mut self, tx: &Transaction) -> Result<()> {
+        let account = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id));
+        // tx not found--surfaced so callers can decide whether to ignore it
+        let Some(mut tx_info) = self.transactions.get(tx.tx_id)? else {
+            return Err(Error::UnknownTransaction(tx.tx_id));
+        };
+        // ensure tx belongs to the same account
+        if !Self::check_account(self.account_mismatch_policy, account, &tx_info)? {
+            return Ok(());
+        }
+        Self::check_currency(tx, &tx_info)?;
+        Self::check_cleared(&tx_info)?;
+        Self::check_under_dispute(&tx_info)?;
+        let postings = match tx_info.tx_type {
+            TransactionType::Withdrawal => {
+                account.resolve_withdrawal(&tx_info.currency, tx_info.disputed)?
+            }
+            _ => account.resolve(&tx_info.currency, tx_info.disputed)?,
+        };
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "resolve",
+            &tx_info.currency,
+            tx_info.disputed,
+            &postings,
+        )?;
+        self.record(Event::DisputeResolved {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            tx_type: tx_info.tx_type,
+            currency: tx_info.currency.clone(),
+            amount: tx_info.disputed,
+        });
+        tx_info.dispute_status = DisputeStatus::Resolved;
+        tx_info.disputed = Amount::ZERO;
+        self.store_settled(tx.tx_id, tx_info)?;
+
+        Ok(())
+    }
+
+    fn process_chargeback(&mut self, tx: &Transaction) -> Result<()> {
+        let account = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id));
+        // tx not found--surfaced so callers can decide whether to ignore it
+        let Some(mut tx_info) = self.transactions.get(tx.tx_id)? else {
+            return Err(Error::UnknownTransaction(tx.tx_id));
+        };
+        // ensure tx belongs to the same account
+        if !Self::check_account(self.account_mismatch_policy, account, &tx_info)? {
+            return Ok(());
+        }
+        Self::check_currency(tx, &tx_info)?;
+        Self::check_cleared(&tx_info)?;
+        Self::check_under_dispute(&tx_info)?;
+        let postings = match tx_info.tx_type {
+            TransactionType::Withdrawal => {
+                account.chargeback_withdrawal_funds(&tx_info.currency, tx_info.disputed)?
+            }
+            _ => account.chargeback_funds(&tx_info.currency, tx_info.disputed)?,
+        };
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "chargeback",
+            &tx_info.currency,
+            tx_info.disputed,
+            &postings,
+        )?;
+        self.record(Event::ChargebackApplied {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            tx_type: tx_info.tx_type,
+            currency: tx_info.currency.clone(),
+            amount: tx_info.disputed,
+        });
+        tx_info.dispute_status = DisputeStatus::ChargedBack;
+        tx_info.disputed = Amount::ZERO;
+        self.store_settled(tx.tx_id, tx_info)?;
+        // locked only once nothing else can fail, as a roll back leaves the status alone
+        if self.lock_policy == LockPolicy::OnChargeback {
+            let reason = format!("chargeback of tx {}", tx.tx_id);
+            if let Some(account) = self.accounts.get_mut(&tx.account_id) {
+                account.lock(&reason);
+            }
+            self.record(Event::AccountLocked {
+                client: tx.account_id,
+                reason,
+            });
+        }
+
+        Ok(())
+    }
+
+    // admin operations act on an existing client as a whole; their tx id references nothing
+    fn process_admin(
+        &mut self,
+        tx: &Transaction,
+        operation: fn(&mut Account) -> Result<()>,
+    ) -> Result<()> {
+        let account = self
+            .accounts
+            .get_mut(&tx.account_id)
+            .ok_or(Error::AccountError("Account does not exist."))?;
+
+        operation(account)?;
+        let event = Event::AccountStatusChanged {
+            client: account.id,
+            status: account.status,
+            reason: account.status_reason.clone(),
+        };
+        self.record(event);
+
+        Ok(())
+    }
+
+    // redo the state change an event describes, without re-checking the policies it was made under
+    fn apply_event(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::Deposited {
+                client,
+                tx,
+                currency,
+                amount,
+                timestamp,
+            } => self.replay_record(
+                client,
+                tx,
+                TransactionType::Deposit,
+                currency,
+                amount,
+                timestamp,
+            ),
+            Event::ProvisionalDeposited {
+                client,
+                tx,
+                currency,
+                amount,
+                timestamp,
+            } => self.replay_record(
+                client,
+                tx,
+                TransactionType::Provisional,
+                currency,
+                amount,
+                timestamp,
+            ),
+            Event::WithdrawalApplied {
+                client,
+                tx,
+                currency,
+                amount,
+                timestamp,
+            } => self.replay_record(
+                client,
+                tx,
+                TransactionType::Withdrawal,
+                currency,
+                amount,
+                timestamp,
+            ),
+            Event::Adjusted {
+                client,
+                tx,
+                currency,
+                amount,
+                timestamp,
+                ..
+            } => self.replay_record(
+                client,
+                tx,
+                TransactionType::Adjustment,
+                currency,
+                amount,
+                timestamp,
+            ),
+            Event::CustomApplied {
+                client,
+                tx,
+                currency,
+                amount,
+                ..
+            } => {
+                let account = self.replay_account(client)?;
+                let postings = match amount < Amount::ZERO {
+                    true => account.withdrawal(&currency, custom::negate(amount)?)?,
+                    false => account.deposit(&currency, amount)?,
+                };
+                self.ledger.post(&currency, &postings)?;
+                self.transactions.mark_evicted(tx, tx);
+                Ok(())
+            }
+            Event::Reversed {
+                client, tx, amount, ..
+            } => {
+                let mut tx_info = self.replay_referenced(tx)?;
+                let postings = self
+                    .replay_account(client)?
+                    .adjust(&tx_info.currency, amount)?;
+                self.ledger.post(&tx_info.currency, &postings)?;
+                tx_info.dispute_status = DisputeStatus::Reversed;
+                tx_info.disputable = Amount::ZERO;
+                self.transactions.insert(tx, tx_info)
+            }
+            Event::Authorized {
+                client,
+                tx,
+                currency,
+                amount,
+                timestamp,
+            } => self.replay_record(
+                client,
+                tx,
+                TransactionType::Authorize,
+                currency,
+                amount,
+                timestamp,
+            ),
+            Event::Captured {
+                client,
+                tx,
+                amount,
+                released,
+                ..
+            } => {
+                let mut tx_info = self.replay_referenced(tx)?;
+                let postings =
+                    self.replay_account(client)?
+                        .capture(&tx_info.currency, amount, released)?;
+                self.ledger.post(&tx_info.currency, &postings)?;
+                tx_info.tx_type = TransactionType::Withdrawal;
+                tx_info.disputable = amount;
+                self.transactions.insert(tx, tx_info)
+            }
+            Event::Voided {
+                client, tx, amount, ..
+            } => {
+                let mut tx_info = self.replay_referenced(tx)?;
+                let postings = self
+                    .replay_account(client)?
+                    .void(&tx_info.currency, amount)?;
+                self.ledger.post(&tx_info.currency, &postings)?;
+                tx_info.tx_type = TransactionType::Void;
+                tx_info.disputable = Amount::ZERO;
+                self.transactions.insert(tx, tx_info)
+            }
+            Event::HoldExpired {
+                client, tx, amount, ..
+            } => {
+                let mut tx_info = self.replay_referenced(tx)?;
+                let postings = self
+                    .replay_account(client)?
+                    .release_hold(&tx_info.currency, amount)?;
+                self.ledger.post(&tx_info.currency, &postings)?;
+                tx_info.tx_type = TransactionType::Void;
+                tx_info.disputable = Amount::ZERO;
+                self.transactions.insert(tx, tx_info)
+            }
+            Event::FeeCharged {
+                client,
+                currency,
+                amount,
+                fee_account,
+                ..
+            } => {
+                let postings = self.replay_account(client)?.pay_fee(&currency, amount)?;
+                self.ledger.post(&currency, &postings)?;
+                let postings = self
+                    .accounts
+                    .entry(fee_account)
+                    .or_insert(Account::new(fee_account))
+                    .receive_fee(&currency, amount)?;
+                self.ledger.post(&currency, &postings)
+            }
+            Event::InterestAccrued {
+                client,
+                currency,
+                amount,
+                to,
+                ..
+            } => {
+                let postings = self
+                    .replay_account(client)?
+                    .credit_interest(&currency, amount)?;
+                self.ledger.post(&currency, &postings)?;
+                self.interest_accrued_to = self.interest_accrued_to.max(Some(to));
+                Ok(())
+            }
+            Event::DepositCleared { client, tx, .. } => {
+                let mut tx_info = self.replay_referenced(tx)?;
+                let postings = self
+                    .replay_account(client)?
+                    .clear(&tx_info.currency, tx_info.disputable)?;
+                self.ledger.post(&tx_info.currency, &postings)?;
+                tx_info.tx_type = TransactionType::Deposit;
+                self.transactions.insert(tx, tx_info)
+            }
+            Event::Refunded {
+                client, tx, amount, ..
+            } => {
+                let mut tx_info = self.replay_referenced(tx)?;
+                let postings = self
+                    .replay_account(client)?
+                    .refund(&tx_info.currency, amount)?;
+                self.ledger.post(&tx_info.currency, &postings)?;
+                Self::apply_refund(&mut tx_info, amount);
+                self.transactions.insert(tx, tx_info)
+            }
+            Event::DisputeOpened {
+                client, tx, amount, ..
+            } => {
+                let mut tx_info = self.replay_referenced(tx)?;
+                let account = self.replay_account(client)?;
+                let postings = match tx_info.tx_type {
+                    TransactionType::Withdrawal => {
+                        account.dispute_withdrawal(&tx_info.currency, amount)?
+                    }
+                    _ => account.dispute(&tx_info.currency, amount)?,
+                };
+                self.ledger.post(&tx_info.currency, &postings)?;
+                tx_info.dispute_status = DisputeStatus::Disputed;
+                tx_info.disputable -= amount;
+                tx_info.disputed = amount;
+                self.transactions.insert(tx, tx_info)
+            }
+            Event::DisputeResolved {
+                client, tx, amount, ..
+            } => {
+                let mut tx_info = self.replay_referenced(tx)?;
+                let account = self.replay_account(client)?;
+                let postings = match tx_info.tx_type {
+                    TransactionType::Withdrawal => {
+                        account.resolve_withdrawal(&tx_info.currency, amount)?
+                    }
+                    _ => account.resolve(&tx_info.currency, amount)?,
+                };
+                self.ledger.post(&tx_info.currency, &postings)?;
+                tx_info.dispute_status = DisputeStatus::Resolved;
+                tx_info.disputed = Amount::ZERO;
+                self.transactions.insert(tx, tx_info)
+            }
+            Event::ChargebackApplied {
+                client, tx, amount, ..
+            } => {
+                let mut tx_info = self.replay_referenced(tx)?;
+                let account = self.replay_account(client)?;
+                let postings = match tx_info.tx_type {
+                    TransactionType::Withdrawal => {
+                        account.chargeback_withdrawal_funds(&tx_info.currency, amount)?
+                    }
+                    _ => account.chargeback_funds(&tx_info.currency, amount)?,
+                };
+                self.ledger.post(&tx_info.currency, &postings)?;
+                tx_info.dispute_status = DisputeStatus::ChargedBack;
+                tx_info.disputed = Amount::ZERO;
+                self.transactions.insert(tx, tx_info)
+            }
+            Event::AccountLocked { client, reason } => {
+                self.replay_account(client)?.lock(&reason);
+                Ok(())
+            }
+            Event::AccountStatusChanged {
+                client,
+                status,
+                reason,
+            } => {
+                // the account may have been created by rows that failed, leaving no event of its own
+                let account = self
+                    .accounts
+                    .entry(client)
+                    .or_insert_with(|| Account::new(client));
+                account.status = status;
+                account.status_reason = reason;
+                Ok(())
+            }
+            Event::BalanceSeeded {
+                client,
+                currency,
+                available,
+                held,
+                total,
+                status,
+            } => {
+                let balance = Balance {
+                    available,
+                    held,
+                    total,
+                };
+                self.seed_balance(client, &currency, balance, status)
+            }
+            Event::AccountsMerged { source, target } => self.merge_accounts(source, target),
+            // the flagged tx's own events carry its state change, as the balance change's do
+            // for a crossed threshold
+            Event::RiskFlagged { .. } | Event::BalanceThresholdCrossed { .. } => Ok(()),
+        }
+    }
+
+    fn replay_record(
+        &mut self,
+        client: u16,
+        tx: u32,
+        tx_type: TransactionType,
+        currency: String,
+        amount: Amount,
+        timestamp: Option<u64>,
+    ) -> Result<()> {
+        let account = self.accounts.entry(client).or_insert(Account::new(client));
+        let postings = match tx_type {
+            TransactionType::Provisional => account.provisional_deposit(&currency, amount)?,
+            TransactionType::Withdrawal => account.withdrawal(&currency, amount)?,
+            TransactionType::Adjustment => account.adjust(&currency, amount)?,
+            TransactionType::Authorize => account.authorize(&currency, amount)?,
+            _ => account.deposit(&currency, amount)?,
+        };
+        self.ledger.post(&currency, &postings)?;
+        let tx_info = TxRecord {
+            tx_type,
+            account_id: client,
+            currency,
+            dispute_status: DisputeStatus::Undisputed,
+            amount,
+            disputable: amount,
+            disputed: Amount::ZERO,
+            refunded: Amount::ZERO,
+            timestamp,
+        };
+
+        self.transactions.insert(tx, tx_info)
+    }
+
+    fn replay_referenced(&self, tx: u32) -> Result<TxRecord> {
+        self.transactions
+            .get(tx)?
+            .ok_or(Error::UnknownTransaction(tx))
+    }
+
+    fn replay_account(&mut self, client: u16) -> Result<&mut Account> {
+        self.accounts
+            .get_mut(&client)
+            .ok_or(Error::AccountError("Account does not exist."))
+    }
+
+    // post a live balance change to the ledger, queueing its audit record when there's a sink,
+    // adding it to the client's history when tracked and reporting the balance thresholds it
+    // crossed. The balance it started from is worked back out of the postings
+    #[allow(clippy::too_many_arguments)]
+    fn post(
+        &mut self,
+        client: u16,
+        tx: Option<u32>,
+        timestamp: Option<u64>,
+        operation: &'static str,
+        currency: &str,
+        amount: Amount,
+        postings: &Postings,
+    ) -> Result<()> {
+        if let Err(e) = self.ledger.post(currency, postings) {
+            // the client's legs are already on its balance, so take them back for the failed
+            // tx to change nothing
+            if let Some(account) = self.accounts.get_mut(&client) {
+                account.unpost(currency, postings);
+            }
+            return Err(e);
+        }
+        self.pending_postings
+            .push((client, currency.to_owned(), *postings));
+        if self.audit_sink.is_none() && self.history.is_none() && self.balance_thresholds.is_none()
+        {
+            return Ok(());
+        }
+        let after = self
+            .accounts
+            .get(&client)
+            .map(|account| account.balance(currency))
+            .unwrap_or_default();
+        if self.audit_sink.is_some() {
+            self.pending_audit.push(AuditRecord {
+                client,
+                tx,
+                operation,
+                currency: currency.to_owned(),
+                amount,
+                before: after.unposted(postings),
+                after,
+                postings: *postings,
+            });
+        }
+        if let Some(history) = &mut self.history {
+            history.entry(client).or_default().push(HistoryEntry {
+                operation,
+                tx,
+                timestamp,
+                effective: self.effective,
+                currency: currency.to_owned(),
+                amount,
+                balance: after,
+            });
+        }
+        let crossed = match &self.balance_thresholds {
+            Some(thresholds) => thresholds.crossed(client, &after.unposted(postings), &after),
+            None => Vec::new(),
+        };
+        for (kind, threshold, balance) in crossed {
+            self.record(Event::BalanceThresholdCrossed {
+                client,
+                tx,
+                currency: currency.to_owned(),
+                kind,
+                threshold,
+                balance,
+            });
+        }
+
+        Ok(())
+    }
+
+    // queue an event for the operation in progress--skipped entirely without a sink or observers
+    fn record(&mut self, event: Event) {
+        if self.event_sink.is_some() || !self.observers.is_empty() {
+            self.pending_events.push(event);
+        }
+    }
+
+    // emit the events and audit records of the operation in progress, which has succeeded. A
+    // sink failing leaves none of them queued for the next operation
+    fn publish_events(&mut self) -> Result<()> {
+        let result = self.deliver_pending();
+        self.discard_pending();
+        result
+    }
+
+    fn deliver_pending(&mut self) -> Result<()> {
+        for event in self.pending_events.drain(..) {
+            observer::notify(&mut self.observers, &event);
+            if let Some(sink) = &mut self.event_sink {
+                sink.emit(&event)?;
+            }
+        }
+        if let Some(sink) = &mut self.audit_sink {
+            for record in self.pending_audit.drain(..) {
+                sink.audit(&record)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // take back every balance change the failed operation in progress made, latest first, along
+    // with its history, and drop everything it queued
+    fn roll_back(&mut self) {
+        while let Some((client, currency, postings)) = self.pending_postings.pop() {
+            if let Some(account) = self.accounts.get_mut(&client) {
+                account.unpost(&currency, &postings);
+            }
+            self.ledger.unpost(&currency, &postings);
+            if let Some(entries) = self
+                .history
+                .as_mut()
+                .and_then(|history| history.get_mut(&client))
+            {
+                entries.pop();
+            }
+        }
+        self.discard_pending();
+    }
+
+    // take back a batch operation that failed part way, putting back the records of the
+    // transactions it had already settled as they were
+    fn roll_back_batch(
+        &mut self,
+        settled: impl IntoIterator<Item = (u32, TxRecord)>,
+    ) -> Result<()> {
+        self.roll_back();
+        for (tx_id, tx_info) in settled {
+            self.transactions.insert(tx_id, tx_info)?;
+        }
+
+        Ok(())
+    }
+
+    fn discard_pending(&mut self) {
+        self.pending_events.clear();
+        self.pending_audit.clear();
+        self.pending_postings.clear();
+    }
+
+    // store a record back after a resolve, chargeback, refund or reversal, evicting it instead
+    // once disputes and refunds can't reference it again, if the policy says to
+    fn store_settled(&mut self, tx_id: u32, tx_info: TxRecord) -> Result<()> {
+        let settled = match tx_info.dispute_status {
+            DisputeStatus::ChargedBack | DisputeStatus::Reversed => true,
+            // a resolved tx can be disputed again or refunded while some of it is left
+            DisputeStatus::Resolved => {
+                tx_info.disputable <= Amount::ZERO && Self::refundable(&tx_info) <= Amount::ZERO
+            }
+            DisputeStatus::Undisputed | DisputeStatus::Disputed => false,
+        };
+        if settled && self.eviction_policy == EvictionPolicy::Settled {
+            self.evict(tx_id, &tx_info)
+        } else {
+            self.transactions.insert(tx_id, tx_info)
+        }
+    }
+
+    // archive the record before dropping it, so a failed write loses nothing
+    fn evict(&mut self, tx_id: u32, tx_info: &TxRecord) -> Result<()> {
+        if let Some(archive) = &mut self.archive {
+            archive.archive(tx_id, tx_info)?;
+        }
+        self.transactions.evict(tx_id)
+    }
+
+    // tx types that store a record under their own tx id (everything else references one)
+    pub(crate) fn creates_record(tx_type: TransactionType) -> bool {
+        matches!(
+            tx_type,
+            TransactionType::Deposit
+                | TransactionType::Provisional
+                | TransactionType::Withdrawal
+                | TransactionType::Adjustment
+                | TransactionType::Authorize
+        )
+    }
+
+    // the amount a dispute holds: the row's amount for a partial dispute, otherwise whatever is
+    // still disputable. A tx can only be disputed while no dispute is open, it hasn't been charged
+    // back, and the disputes so far haven't used up its amount
+    fn dispute_amount(tx: &Transaction, tx_info: &TxRecord) -> Result<Amount> {
+        if !matches!(
+            tx_info.tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) {
+            return Err(Error::TransactionError(
+                "Only deposits and withdrawals can be disputed.",
+            ));
+        }
+        if tx_info.dispute_status == DisputeStatus::Reversed {
+            return Err(Error::TransactionError("Transaction has been reversed."));
+        }
+        if matches!(
+            tx_info.dispute_status,
+            DisputeStatus::Disputed | DisputeStatus::ChargedBack
+        ) || tx_info.disputable <= Amount::ZERO
+        {
+            return Err(Error::TransactionError(
+                "Transaction has already been disputed.",
+            ));
+        }
+
+        match tx.amount {
+            None => Ok(tx_info.disputable),
+            Some(amount) if amount <= Amount::ZERO => Err(Error::TransactionError(
+                "Dispute amount must be greater than zero.",
+            )),
+            Some(amount) if amount > tx_info.disputable => Err(Error::TransactionError(
+                "Dispute amount exceeds the transaction's remaining disputable amount.",
+            )),
+            Some(amount) => Ok(amount),
+        }
+    }
+
+    // how much of a deposit can still be refunded: its original amount less the refunds so far
+    // and whatever the open dispute holds. Disputes that were resolved gave their funds back, so
+    // those can be refunded too
+    fn refundable(tx_info: &TxRecord) -> Amount {
+        match tx_info.tx_type {
+            TransactionType::Deposit => tx_info.amount - tx_info.refunded - tx_info.disputed,
+            _ => Amount::ZERO,
+        }
+    }
+
+    // a refund draws down what's left to dispute as well, so disputes and refunds together never
+    // exceed the original amount
+    fn apply_refund(tx_info: &mut TxRecord, amount: Amount) {
+        tx_info.refunded += amount;
+        tx_info.disputable = tx_info
+            .disputable
+            .min(tx_info.amount - tx_info.refunded - tx_info.disputed);
+    }
+
+    // the amount a refund pays back: the row's amount, otherwise whatever is left to refund
+    fn refund_amount(tx: &Transaction, tx_info: &TxRecord) -> Result<Amount> {
+        if tx_info.tx_type != TransactionType::Deposit {
+            return Err(Error::TransactionError("Only deposits can be refunded."));
+        }
+        if tx_info.dispute_status == DisputeStatus::ChargedBack {
+            return Err(Error::TransactionError(
+                "Transaction has been charged back.",
+            ));
+        }
+        if tx_info.dispute_status == DisputeStatus::Reversed {
+            return Err(Error::TransactionError("Transaction has been reversed."));
+        }
+
+        let refundable = Self::refundable(tx_info);
+        match tx.amount {
+            None if refundable <= Amount::ZERO => Err(Error::TransactionError(
+                "Transaction has already been fully refunded.",
+            )),
+            None => Ok(refundable),
+            Some(amount) if amount <= Amount::ZERO => Err(Error::TransactionError(
+                "Refund amount must be greater than zero.",
+            )),
+            Some(amount) if amount > refundable => Err(Error::TransactionError(
+                "Refund amount exceeds the transaction's remaining refundable amount.",
+            )),
+            Some(amount) => Ok(amount),
+        }
+    }
+
+    // the fee a deposit/withdrawal of `amount` costs, checked up front so that the tx and its fee
+    // are applied together or not at all
+    fn fee(
+        schedule: Option<&FeeSchedule>,
+        tx: &Transaction,
+        account: &Account,
+        currency: &str,
+        amount: Amount,
+    ) -> Result<Amount> {
+        let Some(schedule) = schedule else {
+            return Ok(Amount::ZERO);
+        };
+        let fee = schedule.fee(tx.tx_type, tx.account_id, amount)?;
+        let available = account.balance(currency).available;
+        let left = match tx.tx_type {
+            TransactionType::Withdrawal => available.checked_sub(amount),
+            _ => available.checked_add(amount),
+        };
+        if fee > Amount::ZERO && left.is_none_or(|left| left < fee) {
+            return Err(Error::InsufficientFunds(
+                "Insufficient funds to cover the transaction and its fee.",
+            ));
+        }
+
+        Ok(fee)
+    }
+
+    fn charge_fee(&mut self, tx: &Transaction, currency: &str, fee: Amount) -> Result<()> {
+        let Some(schedule) = &self.fee_schedule else {
+            return Ok(());
+        };
+        if fee <= Amount::ZERO {
+            return Ok(());
+        }
+        let fee_account = schedule.fee_account();
+        let postings = self
+            .accounts
+            .entry(tx.account_id)
+            .or_insert(Account::new(tx.account_id))
+            .pay_fee(currency, fee)?;
+        self.post(
+            tx.account_id,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "fee",
+            currency,
+            fee,
+            &postings,
+        )?;
+        let postings = self
+            .accounts
+            .entry(fee_account)
+            .or_insert(Account::new(fee_account))
+            .receive_fee(currency, fee)?;
+        self.post(
+            fee_account,
+            Some(tx.tx_id),
+            tx.timestamp,
+            "fee_income",
+            currency,
+            fee,
+            &postings,
+        )?;
+        self.record(Event::FeeCharged {
+            client: tx.account_id,
+            tx: tx.tx_id,
+            currency: currency.to_owned(),
+            amount: fee,
+            fee_account,
+        });
+
+        Ok(())
+    }
+
+    // how a reversal changes `available`/`total`: what's left of a deposit after its refunds
+    // comes back out, a withdrawal is paid back in and an adjustment is negated. Disputes that
+    // were resolved gave their funds back, so they don't change it, but an open one must be
+    // settled first
+    fn reversal_change(tx_info: &TxRecord) -> Result<Amount> {
+        match tx_info.dispute_status {
+            DisputeStatus::Disputed => {
+                return Err(Error::TransactionError("Transaction is under dispute."));
+            }
+            DisputeStatus::ChargedBack | DisputeStatus::Reversed => {
+                return Err(Error::TransactionError(
+                    "Charged back or reversed transactions can't be reversed.",
+                ));
+            }
+            DisputeStatus::Undisputed | DisputeStatus::Resolved => {}
+        }
+        match tx_info.tx_type {
+            TransactionType::Deposit => Ok(tx_info.refunded - tx_info.amount),
+            TransactionType::Adjustment => Ok(-tx_info.amount),
+            TransactionType::Withdrawal => Ok(tx_info.amount),
+            _ => Err(Error::TransactionError("Transaction cannot be reversed.")),
+        }
+    }
+
+    // corrections skip the funds check of a withdrawal, so `available` may go negative unless the
+    // policy forbids it, just as for disputes
+    fn check_correction_funds(
+        policy: NegativeAvailablePolicy,
+        account: &Account,
+        currency: &str,
+        change: Amount,
+    ) -> Result<()> {
+        let available = account.balance(currency).available;
+        // a change too large to add can only take `available` below the lowest amount
+        if policy == NegativeAvailablePolicy::Reject
+            && change < Amount::ZERO
+            && available
+                .checked_add(change)
+                .is_none_or(|after| after < Amount::ZERO)
+        {
+            return Err(Error::InsufficientFunds(
+                "Insufficient funds to complete correction.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // whether a referencing tx may act on the stored tx: Ok(false) means the mismatch is to be
+    // ignored
+    fn check_account(
+        policy: AccountMismatchPolicy,
+        account: &Account,
+        tx_info: &TxRecord,
+    ) -> Result<bool> {
+        match (account.validate_tx_account_id(tx_info.account_id), policy) {
+            (Ok(()), _) => Ok(true),
+            (Err(_), AccountMismatchPolicy::Ignore) => Ok(false),
+            (Err(e), AccountMismatchPolicy::Reject) => Err(e),
+        }
+    }
+
+    // only disputed txs can be resolved or charged back
+    fn check_under_dispute(tx_info: &TxRecord) -> Result<()> {
+        if tx_info.dispute_status != DisputeStatus::Disputed {
+            return Err(Error::TransactionError("Transaction is not under dispute."));
+        }
+
+        Ok(())
+    }
+
+    // a row referencing a stored tx may repeat its currency, but never name a different one
+    fn check_currency(tx: &Transaction, tx_info: &TxRecord) -> Result<()> {
+        if let Some(currency) = &tx.currency
+            && *currency != tx_info.currency
+        {
+            return Err(Error::TransactionError(
+                "Transaction currency does not match the referenced transaction.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // chargeback rules only allow disputes for so long after the original tx--when either side
+    // has no timestamp there is nothing to check against
+    fn check_dispute_window(
+        window: Option<Duration>,
+        tx: &Transaction,
+        tx_info: &TxRecord,
+    ) -> Result<()> {
+        let Some(window) = window else {
+            return Ok(());
+        };
+        // a transaction without one can't age out of the window, but a dispute can't skip it
+        let Some(disputed_at) = tx.timestamp else {
+            return Err(Error::TransactionError(
+                "Dispute has no timestamp to check the dispute window by.",
+            ));
+        };
+        if let Some(original_at) = tx_info.timestamp
+            && disputed_at.saturating_sub(original_at) > window.as_secs()
+        {
+            return Err(Error::DisputeWindowExpired(tx.tx_id));
+        }
+
+        Ok(())
+    }
+
+    // uncleared provisional funds and open authorizations are already held, so they can't enter
+    // the dispute flow
+    fn check_cleared(tx_info: &TxRecord) -> Result<()> {
+        match tx_info.tx_type {
+            TransactionType::Provisional => Err(Error::TransactionError(
+                "Provisional deposit has not cleared yet.",
+            )),
+            TransactionType::Authorize => Err(Error::TransactionError(
+                "Authorization has not been captured yet.",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_open_authorization(tx_info: &TxRecord) -> Result<()> {
+        if tx_info.tx_type != TransactionType::Authorize {
+            return Err(Error::TransactionError(
+                "Transaction is not an open authorization.",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::amount::Amount;
+    use crate::error::{ErrorCategory, ErrorCode};
+    use crate::pending::PendingOverflow;
+    use crate::testing::TxBuilder;
+    use crate::transaction::{DEFAULT_CURRENCY, Transaction, TransactionType};
+    use proptest::prelude::*;
+
+    fn new_engine_with_deposit(account_id: u16, tx_id: u32, amount: Amount) -> PaymentsEngine {
+        let mut engine = PaymentsEngine::new();
+        engine
+            .process_tx(&TxBuilder::deposit(account_id, tx_id, amount).build())
+            .unwrap();
+
+        engine
+    }
+
+    #[test]
+    fn test_deposit_success() {
+        let mut engine = PaymentsEngine::new();
+        let deposit_tx = TxBuilder::deposit(1, 1, amount!(100)).build();
+
+        engine.process_tx(&deposit_tx).unwrap();
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(100));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(0));
+    }
+
+    #[test]
+    fn test_with_tx_store_carries_over_records() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100))
+            .with_tx_store(TxStore::memory())
+            .unwrap();
+        let dispute_tx = TxBuilder::dispute(1, 1).build();
+
+        engine.process_tx(&dispute_tx).unwrap();
+
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).held,
+            amount!(100)
+        );
+    }
+
+    #[test]
+    fn test_builder_account_mismatch_ignore() {
+        let mut engine = PaymentsEngine::builder()
+            .account_mismatch_policy(AccountMismatchPolicy::Ignore)
+            .build();
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(100)).build())
+            .unwrap();
+
+        engine
+            .process_tx(&TxBuilder::dispute(2, 1).build())
+            .unwrap();
+
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(0)
+        );
+        assert_eq!(
+            engine.accounts[&2].balance(DEFAULT_CURRENCY),
+            Balance::default()
+        );
+    }
+
+    #[test]
+    fn test_builder_lock_policy_never() {
+        let mut engine = PaymentsEngine::builder()
+            .lock_policy(LockPolicy::Never)
+            .build();
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(100)).build())
+            .unwrap();
+
+        engine
+            .process_tx(&TxBuilder::dispute(1, 1).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::chargeback(1, 1).build())
+            .unwrap();
+
+        let account = &engine.accounts[&1];
+        assert_eq!(account.balance(DEFAULT_CURRENCY).total, amount!(0));
+        assert_eq!(account.status, AccountStatus::Active);
+    }
+
+    #[test]
+    fn test_dispute_of_withdrawn_funds_drives_available_negative() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(&TxBuilder::withdrawal(1, 2, amount!(80)).build())
+            .unwrap();
+
+        engine
+            .process_tx(&TxBuilder::dispute(1, 1).build())
+            .unwrap();
+
+        let balance = engine.accounts[&1].balance(DEFAULT_CURRENCY);
+        assert_eq!(
+            (balance.available, balance.held, balance.total),
+            (amount!(-80), amount!(100), amount!(20))
+        );
+    }
+
+    #[test]
+    fn test_builder_negative_available_reject() {
+        let mut engine = PaymentsEngine::builder()
+            .negative_available_policy(NegativeAvailablePolicy::Reject)
+            .build();
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(100)).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::withdrawal(1, 2, amount!(80)).build())
+            .unwrap();
+
+        let result = engine.process_tx(&TxBuilder::dispute(1, 1).build());
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::InsufficientFunds(_)
+        ));
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(0)
+        );
+    }
+
+    #[test]
+    fn test_builder_amount_limits() {
+        let mut engine = PaymentsEngine::builder()
+            .amount_limits(AmountLimits {
+                min: Some(amount!(0.0001)),
+                max: Some(amount!(1000)),
+            })
+            .build();
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(1000)).build())
+            .unwrap();
+
+        let zero = engine.process_tx(&TxBuilder::deposit(1, 2, amount!(0)).build());
+        let large = engine.process_tx(&TxBuilder::withdrawal(1, 3, amount!(5000)).build());
+
+        assert_eq!(zero.unwrap_err().code(), ErrorCode::AmountBelowMinimum);
+        assert_eq!(large.unwrap_err().code(), ErrorCode::AmountAboveMaximum);
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).total,
+            amount!(1000)
+        );
+        // neither is stored, so the tx ids stay free
+        assert!(!engine.transactions.contains(2).unwrap());
+    }
+
+    #[test]
+    fn test_builder_restore_keeps_policies() {
+        let mut buf = Vec::new();
+        new_engine_with_deposit(1, 1, amount!(100))
+            .snapshot(&mut buf)
+            .unwrap();
+
+        let mut engine = PaymentsEngine::builder()
+            .duplicate_policy(DuplicatePolicy::Skip)
+            .restore(buf.as_slice())
+            .unwrap();
+
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(100)).build())
+            .unwrap();
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).total,
+            amount!(100)
+        );
+    }
+
+    #[test]
+    fn test_events_emitted_for_state_changes() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .event_sink(Box::new(sender))
+            .build();
+
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(100)).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::withdrawal(1, 2, amount!(500)).build())
+            .unwrap_err();
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Dispute, 1, 1)
+                    .amount(amount!(40))
+                    .build(),
+            )
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::chargeback(1, 1).build())
+            .unwrap();
+
+        let events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                Event::Deposited {
+                    client: 1,
+                    tx: 1,
+                    currency: String::new(),
+                    amount: amount!(100),
+                    timestamp: None,
+                },
+                Event::DisputeOpened {
+                    client: 1,
+                    tx: 1,
+                    tx_type: TransactionType::Deposit,
+                    currency: String::new(),
+                    amount: amount!(40),
+                },
+                Event::ChargebackApplied {
+                    client: 1,
+                    tx: 1,
+                    tx_type: TransactionType::Deposit,
+                    currency: String::new(),
+                    amount: amount!(40),
+                },
+                Event::AccountLocked {
+                    client: 1,
+                    reason: "chargeback of tx 1".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_builder_audit_sink() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .audit_sink(Box::new(sender))
+            .build();
+        let balance = |available, held| Balance {
+            available,
+            held,
+            total: available + held,
+        };
+
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(100)).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::withdrawal(1, 2, amount!(500)).build())
+            .unwrap_err();
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Dispute, 1, 1)
+                    .amount(amount!(40))
+                    .build(),
+            )
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::deposit(2, 3, amount!(5)).build())
+            .unwrap();
+        engine.merge_accounts(2, 1).unwrap();
+
+        let records: Vec<_> = receiver.try_iter().collect();
+        let record = |client, tx, operation, amount, before, after, postings| AuditRecord {
+            client,
+            tx,
+            operation,
+            currency: String::new(),
+            amount,
+            before,
+            after,
+            postings,
+        };
+        let posting = |debit, credit, amount| Postings::one(Posting::new(debit, credit, amount));
+        assert_eq!(
+            records,
+            vec![
+                record(
+                    1,
+                    Some(1),
+                    "deposit",
+                    amount!(100),
+                    balance(amount!(0), amount!(0)),
+                    balance(amount!(100), amount!(0)),
+                    posting(
+                        LedgerAccount::Suspense,
+                        LedgerAccount::Available,
+                        amount!(100)
+                    ),
+                ),
+                record(
+                    1,
+                    Some(1),
+                    "dispute",
+                    amount!(40),
+                    balance(amount!(100), amount!(0)),
+                    balance(amount!(60), amount!(40)),
+                    posting(LedgerAccount::Available, LedgerAccount::Held, amount!(40)),
+                ),
+                record(
+                    2,
+                    Some(3),
+                    "deposit",
+                    amount!(5),
+                    balance(amount!(0), amount!(0)),
+                    balance(amount!(5), amount!(0)),
+                    posting(
+                        LedgerAccount::Suspense,
+                        LedgerAccount::Available,
+                        amount!(5)
+                    ),
+                ),
+                record(
+                    2,
+                    None,
+                    "merge",
+                    amount!(5),
+                    balance(amount!(5), amount!(0)),
+                    balance(amount!(0), amount!(0)),
+                    Postings::none(),
+                ),
+                record(
+                    1,
+                    None,
+                    "merge",
+                    amount!(5),
+                    balance(amount!(60), amount!(40)),
+                    balance(amount!(65), amount!(40)),
+                    Postings::none(),
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_builder_observer() {
+        use std::sync::{Arc, Mutex};
+
+        // records every callback as a line
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        impl EngineObserver for Recorder {
+            fn on_tx_applied(&mut self, tx: &Transaction) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("applied {}", tx.tx_type));
+            }
+
+            fn on_tx_rejected(&mut self, tx: &Transaction, error: &Error) {
+                let code = error.code();
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("rejected {} {}", tx.tx_type, code));
+            }
+
+            fn on_account_locked(&mut self, client: u16, _reason: &str) {
+                self.0.lock().unwrap().push(format!("locked {}", client));
+            }
+
+            fn on_dispute_opened(
+                &mut self,
+                _client: u16,
+                tx: u32,
+                _currency: &str,
+                amount: Amount,
+            ) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("dispute {} {}", tx, amount));
+            }
+        }
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = PaymentsEngine::builder()
+            .observer(Box::new(Recorder(calls.clone())))
+            .build();
+
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(10)).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::withdrawal(1, 2, amount!(50)).build())
+            .unwrap_err();
+        engine
+            .process_tx(&TxBuilder::dispute(1, 1).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::chargeback(1, 1).build())
+            .unwrap();
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "applied deposit",
+                "rejected withdrawal insufficient-funds",
+                "dispute 1 10",
+                "applied dispute",
+                "locked 1",
+                "applied chargeback",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_builder_track_history() {
+        let mut engine = PaymentsEngine::builder().track_history(true).build();
+
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(100)).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::withdrawal(1, 2, amount!(500)).build())
+            .unwrap_err();
+        engine
+            .process_tx(&TxBuilder::withdrawal(1, 3, amount!(30)).build())
+            .unwrap();
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Dispute, 1, 1)
+                    .amount(amount!(40))
+                    .build(),
+            )
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::deposit(2, 4, amount!(5)).build())
+            .unwrap();
+
+        let entry = |operation, tx, amount, available, held| HistoryEntry {
+            operation,
+            tx: Some(tx),
+            timestamp: None,
+            effective: None,
+            currency: String::new(),
+            amount,
+            balance: Balance {
+                available,
+                held,
+                total: available + held,
+            },
+        };
+        assert_eq!(
+            engine.history(1),
+            [
+                entry("deposit", 1, amount!(100), amount!(100), amount!(0)),
+                entry("withdrawal", 3, amount!(30), amount!(70), amount!(0)),
+                entry("dispute", 1, amount!(40), amount!(30), amount!(40)),
+            ]
+        );
+        assert_eq!(engine.history(2).len(), 1);
+        assert!(engine.history(3).is_empty());
+
+        // untracked by default
+        let engine = new_engine_with_deposit(1, 1, amount!(100));
+        assert!(engine.history(1).is_empty());
+    }
+
+    #[test]
+    fn test_events_failure_sink_disconnected() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .event_sink(Box::new(sender))
+            .build();
+        drop(receiver);
+
+        let result = engine.process_tx(&TxBuilder::deposit(1, 1, amount!(100)).build());
+
+        assert!(matches!(result.unwrap_err().root(), Error::EventError(_)));
+        // the deposit itself was applied
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).total,
+            amount!(100)
+        );
+    }
+
+    #[test]
+    fn test_builder_reserves_expected_accounts() {
+        let engine = PaymentsEngine::builder().expected_accounts(1000).build();
+
+        assert!(engine.accounts.capacity() >= 1000);
+    }
+
+    #[test]
+    fn test_replay_rebuilds_state() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .event_sink(Box::new(sender))
+            .build();
+        let txs = [
+            TxBuilder::deposit(1, 1, amount!(100)).build(),
+            TxBuilder::withdrawal(1, 2, amount!(30)).build(),
+            TxBuilder::new(TransactionType::Dispute, 1, 1)
+                .amount(amount!(20))
+                .build(),
+            TxBuilder::deposit(2, 3, amount!(50)).build(),
+            TxBuilder::dispute(2, 3).build(),
+            TxBuilder::chargeback(2, 3).build(),
+            TxBuilder::new(TransactionType::Refund, 1, 1)
+                .amount(amount!(10))
+                .build(),
+            TxBuilder::new(TransactionType::Adjustment, 1, 4)
+                .amount(amount!(-5))
+                .reason("correction")
+                .build(),
+            TxBuilder::new(TransactionType::Reversal, 1, 2)
+                .reason("correction")
+                .build(),
+            TxBuilder::new(TransactionType::Authorize, 1, 5)
+                .amount(amount!(20))
+                .build(),
+            TxBuilder::new(TransactionType::Capture, 1, 5)
+                .amount(amount!(15))
+                .build(),
+            TxBuilder::new(TransactionType::Authorize, 1, 6)
+                .amount(amount!(5))
+                .build(),
+            TxBuilder::new(TransactionType::Void, 1, 6).build(),
+        ];
+        for tx in &txs {
+            engine.process_tx(tx).unwrap();
+        }
+        let mut log = Vec::new();
+        let mut sink = crate::JsonlSink::new(&mut log);
+        for event in receiver.try_iter() {
+            sink.emit(&event).unwrap();
+        }
+
+        let mut replayed = PaymentsEngine::replay(log.as_slice()).unwrap();
+
+        for id in [1, 2] {
+            assert_eq!(replayed.accounts[&id], engine.accounts[&id]);
+        }
+        // the open partial dispute carried over
+        replayed
+            .process_tx(&TxBuilder::resolve(1, 1).build())
+            .unwrap();
+        assert_eq!(
+            replayed.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(70)
+        );
+    }
+
+    #[test]
+    fn test_builder_dispute_window() {
+        const DAY: u64 = 24 * 60 * 60;
+        let mut engine = PaymentsEngine::builder()
+            .dispute_window(Duration::from_secs(60 * DAY))
+            .build();
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(10)).timestamp(0).build(),
+            TxBuilder::deposit(1, 2, amount!(20)).timestamp(0).build(),
+            // no timestamp, so never too old
+            TxBuilder::deposit(1, 3, amount!(30)).build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+
+        let late = engine.process_tx(&TxBuilder::dispute(1, 1).timestamp(61 * DAY).build());
+        let in_time = engine.process_tx(&TxBuilder::dispute(1, 2).timestamp(60 * DAY).build());
+        let untimed = engine.process_tx(&TxBuilder::dispute(1, 3).timestamp(90 * DAY).build());
+        let undated = engine.process_tx(&TxBuilder::dispute(1, 1).build());
+
+        assert!(matches!(
+            late.unwrap_err().root(),
+            Error::DisputeWindowExpired(1)
+        ));
+        assert!(in_time.is_ok());
+        assert!(untimed.is_ok());
+        assert!(matches!(
+            undated.unwrap_err().root(),
+            Error::TransactionError(message) if message.contains("no timestamp")
+        ));
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(50)
+        );
+    }
+
+    #[test]
+    fn test_builder_hold_expiry() {
+        const DAY: u64 = 24 * 60 * 60;
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .hold_expiry(Duration::from_secs(7 * DAY))
+            .event_sink(Box::new(sender))
+            .build();
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(100)).build(),
+            TxBuilder::new(TransactionType::Authorize, 1, 2)
+                .amount(amount!(10))
+                .timestamp(0)
+                .build(),
+            TxBuilder::new(TransactionType::Authorize, 1, 3)
+                .amount(amount!(20))
+                .timestamp(2 * DAY)
+                .build(),
+            // no timestamp, so never expires
+            TxBuilder::new(TransactionType::Authorize, 1, 4)
+                .amount(amount!(30))
+                .build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+        // a locked account's holds expire all the same
+        engine.accounts.get_mut(&1).unwrap().lock("test");
+
+        assert_eq!(engine.expire_holds(7 * DAY).unwrap(), vec![2]);
+        assert_eq!(engine.expire_holds(7 * DAY).unwrap(), Vec::<u32>::new());
+        let balance = engine.accounts[&1].balance(DEFAULT_CURRENCY);
+        assert_eq!(
+            (balance.available, balance.held),
+            (amount!(50), amount!(50))
+        );
+        engine.accounts.get_mut(&1).unwrap().unlock().unwrap();
+        assert!(
+            engine
+                .process_tx(&TxBuilder::new(TransactionType::Capture, 1, 2).build())
+                .is_err()
+        );
+
+        let mut log = Vec::new();
+        let mut sink = crate::JsonlSink::new(&mut log);
+        for event in receiver.try_iter() {
+            sink.emit(&event).unwrap();
+        }
+        let replayed = PaymentsEngine::replay(log.as_slice()).unwrap();
+        assert_eq!(replayed.accounts[&1].balances, engine.accounts[&1].balances);
+    }
+
+    #[test]
+    fn test_expire_holds_without_hold_expiry() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Authorize, 1, 2)
+                    .amount(amount!(10))
+                    .timestamp(0)
+                    .build(),
+            )
+            .unwrap();
+
+        assert!(engine.expire_holds(u64::MAX).unwrap().is_empty());
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(10)
+        );
+    }
+
+    #[test]
+    fn test_expire_holds_failure_changes_nothing() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .hold_expiry(Duration::from_secs(60))
+            .event_sink(Box::new(sender))
+            .build();
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(100)).build(),
+            TxBuilder::deposit(2, 2, amount!(100)).build(),
+            TxBuilder::new(TransactionType::Authorize, 1, 3)
+                .amount(amount!(10))
+                .timestamp(0)
+                .build(),
+            TxBuilder::new(TransactionType::Authorize, 2, 4)
+                .amount(amount!(20))
+                .timestamp(0)
+                .build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+        receiver.try_iter().for_each(drop);
+        // client 2's hold is gone from under its authorization, so releasing it fails after
+        // client 1's was released
+        engine
+            .accounts
+            .get_mut(&2)
+            .unwrap()
+            .balances
+            .get_mut(DEFAULT_CURRENCY)
+            .unwrap()
+            .held = Amount::ZERO;
+
+        let result = engine.expire_holds(60);
+
+        assert!(matches!(result, Err(Error::InsufficientFunds(_))));
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(10)
+        );
+        assert_eq!(
+            engine.transactions.get(3).unwrap().unwrap().tx_type,
+            TransactionType::Authorize
+        );
+        // the next operation emits its own events and nothing left over from the failure
+        engine
+            .process_tx(&TxBuilder::deposit(1, 5, amount!(1)).build())
+            .unwrap();
+        assert!(matches!(
+            receiver.try_iter().collect::<Vec<_>>()[..],
+            [Event::Deposited { tx: 5, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_expire_holds_events_failure_sink_disconnected() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .hold_expiry(Duration::from_secs(60))
+            .event_sink(Box::new(sender))
+            .build();
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(100)).build(),
+            TxBuilder::new(TransactionType::Authorize, 1, 2)
+                .amount(amount!(10))
+                .timestamp(0)
+                .build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+        drop(receiver);
+
+        let result = engine.expire_holds(60);
+
+        assert!(matches!(result.unwrap_err().root(), Error::EventError(_)));
+        // the hold itself was released
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(0)
+        );
+        assert!(engine.expire_holds(60).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_builder_clearing_period() {
+        const DAY: u64 = 24 * 60 * 60;
+        let mut engine = PaymentsEngine::builder()
+            .clearing_period(Duration::from_secs(3 * DAY))
+            .build();
+        for tx in [
+            TxBuilder::new(TransactionType::Provisional, 1, 1)
+                .amount(amount!(10))
+                .timestamp(0)
+                .build(),
+            TxBuilder::new(TransactionType::Provisional, 1, 2)
+                .amount(amount!(20))
+                .timestamp(2 * DAY)
+                .build(),
+            // no timestamp, so waits for a clear
+            TxBuilder::new(TransactionType::Provisional, 1, 3)
+                .amount(amount!(30))
+                .build(),
+            TxBuilder::new(TransactionType::Provisional, 2, 4)
+                .amount(amount!(40))
+                .timestamp(0)
+                .build(),
+            // a chargeback locks client 2
+            TxBuilder::deposit(2, 5, amount!(5)).build(),
+            TxBuilder::dispute(2, 5).build(),
+            TxBuilder::chargeback(2, 5).build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+
+        let cleared = engine.clear_due(4 * DAY).unwrap();
+
+        // the locked account's deposit stays held
+        assert_eq!(cleared, vec![1]);
+        let balance = engine.accounts[&1].balance(DEFAULT_CURRENCY);
+        assert_eq!(balance.available, amount!(10));
+        assert_eq!(balance.held, amount!(50));
+        assert_eq!(
+            engine.accounts[&2].balance(DEFAULT_CURRENCY).held,
+            amount!(40)
+        );
+        // a cleared deposit is an ordinary deposit, and can't be cleared again
+        assert!(
+            engine
+                .process_tx(&TxBuilder::new(TransactionType::Clear, 1, 1).build())
+                .is_err()
+        );
+        assert_eq!(engine.clear_due(5 * DAY).unwrap(), vec![2]);
+        assert!(engine.clear_due(u64::MAX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_clear_due_without_clearing_period() {
+        let mut engine = PaymentsEngine::new();
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Provisional, 1, 1)
+                    .amount(amount!(10))
+                    .timestamp(0)
+                    .build(),
+            )
+            .unwrap();
+
+        assert!(engine.clear_due(u64::MAX).unwrap().is_empty());
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(10)
+        );
+    }
+
+    #[test]
+    fn test_clear_due_failure_changes_nothing() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .clearing_period(Duration::from_secs(60))
+            .event_sink(Box::new(sender))
+            .build();
+        for client in 1..=3 {
+            let provisional = TxBuilder::new(TransactionType::Provisional, client, client.into())
+                .amount(amount!(10))
+                .timestamp(0)
+                .build();
+            engine.process_tx(&provisional).unwrap();
+        }
+        receiver.try_iter().for_each(drop);
+        // client 2's deposit is skipped, and client 3's fails after client 1's was cleared
+        engine.accounts.get_mut(&2).unwrap().lock("test");
+        engine
+            .accounts
+            .get_mut(&3)
+            .unwrap()
+            .balances
+            .get_mut(DEFAULT_CURRENCY)
+            .unwrap()
+            .held = Amount::ZERO;
+
+        let result = engine.clear_due(60);
+
+        assert!(matches!(result, Err(Error::InsufficientFunds(_))));
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(10)
+        );
+        assert_eq!(
+            engine.transactions.get(1).unwrap().unwrap().tx_type,
+            TransactionType::Provisional
+        );
+        // the next operation emits its own events and nothing left over from the failure
+        engine
+            .process_tx(&TxBuilder::deposit(1, 4, amount!(1)).build())
+            .unwrap();
+        assert!(matches!(
+            receiver.try_iter().collect::<Vec<_>>()[..],
+            [Event::Deposited { tx: 4, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_eviction_policy_settled() {
+        use std::sync::{Arc, Mutex};
+
+        struct Archive(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Archive {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let archive = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = PaymentsEngine::builder()
+            .eviction_policy(EvictionPolicy::Settled)
+            .archive(Box::new(Archive(archive.clone())))
+            .build();
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(10)).build(),
+            TxBuilder::deposit(1, 2, amount!(10)).build(),
+            TxBuilder::new(TransactionType::Dispute, 1, 1)
+                .amount(amount!(6))
+                .build(),
+            TxBuilder::resolve(1, 1).build(),
+            TxBuilder::new(TransactionType::Refund, 1, 1).build(),
+            TxBuilder::new(TransactionType::Dispute, 1, 2)
+                .amount(amount!(4))
+                .build(),
+            TxBuilder::resolve(1, 2).build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+
+        // tx 2 can still be disputed or refunded for the rest of its amount
+        assert!(engine.transaction(1).unwrap().is_none());
+        assert!(engine.transaction(2).unwrap().is_some());
+        assert_eq!(
+            String::from_utf8(archive.lock().unwrap().clone()).unwrap(),
+            "{\"tx\":1,\"tx_type\":\"deposit\",\"account_id\":1,\"currency\":\"\",\
+             \"dispute_status\":\"Resolved\",\"amount\":\"10\",\"disputable\":\"0\",\
+             \"disputed\":\"0\",\"refunded\":\"10\",\"timestamp\":null}\n"
+        );
+        assert!(matches!(
+            engine
+                .process_tx(&TxBuilder::deposit(1, 1, amount!(1)).build())
+                .unwrap_err()
+                .root(),
+            Error::DuplicateTransaction(1)
+        ));
+        assert!(matches!(
+            engine
+                .process_tx(&TxBuilder::dispute(1, 1).build())
+                .unwrap_err()
+                .root(),
+            Error::TransactionError(message) if message.contains("evicted")
+        ));
+
+        // evicted ids survive a snapshot
+        let mut buf = Vec::new();
+        engine.snapshot(&mut buf).unwrap();
+        let mut restored = PaymentsEngine::restore(buf.as_slice()).unwrap();
+        assert!(matches!(
+            restored
+                .process_tx(&TxBuilder::deposit(1, 1, amount!(1)).build())
+                .unwrap_err()
+                .root(),
+            Error::DuplicateTransaction(1)
+        ));
+    }
+
+    #[test]
+    fn test_evict_expired() {
+        let mut engine = PaymentsEngine::builder()
+            .eviction_policy(EvictionPolicy::Settled)
+            .dispute_window(Duration::from_secs(SECS_PER_DAY))
+            .build();
+        for (tx_type, tx_id, timestamp) in [
+            (TransactionType::Deposit, 1, Some(0)),
+            (TransactionType::Deposit, 2, Some(0)),
+            (TransactionType::Deposit, 3, Some(SECS_PER_DAY)),
+            (TransactionType::Deposit, 4, None),
+            (TransactionType::Dispute, 2, Some(SECS_PER_DAY)),
+        ] {
+            engine
+                .process_tx(&Transaction {
+                    timestamp,
+                    ..TxBuilder::new(tx_type, 1, tx_id)
+                        .amount(amount!(10))
+                        .build()
+                })
+                .unwrap();
+        }
+
+        // tx 2 is under dispute, tx 3 is still within the window and tx 4 has no timestamp
+        assert_eq!(engine.evict_expired(SECS_PER_DAY + 1).unwrap(), 1);
+        assert!(engine.transaction(1).unwrap().is_none());
+        assert_eq!(engine.transactions().count(), 3);
+        assert!(
+            engine
+                .process_tx(&TxBuilder::new(TransactionType::Refund, 1, 1).build())
+                .is_err()
+        );
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).total,
+            amount!(40)
+        );
+    }
+
+    #[test]
+    fn test_check_ledger_success() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        for tx in [
+            TxBuilder::withdrawal(1, 2, amount!(30)).build(),
+            TxBuilder::dispute(1, 1).build(),
+            TxBuilder::chargeback(1, 1).build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+
+        engine.check_ledger().unwrap();
+        assert_eq!(
+            engine
+                .ledger()
+                .balance(DEFAULT_CURRENCY, LedgerAccount::Suspense),
+            amount!(-70)
+        );
+        assert_eq!(
+            engine
+                .ledger()
+                .balance(DEFAULT_CURRENCY, LedgerAccount::ChargebackLoss),
+            amount!(100)
+        );
+    }
+
+    #[test]
+    fn test_check_ledger_failure_unposted_change() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let balance = engine
+            .accounts
+            .get_mut(&1)
+            .unwrap()
+            .balances
+            .get_mut(DEFAULT_CURRENCY)
+            .unwrap();
+        balance.available += amount!(1);
+        balance.total += amount!(1);
+
+        assert!(matches!(engine.check_ledger(), Err(Error::EngineError(_))));
+    }
+
+    #[test]
+    fn test_process_failure_ledger_overflow_changes_nothing() {
+        let mut engine = new_engine_with_deposit(1, 1, Amount::MAX);
+
+        let result = engine.process_tx(&TxBuilder::deposit(2, 2, amount!(1)).build());
+
+        assert!(matches!(result.unwrap_err().root(), Error::EngineError(_)));
+        assert_eq!(
+            engine.account(2).unwrap().balance(DEFAULT_CURRENCY),
+            Balance::default()
+        );
+        assert!(!engine.transactions.contains(2).unwrap());
+        engine.check_ledger().unwrap();
+    }
+
+    #[test]
+    fn test_seed_balance_failure_ledger_overflow() {
+        let mut engine = new_engine_with_deposit(1, 1, Amount::MAX);
+        let balance = Balance {
+            available: amount!(1),
+            held: Amount::ZERO,
+            total: amount!(1),
+        };
+
+        assert!(
+            engine
+                .seed_balance(2, "EUR", balance, AccountStatus::Active)
+                .is_ok()
+        );
+        assert!(
+            engine
+                .seed_balance(2, DEFAULT_CURRENCY, balance, AccountStatus::Locked)
+                .is_err()
+        );
+        let account = engine.account(2).unwrap();
+        assert!(!account.balances.contains_key(DEFAULT_CURRENCY));
+        assert_eq!(account.status, AccountStatus::Active);
+        engine.check_ledger().unwrap();
+    }
+
+    #[test]
+    fn test_builder_interest_rates() {
+        const DAY: u64 = 24 * 60 * 60;
+        let rates = InterestRates::new(Some(rust_decimal::dec!(3.65)), []).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .interest_rates(rates.clone())
+            .event_sink(Box::new(sender))
+            .build();
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(1000)).timestamp(0).build(),
+            // client 1 earns 0.01% a day for the day before this deposit
+            TxBuilder::deposit(2, 2, amount!(1000))
+                .timestamp(DAY + 10)
+                .build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(1000.1)
+        );
+
+        // part of a day earns nothing yet
+        assert_eq!(engine.accrue_interest(2 * DAY - 1).unwrap(), 0);
+        assert_eq!(engine.accrue_interest(2 * DAY).unwrap(), 2);
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(1000.2)
+        );
+        assert_eq!(
+            engine.accounts[&2].balance(DEFAULT_CURRENCY).total,
+            amount!(1000.1)
+        );
+
+        // the clock survives a snapshot, so a restored engine doesn't pay the same days twice
+        let mut buf = Vec::new();
+        engine.snapshot(&mut buf).unwrap();
+        let mut restored = PaymentsEngine::builder()
+            .interest_rates(rates)
+            .restore(buf.as_slice())
+            .unwrap();
+        assert_eq!(restored.accrue_interest(2 * DAY).unwrap(), 0);
+
+        let mut log = Vec::new();
+        let mut sink = crate::JsonlSink::new(&mut log);
+        for event in receiver.try_iter() {
+            sink.emit(&event).unwrap();
+        }
+        let replayed = PaymentsEngine::replay(log.as_slice()).unwrap();
+        assert_eq!(replayed.accounts[&1].balances, engine.accounts[&1].balances);
+        assert_eq!(replayed.interest_accrued_to, Some(2 * DAY));
+    }
+
+    #[test]
+    fn test_accrue_interest_failure_changes_nothing() {
+        const DAY: u64 = 24 * 60 * 60;
+        let rates = InterestRates::new(Some(rust_decimal::dec!(3.65)), []).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .interest_rates(rates)
+            .event_sink(Box::new(sender))
+            .build();
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(1000)).build(),
+            // no room left for client 2's interest, credited after client 1's
+            TxBuilder::deposit(2, 2, Amount::MAX)
+                .currency("EUR")
+                .build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+        engine.accrue_interest(0).unwrap();
+        receiver.try_iter().for_each(drop);
+
+        let result = engine.accrue_interest(DAY);
+
+        assert!(matches!(result, Err(Error::TransactionError(_))));
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(1000)
+        );
+        assert_eq!(engine.interest_accrued_to, Some(0));
+        engine.check_ledger().unwrap();
+        // the next operation emits its own events and nothing left over from the failure
+        engine
+            .process_tx(&TxBuilder::deposit(1, 3, amount!(1)).build())
+            .unwrap();
+        assert!(matches!(
+            receiver.try_iter().collect::<Vec<_>>()[..],
+            [Event::Deposited { tx: 3, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_backdated_transactions_correct_interest() {
+        const DAY: u64 = 24 * 60 * 60;
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .interest_rates(InterestRates::new(Some(rust_decimal::dec!(3.65)), []).unwrap())
+            .event_sink(Box::new(sender))
+            .track_history(true)
+            .build();
+        let backdated = |tx_type, tx_id, amount, effective| Transaction {
+            timestamp: Some(10 * DAY),
+            effective: Some(effective),
+            ..TxBuilder::new(tx_type, 1, tx_id).amount(amount).build()
+        };
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(1000)).timestamp(0).build())
+            .unwrap();
+        engine.accrue_interest(10 * DAY).unwrap();
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).total,
+            amount!(1001.0004)
+        );
+
+        // 0.01% a day on 1000 for the 5 days since it took effect
+        engine
+            .process_tx(&backdated(
+                TransactionType::Deposit,
+                2,
+                amount!(1000),
+                5 * DAY,
+            ))
+            .unwrap();
+        // and 500 taken out 2 days ago earned 0.05 a day it shouldn't have
+        engine
+            .process_tx(&backdated(
+                TransactionType::Withdrawal,
+                3,
+                amount!(500),
+                8 * DAY,
+            ))
+            .unwrap();
+
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).total,
+            amount!(1501.4004)
+        );
+        let interest: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::InterestAccrued {
+                    amount, from, to, ..
+                } => Some((amount, from, to)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            interest,
+            [
+                (amount!(1.0004), 0, 10 * DAY),
+                (amount!(0.5), 5 * DAY, 10 * DAY),
+                (amount!(-0.1), 8 * DAY, 10 * DAY),
+            ]
+        );
+        // the history keeps the posting order, with when each change took effect
+        let history: Vec<_> = engine
+            .history(1)
+            .iter()
+            .map(|entry| (entry.operation, entry.effective))
+            .collect();
+        assert_eq!(
+            history,
+            [
+                ("deposit", None),
+                ("interest", None),
+                ("deposit", Some(5 * DAY)),
+                ("interest", None),
+                ("withdrawal", Some(8 * DAY)),
+                ("interest", None),
+            ]
+        );
+        engine.check_ledger().unwrap();
+    }
+
+    #[test]
+    fn test_process_tx_failure_effective_after_timestamp() {
+        let mut engine = PaymentsEngine::new();
+
+        let result = engine.process_tx(&Transaction {
+            timestamp: Some(10),
+            effective: Some(20),
+            ..TxBuilder::deposit(1, 1, amount!(5)).build()
+        });
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::TransactionError(_)
+        ));
+        assert!(engine.account(1).is_none());
+    }
+
+    #[test]
+    fn test_builder_fee_schedule() {
+        let schedule = FeeSchedule::from_toml(
+            r#"
+            fee_account = 9000
+            [[fees]]
+            type = "deposit"
+            percent = "1"
+            [[fees]]
+            type = "withdrawal"
+            flat = "1"
+            "#,
+        )
+        .unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .fee_schedule(schedule)
+            .event_sink(Box::new(sender))
+            .build();
+
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(100)).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::withdrawal(1, 2, amount!(50)).build())
+            .unwrap();
+        // 48 is available, but not 48 plus the fee
+        let result = engine.process_tx(&TxBuilder::withdrawal(1, 3, amount!(48)).build());
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::InsufficientFunds(_)
+        ));
+
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(48)
+        );
+        assert_eq!(
+            engine.accounts[&9000].balance(DEFAULT_CURRENCY).available,
+            amount!(2)
+        );
+
+        let mut log = Vec::new();
+        let mut sink = crate::JsonlSink::new(&mut log);
+        for event in receiver.try_iter() {
+            sink.emit(&event).unwrap();
+        }
+        let replayed = PaymentsEngine::replay(log.as_slice()).unwrap();
+        assert_eq!(replayed.accounts[&1].balances, engine.accounts[&1].balances);
+        assert_eq!(
+            replayed.accounts[&9000].balances,
+            engine.accounts[&9000].balances
+        );
+    }
+
+    #[test]
+    fn test_process_failure_fee_leg_changes_nothing() {
+        let schedule = FeeSchedule::from_toml(
+            r#"
+            fee_account = 9000
+            [[fees]]
+            type = "withdrawal"
+            flat = "1"
+            "#,
+        )
+        .unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .fee_schedule(schedule)
+            .track_history(true)
+            .event_sink(Box::new(sender))
+            .build();
+        // the fee account is full, with a negative balance elsewhere leaving the ledger room
+        let seeded = |available| Balance {
+            available,
+            held: Amount::ZERO,
+            total: available,
+        };
+        engine
+            .seed_balance(
+                2,
+                DEFAULT_CURRENCY,
+                seeded(-Amount::MAX),
+                AccountStatus::Active,
+            )
+            .unwrap();
+        engine
+            .seed_balance(
+                9000,
+                DEFAULT_CURRENCY,
+                seeded(Amount::MAX),
+                AccountStatus::Active,
+            )
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(100)).build())
+            .unwrap();
+        let events = receiver.try_iter().count();
+
+        // the withdrawal and the client's fee are posted before the fee account overflows
+        let result = engine.process_tx(&TxBuilder::withdrawal(1, 2, amount!(50)).build());
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::TransactionError(_)
+        ));
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY),
+            seeded(amount!(100))
+        );
+        assert_eq!(
+            engine.accounts[&9000].balance(DEFAULT_CURRENCY),
+            seeded(Amount::MAX)
+        );
+        assert_eq!(engine.history(1).len(), 1);
+        assert!(!engine.transactions.contains(2).unwrap());
+        assert_eq!(
+            engine
+                .ledger
+                .balance(DEFAULT_CURRENCY, LedgerAccount::Suspense),
+            -amount!(100)
+        );
+        assert_eq!(events, 3);
+        assert_eq!(receiver.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_builder_withdrawal_limits() {
+        let limits = WithdrawalLimits::from_toml("daily = \"100\"").unwrap();
+        let mut engine = PaymentsEngine::builder().withdrawal_limits(limits).build();
+        let timed = |tx_type, tx_id, amount, timestamp| {
+            TxBuilder::new(tx_type, 1, tx_id)
+                .amount(amount)
+                .timestamp(timestamp)
+                .build()
+        };
+
+        engine
+            .process_tx(&timed(TransactionType::Deposit, 1, amount!(200), 0))
+            .unwrap();
+        engine
+            .process_tx(&timed(TransactionType::Withdrawal, 2, amount!(60), 10))
+            .unwrap();
+        let error = engine
+            .process_tx(&timed(TransactionType::Authorize, 3, amount!(50), 20))
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::LimitExceeded);
+        assert_eq!(error.category(), ErrorCategory::AmountLimit);
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(140)
+        );
+        // a day after the first withdrawal
+        engine
+            .process_tx(&timed(
+                TransactionType::Withdrawal,
+                4,
+                amount!(100),
+                10 + SECS_PER_DAY,
+            ))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_builder_risk_rules() {
+        let rules = RiskRules::from_toml(
+            r#"
+            [[rules]]
+            rule = "deposit_withdraw_velocity"
+            window = 60
+            action = "hold"
+            [[rules]]
+            rule = "dispute_rate"
+            max_percent = "40"
+            action = "reject"
+            "#,
+        )
+        .unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .risk_rules(rules)
+            .event_sink(Box::new(sender))
+            .build();
+        let timed = |tx_type, tx_id, amount, timestamp| Transaction {
+            amount,
+            ..TxBuilder::new(tx_type, 1, tx_id)
+                .timestamp(timestamp)
+                .build()
+        };
+
+        engine
+            .process_tx(&timed(TransactionType::Deposit, 1, Some(amount!(100)), 0))
+            .unwrap();
+        // withdrawn too soon after the deposit, so held as an authorization
+        engine
+            .process_tx(&timed(
+                TransactionType::Withdrawal,
+                2,
+                Some(amount!(30)),
+                10,
+            ))
+            .unwrap();
+        let balance = engine.accounts[&1].balance(DEFAULT_CURRENCY);
+        assert_eq!(balance.available, amount!(70));
+        assert_eq!(balance.total, amount!(100));
+        engine
+            .process_tx(&timed(TransactionType::Capture, 2, None, 20))
+            .unwrap();
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).total,
+            amount!(70)
+        );
+        // one dispute in two transactions is over the rate
+        engine
+            .process_tx(&timed(TransactionType::Dispute, 1, None, 30))
+            .unwrap();
+        let result = engine.process_tx(&timed(TransactionType::Deposit, 3, Some(amount!(5)), 90));
+        assert_eq!(result.unwrap_err().code(), ErrorCode::RiskRejected);
+
+        let events: Vec<_> = receiver.try_iter().collect();
+        assert!(events.contains(&Event::RiskFlagged {
+            client: 1,
+            tx: 2,
+            rule: "deposit_withdraw_velocity".to_string(),
+            action: RiskAction::Hold,
+        }));
+        let mut log = Vec::new();
+        let mut sink = crate::JsonlSink::new(&mut log);
+        for event in &events {
+            sink.emit(event).unwrap();
+        }
+        let replayed = PaymentsEngine::replay(log.as_slice()).unwrap();
+        assert_eq!(replayed.accounts[&1].balances, engine.accounts[&1].balances);
+    }
+
+    #[test]
+    fn test_builder_balance_thresholds() {
+        let thresholds = crate::BalanceThresholds::from_toml(
+            r#"
+            available_below = "50"
+            held_above = "20"
+            "#,
+        )
+        .unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .balance_thresholds(thresholds)
+            .event_sink(Box::new(sender))
+            .build();
+
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(100)).build(),
+            TxBuilder::withdrawal(1, 2, amount!(60)).build(),
+            // already below, so not again
+            TxBuilder::withdrawal(1, 3, amount!(10)).build(),
+            TxBuilder::dispute(1, 1).build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+
+        let crossed: Vec<_> = receiver
+            .try_iter()
+            .filter(|event| matches!(event, Event::BalanceThresholdCrossed { .. }))
+            .collect();
+        assert_eq!(
+            crossed,
+            [
+                Event::BalanceThresholdCrossed {
+                    client: 1,
+                    tx: Some(2),
+                    currency: String::new(),
+                    kind: crate::ThresholdKind::AvailableBelow,
+                    threshold: amount!(50),
+                    balance: amount!(40),
+                },
+                Event::BalanceThresholdCrossed {
+                    client: 1,
+                    tx: Some(1),
+                    currency: String::new(),
+                    kind: crate::ThresholdKind::HeldAbove,
+                    threshold: amount!(20),
+                    balance: amount!(100),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pending_dispute_applies_once_tx_arrives() {
+        let mut engine = PaymentsEngine::builder()
+            .pending_disputes(PendingDisputes {
+                capacity: 10,
+                max_age: None,
+                overflow: PendingOverflow::RejectNew,
+            })
+            .build();
+
+        engine
+            .process_tx(&TxBuilder::dispute(1, 1).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(10)).build())
+            .unwrap();
+
+        let balance = engine.accounts[&1].balance(DEFAULT_CURRENCY);
+        assert_eq!((balance.available, balance.held), (amount!(0), amount!(10)));
+        assert!(engine.take_dead_letters().is_empty());
+    }
+
+    #[test]
+    fn test_pending_dispute_expires() {
+        let mut engine = PaymentsEngine::builder()
+            .pending_disputes(PendingDisputes {
+                capacity: 10,
+                max_age: Some(2),
+                overflow: PendingOverflow::RejectNew,
+            })
+            .build();
+
+        for tx in [
+            TxBuilder::dispute(1, 1).build(),
+            TxBuilder::dispute(1, 2).build(),
+            TxBuilder::deposit(1, 3, amount!(10)).build(),
+            TxBuilder::deposit(1, 2, amount!(20)).build(),
+            TxBuilder::deposit(1, 1, amount!(30)).build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+
+        // tx 2 came two transactions after its dispute, tx 1 three--one too many
+        let dead_letters = engine.take_dead_letters();
+        assert_eq!(dead_letters.len(), 1);
+        assert!(matches!(
+            dead_letters[0].root(),
+            Error::UnknownTransaction(1)
+        ));
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(20)
+        );
+    }
+
+    #[test]
+    fn test_pending_dispute_expiry_skips_retried() {
+        let mut engine = PaymentsEngine::builder()
+            .pending_disputes(PendingDisputes {
+                capacity: 10,
+                max_age: Some(2),
+                overflow: PendingOverflow::RejectNew,
+            })
+            .build();
+
+        for tx in [
+            TxBuilder::dispute(1, 9).build(),
+            TxBuilder::deposit(1, 9, amount!(1)).build(),
+            TxBuilder::dispute(1, 7).build(),
+            TxBuilder::deposit(1, 7, amount!(10)).build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+
+        assert!(engine.take_dead_letters().is_empty());
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(11)
+        );
+    }
+
+    #[test]
+    fn test_pending_dispute_overflow() {
+        let engine = |overflow| {
+            let mut engine = PaymentsEngine::builder()
+                .pending_disputes(PendingDisputes {
+                    capacity: 1,
+                    max_age: None,
+                    overflow,
+                })
+                .build();
+            let first = engine.process_tx(&TxBuilder::dispute(1, 1).build());
+            let second = engine.process_tx(&TxBuilder::dispute(1, 2).build());
+            assert!(first.is_ok());
+            (engine, second)
+        };
+
+        let (mut rejecting, second) = engine(PendingOverflow::RejectNew);
+        assert!(matches!(
+            second.unwrap_err().root(),
+            Error::UnknownTransaction(2)
+        ));
+        assert!(rejecting.take_dead_letters().is_empty());
+
+        let (mut evicting, second) = engine(PendingOverflow::EvictOldest);
+        assert!(second.is_ok());
+        let dead_letters = evicting.take_dead_letters();
+        assert!(matches!(
+            dead_letters[..],
+            [ref e] if matches!(e.root(), Error::UnknownTransaction(1))
+        ));
+        evicting.flush_pending_disputes();
+        assert_eq!(evicting.take_dead_letters().len(), 1);
+    }
+
+    #[test]
+    fn test_replay_keeps_timestamps() {
+        let log = "{\"event\":\"deposited\",\"client\":1,\"tx\":1,\"amount\":\"10\",\
+                   \"timestamp\":100}\n";
+
+        let engine = PaymentsEngine::replay(log.as_bytes()).unwrap();
+
+        assert_eq!(
+            engine.transactions.get(1).unwrap().unwrap().timestamp,
+            Some(100)
+        );
+    }
+
+    #[test]
+    fn test_replay_onto_snapshot() {
+        let earlier = "{\"event\":\"deposited\",\"client\":1,\"tx\":1,\"amount\":\"10\"}\n";
+        let later = "{\"event\":\"dispute_opened\",\"client\":1,\"tx\":1,\"tx_type\":\"deposit\",\
+                     \"amount\":\"10\"}\n";
+        let mut snapshot = Vec::new();
+        PaymentsEngine::replay(earlier.as_bytes())
+            .unwrap()
+            .snapshot(&mut snapshot)
+            .unwrap();
+        let mut engine = PaymentsEngine::restore(snapshot.as_slice()).unwrap();
+
+        engine.replay_onto(later.as_bytes()).unwrap();
+
+        let whole = PaymentsEngine::replay(format!("{}{}", earlier, later).as_bytes()).unwrap();
+        assert_eq!(engine.accounts[&1], whole.accounts[&1]);
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(10)
+        );
+    }
+
+    #[test]
+    fn test_replay_failure_unknown_reference() {
+        let log = "{\"event\":\"deposited\",\"client\":1,\"tx\":1,\"amount\":\"10\"}\n\
+                   {\"event\":\"dispute_opened\",\"client\":1,\"tx\":2,\"tx_type\":\"deposit\",\
+                   \"amount\":\"10\"}\n";
+
+        let error = PaymentsEngine::replay(log.as_bytes()).err().unwrap();
+
+        assert!(matches!(error.root(), Error::UnknownTransaction(2)));
+        assert_eq!(error.context().unwrap().line, Some(2));
+    }
+
+    #[test]
+    fn test_seed_balance_success() {
+        let mut engine = PaymentsEngine::new();
+        let balance = Balance {
+            available: amount!(7),
+            held: amount!(3),
+            total: amount!(10),
+        };
+
+        engine
+            .seed_balance(1, DEFAULT_CURRENCY, balance, AccountStatus::Locked)
+            .unwrap();
+
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY), balance);
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_seed_balance_failure_inconsistent_total() {
+        let mut engine = PaymentsEngine::new();
+        let balance = Balance {
+            available: amount!(7),
+            held: amount!(3),
+            total: amount!(11),
+        };
+
+        let result = engine.seed_balance(1, DEFAULT_CURRENCY, balance, AccountStatus::Active);
+
+        assert!(result.is_err());
+        assert!(engine.account(1).is_none());
+    }
+
+    #[test]
+    fn test_seed_balance_failure_overflow() {
+        let mut engine = PaymentsEngine::new();
+        let balance = Balance {
+            available: Amount::MAX,
+            held: Amount::MAX,
+            total: amount!(1),
+        };
+
+        let result = engine.seed_balance(1, DEFAULT_CURRENCY, balance, AccountStatus::Active);
+
+        assert!(matches!(result, Err(Error::AccountError(_))));
+        assert!(engine.account(1).is_none());
+    }
+
+    #[test]
+    fn test_seed_balance_failure_already_seeded() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+
+        let result = engine.seed_balance(
+            1,
+            DEFAULT_CURRENCY,
+            Balance::default(),
+            AccountStatus::Active,
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_withdrawal_success() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let withdrawal_tx = TxBuilder::withdrawal(1, 2, amount!(60)).build();
+
+        engine.process_tx(&withdrawal_tx).unwrap();
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(40));
+    }
+
+    #[test]
+    fn test_dispute_success() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let dispute_tx = TxBuilder::dispute(1, 1).build();
+
+        engine.process_tx(&dispute_tx).unwrap();
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(0));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(100));
+    }
+
+    #[test]
+    fn test_resolve_success() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let dispute_tx = TxBuilder::dispute(1, 1).build();
+        let resolve_tx = TxBuilder::resolve(1, 1).build();
+
+        engine.process_tx(&dispute_tx).unwrap();
+        engine.process_tx(&resolve_tx).unwrap();
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(100));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(0));
+    }
+
+    #[test]
+    fn test_chargeback_success() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let dispute_tx = TxBuilder::dispute(1, 1).build();
+        let chargeback_tx = &TxBuilder::chargeback(1, 1).build();
+
+        engine.process_tx(&dispute_tx).unwrap();
+        engine.process_tx(chargeback_tx).unwrap();
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(0));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(0));
+        assert!(account.is_locked());
+        assert_eq!(account.status_reason.as_deref(), Some("chargeback of tx 1"));
+    }
+
+    #[test]
+    fn test_partial_dispute_holds_portion() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let dispute_tx = TxBuilder::new(TransactionType::Dispute, 1, 1)
+            .amount(amount!(30))
+            .build();
+        let chargeback_tx = TxBuilder::chargeback(1, 1).build();
+
+        engine.process_tx(&dispute_tx).unwrap();
+        let balance = engine.accounts[&1].balance(DEFAULT_CURRENCY);
+        assert_eq!(
+            (balance.available, balance.held),
+            (amount!(70), amount!(30))
+        );
+
+        engine.process_tx(&chargeback_tx).unwrap();
+        let balance = engine.accounts[&1].balance(DEFAULT_CURRENCY);
+        assert_eq!((balance.available, balance.held), (amount!(70), amount!(0)));
+        assert_eq!(balance.total, amount!(70));
+    }
+
+    #[test]
+    fn test_partial_disputes_cannot_exceed_original() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let resolve_tx = TxBuilder::resolve(1, 1).build();
+
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Dispute, 1, 1)
+                    .amount(amount!(60))
+                    .build(),
+            )
+            .unwrap();
+        engine.process_tx(&resolve_tx).unwrap();
+        let result = engine.process_tx(
+            &TxBuilder::new(TransactionType::Dispute, 1, 1)
+                .amount(amount!(50))
+                .build(),
+        );
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::TransactionError(_)
+        ));
+
+        // without an amount, a dispute takes whatever is left
+        engine
+            .process_tx(&TxBuilder::dispute(1, 1).build())
+            .unwrap();
+        let balance = engine.accounts[&1].balance(DEFAULT_CURRENCY);
+        assert_eq!(
+            (balance.available, balance.held),
+            (amount!(60), amount!(40))
+        );
+
+        engine.process_tx(&resolve_tx).unwrap();
+        let result = engine.process_tx(&TxBuilder::dispute(1, 1).build());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_dispute_failure_while_partial_dispute_open() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Dispute, 1, 1)
+                    .amount(amount!(10))
+                    .build(),
+            )
+            .unwrap();
+        let result = engine.process_tx(
+            &TxBuilder::new(TransactionType::Dispute, 1, 1)
+                .amount(amount!(10))
+                .build(),
+        );
+
+        assert!(result.is_err());
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(10)
+        );
+    }
+
+    #[test]
+    fn test_refund_success() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Refund, 1, 1)
+                    .amount(amount!(30))
+                    .build(),
+            )
+            .unwrap();
+
+        let account = &engine.accounts[&1];
+        let balance = account.balance(DEFAULT_CURRENCY);
+        assert_eq!(
+            (balance.available, balance.held, balance.total),
+            (amount!(70), amount!(0), amount!(70))
+        );
+        assert!(!account.is_locked());
+        assert_eq!(
+            engine.transactions.get(1).unwrap().unwrap().dispute_status,
+            DisputeStatus::Undisputed
+        );
+    }
+
+    #[test]
+    fn test_refunds_cannot_exceed_original() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(&TxBuilder::deposit(1, 2, amount!(500)).build())
+            .unwrap();
+
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Refund, 1, 1)
+                    .amount(amount!(60))
+                    .build(),
+            )
+            .unwrap();
+        let result = engine.process_tx(
+            &TxBuilder::new(TransactionType::Refund, 1, 1)
+                .amount(amount!(50))
+                .build(),
+        );
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::TransactionError(_)
+        ));
+
+        // without an amount, a refund pays back whatever is left, which can't be disputed after
+        engine
+            .process_tx(&TxBuilder::new(TransactionType::Refund, 1, 1).build())
+            .unwrap();
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(500)
+        );
+        assert!(
+            engine
+                .process_tx(&TxBuilder::new(TransactionType::Refund, 1, 1).build())
+                .is_err()
+        );
+        assert!(
+            engine
+                .process_tx(&TxBuilder::dispute(1, 1).build())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_refund_after_resolved_dispute() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(&TxBuilder::dispute(1, 1).build())
+            .unwrap();
+        // the open dispute holds all of it
+        assert!(
+            engine
+                .process_tx(
+                    &TxBuilder::new(TransactionType::Refund, 1, 1)
+                        .amount(amount!(40))
+                        .build()
+                )
+                .is_err()
+        );
+        engine
+            .process_tx(&TxBuilder::resolve(1, 1).build())
+            .unwrap();
+
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Refund, 1, 1)
+                    .amount(amount!(40))
+                    .build(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(60)
+        );
+        let record = engine.transactions.get(1).unwrap().unwrap();
+        assert_eq!(
+            (record.amount, record.refunded, record.disputable),
+            (amount!(100), amount!(40), amount!(0))
+        );
+        let result = engine.process_tx(
+            &TxBuilder::new(TransactionType::Refund, 1, 1)
+                .amount(amount!(61))
+                .build(),
+        );
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::TransactionError(_)
+        ));
+        engine
+            .process_tx(&TxBuilder::new(TransactionType::Refund, 1, 1).build())
+            .unwrap();
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(0)
+        );
+    }
+
+    #[test]
+    fn test_refund_caps_later_disputes() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Dispute, 1, 1)
+                    .amount(amount!(30))
+                    .build(),
+            )
+            .unwrap();
+
+        // all but the disputed 30 can be refunded while the dispute is open
+        engine
+            .process_tx(&TxBuilder::new(TransactionType::Refund, 1, 1).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::resolve(1, 1).build())
+            .unwrap();
+
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(30)
+        );
+        // refunded funds can't be disputed, but the resolved 30 can still be refunded
+        assert!(
+            engine
+                .process_tx(&TxBuilder::dispute(1, 1).build())
+                .is_err()
+        );
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Refund, 1, 1)
+                    .amount(amount!(30))
+                    .build(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_refund_failure_not_deposit() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(&TxBuilder::withdrawal(1, 2, amount!(10)).build())
+            .unwrap();
+
+        let result = engine.process_tx(&TxBuilder::new(TransactionType::Refund, 1, 2).build());
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::TransactionError(_)
+        ));
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(90)
+        );
+    }
+
+    #[test]
+    fn test_adjustment_success() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(10));
+        engine
+            .process_tx(&TxBuilder::new(TransactionType::Freeze, 1, 0).build())
+            .unwrap();
+
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Adjustment, 1, 2)
+                    .amount(amount!(-25))
+                    .reason("correction")
+                    .build(),
+            )
+            .unwrap();
+
+        // corrections go through on a frozen account and may leave `available` negative
+        let balance = engine.accounts[&1].balance(DEFAULT_CURRENCY);
+        assert_eq!(
+            (balance.available, balance.total),
+            (amount!(-15), amount!(-15))
+        );
+        let dispute = engine.process_tx(&TxBuilder::dispute(1, 2).build());
+        assert!(dispute.is_err());
+    }
+
+    #[test]
+    fn test_adjustment_failure_without_reason() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(10));
+
+        let result = engine.process_tx(
+            &TxBuilder::new(TransactionType::Adjustment, 1, 2)
+                .amount(amount!(5))
+                .build(),
+        );
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::TransactionError(_)
+        ));
+        assert!(!engine.transactions.contains(2).unwrap());
+    }
+
+    #[test]
+    fn test_reversal_success() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(&TxBuilder::withdrawal(1, 2, amount!(30)).build())
+            .unwrap();
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Refund, 1, 1)
+                    .amount(amount!(20))
+                    .build(),
+            )
+            .unwrap();
+
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Reversal, 1, 2)
+                    .reason("correction")
+                    .build(),
+            )
+            .unwrap();
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(80)
+        );
+        // only what's left of the deposit after its refund comes back out
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Reversal, 1, 1)
+                    .reason("correction")
+                    .build(),
+            )
+            .unwrap();
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(0)
+        );
+        assert!(!engine.accounts[&1].is_locked());
+
+        // a reversal is final
+        for tx_type in [TransactionType::Reversal, TransactionType::Dispute] {
+            assert!(
+                engine
+                    .process_tx(&TxBuilder::new(tx_type, 1, 1).build())
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_reversal_after_resolved_dispute() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        for tx in [
+            TxBuilder::new(TransactionType::Dispute, 1, 1)
+                .amount(amount!(40))
+                .build(),
+            TxBuilder::resolve(1, 1).build(),
+            TxBuilder::new(TransactionType::Refund, 1, 1)
+                .amount(amount!(25))
+                .build(),
+            TxBuilder::new(TransactionType::Dispute, 1, 1)
+                .amount(amount!(10))
+                .build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+        // an open dispute has to be settled first
+        let result = engine.process_tx(
+            &TxBuilder::new(TransactionType::Reversal, 1, 1)
+                .reason("correction")
+                .build(),
+        );
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::TransactionError(_)
+        ));
+        engine
+            .process_tx(&TxBuilder::resolve(1, 1).build())
+            .unwrap();
+
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Reversal, 1, 1)
+                    .reason("correction")
+                    .build(),
+            )
+            .unwrap();
+
+        // the whole deposit less its refund comes back out
+        let balance = engine.accounts[&1].balance(DEFAULT_CURRENCY);
+        assert_eq!(
+            (balance.available, balance.held, balance.total),
+            (amount!(0), amount!(0), amount!(0))
+        );
+        assert!(engine.check_ledger().is_ok());
+    }
+
+    #[test]
+    fn test_builder_negative_available_reject_corrections() {
+        let mut engine = PaymentsEngine::builder()
+            .negative_available_policy(NegativeAvailablePolicy::Reject)
+            .build();
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(100)).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::withdrawal(1, 2, amount!(80)).build())
+            .unwrap();
+
+        let result = engine.process_tx(
+            &TxBuilder::new(TransactionType::Reversal, 1, 1)
+                .reason("correction")
+                .build(),
+        );
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::InsufficientFunds(_)
+        ));
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(20)
+        );
+    }
+
+    #[test]
+    fn test_check_correction_funds_failure_out_of_range() {
+        let mut account = Account::new(1);
+        account
+            .balances
+            .entry(DEFAULT_CURRENCY.to_string())
+            .or_default()
+            .available = amount!(-1);
+
+        let result = PaymentsEngine::check_correction_funds(
+            NegativeAvailablePolicy::Reject,
+            &account,
+            DEFAULT_CURRENCY,
+            Amount::MIN,
+        );
+
+        assert!(matches!(result, Err(Error::InsufficientFunds(_))));
+    }
+
+    #[test]
+    fn test_authorize_capture_success() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Authorize, 1, 2)
+                    .amount(amount!(40))
+                    .build(),
+            )
+            .unwrap();
+        let balance = engine.accounts[&1].balance(DEFAULT_CURRENCY);
+        assert_eq!(
+            (balance.available, balance.held, balance.total),
+            (amount!(60), amount!(40), amount!(100))
+        );
+
+        // capturing less than was authorized releases the rest
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Capture, 1, 2)
+                    .amount(amount!(25))
+                    .build(),
+            )
+            .unwrap();
+        let balance = engine.accounts[&1].balance(DEFAULT_CURRENCY);
+        assert_eq!(
+            (balance.available, balance.held, balance.total),
+            (amount!(75), amount!(0), amount!(75))
+        );
+
+        // the captured authorization is disputed like a withdrawal of what was captured
+        engine
+            .process_tx(&TxBuilder::dispute(1, 2).build())
+            .unwrap();
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(25)
+        );
+    }
+
+    #[test]
+    fn test_authorize_void_success() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Authorize, 1, 2)
+                    .amount(amount!(40))
+                    .build(),
+            )
+            .unwrap();
+
+        engine
+            .process_tx(&TxBuilder::new(TransactionType::Void, 1, 2).build())
+            .unwrap();
+
+        let balance = engine.accounts[&1].balance(DEFAULT_CURRENCY);
+        assert_eq!(
+            (balance.available, balance.held, balance.total),
+            (amount!(100), amount!(0), amount!(100))
+        );
+        for tx_type in [
+            TransactionType::Capture,
+            TransactionType::Void,
+            TransactionType::Dispute,
+        ] {
+            assert!(
+                engine
+                    .process_tx(&TxBuilder::new(tx_type, 1, 2).build())
+                    .is_err()
+            );
+        }
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).available,
+            amount!(100)
+        );
+    }
+
+    #[test]
+    fn test_authorize_failure_insufficient_funds() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(10));
+
+        let result = engine.process_tx(
+            &TxBuilder::new(TransactionType::Authorize, 1, 2)
+                .amount(amount!(20))
+                .build(),
+        );
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::InsufficientFunds(_)
+        ));
+        assert!(!engine.transactions.contains(2).unwrap());
+    }
+
+    #[test]
+    fn test_capture_failure_exceeds_authorization() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(
+                &TxBuilder::new(TransactionType::Authorize, 1, 2)
+                    .amount(amount!(40))
+                    .build(),
+            )
+            .unwrap();
+
+        let over = engine.process_tx(
+            &TxBuilder::new(TransactionType::Capture, 1, 2)
+                .amount(amount!(50))
+                .build(),
+        );
+        let deposit = engine.process_tx(&TxBuilder::new(TransactionType::Capture, 1, 1).build());
+
+        assert!(over.is_err());
+        assert!(deposit.is_err());
+        assert_eq!(
+            engine.accounts[&1].balance(DEFAULT_CURRENCY).held,
+            amount!(40)
+        );
+    }
+
+    #[test]
+    fn test_freeze_blocks_withdrawals() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+
+        engine
+            .process_tx(&TxBuilder::new(TransactionType::Freeze, 1, 0).build())
+            .unwrap();
+
+        assert!(
+            engine
+                .process_tx(&TxBuilder::deposit(1, 2, amount!(5)).build())
+                .is_ok()
+        );
+        assert!(
+            engine
+                .process_tx(&TxBuilder::withdrawal(1, 3, amount!(5)).build())
+                .is_err()
+        );
+        assert_eq!(engine.account(1).unwrap().status, AccountStatus::Frozen);
+    }
+
+    #[test]
+    fn test_unlock_after_chargeback() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(&TxBuilder::dispute(1, 1).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::chargeback(1, 1).build())
+            .unwrap();
+
+        engine
+            .process_tx(&TxBuilder::new(TransactionType::Unlock, 1, 0).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::deposit(1, 2, amount!(5)).build())
+            .unwrap();
+
+        let account = engine.account(1).unwrap();
+        assert!(!account.is_locked());
+        assert_eq!(account.balance(DEFAULT_CURRENCY).total, amount!(5));
+    }
+
+    #[test]
+    fn test_close_rejects_further_activity() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(&TxBuilder::withdrawal(1, 2, amount!(100)).build())
+            .unwrap();
+
+        engine
+            .process_tx(&TxBuilder::new(TransactionType::Close, 1, 0).build())
+            .unwrap();
+        let result = engine.process_tx(&TxBuilder::deposit(1, 3, amount!(5)).build());
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::AccountClosed(_)
+        ));
+    }
+
+    #[test]
+    fn test_admin_failure_unknown_account() {
+        let mut engine = PaymentsEngine::new();
+
+        let result = engine.process_tx(&TxBuilder::new(TransactionType::Unlock, 1, 0).build());
+
+        assert!(result.is_err());
+        assert!(engine.account(1).is_none());
+    }
+
+    #[test]
+    fn test_merge_accounts_success() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(&TxBuilder::deposit(2, 2, amount!(50)).build())
+            .unwrap();
+
+        engine.merge_accounts(2, 1).unwrap();
+
+        assert!(!engine.accounts.contains_key(&2));
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(150));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).total, amount!(150));
+        assert_eq!(engine.transactions.get(2).unwrap().unwrap().account_id, 1);
+    }
+
+    #[test]
+    fn test_merge_accounts_preserves_dispute_references() {
+        let mut engine = new_engine_with_deposit(2, 1, amount!(100));
+        engine.merge_accounts(2, 1).unwrap();
+        let dispute_tx = TxBuilder::dispute(1, 1).build();
+
+        engine.process_tx(&dispute_tx).unwrap();
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(0));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(100));
+    }
+
+    #[test]
+    fn test_merge_accounts_failure_missing_source() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+
+        let result = engine.merge_accounts(2, 1);
+
+        assert!(result.is_err());
+        assert_eq!(
+            engine
+                .accounts
+                .get(&1)
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .total,
+            amount!(100)
+        );
+    }
+
+    #[test]
+    fn test_merge_accounts_failure_same_account() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+
+        let result = engine.merge_accounts(1, 1);
+
+        assert!(result.is_err());
+        assert!(engine.accounts.contains_key(&1));
+    }
+
+    #[test]
+    fn test_merge_state_success() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(&TxBuilder::dispute(1, 1).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::chargeback(1, 1).build())
+            .unwrap();
+        let shard = new_engine_with_deposit(2, 2, amount!(50));
+
+        engine.merge_state(shard).unwrap();
+
+        engine.check_ledger().unwrap();
+        assert_eq!(
+            engine
+                .ledger()
+                .balance(DEFAULT_CURRENCY, LedgerAccount::Suspense),
+            amount!(-150)
+        );
+        // the shard's stored transactions can still be disputed
+        engine
+            .process_tx(&TxBuilder::dispute(2, 2).build())
+            .unwrap();
+        let account = engine.accounts.get(&2).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(50));
+        assert!(engine.accounts.get(&1).unwrap().is_locked());
+    }
+
+    #[test]
+    fn test_merge_state_failure() {
+        for shard in [
+            new_engine_with_deposit(1, 2, amount!(50)),
+            new_engine_with_deposit(2, 1, amount!(50)),
+        ] {
+            let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+
+            let result = engine.merge_state(shard);
+
+            assert!(matches!(result, Err(Error::StateConflict(_))));
+            assert_eq!(engine.accounts().count(), 1);
+            assert_eq!(
+                engine
+                    .accounts
+                    .get(&1)
+                    .unwrap()
+                    .balance(DEFAULT_CURRENCY)
+                    .total,
+                amount!(100)
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_state_failure_evicted_tx() {
+        let mut shard = PaymentsEngine::builder()
+            .eviction_policy(EvictionPolicy::Settled)
+            .build();
+        for tx in [
+            TxBuilder::deposit(2, 1, amount!(50)).build(),
+            TxBuilder::dispute(2, 1).build(),
+            TxBuilder::chargeback(2, 1).build(),
+        ] {
+            shard.process_tx(&tx).unwrap();
+        }
+        assert!(shard.transactions.is_evicted(1));
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+
+        let result = engine.merge_state(shard);
+
+        assert!(matches!(result, Err(Error::StateConflict(message)) if message.contains("tx 1")));
+        assert_eq!(engine.accounts().count(), 1);
+    }
+
+    #[test]
+    fn test_provisional_clear_success() {
+        let mut engine = PaymentsEngine::new();
+        let provisional_tx = TxBuilder::new(TransactionType::Provisional, 1, 1)
+            .amount(amount!(100))
+            .build();
+        let clear_tx = TxBuilder::new(TransactionType::Clear, 1, 1).build();
+
+        engine.process_tx(&provisional_tx).unwrap();
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(0));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(100));
+
+        engine.process_tx(&clear_tx).unwrap();
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(100));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(0));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).total, amount!(100));
+    }
+
+    #[test]
+    fn test_clear_failure_twice() {
+        let mut engine = PaymentsEngine::new();
+        let provisional_tx = TxBuilder::new(TransactionType::Provisional, 1, 1)
+            .amount(amount!(100))
+            .build();
+        let clear_tx = TxBuilder::new(TransactionType::Clear, 1, 1).build();
+
+        engine.process_tx(&provisional_tx).unwrap();
+        engine.process_tx(&clear_tx).unwrap();
+        let result = engine.process_tx(&clear_tx);
+
+        assert!(result.is_err());
+        assert_eq!(
+            engine
+                .accounts
+                .get(&1)
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .available,
+            amount!(100)
+        );
+    }
+
+    #[test]
+    fn test_clear_failure_not_provisional() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let clear_tx = TxBuilder::new(TransactionType::Clear, 1, 1).build();
+
+        let result = engine.process_tx(&clear_tx);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_dispute_failure_uncleared_provisional() {
+        let mut engine = PaymentsEngine::new();
+        let provisional_tx = TxBuilder::new(TransactionType::Provisional, 1, 1)
+            .amount(amount!(100))
+            .build();
+        let dispute_tx = TxBuilder::dispute(1, 1).build();
+
+        engine.process_tx(&provisional_tx).unwrap();
+        let result = engine.process_tx(&dispute_tx);
+
+        assert!(result.is_err());
+        assert_eq!(
+            engine
+                .accounts
+                .get(&1)
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .held,
+            amount!(100)
+        );
+    }
+
+    #[test]
+    fn test_dispute_sets_status() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let dispute_tx = TxBuilder::dispute(1, 1).build();
+
+        engine.process_tx(&dispute_tx).unwrap();
+
+        let tx_info = engine.transactions.get(1).unwrap().unwrap();
+        assert_eq!(tx_info.dispute_status, DisputeStatus::Disputed);
+    }
+
+    #[test]
+    fn test_dispute_failure_double_dispute() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        engine
+            .process_tx(&TxBuilder::deposit(1, 2, amount!(100)).build())
+            .unwrap();
+        let dispute_tx = TxBuilder::dispute(1, 1).build();
+
+        engine.process_tx(&dispute_tx).unwrap();
+        let result = engine.process_tx(&dispute_tx);
+
+        assert!(result.is_err());
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(100));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(100));
+    }
+
+    #[test]
+    fn test_resolve_failure_without_dispute() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let resolve_tx = TxBuilder::resolve(1, 1).build();
+
+        let result = engine.process_tx(&resolve_tx);
+
+        assert!(result.is_err());
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(100));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(0));
+    }
+
+    #[test]
+    fn test_resolve_failure_already_resolved() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let dispute_tx = TxBuilder::dispute(1, 1).build();
+        let resolve_tx = TxBuilder::resolve(1, 1).build();
+
+        engine.process_tx(&dispute_tx).unwrap();
+        engine.process_tx(&resolve_tx).unwrap();
+        let result = engine.process_tx(&resolve_tx);
+
+        assert!(result.is_err());
+        assert_eq!(
+            engine.transactions.get(1).unwrap().unwrap().dispute_status,
+            DisputeStatus::Resolved
+        );
+    }
+
+    #[test]
+    fn test_dispute_failure_after_resolve() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let dispute_tx = TxBuilder::dispute(1, 1).build();
+        let resolve_tx = TxBuilder::resolve(1, 1).build();
+
+        engine.process_tx(&dispute_tx).unwrap();
+        engine.process_tx(&resolve_tx).unwrap();
+        let result = engine.process_tx(&dispute_tx);
+
+        assert!(result.is_err());
+        assert_eq!(
+            engine
+                .accounts
+                .get(&1)
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .held,
+            amount!(0)
+        );
+    }
+
+    #[test]
+    fn test_chargeback_failure_without_dispute() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let chargeback_tx = TxBuilder::chargeback(1, 1).build();
+
+        let result = engine.process_tx(&chargeback_tx);
+
+        assert!(result.is_err());
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).total, amount!(100));
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_chargeback_failure_archive_leaves_account_active() {
+        // fails every write, so nothing settled can be evicted
+        struct FailingArchive;
+
+        impl ArchiveSink for FailingArchive {
+            fn archive(&mut self, _tx: u32, _record: &TxRecord) -> Result<()> {
+                Err(Error::StoreError("archive unavailable".to_string()))
+            }
+        }
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentsEngine::builder()
+            .eviction_policy(EvictionPolicy::Settled)
+            .archive_sink(Box::new(FailingArchive))
+            .event_sink(Box::new(sender))
+            .build();
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(100)).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::dispute(1, 1).build())
+            .unwrap();
+        receiver.try_iter().for_each(drop);
+
+        let result = engine.process_tx(&TxBuilder::chargeback(1, 1).build());
+
+        assert!(matches!(result.unwrap_err().root(), Error::StoreError(_)));
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.status, AccountStatus::Active);
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(100));
+        assert_eq!(
+            engine.transactions.get(1).unwrap().unwrap().dispute_status,
+            DisputeStatus::Disputed
+        );
+        assert_eq!(receiver.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_dispute_withdrawal_credits_held() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let withdrawal_tx = TxBuilder::withdrawal(1, 2, amount!(40)).build();
+        let dispute_tx = TxBuilder::dispute(1, 2).build();
+
+        engine.process_tx(&withdrawal_tx).unwrap();
+        engine.process_tx(&dispute_tx).unwrap();
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(60));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(40));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).total, amount!(100));
+    }
+
+    #[test]
+    fn test_resolve_withdrawal_dispute_upholds_withdrawal() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let withdrawal_tx = TxBuilder::withdrawal(1, 2, amount!(40)).build();
+        let dispute_tx = TxBuilder::dispute(1, 2).build();
+        let resolve_tx = TxBuilder::resolve(1, 2).build();
+
+        engine.process_tx(&withdrawal_tx).unwrap();
+        engine.process_tx(&dispute_tx).unwrap();
+        engine.process_tx(&resolve_tx).unwrap();
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(60));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(0));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).total, amount!(60));
+    }
+
+    #[test]
+    fn test_chargeback_withdrawal_returns_funds() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let withdrawal_tx = TxBuilder::withdrawal(1, 2, amount!(40)).build();
+        let dispute_tx = TxBuilder::dispute(1, 2).build();
+        let chargeback_tx = TxBuilder::chargeback(1, 2).build();
+
+        engine.process_tx(&withdrawal_tx).unwrap();
+        engine.process_tx(&dispute_tx).unwrap();
+        engine.process_tx(&chargeback_tx).unwrap();
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(100));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(0));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).total, amount!(100));
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_duplicate_deposit_rejected() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let deposit_tx = TxBuilder::deposit(1, 1, amount!(100)).build();
+
+        let result = engine.process_tx(&deposit_tx);
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::DuplicateTransaction(1)
+        ));
+        assert_eq!(
+            engine
+                .accounts
+                .get(&1)
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .total,
+            amount!(100)
+        );
+    }
+
+    #[test]
+    fn test_duplicate_withdrawal_does_not_overwrite_record() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(100));
+        let withdrawal_tx = TxBuilder::withdrawal(1, 1, amount!(10)).build();
+
+        assert!(engine.process_tx(&withdrawal_tx).is_err());
+
+        let tx_info = engine.transactions.get(1).unwrap().unwrap();
+        assert!(matches!(tx_info.tx_type, TransactionType::Deposit));
+        assert_eq!(tx_info.disputable, amount!(100));
+    }
+
+    #[test]
+    fn test_duplicate_deposit_skipped() {
+        let mut engine = PaymentsEngine::new().with_duplicate_policy(DuplicatePolicy::Skip);
+        let deposit_tx = TxBuilder::deposit(1, 1, amount!(100)).build();
+
+        engine.process_tx(&deposit_tx).unwrap();
+        engine.process_tx(&deposit_tx).unwrap();
+
+        assert_eq!(
+            engine
+                .accounts
+                .get(&1)
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .total,
+            amount!(100)
+        );
+    }
+
+    #[test]
+    fn test_failed_tx_id_can_be_reused() {
+        let mut engine = new_engine_with_deposit(1, 1, amount!(10));
+        let withdrawal_tx = TxBuilder::withdrawal(1, 2, amount!(50)).build();
+        let deposit_tx = TxBuilder::deposit(1, 2, amount!(50)).build();
+
+        assert!(engine.process_tx(&withdrawal_tx).is_err());
+        engine.process_tx(&deposit_tx).unwrap();
+
+        assert_eq!(
+            engine
+                .accounts
+                .get(&1)
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .total,
+            amount!(60)
+        );
+    }
+
+    #[test]
+    fn test_dispute_only_affects_original_currency() {
+        let mut engine = PaymentsEngine::new();
+        let mut usd_deposit = TxBuilder::deposit(1, 1, amount!(100)).build();
+        usd_deposit.currency = Some("USD".to_string());
+        let mut eur_deposit = TxBuilder::deposit(1, 2, amount!(30)).build();
+        eur_deposit.currency = Some("EUR".to_string());
+        engine.process_tx(&usd_deposit).unwrap();
+        engine.process_tx(&eur_deposit).unwrap();
+
+        // the dispute row doesn't need to repeat the currency
+        let dispute_tx = TxBuilder::dispute(1, 2).build();
+        engine.process_tx(&dispute_tx).unwrap();
+
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.balance("EUR").held, amount!(30));
+        assert_eq!(account.balance("USD").available, amount!(100));
+        assert_eq!(account.balance("USD").held, amount!(0));
+    }
+
+    #[test]
+    fn test_dispute_failure_currency_mismatch() {
+        let mut engine = PaymentsEngine::new();
+        let mut deposit_tx = TxBuilder::deposit(1, 1, amount!(100)).build();
+        deposit_tx.currency = Some("USD".to_string());
+        engine.process_tx(&deposit_tx).unwrap();
+        let mut dispute_tx = TxBuilder::dispute(1, 1).build();
+        dispute_tx.currency = Some("EUR".to_string());
+
+        let result = engine.process_tx(&dispute_tx);
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::TransactionError(_)
+        ));
+        assert_eq!(engine.account(1).unwrap().balance("USD").held, amount!(0));
+    }
+
+    // arbitrary rows over a few clients and tx ids, so that disputes, resolves and chargebacks
+    // often find their tx--and amounts that are missing, zero or negative as often as not. Every
+    // row carries a reason, so adjustments can apply
+    fn arbitrary_tx() -> impl Strategy<Value = Transaction> {
+        let tx_type = prop_oneof![
+            Just(TransactionType::Adjustment),
+            Just(TransactionType::Authorize),
+            Just(TransactionType::Capture),
+            Just(TransactionType::Chargeback),
+            Just(TransactionType::Clear),
+            Just(TransactionType::Close),
+            Just(TransactionType::Deposit),
+            Just(TransactionType::Dispute),
+            Just(TransactionType::Freeze),
+            Just(TransactionType::Provisional),
+            Just(TransactionType::Refund),
+            Just(TransactionType::Resolve),
+            Just(TransactionType::Reversal),
+            Just(TransactionType::Unlock),
+            Just(TransactionType::Void),
+            Just(TransactionType::Withdrawal),
+        ];
+        let amount = prop_oneof![
+            Just(None),
+            (-1_000i64..100_000).prop_map(|cents| Some(Amount::new(cents, 2))),
+        ];
+        (tx_type, 1..=3u16, 1..=12u32, amount).prop_map(|(tx_type, account_id, tx_id, amount)| {
+            Transaction {
+                amount,
+                ..TxBuilder::new(tx_type, account_id, tx_id)
+                    .reason("correction")
+                    .build()
+            }
+        })
+    }
+
+    // failed rows can leave a zero balance behind, which makes no difference to the funds
+    fn normalized(account: &Account) -> Account {
+        let mut account = account.clone();
+        account
+            .balances
+            .retain(|_, balance| *balance != Balance::default());
+        account
+    }
+
+    // locked accounts keep their funds, bar corrections made by operations
+    fn check_invariants(
+        tx: &Transaction,
+        before: &HashMap<u16, Account>,
+        after: &HashMap<u16, Account>,
+    ) {
+        let correction = matches!(
+            tx.tx_type,
+            TransactionType::Adjustment | TransactionType::Reversal
+        );
+        for account in after.values() {
+            for balance in account.balances.values() {
+                assert_eq!(balance.total, balance.available + balance.held);
+                assert!(balance.held >= Amount::ZERO);
+            }
+            if let Some(previous) = before.get(&account.id)
+                && previous.is_locked()
+                && !correction
+            {
+                assert_eq!(account.balances, previous.balances);
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_invariants_hold_after_every_step(
+            txs in proptest::collection::vec(arbitrary_tx(), 1..60)
+        ) {
+            let mut engine = PaymentsEngine::new();
+            for tx in &txs {
+                let before = engine.accounts.clone();
+                if engine.process_tx(tx).is_err() {
+                    // apart from creating the client's account on first sight
+                    for (id, account) in &engine.accounts {
+                        let previous = before.get(id).cloned().unwrap_or(Account::new(*id));
+                        prop_assert_eq!(normalized(account), normalized(&previous));
+                    }
+                }
+                check_invariants(tx, &before, &engine.accounts);
+                prop_assert!(engine.check_ledger().is_ok());
+            }
+        }
+
+        #[test]
+        fn test_same_input_yields_same_state(
+            txs in proptest::collection::vec(arbitrary_tx(), 1..60)
+        ) {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let mut first = PaymentsEngine::builder()
+                .event_sink(Box::new(sender))
+                .build();
+            let mut second = PaymentsEngine::new();
+            for tx in &txs {
+                let _ = first.process_tx(tx);
+                let _ = second.process_tx(tx);
+            }
+            let mut log = Vec::new();
+            let mut sink = crate::JsonlSink::new(&mut log);
+            for event in receiver.try_iter() {
+                sink.emit(&event).unwrap();
+            }
+
+            let replayed = PaymentsEngine::replay(log.as_slice()).unwrap();
+
+            prop_assert_eq!(&first.accounts, &second.accounts);
+            prop_assert_eq!(replayed.ledger(), first.ledger());
+            // the event log holds the same state, minus accounts that never saw a change
+            for (id, account) in &first.accounts {
+                let replayed_account = replayed
+                    .accounts
+                    .get(id)
+                    .cloned()
+                    .unwrap_or(Account::new(*id));
+                prop_assert_eq!(normalized(&replayed_account), normalized(account));
+            }
+        }
     }
 }
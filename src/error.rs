@@ -1,15 +1,422 @@
+use std::fmt;
+use std::str::FromStr;
+
 use thiserror::Error;
 
+use crate::transaction::{Transaction, TransactionType};
+
+/// Result type returned throughout the engine.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Errors produced while ingesting or applying transactions.
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("AccountClosed: {:?}", .0)]
+    AccountClosed(&'static str),
     #[error("AccountError: {:?}", .0)]
     AccountError(&'static str),
-    #[error("CSV error: {:?}", .0)]
+    #[error("AccountLocked: {:?}", .0)]
+    AccountLocked(&'static str),
+    #[error("AmountAboveMaximum: {:?}", .0)]
+    AmountAboveMaximum(&'static str),
+    #[error("AmountBelowMinimum: {:?}", .0)]
+    AmountBelowMinimum(&'static str),
+    #[error("AuditError: {:?}", .0)]
+    AuditError(String),
+    #[error("ConfigError: {:?}", .0)]
+    ConfigError(String),
+    #[error("CSV error: {}", .0)]
     Csv(#[from] csv::Error),
+    #[error("DisputeWindowExpired: tx {} is too old to dispute.", .0)]
+    DisputeWindowExpired(u32),
+    #[error("DuplicateTransaction: tx {} has already been processed.", .0)]
+    DuplicateTransaction(u32),
+    #[error("EngineError: {:?}", .0)]
+    EngineError(&'static str),
+    #[error("EventError: {:?}", .0)]
+    EventError(String),
+    #[error("InsufficientFunds: {:?}", .0)]
+    InsufficientFunds(&'static str),
     #[error("IoError: {:?}", .0)]
     Io(#[from] std::io::Error),
+    #[error("LimitExceeded: {:?}", .0)]
+    LimitExceeded(&'static str),
+    #[error("ManifestError: {:?}", .0)]
+    ManifestError(String),
+    #[error("RiskRejected: {:?}", .0)]
+    RiskRejected(String),
+    #[error("RuleError: {:?}", .0)]
+    RuleError(String),
+    #[error("SchemaError: {:?}", .0)]
+    SchemaError(String),
+    #[error("SignatureError: {:?}", .0)]
+    SignatureError(&'static str),
+    #[error("SnapshotError: {:?}", .0)]
+    SnapshotError(String),
+    #[error("StateConflict: {:?}", .0)]
+    StateConflict(String),
+    #[error("StoreError: {:?}", .0)]
+    StoreError(String),
     #[error("TransactionError: {:?}", .0)]
     TransactionError(&'static str),
+    #[error("UnknownTransaction: tx {} does not exist.", .0)]
+    UnknownTransaction(u32),
+    #[error("WalError: {:?}", .0)]
+    WalError(String),
+    /// Another error, annotated with the transaction/input row it occurred on.
+    #[error("{error} ({context})")]
+    WithContext {
+        context: ErrorContext,
+        error: Box<Error>,
+    },
+}
+
+/// Stable, machine-readable identifier for each kind of [`Error`], for logs and reject files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    Account,
+    AccountClosed,
+    AccountLocked,
+    AmountAboveMaximum,
+    AmountBelowMinimum,
+    Audit,
+    Config,
+    DisputeWindowExpired,
+    DuplicateTransaction,
+    Engine,
+    Event,
+    InsufficientFunds,
+    InvalidRow,
+    InvalidSignature,
+    InvalidTransaction,
+    Io,
+    LimitExceeded,
+    Manifest,
+    RiskRejected,
+    RuleRejected,
+    Schema,
+    Snapshot,
+    StateConflict,
+    Store,
+    UnknownTransaction,
+    Wal,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorCode::Account => "account",
+            ErrorCode::AccountClosed => "account-closed",
+            ErrorCode::AccountLocked => "account-locked",
+            ErrorCode::AmountAboveMaximum => "amount-above-maximum",
+            ErrorCode::AmountBelowMinimum => "amount-below-minimum",
+            ErrorCode::Audit => "audit",
+            ErrorCode::Config => "config",
+            ErrorCode::DisputeWindowExpired => "dispute-window-expired",
+            ErrorCode::DuplicateTransaction => "duplicate-transaction",
+            ErrorCode::Engine => "engine",
+            ErrorCode::Event => "event",
+            ErrorCode::InsufficientFunds => "insufficient-funds",
+            ErrorCode::InvalidRow => "invalid-row",
+            ErrorCode::InvalidSignature => "invalid-signature",
+            ErrorCode::InvalidTransaction => "invalid-transaction",
+            ErrorCode::Io => "io",
+            ErrorCode::LimitExceeded => "limit-exceeded",
+            ErrorCode::Manifest => "manifest",
+            ErrorCode::RiskRejected => "risk-rejected",
+            ErrorCode::RuleRejected => "rule-rejected",
+            ErrorCode::Schema => "schema",
+            ErrorCode::Snapshot => "snapshot",
+            ErrorCode::StateConflict => "state-conflict",
+            ErrorCode::Store => "store",
+            ErrorCode::UnknownTransaction => "unknown-transaction",
+            ErrorCode::Wal => "wal",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Where an [`Error`] occurred: the transaction being applied and/or the input row it came
+/// from, as far as they are known.
+#[derive(Debug, Default, Clone)]
+pub struct ErrorContext {
+    pub tx_id: Option<u32>,
+    pub account_id: Option<u16>,
+    pub tx_type: Option<TransactionType>,
+    pub line: Option<u64>,
+    /// Byte offset of the input row, for seeking to it in large files.
+    pub byte: Option<u64>,
+    /// The input row as read, fields joined by `,`.
+    pub record: Option<String>,
+}
+
+impl ErrorContext {
+    /// Context identifying `tx`.
+    pub fn for_tx(tx: &Transaction) -> Self {
+        Self {
+            tx_id: Some(tx.tx_id),
+            account_id: Some(tx.account_id),
+            tx_type: Some(tx.tx_type),
+            ..Self::default()
+        }
+    }
+
+    /// Context identifying an input line.
+    pub fn for_line(line: Option<u64>) -> Self {
+        Self {
+            line,
+            ..Self::default()
+        }
+    }
+
+    /// Context identifying a position in csv input, e.g. of a row that could not be read.
+    pub fn for_position(position: Option<&csv::Position>) -> Self {
+        Self {
+            line: position.map(csv::Position::line),
+            byte: position.map(csv::Position::byte),
+            ..Self::default()
+        }
+    }
+
+    /// Context identifying a csv input row: its position and its raw contents.
+    pub fn for_record(record: &csv::StringRecord) -> Self {
+        Self {
+            record: Some(record.iter().collect::<Vec<_>>().join(",")),
+            ..Self::for_position(record.position())
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tx_id.is_none()
+            && self.account_id.is_none()
+            && self.tx_type.is_none()
+            && self.line.is_none()
+            && self.byte.is_none()
+            && self.record.is_none()
+    }
+
+    // fill in whatever this context doesn't know yet
+    fn merge(&mut self, other: ErrorContext) {
+        self.tx_id = self.tx_id.or(other.tx_id);
+        self.account_id = self.account_id.or(other.account_id);
+        self.tx_type = self.tx_type.or(other.tx_type);
+        self.line = self.line.or(other.line);
+        self.byte = self.byte.or(other.byte);
+        self.record = self.record.take().or(other.record);
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        match (self.line, self.byte) {
+            (Some(line), Some(byte)) => parts.push(format!("line {} (byte {})", line, byte)),
+            (Some(line), None) => parts.push(format!("line {}", line)),
+            (None, Some(byte)) => parts.push(format!("byte {}", byte)),
+            (None, None) => {}
+        }
+        match (self.tx_type, self.tx_id) {
+            (Some(tx_type), Some(tx_id)) => parts.push(format!("{} tx {}", tx_type, tx_id)),
+            (None, Some(tx_id)) => parts.push(format!("tx {}", tx_id)),
+            (Some(tx_type), None) => parts.push(tx_type.to_string()),
+            (None, None) => {}
+        }
+        if let Some(account_id) = self.account_id {
+            parts.push(format!("client {}", account_id));
+        }
+        if let Some(record) = &self.record {
+            parts.push(format!("row `{}`", record));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Coarse grouping of [`Error`]s that handling policies can be attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    Parse,
+    InsufficientFunds,
+    LockedAccount,
+    UnknownReference,
+    Duplicate,
+    AmountLimit,
+    Other,
+}
+
+impl FromStr for ErrorCategory {
+    type Err = String;
+
+    // kebab-case names, as used on the command line
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "parse" => Ok(ErrorCategory::Parse),
+            "insufficient-funds" => Ok(ErrorCategory::InsufficientFunds),
+            "locked-account" => Ok(ErrorCategory::LockedAccount),
+            "unknown-reference" => Ok(ErrorCategory::UnknownReference),
+            "duplicate" => Ok(ErrorCategory::Duplicate),
+            "amount-limit" => Ok(ErrorCategory::AmountLimit),
+            "other" => Ok(ErrorCategory::Other),
+            _ => Err(format!("unknown error category `{s}`")),
+        }
+    }
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorCategory::Parse => "parse",
+            ErrorCategory::InsufficientFunds => "insufficient-funds",
+            ErrorCategory::LockedAccount => "locked-account",
+            ErrorCategory::UnknownReference => "unknown-reference",
+            ErrorCategory::Duplicate => "duplicate",
+            ErrorCategory::AmountLimit => "amount-limit",
+            ErrorCategory::Other => "other",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Error {
+    /// Annotates the error with where it occurred. Context already attached takes precedence,
+    /// so callers further out only fill in what is missing.
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        match self {
+            _ if context.is_empty() => self,
+            Error::WithContext {
+                context: mut existing,
+                error,
+            } => {
+                existing.merge(context);
+                Error::WithContext {
+                    context: existing,
+                    error,
+                }
+            }
+            error => Error::WithContext {
+                context,
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// The underlying error, without any attached context.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::WithContext { error, .. } => error.root(),
+            error => error,
+        }
+    }
+
+    /// Where the error occurred, if known.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Error::WithContext { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The machine-readable code for this kind of error.
+    pub fn code(&self) -> ErrorCode {
+        match self.root() {
+            Error::AccountClosed(_) => ErrorCode::AccountClosed,
+            Error::AccountError(_) => ErrorCode::Account,
+            Error::AccountLocked(_) => ErrorCode::AccountLocked,
+            Error::AmountAboveMaximum(_) => ErrorCode::AmountAboveMaximum,
+            Error::AmountBelowMinimum(_) => ErrorCode::AmountBelowMinimum,
+            Error::AuditError(_) => ErrorCode::Audit,
+            Error::ConfigError(_) => ErrorCode::Config,
+            Error::Csv(_) => ErrorCode::InvalidRow,
+            Error::DisputeWindowExpired(_) => ErrorCode::DisputeWindowExpired,
+            Error::DuplicateTransaction(_) => ErrorCode::DuplicateTransaction,
+            Error::EngineError(_) => ErrorCode::Engine,
+            Error::EventError(_) => ErrorCode::Event,
+            Error::InsufficientFunds(_) => ErrorCode::InsufficientFunds,
+            Error::Io(_) => ErrorCode::Io,
+            Error::LimitExceeded(_) => ErrorCode::LimitExceeded,
+            Error::ManifestError(_) => ErrorCode::Manifest,
+            Error::RiskRejected(_) => ErrorCode::RiskRejected,
+            Error::RuleError(_) => ErrorCode::RuleRejected,
+            Error::SchemaError(_) => ErrorCode::Schema,
+            Error::SignatureError(_) => ErrorCode::InvalidSignature,
+            Error::SnapshotError(_) => ErrorCode::Snapshot,
+            Error::StateConflict(_) => ErrorCode::StateConflict,
+            Error::StoreError(_) => ErrorCode::Store,
+            Error::TransactionError(_) => ErrorCode::InvalidTransaction,
+            Error::UnknownTransaction(_) => ErrorCode::UnknownTransaction,
+            Error::WalError(_) => ErrorCode::Wal,
+            Error::WithContext { .. } => unreachable!("root() never returns a context wrapper"),
+        }
+    }
+
+    /// The category this error falls into.
+    pub fn category(&self) -> ErrorCategory {
+        match self.root() {
+            Error::Csv(_) => ErrorCategory::Parse,
+            Error::InsufficientFunds(_) => ErrorCategory::InsufficientFunds,
+            Error::AccountLocked(_) | Error::AccountClosed(_) => ErrorCategory::LockedAccount,
+            Error::UnknownTransaction(_) => ErrorCategory::UnknownReference,
+            Error::DuplicateTransaction(_) => ErrorCategory::Duplicate,
+            Error::AmountAboveMaximum(_)
+            | Error::AmountBelowMinimum(_)
+            | Error::LimitExceeded(_) => ErrorCategory::AmountLimit,
+            _ => ErrorCategory::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_context_keeps_code_and_category() {
+        let error = Error::InsufficientFunds("").with_context(ErrorContext {
+            tx_id: Some(2),
+            account_id: Some(1),
+            tx_type: Some(TransactionType::Withdrawal),
+            ..ErrorContext::default()
+        });
+
+        assert_eq!(error.code(), ErrorCode::InsufficientFunds);
+        assert_eq!(error.category(), ErrorCategory::InsufficientFunds);
+        assert!(error.to_string().ends_with("(withdrawal tx 2, client 1)"));
+    }
+
+    #[test]
+    fn test_with_context_fills_in_missing_fields() {
+        let error = Error::UnknownTransaction(7)
+            .with_context(ErrorContext {
+                tx_id: Some(7),
+                ..ErrorContext::default()
+            })
+            .with_context(ErrorContext::for_line(Some(4)));
+
+        let context = error.context().unwrap();
+        assert_eq!((context.tx_id, context.line), (Some(7), Some(4)));
+        assert!(matches!(error.root(), Error::UnknownTransaction(7)));
+    }
+
+    #[test]
+    fn test_for_record_display() {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader("type,client,tx,amount\ndeposit, 1, 1, ten\n".as_bytes());
+        let record = rdr.records().next().unwrap().unwrap();
+
+        let error = Error::TransactionError("bad").with_context(ErrorContext::for_record(&record));
+
+        assert!(
+            error
+                .to_string()
+                .ends_with("(line 2 (byte 22), row `deposit,1,1,ten`)")
+        );
+    }
+
+    #[test]
+    fn test_with_context_ignores_empty_context() {
+        let error = Error::TransactionError("").with_context(ErrorContext::for_line(None));
+
+        assert!(error.context().is_none());
+    }
 }
@@ -0,0 +1,348 @@
+use std::io::Write;
+use std::sync::mpsc::Sender;
+
+use crate::amount::Amount;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::AccountStatus,
+    error::{Error, Result},
+    risk::RiskAction,
+    thresholds::ThresholdKind,
+    transaction::TransactionType,
+};
+
+/// A state change the engine made, emitted to the engine's [`EventSink`] once the change has been
+/// applied.
+///
+/// Amounts in the unnamed default currency have an empty `currency`, which is left out when
+/// serialized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A deposit was credited to `available`.
+    Deposited {
+        client: u16,
+        tx: u32,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    /// A provisional deposit was credited to `held` until cleared.
+    ProvisionalDeposited {
+        client: u16,
+        tx: u32,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    /// A provisional deposit cleared, moving its funds from `held` to `available`.
+    DepositCleared {
+        client: u16,
+        tx: u32,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+    },
+    /// A withdrawal was debited from `available`.
+    WithdrawalApplied {
+        client: u16,
+        tx: u32,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    /// `amount` was authorized, moving it from `available` to `held` until captured or voided.
+    Authorized {
+        client: u16,
+        tx: u32,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    /// The authorization `tx` was captured: `amount` left `held` and `total`, and the `released`
+    /// rest of the hold went back to `available`.
+    Captured {
+        client: u16,
+        tx: u32,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+        released: Amount,
+    },
+    /// The authorization `tx` was voided, releasing its `amount` back to `available`.
+    Voided {
+        client: u16,
+        tx: u32,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+    },
+    /// The hold of the authorization `tx` expired, releasing its `amount` back to `available`.
+    HoldExpired {
+        client: u16,
+        tx: u32,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+    },
+    /// The signed `amount` was added to `available` and `total` as a correction.
+    Adjusted {
+        client: u16,
+        tx: u32,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+        reason: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    /// The deposit/withdrawal/adjustment `tx` (of type `tx_type`) was reversed, changing
+    /// `available` and `total` by the signed `amount`.
+    Reversed {
+        client: u16,
+        tx: u32,
+        tx_type: TransactionType,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// A [`CustomHandler`](crate::CustomHandler) applying `tx`, of the custom type `tx_type`,
+    /// credited (or, when negative, debited) the signed `amount` to `available` and `total`.
+    CustomApplied {
+        client: u16,
+        tx: u32,
+        tx_type: String,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    /// The deposit/withdrawal `tx` cost its client a fee of `amount`, moved from the client's
+    /// `available` to the `fee_account`'s.
+    FeeCharged {
+        client: u16,
+        tx: u32,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+        fee_account: u16,
+    },
+    /// Interest of `amount` at `rate` percent a year was credited to `client`'s `available`, as a
+    /// synthetic deposit without a tx id, for the whole days from `from` to `to` (seconds since
+    /// the Unix epoch).
+    InterestAccrued {
+        client: u16,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+        rate: Decimal,
+        from: u64,
+        to: u64,
+    },
+    /// `amount` of the deposit `tx` was refunded, debiting it from `available`.
+    Refunded {
+        client: u16,
+        tx: u32,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+    },
+    /// `amount` of the deposit/withdrawal `tx` (of type `tx_type`) was disputed and is now held.
+    DisputeOpened {
+        client: u16,
+        tx: u32,
+        tx_type: TransactionType,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+    },
+    /// The open dispute on `tx` was resolved, releasing `amount`.
+    DisputeResolved {
+        client: u16,
+        tx: u32,
+        tx_type: TransactionType,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+    },
+    /// The open dispute on `tx` was charged back, reversing `amount`.
+    ChargebackApplied {
+        client: u16,
+        tx: u32,
+        tx_type: TransactionType,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        amount: Amount,
+    },
+    /// The account was locked, e.g. by a chargeback.
+    AccountLocked { client: u16, reason: String },
+    /// An admin operation (freeze, unlock or close) changed the account's status.
+    AccountStatusChanged {
+        client: u16,
+        status: AccountStatus,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// An opening balance was seeded.
+    BalanceSeeded {
+        client: u16,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        available: Amount,
+        held: Amount,
+        total: Amount,
+        status: AccountStatus,
+    },
+    /// Client `source` was merged into client `target`.
+    AccountsMerged { source: u16, target: u16 },
+    /// `tx` tripped the risk rule named `rule`, and was applied flagged or held as `action` says.
+    RiskFlagged {
+        client: u16,
+        tx: u32,
+        rule: String,
+        action: RiskAction,
+    },
+    /// A balance change to `client`'s balance in `currency`, by `tx` if it had one, crossed a
+    /// [`BalanceThresholds`](crate::BalanceThresholds) level: `available` fell below `threshold`
+    /// or `held` rose above it, as `kind` says, to `balance`.
+    BalanceThresholdCrossed {
+        client: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tx: Option<u32>,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        kind: ThresholdKind,
+        threshold: Amount,
+        balance: Amount,
+    },
+}
+
+/// Where the engine emits its [`Event`]s.
+pub trait EventSink {
+    /// Delivers one event. An error here fails the operation that caused the event, although
+    /// the state change itself has already been applied.
+    fn emit(&mut self, event: &Event) -> Result<()>;
+
+    /// Pushes out any buffered events.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes events as JSON lines, e.g. to a file or stdout.
+pub struct JsonlSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonlSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> EventSink for JsonlSink<W> {
+    fn emit(&mut self, event: &Event) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, event)
+            .map_err(|e| Error::EventError(e.to_string()))?;
+        writeln!(self.writer).map_err(|e| Error::EventError(e.to_string()))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .map_err(|e| Error::EventError(e.to_string()))
+    }
+}
+
+/// Sends events to a channel, for consumers in the same process.
+impl EventSink for Sender<Event> {
+    fn emit(&mut self, event: &Event) -> Result<()> {
+        self.send(event.clone())
+            .map_err(|_| Error::EventError("event receiver has disconnected".to_string()))
+    }
+}
+
+/// Delivers every event to each of the sinks in turn, e.g. to log events and also alert on some.
+/// Stops at the first sink that fails.
+impl EventSink for Vec<Box<dyn EventSink + Send>> {
+    fn emit(&mut self, event: &Event) -> Result<()> {
+        self.iter_mut().try_for_each(|sink| sink.emit(event))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.iter_mut().try_for_each(|sink| sink.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+
+    #[test]
+    fn test_jsonl_sink() {
+        let mut sink = JsonlSink::new(Vec::new());
+
+        sink.emit(&Event::Deposited {
+            client: 1,
+            tx: 1,
+            currency: String::new(),
+            amount: amount!(10.5),
+            timestamp: None,
+        })
+        .unwrap();
+        sink.emit(&Event::AccountLocked {
+            client: 1,
+            reason: "chargeback of tx 1".to_string(),
+        })
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(sink.writer).unwrap(),
+            "{\"event\":\"deposited\",\"client\":1,\"tx\":1,\"amount\":\"10.5\"}\n\
+             {\"event\":\"account_locked\",\"client\":1,\"reason\":\"chargeback of tx 1\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_vec_sink() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut sinks: Vec<Box<dyn EventSink + Send>> =
+            vec![Box::new(sender.clone()), Box::new(sender)];
+
+        sinks
+            .emit(&Event::AccountsMerged {
+                source: 1,
+                target: 2,
+            })
+            .unwrap();
+
+        assert_eq!(receiver.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_channel_sink_failure_disconnected() {
+        let (mut sender, receiver) = std::sync::mpsc::channel();
+        drop(receiver);
+
+        let result = sender.emit(&Event::AccountsMerged {
+            source: 1,
+            target: 2,
+        });
+
+        assert!(matches!(result, Err(Error::EventError(_))));
+    }
+}
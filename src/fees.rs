@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::{
+    amount::{AMOUNT_DP, Amount, AmountExt},
+    error::{Error, Result},
+    hash::HashMap,
+    transaction::TransactionType,
+};
+
+/// A fee on one transaction type: a flat amount, a percentage of the tx amount, or both.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeRule {
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    /// Only charge clients in this tier. A client's tier rule wins over an untiered one.
+    #[serde(default)]
+    pub tier: Option<String>,
+    #[serde(default)]
+    pub flat: Option<Amount>,
+    /// Percent of the tx amount, e.g. `1.5` for 1.5%.
+    #[serde(default)]
+    pub percent: Option<Decimal>,
+}
+
+/// Per-transaction fees charged as deposits and withdrawals are applied, and credited to a fee
+/// account in the tx's currency.
+///
+/// Written as TOML:
+///
+/// ```toml
+/// fee_account = 9000
+///
+/// [tiers]
+/// premium = [1, 2]
+///
+/// [[fees]]
+/// type = "withdrawal"
+/// flat = "0.50"
+///
+/// [[fees]]
+/// type = "withdrawal"
+/// tier = "premium"
+/// percent = "0.1"
+/// ```
+#[derive(Debug, Clone)]
+pub struct FeeSchedule {
+    fee_account: u16,
+    tier_of: HashMap<u16, String>,
+    rules: Vec<FeeRule>,
+}
+
+// the schedule as written, with tiers listing their clients
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeeScheduleFile {
+    fee_account: u16,
+    #[serde(default)]
+    tiers: BTreeMap<String, Vec<u16>>,
+    #[serde(default)]
+    fees: Vec<FeeRule>,
+}
+
+impl FeeSchedule {
+    /// Fails with [`Error::ConfigError`] if a client is in more than one tier, a rule names an
+    /// unknown tier, a fee is negative, or a rule is for anything but deposits and withdrawals.
+    pub fn new(
+        fee_account: u16,
+        tiers: impl IntoIterator<Item = (String, Vec<u16>)>,
+        rules: Vec<FeeRule>,
+    ) -> Result<Self> {
+        let mut tier_of = HashMap::default();
+        for (tier, clients) in tiers {
+            for client in clients {
+                if tier_of.insert(client, tier.clone()).is_some() {
+                    return Err(Error::ConfigError(format!(
+                        "client {} is in more than one tier",
+                        client
+                    )));
+                }
+            }
+        }
+        for rule in &rules {
+            if !matches!(
+                rule.tx_type,
+                TransactionType::Deposit | TransactionType::Withdrawal
+            ) {
+                return Err(Error::ConfigError(format!(
+                    "fees only apply to deposits and withdrawals, not {}",
+                    rule.tx_type
+                )));
+            }
+            if let Some(tier) = &rule.tier
+                && !tier_of.values().any(|known| known == tier)
+            {
+                return Err(Error::ConfigError(format!("unknown tier {:?}", tier)));
+            }
+            if rule.flat.is_some_and(|flat| flat < Amount::ZERO)
+                || rule.percent.is_some_and(|percent| percent < Decimal::ZERO)
+            {
+                return Err(Error::ConfigError(format!("negative {} fee", rule.tx_type)));
+            }
+        }
+
+        Ok(Self {
+            fee_account,
+            tier_of,
+            rules,
+        })
+    }
+
+    /// Parses a schedule from TOML, failing with [`Error::ConfigError`] like [`new`](Self::new).
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let file: FeeScheduleFile =
+            toml::from_str(text).map_err(|e| Error::ConfigError(e.to_string()))?;
+        Self::new(file.fee_account, file.tiers, file.fees)
+    }
+
+    /// The client account fees are credited to.
+    pub fn fee_account(&self) -> u16 {
+        self.fee_account
+    }
+
+    /// The fee `client` pays on a `tx_type` of `amount`; zero when no rule applies. Percentages
+    /// are rounded half to even to [`AMOUNT_DP`] places.
+    pub fn fee(&self, tx_type: TransactionType, client: u16, amount: Amount) -> Result<Amount> {
+        let rule_for = |tier: Option<&String>| {
+            self.rules
+                .iter()
+                .find(|rule| rule.tx_type == tx_type && rule.tier.as_ref() == tier)
+        };
+        let tier = self.tier_of.get(&client);
+        let Some(rule) = tier
+            .and_then(|tier| rule_for(Some(tier)))
+            .or_else(|| rule_for(None))
+        else {
+            return Ok(Amount::ZERO);
+        };
+
+        let percent = match rule.percent {
+            Some(percent) => amount
+                .to_decimal()
+                .checked_mul(percent)
+                .map(|fee| (fee / Decimal::ONE_HUNDRED).round_dp(AMOUNT_DP))
+                .ok_or(Error::TransactionError("Fee is out of range."))
+                .and_then(Amount::from_decimal)?,
+            None => Amount::ZERO,
+        };
+
+        rule.flat
+            .unwrap_or(Amount::ZERO)
+            .checked_add(percent)
+            .ok_or(Error::TransactionError("Fee is out of range."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+
+    const SCHEDULE: &str = r#"
+        fee_account = 9000
+
+        [tiers]
+        premium = [1]
+
+        [[fees]]
+        type = "withdrawal"
+        flat = "0.50"
+        percent = "1"
+
+        [[fees]]
+        type = "withdrawal"
+        tier = "premium"
+        flat = "0"
+
+        [[fees]]
+        type = "deposit"
+        percent = "0.125"
+    "#;
+
+    #[test]
+    fn test_fee_success() {
+        let schedule = FeeSchedule::from_toml(SCHEDULE).unwrap();
+
+        assert_eq!(schedule.fee_account(), 9000);
+        assert_eq!(
+            schedule
+                .fee(TransactionType::Withdrawal, 2, amount!(100))
+                .unwrap(),
+            amount!(1.5)
+        );
+        // the premium tier's own rule wins
+        assert_eq!(
+            schedule
+                .fee(TransactionType::Withdrawal, 1, amount!(100))
+                .unwrap(),
+            amount!(0)
+        );
+        // premium clients fall back to the untiered rule where their tier has none
+        assert_eq!(
+            schedule
+                .fee(TransactionType::Deposit, 1, amount!(10.01))
+                .unwrap(),
+            amount!(0.0125)
+        );
+    }
+
+    #[test]
+    fn test_from_toml_failure_invalid_schedule() {
+        for schedule in [
+            "fee_account = 1\n[tiers]\na = [2]\nb = [2]",
+            "fee_account = 1\n[[fees]]\ntype = \"deposit\"\ntier = \"gold\"",
+            "fee_account = 1\n[[fees]]\ntype = \"dispute\"\nflat = \"1\"",
+            "fee_account = 1\n[[fees]]\ntype = \"deposit\"\nflat = \"-1\"",
+            "fee_acount = 1",
+        ] {
+            assert!(
+                matches!(FeeSchedule::from_toml(schedule), Err(Error::ConfigError(_))),
+                "{}",
+                schedule
+            );
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rust_decimal::Decimal;
+use tonic::{Request, Response, Status, Streaming, transport::Server};
+
+use payments_engine::{
+    AccountStatus, AsyncPaymentsEngine, Error, ErrorCategory, ErrorCode, PrecisionPolicy, Result,
+    Transaction, TransactionRow, TransactionType,
+};
+
+use proto::{
+    Account, Balance, GetAccountRequest, Rejection, SubmitSummary,
+    payments_server::{Payments, PaymentsServer},
+};
+
+use crate::roles::{Role, Tokens};
+
+mod proto {
+    tonic::include_proto!("payments.v1");
+}
+
+// serve the engine `spawn` starts on the server's runtime over gRPC until the process is stopped,
+// to the callers `tokens` lists, or to anyone without them
+pub fn run(
+    addr: SocketAddr,
+    tokens: Option<Tokens>,
+    spawn: impl FnOnce() -> Result<AsyncPaymentsEngine>,
+) -> Result<()> {
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let engine = spawn()?;
+        tracing::info!(%addr, "listening");
+        Server::builder()
+            .add_service(PaymentsServer::new(PaymentsService {
+                engine,
+                tokens: tokens.map(Arc::new),
+            }))
+            .serve(addr)
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))
+    })
+}
+
+struct PaymentsService {
+    engine: AsyncPaymentsEngine,
+    // without tokens, every caller is an admin
+    tokens: Option<Arc<Tokens>>,
+}
+
+impl PaymentsService {
+    // the role of the caller, by the bearer token in its `authorization` metadata
+    fn caller<T>(&self, request: &Request<T>) -> std::result::Result<Role, Status> {
+        let Some(tokens) = &self.tokens else {
+            return Ok(Role::Admin);
+        };
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok());
+        tokens
+            .role(authorization)
+            .ok_or_else(|| Status::unauthenticated("a known bearer token is required"))
+    }
+
+    // apply one streamed message, recording it in the summary--only a stopped engine, or a
+    // message the caller's role doesn't allow, is fatal
+    async fn submit_one(
+        &self,
+        caller: Role,
+        message: proto::Transaction,
+        summary: &mut SubmitSummary,
+    ) -> std::result::Result<(), Status> {
+        let tx_id = message.tx;
+        let (category, code, error) = match to_transaction(message) {
+            Ok(tx) if caller < Role::to_submit(tx.tx_type) => {
+                let role = format!("{:?}", Role::to_submit(tx.tx_type)).to_lowercase();
+                return Err(Status::permission_denied(format!(
+                    "tx {} takes the {} role",
+                    tx_id, role
+                )));
+            }
+            Ok(tx) => match self.engine.process(tx).await {
+                Ok(()) => {
+                    summary.applied += 1;
+                    return Ok(());
+                }
+                Err(e) if matches!(e.root(), Error::EngineError(_)) => {
+                    return Err(Status::unavailable(e.to_string()));
+                }
+                Err(e) => (e.category(), e.code(), e.to_string()),
+            },
+            Err(reason) => (ErrorCategory::Parse, ErrorCode::InvalidRow, reason),
+        };
+        summary.rejected.push(Rejection {
+            tx: tx_id,
+            category: category.to_string(),
+            error,
+            code: code.to_string(),
+        });
+
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Payments for PaymentsService {
+    async fn submit_transactions(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> std::result::Result<Response<SubmitSummary>, Status> {
+        let caller = self.caller(&request)?;
+        if caller < Role::Operator {
+            return Err(Status::permission_denied("this takes the operator role"));
+        }
+        let mut stream = request.into_inner();
+        let mut summary = SubmitSummary::default();
+        while let Some(message) = stream.message().await? {
+            self.submit_one(caller, message, &mut summary).await?;
+        }
+
+        Ok(Response::new(summary))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<GetAccountRequest>,
+    ) -> std::result::Result<Response<Account>, Status> {
+        self.caller(&request)?;
+        let client = request.into_inner().client;
+        let id = u16::try_from(client)
+            .map_err(|_| Status::invalid_argument(format!("client {} is out of range", client)))?;
+        let account = self
+            .engine
+            .account(id)
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?
+            .ok_or_else(|| Status::not_found(format!("client {} has not been seen", id)))?;
+
+        Ok(Response::new(Account {
+            client,
+            balances: account
+                .balances
+                .iter()
+                .map(|(currency, balance)| Balance {
+                    currency: currency.clone(),
+                    available: balance.available.to_string(),
+                    held: balance.held.to_string(),
+                    total: balance.total.to_string(),
+                })
+                .collect(),
+            locked: account.is_locked(),
+            closed: account.status == AccountStatus::Closed,
+            status: account.status.to_string(),
+        }))
+    }
+}
+
+fn to_transaction(message: proto::Transaction) -> std::result::Result<Transaction, String> {
+    let tx_type = match proto::TransactionType::try_from(message.r#type) {
+        Ok(proto::TransactionType::Deposit) => TransactionType::Deposit,
+        Ok(proto::TransactionType::Withdrawal) => TransactionType::Withdrawal,
+        Ok(proto::TransactionType::Dispute) => TransactionType::Dispute,
+        Ok(proto::TransactionType::Resolve) => TransactionType::Resolve,
+        Ok(proto::TransactionType::Chargeback) => TransactionType::Chargeback,
+        Ok(proto::TransactionType::Provisional) => TransactionType::Provisional,
+        Ok(proto::TransactionType::Clear) => TransactionType::Clear,
+        Ok(proto::TransactionType::Unlock) => TransactionType::Unlock,
+        Ok(proto::TransactionType::Freeze) => TransactionType::Freeze,
+        Ok(proto::TransactionType::Close) => TransactionType::Close,
+        Ok(proto::TransactionType::Refund) => TransactionType::Refund,
+        Ok(proto::TransactionType::Adjustment) => TransactionType::Adjustment,
+        Ok(proto::TransactionType::Reversal) => TransactionType::Reversal,
+        Ok(proto::TransactionType::Authorize) => TransactionType::Authorize,
+        Ok(proto::TransactionType::Capture) => TransactionType::Capture,
+        Ok(proto::TransactionType::Void) => TransactionType::Void,
+        Ok(proto::TransactionType::Unspecified) | Err(_) => {
+            return Err(format!("unknown transaction type {}", message.r#type));
+        }
+    };
+    let account_id = u16::try_from(message.client)
+        .map_err(|_| format!("client {} is out of range", message.client))?;
+    let amount = message
+        .amount
+        .map(|amount| Decimal::from_str(&amount))
+        .transpose()
+        .map_err(|e| format!("invalid amount: {}", e))?;
+
+    TransactionRow {
+        tx_type,
+        account_id,
+        tx_id: message.tx,
+        amount,
+        currency: message.currency,
+        timestamp: message.timestamp,
+        reason: message.reason,
+        effective: message.effective,
+    }
+    .into_transaction(PrecisionPolicy::default())
+    .map_err(|e| format!("invalid amount: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::{PaymentsEngine, amount};
+
+    fn message(
+        r#type: proto::TransactionType,
+        tx: u32,
+        amount: Option<&str>,
+    ) -> proto::Transaction {
+        proto::Transaction {
+            r#type: r#type.into(),
+            client: 1,
+            tx,
+            amount: amount.map(String::from),
+            currency: None,
+            timestamp: None,
+            reason: None,
+            effective: None,
+        }
+    }
+
+    fn service() -> PaymentsService {
+        PaymentsService {
+            engine: AsyncPaymentsEngine::spawn(PaymentsEngine::new()),
+            tokens: None,
+        }
+    }
+
+    #[test]
+    fn test_to_transaction_success() {
+        let tx = to_transaction(message(proto::TransactionType::Deposit, 1, Some("10.5"))).unwrap();
+
+        assert!(matches!(tx.tx_type, TransactionType::Deposit));
+        assert_eq!(tx.amount, Some(amount!(10.5)));
+    }
+
+    #[test]
+    fn test_to_transaction_failure_unspecified_type() {
+        let result = to_transaction(message(proto::TransactionType::Unspecified, 1, None));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_to_transaction_failure_client_out_of_range() {
+        let mut message = message(proto::TransactionType::Deposit, 1, Some("1"));
+        message.client = 70_000;
+
+        assert!(to_transaction(message).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_submit_one_records_rejections() {
+        let service = service();
+        let mut summary = SubmitSummary::default();
+
+        for message in [
+            message(proto::TransactionType::Deposit, 1, Some("10")),
+            message(proto::TransactionType::Withdrawal, 2, Some("50")),
+            message(proto::TransactionType::Deposit, 3, Some("abc")),
+        ] {
+            service
+                .submit_one(Role::Admin, message, &mut summary)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(summary.applied, 1);
+        let rejected: Vec<_> = summary
+            .rejected
+            .iter()
+            .map(|rejection| (rejection.tx, rejection.category.as_str()))
+            .collect();
+        assert_eq!(rejected, vec![(2, "insufficient-funds"), (3, "parse")]);
+    }
+
+    #[tokio::test]
+    async fn test_get_account_success() {
+        let service = service();
+        let mut summary = SubmitSummary::default();
+        service
+            .submit_one(
+                Role::Admin,
+                message(proto::TransactionType::Deposit, 1, Some("10.5")),
+                &mut summary,
+            )
+            .await
+            .unwrap();
+
+        let account = service
+            .get_account(Request::new(GetAccountRequest { client: 1 }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(account.balances[0].available, "10.5");
+    }
+
+    #[tokio::test]
+    async fn test_get_account_failure_unknown_client() {
+        let status = service()
+            .get_account(Request::new(GetAccountRequest { client: 7 }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+}
@@ -0,0 +1,11 @@
+// the hasher behind the engine's account and transaction maps. std's SipHash resists crafted
+// keys but is slow for the small integer ids used here; `fast-hash` swaps in FxHash, which is
+// much cheaper but lets crafted ids collide, so it's only meant for trusted input
+#[cfg(feature = "fast-hash")]
+pub(crate) type BuildHasher = rustc_hash::FxBuildHasher;
+#[cfg(not(feature = "fast-hash"))]
+pub(crate) type BuildHasher = std::collections::hash_map::RandomState;
+
+pub(crate) type HashMap<K, V> = std::collections::HashMap<K, V, BuildHasher>;
+#[cfg(feature = "concurrent-map")]
+pub(crate) type DashMap<K, V> = dashmap::DashMap<K, V, BuildHasher>;
@@ -0,0 +1,25 @@
+use crate::amount::Amount;
+use serde::Serialize;
+
+use crate::account::Balance;
+
+/// A balance change applied to a client, as kept by
+/// [`track_history`](crate::PaymentsEngineBuilder::track_history) and listed by
+/// [`PaymentsEngine::history`](crate::PaymentsEngine::history).
+///
+/// `operation` is named as in [`AuditRecord`](crate::AuditRecord): the tx type, or `fee`,
+/// `fee_income`, `interest`, `hold_expiry`, `seed` or `merge`. `timestamp` is when it happened
+/// (seconds since the Unix epoch): the transaction's own timestamp, or the time interest was
+/// accrued to or a hold expired at, and `None` when that isn't known. `effective` is the
+/// effective date of a backdated transaction's changes, which period reports place them by.
+/// `balance` is the client's balance in `currency` once the change was applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HistoryEntry {
+    pub operation: &'static str,
+    pub tx: Option<u32>,
+    pub timestamp: Option<u64>,
+    pub effective: Option<u64>,
+    pub currency: String,
+    pub amount: Amount,
+    pub balance: Balance,
+}
@@ -0,0 +1,615 @@
+use std::io::Read;
+
+use csv::{ByteRecord, StringRecord};
+
+use payments_engine::{
+    Error, ErrorContext, PaymentsEngine, PrecisionPolicy, Result, RowParser, Transaction,
+    TransactionRow, TransactionType,
+};
+
+use crate::{
+    checkpoint::{Checkpointer, Outputs, Resumable},
+    logging,
+    policy::{ErrorAction, ErrorPolicy, RejectSink},
+    rules::Rules,
+    shadow::Shadow,
+    signature::{RowVerifier, SIGNATURE_COLUMN},
+    summary::Summary,
+    wal::{Wal, WalRecord},
+};
+
+// everything applied to input rows on their way into the engine
+#[derive(Default)]
+pub struct Ingest {
+    pub verifier: Option<RowVerifier>,
+    pub rules: Option<Rules>,
+    pub precision: PrecisionPolicy,
+    pub policy: ErrorPolicy,
+    pub quarantine: Option<RejectSink>,
+    pub rejects: Option<RejectSink>,
+    pub wal: Option<Wal>,
+    pub checkpoint: Option<Checkpointer>,
+    pub shadow: Option<Shadow>,
+    pub summary: Summary,
+}
+
+impl Ingest {
+    // stream csv transaction rows from the reader into the engine, verifying row signatures and
+    // evaluating custom rules first when given--failures are routed through the error policy
+    pub fn process<R: Read>(&mut self, engine: &mut PaymentsEngine, reader: R) -> Result<()> {
+        let seeks = self.checkpoint.as_ref().is_some_and(Checkpointer::seeks);
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(Resumable::new(reader, seeks));
+        let headers = rdr.headers()?.clone();
+        if let Some(checkpoint) = &mut self.checkpoint {
+            checkpoint.seek(&mut rdr)?;
+        }
+        let signature_idx = match self.verifier {
+            Some(_) => Some(
+                headers
+                    .iter()
+                    .position(|header| header == SIGNATURE_COLUMN)
+                    .ok_or(Error::SignatureError(
+                        "Input has no signature column to verify.",
+                    ))?,
+            ),
+            None => None,
+        };
+
+        let parser = RowParser::new(&headers, self.precision);
+        let mut record = ByteRecord::new();
+
+        loop {
+            self.tick(engine, Some(rdr.position()))?;
+            let read = rdr.read_byte_record(&mut record);
+            // rows handled before the checkpoint being resumed from are passed over
+            if matches!(read, Ok(true) | Err(_))
+                && self.checkpoint.as_mut().is_some_and(Checkpointer::read)
+            {
+                continue;
+            }
+            match read {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    let context = ErrorContext::for_position(e.position());
+                    self.handle_failure(
+                        Error::Csv(e),
+                        context,
+                        None,
+                        "skipping invalid transaction row",
+                    )?;
+                    continue;
+                }
+            }
+
+            // reject tampered/unsigned rows before they reach the engine
+            if let (Some(verifier), Some(idx)) = (&self.verifier, signature_idx)
+                && let record = string_record(&record)
+                && let Err(e) = verifier.verify(&record, idx)
+            {
+                self.handle_failure(
+                    e,
+                    ErrorContext::for_record(&record),
+                    Some(&record),
+                    "rejecting unverified transaction row",
+                )?;
+                continue;
+            }
+
+            // make sure csv row is a valid transaciton, ignore if not
+            let tx = match parser.parse(&record) {
+                Ok(tx) => tx,
+                Err(e) => {
+                    let record = string_record(&record);
+                    self.handle_failure(
+                        e,
+                        ErrorContext::for_record(&record),
+                        Some(&record),
+                        "skipping invalid transaction row",
+                    )?;
+                    continue;
+                }
+            };
+
+            self.apply(engine, tx, || string_record(&record))?;
+        }
+
+        self.flush()
+    }
+
+    // apply transactions parsed from another input format, each paired with the source row it
+    // came from for failure reports--rows that didn't parse go through the error policy like
+    // invalid csv rows
+    pub fn process_rows<I>(&mut self, engine: &mut PaymentsEngine, rows: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (StringRecord, Result<TransactionRow>)>,
+    {
+        if self.verifier.is_some() {
+            return Err(Error::SignatureError(
+                "Input has no signature column to verify.",
+            ));
+        }
+        for (record, row) in rows {
+            // parsed rows have no byte offset to record
+            self.tick(engine, None)?;
+            if self.checkpoint.as_mut().is_some_and(Checkpointer::read) {
+                continue;
+            }
+            match row.and_then(|row| row.into_transaction(self.precision)) {
+                Ok(tx) => self.apply(engine, tx, || record.clone())?,
+                Err(e) => self.handle_failure(
+                    e,
+                    ErrorContext::for_record(&record),
+                    Some(&record),
+                    "skipping invalid transaction row",
+                )?,
+            }
+        }
+
+        self.flush()
+    }
+
+    // run custom rules over a parsed transaction, log it to the WAL and apply it. `record` builds
+    // the input row it came from, only needed if it fails
+    fn apply(
+        &mut self,
+        engine: &mut PaymentsEngine,
+        mut tx: Transaction,
+        record: impl Fn() -> StringRecord,
+    ) -> Result<()> {
+        let account = engine.account(tx.account_id);
+        if let Some(rules) = &self.rules
+            && let Err(e) = rules.apply(&mut tx, account)
+        {
+            let e = e.with_context(ErrorContext::for_tx(&tx));
+            let record = record();
+            self.handle_failure(
+                e,
+                ErrorContext::for_record(&record),
+                Some(&record),
+                "rejected transaction",
+            )?;
+            return Ok(());
+        }
+
+        // log before the engine mutates anything--a failed append fails the run, since the
+        // tx could no longer be recovered
+        if let Some(wal) = &mut self.wal {
+            wal.append(&WalRecord::Tx(tx.clone()))?;
+        }
+
+        // if processing fails, hand the error to the policy and continue processing txs
+        let result = engine.process_tx(&tx);
+        if let Some(shadow) = &mut self.shadow {
+            let primary = result.as_ref().err().map(Error::code);
+            shadow.apply(&tx, primary, || {
+                record().position().map(|position| position.line())
+            })?;
+        }
+        match result {
+            Ok(()) => self.summary.record_applied(tx.tx_type),
+            Err(e) => {
+                let record = record();
+                self.handle_failure(
+                    e,
+                    ErrorContext::for_record(&record),
+                    Some(&record),
+                    "failed transaction",
+                )?
+            }
+        }
+        self.handle_dead_letters(engine)?;
+
+        Ok(())
+    }
+
+    // compact the WAL once its segment is full, and save a checkpoint if one is due, with the
+    // next row at `next`
+    fn tick(&mut self, engine: &mut PaymentsEngine, next: Option<&csv::Position>) -> Result<()> {
+        // held disputes aren't part of the snapshot, so compacting waits until none are
+        if let Some(wal) = &mut self.wal
+            && wal.full()
+            && !engine.has_pending_disputes()
+        {
+            wal.compact(engine)?;
+        }
+        if !self.checkpoint.as_ref().is_some_and(Checkpointer::due) {
+            return Ok(());
+        }
+        let outputs = self.outputs()?;
+        match &mut self.checkpoint {
+            Some(checkpoint) => checkpoint.save(engine, next, outputs),
+            None => Ok(()),
+        }
+    }
+
+    // save a checkpoint past the input just read to its end
+    pub fn end_input(&mut self, engine: &mut PaymentsEngine) -> Result<()> {
+        let outputs = self.outputs()?;
+        match &mut self.checkpoint {
+            Some(checkpoint) => checkpoint.end_input(engine, outputs),
+            None => Ok(()),
+        }
+    }
+
+    // the rows rejected so far written out, so a checkpoint can note how long their files are
+    fn outputs(&mut self) -> Result<Outputs> {
+        let written = |sink: &mut Option<RejectSink>| -> Result<Option<u64>> {
+            let Some(sink) = sink else {
+                return Ok(None);
+            };
+            sink.flush()?;
+            Ok(Some(sink.written()))
+        };
+
+        Ok(Outputs {
+            quarantine: written(&mut self.quarantine)?,
+            rejects: written(&mut self.rejects)?,
+        })
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(quarantine) = &mut self.quarantine {
+            quarantine.flush()?;
+        }
+        if let Some(rejects) = &mut self.rejects {
+            rejects.flush()?;
+        }
+        if let Some(wal) = &mut self.wal {
+            wal.sync()?;
+        }
+
+        Ok(())
+    }
+
+    // give up on disputes still waiting for their tx once all input is in
+    pub fn finish(&mut self, engine: &mut PaymentsEngine) -> Result<()> {
+        engine.flush_pending_disputes();
+        self.handle_dead_letters(engine)?;
+        if let Some(quarantine) = &mut self.quarantine {
+            quarantine.flush()?;
+        }
+        if let Some(rejects) = &mut self.rejects {
+            rejects.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // pending disputes the engine gave up on are failed rows too, only reported late and without
+    // their original row
+    fn handle_dead_letters(&mut self, engine: &mut PaymentsEngine) -> Result<()> {
+        for error in engine.take_dead_letters() {
+            // parking the dispute counted as applying it
+            self.summary.retract_applied(TransactionType::Dispute);
+            self.handle_failure(
+                error,
+                ErrorContext::default(),
+                None,
+                "gave up on pending dispute",
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // only an `abort` action turns a row failure into an error for the whole run. Callers build
+    // the row context only on failure, so successful rows don't pay for copying the raw row
+    fn handle_failure(
+        &mut self,
+        error: Error,
+        row: ErrorContext,
+        record: Option<&StringRecord>,
+        context: &str,
+    ) -> Result<()> {
+        let error = error.with_context(row);
+        self.summary.record_rejected(&error);
+        // the engine can't be trusted after a storage failure, nor the event or audit log after
+        // a failed write, whatever the policy says
+        if let Error::StoreError(_) | Error::EventError(_) | Error::AuditError(_) = error.root() {
+            return Err(error);
+        }
+        // every failed row is recorded, whatever the policy then does with it
+        if let Some(rejects) = &mut self.rejects {
+            rejects.write(&error, record)?;
+        }
+        match self.policy.action(error.category()) {
+            ErrorAction::Skip => Ok(()),
+            ErrorAction::Warn => {
+                logging::failure(context, &error);
+                Ok(())
+            }
+            ErrorAction::Quarantine => match &mut self.quarantine {
+                Some(quarantine) => quarantine.write(&error, record),
+                // quarantine actions are only configurable alongside a quarantine sink
+                None => {
+                    logging::failure(context, &error);
+                    Ok(())
+                }
+            },
+            ErrorAction::Abort => Err(error),
+        }
+    }
+}
+
+// failed rows are reported as text; bytes that aren't valid utf-8 are replaced
+fn string_record(record: &ByteRecord) -> StringRecord {
+    StringRecord::from_byte_record_lossy(record.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::amount;
+    use payments_engine::{
+        DEFAULT_CURRENCY, ErrorCategory, PendingDisputes, PendingOverflow, RoundingMode,
+        WithdrawalLimits,
+    };
+    use std::path::Path;
+
+    const INPUT: &str = "type,client,tx,amount\n\
+                         deposit,1,1,10\n\
+                         withdrawal,1,2,50\n\
+                         dispute,1,99,\n\
+                         deposit,1,3,5\n";
+
+    #[test]
+    fn test_process_default_policy_continues() {
+        let mut engine = PaymentsEngine::new();
+
+        Ingest::default()
+            .process(&mut engine, INPUT.as_bytes())
+            .unwrap();
+
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).total,
+            amount!(15)
+        );
+    }
+
+    #[test]
+    fn test_process_abort_policy_stops() {
+        let mut engine = PaymentsEngine::new();
+        let mut ingest = Ingest::default();
+        ingest
+            .policy
+            .set(ErrorCategory::InsufficientFunds, ErrorAction::Abort);
+
+        let result = ingest.process(&mut engine, INPUT.as_bytes());
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::InsufficientFunds(_)
+        ));
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).total,
+            amount!(10)
+        );
+    }
+
+    #[test]
+    fn test_process_abort_on_unknown_reference() {
+        let mut engine = PaymentsEngine::new();
+        let mut ingest = Ingest::default();
+        ingest
+            .policy
+            .set(ErrorCategory::UnknownReference, ErrorAction::Abort);
+
+        let result = ingest.process(&mut engine, INPUT.as_bytes());
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::UnknownTransaction(99)
+        ));
+    }
+
+    #[test]
+    fn test_process_abort_on_parse_error() {
+        let mut engine = PaymentsEngine::new();
+        let mut ingest = Ingest::default();
+        ingest.policy.set(ErrorCategory::Parse, ErrorAction::Abort);
+        let input = "type,client,tx,amount\nbadtype,1,1,10\n";
+
+        let result = ingest.process(&mut engine, input.as_bytes());
+
+        assert!(matches!(result.unwrap_err().root(), Error::Csv(_)));
+    }
+
+    #[test]
+    fn test_process_fast_path_falls_back_to_serde() {
+        let mut engine = PaymentsEngine::new();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1e2\n\
+                     deposit,1,2,ten\n\
+                     withdrawal,1,3,25.5\n";
+        let mut ingest = Ingest::default();
+
+        ingest.process(&mut engine, input.as_bytes()).unwrap();
+
+        assert_eq!(ingest.summary.failures(), 1);
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).total,
+            amount!(74.5)
+        );
+    }
+
+    #[test]
+    fn test_process_precision_policy() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.00005\n\
+                     deposit,1,2,2.99999\n";
+        let total = |precision| {
+            let mut engine = PaymentsEngine::new();
+            let mut ingest = Ingest {
+                precision,
+                ..Ingest::default()
+            };
+            ingest.process(&mut engine, input.as_bytes()).unwrap();
+            let account = engine.account(1);
+            (
+                account.map_or(amount!(0), |account| {
+                    account.balance(DEFAULT_CURRENCY).total
+                }),
+                ingest.summary.failures(),
+            )
+        };
+
+        assert_eq!(total(PrecisionPolicy::default()), (amount!(4.0000), 0));
+        assert_eq!(
+            total(PrecisionPolicy::Round(RoundingMode::HalfUp)),
+            (amount!(4.0001), 0)
+        );
+        assert_eq!(total(PrecisionPolicy::Truncate), (amount!(3.9999), 0));
+        assert_eq!(total(PrecisionPolicy::Reject), (amount!(0), 2));
+    }
+
+    #[test]
+    fn test_process_corrections_with_reason() {
+        let input = "type,client,tx,amount,reason\n\
+                     deposit,1,1,10,\n\
+                     adjustment,1,2,-2.5,bank fee\n\
+                     adjustment,1,3,4,\n\
+                     reversal,1,1,,entered twice\n";
+        let mut engine = PaymentsEngine::new();
+        let mut ingest = Ingest::default();
+
+        ingest.process(&mut engine, input.as_bytes()).unwrap();
+
+        // the adjustment without a reason fails
+        assert_eq!(ingest.summary.failures(), 1);
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).total,
+            amount!(-2.5)
+        );
+    }
+
+    #[test]
+    fn test_finish_fails_disputes_still_pending() {
+        let mut engine = PaymentsEngine::builder()
+            .pending_disputes(PendingDisputes {
+                capacity: 10,
+                max_age: None,
+                overflow: PendingOverflow::RejectNew,
+            })
+            .build();
+        let input = "type,client,tx,amount\n\
+                     dispute,1,1,\n\
+                     dispute,1,2,\n\
+                     deposit,1,1,10\n";
+        let mut ingest = Ingest::default();
+
+        ingest.process(&mut engine, input.as_bytes()).unwrap();
+        assert_eq!(ingest.summary.failures(), 0);
+        ingest.finish(&mut engine).unwrap();
+
+        assert_eq!(ingest.summary.failures(), 1);
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).held,
+            amount!(10)
+        );
+    }
+
+    #[test]
+    fn test_process_rejects_every_failed_row() {
+        let path = std::env::temp_dir().join(format!("rejects-{}.csv", std::process::id()));
+        let mut engine = PaymentsEngine::new();
+        let mut ingest = Ingest {
+            rejects: Some(RejectSink::new(Box::new(
+                std::fs::File::create(&path).unwrap(),
+            ))),
+            ..Ingest::default()
+        };
+
+        ingest.process(&mut engine, INPUT.as_bytes()).unwrap();
+        assert_eq!(ingest.summary.failures(), 2);
+        drop(ingest);
+
+        let rejects = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows: Vec<_> = rejects
+            .lines()
+            .map(|line| line.split(',').take(3).collect::<Vec<_>>())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["3", "37", "insufficient-funds"],
+                vec!["4", "55", "unknown-transaction"]
+            ]
+        );
+        assert!(
+            rejects
+                .lines()
+                .next()
+                .unwrap()
+                .ends_with(",withdrawal,1,2,50")
+        );
+    }
+
+    #[test]
+    fn test_process_resumes_from_checkpoint() {
+        let dir = std::env::temp_dir();
+        let checkpoint = dir.join(format!("ingest-checkpoint-{}.json", std::process::id()));
+        let rejects = dir.join(format!("ingest-rejects-{}.csv", std::process::id()));
+        let builder = || {
+            PaymentsEngine::builder()
+                .withdrawal_limits(WithdrawalLimits::from_toml("daily = \"100\"").unwrap())
+        };
+        let header = "type,client,tx,amount,timestamp\n";
+        let handled = "deposit,1,1,200,0\n\
+                       dispute,1,99,,1\n\
+                       withdrawal,1,3,60,10\n";
+        let rest = "withdrawal,1,4,50,20\n";
+        // a checkpoint before the last row, which is rejected, then an interruption
+        let mut engine = builder().build();
+        let mut ingest = Ingest {
+            rejects: Some(RejectSink::create(&rejects, None).unwrap()),
+            checkpoint: Some(Checkpointer::new(&checkpoint, 3)),
+            ..Ingest::default()
+        };
+        let checkpointer = ingest.checkpoint.as_mut().unwrap();
+        assert!(checkpointer.start_input(Path::new("in.csv")));
+        let input = format!("{}{}{}", header, handled, rest);
+        ingest.process(&mut engine, input.as_bytes()).unwrap();
+        drop(ingest);
+
+        let (checkpointer, mut engine) = Checkpointer::resume(&checkpoint, 3, builder()).unwrap();
+        let resumed = checkpointer.resumed_outputs();
+        let mut ingest = Ingest {
+            rejects: Some(RejectSink::create(&rejects, resumed.rejects).unwrap()),
+            checkpoint: Some(checkpointer),
+            ..Ingest::default()
+        };
+        let checkpointer = ingest.checkpoint.as_mut().unwrap();
+        assert!(checkpointer.start_input(Path::new("in.csv")));
+        // the rows before the checkpoint are skipped over unread
+        let garbled = format!("{}\n", "x".repeat(handled.len() - 1));
+        let input = format!("{}{}{}", header, garbled, rest);
+        ingest.process(&mut engine, input.as_bytes()).unwrap();
+        ingest.finish(&mut engine).unwrap();
+        drop(ingest);
+
+        let written = std::fs::read_to_string(&rejects).unwrap();
+        std::fs::remove_file(&rejects).unwrap();
+        std::fs::remove_file(&checkpoint).unwrap();
+        // the last row is rejected once, by the daily limit the first run's withdrawal counts
+        // towards, and on its own line
+        let rows: Vec<_> = written
+            .lines()
+            .map(|line| line.split(',').take(3).collect::<Vec<_>>())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["3", "50", "unknown-transaction"],
+                vec!["5", "87", "limit-exceeded"]
+            ]
+        );
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).total,
+            amount!(140)
+        );
+    }
+}
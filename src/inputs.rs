@@ -0,0 +1,134 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[cfg(not(feature = "mmap"))]
+use payments_engine::Error;
+use payments_engine::Result;
+
+use crate::STDIN_PATH;
+
+// the order a directory's files are processed in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputOrder {
+    // lexicographic by file name
+    #[default]
+    Name,
+    // oldest modification time first, ties by name
+    Mtime,
+}
+
+// the input files to process, in order: files as given, and directories replaced by the files in
+// them (not recursing, and skipping hidden ones) in `order`
+pub fn expand(paths: Vec<PathBuf>, order: InputOrder) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        if path.as_os_str() == STDIN_PATH || !path.is_dir() {
+            files.push(path);
+            continue;
+        }
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&path)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() || entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let modified = match order {
+                InputOrder::Name => SystemTime::UNIX_EPOCH,
+                InputOrder::Mtime => metadata.modified()?,
+            };
+            entries.push((modified, entry.path()));
+        }
+        entries.sort_unstable();
+        files.extend(entries.into_iter().map(|(_, path)| path));
+    }
+
+    Ok(files)
+}
+
+// run `f` over the bytes of the file at `path`, mapped into memory rather than read into buffers
+#[cfg(feature = "mmap")]
+pub fn with_mapped<T>(path: &Path, f: impl FnOnce(&[u8]) -> Result<T>) -> Result<T> {
+    let file = fs::File::open(path)?;
+    // SAFETY: the map is only valid while nothing else truncates or rewrites the file. Inputs are
+    // batch files that are done being written by the time they're processed; one that shrinks
+    // mid-run would fault the process rather than corrupt its state
+    let mapped = unsafe { memmap2::Mmap::map(&file)? };
+    #[cfg(unix)]
+    mapped.advise(memmap2::Advice::Sequential)?;
+    f(&mapped)
+}
+
+#[cfg(not(feature = "mmap"))]
+pub fn with_mapped<T>(_path: &Path, _f: impl FnOnce(&[u8]) -> Result<T>) -> Result<T> {
+    Err(Error::ConfigError(
+        "--mmap requires building with the `mmap` feature".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_expand() {
+        let dir = std::env::temp_dir().join(format!("payments-inputs-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        let now = SystemTime::now();
+        for (name, age) in [("b.csv", 3), ("a.csv", 1), ("c.csv", 2), (".hidden", 0)] {
+            let file = fs::File::create(dir.join(name)).unwrap();
+            file.set_modified(now - Duration::from_secs(age * 60))
+                .unwrap();
+        }
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files
+                .iter()
+                .map(|file| file.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        let by_name = expand(
+            vec![PathBuf::from("z.csv"), dir.clone(), PathBuf::from("-")],
+            InputOrder::Name,
+        );
+        let by_mtime = expand(vec![dir.clone()], InputOrder::Mtime);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            names(by_name.unwrap()),
+            ["z.csv", "a.csv", "b.csv", "c.csv", "-"]
+        );
+        assert_eq!(names(by_mtime.unwrap()), ["b.csv", "c.csv", "a.csv"]);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_with_mapped() {
+        let path = std::env::temp_dir().join(format!("payments-mmap-{}.csv", std::process::id()));
+        fs::write(&path, "type,client,tx,amount\ndeposit,1,1,10\n").unwrap();
+        let empty = path.with_extension("empty");
+        fs::write(&empty, "").unwrap();
+
+        let contents = with_mapped(&path, |bytes| Ok(bytes.to_vec()));
+        let empty_len = with_mapped(&empty, |bytes| Ok(bytes.len()));
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&empty).unwrap();
+
+        assert_eq!(
+            contents.unwrap(),
+            b"type,client,tx,amount\ndeposit,1,1,10\n"
+        );
+        assert_eq!(empty_len.unwrap(), 0);
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    #[test]
+    fn test_with_mapped_failure_without_feature() {
+        assert!(matches!(
+            with_mapped(Path::new("tx.csv"), |_| Ok(())),
+            Err(Error::ConfigError(_))
+        ));
+    }
+}
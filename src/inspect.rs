@@ -0,0 +1,182 @@
+use std::io::Write;
+
+use payments_engine::{Account, DisputeStatus, Error, PaymentsEngine, Result, TxRecord};
+use serde::Serialize;
+
+// what `inspect` prints: the client's account and/or the stored tx asked for
+#[derive(Serialize)]
+struct Report<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    account: Option<AccountReport<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transaction: Option<StoredTx>,
+}
+
+#[derive(Serialize)]
+struct AccountReport<'a> {
+    #[serde(flatten)]
+    account: &'a Account,
+    open_disputes: Vec<StoredTx>,
+    // the client's stored and archived transactions, by tx id--a snapshot keeps nothing else of
+    // its history
+    transactions: Vec<StoredTx>,
+}
+
+#[derive(Clone, Serialize)]
+struct StoredTx {
+    tx: u32,
+    #[serde(flatten)]
+    record: TxRecord,
+    // evicted from the store, and read back from the archive
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    archived: bool,
+}
+
+// write `client`'s account and/or the stored tx `tx` as pretty-printed JSON, failing if either
+// doesn't exist. `archived` are the transactions evicted from the store that the archive has
+pub fn run<W: Write>(
+    engine: &PaymentsEngine,
+    client: Option<u16>,
+    tx: Option<u32>,
+    archived: &[(u32, TxRecord)],
+    mut writer: W,
+) -> Result<()> {
+    let account = match client {
+        Some(client) => {
+            let account = engine
+                .account(client)
+                .ok_or(Error::AccountError("Account does not exist."))?;
+            let mut transactions = Vec::new();
+            for record in engine.transactions() {
+                let (tx, record) = record?;
+                if record.account_id == client {
+                    transactions.push(StoredTx {
+                        tx,
+                        record,
+                        archived: false,
+                    });
+                }
+            }
+            transactions.extend(
+                archived
+                    .iter()
+                    .filter(|(_, record)| record.account_id == client)
+                    .map(|(tx, record)| StoredTx {
+                        tx: *tx,
+                        record: record.clone(),
+                        archived: true,
+                    }),
+            );
+            transactions.sort_unstable_by_key(|stored| stored.tx);
+            let open_disputes = transactions
+                .iter()
+                .filter(|stored| stored.record.dispute_status == DisputeStatus::Disputed)
+                .cloned()
+                .collect();
+            Some(AccountReport {
+                account,
+                open_disputes,
+                transactions,
+            })
+        }
+        None => None,
+    };
+    let transaction = match tx {
+        Some(tx) => Some(match engine.transaction(tx)? {
+            Some(record) => StoredTx {
+                tx,
+                record,
+                archived: false,
+            },
+            None => archived
+                .iter()
+                .find(|(id, _)| *id == tx)
+                .map(|(_, record)| StoredTx {
+                    tx,
+                    record: record.clone(),
+                    archived: true,
+                })
+                .ok_or(Error::UnknownTransaction(tx))?,
+        }),
+        None => None,
+    };
+
+    serde_json::to_writer_pretty(
+        &mut writer,
+        &Report {
+            account,
+            transaction,
+        },
+    )
+    .map_err(std::io::Error::other)?;
+    writeln!(writer)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::testing::TxBuilder;
+
+    const EVENTS: &str = "{\"event\":\"deposited\",\"client\":1,\"tx\":1,\"amount\":\"10\"}\n\
+                          {\"event\":\"deposited\",\"client\":2,\"tx\":2,\"amount\":\"3\"}\n\
+                          {\"event\":\"withdrawal_applied\",\"client\":1,\"tx\":3,\"amount\":\"4\"}\n\
+                          {\"event\":\"dispute_opened\",\"client\":1,\"tx\":1,\"tx_type\":\"deposit\",\"amount\":\"10\"}\n";
+
+    #[test]
+    fn test_run_success() {
+        let engine = PaymentsEngine::replay(EVENTS.as_bytes()).unwrap();
+        let mut out = Vec::new();
+
+        run(&engine, Some(1), Some(2), &[], &mut out).unwrap();
+
+        let report: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(report["account"]["id"], 1);
+        assert_eq!(report["account"]["status"], "active");
+        assert_eq!(report["account"]["balances"][""]["held"], "10");
+        assert_eq!(report["account"]["open_disputes"][0]["tx"], 1);
+        assert_eq!(report["account"]["open_disputes"][0]["disputed"], "10");
+        assert_eq!(report["account"]["transactions"][0]["tx"], 1);
+        assert_eq!(report["account"]["transactions"][1]["tx"], 3);
+        assert_eq!(
+            report["account"]["transactions"][1]["tx_type"],
+            "withdrawal"
+        );
+        assert_eq!(report["transaction"]["tx"], 2);
+        assert_eq!(report["transaction"]["account_id"], 2);
+    }
+
+    #[test]
+    fn test_run_archived_success() {
+        let engine = PaymentsEngine::replay(EVENTS.as_bytes()).unwrap();
+        let evicted = TxBuilder::deposit(1, 9, payments_engine::amount!(5)).build();
+        let archived = [(9, TxRecord::try_from(&evicted).unwrap())];
+        let mut out = Vec::new();
+
+        run(&engine, Some(1), Some(9), &archived, &mut out).unwrap();
+
+        let report: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(report["transaction"]["tx"], 9);
+        assert_eq!(report["transaction"]["archived"], true);
+        let transactions = report["account"]["transactions"].as_array().unwrap();
+        assert_eq!(transactions.len(), 3);
+        assert_eq!(transactions[1].get("archived"), None);
+        assert_eq!(transactions[2]["tx"], 9);
+        assert_eq!(transactions[2]["archived"], true);
+    }
+
+    #[test]
+    fn test_run_failure_unknown() {
+        let engine = PaymentsEngine::replay(EVENTS.as_bytes()).unwrap();
+
+        assert!(matches!(
+            run(&engine, Some(9), None, &[], Vec::new()),
+            Err(Error::AccountError(_))
+        ));
+        assert!(matches!(
+            run(&engine, None, Some(9), &[], Vec::new()),
+            Err(Error::UnknownTransaction(9))
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+use rust_decimal::Decimal;
+
+use crate::{
+    amount::{AMOUNT_DP, Amount, AmountExt},
+    error::{Error, Result},
+    hash::HashMap,
+};
+
+// seconds in a day, the period interest compounds over
+pub(crate) const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+// interest rates are annual, paid out daily
+const DAYS_PER_YEAR: u32 = 365;
+
+/// Annual interest rates paid on `available` balances, per currency.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterestRates {
+    default: Option<Decimal>,
+    by_currency: HashMap<String, Decimal>,
+}
+
+impl InterestRates {
+    /// Rates are percentages a year, e.g. `2.5` for 2.5%. `default` applies to every currency
+    /// without a rate of its own; the default currency is `""`. Fails with
+    /// [`Error::ConfigError`] if a rate is negative.
+    pub fn new(
+        default: Option<Decimal>,
+        by_currency: impl IntoIterator<Item = (String, Decimal)>,
+    ) -> Result<Self> {
+        let by_currency: HashMap<String, Decimal> = by_currency.into_iter().collect();
+        if let Some(rate) = default
+            .iter()
+            .chain(by_currency.values())
+            .find(|rate| rate.is_sign_negative())
+        {
+            return Err(Error::ConfigError(format!(
+                "negative interest rate {}",
+                rate
+            )));
+        }
+
+        Ok(Self {
+            default,
+            by_currency,
+        })
+    }
+
+    /// The annual rate for `currency`, if it earns interest.
+    pub fn rate(&self, currency: &str) -> Option<Decimal> {
+        self.by_currency
+            .get(currency)
+            .copied()
+            .or(self.default)
+            .filter(|rate| !rate.is_zero())
+    }
+
+    /// The interest `balance` of `currency` earns over `days`, compounding daily. Each day's
+    /// interest is rounded half to even to [`AMOUNT_DP`] places.
+    pub fn interest(&self, currency: &str, balance: Amount, days: u64) -> Result<Amount> {
+        let Some(rate) = self.rate(currency) else {
+            return Ok(Amount::ZERO);
+        };
+        let daily = rate / Decimal::ONE_HUNDRED / Decimal::from(DAYS_PER_YEAR);
+        let start = balance.to_decimal();
+        let mut balance = start;
+        for _ in 0..days {
+            let interest = balance
+                .checked_mul(daily)
+                .map(|interest| interest.round_dp(AMOUNT_DP))
+                .ok_or(Error::TransactionError("Interest is out of range."))?;
+            // a balance too small to earn anything in a day never will
+            if interest.is_zero() {
+                break;
+            }
+            balance = balance
+                .checked_add(interest)
+                .ok_or(Error::TransactionError("Interest is out of range."))?;
+        }
+
+        Amount::from_decimal(balance - start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_interest_success() {
+        let rates = InterestRates::new(Some(dec!(3.65)), [("EUR".to_string(), dec!(0))]).unwrap();
+
+        // 0.01% a day
+        assert_eq!(rates.interest("", amount!(1000), 1).unwrap(), amount!(0.1));
+        assert_eq!(rates.interest("", amount!(1000), 2).unwrap(), amount!(0.2));
+        assert_eq!(
+            rates.interest("", amount!(10000), 3).unwrap(),
+            amount!(3.0003)
+        );
+        assert_eq!(
+            rates.interest("EUR", amount!(1000), 30).unwrap(),
+            amount!(0)
+        );
+        // 0.00004 a day rounds to nothing
+        assert_eq!(rates.interest("", amount!(0.4), 365).unwrap(), amount!(0));
+    }
+
+    #[test]
+    fn test_new_failure_negative_rate() {
+        assert!(matches!(
+            InterestRates::new(None, [("".to_string(), dec!(-1))]),
+            Err(Error::ConfigError(_))
+        ));
+    }
+}
@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Read};
+use std::path::Path;
+
+use csv::StringRecord;
+#[cfg(not(feature = "iso20022"))]
+use payments_engine::Error;
+use payments_engine::{Result, TransactionRow};
+use serde::Deserialize;
+
+// the format input files are read in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+    // by extension: `.xml` (compressed or not) is ISO 20022, anything else (and stdin) is csv
+    #[default]
+    Auto,
+    Csv,
+    Iso20022,
+}
+
+impl InputFormat {
+    // the format `path` is read in, `Auto` resolved by its extension
+    pub fn of(self, path: &Path) -> InputFormat {
+        if self != InputFormat::Auto {
+            return self;
+        }
+        let name = path.to_string_lossy();
+        let name = name
+            .strip_suffix(".gz")
+            .or_else(|| name.strip_suffix(".zst"))
+            .unwrap_or(&name);
+        match name.ends_with(".xml") {
+            true => InputFormat::Iso20022,
+            false => InputFormat::Csv,
+        }
+    }
+}
+
+// one row of an accounts mapping csv: the account as ISO 20022 messages identify it (IBAN or
+// other id) and the client it belongs to
+#[derive(Deserialize)]
+struct AccountRow {
+    account: String,
+    client: u16,
+}
+
+// read the account-to-client mapping for ISO 20022 input
+pub fn load_accounts<R: Read>(reader: R) -> Result<HashMap<String, u16>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut accounts = HashMap::new();
+    for row in rdr.deserialize() {
+        let row: AccountRow = row?;
+        accounts.insert(row.account, row.client);
+    }
+
+    Ok(accounts)
+}
+
+#[cfg(not(feature = "iso20022"))]
+pub fn parse<R: BufRead>(
+    _reader: R,
+    _accounts: &HashMap<String, u16>,
+) -> Result<Vec<(StringRecord, Result<TransactionRow>)>> {
+    Err(Error::ConfigError(
+        "ISO 20022 input requires building with the `iso20022` feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "iso20022")]
+pub use xml::parse;
+
+#[cfg(feature = "iso20022")]
+mod xml {
+    use std::str::FromStr;
+
+    use payments_engine::{Error, TransactionType};
+    use quick_xml::{Reader, events::Event};
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::dates;
+
+    // the messages understood, by the element under `Document`
+    #[derive(Clone, Copy)]
+    enum Message {
+        // customer credit transfer initiation: each transfer is paid out of the debtor's account
+        Pain001,
+        // bank-to-customer statement: each booked entry credits or debits the statement's account
+        Camt053,
+    }
+
+    impl Message {
+        fn name(self) -> &'static str {
+            match self {
+                Message::Pain001 => "pain.001",
+                Message::Camt053 => "camt.053",
+            }
+        }
+
+        // the element holding what its entries share, and the element of each entry
+        fn tags(self) -> (&'static [u8], &'static [u8]) {
+            match self {
+                Message::Pain001 => (b"PmtInf", b"CdtTrfTxInf"),
+                Message::Camt053 => (b"Stmt", b"Ntry"),
+            }
+        }
+    }
+
+    // the text of an element, keyed by its path below the enclosing group or entry, e.g.
+    // `PmtId/EndToEndId`; attributes are keyed `path@name`
+    #[derive(Default)]
+    struct Fields {
+        depth: usize,
+        values: HashMap<String, String>,
+    }
+
+    impl Fields {
+        fn get(&self, path: &str) -> Option<&str> {
+            self.values.get(path).map(String::as_str)
+        }
+
+        // the first of `paths` present
+        fn first(&self, paths: &[&str]) -> Option<&str> {
+            paths.iter().find_map(|path| self.get(path))
+        }
+    }
+
+    // the transactions in a pain.001 or camt.053 document, each with a row describing the entry
+    // it came from (message, reference, account, amount, currency) for failure reports. Entries
+    // that don't map to a transaction come back as errors; only a malformed document, or one
+    // that is neither message, fails the whole parse. camt.053 entries that aren't booked are
+    // left out, as their funds haven't moved yet
+    pub fn parse<R: BufRead>(
+        reader: R,
+        accounts: &HashMap<String, u16>,
+    ) -> Result<Vec<(StringRecord, Result<TransactionRow>)>> {
+        let malformed = |e: quick_xml::Error| Error::SchemaError(format!("malformed XML: {}", e));
+        let mut reader = Reader::from_reader(reader);
+        reader.config_mut().trim_text(true);
+
+        let mut rows = Vec::new();
+        let mut buf = Vec::new();
+        let mut path: Vec<Vec<u8>> = Vec::new();
+        let mut message = None;
+        let mut group: Option<Fields> = None;
+        let mut entry: Option<Fields> = None;
+        loop {
+            let event = reader.read_event_into(&mut buf).map_err(malformed)?;
+            let (start, empty) = match &event {
+                Event::Start(start) => (Some(start.clone()), false),
+                Event::Empty(start) => (Some(start.clone()), true),
+                _ => (None, false),
+            };
+            if let Some(start) = start {
+                let name = start.local_name().as_ref().to_vec();
+                if path.len() == 1 {
+                    message = match name.as_slice() {
+                        b"CstmrCdtTrfInitn" => Some(Message::Pain001),
+                        b"BkToCstmrStmt" => Some(Message::Camt053),
+                        _ => None,
+                    };
+                }
+                path.push(name);
+                if let Some(message) = message {
+                    let (group_tag, entry_tag) = message.tags();
+                    let tag = path.last().unwrap().as_slice();
+                    if tag == group_tag {
+                        group = Some(Fields {
+                            depth: path.len(),
+                            ..Fields::default()
+                        });
+                    } else if tag == entry_tag {
+                        entry = Some(Fields {
+                            depth: path.len(),
+                            ..Fields::default()
+                        });
+                    }
+                    if let Some(fields) = entry.as_mut().or(group.as_mut()) {
+                        for attribute in start.attributes() {
+                            let attribute = attribute.map_err(|e| malformed(e.into()))?;
+                            let value = attribute.unescape_value().map_err(malformed)?;
+                            let key = format!(
+                                "{}@{}",
+                                relative(&path, fields.depth),
+                                String::from_utf8_lossy(attribute.key.local_name().as_ref())
+                            );
+                            fields.values.insert(key, value.into_owned());
+                        }
+                    }
+                }
+            }
+
+            match event {
+                Event::Text(ref text) => {
+                    if let Some(fields) = entry.as_mut().or(group.as_mut()) {
+                        let text = text.unescape().map_err(malformed)?;
+                        fields
+                            .values
+                            .insert(relative(&path, fields.depth), text.into_owned());
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+
+            if matches!(event, Event::End(_)) || empty {
+                if let (Some(message), Some(fields)) = (message, &entry)
+                    && fields.depth == path.len()
+                {
+                    let none = Fields::default();
+                    let shared = group.as_ref().unwrap_or(&none);
+                    if let Some(row) = transaction(message, shared, fields, accounts) {
+                        rows.push(row);
+                    }
+                    entry = None;
+                } else if group
+                    .as_ref()
+                    .is_some_and(|group| group.depth == path.len())
+                {
+                    group = None;
+                }
+                path.pop();
+            }
+            buf.clear();
+        }
+        if message.is_none() && rows.is_empty() {
+            return Err(Error::SchemaError(
+                "not a pain.001 or camt.053 document".to_string(),
+            ));
+        }
+
+        Ok(rows)
+    }
+
+    fn relative(path: &[Vec<u8>], depth: usize) -> String {
+        path[depth..]
+            .iter()
+            .map(|name| String::from_utf8_lossy(name))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    // the transaction an entry maps to, or `None` for an entry to leave out
+    fn transaction(
+        message: Message,
+        group: &Fields,
+        entry: &Fields,
+        accounts: &HashMap<String, u16>,
+    ) -> Option<(StringRecord, Result<TransactionRow>)> {
+        let (account, amount, currency, references, date, value_date, reason) = match message {
+            Message::Pain001 => (
+                group.first(&["DbtrAcct/Id/IBAN", "DbtrAcct/Id/Othr/Id"]),
+                entry.get("Amt/InstdAmt"),
+                entry.get("Amt/InstdAmt@Ccy"),
+                [
+                    entry.get("PmtId/EndToEndId"),
+                    entry.get("PmtId/InstrId"),
+                    None,
+                ],
+                group.first(&["ReqdExctnDt/Dt", "ReqdExctnDt/DtTm", "ReqdExctnDt"]),
+                None,
+                entry.get("RmtInf/Ustrd"),
+            ),
+            Message::Camt053 => {
+                if entry.first(&["Sts/Cd", "Sts"]) != Some("BOOK") {
+                    return None;
+                }
+                (
+                    group.first(&["Acct/Id/IBAN", "Acct/Id/Othr/Id"]),
+                    entry.get("Amt"),
+                    entry.get("Amt@Ccy"),
+                    [
+                        entry.get("AcctSvcrRef"),
+                        entry.get("NtryRef"),
+                        entry.get("NtryDtls/TxDtls/Refs/EndToEndId"),
+                    ],
+                    entry.first(&["BookgDt/Dt", "BookgDt/DtTm"]),
+                    entry.first(&["ValDt/Dt", "ValDt/DtTm"]),
+                    entry.first(&["AddtlNtryInf", "NtryDtls/TxDtls/RmtInf/Ustrd"]),
+                )
+            }
+        };
+        let reference = references.iter().flatten().next().copied();
+        let record = StringRecord::from(vec![
+            message.name(),
+            reference.unwrap_or_default(),
+            account.unwrap_or_default(),
+            amount.unwrap_or_default(),
+            currency.unwrap_or_default(),
+        ]);
+
+        let row = || -> Result<TransactionRow> {
+            let tx_type = match message {
+                Message::Pain001 => TransactionType::Withdrawal,
+                Message::Camt053 => match entry.get("CdtDbtInd") {
+                    Some("CRDT") => TransactionType::Deposit,
+                    Some("DBIT") => TransactionType::Withdrawal,
+                    _ => {
+                        return Err(Error::TransactionError(
+                            "Entry is neither credit nor debit.",
+                        ));
+                    }
+                },
+            };
+            let account_id = account
+                .and_then(|account| accounts.get(account))
+                .copied()
+                .ok_or(Error::TransactionError(
+                    "Account is not mapped to a client.",
+                ))?;
+            // the engine's tx ids are numbers, so only a numeric bank reference can be one
+            let tx_id = references
+                .iter()
+                .flatten()
+                .find_map(|reference| reference.parse().ok())
+                .ok_or(Error::TransactionError(
+                    "Entry has no numeric reference to use as tx id.",
+                ))?;
+            let amount = amount
+                .and_then(|amount| Decimal::from_str(amount).ok())
+                .ok_or(Error::TransactionError("Invalid transaction amount."))?;
+            let timestamp = match date {
+                Some(date) => Some(
+                    dates::midnight(date)
+                        .ok_or(Error::TransactionError("Invalid booking date."))?,
+                ),
+                None => None,
+            };
+            // an entry valued before it was booked is backdated to its value date
+            let effective = match value_date {
+                Some(date) => Some(
+                    dates::midnight(date).ok_or(Error::TransactionError("Invalid value date."))?,
+                ),
+                None => None,
+            }
+            .filter(|&effective| timestamp.is_some_and(|timestamp| effective < timestamp));
+
+            Ok(TransactionRow {
+                tx_type,
+                account_id,
+                tx_id,
+                amount: Some(amount),
+                currency: currency.map(str::to_string),
+                timestamp,
+                reason: reason.map(str::to_string),
+                effective,
+            })
+        };
+
+        Some((record, row()))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const PAIN_001: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.09">
+  <CstmrCdtTrfInitn>
+    <GrpHdr><MsgId>MSG-1</MsgId><NbOfTxs>2</NbOfTxs></GrpHdr>
+    <PmtInf>
+      <PmtInfId>P-1</PmtInfId>
+      <ReqdExctnDt><Dt>2024-03-01</Dt></ReqdExctnDt>
+      <DbtrAcct><Id><IBAN>DE89370400440532013000</IBAN></Id></DbtrAcct>
+      <CdtTrfTxInf>
+        <PmtId><InstrId>I-1</InstrId><EndToEndId>1001</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">12.50</InstdAmt></Amt>
+        <RmtInf><Ustrd>Invoice 7 &amp; 8</Ustrd></RmtInf>
+      </CdtTrfTxInf>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>E2E-X</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">3</InstdAmt></Amt>
+      </CdtTrfTxInf>
+    </PmtInf>
+  </CstmrCdtTrfInitn>
+</Document>"#;
+
+        const CAMT_053: &str = r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.08">
+  <BkToCstmrStmt>
+    <Stmt>
+      <Id>S-1</Id>
+      <Acct><Id><Othr><Id>ACC-7</Id></Othr></Id></Acct>
+      <Ntry>
+        <NtryRef>2001</NtryRef>
+        <Amt Ccy="USD">100.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts><Cd>BOOK</Cd></Sts>
+        <BookgDt><DtTm>2024-02-29T10:00:00</DtTm></BookgDt>
+        <ValDt><Dt>2024-02-27</Dt></ValDt>
+        <AddtlNtryInf>Wire in</AddtlNtryInf>
+      </Ntry>
+      <Ntry>
+        <NtryRef>2002</NtryRef>
+        <Amt Ccy="USD">5</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts><Cd>PDNG</Cd></Sts>
+      </Ntry>
+      <Ntry>
+        <AcctSvcrRef>2003</AcctSvcrRef>
+        <Amt Ccy="USD">40</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+
+        fn accounts() -> HashMap<String, u16> {
+            load_accounts("account,client\nDE89370400440532013000,1\nACC-7,2\n".as_bytes()).unwrap()
+        }
+
+        #[test]
+        fn test_parse_pain_001() {
+            let rows = parse(PAIN_001.as_bytes(), &accounts()).unwrap();
+
+            assert_eq!(rows.len(), 2);
+            let (record, row) = &rows[0];
+            let row = row.as_ref().unwrap();
+            assert_eq!(row.tx_type, TransactionType::Withdrawal);
+            assert_eq!((row.account_id, row.tx_id), (1, 1001));
+            assert_eq!(row.amount, Some(Decimal::new(1250, 2)));
+            assert_eq!(row.currency.as_deref(), Some("EUR"));
+            assert_eq!(row.timestamp, Some(1_709_251_200));
+            assert_eq!(row.reason.as_deref(), Some("Invoice 7 & 8"));
+            assert_eq!(&record[0], "pain.001");
+            assert!(matches!(rows[1].1, Err(Error::TransactionError(_))));
+        }
+
+        #[test]
+        fn test_parse_camt_053() {
+            let rows = parse(CAMT_053.as_bytes(), &accounts()).unwrap();
+
+            // the pending entry is left out
+            assert_eq!(rows.len(), 2);
+            let deposit = rows[0].1.as_ref().unwrap();
+            assert_eq!(deposit.tx_type, TransactionType::Deposit);
+            assert_eq!((deposit.account_id, deposit.tx_id), (2, 2001));
+            assert_eq!(deposit.timestamp, Some(1_709_164_800));
+            // valued two days before it was booked
+            assert_eq!(deposit.effective, Some(1_708_992_000));
+            assert_eq!(deposit.reason.as_deref(), Some("Wire in"));
+            let withdrawal = rows[1].1.as_ref().unwrap();
+            assert_eq!(withdrawal.tx_type, TransactionType::Withdrawal);
+            assert_eq!(withdrawal.tx_id, 2003);
+        }
+
+        #[test]
+        fn test_parse_failure() {
+            assert!(matches!(
+                parse("<Document><Other/></Document>".as_bytes(), &accounts()),
+                Err(Error::SchemaError(_))
+            ));
+            assert!(matches!(
+                parse("<Document><BkToCstmrStmt></Stmt>".as_bytes(), &accounts()),
+                Err(Error::SchemaError(_))
+            ));
+            let unmapped =
+                parse(CAMT_053.replace("ACC-7", "ACC-9").as_bytes(), &accounts()).unwrap();
+            assert!(matches!(unmapped[0].1, Err(Error::TransactionError(_))));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_of() {
+        assert_eq!(
+            InputFormat::Auto.of(Path::new("stmt.xml")),
+            InputFormat::Iso20022
+        );
+        assert_eq!(
+            InputFormat::Auto.of(Path::new("stmt.xml.gz")),
+            InputFormat::Iso20022
+        );
+        assert_eq!(InputFormat::Auto.of(Path::new("tx.csv")), InputFormat::Csv);
+        assert_eq!(InputFormat::Auto.of(Path::new("-")), InputFormat::Csv);
+        assert_eq!(
+            InputFormat::Iso20022.of(Path::new("tx.csv")),
+            InputFormat::Iso20022
+        );
+    }
+}
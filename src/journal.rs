@@ -0,0 +1,204 @@
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    amount::Amount,
+    audit::{AuditRecord, AuditSink},
+    error::{Error, Result},
+    ledger::LedgerAccount,
+};
+
+/// The general-ledger account codes the engine's [`LedgerAccount`]s are booked to, for a journal
+/// an accounting system can import.
+///
+/// Written as TOML, with a code for every ledger account:
+///
+/// ```toml
+/// # client funds, a liability
+/// available = "2100"
+/// held = "2110"
+/// # the cash the funds came in as and go out as
+/// suspense = "1000"
+/// chargeback_loss = "6100"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GlMapping {
+    available: String,
+    held: String,
+    suspense: String,
+    chargeback_loss: String,
+}
+
+impl GlMapping {
+    /// Parses a mapping from TOML, failing with [`Error::ConfigError`] if a code is missing or
+    /// empty.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let mapping: GlMapping =
+            toml::from_str(text).map_err(|e| Error::ConfigError(e.to_string()))?;
+        for account in [
+            LedgerAccount::Available,
+            LedgerAccount::Held,
+            LedgerAccount::Suspense,
+            LedgerAccount::ChargebackLoss,
+        ] {
+            if mapping.code(account).is_empty() {
+                return Err(Error::ConfigError(format!(
+                    "GL account code for `{:?}` is empty",
+                    account
+                )));
+            }
+        }
+
+        Ok(mapping)
+    }
+
+    /// The GL account code `account` is booked to.
+    pub fn code(&self, account: LedgerAccount) -> &str {
+        match account {
+            LedgerAccount::Available => &self.available,
+            LedgerAccount::Held => &self.held,
+            LedgerAccount::Suspense => &self.suspense,
+            LedgerAccount::ChargebackLoss => &self.chargeback_loss,
+        }
+    }
+}
+
+// one line of the journal: a posting booked to the GL accounts
+#[derive(Serialize)]
+struct JournalRow<'a> {
+    entry: u64,
+    client: u16,
+    tx: Option<u32>,
+    operation: &'a str,
+    currency: &'a str,
+    debit: &'a str,
+    credit: &'a str,
+    amount: Amount,
+}
+
+/// Writes the ledger postings behind every balance mutation as a CSV journal, with a header row,
+/// booked to the GL accounts of a [`GlMapping`].
+///
+/// Each [`AuditRecord`] becomes a journal entry, numbered from 1, of one line per non-zero
+/// posting. Merges only move funds between clients, which share their GL accounts, so they book
+/// nothing.
+pub struct GlJournalSink<W: Write> {
+    mapping: GlMapping,
+    writer: csv::Writer<W>,
+    entries: u64,
+}
+
+impl<W: Write> GlJournalSink<W> {
+    pub fn new(mapping: GlMapping, writer: W) -> Self {
+        Self {
+            mapping,
+            writer: csv::Writer::from_writer(writer),
+            entries: 0,
+        }
+    }
+}
+
+impl<W: Write> AuditSink for GlJournalSink<W> {
+    fn audit(&mut self, record: &AuditRecord) -> Result<()> {
+        let mut postings = record
+            .postings
+            .iter()
+            .filter(|posting| posting.amount != Amount::ZERO)
+            .peekable();
+        if postings.peek().is_none() {
+            return Ok(());
+        }
+        self.entries += 1;
+        for posting in postings {
+            self.writer
+                .serialize(JournalRow {
+                    entry: self.entries,
+                    client: record.client,
+                    tx: record.tx,
+                    operation: record.operation,
+                    currency: &record.currency,
+                    debit: self.mapping.code(posting.debit),
+                    credit: self.mapping.code(posting.credit),
+                    amount: posting.amount,
+                })
+                .map_err(|e| Error::AuditError(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .map_err(|e| Error::AuditError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::engine::PaymentsEngine;
+    use crate::testing::TxBuilder;
+
+    const MAPPING: &str = "available = \"2100\"\n\
+                           held = \"2110\"\n\
+                           suspense = \"1000\"\n\
+                           chargeback_loss = \"6100\"\n";
+
+    // a sink writing to a buffer the test can read back once the engine is done with it
+    struct Shared(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_from_toml_failure() {
+        assert!(matches!(
+            GlMapping::from_toml("available = \"2100\""),
+            Err(Error::ConfigError(_))
+        ));
+        assert!(matches!(
+            GlMapping::from_toml(&MAPPING.replace("\"6100\"", "\"\"")),
+            Err(Error::ConfigError(message)) if message.contains("ChargebackLoss")
+        ));
+    }
+
+    #[test]
+    fn test_journal_books_postings_to_gl_accounts() {
+        let journal = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut engine = PaymentsEngine::builder()
+            .audit_sink(Box::new(GlJournalSink::new(
+                GlMapping::from_toml(MAPPING).unwrap(),
+                Shared(journal.clone()),
+            )))
+            .build();
+        let mut process = |tx: TxBuilder| engine.process_tx(&tx.build()).unwrap();
+        process(TxBuilder::deposit(1, 1, amount!(10)));
+        process(TxBuilder::deposit(2, 2, amount!(5)));
+        process(TxBuilder::dispute(1, 1));
+        process(TxBuilder::chargeback(1, 1));
+        engine.merge_accounts(2, 3).unwrap();
+
+        engine.flush_events().unwrap();
+
+        // the merge books nothing
+        assert_eq!(
+            String::from_utf8(journal.lock().unwrap().clone()).unwrap(),
+            "entry,client,tx,operation,currency,debit,credit,amount\n\
+             1,1,1,deposit,,1000,2100,10\n\
+             2,2,2,deposit,,1000,2100,5\n\
+             3,1,1,dispute,,2100,2110,10\n\
+             4,1,1,chargeback,,2110,6100,10\n"
+        );
+    }
+}
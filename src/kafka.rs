@@ -0,0 +1,437 @@
+use std::collections::BTreeMap;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use rdkafka::{
+    ClientConfig, Message, Offset, TopicPartitionList,
+    consumer::{CommitMode, Consumer, StreamConsumer},
+    error::KafkaError,
+    producer::{FutureProducer, FutureRecord},
+};
+
+use payments_engine::{
+    DuplicatePolicy, Error, Event, PaymentsEngine, PaymentsEngineBuilder, Result, Transaction,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aggregates::WindowAggregates,
+    avro::SchemaRegistry,
+    checkpoint, dates, logging,
+    output::{OutputFormat, write_accounts},
+    wal::{Wal, WalRecord},
+};
+
+pub struct KafkaOptions {
+    pub brokers: String,
+    pub topic: String,
+    pub group_id: String,
+    pub emit_interval: Duration,
+    // decode messages as registry-framed Avro rather than JSON
+    pub registry: Option<SchemaRegistry>,
+    // save the engine state along with the offsets it covers here, committing offsets only once
+    // it has been saved
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    // evict transactions past the engine's dispute window this often, by the wall clock
+    pub evict_interval: Option<Duration>,
+    // publish per-client aggregates of every window to a topic
+    pub aggregates: Option<AggregateOptions>,
+}
+
+pub struct AggregateOptions {
+    pub topic: String,
+    pub window: Duration,
+}
+
+// how long publishing one aggregate may wait for room in the producer's queue
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// bump whenever the offsets' layout changes so old checkpoints are refused rather than misread
+const OFFSETS_VERSION: u32 = 1;
+
+// the next offset to consume per topic and partition, saved with the engine state that covers
+// everything before them
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Offsets {
+    version: u32,
+    next: BTreeMap<String, BTreeMap<i32, i64>>,
+}
+
+impl Offsets {
+    fn record(&mut self, topic: &str, partition: i32, offset: i64) {
+        self.next
+            .entry(topic.to_string())
+            .or_default()
+            .insert(partition, offset + 1);
+    }
+
+    fn partition_list(&self) -> Result<TopicPartitionList> {
+        let mut list = TopicPartitionList::new();
+        for (topic, partitions) in &self.next {
+            for (&partition, &offset) in partitions {
+                list.add_partition_offset(topic, partition, Offset::Offset(offset))
+                    .map_err(kafka_error)?;
+            }
+        }
+        Ok(list)
+    }
+}
+
+// consume JSON (or, with a schema registry, Avro) transactions until the process is stopped. Offsets are committed only once a
+// message has been handled (and logged to the WAL, when given), so delivery is at-least-once--
+// redelivered deposits/withdrawals are skipped as duplicates rather than applied twice.
+//
+// With a checkpoint, offsets are instead committed only once the engine state covering them has
+// been saved. A restart restores that state and commits its offsets back before consuming, so
+// every message is applied exactly once to the state that carries on. The engine is built, or
+// restored, with `builder`, so a restored engine keeps its limits and risk rules along with the
+// windows they look back on.
+//
+// With aggregates, every window aligned to a multiple of its length closes by publishing each
+// active client's totals from the engine's events over it
+pub fn run(
+    options: KafkaOptions,
+    builder: PaymentsEngineBuilder,
+    wal_dir: Option<&Path>,
+) -> Result<()> {
+    let mut registry = options.registry;
+    let (builder, events) = match &options.aggregates {
+        Some(_) => {
+            let (sender, events) = mpsc::channel();
+            (builder.event_sink(Box::new(sender)), Some(events))
+        }
+        None => (builder, None),
+    };
+    let mut offsets = Offsets {
+        version: OFFSETS_VERSION,
+        ..Offsets::default()
+    };
+    // the WAL and the checkpoint can't be combined, so the engine comes from one or the other
+    let (mut wal, engine) = match (wal_dir, options.checkpoint.as_deref()) {
+        (Some(dir), _) => {
+            let (wal, engine) = Wal::recover(dir, builder)?;
+            (Some(wal), engine)
+        }
+        (None, Some(path)) => match checkpoint::load(path)? {
+            Some((saved, state)) => {
+                let saved: Offsets = saved;
+                if saved.version != OFFSETS_VERSION {
+                    return Err(Error::SnapshotError(format!(
+                        "checkpoint version {} is not supported (expected {})",
+                        saved.version, OFFSETS_VERSION
+                    )));
+                }
+                offsets = saved;
+                tracing::info!(offsets = ?offsets.next, "resuming from checkpoint");
+                (None, builder.restore(state)?)
+            }
+            None => (None, builder.build()),
+        },
+        (None, None) => (None, builder.build()),
+    };
+    let mut engine = engine.with_duplicate_policy(DuplicatePolicy::Skip);
+    // replaying the WAL re-emits events already counted in windows before the restart
+    if let Some(events) = &events {
+        events.try_iter().for_each(drop);
+    }
+
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &options.brokers)
+            .set("group.id", &options.group_id)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .map_err(kafka_error)?;
+        // whatever was committed after the checkpoint was saved is ahead of the restored state
+        if !offsets.next.is_empty() {
+            consumer
+                .commit(&offsets.partition_list()?, CommitMode::Sync)
+                .map_err(kafka_error)?;
+        }
+        consumer.subscribe(&[&options.topic]).map_err(kafka_error)?;
+
+        let mut emit = tokio::time::interval(options.emit_interval);
+        // the first tick completes immediately--skip it rather than emit an empty state
+        emit.tick().await;
+        let mut save = tokio::time::interval(options.checkpoint_interval);
+        save.tick().await;
+        // never ticked without an eviction interval
+        let mut evict =
+            tokio::time::interval(options.evict_interval.unwrap_or(options.emit_interval));
+        evict.tick().await;
+        // never ticked without aggregates
+        let length = options
+            .aggregates
+            .as_ref()
+            .map_or(options.emit_interval, |aggregates| aggregates.window)
+            .as_secs()
+            .max(1);
+        let now = dates::now();
+        let window = WindowAggregates::new(now - now % length, length);
+        let mut close = tokio::time::interval_at(
+            tokio::time::Instant::now() + Duration::from_secs(window.end() - now),
+            Duration::from_secs(length),
+        );
+        let mut aggregator = match (options.aggregates, events) {
+            (Some(aggregates), Some(events)) => Some(Aggregator {
+                events,
+                producer: ClientConfig::new()
+                    .set("bootstrap.servers", &options.brokers)
+                    .create()
+                    .map_err(kafka_error)?,
+                topic: aggregates.topic,
+                window,
+            }),
+            _ => None,
+        };
+        // whether messages were handled since the last checkpoint
+        let mut unsaved = false;
+        loop {
+            tokio::select! {
+                _ = emit.tick() => write_accounts(
+                    &engine,
+                    BufWriter::new(std::io::stdout()),
+                    OutputFormat::Csv,
+                )?,
+                _ = save.tick(), if unsaved => {
+                    let path = options.checkpoint.as_deref().expect("only unsaved with a checkpoint");
+                    checkpoint::save(path, &offsets, &mut engine)?;
+                    consumer
+                        .commit(&offsets.partition_list()?, CommitMode::Async)
+                        .map_err(kafka_error)?;
+                    unsaved = false;
+                }
+                _ = evict.tick(), if options.evict_interval.is_some() => {
+                    let now = dates::now();
+                    if let Some(wal) = &mut wal {
+                        wal.append(&WalRecord::EvictExpired { now })?;
+                        wal.sync()?;
+                    }
+                    let count = engine.evict_expired(now)?;
+                    tracing::info!(count, "evicted transactions past the dispute window");
+                    unsaved |= count > 0 && options.checkpoint.is_some();
+                }
+                _ = close.tick(), if aggregator.is_some() => {
+                    aggregator.as_mut().expect("only closed with aggregates").close().await;
+                }
+                message = consumer.recv() => {
+                    let message = message.map_err(kafka_error)?;
+                    handle_payload(
+                        &mut engine,
+                        wal.as_mut(),
+                        registry.as_mut(),
+                        message.payload(),
+                    )?;
+                    if options.checkpoint.is_some() {
+                        offsets.record(message.topic(), message.partition(), message.offset());
+                        unsaved = true;
+                        continue;
+                    }
+                    if let Some(wal) = &mut wal {
+                        // the engine has every record applied, so a full segment can go
+                        if wal.full() {
+                            wal.compact(&mut engine)?;
+                        } else {
+                            wal.sync()?;
+                        }
+                    }
+                    consumer
+                        .commit_message(&message, CommitMode::Async)
+                        .map_err(kafka_error)?;
+                }
+            }
+        }
+    })
+}
+
+// the engine's events, totalled per client over each window and published to `topic` as the
+// window closes
+struct Aggregator {
+    events: mpsc::Receiver<Event>,
+    producer: FutureProducer,
+    topic: String,
+    window: WindowAggregates,
+}
+
+impl Aggregator {
+    // a window that can't be published is logged and dropped rather than stopping the consumer
+    async fn close(&mut self) {
+        for event in self.events.try_iter() {
+            self.window.add(&event);
+        }
+        for aggregate in self.window.close() {
+            let payload = serde_json::to_vec(&aggregate).expect("aggregates serialize");
+            let key = aggregate.client.to_string();
+            let record = FutureRecord::to(&self.topic).key(&key).payload(&payload);
+            if let Err((e, _)) = self.producer.send(record, PUBLISH_TIMEOUT).await {
+                tracing::warn!(
+                    error = %e,
+                    client = aggregate.client,
+                    start = aggregate.start,
+                    "failed to publish window aggregate"
+                );
+            }
+        }
+    }
+}
+
+fn kafka_error(error: KafkaError) -> Error {
+    Error::Io(std::io::Error::other(error))
+}
+
+// bad payloads and failed transactions are logged and count as handled, so their offsets are
+// committed; only a failed WAL append or schema registry lookup leaves the message to be
+// redelivered
+fn handle_payload(
+    engine: &mut PaymentsEngine,
+    wal: Option<&mut Wal>,
+    registry: Option<&mut SchemaRegistry>,
+    payload: Option<&[u8]>,
+) -> Result<()> {
+    let payload = payload.unwrap_or_default();
+    let parsed = match registry {
+        Some(registry) => match registry.transaction(payload) {
+            Err(Error::SchemaError(e)) => Err(e),
+            parsed => Ok(parsed?),
+        },
+        None => serde_json::from_slice::<Transaction>(payload).map_err(|e| e.to_string()),
+    };
+    let tx = match parsed {
+        Ok(tx) => tx,
+        Err(e) => {
+            tracing::warn!(error = %e, "skipping invalid transaction message");
+            return Ok(());
+        }
+    };
+
+    if let Some(wal) = wal {
+        wal.append(&WalRecord::Tx(tx.clone()))?;
+    }
+    if let Err(e) = engine.process_tx(&tx) {
+        logging::failure("failed transaction", &e);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::amount;
+    use payments_engine::{DEFAULT_CURRENCY, WithdrawalLimits};
+
+    #[test]
+    fn test_handle_payload_success() {
+        let mut engine = PaymentsEngine::new();
+        let payload = br#"{"type":"deposit","client":1,"tx":1,"amount":"10.5"}"#;
+
+        handle_payload(&mut engine, None, None, Some(payload)).unwrap();
+
+        assert_eq!(
+            engine
+                .account(1)
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .available,
+            amount!(10.5)
+        );
+    }
+
+    #[test]
+    fn test_handle_payload_skips_invalid_message() {
+        let mut engine = PaymentsEngine::new();
+
+        assert!(handle_payload(&mut engine, None, None, Some(b"not json")).is_ok());
+        assert!(handle_payload(&mut engine, None, None, None).is_ok());
+        assert_eq!(engine.accounts().count(), 0);
+    }
+
+    #[test]
+    fn test_offsets_checkpoint() {
+        let path = std::env::temp_dir().join(format!(
+            "payments-engine-kafka-checkpoint-{}.json",
+            std::process::id()
+        ));
+        let mut engine = PaymentsEngine::new();
+        let mut offsets = Offsets {
+            version: OFFSETS_VERSION,
+            ..Offsets::default()
+        };
+        let payload = br#"{"type":"deposit","client":1,"tx":1,"amount":"10.5"}"#;
+        handle_payload(&mut engine, None, None, Some(payload)).unwrap();
+        offsets.record("transactions", 0, 41);
+        offsets.record("transactions", 1, 7);
+        offsets.record("transactions", 0, 42);
+
+        checkpoint::save(&path, &offsets, &mut engine).unwrap();
+        let (saved, state) = checkpoint::load::<Offsets>(&path).unwrap().unwrap();
+        let restored = PaymentsEngine::restore(state).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(saved, offsets);
+        assert_eq!(
+            saved.partition_list().unwrap().elements()[0].offset(),
+            Offset::Offset(43)
+        );
+        assert_eq!(
+            restored
+                .account(1)
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .available,
+            amount!(10.5)
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_keeps_withdrawal_windows() {
+        let path = std::env::temp_dir().join(format!(
+            "payments-engine-kafka-windows-{}.json",
+            std::process::id()
+        ));
+        let builder = || {
+            PaymentsEngine::builder()
+                .withdrawal_limits(WithdrawalLimits::from_toml("daily = \"100\"").unwrap())
+        };
+        let mut engine = builder().build();
+        for payload in [
+            &br#"{"type":"deposit","client":1,"tx":1,"amount":"200","timestamp":0}"#[..],
+            br#"{"type":"withdrawal","client":1,"tx":2,"amount":"60","timestamp":10}"#,
+        ] {
+            handle_payload(&mut engine, None, None, Some(payload)).unwrap();
+        }
+        let offsets = Offsets {
+            version: OFFSETS_VERSION,
+            ..Offsets::default()
+        };
+
+        checkpoint::save(&path, &offsets, &mut engine).unwrap();
+        let (_, state) = checkpoint::load::<Offsets>(&path).unwrap().unwrap();
+        let mut restored = builder().restore(state).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // the second withdrawal would only fit a window that forgot the first
+        let payload = br#"{"type":"withdrawal","client":1,"tx":3,"amount":"50","timestamp":20}"#;
+        handle_payload(&mut restored, None, None, Some(payload)).unwrap();
+
+        assert_eq!(
+            restored
+                .account(1)
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .available,
+            amount!(140)
+        );
+    }
+
+    #[test]
+    fn test_handle_payload_continues_past_failed_transaction() {
+        let mut engine = PaymentsEngine::new();
+        let payload = br#"{"type":"withdrawal","client":1,"tx":1,"amount":"10"}"#;
+
+        assert!(handle_payload(&mut engine, None, None, Some(payload)).is_ok());
+    }
+}
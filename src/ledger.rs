@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::ops::Deref;
+
+use crate::amount::Amount;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::Account,
+    error::{Error, Result},
+};
+
+/// An account of the double-entry ledger behind the balances. Every client balance has its own
+/// `Available` and `Held`, while `Suspense` (the other side of funds entering and leaving the
+/// engine) and `ChargebackLoss` (funds lost to chargebacks) are the engine's, one per currency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerAccount {
+    Available,
+    Held,
+    Suspense,
+    ChargebackLoss,
+}
+
+/// A balanced double-entry posting: `amount` debited to `debit` and credited to `credit`.
+///
+/// A ledger account's balance is its credits less its debits, so across all ledger accounts the
+/// balances of a currency always sum to zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Posting {
+    pub debit: LedgerAccount,
+    pub credit: LedgerAccount,
+    pub amount: Amount,
+}
+
+impl Posting {
+    pub fn new(debit: LedgerAccount, credit: LedgerAccount, amount: Amount) -> Self {
+        Self {
+            debit,
+            credit,
+            amount,
+        }
+    }
+}
+
+/// The postings a single balance operation made, at most two. Derefs to a slice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Postings {
+    entries: [Posting; 2],
+    len: usize,
+}
+
+impl Postings {
+    pub(crate) fn none() -> Self {
+        Self {
+            entries: [Posting::new(
+                LedgerAccount::Suspense,
+                LedgerAccount::Suspense,
+                Amount::ZERO,
+            ); 2],
+            len: 0,
+        }
+    }
+
+    pub(crate) fn one(posting: Posting) -> Self {
+        Self {
+            entries: [posting; 2],
+            len: 1,
+        }
+    }
+
+    pub(crate) fn two(first: Posting, second: Posting) -> Self {
+        Self {
+            entries: [first, second],
+            len: 2,
+        }
+    }
+}
+
+impl Deref for Postings {
+    type Target = [Posting];
+
+    fn deref(&self) -> &[Posting] {
+        &self.entries[..self.len]
+    }
+}
+
+/// The engine's side of the ledger: the `Suspense` and `ChargebackLoss` balances per currency.
+/// The client side lives in each [`Balance`](crate::Balance).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Ledger {
+    balances: BTreeMap<String, EngineBalances>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+struct EngineBalances {
+    suspense: Amount,
+    chargeback_loss: Amount,
+}
+
+impl EngineBalances {
+    fn get_mut(&mut self, account: LedgerAccount) -> Option<&mut Amount> {
+        match account {
+            LedgerAccount::Suspense => Some(&mut self.suspense),
+            LedgerAccount::ChargebackLoss => Some(&mut self.chargeback_loss),
+            LedgerAccount::Available | LedgerAccount::Held => None,
+        }
+    }
+}
+
+impl Ledger {
+    /// The balance of the engine's ledger `account` in `currency`; zero for the client accounts.
+    pub fn balance(&self, currency: &str, account: LedgerAccount) -> Amount {
+        let mut balances = self.balances.get(currency).copied().unwrap_or_default();
+        balances.get_mut(account).copied().unwrap_or_default()
+    }
+
+    /// The currencies the engine has posted to.
+    pub fn currencies(&self) -> impl Iterator<Item = &str> {
+        self.balances.keys().map(String::as_str)
+    }
+
+    // post the engine's legs of `postings`--the client's legs were posted to its balance already.
+    // Worked out in full first, so an overflow posts nothing
+    pub(crate) fn post(&mut self, currency: &str, postings: &[Posting]) -> Result<()> {
+        let mut balances = self.balances.get(currency).copied().unwrap_or_default();
+        for posting in postings {
+            for (account, credit) in [(posting.debit, false), (posting.credit, true)] {
+                let Some(balance) = balances.get_mut(account) else {
+                    continue;
+                };
+                let posted = match credit {
+                    true => balance.checked_add(posting.amount),
+                    false => balance.checked_sub(posting.amount),
+                };
+                *balance = posted.ok_or(Error::EngineError(
+                    "Overflow Error: invalid ledger posting.",
+                ))?;
+            }
+        }
+        match self.balances.get_mut(currency) {
+            Some(existing) => *existing = balances,
+            None => {
+                self.balances.insert(currency.to_owned(), balances);
+            }
+        }
+
+        Ok(())
+    }
+
+    // take back the engine's legs of `postings`, posted before. They were posted without
+    // overflowing, so undoing them can't overflow either
+    pub(crate) fn unpost(&mut self, currency: &str, postings: &[Posting]) {
+        let Some(balances) = self.balances.get_mut(currency) else {
+            return;
+        };
+        for posting in postings {
+            for (account, credit) in [(posting.debit, false), (posting.credit, true)] {
+                let Some(balance) = balances.get_mut(account) else {
+                    continue;
+                };
+                match credit {
+                    true => *balance -= posting.amount,
+                    false => *balance += posting.amount,
+                }
+            }
+        }
+    }
+
+    // the engine accounts of this ledger and `other` added up, e.g. to merge the states of engines
+    // that each processed a share of the clients
+    pub(crate) fn combine(&self, other: &Ledger) -> Result<Ledger> {
+        let mut combined = self.clone();
+        for (currency, theirs) in &other.balances {
+            let ours = combined.balances.entry(currency.clone()).or_default();
+            let overflow = || Error::EngineError("Overflow Error: invalid ledger posting.");
+            ours.suspense = ours
+                .suspense
+                .checked_add(theirs.suspense)
+                .ok_or_else(overflow)?;
+            ours.chargeback_loss = ours
+                .chargeback_loss
+                .checked_add(theirs.chargeback_loss)
+                .ok_or_else(overflow)?;
+        }
+
+        Ok(combined)
+    }
+
+    // in every currency the clients' `available` and `held` and the engine's accounts must sum
+    // to zero, or some balance changed without a matching posting
+    pub(crate) fn check<'a>(&self, accounts: impl Iterator<Item = &'a Account>) -> Result<()> {
+        let mut sums = BTreeMap::<&str, Amount>::new();
+        let mut add = |currency, amount: Amount| {
+            let sum = sums.entry(currency).or_default();
+            *sum = sum
+                .checked_add(amount)
+                .ok_or(Error::EngineError("Ledger does not balance."))?;
+            Ok::<_, Error>(())
+        };
+        for (currency, balances) in &self.balances {
+            add(currency.as_str(), balances.suspense)?;
+            add(currency.as_str(), balances.chargeback_loss)?;
+        }
+        for account in accounts {
+            for (currency, balance) in &account.balances {
+                add(currency.as_str(), balance.total)?;
+            }
+        }
+        if sums.values().any(|sum| *sum != Amount::ZERO) {
+            return Err(Error::EngineError("Ledger does not balance."));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+
+    #[test]
+    fn test_post_success() {
+        let mut ledger = Ledger::default();
+
+        ledger
+            .post(
+                "",
+                &Postings::one(Posting::new(
+                    LedgerAccount::Suspense,
+                    LedgerAccount::Available,
+                    amount!(100),
+                )),
+            )
+            .unwrap();
+        ledger
+            .post(
+                "",
+                &Postings::two(
+                    Posting::new(LedgerAccount::Available, LedgerAccount::Held, amount!(30)),
+                    Posting::new(
+                        LedgerAccount::Held,
+                        LedgerAccount::ChargebackLoss,
+                        amount!(30),
+                    ),
+                ),
+            )
+            .unwrap();
+
+        assert_eq!(ledger.balance("", LedgerAccount::Suspense), amount!(-100));
+        assert_eq!(
+            ledger.balance("", LedgerAccount::ChargebackLoss),
+            amount!(30)
+        );
+        assert_eq!(ledger.balance("", LedgerAccount::Available), amount!(0));
+        assert_eq!(ledger.balance("EUR", LedgerAccount::Suspense), amount!(0));
+    }
+}
@@ -0,0 +1,117 @@
+//! Core payments engine: applies deposits, withdrawals, disputes, resolves and chargebacks to
+//! client accounts.
+//!
+//! Feed parsed [`Transaction`]s to [`PaymentsEngine::process_tx`] one at a time, in input order,
+//! then read the resulting balances through [`PaymentsEngine::accounts`] or
+//! [`PaymentsEngine::account`]. A failed transaction leaves engine state untouched, so callers
+//! can decide per [`Error`] whether to continue. [`PaymentsEngine::builder`] configures how the
+//! engine handles duplicates, cross-client references and chargeback locks, where it emits an
+//! [`Event`] for every state change, which [`EngineObserver`]s it calls back as it goes, and
+//! which [`Middleware`] wraps `process_tx`.
+//!
+//! [`testing`] builds transactions and whole scenarios of them for tests against the engine.
+//!
+//! With the `arrow` feature, [`PaymentsEngine`] also takes transactions and returns accounts as
+//! Arrow record batches, for data pipelines that already hold them in that form.
+//!
+//! [`SharedPaymentsEngine`] runs an engine on a thread of its own behind a cloneable, thread-safe
+//! handle, so several threads of a host application can submit transactions and query accounts
+//! without a mutex around the whole engine.
+//!
+//! With the `tokio` feature, `AsyncPaymentsEngine` runs an engine on its own task and exposes it
+//! through a cloneable handle for use from async services, or runs an engine per client so that
+//! different clients' transactions are applied concurrently. The `concurrent-map` feature adds a
+//! third way, keeping an engine per client in a concurrent map and applying transactions on the
+//! caller's task. `Simulation` replays the per-client scheduling on a single thread, in an order
+//! picked from a seed, so races between clients' engines can be found and reproduced.
+//!
+//! ```
+//! use payments_engine::{DEFAULT_CURRENCY, PaymentsEngine, Transaction, TransactionType, amount};
+//!
+//! let mut engine = PaymentsEngine::new();
+//! let deposit = Transaction {
+//!     tx_type: TransactionType::Deposit,
+//!     account_id: 1,
+//!     tx_id: 1,
+//!     amount: Some(amount!(10.5)),
+//!     currency: None,
+//!     timestamp: None,
+//!     reason: None,
+//!     effective: None,
+//! };
+//! engine.process_tx(&deposit).unwrap();
+//!
+//! assert_eq!(engine.account(1).unwrap().balance(DEFAULT_CURRENCY).available, amount!(10.5));
+//! ```
+
+mod account;
+#[cfg(feature = "tokio")]
+mod actors;
+mod amount;
+mod archive;
+#[cfg(feature = "arrow")]
+mod arrow;
+#[cfg(feature = "tokio")]
+mod async_engine;
+mod audit;
+#[cfg(feature = "disk-store")]
+mod bloom;
+mod custom;
+mod engine;
+mod error;
+mod events;
+mod fees;
+mod hash;
+mod history;
+mod interest;
+mod journal;
+mod ledger;
+mod limits;
+mod middleware;
+mod observer;
+mod pending;
+mod risk;
+mod rows;
+#[cfg(feature = "concurrent-map")]
+mod sharded;
+mod shared;
+#[cfg(feature = "tokio")]
+mod simulation;
+mod snapshot;
+mod store;
+pub mod testing;
+mod thresholds;
+mod transaction;
+
+pub use account::{Account, AccountStatus, AmountLimits, Balance};
+pub use amount::{AMOUNT_DP, Amount, AmountExt, PrecisionPolicy, RoundingMode};
+pub use archive::{ArchiveSink, JsonlArchive};
+#[cfg(feature = "tokio")]
+pub use async_engine::AsyncPaymentsEngine;
+pub use audit::{AuditRecord, AuditSink, CsvAuditSink, JsonlAuditSink};
+pub use custom::{AccountHandle, CustomHandler, CustomTransaction};
+pub use engine::{
+    AccountMismatchPolicy, DuplicatePolicy, EvictionPolicy, LockPolicy, NegativeAvailablePolicy,
+    PaymentsEngine, PaymentsEngineBuilder,
+};
+pub use error::{Error, ErrorCategory, ErrorCode, ErrorContext, Result};
+pub use events::{Event, EventSink, JsonlSink};
+pub use fees::{FeeRule, FeeSchedule};
+pub use history::HistoryEntry;
+pub use interest::InterestRates;
+pub use journal::{GlJournalSink, GlMapping};
+pub use ledger::{Ledger, LedgerAccount, Posting, Postings};
+pub use limits::{WithdrawalLimit, WithdrawalLimits};
+pub use middleware::{Middleware, Next};
+pub use observer::EngineObserver;
+pub use pending::{PendingDisputes, PendingOverflow};
+pub use risk::{RiskAction, RiskRule, RiskRules};
+pub use rows::{Row, RowParser};
+pub use shared::SharedPaymentsEngine;
+#[cfg(feature = "tokio")]
+pub use simulation::{Simulation, SimulationRun};
+pub use store::TxStore;
+pub use thresholds::{BalanceThreshold, BalanceThresholds, ThresholdKind};
+pub use transaction::{
+    DEFAULT_CURRENCY, DisputeStatus, Transaction, TransactionRow, TransactionType, TxRecord,
+};
@@ -0,0 +1,363 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    amount::Amount,
+    error::{Error, Result},
+    hash::HashMap,
+    transaction::{Transaction, TransactionType},
+};
+
+/// How long the daily withdrawal total looks back, in seconds.
+pub const DAILY_WINDOW: u64 = 24 * 60 * 60;
+
+/// Caps on what a client may withdraw in one currency. Unset caps don't apply.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WithdrawalLimit {
+    /// The most a single withdrawal may take.
+    pub single: Option<Amount>,
+    /// The most a client's withdrawals may take in total over the past 24 hours.
+    pub daily: Option<Amount>,
+}
+
+/// Withdrawal caps enforced as withdrawals and authorizations are applied, globally and per
+/// client, along with the recent withdrawals the daily caps are measured against. Amounts in
+/// different currencies are capped separately.
+///
+/// The daily cap is a rolling total over the 24 hours up to each withdrawal, going by the
+/// transactions' timestamps, so while one applies a withdrawal without a timestamp is refused.
+///
+/// Written as TOML, with a client's own caps taking the place of the global ones they set:
+///
+/// ```toml
+/// single = "1000"
+/// daily = "5000"
+///
+/// [[clients]]
+/// client = 7
+/// daily = "100"
+/// ```
+///
+/// The recent withdrawals are saved in engine snapshots, so an engine restored with limits
+/// measures the daily caps against the withdrawals made before the snapshot too.
+#[derive(Debug, Clone, Default)]
+pub struct WithdrawalLimits {
+    global: WithdrawalLimit,
+    per_client: HashMap<u16, WithdrawalLimit>,
+    // timestamp and amount of every withdrawal in the past day, oldest first, per client and
+    // currency
+    recent: HashMap<(u16, String), VecDeque<(u64, Amount)>>,
+}
+
+// a client's withdrawals of the past day in one currency, as saved in snapshots
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RecentWithdrawals {
+    client: u16,
+    currency: String,
+    withdrawals: VecDeque<(u64, Amount)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WithdrawalLimitsFile {
+    #[serde(default)]
+    single: Option<Amount>,
+    #[serde(default)]
+    daily: Option<Amount>,
+    #[serde(default)]
+    clients: Vec<ClientLimit>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientLimit {
+    client: u16,
+    #[serde(default)]
+    single: Option<Amount>,
+    #[serde(default)]
+    daily: Option<Amount>,
+}
+
+impl WithdrawalLimits {
+    /// Limits applying `global` to every client but those in `per_client`, whose caps take the
+    /// place of the global ones they set.
+    pub fn new(
+        global: WithdrawalLimit,
+        per_client: impl IntoIterator<Item = (u16, WithdrawalLimit)>,
+    ) -> Self {
+        Self {
+            global,
+            per_client: per_client.into_iter().collect(),
+            recent: HashMap::default(),
+        }
+    }
+
+    /// Parses limits from TOML, failing with [`Error::ConfigError`] if a client is listed twice.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let file: WithdrawalLimitsFile =
+            toml::from_str(text).map_err(|e| Error::ConfigError(e.to_string()))?;
+        let mut per_client = HashMap::default();
+        for entry in file.clients {
+            let limit = WithdrawalLimit {
+                single: entry.single,
+                daily: entry.daily,
+            };
+            if per_client.insert(entry.client, limit).is_some() {
+                return Err(Error::ConfigError(format!(
+                    "client {} has more than one withdrawal limit",
+                    entry.client
+                )));
+            }
+        }
+
+        Ok(Self::new(
+            WithdrawalLimit {
+                single: file.single,
+                daily: file.daily,
+            },
+            per_client,
+        ))
+    }
+
+    /// The caps that apply to `client`.
+    pub fn limit(&self, client: u16) -> WithdrawalLimit {
+        match self.per_client.get(&client) {
+            Some(own) => WithdrawalLimit {
+                single: own.single.or(self.global.single),
+                daily: own.daily.or(self.global.daily),
+            },
+            None => self.global,
+        }
+    }
+
+    // fail with `Error::LimitExceeded` if the withdrawal or authorization `tx` would go over its
+    // client's caps
+    pub(crate) fn check(&self, tx: &Transaction) -> Result<()> {
+        if !Self::counts(tx.tx_type) {
+            return Ok(());
+        }
+        let Some(amount) = tx.amount else {
+            return Ok(());
+        };
+        let limit = self.limit(tx.account_id);
+        if limit.single.is_some_and(|single| amount > single) {
+            return Err(Error::LimitExceeded(
+                "Withdrawal is above the single withdrawal limit.",
+            ));
+        }
+        let Some(daily) = limit.daily else {
+            return Ok(());
+        };
+        let Some(now) = tx.timestamp else {
+            return Err(Error::TransactionError(
+                "Withdrawal has no timestamp to apply the daily limit by.",
+            ));
+        };
+        let mut total = amount;
+        if let Some(recent) = self
+            .recent
+            .get(&(tx.account_id, tx.currency_code().to_string()))
+        {
+            for &(at, withdrawn) in recent {
+                if now.saturating_sub(at) < DAILY_WINDOW {
+                    total += withdrawn;
+                }
+            }
+        }
+        if total > daily {
+            return Err(Error::LimitExceeded(
+                "Withdrawal would exceed the daily withdrawal limit.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // add the applied withdrawal or authorization `tx` to its client's recent withdrawals
+    pub(crate) fn record(&mut self, tx: &Transaction) {
+        let (Some(amount), Some(now)) = (tx.amount, tx.timestamp) else {
+            return;
+        };
+        if !Self::counts(tx.tx_type) || self.limit(tx.account_id).daily.is_none() {
+            return;
+        }
+        let recent = self
+            .recent
+            .entry((tx.account_id, tx.currency_code().to_string()))
+            .or_default();
+        while recent
+            .front()
+            .is_some_and(|&(at, _)| now.saturating_sub(at) >= DAILY_WINDOW)
+        {
+            recent.pop_front();
+        }
+        recent.push_back((now, amount));
+    }
+
+    // every client's recent withdrawals, in client and currency order
+    pub(crate) fn recent(&self) -> Vec<RecentWithdrawals> {
+        let mut recent: Vec<_> = self
+            .recent
+            .iter()
+            .map(|((client, currency), withdrawals)| RecentWithdrawals {
+                client: *client,
+                currency: currency.clone(),
+                withdrawals: withdrawals.clone(),
+            })
+            .collect();
+        recent.sort_by(|a, b| (a.client, &a.currency).cmp(&(b.client, &b.currency)));
+        recent
+    }
+
+    // take up the recent withdrawals of a snapshot or of another engine's limits
+    pub(crate) fn restore_recent(&mut self, recent: Vec<RecentWithdrawals>) {
+        for entry in recent {
+            self.recent
+                .insert((entry.client, entry.currency), entry.withdrawals);
+        }
+    }
+
+    fn counts(tx_type: TransactionType) -> bool {
+        matches!(
+            tx_type,
+            TransactionType::Withdrawal | TransactionType::Authorize
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::testing::TxBuilder;
+
+    #[test]
+    fn test_from_toml() {
+        let limits = WithdrawalLimits::from_toml(
+            r#"
+            single = "100"
+            daily = "500"
+            [[clients]]
+            client = 7
+            daily = "50"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            limits.limit(1),
+            WithdrawalLimit {
+                single: Some(amount!(100)),
+                daily: Some(amount!(500)),
+            }
+        );
+        assert_eq!(
+            limits.limit(7),
+            WithdrawalLimit {
+                single: Some(amount!(100)),
+                daily: Some(amount!(50)),
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_toml_failure() {
+        for text in [
+            "[[clients]]\nclient = 1\ndaily = \"5\"\n[[clients]]\nclient = 1\nsingle = \"5\"",
+            "weekly = \"5\"",
+        ] {
+            assert!(
+                matches!(
+                    WithdrawalLimits::from_toml(text),
+                    Err(Error::ConfigError(_))
+                ),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_single() {
+        let limits = WithdrawalLimits::new(
+            WithdrawalLimit {
+                single: Some(amount!(100)),
+                daily: None,
+            },
+            [],
+        );
+
+        assert!(
+            limits
+                .check(&TxBuilder::withdrawal(1, 1, amount!(100)).build())
+                .is_ok()
+        );
+        assert!(matches!(
+            limits.check(&TxBuilder::withdrawal(1, 1, amount!(100.01)).build()),
+            Err(Error::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_daily() {
+        let mut limits = WithdrawalLimits::new(
+            WithdrawalLimit::default(),
+            [(
+                1,
+                WithdrawalLimit {
+                    single: None,
+                    daily: Some(amount!(100)),
+                },
+            )],
+        );
+
+        limits.record(
+            &TxBuilder::withdrawal(1, 1, amount!(60))
+                .timestamp(0)
+                .build(),
+        );
+        limits.record(
+            &TxBuilder::withdrawal(1, 1, amount!(30))
+                .timestamp(1_000)
+                .build(),
+        );
+        assert!(
+            limits
+                .check(
+                    &TxBuilder::withdrawal(1, 1, amount!(10))
+                        .timestamp(2_000)
+                        .build()
+                )
+                .is_ok()
+        );
+        assert!(matches!(
+            limits.check(
+                &TxBuilder::withdrawal(1, 1, amount!(11))
+                    .timestamp(2_000)
+                    .build()
+            ),
+            Err(Error::LimitExceeded(_))
+        ));
+        // the first withdrawal has rolled out of the window
+        assert!(
+            limits
+                .check(
+                    &TxBuilder::withdrawal(1, 1, amount!(70))
+                        .timestamp(DAILY_WINDOW)
+                        .build()
+                )
+                .is_ok()
+        );
+        assert!(matches!(
+            limits.check(&TxBuilder::withdrawal(1, 1, amount!(10)).build()),
+            Err(Error::TransactionError(_))
+        ));
+        // other clients aren't capped
+        assert!(
+            limits
+                .check(&TxBuilder::withdrawal(2, 1, amount!(1_000)).build())
+                .is_ok()
+        );
+    }
+}
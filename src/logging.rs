@@ -0,0 +1,62 @@
+use payments_engine::Error;
+use tracing::level_filters::LevelFilter;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    // adds a span per transaction, and whether it was applied
+    Debug,
+    Trace,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    // one JSON object per line, with the fields of the enclosing spans
+    Json,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+// logs go to stderr, leaving stdout for the account output
+pub fn init(level: LogLevel, format: LogFormat) {
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().with_span_list(true).init(),
+    }
+}
+
+// a failed row or merge as a structured event: where it happened as separate fields (taken from
+// the error's context), plus its code and message
+pub fn failure(message: &str, error: &Error) {
+    let context = error.context().cloned().unwrap_or_default();
+    tracing::warn!(
+        line = context.line,
+        byte = context.byte,
+        tx_id = context.tx_id,
+        client = context.account_id,
+        tx_type = context.tx_type.map(tracing::field::display),
+        code = %error.code(),
+        error = %error.root(),
+        record = context.record,
+        "{}",
+        message
+    );
+}
@@ -1,52 +1,1912 @@
-use std::env;
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
-
-use crate::{engine::PaymentsEngine, error::Result};
-
-mod account;
-mod engine;
-mod error;
-mod transaction;
-
-fn main() -> Result<()> {
-    let mut engine = PaymentsEngine::new();
-
-    let fpath = env::args().nth(1).expect("Usage: cargo run -- {file_path}");
-    let file = File::open(fpath)?;
-    let reader = BufReader::new(file);
-    let mut rdr = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_reader(reader);
-
-    for result in rdr.deserialize() {
-        // make sure csv row is a valid transaciton, ignore if not
-        match result {
-            Ok(tx) => {
-                // if processing fails, log error to stderr and continue processing txs
-                if let Err(e) = engine.process_tx(&tx) {
-                    eprintln!("failed transaction: {}", e);
-                    continue;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use payments_engine::{
+    AccountMismatchPolicy, Amount, AmountLimits, ArchiveSink, AuditSink, BalanceThresholds,
+    CsvAuditSink, DuplicatePolicy, Error, ErrorCategory, EventSink, EvictionPolicy, FeeSchedule,
+    GlJournalSink, GlMapping, InterestRates, JsonlArchive, JsonlAuditSink, JsonlSink, LockPolicy,
+    NegativeAvailablePolicy, PaymentsEngine, PaymentsEngineBuilder, PendingDisputes,
+    PendingOverflow, PrecisionPolicy, Result, RiskRules, RoundingMode, TxRecord, TxStore,
+    WithdrawalLimits,
+};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::{
+    checkpoint::Checkpointer,
+    compression::Compression,
+    config::EngineConfig,
+    dates::SECS_PER_DAY,
+    ingest::Ingest,
+    inputs::InputOrder,
+    iso20022::InputFormat,
+    logging::{LogFormat, LogLevel},
+    manifest::{ProcessedFiles, Reprocess},
+    ofx::StatementFormat,
+    output::{OutputFormat, write_accounts},
+    policy::{ErrorAction, ErrorPolicy, RejectSink},
+    rules::Rules,
+    shadow::Shadow,
+    signature::RowVerifier,
+    summary::Summary,
+    wal::{Wal, WalRecord},
+};
+
+#[cfg(feature = "kafka")]
+mod aggregates;
+#[cfg(feature = "kafka")]
+mod avro;
+mod camt053;
+mod checkpoint;
+#[cfg(feature = "client")]
+mod client;
+mod compression;
+mod config;
+mod crypt;
+mod dates;
+mod diff;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod ingest;
+mod inputs;
+mod inspect;
+mod iso20022;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod logging;
+mod manifest;
+#[cfg(feature = "server")]
+mod metrics;
+mod nacha;
+mod ofx;
+mod output;
+#[cfg(feature = "parquet")]
+mod parquet_archive;
+mod policy;
+mod remote;
+mod repl;
+mod replay;
+#[cfg(any(feature = "server", feature = "grpc"))]
+mod roles;
+mod rotate;
+mod rules;
+mod seed;
+mod selftest;
+#[cfg(feature = "server")]
+mod server;
+mod shadow;
+mod signature;
+mod summary;
+mod wal;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+// conventional path for reading input from stdin
+const STDIN_PATH: &str = "-";
+// conventional path for writing --events to stdout
+const STDOUT_PATH: &str = "-";
+// conventional path for writing the --summary report to stderr
+const STDERR_PATH: &str = "-";
+
+#[derive(Clone, Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Most verbose level of log messages written to stderr (`debug` adds a span per
+    /// transaction)
+    #[arg(long, value_enum, global = true, default_value_t = LogLevel::Info)]
+    log_level: LogLevel,
+
+    /// Format of log messages: human-readable text or one JSON object per line
+    #[arg(long, value_enum, global = true, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Encrypt saved state, checkpoints, the write-ahead log and the --events and --audit files
+    /// with AES-256-GCM under the key in this file (32 bytes, or 64 hex digits)
+    #[arg(long, value_name = "PATH", global = true)]
+    encryption_key: Option<PathBuf>,
+
+    /// Also read files encrypted under the key in this file, e.g. the one --encryption-key
+    /// replaced; may be repeated
+    #[arg(long, value_name = "PATH", global = true)]
+    decryption_key: Vec<PathBuf>,
+
+    /// Transactions CSV files, directories of them, or `s3://`/`gs://` object URLs (needs the
+    /// `object-store` feature), processed in order into one engine; reads stdin when omitted or
+    /// `-`
+    #[arg(conflicts_with = "manifest")]
+    input: Vec<PathBuf>,
+
+    /// Order the files in an input directory are processed in: by `name` (lexicographic) or by
+    /// modification time (`mtime`, oldest first)
+    #[arg(long, value_enum, default_value_t = InputOrder::Name)]
+    input_order: InputOrder,
+
+    /// How input files (and manifest batches) are compressed: `auto` goes by extension (`.gz`
+    /// is gzip, `.zst` is zstd), while `none`, `gzip` and `zstd` apply to every input, stdin
+    /// included (needs the `compression` feature to decompress)
+    #[arg(long, value_enum, default_value_t = Compression::Auto)]
+    compression: Compression,
+
+    /// Read input files (and manifest batches) through a read-only memory map instead of
+    /// buffered reads; stdin is still streamed (needs the `mmap` feature)
+    #[arg(long)]
+    mmap: bool,
+
+    /// Format of the input files: `auto` goes by extension (`.xml`, compressed or not, is
+    /// ISO 20022), while `csv` and `iso20022` apply to every input, stdin included (ISO 20022
+    /// needs the `iso20022` feature)
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    input_format: InputFormat,
+
+    /// CSV (account,client) mapping the IBANs or other account ids in ISO 20022 input to
+    /// client ids
+    #[arg(long, value_name = "PATH")]
+    iso_accounts: Option<PathBuf>,
+
+    /// Read engine behavior (policies, limits, precision and the like) from the TOML file at PATH,
+    /// keyed like the options below; options given on the command line take precedence
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Process the batches listed in a manifest CSV (seq,path,rows,sha256) in sequence, after
+    /// validating that none are missing, out of order, or altered
+    #[arg(long, value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// Keep a list of the input files processed, by name and sha256 of their contents, in the CSV
+    /// at PATH, and pass over inputs with the same contents as one processed before; needs
+    /// --save-state or --wal-dir to carry the state they went into over to the next run
+    #[arg(long, value_name = "PATH")]
+    processed: Option<PathBuf>,
+
+    /// What to do with an input processed before: `skip` it with a warning, or `refuse` the run
+    #[arg(long, value_enum, default_value_t = Reprocess::Skip, requires = "processed")]
+    reprocess: Reprocess,
+
+    /// Require every row to carry a valid HMAC-SHA256 `signature` column, keyed with the
+    /// contents of this file; unsigned or tampered rows are rejected
+    #[arg(long, value_name = "PATH")]
+    hmac_key_file: Option<PathBuf>,
+
+    /// Evaluate the Rhai rules script at PATH against every transaction before it is applied;
+    /// the script can reject a transaction or rewrite its amount; needs the `rules` feature
+    #[arg(long, value_name = "PATH")]
+    rules: Option<PathBuf>,
+
+    /// Handle failures in CATEGORY (parse, insufficient-funds, locked-account,
+    /// unknown-reference, duplicate, amount-limit, other) with ACTION (skip, warn, quarantine, abort)
+    /// (repeatable)
+    #[arg(long = "on-error", value_name = "CATEGORY=ACTION", value_parser = parse_error_action)]
+    error_actions: Vec<(ErrorCategory, ErrorAction)>,
+
+    /// How failed rows are handled by default: `skip` logs and drops them, `fail` stops at the
+    /// first one and exits non-zero, `collect` processes everything and then exits non-zero if any
+    /// row failed; --on-error overrides single categories
+    #[arg(long, value_enum, default_value_t = ErrorPolicyMode::Skip)]
+    error_policy: ErrorPolicyMode,
+
+    /// Shorthand for --error-policy fail
+    #[arg(long, conflicts_with = "error_policy")]
+    strict: bool,
+
+    /// Write rows handled with the `quarantine` action to this CSV file
+    #[arg(long, value_name = "PATH")]
+    quarantine: Option<PathBuf>,
+
+    /// Write every skipped or failed row to this CSV file (line number, error code, error, then
+    /// the original fields), whatever --on-error does with it
+    #[arg(long, value_name = "PATH")]
+    rejects: Option<PathBuf>,
+
+    /// How to handle deposits/withdrawals reusing an already applied tx id: `reject` logs and
+    /// drops them, `skip` drops them silently (idempotent re-processing), `error` fails the run
+    #[arg(long, value_enum, default_value_t = DuplicateMode::Reject)]
+    duplicates: DuplicateMode,
+
+    /// What to do with amounts that have more than 4 decimal places: `round` them (see
+    /// --rounding-mode), `truncate` the extra places, or `reject` the row
+    #[arg(long, value_enum, default_value_t = PrecisionMode::Round)]
+    precision: PrecisionMode,
+
+    /// How --precision round rounds: `half-even` (banker's rounding), `half-up`/`half-down`
+    /// (halfway cases away from/toward zero), `ceiling` or `floor`
+    #[arg(long, value_enum, default_value_t = RoundingModeArg::HalfEven)]
+    rounding_mode: RoundingModeArg,
+
+    /// Reject deposits and withdrawals below AMOUNT, e.g. 0.0001 to refuse zero amounts
+    /// (`amount-below-minimum`)
+    #[arg(long, value_name = "AMOUNT")]
+    min_amount: Option<Amount>,
+
+    /// Reject deposits and withdrawals above AMOUNT (`amount-above-maximum`)
+    #[arg(long, value_name = "AMOUNT")]
+    max_amount: Option<Amount>,
+
+    /// Whether a chargeback locks the account: `on-chargeback`, or `never` to only reverse the
+    /// funds
+    #[arg(long, value_enum, default_value_t = LockMode::OnChargeback)]
+    lock_policy: LockMode,
+
+    /// How to handle a dispute, resolve, chargeback or clear referencing another client's
+    /// transaction: `reject` fails the row, `ignore` drops it without an error
+    #[arg(long, value_enum, default_value_t = AccountMismatchMode::Reject)]
+    account_mismatch: AccountMismatchMode,
+
+    /// Whether a dispute may hold funds the client no longer has available, driving `available`
+    /// negative (`allow`), or fails with `insufficient-funds` (`reject`)
+    #[arg(long, value_enum, default_value_t = NegativeAvailableMode::Allow)]
+    negative_available: NegativeAvailableMode,
+
+    /// Reject disputes arriving more than DAYS after the disputed transaction
+    /// (`dispute-window-expired`); needs a timestamp column (seconds since the Unix epoch)
+    #[arg(long, value_name = "DAYS")]
+    dispute_window: Option<u64>,
+
+    /// Let authorization holds expire DAYS after the authorization; needs a timestamp column
+    #[arg(long, value_name = "DAYS")]
+    hold_expiry: Option<u64>,
+
+    /// Let provisional deposits clear DAYS after they were made instead of waiting for a `clear`;
+    /// needs a timestamp column
+    #[arg(long, value_name = "DAYS")]
+    clearing_period: Option<u64>,
+
+    /// Pay daily interest on available balances at PERCENT a year, for every currency or only
+    /// CURRENCY (repeatable); accrues up to each timestamped row and to --as-of
+    #[arg(long = "interest-rate", value_name = "[CURRENCY=]PERCENT", value_parser = parse_interest_rate)]
+    interest_rates: Vec<(Option<String>, Decimal)>,
+
+    /// Once the input has been processed, accrue interest up to TIMESTAMP (seconds since the Unix
+    /// epoch), release the authorization holds that have expired by then, clear the provisional
+    /// deposits whose clearing period has ended and, with --evict-settled, evict the
+    /// transactions past the dispute window
+    #[arg(long, value_name = "TIMESTAMP")]
+    as_of: Option<u64>,
+
+    /// Charge per-transaction fees on deposits and withdrawals by the TOML fee schedule at PATH,
+    /// crediting them to its fee account
+    #[arg(long, value_name = "PATH")]
+    fee_schedule: Option<PathBuf>,
+
+    /// Cap single withdrawals and each client's withdrawals over the past 24 hours by the TOML
+    /// limits at PATH
+    #[arg(long, value_name = "PATH")]
+    withdrawal_limits: Option<PathBuf>,
+
+    /// Emit a `balance_threshold_crossed` event (and webhook alert) whenever a balance change
+    /// takes `available` below or `held` above a level of the TOML thresholds at PATH
+    #[arg(long, value_name = "PATH")]
+    balance_thresholds: Option<PathBuf>,
+
+    /// Assess deposits and withdrawals by the TOML risk rules at PATH, flagging, holding or
+    /// refusing those that trip one
+    #[arg(long, value_name = "PATH")]
+    risk_rules: Option<PathBuf>,
+
+    /// Hold up to N disputes of not-yet-seen transactions and apply them once the transaction
+    /// arrives, for input that isn't perfectly ordered; disputes still waiting at the end fail as
+    /// `unknown-transaction`
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pending_disputes: usize,
+
+    /// Give up on a pending dispute once N more transactions have passed without its
+    /// transaction
+    #[arg(long, value_name = "N")]
+    pending_dispute_max_age: Option<u64>,
+
+    /// What to do with another dispute once --pending-disputes are full: `reject-new` fails it,
+    /// `evict-oldest` gives up on the oldest pending one instead
+    #[arg(long, value_enum, default_value_t = PendingOverflowMode::RejectNew)]
+    pending_overflow: PendingOverflowMode,
+
+    /// Reserve room for N client accounts up front, sparing the engine from regrowing its
+    /// account map as clients are first seen
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    expected_accounts: usize,
+
+    /// Merge client SOURCE into client TARGET once all transactions are processed (repeatable)
+    #[arg(long = "merge", value_name = "SOURCE:TARGET", value_parser = parse_merge)]
+    merges: Vec<(u16, u16)>,
+
+    /// Also apply every transaction to a shadow engine, configured like this run but for the
+    /// engine policies set in the TOML file at PATH (keyed like --config), and report where its
+    /// decisions and final balances differ; the run's own results are unaffected
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["checkpoint", "wal_dir"])]
+    shadow: Option<PathBuf>,
+
+    /// Write every divergence of the --shadow engine to PATH as a JSON line
+    #[arg(long, value_name = "PATH", requires = "shadow")]
+    shadow_report: Option<PathBuf>,
+
+    /// Start from the engine state saved by a previous run's --save-state instead of empty
+    #[arg(long, value_name = "PATH")]
+    load_state: Option<PathBuf>,
+
+    /// Seed opening balances from an accounts CSV (client,available,held,total,locked, plus an
+    /// optional currency column) before processing, e.g. a previous run's output
+    #[arg(long, value_name = "PATH", conflicts_with = "wal_dir")]
+    accounts_in: Option<PathBuf>,
+
+    /// Log every accepted transaction to a write-ahead log in DIR before applying it; on start,
+    /// the log already in DIR is replayed to rebuild the previous state
+    #[arg(long, value_name = "DIR", conflicts_with = "load_state")]
+    wal_dir: Option<PathBuf>,
+
+    /// Save the final engine state (balances, stored transactions and dispute status) to PATH
+    #[arg(long, value_name = "PATH")]
+    save_state: Option<PathBuf>,
+
+    /// Periodically save how far the input has been read, along with the engine state, to PATH,
+    /// so an interrupted run can pick up from there with --resume
+    #[arg(long, value_name = "PATH")]
+    checkpoint: Option<PathBuf>,
+
+    /// Rows between --checkpoint saves; one is also saved at the end of every input
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 100_000,
+        requires = "checkpoint"
+    )]
+    checkpoint_interval: u64,
+
+    /// Pick up from the --checkpoint file left by an interrupted run, passing over the inputs it
+    /// read to the end and the rows it covers of any it read part way; starts from the beginning
+    /// if there is none yet
+    #[arg(
+        long,
+        requires = "checkpoint",
+        conflicts_with_all = ["load_state", "accounts_in", "wal_dir"]
+    )]
+    resume: bool,
+
+    /// Write an event for every state change (deposits, withdrawals, dispute steps, locks, admin
+    /// operations, seeds and merges) to PATH as JSON lines, or to stdout when PATH is `-` (which
+    /// needs --output, so the accounts don't go to stdout too)
+    #[arg(long, value_name = "PATH")]
+    events: Option<PathBuf>,
+
+    /// POST a JSON alert to URL whenever an account is locked (e.g. by a chargeback) or crosses
+    /// a chargeback threshold, retrying with backoff; needs the `webhook` feature
+    #[arg(long, value_name = "URL")]
+    webhook_url: Option<String>,
+
+    /// Also alert the webhook once a client's chargebacks reach N
+    #[arg(long, value_name = "N", requires = "webhook_url")]
+    webhook_chargebacks: Option<u32>,
+
+    /// Also alert the webhook once the amount charged back from a client in a currency reaches
+    /// AMOUNT
+    #[arg(long, value_name = "AMOUNT", requires = "webhook_url")]
+    webhook_charged_back: Option<Amount>,
+
+    /// Record webhook alerts and how their delivery went in an outbox at PATH, as JSON lines, and
+    /// post the alerts an earlier run left undelivered there first
+    #[arg(long, value_name = "PATH", requires = "webhook_url")]
+    webhook_outbox: Option<PathBuf>,
+
+    /// Write an audit record for every balance mutation (client, tx, operation, amount and each
+    /// balance field before and after) to PATH
+    #[arg(long, value_name = "PATH")]
+    audit: Option<PathBuf>,
+
+    /// Format of the --audit log: `jsonl` or `csv`
+    #[arg(long, value_enum, default_value_t = AuditFormat::Jsonl)]
+    audit_format: AuditFormat,
+
+    /// Move the --events and --audit files aside to PATH.1, PATH.2 and so on once they reach
+    /// BYTES, carrying on in a fresh file; a file is only ever split between records
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..))]
+    rotate_size: Option<u64>,
+
+    /// Also rotate the --events and --audit files once they've been written to for SECS
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    rotate_interval: Option<u64>,
+
+    /// Write a CSV journal of the ledger postings behind every balance mutation to PATH, booked
+    /// to the GL account codes of the --gl-mapping file
+    #[arg(long, value_name = "PATH", requires = "gl_mapping")]
+    gl_journal: Option<PathBuf>,
+
+    /// TOML file mapping the ledger accounts (available, held, suspense, chargeback_loss) to GL
+    /// account codes for --gl-journal
+    #[arg(long, value_name = "PATH", requires = "gl_journal")]
+    gl_mapping: Option<PathBuf>,
+
+    /// Write an ACH file in NACHA format to PATH at the end of the run, paying each client's
+    /// available balance in the default currency out to the bank account --ach-config gives it
+    #[arg(long, value_name = "PATH", requires = "ach_config")]
+    ach: Option<PathBuf>,
+
+    /// TOML file with the originator's details and each client's bank account for --ach
+    #[arg(long, value_name = "PATH", requires = "ach")]
+    ach_config: Option<PathBuf>,
+
+    /// Day the --ach payouts settle (YYYY-MM-DD, UTC); the day after the run by default
+    #[arg(long, value_name = "DATE", value_parser = parse_date, requires = "ach")]
+    ach_effective_date: Option<u64>,
+
+    /// Write the final account state to PATH instead of stdout
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Format of the final account state: one CSV row, JSON array element or JSON line per
+    /// client (and currency, once any named currency is held)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    output_format: OutputFormat,
+
+    /// After processing, report transaction counts per type, failures per error code, accounts
+    /// created and locked, and timing to PATH, or to stderr when PATH is omitted or `-`
+    #[arg(long, value_name = "PATH", num_args = 0..=1, default_missing_value = STDERR_PATH)]
+    summary: Option<PathBuf>,
+
+    /// Where to keep the transactions later disputes can reference: `memory`, or `disk` to keep
+    /// memory bounded on very large inputs (needs the `disk-store` feature)
+    #[arg(long, value_enum, default_value_t = TxStoreMode::Memory)]
+    tx_store: TxStoreMode,
+
+    /// Directory to create the `--tx-store disk` scratch database in, as a new subdirectory that
+    /// is removed again on exit; defaults to the system temp directory
+    #[arg(long, value_name = "DIR")]
+    tx_store_dir: Option<PathBuf>,
+
+    /// Drop transactions from the store once nothing can reference them again: charged back,
+    /// reversed or fully resolved. Their ids are still caught as duplicates, but later rows
+    /// referencing them fail with `invalid-transaction`
+    #[arg(long)]
+    evict_settled: bool,
+
+    /// Write every transaction --evict-settled drops to PATH as a JSON line
+    #[arg(long, value_name = "PATH", requires = "evict_settled")]
+    archive: Option<PathBuf>,
+
+    /// Format of --archive: JSON lines, or Parquet files partitioned by day under the directory
+    /// PATH (needs the `parquet` feature)
+    #[arg(long, value_enum, default_value_t = ArchiveFormat::Jsonl)]
+    archive_format: ArchiveFormat,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ArchiveFormat {
+    Jsonl,
+    Parquet,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AuditFormat {
+    Jsonl,
+    Csv,
+}
+
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum DuplicateMode {
+    Reject,
+    Skip,
+    Error,
+}
+
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum LockMode {
+    OnChargeback,
+    Never,
+}
+
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum AccountMismatchMode {
+    Reject,
+    Ignore,
+}
+
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum NegativeAvailableMode {
+    Allow,
+    Reject,
+}
+
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum PendingOverflowMode {
+    RejectNew,
+    EvictOldest,
+}
+
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum PrecisionMode {
+    Reject,
+    Truncate,
+    Round,
+}
+
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum RoundingModeArg {
+    HalfEven,
+    HalfUp,
+    HalfDown,
+    Ceiling,
+    Floor,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ErrorPolicyMode {
+    Skip,
+    Fail,
+    Collect,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TxStoreMode {
+    Memory,
+    Disk,
+}
+
+#[derive(Clone, Subcommand)]
+enum Command {
+    /// Run the bundled end-to-end fixtures through the full pipeline and verify the outputs
+    Selftest,
+    /// Rebuild the account state from an event log written by --events and write it like a
+    /// normal run, or check it against an accounts CSV
+    Replay {
+        /// Event logs (JSON lines), e.g. rotated segments, replayed in the order given; `-`
+        /// reads stdin
+        #[arg(value_name = "EVENTS", required = true)]
+        events: Vec<PathBuf>,
+
+        /// Replay on top of the state saved by `compact-events` or --save-state
+        #[arg(long, value_name = "PATH")]
+        load_state: Option<PathBuf>,
+
+        /// Instead of writing the state, compare it with this accounts CSV (e.g. the original
+        /// run's output), report differing rows on stderr and exit non-zero on any difference
+        #[arg(long, value_name = "PATH")]
+        verify: Option<PathBuf>,
+
+        /// Format of the account state
+        #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+        output_format: OutputFormat,
+    },
+    /// Fold rotated --events segments into a state snapshot and delete them, so the log stops
+    /// growing without bound; `replay --load-state` carries on from the snapshot with the
+    /// segments written since
+    CompactEvents {
+        /// Segments to fold in; numbered ones (`events.jsonl.2`) are folded in by number
+        #[arg(value_name = "SEGMENTS", required = true)]
+        segments: Vec<PathBuf>,
+
+        /// Snapshot to write, replaced atomically; one already there is folded into first
+        #[arg(long, value_name = "PATH")]
+        save_state: PathBuf,
+    },
+    /// Write a file encrypted with --encryption-key or --decryption-key, such as an --audit log,
+    /// to stdout decrypted
+    Decrypt {
+        #[arg(value_name = "PATH")]
+        path: PathBuf,
+    },
+    /// Rewrite files under the current --encryption-key, e.g. after rotating keys, reading them
+    /// with the one they are under (or as they are, if not encrypted yet); each is replaced
+    /// atomically. Files a running process is appending to must be left alone
+    Reencrypt {
+        #[arg(value_name = "PATHS", required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Compare two account states, each an accounts CSV or a state saved by --save-state, and
+    /// write a row per client and currency whose balances or status differ; exits non-zero on
+    /// any difference
+    Diff {
+        /// The state to compare against
+        #[arg(value_name = "OLD")]
+        old: PathBuf,
+
+        /// The state compared
+        #[arg(value_name = "NEW")]
+        new: PathBuf,
+
+        /// Format of the report
+        #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+        output_format: OutputFormat,
+    },
+    /// Combine the engine states saved by --save-state of runs over inputs partitioned by client,
+    /// and write the merged account state like a normal run
+    Merge {
+        /// Engine states saved by --save-state, one per shard
+        #[arg(value_name = "STATE", required = true)]
+        states: Vec<PathBuf>,
+
+        /// Save the merged engine state to PATH
+        #[arg(long, value_name = "PATH")]
+        save_state: Option<PathBuf>,
+
+        /// Format of the account state
+        #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+        output_format: OutputFormat,
+    },
+    /// Print a client's balances, status, open disputes and stored transactions, and/or a stored
+    /// transaction, from the engine state saved by --save-state, as JSON
+    Inspect {
+        /// Engine state saved by a previous run's --save-state
+        #[arg(long, value_name = "PATH")]
+        state: PathBuf,
+
+        /// Client to print
+        #[arg(long, required_unless_present = "tx")]
+        client: Option<u16>,
+
+        /// Stored transaction to print
+        #[arg(long)]
+        tx: Option<u32>,
+
+        /// Also look transactions up in the Parquet archive in DIR, written by
+        /// `--archive-format parquet` (needs the `parquet` feature)
+        #[arg(long, value_name = "DIR")]
+        archive: Option<PathBuf>,
+    },
+    /// Apply transactions typed one per line (CSV rows or separated by spaces), printing the
+    /// client's balances after each; `help` lists the other commands
+    Repl {
+        /// Start from the engine state saved by a previous run's --save-state
+        #[arg(long, value_name = "PATH")]
+        load_state: Option<PathBuf>,
+    },
+    /// Process transaction CSVs and write each account's statement for a date range as OFX or
+    /// camt.053, one `<client>.ofx` or `<client>.xml` file per account, for importing into
+    /// accounting and treasury tools
+    Export {
+        /// Input CSV files; `-` or none reads stdin
+        #[arg(value_name = "FILE")]
+        input: Vec<PathBuf>,
+
+        /// First day of the statements (YYYY-MM-DD, UTC); transactions are placed by their
+        /// timestamp column
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        from: u64,
+
+        /// Last day of the statements, included
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        to: u64,
+
+        /// Directory the statements are written to
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        dir: PathBuf,
+
+        /// Currency code amounts without a currency are reported in
+        #[arg(long, value_name = "CODE", default_value = "USD")]
+        default_currency: String,
+
+        /// Statement layout: `ofx` (OFX 2.2) or `camt053` (ISO 20022 camt.053)
+        #[arg(long, value_enum, default_value_t = StatementFormat::Ofx)]
+        format: StatementFormat,
+
+        /// Start from the engine state saved by a previous run's --save-state; its balances open
+        /// the statements
+        #[arg(long, value_name = "PATH")]
+        load_state: Option<PathBuf>,
+    },
+    /// Run an HTTP server accepting transactions (POST /transactions) and serving balances
+    /// (GET /accounts, GET /accounts/{id}) and events as they happen (GET /events)
+    #[cfg(feature = "server")]
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: std::net::SocketAddr,
+
+        /// Start from the engine state saved by a previous run's --save-state
+        #[arg(long, value_name = "PATH")]
+        load_state: Option<PathBuf>,
+
+        /// Run an engine per client, each on its own task, so requests for different clients
+        /// are applied concurrently rather than one at a time
+        #[arg(long, conflicts_with = "load_state")]
+        actors: bool,
+
+        /// Keep an engine per client in a concurrent map instead, applying each request on the
+        /// task that received it (needs the `concurrent-map` feature)
+        #[arg(long, conflicts_with_all = ["load_state", "actors"])]
+        sharded: bool,
+
+        /// Only serve callers presenting a bearer token listed in the CSV (token,role) at PATH,
+        /// as far as its role allows: `auditor` reads, `operator` also submits transactions,
+        /// and `admin` also freezes, unlocks and closes accounts and posts adjustments and
+        /// reversals
+        #[arg(long, value_name = "PATH")]
+        tokens: Option<PathBuf>,
+
+        #[command(flatten)]
+        retention: Retention,
+    },
+    /// Run a gRPC server (proto/payments.proto) accepting streamed transactions
+    /// (SubmitTransactions) and serving balances (GetAccount)
+    #[cfg(feature = "grpc")]
+    ServeGrpc {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:50051")]
+        listen: std::net::SocketAddr,
+
+        /// Start from the engine state saved by a previous run's --save-state
+        #[arg(long, value_name = "PATH")]
+        load_state: Option<PathBuf>,
+
+        /// Run an engine per client, each on its own task, so requests for different clients
+        /// are applied concurrently rather than one at a time
+        #[arg(long, conflicts_with = "load_state")]
+        actors: bool,
+
+        /// Keep an engine per client in a concurrent map instead, applying each request on the
+        /// task that received it (needs the `concurrent-map` feature)
+        #[arg(long, conflicts_with_all = ["load_state", "actors"])]
+        sharded: bool,
+
+        /// Only serve callers presenting a bearer token listed in the CSV (token,role) at PATH,
+        /// as far as its role allows: `auditor` reads, `operator` also submits transactions,
+        /// and `admin` also freezes, unlocks and closes accounts and posts adjustments and
+        /// reversals
+        #[arg(long, value_name = "PATH")]
+        tokens: Option<PathBuf>,
+
+        #[command(flatten)]
+        retention: Retention,
+    },
+    /// Look up accounts on, submit a transaction to or follow the events of a running `serve`
+    #[cfg(feature = "client")]
+    Client {
+        /// URL of the server
+        #[arg(long, value_name = "URL", default_value = "http://127.0.0.1:8080")]
+        server: String,
+
+        /// Bearer token to present to a server started with --tokens
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Format of the account state
+        #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+        output_format: OutputFormat,
+
+        #[command(subcommand)]
+        command: client::ClientCommand,
+    },
+    /// Consume JSON (or Avro) transactions from a Kafka topic, committing offsets only once each
+    /// is processed, and periodically write the account state to stdout
+    #[cfg(feature = "kafka")]
+    Kafka {
+        /// Comma-separated list of bootstrap brokers
+        #[arg(long, default_value = "localhost:9092")]
+        brokers: String,
+
+        /// Topic to consume transactions from
+        #[arg(long)]
+        topic: String,
+
+        /// Consumer group id offsets are committed under
+        #[arg(long, default_value = "payments-engine")]
+        group_id: String,
+
+        /// Seconds between writes of the account state
+        #[arg(
+            long,
+            value_name = "SECS",
+            default_value_t = 60,
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        emit_interval: u64,
+
+        /// Log every consumed transaction to a write-ahead log in DIR before applying it, and
+        /// replay it on start; without it, state is lost on restart
+        #[arg(long, value_name = "DIR")]
+        wal_dir: Option<PathBuf>,
+
+        /// Decode messages as Avro in the schema registry wire format, fetching their writer
+        /// schemas from the Confluent-compatible registry at URL; without it, messages are JSON
+        #[arg(long, value_name = "URL")]
+        schema_registry: Option<String>,
+
+        /// Save the engine state with the offsets it covers to PATH, committing offsets only
+        /// once it is saved, and resume from it on start, so each message is applied exactly
+        /// once
+        #[arg(long, value_name = "PATH", conflicts_with = "wal_dir")]
+        checkpoint: Option<PathBuf>,
+
+        /// Seconds between --checkpoint saves
+        #[arg(
+            long,
+            value_name = "SECS",
+            default_value_t = 10,
+            requires = "checkpoint"
+        )]
+        checkpoint_interval: u64,
+
+        /// Publish each client's deposit and withdrawal totals, dispute and chargeback counts
+        /// and net flow over every --aggregate-window to TOPIC, as JSON keyed by client
+        #[arg(long, value_name = "TOPIC")]
+        aggregates_topic: Option<String>,
+
+        /// Seconds each --aggregates-topic window covers; windows are aligned to multiples of it
+        #[arg(
+            long,
+            value_name = "SECS",
+            default_value_t = 60,
+            requires = "aggregates_topic",
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        aggregate_window: u64,
+
+        #[command(flatten)]
+        retention: Retention,
+    },
+}
+
+// how long a long-running mode keeps transactions that can no longer be disputed
+#[cfg(any(feature = "server", feature = "grpc", feature = "kafka"))]
+#[derive(Clone, clap::Args)]
+struct Retention {
+    /// Reject disputes arriving more than DAYS after the disputed transaction, and evict
+    /// deposits and withdrawals older than that from memory every --evict-interval, along with
+    /// settled ones, so memory stays bounded; needs timestamps (seconds since the Unix epoch)
+    #[arg(long, value_name = "DAYS")]
+    dispute_window: Option<u64>,
+
+    /// Seconds between evictions of transactions past the --dispute-window
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 3600,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    evict_interval: u64,
+
+    /// Write every evicted transaction to PATH as a JSON line
+    #[arg(long, value_name = "PATH", requires = "dispute_window")]
+    archive: Option<PathBuf>,
+
+    /// Format of --archive: JSON lines, or Parquet files partitioned by day under the directory
+    /// PATH (needs the `parquet` feature)
+    #[arg(long, value_enum, default_value_t = ArchiveFormat::Jsonl)]
+    archive_format: ArchiveFormat,
+}
+
+#[cfg(any(feature = "server", feature = "grpc", feature = "kafka"))]
+impl Retention {
+    // a builder with the dispute window and eviction policy, without the archive
+    fn builder(&self) -> PaymentsEngineBuilder {
+        match self.dispute_window {
+            Some(days) => PaymentsEngine::builder()
+                .dispute_window(Duration::from_secs(days.saturating_mul(SECS_PER_DAY)))
+                .eviction_policy(EvictionPolicy::Settled),
+            None => PaymentsEngine::builder(),
+        }
+    }
+
+    fn archiving_builder(&self) -> Result<PaymentsEngineBuilder> {
+        Ok(match &self.archive {
+            Some(path) => self
+                .builder()
+                .archive_sink(archive_sink(path, self.archive_format)?),
+            None => self.builder(),
+        })
+    }
+
+    // how often to evict, if there's a window to evict past
+    fn evict_interval(&self) -> Option<Duration> {
+        self.dispute_window
+            .map(|_| Duration::from_secs(self.evict_interval))
+    }
+}
+
+fn parse_merge(s: &str) -> std::result::Result<(u16, u16), String> {
+    let (source, target) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected SOURCE:TARGET, got `{s}`"))?;
+    let source = source
+        .parse()
+        .map_err(|e| format!("invalid source client id: {e}"))?;
+    let target = target
+        .parse()
+        .map_err(|e| format!("invalid target client id: {e}"))?;
+
+    Ok((source, target))
+}
+
+fn parse_date(s: &str) -> std::result::Result<u64, String> {
+    match s.len() {
+        10 => dates::midnight(s).ok_or_else(|| format!("invalid date `{s}`")),
+        _ => Err(format!("expected YYYY-MM-DD, got `{s}`")),
+    }
+}
+
+fn parse_error_action(s: &str) -> std::result::Result<(ErrorCategory, ErrorAction), String> {
+    let (category, action) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected CATEGORY=ACTION, got `{s}`"))?;
+
+    Ok((
+        category.parse::<ErrorCategory>()?,
+        ErrorAction::from_str(action, true)?,
+    ))
+}
+
+fn parse_interest_rate(s: &str) -> std::result::Result<(Option<String>, Decimal), String> {
+    let (currency, rate) = match s.split_once('=') {
+        Some((currency, rate)) => (Some(currency.to_string()), rate),
+        None => (None, s),
+    };
+    let rate = rate
+        .parse()
+        .map_err(|e| format!("invalid interest rate: {e}"))?;
+
+    Ok((currency, rate))
+}
+
+fn main() -> Result<ExitCode> {
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    logging::init(cli.log_level, cli.log_format);
+    if cli.encryption_key.is_some() || !cli.decryption_key.is_empty() {
+        let current = cli
+            .encryption_key
+            .as_deref()
+            .map(crypt::read_key)
+            .transpose()?;
+        let older = cli
+            .decryption_key
+            .iter()
+            .map(|path| crypt::read_key(path))
+            .collect::<Result<Vec<_>>>()?;
+        crypt::init(crypt::Keyring::new(current, &older));
+    }
+
+    match cli.command {
+        Some(Command::Selftest) => {
+            let passed = selftest::run(std::io::stdout().lock())?;
+            return Ok(if passed {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            });
+        }
+        Some(Command::Replay {
+            events,
+            load_state,
+            verify,
+            output_format,
+        }) => {
+            let mut engine = load_engine(PaymentsEngine::builder(), load_state.as_deref())?;
+            for path in events {
+                if path.as_os_str() == STDIN_PATH {
+                    engine.replay_onto(BufReader::new(crypt::Reader::new(
+                        std::io::stdin().lock(),
+                    )?))?;
+                } else {
+                    engine.replay_onto(BufReader::new(crypt::Reader::new(File::open(path)?)?))?;
                 }
             }
+            let Some(path) = verify else {
+                write_accounts(&engine, BufWriter::new(std::io::stdout()), output_format)?;
+                return Ok(ExitCode::SUCCESS);
+            };
+            let matched = replay::verify(
+                &engine,
+                BufReader::new(File::open(path)?),
+                std::io::stderr().lock(),
+            )?;
+            return Ok(if matched {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            });
+        }
+        Some(Command::CompactEvents {
+            mut segments,
+            save_state: state_path,
+        }) => {
+            let existing = state_path.exists().then_some(state_path.as_path());
+            let mut engine = load_engine(PaymentsEngine::builder(), existing)?;
+            // unnumbered segments first, as given
+            segments.sort_by_key(|path| {
+                path.extension()
+                    .and_then(|extension| extension.to_str()?.parse::<u64>().ok())
+            });
+            for path in &segments {
+                engine.replay_onto(BufReader::new(crypt::Reader::new(File::open(path)?)?))?;
+            }
+            save_state(&engine, &state_path)?;
+            // only once the snapshot holding them is saved
+            for path in &segments {
+                fs::remove_file(path)?;
+            }
+            tracing::info!(count = segments.len(), "compacted event log segments");
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Decrypt { path }) => {
+            let mut reader = crypt::Reader::new(File::open(path)?)?;
+            std::io::copy(&mut reader, &mut std::io::stdout().lock())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Reencrypt { paths }) => {
+            for path in &paths {
+                crypt::reencrypt(path)?;
+            }
+            tracing::info!(count = paths.len(), "re-encrypted files");
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Diff {
+            old,
+            new,
+            output_format,
+        }) => {
+            let identical = diff::run(
+                &diff::load(&old)?,
+                &diff::load(&new)?,
+                BufWriter::new(std::io::stdout()),
+                output_format,
+            )?;
+            return Ok(if identical {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            });
+        }
+        Some(Command::Merge {
+            states,
+            save_state: save_path,
+            output_format,
+        }) => {
+            let mut engine = PaymentsEngine::new();
+            for path in &states {
+                let shard = load_engine(PaymentsEngine::builder(), Some(path))?;
+                engine.merge_state(shard).map_err(|e| match e {
+                    Error::StateConflict(conflict) => {
+                        Error::StateConflict(format!("{}: {}", path.display(), conflict))
+                    }
+                    e => e,
+                })?;
+            }
+            if let Some(path) = &save_path {
+                save_state(&engine, path)?;
+            }
+            write_accounts(&engine, BufWriter::new(std::io::stdout()), output_format)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Inspect {
+            state,
+            client,
+            tx,
+            archive,
+        }) => {
+            let engine = load_engine(PaymentsEngine::builder(), Some(&state))?;
+            let archived = match archive {
+                Some(dir) => read_archive(&dir, client, tx)?,
+                None => Vec::new(),
+            };
+            inspect::run(
+                &engine,
+                client,
+                tx,
+                &archived,
+                BufWriter::new(std::io::stdout()),
+            )?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Repl { load_state }) => {
+            let mut engine = load_engine(PaymentsEngine::builder(), load_state.as_deref())?;
+            let stdin = std::io::stdin();
+            let prompt = stdin.is_terminal();
+            repl::run(&mut engine, stdin.lock(), std::io::stdout().lock(), prompt)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Export {
+            input,
+            from,
+            to,
+            dir,
+            default_currency,
+            format,
+            load_state,
+        }) => {
+            if to < from {
+                return Err(Error::ConfigError(
+                    "--to must not be before --from".to_string(),
+                ));
+            }
+            let mut engine = load_engine(
+                PaymentsEngine::builder().track_history(true),
+                load_state.as_deref(),
+            )?;
+            let mut ingest = Ingest::default();
+            let inputs = match input.is_empty() {
+                true => vec![PathBuf::from(STDIN_PATH)],
+                false => input,
+            };
+            for fpath in inputs {
+                let compression = Compression::Auto.of(&fpath);
+                if fpath.as_os_str() == STDIN_PATH {
+                    let stdin = std::io::stdin().lock();
+                    ingest.process(&mut engine, compression::decompress(stdin, compression)?)?;
+                } else {
+                    let file = BufReader::new(File::open(&fpath)?);
+                    ingest.process(&mut engine, compression::decompress(file, compression)?)?;
+                }
+            }
+            ingest.finish(&mut engine)?;
+            let period = ofx::Period {
+                start: from,
+                end: to + SECS_PER_DAY,
+            };
+            let written = ofx::export(&engine, period, &default_currency, &dir, format)?;
+            tracing::info!(count = written, "wrote statements");
+            return Ok(ExitCode::SUCCESS);
+        }
+        #[cfg(feature = "server")]
+        Some(Command::Serve {
+            listen,
+            load_state,
+            actors,
+            sharded,
+            tokens,
+            retention,
+        }) => {
+            let tokens = tokens.as_deref().map(roles::Tokens::load).transpose()?;
+            let events = server::event_channel();
+            let with_events = {
+                let events = events.clone();
+                move |builder: PaymentsEngineBuilder| {
+                    builder.event_sink(Box::new(server::EventBroadcast(events.clone())))
+                }
+            };
+            let engine = load_engine(
+                with_events(retention.archiving_builder()?),
+                load_state.as_deref(),
+            )?;
+            server::run(listen, tokens, events, move || {
+                spawn_server_engine(engine, actors, sharded, retention, with_events)
+            })?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        #[cfg(feature = "grpc")]
+        Some(Command::ServeGrpc {
+            listen,
+            load_state,
+            actors,
+            sharded,
+            tokens,
+            retention,
+        }) => {
+            let tokens = tokens.as_deref().map(roles::Tokens::load).transpose()?;
+            let engine = load_engine(retention.archiving_builder()?, load_state.as_deref())?;
+            grpc::run(listen, tokens, move || {
+                spawn_server_engine(engine, actors, sharded, retention, |builder| builder)
+            })?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        #[cfg(feature = "client")]
+        Some(Command::Client {
+            server,
+            token,
+            output_format,
+            command,
+        }) => {
+            client::Client::new(&server, token).run(
+                command,
+                BufWriter::new(std::io::stdout()),
+                output_format,
+            )?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        #[cfg(feature = "kafka")]
+        Some(Command::Kafka {
+            brokers,
+            topic,
+            group_id,
+            emit_interval,
+            wal_dir,
+            schema_registry,
+            checkpoint,
+            checkpoint_interval,
+            aggregates_topic,
+            aggregate_window,
+            retention,
+        }) => {
+            let options = kafka::KafkaOptions {
+                brokers,
+                topic,
+                group_id,
+                emit_interval: std::time::Duration::from_secs(emit_interval),
+                registry: schema_registry.as_deref().map(avro::SchemaRegistry::new),
+                checkpoint,
+                checkpoint_interval: std::time::Duration::from_secs(checkpoint_interval.max(1)),
+                evict_interval: retention.evict_interval(),
+                aggregates: aggregates_topic.map(|topic| kafka::AggregateOptions {
+                    topic,
+                    window: std::time::Duration::from_secs(aggregate_window),
+                }),
+            };
+            kafka::run(options, retention.archiving_builder()?, wal_dir.as_deref())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => {}
+    }
+
+    if let Some(path) = cli.config.take() {
+        EngineConfig::load(&path)?.apply(&mut cli, &matches)?;
+    }
+    // the shadow engine differs only in the policies its config sets
+    let shadow_cli = match &cli.shadow {
+        Some(path) => {
+            let mut shadow_cli = cli.clone();
+            EngineConfig::load(path)?.overlay(&mut shadow_cli)?;
+            Some(shadow_cli)
+        }
+        None => None,
+    };
+    let rotation = rotate::Rotation {
+        size: cli.rotate_size,
+        interval: cli.rotate_interval.map(Duration::from_secs),
+    };
+    let builder = engine_builder(&cli)?.tx_store(tx_store(cli.tx_store, cli.tx_store_dir)?);
+    let mut sinks: Vec<Box<dyn EventSink + Send>> = Vec::new();
+    match &cli.events {
+        // the events and the accounts can't share stdout, as neither could be read back then
+        Some(path) if path.as_os_str() == STDOUT_PATH && cli.output.is_none() => {
+            return Err(Error::ConfigError(
+                "--events - needs --output, as the accounts are written to stdout".to_string(),
+            ));
+        }
+        Some(path) if path.as_os_str() == STDOUT_PATH => {
+            sinks.push(Box::new(JsonlSink::new(BufWriter::new(std::io::stdout()))))
+        }
+        Some(path) => sinks.push(Box::new(JsonlSink::new(BufWriter::new(
+            rotate::RotatingFile::create(path, rotation, false)?,
+        )))),
+        None => {}
+    }
+    if let Some(url) = &cli.webhook_url {
+        sinks.push(webhook_sink(
+            url,
+            cli.webhook_chargebacks,
+            cli.webhook_charged_back,
+            cli.webhook_outbox.as_deref(),
+        )?);
+    }
+    let builder = match sinks.len() {
+        0 => builder,
+        1 => builder.event_sink(sinks.pop().unwrap()),
+        _ => builder.event_sink(Box::new(sinks)),
+    };
+    let mut audit_sinks: Vec<Box<dyn AuditSink + Send>> = Vec::new();
+    if let Some(path) = &cli.audit {
+        // every CSV segment starts with the header row
+        let header = matches!(cli.audit_format, AuditFormat::Csv);
+        let writer = BufWriter::new(rotate::RotatingFile::create(path, rotation, header)?);
+        audit_sinks.push(match cli.audit_format {
+            AuditFormat::Jsonl => Box::new(JsonlAuditSink::new(writer)),
+            AuditFormat::Csv => Box::new(CsvAuditSink::new(writer)),
+        });
+    }
+    // read before the run so a broken config doesn't waste it
+    let ach_config = cli
+        .ach_config
+        .as_deref()
+        .map(|path| nacha::AchConfig::from_toml(&fs::read_to_string(path)?))
+        .transpose()?;
+    if let (Some(path), Some(mapping)) = (&cli.gl_journal, &cli.gl_mapping) {
+        let mapping = GlMapping::from_toml(&fs::read_to_string(mapping)?)?;
+        audit_sinks.push(Box::new(GlJournalSink::new(
+            mapping,
+            BufWriter::new(File::create(path)?),
+        )));
+    }
+    let builder = match audit_sinks.len() {
+        0 => builder,
+        1 => builder.audit_sink(audit_sinks.pop().unwrap()),
+        _ => builder.audit_sink(Box::new(audit_sinks)),
+    };
+    let builder = match &cli.archive {
+        Some(path) => builder.archive_sink(archive_sink(path, cli.archive_format)?),
+        None => builder,
+    };
+    // held disputes aren't part of the saved state, so a resumed run would lose them
+    if cli.checkpoint.is_some() && cli.pending_disputes > 0 {
+        return Err(Error::ConfigError(
+            "--checkpoint can't be combined with --pending-disputes".to_string(),
+        ));
+    }
+    // a file passed over next time must have its transactions in the state that run starts from
+    if cli.processed.is_some() && cli.save_state.is_none() && cli.wal_dir.is_none() {
+        return Err(Error::ConfigError(
+            "--processed needs --save-state or --wal-dir".to_string(),
+        ));
+    }
+    // with a WAL, the engine is rebuilt from it instead of loaded (the two can't be combined)
+    let start = |builder| match &cli.wal_dir {
+        Some(dir) => Wal::recover(dir, builder).map(|(wal, engine)| (Some(wal), engine)),
+        None => Ok((None, load_engine(builder, cli.load_state.as_deref())?)),
+    };
+    let (checkpoint, wal, mut engine) = match &cli.checkpoint {
+        Some(path) if cli.resume => {
+            let (checkpoint, engine) =
+                Checkpointer::resume(path, cli.checkpoint_interval, builder)?;
+            (Some(checkpoint), None, engine)
+        }
+        Some(path) => {
+            let (wal, engine) = start(builder)?;
+            (
+                Some(Checkpointer::new(path, cli.checkpoint_interval)),
+                wal,
+                engine,
+            )
+        }
+        None => {
+            let (wal, engine) = start(builder)?;
+            (None, wal, engine)
+        }
+    };
+    let error_policy = match cli.strict {
+        true => ErrorPolicyMode::Fail,
+        false => cli.error_policy,
+    };
+    let mut policy = ErrorPolicy::default();
+    match error_policy {
+        ErrorPolicyMode::Skip => {}
+        ErrorPolicyMode::Fail => policy.set_fallback(ErrorAction::Abort),
+        ErrorPolicyMode::Collect => policy.set_fallback(ErrorAction::Warn),
+    }
+    if let DuplicateMode::Error = cli.duplicates {
+        policy.set(ErrorCategory::Duplicate, ErrorAction::Abort);
+    }
+    for (category, action) in cli.error_actions {
+        policy.set(category, action);
+    }
+    if policy.uses(ErrorAction::Quarantine) && cli.quarantine.is_none() {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "the `quarantine` action requires --quarantine <PATH>",
+            )
+            .exit();
+    }
+    let mut shadow = match &shadow_cli {
+        Some(shadow_cli) => Some(Shadow::new(
+            load_engine(engine_builder(shadow_cli)?, cli.load_state.as_deref())?,
+            match &cli.shadow_report {
+                Some(path) => Some(Box::new(BufWriter::new(File::create(path)?))),
+                None => None,
+            },
+        )),
+        None => None,
+    };
+    if let Some(path) = &cli.accounts_in {
+        seed::load(&mut engine, BufReader::new(File::open(path)?))?;
+        if let Some(shadow) = &mut shadow {
+            seed::load(shadow.engine(), BufReader::new(File::open(path)?))?;
+        }
+    }
+    let accounts_before = engine.accounts().count();
+    let resumed = checkpoint
+        .as_ref()
+        .map(Checkpointer::resumed_outputs)
+        .unwrap_or_default();
+    let mut ingest = Ingest {
+        verifier: cli
+            .hmac_key_file
+            .as_deref()
+            .map(RowVerifier::from_key_file)
+            .transpose()?,
+        rules: cli.rules.as_deref().map(Rules::from_file).transpose()?,
+        precision: match cli.precision {
+            PrecisionMode::Reject => PrecisionPolicy::Reject,
+            PrecisionMode::Truncate => PrecisionPolicy::Truncate,
+            PrecisionMode::Round => PrecisionPolicy::Round(match cli.rounding_mode {
+                RoundingModeArg::HalfEven => RoundingMode::HalfEven,
+                RoundingModeArg::HalfUp => RoundingMode::HalfUp,
+                RoundingModeArg::HalfDown => RoundingMode::HalfDown,
+                RoundingModeArg::Ceiling => RoundingMode::Ceiling,
+                RoundingModeArg::Floor => RoundingMode::Floor,
+            }),
+        },
+        policy,
+        quarantine: cli
+            .quarantine
+            .as_deref()
+            .map(|path| RejectSink::create(path, resumed.quarantine))
+            .transpose()?,
+        rejects: cli
+            .rejects
+            .as_deref()
+            .map(|path| RejectSink::create(path, resumed.rejects))
+            .transpose()?,
+        wal,
+        checkpoint,
+        shadow,
+        summary: Summary::default(),
+    };
+
+    let accounts = match &cli.iso_accounts {
+        Some(path) => iso20022::load_accounts(File::open(path)?)?,
+        None => HashMap::new(),
+    };
+    let inputs = match cli.manifest {
+        Some(manifest_path) => manifest::load(&manifest_path, cli.compression)?,
+        None if cli.input.is_empty() => vec![PathBuf::from(STDIN_PATH)],
+        None => inputs::expand(cli.input, cli.input_order)?,
+    };
+    let mut processed = cli
+        .processed
+        .as_deref()
+        .map(ProcessedFiles::load)
+        .transpose()?;
+    let inputs = match &mut processed {
+        Some(processed) => processed.filter(inputs, cli.reprocess)?,
+        None => inputs,
+    };
+    for fpath in inputs {
+        if let Some(checkpoint) = &mut ingest.checkpoint
+            && !checkpoint.start_input(&fpath)
+        {
+            continue;
+        }
+        ingest.summary.start_file();
+        let compression = cli.compression.of(&fpath);
+        let format = cli.input_format.of(&fpath);
+        if fpath.as_os_str() == STDIN_PATH {
+            // stdin is already buffered, so stream it straight through
+            process_input(
+                &mut ingest,
+                &mut engine,
+                compression::decompress(std::io::stdin().lock(), compression)?,
+                format,
+                &accounts,
+            )?;
+        } else if remote::is_remote(&fpath) {
+            let object = BufReader::new(remote::open(&fpath.to_string_lossy())?);
+            process_input(
+                &mut ingest,
+                &mut engine,
+                compression::decompress(object, compression)?,
+                format,
+                &accounts,
+            )?;
+        } else if cli.mmap {
+            inputs::with_mapped(&fpath, |bytes| {
+                process_input(
+                    &mut ingest,
+                    &mut engine,
+                    compression::decompress(bytes, compression)?,
+                    format,
+                    &accounts,
+                )
+            })?;
+        } else {
+            let file = BufReader::new(File::open(&fpath)?);
+            process_input(
+                &mut ingest,
+                &mut engine,
+                compression::decompress(file, compression)?,
+                format,
+                &accounts,
+            )?;
+        }
+        ingest.summary.end_file(fpath.display().to_string());
+        ingest.end_input(&mut engine)?;
+    }
+    ingest.finish(&mut engine)?;
+
+    if let Some(now) = cli.as_of {
+        if let Some(wal) = &mut ingest.wal {
+            wal.append(&WalRecord::AccrueInterest { now })?;
+            wal.append(&WalRecord::ExpireHolds { now })?;
+            wal.append(&WalRecord::ClearDue { now })?;
+            wal.append(&WalRecord::EvictExpired { now })?;
+        }
+        // interest first, as the holds weren't available until now
+        let credited = engine.accrue_interest(now)?;
+        tracing::info!(count = credited, "accrued interest");
+        let released = engine.expire_holds(now)?;
+        tracing::info!(count = released.len(), "released expired holds");
+        let cleared = engine.clear_due(now)?;
+        tracing::info!(count = cleared.len(), "cleared provisional deposits");
+        let evicted = engine.evict_expired(now)?;
+        tracing::info!(
+            count = evicted,
+            "evicted transactions past the dispute window"
+        );
+        if let Some(shadow) = &mut ingest.shadow {
+            shadow.engine().accrue_interest(now)?;
+            shadow.engine().expire_holds(now)?;
+            shadow.engine().clear_due(now)?;
+            shadow.engine().evict_expired(now)?;
+        }
+    }
+
+    // apply administrative merges after ingestion, same best-effort handling as txs
+    for (source, target) in cli.merges {
+        if let Some(wal) = &mut ingest.wal {
+            wal.append(&WalRecord::Merge { source, target })?;
+        }
+        // a merge the shadow engine refuses shows in its final balances
+        if let Some(shadow) = &mut ingest.shadow {
+            let _ = shadow.engine().merge_accounts(source, target);
+        }
+        match engine.merge_accounts(source, target) {
+            Ok(()) => {}
+            // the engine can't be trusted after a storage failure, nor the event or audit log
+            // after a failed write
+            Err(e @ (Error::StoreError(_) | Error::EventError(_) | Error::AuditError(_))) => {
+                return Err(e);
+            }
+            Err(e) if error_policy == ErrorPolicyMode::Fail => return Err(e),
             Err(e) => {
-                eprintln!("skipping invalid transaction row: {}", e);
-                continue;
+                logging::failure(&format!("failed account merge {}:{}", source, target), &e);
+                ingest.summary.record_rejected(&e);
             }
         }
     }
 
-    let mut stdout = BufWriter::new(std::io::stdout());
+    // the run's records are all applied, and its held disputes given up on, so the whole log
+    // can be compacted into a snapshot
+    if let Some(wal) = &mut ingest.wal {
+        wal.compact(&mut engine)?;
+    }
+    engine.flush_events()?;
+    if let Some(shadow) = ingest.shadow.take() {
+        shadow.finish(&engine)?;
+    }
+    if let Some(path) = &cli.save_state {
+        save_state(&engine, path)?;
+    }
+    // only once the state they went into is saved, so an interrupted run processes them again
+    if let Some(processed) = &processed {
+        processed.save()?;
+    }
+    match &cli.output {
+        Some(path) => write_accounts(
+            &engine,
+            BufWriter::new(File::create(path)?),
+            cli.output_format,
+        )?,
+        None => write_accounts(
+            &engine,
+            BufWriter::new(std::io::stdout()),
+            cli.output_format,
+        )?,
+    }
 
-    // write the account balances/state to stdout in csv format
-    writeln!(stdout, "client,available,held,total,locked")?;
-    for (id, account) in &engine.accounts {
-        writeln!(
-            stdout,
-            "{},{:.4},{:.4},{:.4},{}",
-            id, account.available, account.held, account.total, account.locked
+    if let (Some(path), Some(config)) = (&cli.ach, &ach_config) {
+        let now = dates::now();
+        let effective = cli.ach_effective_date.unwrap_or(now + SECS_PER_DAY);
+        let written = nacha::write(
+            BufWriter::new(File::create(path)?),
+            &engine,
+            config,
+            effective,
+            now,
         )?;
+        tracing::info!(count = written, "wrote ACH payouts");
+    }
+
+    match cli.summary.as_deref() {
+        Some(path) if path.as_os_str() == STDERR_PATH => {
+            ingest
+                .summary
+                .write(std::io::stderr().lock(), &engine, accounts_before)?
+        }
+        Some(path) => ingest.summary.write(
+            BufWriter::new(File::create(path)?),
+            &engine,
+            accounts_before,
+        )?,
+        None => {}
+    }
+
+    if error_policy == ErrorPolicyMode::Collect && ingest.summary.failures() > 0 {
+        tracing::error!(failures = ingest.summary.failures(), "run had failed rows");
+        return Ok(ExitCode::FAILURE);
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+// an engine builder with the engine policies `cli` sets, without the tx store or any sinks
+fn engine_builder(cli: &Cli) -> Result<PaymentsEngineBuilder> {
+    let builder = PaymentsEngine::builder()
+        .duplicate_policy(match cli.duplicates {
+            DuplicateMode::Skip => DuplicatePolicy::Skip,
+            DuplicateMode::Reject | DuplicateMode::Error => DuplicatePolicy::Reject,
+        })
+        .amount_limits(AmountLimits {
+            min: cli.min_amount,
+            max: cli.max_amount,
+        })
+        .lock_policy(match cli.lock_policy {
+            LockMode::OnChargeback => LockPolicy::OnChargeback,
+            LockMode::Never => LockPolicy::Never,
+        })
+        .account_mismatch_policy(match cli.account_mismatch {
+            AccountMismatchMode::Reject => AccountMismatchPolicy::Reject,
+            AccountMismatchMode::Ignore => AccountMismatchPolicy::Ignore,
+        })
+        .negative_available_policy(match cli.negative_available {
+            NegativeAvailableMode::Allow => NegativeAvailablePolicy::Allow,
+            NegativeAvailableMode::Reject => NegativeAvailablePolicy::Reject,
+        })
+        .eviction_policy(if cli.evict_settled {
+            EvictionPolicy::Settled
+        } else {
+            EvictionPolicy::Keep
+        })
+        .expected_accounts(cli.expected_accounts);
+    let builder = match cli.pending_disputes {
+        0 => builder,
+        capacity => builder.pending_disputes(PendingDisputes {
+            capacity,
+            max_age: cli.pending_dispute_max_age,
+            overflow: match cli.pending_overflow {
+                PendingOverflowMode::RejectNew => PendingOverflow::RejectNew,
+                PendingOverflowMode::EvictOldest => PendingOverflow::EvictOldest,
+            },
+        }),
+    };
+    let builder = match cli.dispute_window {
+        Some(days) => {
+            builder.dispute_window(Duration::from_secs(days.saturating_mul(SECS_PER_DAY)))
+        }
+        None => builder,
+    };
+    let builder = match cli.hold_expiry {
+        Some(days) => builder.hold_expiry(Duration::from_secs(days.saturating_mul(SECS_PER_DAY))),
+        None => builder,
+    };
+    let builder = match cli.clearing_period {
+        Some(days) => {
+            builder.clearing_period(Duration::from_secs(days.saturating_mul(SECS_PER_DAY)))
+        }
+        None => builder,
+    };
+    let builder = if cli.interest_rates.is_empty() {
+        builder
+    } else {
+        let default = cli
+            .interest_rates
+            .iter()
+            .rev()
+            .find_map(|(currency, rate)| currency.is_none().then_some(*rate));
+        let by_currency = cli
+            .interest_rates
+            .iter()
+            .filter_map(|(currency, rate)| Some((currency.clone()?, *rate)));
+        builder.interest_rates(InterestRates::new(default, by_currency)?)
+    };
+    let builder = match &cli.fee_schedule {
+        Some(path) => builder.fee_schedule(FeeSchedule::from_toml(&fs::read_to_string(path)?)?),
+        None => builder,
+    };
+    let builder = match &cli.withdrawal_limits {
+        Some(path) => {
+            builder.withdrawal_limits(WithdrawalLimits::from_toml(&fs::read_to_string(path)?)?)
+        }
+        None => builder,
+    };
+    let builder = match &cli.balance_thresholds {
+        Some(path) => {
+            builder.balance_thresholds(BalanceThresholds::from_toml(&fs::read_to_string(path)?)?)
+        }
+        None => builder,
+    };
+    let builder = match &cli.risk_rules {
+        Some(path) => builder.risk_rules(RiskRules::from_toml(&fs::read_to_string(path)?)?),
+        None => builder,
+    };
+
+    Ok(builder)
+}
+
+// read one input in `format` into the engine
+fn process_input(
+    ingest: &mut Ingest,
+    engine: &mut PaymentsEngine,
+    reader: Box<dyn Read + '_>,
+    format: InputFormat,
+    accounts: &HashMap<String, u16>,
+) -> Result<()> {
+    match format {
+        InputFormat::Auto | InputFormat::Csv => ingest.process(engine, reader),
+        InputFormat::Iso20022 => {
+            let rows = iso20022::parse(BufReader::new(reader), accounts)?;
+            ingest.process_rows(engine, rows)
+        }
+    }
+}
+
+// an engine restored from a saved state, or an empty one
+fn load_engine(
+    builder: PaymentsEngineBuilder,
+    state_path: Option<&Path>,
+) -> Result<PaymentsEngine> {
+    match state_path {
+        Some(path) => builder.restore(BufReader::new(crypt::Reader::new(File::open(path)?)?)),
+        None => Ok(builder.build()),
+    }
+}
+
+// the sink for --archive PATH in `format`
+fn archive_sink(path: &Path, format: ArchiveFormat) -> Result<Box<dyn ArchiveSink + Send>> {
+    match format {
+        ArchiveFormat::Jsonl => Ok(Box::new(JsonlArchive::new(BufWriter::new(File::create(
+            path,
+        )?)))),
+        ArchiveFormat::Parquet => parquet_archive(path),
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_archive(dir: &Path) -> Result<Box<dyn ArchiveSink + Send>> {
+    Ok(Box::new(parquet_archive::ParquetArchive::new(dir)?))
+}
+
+#[cfg(not(feature = "parquet"))]
+fn parquet_archive(_dir: &Path) -> Result<Box<dyn ArchiveSink + Send>> {
+    Err(Error::ConfigError(
+        "--archive-format parquet requires building with the `parquet` feature".to_string(),
+    ))
+}
+
+// the transactions archived under the Parquet archive `dir` of `client` or with id `tx`
+#[cfg(feature = "parquet")]
+fn read_archive(dir: &Path, client: Option<u16>, tx: Option<u32>) -> Result<Vec<(u32, TxRecord)>> {
+    parquet_archive::read(dir, |id, record| {
+        client == Some(record.account_id) || tx == Some(id)
+    })
+}
+
+#[cfg(not(feature = "parquet"))]
+fn read_archive(
+    _dir: &Path,
+    _client: Option<u16>,
+    _tx: Option<u32>,
+) -> Result<Vec<(u32, TxRecord)>> {
+    Err(Error::ConfigError(
+        "--archive requires building with the `parquet` feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "webhook")]
+fn webhook_sink(
+    url: &str,
+    chargebacks: Option<u32>,
+    charged_back: Option<Amount>,
+    outbox: Option<&Path>,
+) -> Result<Box<dyn EventSink + Send>> {
+    Ok(Box::new(webhook::WebhookSink::new(
+        url,
+        webhook::Thresholds {
+            chargebacks,
+            charged_back,
+        },
+        outbox,
+    )?))
+}
+
+#[cfg(not(feature = "webhook"))]
+fn webhook_sink(
+    _url: &str,
+    _chargebacks: Option<u32>,
+    _charged_back: Option<Amount>,
+    _outbox: Option<&Path>,
+) -> Result<Box<dyn EventSink + Send>> {
+    Err(Error::ConfigError(
+        "--webhook-url requires building with the `webhook` feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "disk-store")]
+fn tx_store(mode: TxStoreMode, dir: Option<PathBuf>) -> Result<TxStore> {
+    match mode {
+        TxStoreMode::Memory => Ok(TxStore::memory()),
+        TxStoreMode::Disk => TxStore::disk(&dir.unwrap_or_else(std::env::temp_dir)),
+    }
+}
+
+#[cfg(not(feature = "disk-store"))]
+fn tx_store(mode: TxStoreMode, _dir: Option<PathBuf>) -> Result<TxStore> {
+    match mode {
+        TxStoreMode::Memory => Ok(TxStore::memory()),
+        TxStoreMode::Disk => Err(Error::ConfigError(
+            "--tx-store disk requires building with the `disk-store` feature".to_string(),
+        )),
+    }
+}
+
+// the engine a server mode runs on a task of its own, or with --actors or --sharded a fresh
+// engine per client, its builder finished by `configure`
+#[cfg(any(feature = "server", feature = "grpc"))]
+fn spawn_server_engine(
+    engine: PaymentsEngine,
+    actors: bool,
+    sharded: bool,
+    retention: Retention,
+    configure: impl Fn(PaymentsEngineBuilder) -> PaymentsEngineBuilder + Send + Sync + 'static,
+) -> Result<payments_engine::AsyncPaymentsEngine> {
+    let evict_interval = retention.evict_interval();
+    if (actors || sharded) && retention.archive.is_some() {
+        // each client's engine would need an archive of its own
+        return Err(Error::ConfigError(
+            "--archive can't be combined with --actors or --sharded".to_string(),
+        ));
+    }
+    let factory = move || configure(retention.builder()).build();
+    let engine = if sharded {
+        spawn_sharded(factory)?
+    } else if actors {
+        payments_engine::AsyncPaymentsEngine::spawn_per_account(factory)?
+    } else {
+        payments_engine::AsyncPaymentsEngine::spawn(engine)
+    };
+    if let Some(interval) = evict_interval {
+        tokio::spawn(evict_periodically(engine.clone(), interval));
+    }
+
+    Ok(engine)
+}
+
+#[cfg(all(any(feature = "server", feature = "grpc"), feature = "concurrent-map"))]
+fn spawn_sharded(
+    factory: impl Fn() -> PaymentsEngine + Send + Sync + 'static,
+) -> Result<payments_engine::AsyncPaymentsEngine> {
+    payments_engine::AsyncPaymentsEngine::spawn_sharded(factory)
+}
+
+#[cfg(all(
+    any(feature = "server", feature = "grpc"),
+    not(feature = "concurrent-map")
+))]
+fn spawn_sharded(
+    _factory: impl Fn() -> PaymentsEngine + Send + Sync + 'static,
+) -> Result<payments_engine::AsyncPaymentsEngine> {
+    Err(Error::ConfigError(
+        "--sharded requires building with the `concurrent-map` feature".to_string(),
+    ))
+}
+
+// evict what can no longer be disputed by the wall clock every `interval`, for as long as the
+// server runs. A failed eviction is logged and stops further ones, leaving the server serving
+#[cfg(any(feature = "server", feature = "grpc"))]
+async fn evict_periodically(engine: payments_engine::AsyncPaymentsEngine, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    // the first tick completes immediately, with nothing to evict yet
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        match engine.evict_expired(dates::now()).await {
+            Ok(count) => tracing::info!(count, "evicted transactions past the dispute window"),
+            Err(e) => {
+                tracing::error!(error = %e, "stopped evicting transactions");
+                return;
+            }
+        }
     }
+}
+
+// write to a sibling temp file and rename over the target, so a crash mid-write never leaves a
+// truncated state file behind for the next run to load
+fn save_state(engine: &PaymentsEngine, path: &Path) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut writer = BufWriter::new(crypt::Writer::new(File::create(&tmp_path)?)?);
+    engine.snapshot(&mut writer)?;
+    writer.flush()?;
+    writer.get_ref().get_ref().sync_all()?;
+    fs::rename(&tmp_path, path)?;
 
     Ok(())
 }
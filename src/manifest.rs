@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use payments_engine::{Error, Result};
+
+use crate::{
+    STDIN_PATH,
+    compression::{self, Compression},
+    remote,
+};
+
+// one input batch as listed in the manifest--row count and digest are optional checks
+#[derive(Debug, Deserialize)]
+struct ManifestEntry {
+    seq: u32,
+    path: PathBuf,
+    rows: Option<u64>,
+    sha256: Option<String>,
+}
+
+// reads the manifest and validates every listed batch (ordering, presence, row count, digest)
+// before returning the input paths in processing order--nothing is ingested if any batch is bad.
+// Compressed batches have their rows counted once decompressed, but the digest is of the file
+pub fn load(manifest_path: &Path, compression: Compression) -> Result<Vec<PathBuf>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(manifest_path)?;
+    let entries = rdr
+        .deserialize()
+        .collect::<std::result::Result<Vec<ManifestEntry>, _>>()?;
+
+    validate_sequence(&entries)?;
+
+    // input paths are relative to the manifest's own directory
+    let base_dir = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut paths = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        let path = base_dir.join(&entry.path);
+        let file = File::open(&path).map_err(|e| {
+            Error::ManifestError(format!(
+                "batch {} ({}) is missing: {}",
+                entry.seq,
+                path.display(),
+                e
+            ))
+        })?;
+        let (rows, digest) = count_and_digest(BufReader::new(file), compression.of(&path))?;
+        validate_batch(entry, rows, &digest)?;
+
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+// batches must be listed in order as a contiguous 1..=n sequence
+fn validate_sequence(entries: &[ManifestEntry]) -> Result<()> {
+    if entries.is_empty() {
+        return Err(Error::ManifestError("manifest lists no batches".into()));
+    }
+
+    for (expected, entry) in (1..).zip(entries) {
+        if entry.seq != expected {
+            return Err(Error::ManifestError(format!(
+                "expected batch {} but found batch {} ({}): batches are missing or out of order",
+                expected,
+                entry.seq,
+                entry.path.display()
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_batch(entry: &ManifestEntry, rows: u64, digest: &str) -> Result<()> {
+    if let Some(expected) = entry.rows.filter(|&expected| expected != rows) {
+        return Err(Error::ManifestError(format!(
+            "batch {} ({}) has {} rows, manifest expects {}",
+            entry.seq,
+            entry.path.display(),
+            rows,
+            expected
+        )));
+    }
+    if let Some(expected) = entry
+        .sha256
+        .as_deref()
+        .filter(|expected| !expected.eq_ignore_ascii_case(digest))
+    {
+        return Err(Error::ManifestError(format!(
+            "batch {} ({}) has sha256 {}, manifest expects {}",
+            entry.seq,
+            entry.path.display(),
+            digest,
+            expected
+        )));
+    }
+
+    Ok(())
+}
+
+// single pass over a batch counting csv data rows (header excluded) while hashing the raw bytes
+fn count_and_digest<R: Read>(reader: R, compression: Compression) -> Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(compression::decompress(
+            HashingReader {
+                inner: reader,
+                hasher: &mut hasher,
+            },
+            compression,
+        )?);
+
+    let mut rows = 0;
+    for record in rdr.byte_records() {
+        record?;
+        rows += 1;
+    }
+    drop(rdr);
+
+    Ok((rows, format!("{:x}", hasher.finalize())))
+}
+
+// what to do with an input whose contents were processed before
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Reprocess {
+    // pass over it, logging a warning
+    #[default]
+    Skip,
+    // fail the run before anything is processed
+    Refuse,
+}
+
+// an input file as recorded in the processed-files list
+#[derive(Debug, Deserialize, Serialize)]
+struct ProcessedFile {
+    name: String,
+    sha256: String,
+}
+
+// the input files earlier runs processed, kept as a CSV of `name,sha256` so a re-delivered file
+// isn't applied twice. Files are told apart by the digest of their contents, as a re-delivery may
+// come under another name. Stdin and remote objects aren't tracked
+pub struct ProcessedFiles {
+    path: PathBuf,
+    // names by digest, of earlier runs' files and then this run's
+    seen: HashMap<String, String>,
+    added: Vec<ProcessedFile>,
+}
+
+impl ProcessedFiles {
+    // the list at `path`, empty if there's no file there yet
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut seen = HashMap::new();
+        match File::open(path) {
+            Ok(file) => {
+                for file in csv::Reader::from_reader(file).deserialize() {
+                    let ProcessedFile { name, sha256 } = file?;
+                    seen.insert(sha256, name);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            seen,
+            added: Vec::new(),
+        })
+    }
+
+    // the inputs still to process, in order, passing over or refusing the ones with contents
+    // processed before, by an earlier run or earlier in this one. The rest are only added to the
+    // list once `save` is called
+    pub fn filter(&mut self, inputs: Vec<PathBuf>, reprocess: Reprocess) -> Result<Vec<PathBuf>> {
+        let mut kept = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            if input.as_os_str() == STDIN_PATH || remote::is_remote(&input) {
+                kept.push(input);
+                continue;
+            }
+            let digest = digest(File::open(&input)?)?;
+            let name = input.display().to_string();
+            match (self.seen.get(&digest), reprocess) {
+                (Some(earlier), Reprocess::Skip) => {
+                    tracing::warn!(file = %name, processed_as = %earlier, "skipping input processed before");
+                }
+                (Some(earlier), Reprocess::Refuse) => {
+                    return Err(Error::ManifestError(format!(
+                        "{} has the same contents as {}, which was processed before",
+                        name, earlier
+                    )));
+                }
+                (None, _) => {
+                    self.seen.insert(digest.clone(), name.clone());
+                    self.added.push(ProcessedFile {
+                        name,
+                        sha256: digest,
+                    });
+                    kept.push(input);
+                }
+            }
+        }
+
+        Ok(kept)
+    }
+
+    // appends the files this run processed to the list
+    pub fn save(&self) -> Result<()> {
+        let header = !self.path.exists();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(header)
+            .from_writer(file);
+        for file in &self.added {
+            writer.serialize(file)?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+// the sha256 of a file's bytes as stored, compressed or not
+fn digest<R: Read>(reader: R) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(
+        &mut HashingReader {
+            inner: reader,
+            hasher: &mut hasher,
+        },
+        &mut io::sink(),
+    )?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+struct HashingReader<'a, R> {
+    inner: R,
+    hasher: &'a mut Sha256,
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(seq: u32, rows: Option<u64>, sha256: Option<&str>) -> ManifestEntry {
+        ManifestEntry {
+            seq,
+            path: PathBuf::from(format!("batch-{}.csv", seq)),
+            rows,
+            sha256: sha256.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_validate_sequence_success() {
+        let entries = vec![entry(1, None, None), entry(2, None, None)];
+
+        assert!(validate_sequence(&entries).is_ok());
+    }
+
+    #[test]
+    fn test_validate_sequence_failure_missing_batch() {
+        let entries = vec![entry(1, None, None), entry(3, None, None)];
+
+        assert!(validate_sequence(&entries).is_err());
+    }
+
+    #[test]
+    fn test_validate_sequence_failure_out_of_order() {
+        let entries = vec![entry(2, None, None), entry(1, None, None)];
+
+        assert!(validate_sequence(&entries).is_err());
+    }
+
+    #[test]
+    fn test_validate_sequence_failure_empty() {
+        assert!(validate_sequence(&[]).is_err());
+    }
+
+    #[test]
+    fn test_count_and_digest() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndispute,1,1,\n";
+
+        let (rows, digest) = count_and_digest(input.as_bytes(), Compression::None).unwrap();
+
+        assert_eq!(rows, 2);
+        assert_eq!(digest, format!("{:x}", Sha256::digest(input.as_bytes())));
+    }
+
+    #[test]
+    fn test_processed_files_filter() {
+        let dir =
+            std::env::temp_dir().join(format!("payments-engine-processed-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        };
+        let first = write("a.csv", "type,client,tx,amount\ndeposit,1,1,1.0\n");
+        let second = write("b.csv", "type,client,tx,amount\ndeposit,1,2,1.0\n");
+        // a re-delivery of a.csv under another name
+        let again = write("a-again.csv", "type,client,tx,amount\ndeposit,1,1,1.0\n");
+        let list = dir.join("processed.csv");
+
+        let mut processed = ProcessedFiles::load(&list).unwrap();
+        let kept = processed
+            .filter(vec![first.clone(), again.clone()], Reprocess::Skip)
+            .unwrap();
+        assert_eq!(kept, [first.as_path()]);
+        processed.save().unwrap();
+
+        // a later run picks up where the list left off
+        let mut processed = ProcessedFiles::load(&list).unwrap();
+        let kept = processed
+            .filter(vec![again.clone(), second.clone()], Reprocess::Skip)
+            .unwrap();
+        assert_eq!(kept, [second.as_path()]);
+        processed.save().unwrap();
+        let error = ProcessedFiles::load(&list)
+            .unwrap()
+            .filter(vec![second], Reprocess::Refuse)
+            .unwrap_err();
+        assert!(matches!(error, Error::ManifestError(_)));
+        assert_eq!(std::fs::read_to_string(&list).unwrap().lines().count(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_batch_success() {
+        let digest = "ab".repeat(32);
+        let batch = entry(1, Some(2), Some(&digest.to_uppercase()));
+
+        assert!(validate_batch(&batch, 2, &digest).is_ok());
+        assert!(validate_batch(&entry(1, None, None), 2, &digest).is_ok());
+    }
+
+    #[test]
+    fn test_validate_batch_failure_row_count() {
+        let batch = entry(1, Some(3), None);
+
+        assert!(validate_batch(&batch, 2, "").is_err());
+    }
+
+    #[test]
+    fn test_validate_batch_failure_digest() {
+        let batch = entry(1, None, Some("00"));
+
+        assert!(validate_batch(&batch, 2, &"ab".repeat(32)).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicU64;
+use std::time::Duration;
+
+use prometheus_client::{
+    encoding::{EncodeLabelSet, text::encode},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{Histogram, exponential_buckets},
+    },
+    registry::Registry,
+};
+use rust_decimal::prelude::ToPrimitive;
+
+use payments_engine::{Account, Amount, AmountExt, Error, TransactionType};
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct TypeLabels {
+    r#type: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct CodeLabels {
+    code: String,
+}
+
+// the default currency is exported with an empty label
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct CurrencyLabels {
+    currency: String,
+}
+
+// Prometheus metrics for the server: counters and latencies are recorded as transactions are
+// handled, while the account gauges are refreshed from the engine on every scrape
+pub struct Metrics {
+    registry: Registry,
+    applied: Family<TypeLabels, Counter>,
+    failures: Family<CodeLabels, Counter>,
+    latency: Histogram,
+    accounts: Gauge,
+    held: Family<CurrencyLabels, Gauge<f64, AtomicU64>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let mut registry = Registry::with_prefix("payments");
+        let applied = Family::<TypeLabels, Counter>::default();
+        registry.register(
+            "transactions",
+            "Transactions applied, per type",
+            applied.clone(),
+        );
+        let failures = Family::<CodeLabels, Counter>::default();
+        registry.register(
+            "failures",
+            "Transactions that failed, per error code",
+            failures.clone(),
+        );
+        // 10µs up to ~2.6s
+        let latency = Histogram::new(exponential_buckets(0.00001, 4.0, 10));
+        registry.register(
+            "processing_seconds",
+            "Time taken to process a transaction, including queueing for the engine",
+            latency.clone(),
+        );
+        let accounts = Gauge::default();
+        registry.register("accounts", "Client accounts", accounts.clone());
+        let held = Family::<CurrencyLabels, Gauge<f64, AtomicU64>>::default();
+        registry.register(
+            "held",
+            "Funds held by open disputes, uncleared deposits and authorizations, per currency",
+            held.clone(),
+        );
+
+        Self {
+            registry,
+            applied,
+            failures,
+            latency,
+            accounts,
+            held,
+        }
+    }
+}
+
+impl Metrics {
+    pub fn record_applied(&self, tx_type: TransactionType, elapsed: Duration) {
+        self.applied
+            .get_or_create(&TypeLabels {
+                r#type: tx_type.to_string(),
+            })
+            .inc();
+        self.latency.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn record_failure(&self, error: &Error, elapsed: Duration) {
+        self.failures
+            .get_or_create(&CodeLabels {
+                code: error.code().to_string(),
+            })
+            .inc();
+        self.latency.observe(elapsed.as_secs_f64());
+    }
+
+    // the text exposition format, with the account gauges taken from `accounts`
+    pub fn render(&self, accounts: &[Account]) -> String {
+        self.accounts.set(accounts.len() as i64);
+        let mut held = BTreeMap::<&str, Amount>::new();
+        for account in accounts {
+            for (currency, balance) in &account.balances {
+                *held.entry(currency).or_default() += balance.held;
+            }
+        }
+        self.held.clear();
+        for (currency, amount) in held {
+            self.held
+                .get_or_create(&CurrencyLabels {
+                    currency: currency.to_string(),
+                })
+                .set(amount.to_decimal().to_f64().unwrap_or(f64::NAN));
+        }
+
+        let mut out = String::new();
+        // writing to a String can't fail
+        let _ = encode(&mut out, &self.registry);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::amount;
+    use payments_engine::{Balance, DEFAULT_CURRENCY};
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.record_applied(TransactionType::Deposit, Duration::from_millis(1));
+        metrics.record_failure(&Error::InsufficientFunds(""), Duration::from_millis(1));
+        let mut account = Account::new(1);
+        account.balances.insert(
+            DEFAULT_CURRENCY.to_string(),
+            Balance {
+                available: amount!(5),
+                held: amount!(2.5),
+                total: amount!(7.5),
+            },
+        );
+
+        let out = metrics.render(&[account]);
+
+        assert!(out.contains("payments_transactions_total{type=\"deposit\"} 1"));
+        assert!(out.contains("payments_failures_total{code=\"insufficient-funds\"} 1"));
+        assert!(out.contains("payments_processing_seconds_count 2"));
+        assert!(out.contains("payments_accounts 1"));
+        assert!(out.contains("payments_held{currency=\"\"} 2.5"));
+    }
+}
@@ -0,0 +1,221 @@
+use crate::{
+    engine::PaymentsEngine,
+    error::{ErrorContext, Result},
+    transaction::Transaction,
+};
+
+/// A layer around [`process_tx`](PaymentsEngine::process_tx), registered with
+/// [`middleware`](crate::PaymentsEngineBuilder::middleware), for cross-cutting concerns like
+/// dedup, rate limiting, enrichment or metrics.
+///
+/// Like a tower layer, a middleware gets each transaction with the [`Next`] layer in, and
+/// decides what to do with it:
+/// - before: pass on a changed transaction, e.g. with a currency filled in, or refuse it by
+///   returning an error without calling [`Next::run`], leaving the engine untouched
+/// - after: look at the result [`Next::run`] returns, and return it or another
+///
+/// Middleware run in the order they were registered, the first being the outermost. Unlike
+/// [`EngineObserver`](crate::EngineObserver)s, which only see what reaches the engine, they
+/// see every transaction given to `process_tx`.
+///
+/// ```
+/// use payments_engine::{
+///     Error, Middleware, Next, PaymentsEngine, Result, Transaction, TransactionType, amount,
+/// };
+///
+/// // refuses withdrawals once a client has made `limit` of them
+/// struct WithdrawalCap {
+///     limit: usize,
+///     made: std::collections::HashMap<u16, usize>,
+/// }
+///
+/// impl Middleware for WithdrawalCap {
+///     fn handle(&mut self, tx: &Transaction, next: Next<'_>) -> Result<()> {
+///         if tx.tx_type != TransactionType::Withdrawal {
+///             return next.run(tx);
+///         }
+///         let made = self.made.entry(tx.account_id).or_default();
+///         if *made == self.limit {
+///             return Err(Error::LimitExceeded("Too many withdrawals."));
+///         }
+///         next.run(tx).inspect(|()| *made += 1)
+///     }
+/// }
+///
+/// let mut engine = PaymentsEngine::builder()
+///     .middleware(Box::new(WithdrawalCap { limit: 1, made: Default::default() }))
+///     .build();
+/// let tx = |tx_type, tx_id| Transaction {
+///     tx_type,
+///     account_id: 1,
+///     tx_id,
+///     amount: Some(amount!(1)),
+///     currency: None,
+///     timestamp: None,
+///     reason: None,
+///     effective: None,
+/// };
+/// engine.process_tx(&tx(TransactionType::Deposit, 1)).unwrap();
+/// engine.process_tx(&tx(TransactionType::Withdrawal, 2)).unwrap();
+///
+/// let result = engine.process_tx(&tx(TransactionType::Withdrawal, 3));
+///
+/// assert!(matches!(result.unwrap_err().root(), Error::LimitExceeded(_)));
+/// ```
+pub trait Middleware {
+    /// Handles `tx`, passing it on through `next` or not.
+    fn handle(&mut self, tx: &Transaction, next: Next<'_>) -> Result<()>;
+}
+
+/// The layers inside a [`Middleware`], ending with the engine itself.
+pub struct Next<'a> {
+    middleware: &'a mut [Box<dyn Middleware + Send>],
+    engine: &'a mut PaymentsEngine,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middleware: &'a mut [Box<dyn Middleware + Send>],
+        engine: &'a mut PaymentsEngine,
+    ) -> Self {
+        Self { middleware, engine }
+    }
+
+    /// The engine, as it is before `tx` is passed on, e.g. to look up the client's account.
+    pub fn engine(&self) -> &PaymentsEngine {
+        self.engine
+    }
+
+    /// Passes `tx` on to the next layer, applying it once it reaches the engine.
+    pub fn run(self, tx: &Transaction) -> Result<()> {
+        match self.middleware.split_first_mut() {
+            Some((first, rest)) => first
+                .handle(tx, Next::new(rest, self.engine))
+                // a middleware's own errors get the transaction's context as the engine's do
+                .map_err(|e| e.with_context(ErrorContext::for_tx(tx))),
+            None => self.engine.apply_tx(tx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::amount::Amount;
+    use crate::error::Error;
+    use crate::testing::TxBuilder;
+    use crate::transaction::DEFAULT_CURRENCY;
+    use std::sync::{Arc, Mutex};
+
+    // records what it sees before and after the layers inside it
+    struct Trace(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl Middleware for Trace {
+        fn handle(&mut self, tx: &Transaction, next: Next<'_>) -> Result<()> {
+            self.1
+                .lock()
+                .unwrap()
+                .push(format!("{} before {}", self.0, tx.tx_id));
+            let result = next.run(tx);
+            self.1.lock().unwrap().push(format!(
+                "{} after {} ok={}",
+                self.0,
+                tx.tx_id,
+                result.is_ok()
+            ));
+            result
+        }
+    }
+
+    // fills in a currency for transactions without one
+    struct DefaultCurrency;
+
+    impl Middleware for DefaultCurrency {
+        fn handle(&mut self, tx: &Transaction, next: Next<'_>) -> Result<()> {
+            match tx.currency {
+                Some(_) => next.run(tx),
+                None => next.run(&Transaction {
+                    currency: Some("EUR".to_string()),
+                    ..tx.clone()
+                }),
+            }
+        }
+    }
+
+    // refuses clients the engine doesn't know yet
+    struct KnownClientsOnly;
+
+    impl Middleware for KnownClientsOnly {
+        fn handle(&mut self, tx: &Transaction, next: Next<'_>) -> Result<()> {
+            if tx.tx_id > 1 && next.engine().account(tx.account_id).is_none() {
+                return Err(Error::AccountError("Unknown client."));
+            }
+            next.run(tx)
+        }
+    }
+
+    #[test]
+    fn test_middleware_run_in_order_around_the_engine() {
+        let trace = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = PaymentsEngine::builder()
+            .middleware(Box::new(Trace("outer", trace.clone())))
+            .middleware(Box::new(Trace("inner", trace.clone())))
+            .build();
+
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(5)).build())
+            .unwrap();
+        let result = engine.process_tx(&TxBuilder::withdrawal(1, 2, amount!(10)).build());
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::InsufficientFunds(_)
+        ));
+        assert_eq!(
+            *trace.lock().unwrap(),
+            [
+                "outer before 1",
+                "inner before 1",
+                "inner after 1 ok=true",
+                "outer after 1 ok=true",
+                "outer before 2",
+                "inner before 2",
+                "inner after 2 ok=false",
+                "outer after 2 ok=false",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_middleware_changes_transaction_success() {
+        let mut engine = PaymentsEngine::builder()
+            .middleware(Box::new(DefaultCurrency))
+            .build();
+
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(5)).build())
+            .unwrap();
+
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.balance("EUR").total, amount!(5));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).total, Amount::ZERO);
+    }
+
+    #[test]
+    fn test_middleware_refuses_transaction_failure() {
+        let mut engine = PaymentsEngine::builder()
+            .middleware(Box::new(KnownClientsOnly))
+            .build();
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(5)).build())
+            .unwrap();
+
+        let result = engine.process_tx(&TxBuilder::deposit(2, 2, amount!(5)).build());
+
+        let error = result.unwrap_err();
+        assert!(matches!(error.root(), Error::AccountError(_)));
+        assert_eq!(error.context().unwrap().tx_id, Some(2));
+        assert!(engine.account(2).is_none());
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use payments_engine::{AccountStatus, AmountExt, DEFAULT_CURRENCY, Error, PaymentsEngine, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::dates;
+
+// every record is this many characters, and the file is padded to whole blocks of records
+const RECORD_SIZE: usize = 94;
+const BLOCKING_FACTOR: usize = 10;
+// credits only
+const SERVICE_CLASS: &str = "220";
+// the largest amount an entry's 10-digit field holds, in cents
+const MAX_ENTRY_CENTS: u64 = 9_999_999_999;
+
+// the originator and the receiving bank accounts of an ACH payout file, from TOML:
+//
+//   destination = "091000019"
+//   destination_name = "FIRST BANK"
+//   origin = "1234567890"
+//   origin_name = "ACME PAYMENTS"
+//   company_name = "ACME PAYMENTS"
+//   company_id = "1234567890"
+//   entry_description = "PAYOUT"
+//
+//   [[clients]]
+//   client = 7
+//   name = "Jane Doe"
+//   routing = "021000021"
+//   account = "12345678"
+//   account_type = "checking"
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AchConfig {
+    // routing number of the bank the file is sent to
+    destination: String,
+    destination_name: String,
+    // the originator's id with that bank, often its tax id
+    origin: String,
+    origin_name: String,
+    company_name: String,
+    company_id: String,
+    // the first 8 digits of the originating bank's routing number; the destination's by default
+    #[serde(default)]
+    originating_dfi: Option<String>,
+    #[serde(default = "default_description")]
+    entry_description: String,
+    #[serde(default)]
+    clients: Vec<BankAccount>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BankAccount {
+    client: u16,
+    name: String,
+    routing: String,
+    account: String,
+    #[serde(default)]
+    account_type: AccountType,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum AccountType {
+    #[default]
+    Checking,
+    Savings,
+}
+
+impl AccountType {
+    // the transaction code of a credit to it
+    fn credit_code(self) -> &'static str {
+        match self {
+            AccountType::Checking => "22",
+            AccountType::Savings => "32",
+        }
+    }
+}
+
+fn default_description() -> String {
+    "PAYOUT".to_string()
+}
+
+impl AchConfig {
+    // fails with a config error on an invalid routing number, a field too long for its record
+    // or a client listed twice
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let config: AchConfig =
+            toml::from_str(text).map_err(|e| Error::ConfigError(e.to_string()))?;
+        check_routing("destination", &config.destination)?;
+        for (field, value, width) in [
+            ("origin", &config.origin, 10),
+            ("company_id", &config.company_id, 10),
+        ] {
+            if value.is_empty() || value.len() > width || !value.is_ascii() {
+                return Err(Error::ConfigError(format!(
+                    "ACH {} must be 1 to {} ASCII characters",
+                    field, width
+                )));
+            }
+        }
+        if let Some(dfi) = &config.originating_dfi
+            && (dfi.len() != 8 || !dfi.bytes().all(|b| b.is_ascii_digit()))
+        {
+            return Err(Error::ConfigError(
+                "ACH originating_dfi must be 8 digits".to_string(),
+            ));
+        }
+        let mut seen = BTreeMap::new();
+        for account in &config.clients {
+            check_routing(
+                &format!("routing of client {}", account.client),
+                &account.routing,
+            )?;
+            if account.account.is_empty()
+                || account.account.len() > 17
+                || !account
+                    .account
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+            {
+                return Err(Error::ConfigError(format!(
+                    "ACH account of client {} must be 1 to 17 letters, digits or hyphens",
+                    account.client
+                )));
+            }
+            if seen.insert(account.client, ()).is_some() {
+                return Err(Error::ConfigError(format!(
+                    "client {} has more than one ACH bank account",
+                    account.client
+                )));
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn originating_dfi(&self) -> &str {
+        self.originating_dfi
+            .as_deref()
+            .unwrap_or(&self.destination[..8])
+    }
+}
+
+// a 9-digit ABA routing number whose check digit matches
+fn check_routing(field: &str, routing: &str) -> Result<()> {
+    let digits: Vec<u32> = routing.chars().filter_map(|c| c.to_digit(10)).collect();
+    let valid = routing.len() == 9
+        && digits.len() == 9
+        && (3 * (digits[0] + digits[3] + digits[6])
+            + 7 * (digits[1] + digits[4] + digits[7])
+            + (digits[2] + digits[5] + digits[8]))
+            .is_multiple_of(10);
+    match valid {
+        true => Ok(()),
+        false => Err(Error::ConfigError(format!(
+            "ACH {} `{}` is not a valid routing number",
+            field, routing
+        ))),
+    }
+}
+
+// write a NACHA file with a single PPD batch crediting every configured client's available
+// balance in the default currency, in whole cents, to its bank account on the `effective` day,
+// returning how many entries it has. Accounts that are locked, frozen or closed, or have nothing
+// to pay out, are left out, as are clients without a bank account, which are logged. `now` dates
+// the file
+pub fn write<W: Write>(
+    mut out: W,
+    engine: &PaymentsEngine,
+    config: &AchConfig,
+    effective: u64,
+    now: u64,
+) -> Result<usize> {
+    let accounts: BTreeMap<u16, &BankAccount> = config
+        .clients
+        .iter()
+        .map(|account| (account.client, account))
+        .collect();
+    let mut payouts = Vec::new();
+    let mut clients: Vec<_> = engine.accounts().collect();
+    clients.sort_unstable_by_key(|account| account.id);
+    for account in clients {
+        let available = account.balance(DEFAULT_CURRENCY).available.to_decimal();
+        let cents = (available * Decimal::ONE_HUNDRED).floor();
+        if account.status != AccountStatus::Active || cents <= Decimal::ZERO {
+            continue;
+        }
+        let Some(bank) = accounts.get(&account.id) else {
+            tracing::warn!(client = account.id, "no ACH bank account to pay out to");
+            continue;
+        };
+        let cents = u64::try_from(cents)
+            .ok()
+            .filter(|&cents| cents <= MAX_ENTRY_CENTS)
+            .ok_or_else(|| {
+                Error::ConfigError(format!(
+                    "payout to client {} is too large for an ACH entry",
+                    account.id
+                ))
+            })?;
+        payouts.push((*bank, cents));
+    }
+
+    let created = dates::compact(now);
+    let dfi = config.originating_dfi();
+    let mut records = vec![format!(
+        "101 {}{:>10}{}{}A{:03}{:02}1{}{}{}",
+        config.destination,
+        config.origin,
+        &created[2..8],
+        &created[8..12],
+        RECORD_SIZE,
+        BLOCKING_FACTOR,
+        alpha(&config.destination_name, 23),
+        alpha(&config.origin_name, 23),
+        alpha("", 8),
+    )];
+    let mut hash = 0u64;
+    let mut total = 0u64;
+    if !payouts.is_empty() {
+        records.push(format!(
+            "5{}{}{}{}PPD{}{}{}   1{}{:07}",
+            SERVICE_CLASS,
+            alpha(&config.company_name, 16),
+            alpha("", 20),
+            alpha(&config.company_id, 10),
+            alpha(&config.entry_description, 10),
+            &created[2..8],
+            &dates::compact(effective)[2..8],
+            dfi,
+            1
+        ));
+        for (i, (bank, cents)) in payouts.iter().enumerate() {
+            hash += bank.routing[..8]
+                .parse::<u64>()
+                .expect("routing numbers are digits");
+            total += cents;
+            records.push(format!(
+                "6{}{}{}{:010}{}{}  0{}{:07}",
+                bank.account_type.credit_code(),
+                &bank.routing,
+                alpha(&bank.account, 17),
+                cents,
+                alpha(&bank.client.to_string(), 15),
+                alpha(&bank.name, 22),
+                dfi,
+                i + 1
+            ));
+        }
+        records.push(format!(
+            "8{}{:06}{:010}{:012}{:012}{}{}{}{}{:07}",
+            SERVICE_CLASS,
+            payouts.len(),
+            hash % 10_000_000_000,
+            0,
+            total,
+            alpha(&config.company_id, 10),
+            alpha("", 19),
+            alpha("", 6),
+            dfi,
+            1
+        ));
+    }
+    let blocks = (records.len() + 1).div_ceil(BLOCKING_FACTOR);
+    records.push(format!(
+        "9{:06}{:06}{:08}{:010}{:012}{:012}{}",
+        usize::from(!payouts.is_empty()),
+        blocks,
+        payouts.len(),
+        hash % 10_000_000_000,
+        0,
+        total,
+        alpha("", 39)
+    ));
+    // the last block is filled out with records of nines
+    records.resize(blocks * BLOCKING_FACTOR, "9".repeat(RECORD_SIZE));
+    for record in &records {
+        debug_assert_eq!(record.len(), RECORD_SIZE, "{}", record);
+        writeln!(out, "{}", record)?;
+    }
+    out.flush()?;
+
+    Ok(payouts.len())
+}
+
+// `value` in upper case, left-justified in a field of `width`, cut to fit; characters outside
+// printable ASCII become spaces
+fn alpha(value: &str, width: usize) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| match c.is_ascii_graphic() {
+            true => c.to_ascii_uppercase(),
+            false => ' ',
+        })
+        .take(width)
+        .collect();
+    format!("{:<width$}", value, width = width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::amount;
+    use payments_engine::testing::TxBuilder;
+
+    const CONFIG: &str = r#"
+        destination = "091000019"
+        destination_name = "First Bank"
+        origin = "1234567890"
+        origin_name = "Acme Payments"
+        company_name = "Acme Payments"
+        company_id = "1234567890"
+
+        [[clients]]
+        client = 1
+        name = "Jane Doe"
+        routing = "021000021"
+        account = "12345678"
+
+        [[clients]]
+        client = 2
+        name = "John Roe"
+        routing = "091000019"
+        account = "987-654"
+        account_type = "savings"
+
+        [[clients]]
+        client = 3
+        name = "Locked Out"
+        routing = "021000021"
+        account = "555"
+    "#;
+
+    #[test]
+    fn test_write() {
+        let mut engine = PaymentsEngine::new();
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(100.1299)).build(),
+            TxBuilder::deposit(2, 2, amount!(20)).build(),
+            TxBuilder::deposit(3, 3, amount!(30)).build(),
+            TxBuilder::dispute(3, 3).build(),
+            TxBuilder::chargeback(3, 3).build(),
+            // no bank account
+            TxBuilder::deposit(4, 4, amount!(40)).build(),
+        ] {
+            engine.process_tx(&tx).unwrap();
+        }
+        let config = AchConfig::from_toml(CONFIG).unwrap();
+        let mut out = Vec::new();
+
+        // 2024-03-01 12:30 UTC, paid out the next day
+        let now = 1_709_296_200;
+        let written = write(&mut out, &engine, &config, now + 86_400, now).unwrap();
+
+        assert_eq!(written, 2);
+        let file = String::from_utf8(out).unwrap();
+        let records: Vec<&str> = file.lines().collect();
+        assert_eq!(
+            records[..6],
+            [
+                "101 09100001912345678902403011230A094101FIRST BANK             ACME PAYMENTS                  ",
+                "5220ACME PAYMENTS                       1234567890PPDPAYOUT    240301240302   1091000010000001",
+                // 100.1299 is paid out as 100.12
+                "62202100002112345678         00000100121              JANE DOE                0091000010000001",
+                "632091000019987-654          00000020002              JOHN ROE                0091000010000002",
+                "822000000200112000030000000000000000000120121234567890                         091000010000001",
+                "9000001000001000000020011200003000000000000000000012012                                       ",
+            ]
+        );
+        // padded to a whole block of ten records
+        assert_eq!(records.len(), 10);
+        assert!(
+            records[6..]
+                .iter()
+                .all(|record| *record == "9".repeat(RECORD_SIZE))
+        );
+    }
+
+    #[test]
+    fn test_write_without_payouts() {
+        let config = AchConfig::from_toml(CONFIG).unwrap();
+        let mut out = Vec::new();
+
+        let written = write(&mut out, &PaymentsEngine::new(), &config, 0, 0).unwrap();
+
+        assert_eq!(written, 0);
+        let file = String::from_utf8(out).unwrap();
+        let records: Vec<&str> = file.lines().collect();
+        assert_eq!(records.len(), 10);
+        assert!(records[1].starts_with("9000000000001"));
+    }
+
+    #[test]
+    fn test_from_toml_failure() {
+        for (broken, with) in [
+            (
+                r#"destination = "091000019""#,
+                r#"destination = "091000018""#,
+            ),
+            (r#"account = "555""#, r#"account = "555 666""#),
+            ("client = 3", "client = 2"),
+            (
+                r#"company_id = "1234567890""#,
+                r#"company_id = "12345678901""#,
+            ),
+        ] {
+            let config = CONFIG.replace(broken, with);
+            assert!(
+                matches!(AchConfig::from_toml(&config), Err(Error::ConfigError(_))),
+                "{}",
+                with
+            );
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use crate::amount::Amount;
+
+use crate::{error::Error, events::Event, transaction::Transaction};
+
+/// Hooks into the engine's processing, e.g. for custom alerting, metrics or mirroring, registered
+/// with [`observer`](crate::PaymentsEngineBuilder::observer).
+///
+/// Every callback does nothing by default, so an observer only implements the ones it needs.
+/// Callbacks run synchronously on the processing thread, after the change they report has been
+/// applied; they can't fail or hold up the change. The callbacks for what a transaction changed
+/// (a lock, an opened dispute) come before [`on_tx_applied`](Self::on_tx_applied) for it.
+pub trait EngineObserver {
+    /// `tx` was applied. A dispute parked until its tx arrives is reported once it is applied.
+    fn on_tx_applied(&mut self, _tx: &Transaction) {}
+
+    /// `tx` failed with `error`, as [`process_tx`](crate::PaymentsEngine::process_tx) returns it.
+    fn on_tx_rejected(&mut self, _tx: &Transaction, _error: &Error) {}
+
+    /// `client`'s account was locked, e.g. by a chargeback.
+    fn on_account_locked(&mut self, _client: u16, _reason: &str) {}
+
+    /// A dispute on `client`'s `tx` was opened, holding `amount` in `currency`.
+    fn on_dispute_opened(&mut self, _client: u16, _tx: u32, _currency: &str, _amount: Amount) {}
+}
+
+// tell `observers` about an applied state change they have a callback for
+pub(crate) fn notify(observers: &mut [Box<dyn EngineObserver + Send>], event: &Event) {
+    for observer in observers {
+        match event {
+            Event::AccountLocked { client, reason } => observer.on_account_locked(*client, reason),
+            Event::DisputeOpened {
+                client,
+                tx,
+                currency,
+                amount,
+                ..
+            } => observer.on_dispute_opened(*client, *tx, currency, *amount),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use payments_engine::{AmountExt, Balance, DEFAULT_CURRENCY, HistoryEntry, PaymentsEngine, Result};
+use rust_decimal::Decimal;
+
+use crate::{camt053, dates, output::fixed_dp};
+
+// OFX wants a bank routing number, which doesn't apply here; importers only need one present
+const BANK_ID: &str = "000000000";
+
+// the days a statement covers, as seconds since the epoch: from the start of the first day up
+// to, not including, the end of the last
+#[derive(Clone, Copy)]
+pub struct Period {
+    pub start: u64,
+    pub end: u64,
+}
+
+// the layout statements are written in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatementFormat {
+    // OFX 2.2, `<client>.ofx`
+    #[default]
+    Ofx,
+    // ISO 20022 camt.053 bank-to-customer statement, `<client>.xml`
+    Camt053,
+}
+
+// one currency's statement
+pub struct Statement<'a> {
+    pub currency: &'a str,
+    // the changes dated within the period that moved the total, in date order, with their index
+    // in the client's history and how much they moved it by
+    pub lines: Vec<(usize, &'a HistoryEntry, Decimal)>,
+    // the balance as of the start of the period
+    pub opening: Balance,
+    // the balance as of the end of the period
+    pub closing: Balance,
+}
+
+// when a change took effect: a backdated transaction's effective date, else when it happened
+fn date(entry: &HistoryEntry) -> Option<u64> {
+    entry.effective.or(entry.timestamp)
+}
+
+// write the statement of every account with balance changes up to the end of `period` into
+// `dir` as `<client>.ofx` or `<client>.xml`, returning how many were written. Amounts in the
+// default currency are reported in `default_currency`
+pub fn export(
+    engine: &PaymentsEngine,
+    period: Period,
+    default_currency: &str,
+    dir: &Path,
+    format: StatementFormat,
+) -> Result<usize> {
+    let now = dates::now();
+    let mut clients: Vec<u16> = engine.accounts().map(|account| account.id).collect();
+    clients.sort_unstable();
+
+    let mut written = 0;
+    for client in clients {
+        let statements = statements(engine.history(client), period);
+        if statements.is_empty() {
+            continue;
+        }
+        let extension = match format {
+            StatementFormat::Ofx => "ofx",
+            StatementFormat::Camt053 => "xml",
+        };
+        let mut out = BufWriter::new(File::create(dir.join(format!("{}.{}", client, extension)))?);
+        match format {
+            StatementFormat::Ofx => {
+                write(&mut out, client, &statements, period, default_currency, now)?
+            }
+            StatementFormat::Camt053 => {
+                camt053::write(&mut out, client, &statements, period, default_currency, now)?
+            }
+        }
+        out.flush()?;
+        written += 1;
+    }
+
+    Ok(written)
+}
+
+// a client's history split by currency, each change placed by its date. Undated changes (e.g.
+// seeded balances) take the date of the change applied before them, counting toward the
+// balances but not listed, as they can't be placed in the period themselves. The opening and
+// closing balances add up the changes dated before the start and the end of the period, so a
+// change backdated into the period after its end moves both its closing balance and those of
+// later periods
+pub fn statements(history: &[HistoryEntry], period: Period) -> Vec<Statement<'_>> {
+    let mut by_currency: BTreeMap<&str, Statement> = BTreeMap::new();
+    // each currency's balance before the change at hand, to tell what it changed
+    let mut balances: BTreeMap<&str, Balance> = BTreeMap::new();
+    let mut placed = None;
+    for (index, entry) in history.iter().enumerate() {
+        placed = date(entry).or(placed);
+        let before = balances
+            .insert(&entry.currency, entry.balance)
+            .unwrap_or_default();
+        let change = Balance {
+            available: entry.balance.available - before.available,
+            held: entry.balance.held - before.held,
+            total: entry.balance.total - before.total,
+        };
+        if placed.is_some_and(|placed| placed >= period.end) {
+            continue;
+        }
+        let statement = by_currency
+            .entry(&entry.currency)
+            .or_insert_with(|| Statement {
+                currency: &entry.currency,
+                lines: Vec::new(),
+                opening: Balance::default(),
+                closing: Balance::default(),
+            });
+        statement.closing = add(statement.closing, change);
+        if placed.is_some_and(|placed| placed >= period.start) {
+            let total = change.total.to_decimal();
+            if date(entry).is_some() && !total.is_zero() {
+                statement.lines.push((index, entry, total));
+            }
+        } else {
+            statement.opening = add(statement.opening, change);
+        }
+    }
+
+    by_currency
+        .into_values()
+        .map(|mut statement| {
+            statement
+                .lines
+                .sort_by_key(|(index, entry, _)| (date(entry), *index));
+            statement
+        })
+        .collect()
+}
+
+fn add(balance: Balance, change: Balance) -> Balance {
+    Balance {
+        available: balance.available + change.available,
+        held: balance.held + change.held,
+        total: balance.total + change.total,
+    }
+}
+
+// an OFX 2.2 document with one bank statement per currency
+fn write<W: Write>(
+    out: &mut W,
+    client: u16,
+    statements: &[Statement],
+    period: Period,
+    default_currency: &str,
+    now: u64,
+) -> Result<()> {
+    let start = dates::compact(period.start);
+    let end = dates::compact(period.end - 1);
+    writeln!(
+        out,
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>"#
+    )?;
+    writeln!(
+        out,
+        r#"<?OFX OFXHEADER="200" VERSION="220" SECURITY="NONE" OLDFILEUID="NONE" NEWFILEUID="NONE"?>"#
+    )?;
+    writeln!(out, "<OFX>")?;
+    writeln!(out, "<SIGNONMSGSRSV1><SONRS>")?;
+    writeln!(
+        out,
+        "<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>"
+    )?;
+    writeln!(out, "<DTSERVER>{}</DTSERVER>", dates::compact(now))?;
+    writeln!(out, "<LANGUAGE>ENG</LANGUAGE>")?;
+    writeln!(out, "</SONRS></SIGNONMSGSRSV1>")?;
+    writeln!(out, "<BANKMSGSRSV1>")?;
+    for (i, statement) in statements.iter().enumerate() {
+        // a client's balances in other currencies are told apart as accounts of their own
+        let (currency, account) = match statement.currency {
+            DEFAULT_CURRENCY => (escape(default_currency), client.to_string()),
+            currency => (escape(currency), format!("{}-{}", client, escape(currency))),
+        };
+        writeln!(out, "<STMTTRNRS>")?;
+        writeln!(out, "<TRNUID>{}</TRNUID>", i)?;
+        writeln!(
+            out,
+            "<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>"
+        )?;
+        writeln!(out, "<STMTRS>")?;
+        writeln!(out, "<CURDEF>{}</CURDEF>", currency)?;
+        writeln!(
+            out,
+            "<BANKACCTFROM><BANKID>{}</BANKID><ACCTID>{}</ACCTID><ACCTTYPE>CHECKING</ACCTTYPE></BANKACCTFROM>",
+            BANK_ID, account
+        )?;
+        writeln!(out, "<BANKTRANLIST>")?;
+        writeln!(out, "<DTSTART>{}</DTSTART><DTEND>{}</DTEND>", start, end)?;
+        for (index, entry, change) in &statement.lines {
+            let kind = match entry.operation {
+                "fee" => "FEE",
+                "interest" => "INT",
+                _ if change.is_sign_negative() => "DEBIT",
+                _ => "CREDIT",
+            };
+            // stable across exports of overlapping periods, so importers can skip what they have
+            let id = match entry.tx {
+                Some(tx) => format!("{}-{}", tx, entry.operation),
+                None => format!("{}-{}", entry.operation, index),
+            };
+            writeln!(out, "<STMTTRN>")?;
+            writeln!(out, "<TRNTYPE>{}</TRNTYPE>", kind)?;
+            writeln!(
+                out,
+                "<DTPOSTED>{}</DTPOSTED>",
+                dates::compact(entry.timestamp.unwrap_or(period.start))
+            )?;
+            // a backdated entry says when it took effect as well as when it was posted
+            if let Some(effective) = entry.effective {
+                writeln!(out, "<DTUSER>{}</DTUSER>", dates::compact(effective))?;
+            }
+            writeln!(out, "<TRNAMT>{}</TRNAMT>", fixed_dp(*change))?;
+            writeln!(out, "<FITID>{}</FITID>", id)?;
+            writeln!(out, "<NAME>{}</NAME>", entry.operation)?;
+            if let Some(tx) = entry.tx {
+                writeln!(out, "<MEMO>tx {}</MEMO>", tx)?;
+            }
+            writeln!(out, "</STMTTRN>")?;
+        }
+        writeln!(out, "</BANKTRANLIST>")?;
+        writeln!(
+            out,
+            "<LEDGERBAL><BALAMT>{}</BALAMT><DTASOF>{}</DTASOF></LEDGERBAL>",
+            fixed_dp(statement.closing.total.to_decimal()),
+            end
+        )?;
+        writeln!(
+            out,
+            "<AVAILBAL><BALAMT>{}</BALAMT><DTASOF>{}</DTASOF></AVAILBAL>",
+            fixed_dp(statement.closing.available.to_decimal()),
+            end
+        )?;
+        writeln!(out, "</STMTRS>")?;
+        writeln!(out, "</STMTTRNRS>")?;
+    }
+    writeln!(out, "</BANKMSGSRSV1>")?;
+    writeln!(out, "</OFX>")?;
+
+    Ok(())
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::testing::TxBuilder;
+    use payments_engine::{Transaction, TransactionType, amount};
+
+    const DAY: u64 = dates::SECS_PER_DAY;
+
+    fn engine() -> PaymentsEngine {
+        let mut engine = PaymentsEngine::builder().track_history(true).build();
+        let txs = [
+            (TransactionType::Deposit, 1, None, Some(0)),
+            (TransactionType::Deposit, 2, None, Some(DAY)),
+            (TransactionType::Dispute, 2, None, Some(DAY + 1)),
+            (TransactionType::Resolve, 2, None, Some(DAY + 2)),
+            (TransactionType::Withdrawal, 5, None, Some(DAY + 3)),
+            (TransactionType::Deposit, 3, Some("EUR"), Some(DAY + 4)),
+            (TransactionType::Deposit, 4, None, Some(2 * DAY)),
+        ];
+        for (tx_type, tx_id, currency, timestamp) in txs {
+            engine
+                .process_tx(&Transaction {
+                    currency: currency.map(str::to_string),
+                    timestamp,
+                    ..TxBuilder::new(tx_type, 1, tx_id)
+                        .amount(amount!(10))
+                        .build()
+                })
+                .unwrap();
+        }
+        engine
+    }
+
+    #[test]
+    fn test_statements() {
+        let engine = engine();
+        let period = Period {
+            start: DAY,
+            end: 2 * DAY,
+        };
+
+        let statements = statements(engine.history(1), period);
+
+        // the default currency sorts first
+        assert_eq!(statements.len(), 2);
+        let lines: Vec<_> = statements[0]
+            .lines
+            .iter()
+            .map(|(_, entry, change)| (entry.operation, entry.tx, *change))
+            .collect();
+        // the dispute and resolve only moved funds between available and held, and the deposits
+        // either side of the period are left out
+        assert_eq!(
+            lines,
+            [
+                ("deposit", Some(2), Decimal::from(10)),
+                ("withdrawal", Some(5), Decimal::from(-10)),
+            ]
+        );
+        assert_eq!(statements[0].opening.total, amount!(10));
+        assert_eq!(statements[0].closing.total, amount!(10));
+        assert_eq!(statements[1].currency, "EUR");
+        assert_eq!(statements[1].opening.total, amount!(0));
+        assert_eq!(statements[1].closing.total, amount!(10));
+    }
+
+    #[test]
+    fn test_statements_place_backdated_changes_by_effective_date() {
+        let mut engine = engine();
+        // posted on the third day, in effect from the second
+        engine
+            .process_tx(&Transaction {
+                effective: Some(DAY + 1),
+                ..TxBuilder::withdrawal(1, 6, amount!(5))
+                    .timestamp(2 * DAY + 10)
+                    .build()
+            })
+            .unwrap();
+        let day = |day| Period {
+            start: day * DAY,
+            end: (day + 1) * DAY,
+        };
+
+        let second = statements(engine.history(1), day(1));
+        let third = statements(engine.history(1), day(2));
+
+        let lines: Vec<_> = second[0]
+            .lines
+            .iter()
+            .map(|(_, entry, change)| (entry.tx, *change))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (Some(2), Decimal::from(10)),
+                (Some(6), Decimal::from(-5)),
+                (Some(5), Decimal::from(-10)),
+            ]
+        );
+        assert_eq!(second[0].closing.total, amount!(5));
+        assert_eq!(third[0].opening.total, amount!(5));
+        assert_eq!(third[0].lines.len(), 1);
+        assert_eq!(third[0].closing.total, amount!(15));
+    }
+
+    #[test]
+    fn test_write() {
+        let engine = engine();
+        let period = Period {
+            start: DAY,
+            end: 2 * DAY,
+        };
+        let statements = statements(engine.history(1), period);
+        let mut out = Vec::new();
+
+        write(&mut out, 1, &statements, period, "USD", 0).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("<CURDEF>USD</CURDEF>"));
+        assert!(out.contains("<ACCTID>1</ACCTID>"));
+        assert!(out.contains("<ACCTID>1-EUR</ACCTID>"));
+        assert!(out.contains("<DTSTART>19700102000000</DTSTART><DTEND>19700102235959</DTEND>"));
+        assert!(out.contains(
+            "<TRNTYPE>DEBIT</TRNTYPE>\n<DTPOSTED>19700102000003</DTPOSTED>\n<TRNAMT>-10.0000</TRNAMT>\n<FITID>5-withdrawal</FITID>"
+        ));
+        assert!(out.contains("<LEDGERBAL><BALAMT>10.0000</BALAMT>"));
+    }
+}
@@ -0,0 +1,166 @@
+use std::io::Write;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use payments_engine::{
+    Account, AccountStatus, AmountExt, Balance, DEFAULT_CURRENCY, PaymentsEngine, Result,
+};
+
+// amounts are written with this many decimal places
+const OUTPUT_DP: u32 = 4;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    // a single JSON array of rows
+    Json,
+    // one JSON row per line
+    Jsonl,
+}
+
+// one output row per (client, currency)--the currency is left out entirely unless some account
+// holds a named currency, so single-currency output keeps its original shape
+#[derive(Serialize)]
+struct AccountRow<'a> {
+    client: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<&'a str>,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    status: AccountStatus,
+}
+
+// write the account balances/state in the given format
+pub fn write_accounts<W: Write>(
+    engine: &PaymentsEngine,
+    writer: W,
+    format: OutputFormat,
+) -> Result<()> {
+    write_account_list(engine.accounts(), writer, format)
+}
+
+// write the balances/state of `accounts`, e.g. as a server returned them, like an engine's
+pub fn write_account_list<'a, W: Write>(
+    accounts: impl IntoIterator<Item = &'a Account>,
+    mut writer: W,
+    format: OutputFormat,
+) -> Result<()> {
+    let accounts: Vec<_> = accounts.into_iter().collect();
+    let multi_currency = accounts.iter().any(|account| {
+        account
+            .balances
+            .keys()
+            .any(|currency| currency != DEFAULT_CURRENCY)
+    });
+    let rows = accounts
+        .into_iter()
+        .flat_map(|account| account_rows(account, multi_currency));
+
+    match format {
+        OutputFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(&mut writer);
+            for row in rows {
+                csv_writer.serialize(row)?;
+            }
+            csv_writer.flush()?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut writer, &rows.collect::<Vec<_>>())
+                .map_err(std::io::Error::other)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Jsonl => {
+            for row in rows {
+                serde_json::to_writer(&mut writer, &row).map_err(std::io::Error::other)?;
+                writeln!(writer)?;
+            }
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn account_rows(account: &Account, multi_currency: bool) -> Vec<AccountRow<'_>> {
+    // an account that never held funds still gets a (zero) row
+    let balances: Vec<_> = if account.balances.is_empty() {
+        vec![(DEFAULT_CURRENCY, Balance::default())]
+    } else {
+        account
+            .balances
+            .iter()
+            .map(|(currency, balance)| (currency.as_str(), *balance))
+            .collect()
+    };
+
+    balances
+        .into_iter()
+        .map(|(currency, balance)| AccountRow {
+            client: account.id,
+            currency: multi_currency.then_some(currency),
+            available: fixed_dp(balance.available.to_decimal()),
+            held: fixed_dp(balance.held.to_decimal()),
+            total: fixed_dp(balance.total.to_decimal()),
+            locked: account.is_locked(),
+            status: account.status,
+        })
+        .collect()
+}
+
+// `amount` with exactly the four decimal places amounts are written with
+pub fn fixed_dp(amount: Decimal) -> Decimal {
+    let mut amount = amount.round_dp(OUTPUT_DP);
+    amount.rescale(OUTPUT_DP);
+    amount
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::amount;
+    use payments_engine::testing::TxBuilder;
+
+    fn engine() -> PaymentsEngine {
+        let mut engine = PaymentsEngine::new();
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(10.5)).build())
+            .unwrap();
+        engine
+    }
+
+    fn output(format: OutputFormat) -> String {
+        let mut out = Vec::new();
+        write_accounts(&engine(), &mut out, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_accounts_csv() {
+        assert_eq!(
+            output(OutputFormat::Csv),
+            "client,available,held,total,locked,status\n1,10.5000,0.0000,10.5000,false,active\n"
+        );
+    }
+
+    #[test]
+    fn test_write_accounts_json() {
+        assert_eq!(
+            output(OutputFormat::Json),
+            "[{\"client\":1,\"available\":\"10.5000\",\"held\":\"0.0000\",\
+             \"total\":\"10.5000\",\"locked\":false,\"status\":\"active\"}]\n"
+        );
+    }
+
+    #[test]
+    fn test_write_accounts_jsonl() {
+        assert_eq!(
+            output(OutputFormat::Jsonl),
+            "{\"client\":1,\"available\":\"10.5000\",\"held\":\"0.0000\",\
+             \"total\":\"10.5000\",\"locked\":false,\"status\":\"active\"}\n"
+        );
+    }
+}
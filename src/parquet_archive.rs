@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parquet::{
+    basic::Compression,
+    data_type::{ByteArray, ByteArrayType, Int32Type, Int64Type},
+    errors::ParquetError,
+    file::{
+        properties::WriterProperties,
+        reader::{FileReader, SerializedFileReader},
+        writer::SerializedFileWriter,
+    },
+    schema::{parser::parse_message_type, types::Type},
+};
+use payments_engine::{ArchiveSink, Error, Result, TxRecord};
+use serde::Serialize;
+
+use crate::dates;
+
+// the columns of an archive file, named after the record's fields so that rows read back into
+// records. Amounts are kept as decimal strings so no precision is lost
+const SCHEMA: &str = "message archived_tx {
+    REQUIRED INT32 tx (INTEGER(32, false));
+    REQUIRED BYTE_ARRAY tx_type (UTF8);
+    REQUIRED INT32 account_id (INTEGER(16, false));
+    REQUIRED BYTE_ARRAY currency (UTF8);
+    REQUIRED BYTE_ARRAY dispute_status (UTF8);
+    REQUIRED BYTE_ARRAY amount (UTF8);
+    REQUIRED BYTE_ARRAY disputable (UTF8);
+    REQUIRED BYTE_ARRAY disputed (UTF8);
+    REQUIRED BYTE_ARRAY refunded (UTF8);
+    OPTIONAL INT64 timestamp (INTEGER(64, false));
+}";
+
+// the records a partition buffers before writing them out as a file of their own
+const FILE_ROWS: usize = 65_536;
+
+// archives evicted transactions as Parquet files under a directory, partitioned Hive-style by
+// the day each transaction happened (`date=YYYY-MM-DD`, or `date=unknown` without a timestamp).
+// A partition's records are written as a new `part-NNNNN.parquet` file once it has FILE_ROWS of
+// them, and on every flush
+pub struct ParquetArchive {
+    dir: PathBuf,
+    schema: Arc<Type>,
+    partitions: BTreeMap<String, Vec<(u32, TxRecord)>>,
+}
+
+impl ParquetArchive {
+    pub fn new(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let schema = parse_message_type(SCHEMA).expect("the schema parses");
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            schema: Arc::new(schema),
+            partitions: BTreeMap::new(),
+        })
+    }
+}
+
+impl ArchiveSink for ParquetArchive {
+    fn archive(&mut self, tx: u32, record: &TxRecord) -> Result<()> {
+        let partition = match record.timestamp {
+            Some(at) => dates::iso(at)[..10].to_string(),
+            None => "unknown".to_string(),
+        };
+        let rows = self.partitions.entry(partition.clone()).or_default();
+        rows.push((tx, record.clone()));
+        if rows.len() < FILE_ROWS {
+            return Ok(());
+        }
+        match write(&self.dir, &self.schema, &partition, rows) {
+            Ok(()) => {
+                rows.clear();
+                Ok(())
+            }
+            // the record stays in the store, so it isn't buffered twice
+            Err(e) => {
+                rows.pop();
+                Err(e)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for (partition, rows) in &mut self.partitions {
+            if !rows.is_empty() {
+                write(&self.dir, &self.schema, partition, rows)?;
+                rows.clear();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// a column's values, in the schema's order
+enum Column {
+    Int32(Vec<i32>),
+    Text(Vec<ByteArray>),
+    // the values present, and a definition level per row telling whether it has one
+    OptionalInt64(Vec<i64>, Vec<i16>),
+}
+
+// write `rows` as a new file in the `partition` directory, numbered after the files already
+// there, so earlier runs' files stay as they are
+fn write(dir: &Path, schema: &Arc<Type>, partition: &str, rows: &[(u32, TxRecord)]) -> Result<()> {
+    let dir = dir.join(format!("date={}", partition));
+    fs::create_dir_all(&dir)?;
+    let path = (0..)
+        .map(|n| dir.join(format!("part-{:05}.parquet", n)))
+        .find(|path| !path.exists())
+        .expect("a free file name");
+
+    let text = |value: fn(&TxRecord) -> String| {
+        Column::Text(
+            rows.iter()
+                .map(|(_, record)| ByteArray::from(value(record).as_str()))
+                .collect(),
+        )
+    };
+    let columns = [
+        // unsigned values are stored bit for bit, as their logical type says
+        Column::Int32(rows.iter().map(|(tx, _)| *tx as i32).collect()),
+        text(|record| record.tx_type.to_string()),
+        Column::Int32(
+            rows.iter()
+                .map(|(_, record)| i32::from(record.account_id))
+                .collect(),
+        ),
+        text(|record| record.currency.clone()),
+        text(|record| name(record.dispute_status)),
+        text(|record| record.amount.to_string()),
+        text(|record| record.disputable.to_string()),
+        text(|record| record.disputed.to_string()),
+        text(|record| record.refunded.to_string()),
+        Column::OptionalInt64(
+            rows.iter()
+                .filter_map(|(_, record)| record.timestamp.map(|at| at as i64))
+                .collect(),
+            rows.iter()
+                .map(|(_, record)| i16::from(record.timestamp.is_some()))
+                .collect(),
+        ),
+    ];
+
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = SerializedFileWriter::new(
+        File::create_new(&path)?,
+        schema.clone(),
+        Arc::new(properties),
+    )
+    .map_err(parquet_error)?;
+    let mut group = writer.next_row_group().map_err(parquet_error)?;
+    for column in columns {
+        let mut out = group
+            .next_column()
+            .map_err(parquet_error)?
+            .expect("a column per schema field");
+        match column {
+            Column::Int32(values) => out.typed::<Int32Type>().write_batch(&values, None, None),
+            Column::Text(values) => out
+                .typed::<ByteArrayType>()
+                .write_batch(&values, None, None),
+            Column::OptionalInt64(values, levels) => {
+                out.typed::<Int64Type>()
+                    .write_batch(&values, Some(&levels), None)
+            }
+        }
+        .map_err(parquet_error)?;
+        out.close().map_err(parquet_error)?;
+    }
+    group.close().map_err(parquet_error)?;
+    writer.close().map_err(parquet_error)?;
+
+    Ok(())
+}
+
+// every record archived under `dir` that `keep` picks, ordered by tx id
+pub fn read(
+    dir: &Path,
+    mut keep: impl FnMut(u32, &TxRecord) -> bool,
+) -> Result<Vec<(u32, TxRecord)>> {
+    let mut records = Vec::new();
+    for partition in fs::read_dir(dir)? {
+        let partition = partition?.path();
+        if !partition.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(&partition)? {
+            let path = file?.path();
+            if path
+                .extension()
+                .is_none_or(|extension| extension != "parquet")
+            {
+                continue;
+            }
+            let reader = SerializedFileReader::new(File::open(&path)?).map_err(parquet_error)?;
+            for row in reader.get_row_iter(None).map_err(parquet_error)? {
+                let row = row.map_err(parquet_error)?.to_json_value();
+                let tx = row["tx"]
+                    .as_u64()
+                    .and_then(|tx| u32::try_from(tx).ok())
+                    .ok_or_else(|| {
+                        Error::StoreError(format!("{}: invalid tx id", path.display()))
+                    })?;
+                let record: TxRecord = serde_json::from_value(row)
+                    .map_err(|e| Error::StoreError(format!("{}: {}", path.display(), e)))?;
+                if keep(tx, &record) {
+                    records.push((tx, record));
+                }
+            }
+        }
+    }
+    records.sort_unstable_by_key(|(tx, _)| *tx);
+
+    Ok(records)
+}
+
+// a unit variant's name, as the record's other serializations spell it
+fn name(value: impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => unreachable!("a unit variant serializes as its name"),
+    }
+}
+
+fn parquet_error(error: ParquetError) -> Error {
+    Error::StoreError(format!("Parquet archive: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::testing::TxBuilder;
+    use payments_engine::{EvictionPolicy, PaymentsEngine, Transaction, TransactionType, amount};
+    use std::time::Duration;
+
+    const DAY: u64 = dates::SECS_PER_DAY;
+
+    #[test]
+    fn test_archive_reads_back_by_partition() {
+        let dir =
+            std::env::temp_dir().join(format!("payments-engine-archive-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut engine = PaymentsEngine::builder()
+            .dispute_window(Duration::from_secs(DAY))
+            .eviction_policy(EvictionPolicy::Settled)
+            .archive_sink(Box::new(ParquetArchive::new(&dir).unwrap()))
+            .build();
+        for (tx_type, tx_id, amount, timestamp) in [
+            (TransactionType::Deposit, 1, Some(amount!(10.5)), Some(0)),
+            (TransactionType::Deposit, 2, Some(amount!(3)), Some(DAY)),
+            (TransactionType::Withdrawal, 3, Some(amount!(1)), Some(DAY)),
+            // evicted as soon as it's settled
+            (TransactionType::Deposit, 4, Some(amount!(2)), Some(2 * DAY)),
+            (TransactionType::Dispute, 4, None, Some(2 * DAY)),
+            (TransactionType::Chargeback, 4, None, Some(2 * DAY)),
+        ] {
+            engine
+                .process_tx(&Transaction {
+                    amount,
+                    timestamp,
+                    ..TxBuilder::new(tx_type, 1, tx_id).build()
+                })
+                .unwrap();
+        }
+
+        assert_eq!(engine.evict_expired(3 * DAY).unwrap(), 3);
+        engine.flush_events().unwrap();
+
+        let mut partitions: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        partitions.sort();
+        assert_eq!(
+            partitions,
+            ["date=1970-01-01", "date=1970-01-02", "date=1970-01-03"]
+        );
+        let records = read(&dir, |_, _| true).unwrap();
+        let ids: Vec<_> = records.iter().map(|(tx, _)| *tx).collect();
+        assert_eq!(ids, [1, 2, 3, 4]);
+        let (_, deposit) = &records[0];
+        assert_eq!(deposit.tx_type, TransactionType::Deposit);
+        assert_eq!(deposit.amount, amount!(10.5));
+        assert_eq!(deposit.timestamp, Some(0));
+        let (_, charged_back) = &records[3];
+        assert_eq!(
+            charged_back.dispute_status,
+            payments_engine::DisputeStatus::ChargedBack
+        );
+        assert_eq!(read(&dir, |tx, _| tx == 3).unwrap().len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::VecDeque;
+
+use crate::{
+    error::{Error, ErrorContext},
+    hash::HashMap,
+    transaction::Transaction,
+};
+
+/// What to do with a dispute of an unseen tx when the pending-dispute buffer is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PendingOverflow {
+    /// Refuse the new dispute with [`Error::UnknownTransaction`], as without a buffer.
+    #[default]
+    RejectNew,
+    /// Make room by giving up on the oldest pending dispute.
+    EvictOldest,
+}
+
+/// Buffers disputes whose referenced transaction hasn't been seen yet, for input that isn't
+/// perfectly ordered. Each is retried once its deposit or withdrawal is applied. Ones that wait
+/// too long or are pushed out of a full buffer become dead letters, to be collected with
+/// [`PaymentsEngine::take_dead_letters`](crate::PaymentsEngine::take_dead_letters).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingDisputes {
+    /// Most disputes held at once.
+    pub capacity: usize,
+    /// How many further transactions a dispute may wait for its tx; `None` waits until
+    /// [`PaymentsEngine::flush_pending_disputes`](crate::PaymentsEngine::flush_pending_disputes).
+    pub max_age: Option<u64>,
+    pub overflow: PendingOverflow,
+}
+
+// stale order entries kept before the whole queue is swept for them
+const STALE_SLACK: usize = 64;
+
+// the engine's buffer: disputes by referenced tx id, plus their arrival order for expiry and
+// eviction. Each parked dispute gets a sequence number, so an order entry can tell whether its
+// dispute is still waiting: retried ones leave theirs behind, which is skipped once it reaches
+// the front and swept out once such entries outnumber the live ones
+#[derive(Default)]
+pub(crate) struct PendingBuffer {
+    config: Option<PendingDisputes>,
+    // counts processed transactions, stamping each parked dispute with its arrival
+    clock: u64,
+    next_seq: u64,
+    by_tx: HashMap<u32, Vec<Parked>>,
+    // (seq, arrival, tx id) of every parked dispute, oldest first
+    order: VecDeque<(u64, u64, u32)>,
+    len: usize,
+    dead_letters: Vec<Error>,
+}
+
+struct Parked {
+    seq: u64,
+    tx: Transaction,
+}
+
+impl PendingBuffer {
+    pub(crate) fn new(config: Option<PendingDisputes>) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    // advance the clock by one transaction, giving up on disputes that have waited too long
+    pub(crate) fn tick(&mut self) {
+        let Some(config) = self.config else {
+            return;
+        };
+        self.clock += 1;
+        let Some(max_age) = config.max_age else {
+            return;
+        };
+        loop {
+            self.drop_stale_front();
+            match self.order.front() {
+                Some(&(_, arrived, _)) if self.clock - arrived > max_age => self.give_up_oldest(),
+                _ => return,
+            }
+        }
+    }
+
+    // hold on to a dispute of an unseen tx--false if buffering is off or the buffer is full
+    pub(crate) fn park(&mut self, tx: &Transaction) -> bool {
+        let Some(config) = self.config else {
+            return false;
+        };
+        if self.len >= config.capacity {
+            match config.overflow {
+                PendingOverflow::RejectNew => return false,
+                PendingOverflow::EvictOldest => {
+                    self.give_up_oldest();
+                    // a zero capacity buffer never holds anything
+                    if self.len >= config.capacity {
+                        return false;
+                    }
+                }
+            }
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.by_tx.entry(tx.tx_id).or_default().push(Parked {
+            seq,
+            tx: tx.clone(),
+        });
+        self.order.push_back((seq, self.clock, tx.tx_id));
+        self.len += 1;
+
+        true
+    }
+
+    // the disputes waiting for `tx_id`, in arrival order, which are no longer pending
+    pub(crate) fn take(&mut self, tx_id: u32) -> Vec<Transaction> {
+        if self.len == 0 {
+            return Vec::new();
+        }
+        let disputes = self.by_tx.remove(&tx_id).unwrap_or_default();
+        if disputes.is_empty() {
+            return Vec::new();
+        }
+        self.len -= disputes.len();
+        if self.order.len() > 2 * self.len + STALE_SLACK {
+            let by_tx = &self.by_tx;
+            self.order
+                .retain(|&(seq, _, tx_id)| Self::is_live(by_tx, seq, tx_id));
+        }
+
+        disputes.into_iter().map(|parked| parked.tx).collect()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn flush(&mut self) {
+        while self.len > 0 {
+            self.give_up_oldest();
+        }
+        self.order.clear();
+    }
+
+    pub(crate) fn dead_letter(&mut self, error: Error) {
+        self.dead_letters.push(error);
+    }
+
+    pub(crate) fn take_dead_letters(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.dead_letters)
+    }
+
+    // disputes of one tx are parked in order and only leave from the front or all at once, so
+    // one still waiting is the first of its tx's from its own seq on
+    fn is_live(by_tx: &HashMap<u32, Vec<Parked>>, seq: u64, tx_id: u32) -> bool {
+        by_tx
+            .get(&tx_id)
+            .and_then(|waiting| waiting.first())
+            .is_some_and(|first| first.seq <= seq)
+    }
+
+    fn drop_stale_front(&mut self) {
+        while let Some(&(seq, _, tx_id)) = self.order.front()
+            && !Self::is_live(&self.by_tx, seq, tx_id)
+        {
+            self.order.pop_front();
+        }
+    }
+
+    fn give_up_oldest(&mut self) {
+        self.drop_stale_front();
+        let Some((_, _, tx_id)) = self.order.pop_front() else {
+            return;
+        };
+        let waiting = self.by_tx.get_mut(&tx_id).expect("the front is live");
+        let parked = waiting.remove(0);
+        if waiting.is_empty() {
+            self.by_tx.remove(&tx_id);
+        }
+        self.len -= 1;
+        self.dead_letter(
+            Error::UnknownTransaction(parked.tx.tx_id)
+                .with_context(ErrorContext::for_tx(&parked.tx)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TxBuilder;
+
+    fn tx_ids(disputes: Vec<Transaction>) -> Vec<u32> {
+        disputes.iter().map(|tx| tx.tx_id).collect()
+    }
+
+    fn buffer(capacity: usize, max_age: Option<u64>) -> PendingBuffer {
+        PendingBuffer::new(Some(PendingDisputes {
+            capacity,
+            max_age,
+            overflow: PendingOverflow::EvictOldest,
+        }))
+    }
+
+    #[test]
+    fn test_tick_skips_taken_disputes() {
+        let mut pending = buffer(10, Some(2));
+
+        pending.tick();
+        assert!(pending.park(&TxBuilder::dispute(1, 9).build()));
+        pending.tick();
+        assert_eq!(tx_ids(pending.take(9)), [9]);
+        pending.tick();
+        assert!(pending.park(&TxBuilder::dispute(1, 7).build()));
+        pending.tick();
+
+        // the taken dispute of tx 9 at the front doesn't expire the one of tx 7 behind it
+        assert_eq!(tx_ids(pending.take(7)), [7]);
+        assert!(pending.take_dead_letters().is_empty());
+    }
+
+    #[test]
+    fn test_tick_expires_only_old_disputes() {
+        let mut pending = buffer(10, Some(2));
+
+        pending.tick();
+        pending.park(&TxBuilder::dispute(1, 1).build());
+        pending.tick();
+        pending.park(&TxBuilder::dispute(1, 2).build());
+        pending.tick();
+        pending.tick();
+
+        let dead_letters = pending.take_dead_letters();
+        assert!(matches!(
+            dead_letters[..],
+            [ref e] if matches!(e.root(), Error::UnknownTransaction(1))
+        ));
+        assert_eq!(tx_ids(pending.take(2)), [2]);
+    }
+
+    #[test]
+    fn test_park_evicts_oldest_live_dispute() {
+        let mut pending = buffer(2, None);
+
+        pending.park(&TxBuilder::dispute(1, 1).build());
+        pending.park(&TxBuilder::dispute(1, 2).build());
+        pending.take(1);
+        pending.park(&TxBuilder::dispute(1, 3).build());
+        pending.park(&TxBuilder::dispute(1, 4).build());
+
+        let dead_letters = pending.take_dead_letters();
+        assert!(matches!(
+            dead_letters[..],
+            [ref e] if matches!(e.root(), Error::UnknownTransaction(2))
+        ));
+        assert_eq!(tx_ids(pending.take(3)), [3]);
+    }
+
+    #[test]
+    fn test_take_prunes_stale_order() {
+        let mut pending = buffer(10, None);
+
+        for tx_id in 0..1_000 {
+            pending.park(&TxBuilder::dispute(1, tx_id).build());
+            pending.take(tx_id);
+        }
+        pending.park(&TxBuilder::dispute(1, 5_000).build());
+
+        assert!(pending.order.len() <= STALE_SLACK + 2);
+        pending.flush();
+        assert_eq!(pending.take_dead_letters().len(), 1);
+        assert!(pending.order.is_empty());
+    }
+}
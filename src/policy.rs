@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use csv::StringRecord;
+
+use payments_engine::{Error, ErrorCategory, ErrorContext, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ErrorAction {
+    // drop the row silently
+    Skip,
+    // drop the row and log the error to stderr
+    Warn,
+    // drop the row and copy it to the quarantine file
+    Quarantine,
+    // stop processing and fail the run
+    Abort,
+}
+
+// maps each error category to an action--unconfigured categories use the fallback action when
+// one is set, otherwise the historical skip-and-log behavior, except unknown tx references which
+// have always been ignored silently
+#[derive(Debug, Default)]
+pub struct ErrorPolicy {
+    overrides: HashMap<ErrorCategory, ErrorAction>,
+    fallback: Option<ErrorAction>,
+}
+
+impl ErrorPolicy {
+    pub fn set(&mut self, category: ErrorCategory, action: ErrorAction) {
+        self.overrides.insert(category, action);
+    }
+
+    pub fn set_fallback(&mut self, action: ErrorAction) {
+        self.fallback = Some(action);
+    }
+
+    pub fn action(&self, category: ErrorCategory) -> ErrorAction {
+        match (self.overrides.get(&category).copied(), self.fallback) {
+            (Some(action), _) | (None, Some(action)) => action,
+            (None, None) if category == ErrorCategory::UnknownReference => ErrorAction::Skip,
+            (None, None) => ErrorAction::Warn,
+        }
+    }
+
+    pub fn uses(&self, action: ErrorAction) -> bool {
+        self.fallback == Some(action) || self.overrides.values().any(|a| *a == action)
+    }
+}
+
+// csv sink for failed rows (the quarantine and rejects files): line number, byte offset, error
+// code, error, then the original fields
+pub struct RejectSink {
+    writer: csv::Writer<Counted>,
+}
+
+// counts the bytes written through it
+struct Counted {
+    inner: Box<dyn Write>,
+    written: u64,
+}
+
+impl Write for Counted {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl RejectSink {
+    pub fn new(writer: Box<dyn Write>) -> Self {
+        Self::appending(writer, 0)
+    }
+
+    // a new file at `path`, or on resume the one the checkpoint noted, cut back to how long it
+    // was then so that rows rejected again after it aren't written twice
+    pub fn create(path: &Path, resume_at: Option<u64>) -> Result<Self> {
+        let Some(len) = resume_at else {
+            return Ok(Self::new(Box::new(File::create(path)?)));
+        };
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        let len = len.min(file.metadata()?.len());
+        file.set_len(len)?;
+
+        Ok(Self::appending(Box::new(file), len))
+    }
+
+    // write after the `written` bytes already in the file behind `writer`
+    fn appending(writer: Box<dyn Write>, written: u64) -> Self {
+        let writer = Counted {
+            inner: writer,
+            written,
+        };
+        Self {
+            writer: csv::WriterBuilder::new().flexible(true).from_writer(writer),
+        }
+    }
+
+    // how long the file is, counting only rows that have been flushed
+    pub fn written(&self) -> u64 {
+        self.writer.get_ref().written
+    }
+
+    pub fn write(&mut self, error: &Error, record: Option<&StringRecord>) -> Result<()> {
+        let context = error.context();
+        let position = |field: fn(&ErrorContext) -> Option<u64>| {
+            context
+                .and_then(field)
+                .map(|value| value.to_string())
+                .unwrap_or_default()
+        };
+        let line = position(|context| context.line);
+        let byte = position(|context| context.byte);
+        let code = error.code().to_string();
+        let reason = error.to_string();
+
+        let mut row = vec![line.as_str(), byte.as_str(), code.as_str(), reason.as_str()];
+        row.extend(record.into_iter().flat_map(|record| record.iter()));
+        self.writer.write_record(&row)?;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_actions() {
+        let policy = ErrorPolicy::default();
+
+        assert_eq!(policy.action(ErrorCategory::Parse), ErrorAction::Warn);
+        assert_eq!(
+            policy.action(ErrorCategory::InsufficientFunds),
+            ErrorAction::Warn
+        );
+        assert_eq!(
+            policy.action(ErrorCategory::UnknownReference),
+            ErrorAction::Skip
+        );
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut policy = ErrorPolicy::default();
+        policy.set(ErrorCategory::LockedAccount, ErrorAction::Abort);
+        policy.set(ErrorCategory::UnknownReference, ErrorAction::Quarantine);
+
+        assert_eq!(
+            policy.action(ErrorCategory::LockedAccount),
+            ErrorAction::Abort
+        );
+        assert_eq!(
+            policy.action(ErrorCategory::UnknownReference),
+            ErrorAction::Quarantine
+        );
+        assert_eq!(policy.action(ErrorCategory::Other), ErrorAction::Warn);
+        assert!(policy.uses(ErrorAction::Quarantine));
+        assert!(!policy.uses(ErrorAction::Skip));
+    }
+
+    #[test]
+    fn test_fallback() {
+        let mut policy = ErrorPolicy::default();
+        policy.set_fallback(ErrorAction::Abort);
+        policy.set(ErrorCategory::Duplicate, ErrorAction::Skip);
+
+        assert_eq!(
+            policy.action(ErrorCategory::UnknownReference),
+            ErrorAction::Abort
+        );
+        assert_eq!(policy.action(ErrorCategory::Duplicate), ErrorAction::Skip);
+        assert!(policy.uses(ErrorAction::Abort));
+    }
+
+    #[test]
+    fn test_error_categories() {
+        assert_eq!(
+            Error::InsufficientFunds("").category(),
+            ErrorCategory::InsufficientFunds
+        );
+        assert_eq!(
+            Error::AccountLocked("").category(),
+            ErrorCategory::LockedAccount
+        );
+        assert_eq!(
+            Error::UnknownTransaction(1).category(),
+            ErrorCategory::UnknownReference
+        );
+        assert_eq!(
+            Error::DuplicateTransaction(1).category(),
+            ErrorCategory::Duplicate
+        );
+        assert_eq!(
+            Error::AmountBelowMinimum("").category(),
+            ErrorCategory::AmountLimit
+        );
+        assert_eq!(Error::TransactionError("").category(), ErrorCategory::Other);
+    }
+}
@@ -0,0 +1,289 @@
+use std::io::Read;
+use std::path::Path;
+
+use payments_engine::{Error, Result};
+
+#[cfg(feature = "object-store")]
+pub use store::open;
+
+// URL schemes read straight from an object store instead of the local filesystem
+const SCHEMES: [&str; 2] = ["s3://", "gs://"];
+
+// whether `path` names an object in a store (`s3://bucket/key` or `gs://bucket/key`)
+pub fn is_remote(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| SCHEMES.iter().any(|scheme| path.starts_with(scheme)))
+}
+
+// split an object URL into its scheme, bucket and key
+fn split(url: &str) -> Result<(&str, &str, &str)> {
+    let invalid = || Error::ConfigError(format!("{} is not an s3:// or gs:// object URL", url));
+    let (scheme, rest) = url.split_once("://").ok_or_else(invalid)?;
+    let (bucket, key) = rest.split_once('/').ok_or_else(invalid)?;
+    if !matches!(scheme, "s3" | "gs") || bucket.is_empty() || key.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok((scheme, bucket, key))
+}
+
+#[cfg(not(feature = "object-store"))]
+pub fn open(url: &str) -> Result<Box<dyn Read>> {
+    split(url)?;
+    Err(Error::ConfigError(
+        "object store input requires building with the `object-store` feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "object-store")]
+mod store {
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use object_store::{
+        GetOptions, GetRange, ObjectStore, aws::AmazonS3Builder, gcp::GoogleCloudStorageBuilder,
+        path::Path as ObjectPath,
+    };
+    use tokio::runtime::Runtime;
+    use tokio_stream::{Stream, StreamExt};
+
+    use super::*;
+
+    // how many times in a row a broken download is picked up again from where it stopped, on top
+    // of the store client's own retries of each request
+    const MAX_RESUMES: u32 = 5;
+    // wait before the first resume, doubling for each one after
+    const RESUME_BACKOFF: Duration = Duration::from_millis(100);
+
+    type ByteStream = Pin<Box<dyn Stream<Item = object_store::Result<Bytes>> + Send>>;
+
+    // stream the object at `url`, with credentials and region taken from the usual
+    // `AWS_*`/`GOOGLE_*` environment variables
+    pub fn open(url: &str) -> Result<ObjectReader> {
+        let (scheme, bucket, key) = split(url)?;
+        let config = |e: object_store::Error| Error::ConfigError(e.to_string());
+        let store: Arc<dyn ObjectStore> = match scheme {
+            "s3" => Arc::new(
+                AmazonS3Builder::from_env()
+                    .with_bucket_name(bucket)
+                    .build()
+                    .map_err(config)?,
+            ),
+            _ => Arc::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_bucket_name(bucket)
+                    .build()
+                    .map_err(config)?,
+            ),
+        };
+        let location = ObjectPath::parse(key).map_err(|e| Error::ConfigError(e.to_string()))?;
+
+        ObjectReader::new(store, location)
+    }
+
+    // a blocking reader over an object's bytes. A download that breaks off part way is resumed
+    // with a range request from the last byte received, pinned to the object's ETag so a
+    // rewritten object fails the read instead of splicing two versions together
+    pub struct ObjectReader {
+        runtime: Runtime,
+        store: Arc<dyn ObjectStore>,
+        location: ObjectPath,
+        e_tag: Option<String>,
+        size: u64,
+        // bytes received so far, where a resumed request starts
+        received: u64,
+        stream: Option<ByteStream>,
+        // received but not yet read
+        chunk: Bytes,
+        // resumes since bytes last arrived
+        resumes: u32,
+    }
+
+    impl ObjectReader {
+        pub fn new(store: Arc<dyn ObjectStore>, location: ObjectPath) -> Result<Self> {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            let result = runtime
+                .block_on(store.get(&location))
+                .map_err(io::Error::other)?;
+
+            Ok(ObjectReader {
+                runtime,
+                store,
+                location,
+                e_tag: result.meta.e_tag.clone(),
+                size: result.meta.size,
+                received: 0,
+                stream: Some(Box::pin(result.into_stream())),
+                chunk: Bytes::new(),
+                resumes: 0,
+            })
+        }
+
+        // give up on the current stream, waiting before it is resumed or failing once it has been
+        // resumed too often
+        fn broken(&mut self, error: impl std::fmt::Display) -> io::Result<()> {
+            self.stream = None;
+            if self.resumes == MAX_RESUMES {
+                return Err(io::Error::other(format!(
+                    "{}: download failed at byte {} of {} after {} resumes: {}",
+                    self.location, self.received, self.size, MAX_RESUMES, error
+                )));
+            }
+            tracing::warn!(
+                object = %self.location,
+                received = self.received,
+                %error,
+                "object download broke off, resuming"
+            );
+            std::thread::sleep(RESUME_BACKOFF * 2u32.pow(self.resumes));
+            self.resumes += 1;
+
+            Ok(())
+        }
+
+        fn resume(&mut self) -> io::Result<()> {
+            let options = GetOptions {
+                range: Some(GetRange::Offset(self.received)),
+                if_match: self.e_tag.clone(),
+                ..Default::default()
+            };
+            match self
+                .runtime
+                .block_on(self.store.get_opts(&self.location, options))
+            {
+                Ok(result) => self.stream = Some(Box::pin(result.into_stream())),
+                Err(e @ object_store::Error::Precondition { .. }) => {
+                    return Err(io::Error::other(format!(
+                        "{} changed while it was being read: {}",
+                        self.location, e
+                    )));
+                }
+                Err(e) => self.broken(e)?,
+            }
+
+            Ok(())
+        }
+    }
+
+    impl Read for ObjectReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            loop {
+                if !self.chunk.is_empty() {
+                    let n = buf.len().min(self.chunk.len());
+                    buf[..n].copy_from_slice(&self.chunk.split_to(n));
+                    return Ok(n);
+                }
+                if self.received >= self.size {
+                    return Ok(0);
+                }
+                let Some(stream) = self.stream.as_mut() else {
+                    self.resume()?;
+                    continue;
+                };
+                match self.runtime.block_on(stream.next()) {
+                    Some(Ok(bytes)) => {
+                        self.received += bytes.len() as u64;
+                        self.chunk = bytes;
+                        self.resumes = 0;
+                    }
+                    Some(Err(e)) => self.broken(e)?,
+                    None => self.broken("connection closed early")?,
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use object_store::memory::InMemory;
+
+        use super::*;
+
+        const CSV: &str = "type,client,tx,amount\ndeposit,1,1,10\n";
+
+        fn put(store: &InMemory, bytes: &'static [u8]) {
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(store.put(
+                    &ObjectPath::from("tx.csv"),
+                    Bytes::from_static(bytes).into(),
+                ))
+                .unwrap();
+        }
+
+        // a reader whose first download breaks off after `CSV`'s first five bytes
+        fn broken_reader() -> (Arc<InMemory>, ObjectReader) {
+            let store = Arc::new(InMemory::new());
+            put(&store, CSV.as_bytes());
+            let mut reader = ObjectReader::new(store.clone(), ObjectPath::from("tx.csv")).unwrap();
+            reader.stream = Some(Box::pin(tokio_stream::iter(vec![
+                Ok(Bytes::from_static(&CSV.as_bytes()[..5])),
+                Err(object_store::Error::Generic {
+                    store: "test",
+                    source: "connection reset".into(),
+                }),
+            ])));
+            (store, reader)
+        }
+
+        #[test]
+        fn test_object_reader_resumes() {
+            let (_store, mut reader) = broken_reader();
+
+            let mut out = String::new();
+            reader.read_to_string(&mut out).unwrap();
+
+            assert_eq!(out, CSV);
+        }
+
+        #[test]
+        fn test_object_reader_failure_object_changed() {
+            let (store, mut reader) = broken_reader();
+            put(&store, b"x");
+
+            let mut out = String::new();
+            let e = reader.read_to_string(&mut out).unwrap_err();
+
+            assert!(e.to_string().contains("changed while it was being read"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_remote() {
+        assert!(is_remote(Path::new("s3://bucket/tx.csv")));
+        assert!(is_remote(Path::new("gs://bucket/2024/tx.csv.gz")));
+        assert!(!is_remote(Path::new("tx.csv")));
+        assert!(!is_remote(Path::new("-")));
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            split("s3://bucket/2024/tx.csv").unwrap(),
+            ("s3", "bucket", "2024/tx.csv")
+        );
+        for url in ["s3://bucket", "s3://bucket/", "s3:///tx.csv", "tx.csv"] {
+            assert!(matches!(split(url), Err(Error::ConfigError(_))));
+        }
+    }
+
+    #[cfg(not(feature = "object-store"))]
+    #[test]
+    fn test_open_failure_without_feature() {
+        assert!(matches!(
+            open("s3://bucket/tx.csv"),
+            Err(Error::ConfigError(_))
+        ));
+    }
+}
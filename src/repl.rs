@@ -0,0 +1,158 @@
+use std::io::{BufRead, Write};
+
+use csv::StringRecord;
+use payments_engine::{Error, PaymentsEngine, Result, Transaction, TransactionRow};
+
+use crate::{
+    inspect,
+    output::{OutputFormat, write_accounts},
+};
+
+const HELP: &str = "\
+transactions, as CSV rows or separated by spaces:
+  TYPE,CLIENT,TX[,AMOUNT[,CURRENCY]]     e.g. deposit,1,1,10 or dispute 1 1
+commands:
+  accounts      print every account as CSV
+  show CLIENT   print a client's balances, open disputes and stored transactions
+  tx TX         print a stored transaction
+  help          print this help
+  quit          leave (as does end of input)";
+
+// the columns a typed transaction has, in order; trailing ones can be left out
+const COLUMNS: [&str; 5] = ["type", "client", "tx", "amount", "currency"];
+
+// read transactions and commands from `input` a line at a time, applying them to `engine` and
+// writing the outcome to `out`. A failed transaction or command is reported and the session
+// carries on. With `prompt`, each line is asked for with `> `
+pub fn run<R: BufRead, W: Write>(
+    engine: &mut PaymentsEngine,
+    input: R,
+    mut out: W,
+    prompt: bool,
+) -> Result<()> {
+    let mut lines = input.lines();
+    loop {
+        if prompt {
+            write!(out, "> ")?;
+            out.flush()?;
+        }
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let line = line?;
+        let mut words = line.split_whitespace();
+        let result = match (words.next(), words.next()) {
+            (None, _) => Ok(()),
+            (Some(comment), _) if comment.starts_with('#') => Ok(()),
+            (Some("quit" | "exit"), None) => break,
+            (Some("help"), None) => writeln!(out, "{}", HELP).map_err(Error::from),
+            (Some("accounts"), None) => write_accounts(engine, &mut out, OutputFormat::Csv),
+            (Some("show"), Some(client)) => match client.parse() {
+                Ok(client) => inspect::run(engine, Some(client), None, &[], &mut out),
+                Err(_) => Err(Error::TransactionError("Invalid client id.")),
+            },
+            (Some("tx"), Some(tx)) => match tx.parse() {
+                Ok(tx) => inspect::run(engine, None, Some(tx), &[], &mut out),
+                Err(_) => Err(Error::TransactionError("Invalid tx id.")),
+            },
+            _ => apply(engine, &line, &mut out),
+        };
+        if let Err(e) = result {
+            writeln!(out, "error: {}", e)?;
+        }
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+// apply one typed transaction and print the client's balances after it
+fn apply<W: Write>(engine: &mut PaymentsEngine, line: &str, out: &mut W) -> Result<()> {
+    let tx = parse(line)?;
+    engine.process_tx(&tx)?;
+    writeln!(out, "ok")?;
+    if let Some(account) = engine.account(tx.account_id) {
+        for (currency, balance) in &account.balances {
+            let currency = match currency.is_empty() {
+                true => String::new(),
+                false => format!(" {}", currency),
+            };
+            writeln!(
+                out,
+                "client {}{}: available {}, held {}, total {}, {}",
+                account.id,
+                currency,
+                balance.available,
+                balance.held,
+                balance.total,
+                account.status
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn parse(line: &str) -> Result<Transaction> {
+    let fields: Vec<&str> = match line.contains(',') {
+        true => line.split(',').map(str::trim).collect(),
+        false => line.split_whitespace().collect(),
+    };
+    if fields.len() > COLUMNS.len() {
+        return Err(Error::TransactionError("Too many fields."));
+    }
+    let headers = StringRecord::from(&COLUMNS[..fields.len()]);
+    let row: TransactionRow = StringRecord::from(fields).deserialize(Some(&headers))?;
+
+    row.try_into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(input: &str) -> String {
+        let mut engine = PaymentsEngine::new();
+        let mut out = Vec::new();
+        run(&mut engine, input.as_bytes(), &mut out, false).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_run_success() {
+        let out = session(
+            "deposit,1,1,10\n\
+             # a comment\n\
+             \n\
+             withdrawal 1 2 4\n\
+             dispute, 1, 1\n\
+             accounts\n\
+             quit\n\
+             deposit,1,3,10\n",
+        );
+
+        assert_eq!(
+            out,
+            "ok\n\
+             client 1: available 10, held 0, total 10, active\n\
+             ok\n\
+             client 1: available 6, held 0, total 6, active\n\
+             ok\n\
+             client 1: available -4, held 10, total 6, active\n\
+             client,available,held,total,locked,status\n\
+             1,-4.0000,10.0000,6.0000,false,active\n"
+        );
+    }
+
+    #[test]
+    fn test_run_failure_reported() {
+        let out = session("withdrawal,1,1,5\nrefill,1,2,5\nshow x\ntx 7\n");
+
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("error: InsufficientFunds"));
+        assert!(lines[1].starts_with("error: CSV error"));
+        assert!(lines[2].starts_with("error: TransactionError"));
+        assert!(lines[3].starts_with("error: UnknownTransaction"));
+    }
+}
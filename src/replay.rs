@@ -0,0 +1,75 @@
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+
+use payments_engine::{PaymentsEngine, Result};
+
+use crate::{
+    output::{OutputFormat, write_accounts},
+    seed,
+};
+
+// compare the replayed account state with an accounts csv (e.g. the original run's output),
+// writing every differing row to `report`, and return whether they matched. Both sides go through
+// the same csv rendering, so rounding and column layout can't cause false mismatches
+pub fn verify<R: Read, W: Write>(
+    engine: &PaymentsEngine,
+    expected: R,
+    mut report: W,
+) -> Result<bool> {
+    let mut expected_engine = PaymentsEngine::new();
+    seed::load(&mut expected_engine, expected)?;
+
+    let replayed = render(engine)?;
+    let expected = render(&expected_engine)?;
+    for row in expected.difference(&replayed) {
+        writeln!(report, "expected: {}", row)?;
+    }
+    for row in replayed.difference(&expected) {
+        writeln!(report, "replayed: {}", row)?;
+    }
+
+    Ok(replayed == expected)
+}
+
+// rows are written in map order, so compare them as a set (the header included)
+fn render(engine: &PaymentsEngine) -> Result<BTreeSet<String>> {
+    let mut output = Vec::new();
+    write_accounts(engine, &mut output, OutputFormat::Csv)?;
+
+    Ok(String::from_utf8_lossy(&output)
+        .lines()
+        .map(str::to_owned)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVENTS: &str = "{\"event\":\"deposited\",\"client\":1,\"tx\":1,\"amount\":\"10\"}\n\
+                          {\"event\":\"withdrawal_applied\",\"client\":1,\"tx\":2,\"amount\":\"4\"}\n";
+
+    #[test]
+    fn test_verify_success() {
+        let engine = PaymentsEngine::replay(EVENTS.as_bytes()).unwrap();
+        let expected = "client,available,held,total,locked\n1,6,0,6,false\n";
+        let mut report = Vec::new();
+
+        assert!(verify(&engine, expected.as_bytes(), &mut report).unwrap());
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn test_verify_failure_mismatch() {
+        let engine = PaymentsEngine::replay(EVENTS.as_bytes()).unwrap();
+        let expected = "client,available,held,total,locked\n1,10,0,10,false\n";
+        let mut report = Vec::new();
+
+        assert!(!verify(&engine, expected.as_bytes(), &mut report).unwrap());
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "expected: 1,10.0000,0.0000,10.0000,false,active\n\
+             replayed: 1,6.0000,0.0000,6.0000,false,active\n"
+        );
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::VecDeque;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    hash::HashMap,
+    transaction::{Transaction, TransactionType},
+};
+
+/// What a tripped [`RiskRule`] does with the transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskAction {
+    /// Apply it as usual, flagged for review.
+    Flag,
+    /// Apply it with its funds held for review: a deposit as a provisional deposit, released by a
+    /// `clear`, and a withdrawal as an authorization, completed by a `capture` or dropped by a
+    /// `void`.
+    Hold,
+    /// Refuse it with [`Error::RiskRejected`].
+    Reject,
+}
+
+/// A check of each deposit and withdrawal against its client's activity so far.
+///
+/// Windows are in seconds, measured by the transactions' timestamps, so transactions without a
+/// timestamp never trip a windowed rule.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case", deny_unknown_fields)]
+pub enum RiskRule {
+    /// A withdrawal by a client who already made `max` withdrawals in the `window` before it.
+    WithdrawalCount {
+        max: u32,
+        window: u64,
+        action: RiskAction,
+    },
+    /// A withdrawal within `window` of the client's last deposit.
+    DepositWithdrawVelocity { window: u64, action: RiskAction },
+    /// A deposit or withdrawal by a client who has disputed more than `max_percent` of their
+    /// deposits and withdrawals, once they have made at least `min_transactions`.
+    DisputeRate {
+        max_percent: Decimal,
+        #[serde(default)]
+        min_transactions: u32,
+        action: RiskAction,
+    },
+}
+
+impl RiskRule {
+    /// The rule's name, as written in TOML.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RiskRule::WithdrawalCount { .. } => "withdrawal_count",
+            RiskRule::DepositWithdrawVelocity { .. } => "deposit_withdraw_velocity",
+            RiskRule::DisputeRate { .. } => "dispute_rate",
+        }
+    }
+
+    /// What the rule does with a transaction that trips it.
+    pub fn action(&self) -> RiskAction {
+        match self {
+            RiskRule::WithdrawalCount { action, .. }
+            | RiskRule::DepositWithdrawVelocity { action, .. }
+            | RiskRule::DisputeRate { action, .. } => *action,
+        }
+    }
+
+    fn trips(&self, tx: &Transaction, activity: &Activity) -> bool {
+        match *self {
+            RiskRule::WithdrawalCount { max, window, .. } => {
+                let Some(now) = tx.timestamp else {
+                    return false;
+                };
+                let recent = activity
+                    .withdrawals
+                    .iter()
+                    .filter(|&&at| at <= now && now - at < window)
+                    .count();
+                tx.tx_type == TransactionType::Withdrawal && recent >= max as usize
+            }
+            RiskRule::DepositWithdrawVelocity { window, .. } => {
+                tx.tx_type == TransactionType::Withdrawal
+                    && tx
+                        .timestamp
+                        .zip(activity.last_deposit)
+                        .is_some_and(|(now, deposited)| now.saturating_sub(deposited) < window)
+            }
+            RiskRule::DisputeRate {
+                max_percent,
+                min_transactions,
+                ..
+            } => {
+                activity.transactions > 0
+                    && activity.transactions >= min_transactions
+                    && Decimal::from(activity.disputes) * Decimal::ONE_HUNDRED
+                        > max_percent * Decimal::from(activity.transactions)
+            }
+        }
+    }
+}
+
+// what the rules look back on for one client, saved in snapshots
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Activity {
+    // timestamps of the withdrawals still inside the longest withdrawal window, oldest first
+    withdrawals: VecDeque<u64>,
+    last_deposit: Option<u64>,
+    // deposits and withdrawals applied
+    transactions: u32,
+    disputes: u32,
+}
+
+/// Risk rules evaluated inline as deposits and withdrawals are applied, along with the
+/// per-client activity they look back on. When several rules trip, the strictest action wins.
+///
+/// Written as TOML:
+///
+/// ```toml
+/// [[rules]]
+/// rule = "withdrawal_count"
+/// max = 3
+/// window = 86400
+/// action = "hold"
+///
+/// [[rules]]
+/// rule = "deposit_withdraw_velocity"
+/// window = 600
+/// action = "flag"
+///
+/// [[rules]]
+/// rule = "dispute_rate"
+/// max_percent = "20"
+/// min_transactions = 10
+/// action = "reject"
+/// ```
+///
+/// The activity is saved in engine snapshots, so an engine restored with rules looks back on the
+/// activity from before the snapshot too.
+#[derive(Debug, Clone, Default)]
+pub struct RiskRules {
+    rules: Vec<RiskRule>,
+    // how long withdrawals are remembered for
+    withdrawal_window: u64,
+    activity: HashMap<u16, Activity>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RiskRulesFile {
+    #[serde(default)]
+    rules: Vec<RiskRule>,
+}
+
+impl RiskRules {
+    /// Fails with [`Error::ConfigError`] if a window is zero or a percentage negative.
+    pub fn new(rules: Vec<RiskRule>) -> Result<Self> {
+        let mut withdrawal_window = 0;
+        for rule in &rules {
+            match *rule {
+                RiskRule::WithdrawalCount { window: 0, .. }
+                | RiskRule::DepositWithdrawVelocity { window: 0, .. } => {
+                    return Err(Error::ConfigError(format!(
+                        "{} rule has an empty window",
+                        rule.name()
+                    )));
+                }
+                RiskRule::WithdrawalCount { window, .. } => {
+                    withdrawal_window = withdrawal_window.max(window)
+                }
+                RiskRule::DisputeRate { max_percent, .. } if max_percent < Decimal::ZERO => {
+                    return Err(Error::ConfigError(
+                        "dispute_rate rule has a negative max_percent".to_string(),
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            rules,
+            withdrawal_window,
+            activity: HashMap::default(),
+        })
+    }
+
+    /// Parses rules from TOML, failing with [`Error::ConfigError`] like [`new`](Self::new).
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let file: RiskRulesFile =
+            toml::from_str(text).map_err(|e| Error::ConfigError(e.to_string()))?;
+        Self::new(file.rules)
+    }
+
+    // the tripped rule with the strictest action, if any, for a deposit or withdrawal about to
+    // be applied
+    pub(crate) fn assess(&self, tx: &Transaction) -> Option<&RiskRule> {
+        if !matches!(
+            tx.tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) {
+            return None;
+        }
+        let activity = self.activity.get(&tx.account_id)?;
+        self.rules
+            .iter()
+            .filter(|rule| rule.trips(tx, activity))
+            .max_by_key(|rule| rule.action())
+    }
+
+    pub(crate) fn activity(&self) -> &HashMap<u16, Activity> {
+        &self.activity
+    }
+
+    // take up the activity of a snapshot or of another engine's rules
+    pub(crate) fn restore_activity(&mut self, activity: HashMap<u16, Activity>) {
+        self.activity.extend(activity);
+    }
+
+    // add an applied transaction to its client's activity
+    pub(crate) fn record(&mut self, tx: &Transaction) {
+        let activity = self.activity.entry(tx.account_id).or_default();
+        match tx.tx_type {
+            TransactionType::Deposit => {
+                activity.transactions += 1;
+                activity.last_deposit = tx.timestamp.or(activity.last_deposit);
+            }
+            TransactionType::Withdrawal => {
+                activity.transactions += 1;
+                if let Some(now) = tx.timestamp
+                    && self.withdrawal_window > 0
+                {
+                    while activity
+                        .withdrawals
+                        .front()
+                        .is_some_and(|&at| now.saturating_sub(at) >= self.withdrawal_window)
+                    {
+                        activity.withdrawals.pop_front();
+                    }
+                    activity.withdrawals.push_back(now);
+                }
+            }
+            TransactionType::Dispute => activity.disputes += 1,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::testing::TxBuilder;
+
+    fn tripped(rules: &RiskRules, tx: &Transaction) -> Option<(&'static str, RiskAction)> {
+        rules.assess(tx).map(|rule| (rule.name(), rule.action()))
+    }
+
+    #[test]
+    fn test_from_toml_failure() {
+        for text in [
+            "[[rules]]\nrule = \"withdrawal_count\"\nmax = 1\nwindow = 0\naction = \"flag\"",
+            "[[rules]]\nrule = \"dispute_rate\"\nmax_percent = \"-1\"\naction = \"flag\"",
+            "[[rules]]\nrule = \"dispute_rate\"\nmax_percent = \"1\"\naction = \"block\"",
+            "[[rules]]\nrule = \"velocity\"\nwindow = 1\naction = \"flag\"",
+        ] {
+            assert!(
+                matches!(RiskRules::from_toml(text), Err(Error::ConfigError(_))),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_assess_withdrawals() {
+        let mut rules = RiskRules::from_toml(
+            r#"
+            [[rules]]
+            rule = "withdrawal_count"
+            max = 2
+            window = 100
+            action = "hold"
+
+            [[rules]]
+            rule = "deposit_withdraw_velocity"
+            window = 10
+            action = "flag"
+            "#,
+        )
+        .unwrap();
+
+        rules.record(&TxBuilder::deposit(1, 1, amount!(10)).timestamp(0).build());
+        assert_eq!(
+            tripped(
+                &rules,
+                &TxBuilder::withdrawal(1, 2, amount!(10))
+                    .timestamp(5)
+                    .build()
+            ),
+            Some(("deposit_withdraw_velocity", RiskAction::Flag))
+        );
+        rules.record(
+            &TxBuilder::withdrawal(1, 2, amount!(10))
+                .timestamp(5)
+                .build(),
+        );
+        rules.record(
+            &TxBuilder::withdrawal(1, 3, amount!(10))
+                .timestamp(50)
+                .build(),
+        );
+        // the hold is stricter than the flag
+        assert_eq!(
+            tripped(
+                &rules,
+                &TxBuilder::withdrawal(1, 4, amount!(10))
+                    .timestamp(60)
+                    .build()
+            ),
+            Some(("withdrawal_count", RiskAction::Hold))
+        );
+        assert_eq!(
+            tripped(
+                &rules,
+                &TxBuilder::withdrawal(1, 4, amount!(10))
+                    .timestamp(105)
+                    .build()
+            ),
+            None
+        );
+        assert_eq!(
+            tripped(&rules, &TxBuilder::withdrawal(1, 4, amount!(10)).build()),
+            None
+        );
+        assert_eq!(
+            tripped(
+                &rules,
+                &TxBuilder::deposit(1, 4, amount!(10)).timestamp(9).build()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_assess_dispute_rate() {
+        let mut rules = RiskRules::new(vec![RiskRule::DisputeRate {
+            max_percent: Decimal::from(40),
+            min_transactions: 2,
+            action: RiskAction::Reject,
+        }])
+        .unwrap();
+
+        rules.record(&TxBuilder::deposit(1, 1, amount!(10)).build());
+        rules.record(
+            &TxBuilder::new(TransactionType::Dispute, 1, 1)
+                .amount(amount!(10))
+                .build(),
+        );
+        // too few transactions to judge by
+        assert_eq!(
+            tripped(&rules, &TxBuilder::deposit(1, 2, amount!(10)).build()),
+            None
+        );
+        rules.record(&TxBuilder::deposit(1, 2, amount!(10)).build());
+        assert_eq!(
+            tripped(&rules, &TxBuilder::deposit(1, 3, amount!(10)).build()),
+            Some(("dispute_rate", RiskAction::Reject))
+        );
+        rules.record(&TxBuilder::deposit(1, 3, amount!(10)).build());
+        assert_eq!(
+            tripped(&rules, &TxBuilder::deposit(1, 4, amount!(10)).build()),
+            None
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use payments_engine::{Error, Result, TransactionType};
+
+// what a server caller may do, each role allowed everything the ones before it are: auditors
+// only read, operators also submit transactions, and admins also change account status and
+// post corrections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Auditor,
+    Operator,
+    Admin,
+}
+
+impl Role {
+    // the role submitting a tx of `tx_type` takes
+    pub fn to_submit(tx_type: TransactionType) -> Role {
+        match tx_type {
+            TransactionType::Adjustment
+            | TransactionType::Reversal
+            | TransactionType::Freeze
+            | TransactionType::Unlock
+            | TransactionType::Close => Role::Admin,
+            _ => Role::Operator,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenRow {
+    token: String,
+    role: Role,
+}
+
+// the bearer tokens server callers present, and the roles they grant, read from a CSV of
+// `token,role`
+pub struct Tokens(pub HashMap<String, Role>);
+
+impl Tokens {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut tokens = HashMap::new();
+        for row in csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?
+            .deserialize()
+        {
+            let TokenRow { token, role } = row?;
+            if token.is_empty() {
+                return Err(Error::ConfigError(format!(
+                    "{} lists an empty token",
+                    path.display()
+                )));
+            }
+            tokens.insert(token, role);
+        }
+
+        Ok(Self(tokens))
+    }
+
+    // the role of a caller sending `authorization` as its `Authorization` header, if that's a
+    // known bearer token
+    pub fn role(&self, authorization: Option<&str>) -> Option<Role> {
+        let token = authorization?.strip_prefix("Bearer ")?.trim();
+        self.0.get(token).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_role_success() {
+        let path =
+            std::env::temp_dir().join(format!("payments-engine-tokens-{}", std::process::id()));
+        std::fs::write(&path, "token,role\nread-only, auditor\nroot,admin\n").unwrap();
+
+        let tokens = Tokens::load(&path).unwrap();
+
+        assert_eq!(tokens.role(Some("Bearer read-only")), Some(Role::Auditor));
+        assert_eq!(tokens.role(Some("Bearer root")), Some(Role::Admin));
+        assert_eq!(tokens.role(Some("root")), None);
+        assert_eq!(tokens.role(Some("Bearer other")), None);
+        assert_eq!(tokens.role(None), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_failure_unknown_role() {
+        let path =
+            std::env::temp_dir().join(format!("payments-engine-tokens-bad-{}", std::process::id()));
+        std::fs::write(&path, "token,role\nx,superuser\n").unwrap();
+
+        assert!(Tokens::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_to_submit() {
+        assert_eq!(Role::to_submit(TransactionType::Deposit), Role::Operator);
+        assert_eq!(Role::to_submit(TransactionType::Chargeback), Role::Operator);
+        assert_eq!(Role::to_submit(TransactionType::Unlock), Role::Admin);
+        assert_eq!(Role::to_submit(TransactionType::Adjustment), Role::Admin);
+        assert!(Role::Admin > Role::Operator && Role::Operator > Role::Auditor);
+    }
+}
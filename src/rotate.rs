@@ -0,0 +1,205 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::crypt;
+
+// when a log file is rotated: once it holds `size` bytes, or has been open for `interval`,
+// whichever comes first. Without either, it never rotates
+#[derive(Clone, Copy, Default)]
+pub struct Rotation {
+    pub size: Option<u64>,
+    pub interval: Option<Duration>,
+}
+
+// a line-oriented log at `path`, moved aside to `path.N` once due for rotation and carried on
+// in a fresh file. N counts up from 1 past any segments already there, so segments sort oldest
+// first. It only rotates between lines, so every segment holds whole records, and with
+// `keep_header` every segment starts with the first line written, e.g. a CSV header
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: crypt::Writer<File>,
+    written: u64,
+    opened: Instant,
+    line_start: bool,
+    // the first line so far, and whether it's complete, when kept
+    header: Option<(Vec<u8>, bool)>,
+    next_segment: u64,
+}
+
+impl RotatingFile {
+    pub fn create(path: &Path, rotation: Rotation, keep_header: bool) -> io::Result<Self> {
+        let next_segment = (1..)
+            .find(|n| !segment_path(path, *n).exists())
+            .expect("a free segment number");
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            file: crypt::Writer::new(File::create(path)?)?,
+            written: 0,
+            opened: Instant::now(),
+            line_start: true,
+            header: keep_header.then(|| (Vec::new(), false)),
+            next_segment,
+        })
+    }
+
+    // due with more than the header written since the last rotation
+    fn due(&self) -> bool {
+        let header = match &self.header {
+            Some((header, _)) => header.len() as u64,
+            None => 0,
+        };
+        self.written > header
+            && (self.rotation.size.is_some_and(|size| self.written >= size)
+                || self
+                    .rotation
+                    .interval
+                    .is_some_and(|interval| self.opened.elapsed() >= interval))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        fs::rename(&self.path, segment_path(&self.path, self.next_segment))?;
+        self.next_segment += 1;
+        self.file = crypt::Writer::new(File::create(&self.path)?)?;
+        self.written = 0;
+        self.opened = Instant::now();
+        if let Some((header, true)) = &self.header {
+            self.file.write_all(header)?;
+            self.written = header.len() as u64;
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.line_start && self.due() {
+            self.rotate()?;
+        }
+        // stop at the end of the line that makes the segment due, so the next write starts a
+        // new one
+        let reach = match (self.due(), self.rotation.size) {
+            (true, _) => 0,
+            (false, Some(size)) => size.saturating_sub(self.written) as usize,
+            (false, None) => usize::MAX,
+        };
+        let from = reach.saturating_sub(1);
+        let len = match buf.get(from..) {
+            Some(rest) => rest
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(buf.len(), |end| from + end + 1),
+            None => buf.len(),
+        };
+        let written = self.file.write(&buf[..len])?;
+        let written_bytes = &buf[..written];
+        if let Some((header, complete)) = &mut self.header
+            && !*complete
+        {
+            let line = match written_bytes.iter().position(|&byte| byte == b'\n') {
+                Some(end) => {
+                    *complete = true;
+                    &written_bytes[..=end]
+                }
+                None => written_bytes,
+            };
+            header.extend_from_slice(line);
+        }
+        if let Some(&last) = written_bytes.last() {
+            self.line_start = last == b'\n';
+        }
+        self.written += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn segment_path(path: &Path, n: u64) -> PathBuf {
+    let mut segment = path.as_os_str().to_owned();
+    segment.push(format!(".{}", n));
+    PathBuf::from(segment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "payments-engine-rotate-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_rotates_by_size_between_lines() {
+        let dir = dir("size");
+        let path = dir.join("audit.csv");
+        let rotation = Rotation {
+            size: Some(20),
+            interval: None,
+        };
+        let mut file = io::BufWriter::new(RotatingFile::create(&path, rotation, true).unwrap());
+
+        file.write_all(b"client,tx\n1,1\n1,2\n2,3\n2,4\n3,555555555\n3,6\n")
+            .unwrap();
+        file.flush().unwrap();
+
+        // every segment starts with the header and holds whole rows
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!(read(dir.join("audit.csv.1")), "client,tx\n1,1\n1,2\n2,3\n");
+        assert_eq!(
+            read(dir.join("audit.csv.2")),
+            "client,tx\n2,4\n3,555555555\n"
+        );
+        assert_eq!(read(dir.join("audit.csv")), "client,tx\n3,6\n");
+
+        // a later run carries on after the segments already there
+        let mut file = RotatingFile::create(&path, rotation, false).unwrap();
+        file.write_all(b"0123456789012345678901234\nx\n").unwrap();
+        assert_eq!(read(dir.join("audit.csv.3")), "0123456789012345678901234\n");
+        assert_eq!(read(dir.join("audit.csv")), "x\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotates_by_interval() {
+        let dir = dir("interval");
+        let path = dir.join("events.jsonl");
+        let mut file = RotatingFile::create(
+            &path,
+            Rotation {
+                size: None,
+                interval: Some(Duration::ZERO),
+            },
+            false,
+        )
+        .unwrap();
+
+        file.write_all(b"{\"a\":1}\n").unwrap();
+        file.write_all(b"{\"a\":2}\n").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.join("events.jsonl.1")).unwrap(),
+            "{\"a\":1}\n"
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"a\":2}\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+use std::str::FromStr;
+
+use csv::{ByteRecord, StringRecord};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::{
+    amount::{Amount, AmountExt, PrecisionPolicy},
+    custom::CustomTransaction,
+    error::{Error, Result},
+    transaction::{Transaction, TransactionRow, TransactionType, deserialize_amount},
+};
+
+/// Turns CSV rows into [`Transaction`]s, the way the CLI reads its input.
+///
+/// Rows are expected from a reader trimming every field (`csv::Trim::All`), and their amounts are
+/// brought in line by a [`PrecisionPolicy`]. When the header names the known columns and nothing
+/// else, rows are parsed straight from their bytes, and anything out of the ordinary goes through
+/// serde, which also produces the error for invalid rows.
+///
+/// [`parse_row`](Self::parse_row) also reads rows of types of a deployment's own, for
+/// [`PaymentsEngine::process_custom`](crate::PaymentsEngine::process_custom).
+pub struct RowParser {
+    headers: ByteRecord,
+    columns: Option<Columns>,
+    tx_type: Option<usize>,
+    precision: PrecisionPolicy,
+}
+
+/// A row read by [`RowParser::parse_row`].
+#[derive(Debug, Clone)]
+pub enum Row {
+    /// A row of one of the built-in types, for
+    /// [`PaymentsEngine::process_tx`](crate::PaymentsEngine::process_tx).
+    Transaction(Transaction),
+    /// A row of any other type, for
+    /// [`PaymentsEngine::process_custom`](crate::PaymentsEngine::process_custom), which refuses
+    /// the types that have no handler registered.
+    Custom(CustomTransaction),
+}
+
+impl RowParser {
+    pub fn new(headers: &StringRecord, precision: PrecisionPolicy) -> Self {
+        Self {
+            headers: headers.as_byte_record().clone(),
+            columns: Columns::for_headers(headers),
+            tx_type: headers.iter().position(|header| header == "type"),
+            precision,
+        }
+    }
+
+    /// Fails with [`Error::Csv`] if the row doesn't deserialize, or as
+    /// [`TransactionRow::into_transaction`] does.
+    pub fn parse(&self, record: &ByteRecord) -> Result<Transaction> {
+        match self
+            .columns
+            .as_ref()
+            .and_then(|columns| columns.parse(record, self.precision))
+        {
+            Some(tx) => Ok(tx),
+            None => record
+                .deserialize::<TransactionRow>(Some(&self.headers))
+                .map_err(Error::Csv)
+                .and_then(|row| row.into_transaction(self.precision)),
+        }
+    }
+
+    /// Parses the row as [`parse`](Self::parse) does when its type is a built-in one, and as a
+    /// [`CustomTransaction`] otherwise. Fails with [`Error::Csv`] if the row doesn't deserialize,
+    /// or if the policy rejects its amount.
+    pub fn parse_row(&self, record: &ByteRecord) -> Result<Row> {
+        let custom = self
+            .tx_type
+            .and_then(|idx| record.get(idx))
+            .is_some_and(|name| builtin_type(name).is_none());
+        if !custom {
+            return self.parse(record).map(Row::Transaction);
+        }
+        let row = record
+            .deserialize::<CustomRow>(Some(&self.headers))
+            .map_err(Error::Csv)?;
+        let amount = row
+            .amount
+            .map(|amount| self.precision.apply(amount).and_then(Amount::from_decimal))
+            .transpose()?;
+
+        Ok(Row::Custom(CustomTransaction {
+            tx_type: row.tx_type,
+            account_id: row.account_id,
+            tx_id: row.tx_id,
+            amount,
+            currency: row.currency,
+            timestamp: row.timestamp,
+            reason: row.reason,
+        }))
+    }
+}
+
+// a custom row as read, its amount yet to be brought in line by the precision policy
+#[derive(Deserialize)]
+struct CustomRow {
+    #[serde(rename = "type")]
+    tx_type: String,
+    #[serde(rename = "client")]
+    account_id: u16,
+    #[serde(rename = "tx")]
+    tx_id: u32,
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    amount: Option<Decimal>,
+    #[serde(default)]
+    currency: Option<String>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+// the built-in type named `name` as in input, if any
+fn builtin_type(name: &[u8]) -> Option<TransactionType> {
+    Some(match name {
+        b"adjustment" => TransactionType::Adjustment,
+        b"authorize" => TransactionType::Authorize,
+        b"capture" => TransactionType::Capture,
+        b"chargeback" => TransactionType::Chargeback,
+        b"clear" => TransactionType::Clear,
+        b"close" => TransactionType::Close,
+        b"deposit" => TransactionType::Deposit,
+        b"dispute" => TransactionType::Dispute,
+        b"freeze" => TransactionType::Freeze,
+        b"provisional" => TransactionType::Provisional,
+        b"refund" => TransactionType::Refund,
+        b"resolve" => TransactionType::Resolve,
+        b"reversal" => TransactionType::Reversal,
+        b"unlock" => TransactionType::Unlock,
+        b"void" => TransactionType::Void,
+        b"withdrawal" => TransactionType::Withdrawal,
+        _ => return None,
+    })
+}
+
+// where the known columns sit in the input, when its header has those and nothing else. Rows can
+// then be parsed straight from their bytes, without serde allocating a string per field
+struct Columns {
+    tx_type: usize,
+    client: usize,
+    tx: usize,
+    amount: usize,
+    currency: Option<usize>,
+    timestamp: Option<usize>,
+}
+
+impl Columns {
+    fn for_headers(headers: &StringRecord) -> Option<Self> {
+        let position = |name| headers.iter().position(|header| header == name);
+        let columns = Columns {
+            tx_type: position("type")?,
+            client: position("client")?,
+            tx: position("tx")?,
+            amount: position("amount")?,
+            currency: position("currency"),
+            timestamp: position("timestamp"),
+        };
+        let known =
+            4 + usize::from(columns.currency.is_some()) + usize::from(columns.timestamp.is_some());
+
+        (headers.len() == known).then_some(columns)
+    }
+
+    // `None` for anything out of the ordinary, leaving the row to serde
+    fn parse(&self, record: &ByteRecord, precision: PrecisionPolicy) -> Option<Transaction> {
+        let field = |idx| std::str::from_utf8(record.get(idx)?).ok();
+        let tx_type = builtin_type(record.get(self.tx_type)?)?;
+        let amount = match field(self.amount)? {
+            "" => None,
+            amount => Some(Decimal::from_str(amount).ok()?),
+        };
+        let currency = match self.currency {
+            Some(idx) => Some(field(idx)?).filter(|currency| !currency.is_empty()),
+            None => None,
+        };
+        let timestamp = match self.timestamp.map(field) {
+            Some(Some("")) | None => None,
+            Some(timestamp) => Some(timestamp?.parse().ok()?),
+        };
+
+        TransactionRow {
+            tx_type,
+            account_id: field(self.client)?.parse().ok()?,
+            tx_id: field(self.tx)?.parse().ok()?,
+            amount,
+            currency: currency.map(str::to_owned),
+            timestamp,
+            reason: None,
+            effective: None,
+        }
+        .into_transaction(precision)
+        .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::amount::AmountExt;
+
+    #[test]
+    fn test_columns_parse_matches_serde() {
+        let headers = StringRecord::from(vec!["client", "type", "tx", "amount", "currency"]);
+        let columns = Columns::for_headers(&headers).unwrap();
+        for fields in [
+            vec!["1", "deposit", "1", "10.25", "USD"],
+            vec!["2", "dispute", "1", "", ""],
+            vec!["3", "withdrawal", "7", "0.0001", ""],
+            vec!["4", "deposit", "2", "1.00015", ""],
+        ] {
+            let record = ByteRecord::from(fields);
+
+            let fast = columns.parse(&record, PrecisionPolicy::default()).unwrap();
+
+            let serde = record
+                .deserialize::<Transaction>(Some(headers.as_byte_record()))
+                .unwrap();
+            assert_eq!(fast.tx_type, serde.tx_type);
+            assert_eq!(fast.account_id, serde.account_id);
+            assert_eq!(fast.tx_id, serde.tx_id);
+            assert_eq!(fast.amount, serde.amount);
+            assert_eq!(fast.currency, serde.currency);
+        }
+    }
+
+    #[test]
+    fn test_parse_falls_back_to_serde() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let parser = RowParser::new(&headers, PrecisionPolicy::Reject);
+
+        let tx = parser
+            .parse(&ByteRecord::from(vec!["deposit", "1", "1", "1e2"]))
+            .unwrap();
+
+        assert_eq!(tx.amount, Some(amount!(100)));
+        assert!(matches!(
+            parser.parse(&ByteRecord::from(vec!["deposit", "1", "2", "ten"])),
+            Err(Error::Csv(_))
+        ));
+        assert!(matches!(
+            parser.parse(&ByteRecord::from(vec!["deposit", "1", "3", "1.00001"])),
+            Err(Error::TransactionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_reads_amounts_exactly_either_way() {
+        let fast = RowParser::new(
+            &StringRecord::from(vec!["type", "client", "tx", "amount"]),
+            PrecisionPolicy::Reject,
+        );
+        // an extra column sends every row through serde
+        let serde = RowParser::new(
+            &StringRecord::from(vec!["type", "client", "tx", "amount", "note"]),
+            PrecisionPolicy::Reject,
+        );
+        assert!(fast.columns.is_some() && serde.columns.is_none());
+
+        let expected = Decimal::from_str("123456789012345.6789").unwrap();
+        for (parser, fields) in [
+            (&fast, vec!["deposit", "1", "1", "123456789012345.6789"]),
+            (
+                &serde,
+                vec!["deposit", "1", "1", "123456789012345.6789", ""],
+            ),
+        ] {
+            let tx = parser.parse(&ByteRecord::from(fields)).unwrap();
+
+            assert_eq!(tx.amount.map(|amount| amount.to_decimal()), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_parse_row_reads_custom_types() {
+        let parser = RowParser::new(
+            &StringRecord::from(vec!["type", "client", "tx", "amount"]),
+            PrecisionPolicy::Truncate,
+        );
+
+        let builtin = parser
+            .parse_row(&ByteRecord::from(vec!["deposit", "1", "1", "2"]))
+            .unwrap();
+        let custom = parser
+            .parse_row(&ByteRecord::from(vec!["bonus", "1", "2", "1.00009"]))
+            .unwrap();
+
+        assert!(matches!(builtin, Row::Transaction(tx) if tx.amount == Some(amount!(2))));
+        let Row::Custom(custom) = custom else {
+            panic!("expected a custom row");
+        };
+        assert_eq!(custom.tx_type, "bonus");
+        assert_eq!((custom.account_id, custom.tx_id), (1, 2));
+        assert_eq!(custom.amount, Some(amount!(1)));
+        assert!(matches!(
+            parser.parse_row(&ByteRecord::from(vec!["bonus", "one", "3", "1"])),
+            Err(Error::Csv(_))
+        ));
+    }
+
+    #[test]
+    fn test_columns_leave_unusual_input_to_serde() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let columns = Columns::for_headers(&headers).unwrap();
+
+        assert!(
+            columns
+                .parse(
+                    &ByteRecord::from(vec!["Deposit", "1", "1", "1"]),
+                    PrecisionPolicy::default()
+                )
+                .is_none()
+        );
+        assert!(
+            columns
+                .parse(
+                    &ByteRecord::from(vec!["deposit", "1", "1", "1e3"]),
+                    PrecisionPolicy::default()
+                )
+                .is_none()
+        );
+        assert!(
+            Columns::for_headers(&StringRecord::from(vec![
+                "type", "client", "tx", "amount", "memo"
+            ]))
+            .is_none()
+        );
+    }
+}
@@ -0,0 +1,222 @@
+#[cfg(feature = "rules")]
+use std::fs;
+use std::path::Path;
+
+#[cfg(feature = "rules")]
+use rhai::{AST, Dynamic, Engine, Map, Scope};
+#[cfg(feature = "rules")]
+use rust_decimal::Decimal;
+
+use payments_engine::{Account, Error, Result, Transaction};
+#[cfg(feature = "rules")]
+use payments_engine::{Amount, AmountExt};
+
+// upper bound on script operations per tx so a runaway rule can't stall ingestion
+#[cfg(feature = "rules")]
+const MAX_OPERATIONS: u64 = 100_000;
+
+// operator-supplied Rhai rules evaluated against every tx before it reaches the engine
+#[cfg(feature = "rules")]
+pub struct Rules {
+    engine: Engine,
+    ast: AST,
+}
+
+// without the `rules` feature there is no script engine, so rules can't be loaded at all
+#[cfg(not(feature = "rules"))]
+pub enum Rules {}
+
+#[cfg(not(feature = "rules"))]
+impl Rules {
+    pub fn from_file(_path: &Path) -> Result<Self> {
+        Err(Error::ConfigError(
+            "--rules requires building with the `rules` feature".to_string(),
+        ))
+    }
+
+    pub fn apply(&self, _tx: &mut Transaction, _account: Option<&Account>) -> Result<()> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "rules")]
+impl Rules {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let script = fs::read_to_string(path)?;
+
+        Self::compile(&script)
+    }
+
+    fn compile(script: &str) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine
+            .compile(script)
+            .map_err(|e| Error::RuleError(format!("failed to compile rules: {}", e)))?;
+
+        Ok(Self { engine, ast })
+    }
+
+    // the script sees `tx` (type, client, tx, amount, currency) and a snapshot of `account`
+    // (available, held, total in the tx's currency, locked and status--zeroed/active for unseen
+    // clients). It rejects the tx by evaluating to `false` or to a string reason, and may enrich
+    // it by assigning a new `tx.amount`
+    pub fn apply(&self, tx: &mut Transaction, account: Option<&Account>) -> Result<()> {
+        let mut scope = Scope::new();
+        scope.push("tx", tx_map(tx));
+        scope.push_constant("account", account_map(tx, account));
+
+        let outcome = self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast)
+            .map_err(|e| Error::RuleError(format!("rule evaluation failed: {}", e)))?;
+
+        if let Some(false) = outcome.clone().try_cast::<bool>() {
+            return Err(Error::RuleError("rejected by rule".into()));
+        }
+        if let Some(reason) = outcome.try_cast::<String>() {
+            return Err(Error::RuleError(format!("rejected by rule: {}", reason)));
+        }
+
+        let amount = scope
+            .get_value::<Map>("tx")
+            .and_then(|map| map.get("amount").cloned())
+            .unwrap_or(Dynamic::UNIT);
+        tx.amount =
+            if amount.is_unit() {
+                None
+            } else {
+                let amount = amount.try_cast::<Decimal>().ok_or_else(|| {
+                    Error::RuleError("rules must set tx.amount to a decimal or ()".into())
+                })?;
+                Some(Amount::from_decimal(amount).map_err(|e| {
+                    Error::RuleError(format!("rules set an invalid tx.amount: {}", e))
+                })?)
+            };
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "rules")]
+fn tx_map(tx: &Transaction) -> Map {
+    let mut map = Map::new();
+    map.insert("type".into(), tx.tx_type.to_string().into());
+    map.insert("client".into(), Dynamic::from_int(tx.account_id.into()));
+    map.insert("tx".into(), Dynamic::from_int(tx.tx_id.into()));
+    map.insert(
+        "amount".into(),
+        tx.amount.map_or(Dynamic::UNIT, |amount| {
+            Dynamic::from_decimal(amount.to_decimal())
+        }),
+    );
+    map.insert("currency".into(), tx.currency_code().into());
+
+    map
+}
+
+#[cfg(feature = "rules")]
+fn account_map(tx: &Transaction, account: Option<&Account>) -> Map {
+    let unseen = Account::new(tx.account_id);
+    let account = account.unwrap_or(&unseen);
+    let balance = account.balance(tx.currency_code());
+
+    let mut map = Map::new();
+    map.insert("client".into(), Dynamic::from_int(account.id.into()));
+    map.insert(
+        "available".into(),
+        Dynamic::from_decimal(balance.available.to_decimal()),
+    );
+    map.insert(
+        "held".into(),
+        Dynamic::from_decimal(balance.held.to_decimal()),
+    );
+    map.insert(
+        "total".into(),
+        Dynamic::from_decimal(balance.total.to_decimal()),
+    );
+    map.insert("locked".into(), account.is_locked().into());
+    map.insert("status".into(), account.status.to_string().into());
+
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "rules")]
+    use payments_engine::DEFAULT_CURRENCY;
+    #[cfg(feature = "rules")]
+    use payments_engine::amount;
+    #[cfg(feature = "rules")]
+    use payments_engine::testing::TxBuilder;
+
+    #[cfg(feature = "rules")]
+    #[cfg(feature = "rules")]
+    #[test]
+    fn test_apply_accepts() {
+        let rules = Rules::compile(r#"tx.type == "deposit" && tx.amount < 1000"#).unwrap();
+        let mut tx = TxBuilder::deposit(1, 1, amount!(100)).build();
+
+        assert!(rules.apply(&mut tx, None).is_ok());
+        assert_eq!(tx.amount, Some(amount!(100)));
+    }
+
+    #[cfg(feature = "rules")]
+    #[test]
+    fn test_apply_rejects_on_false() {
+        let rules = Rules::compile("tx.amount < 1000").unwrap();
+        let mut tx = TxBuilder::deposit(1, 1, amount!(5000)).build();
+
+        assert!(rules.apply(&mut tx, None).is_err());
+    }
+
+    #[cfg(feature = "rules")]
+    #[test]
+    fn test_apply_rejects_with_reason() {
+        let rules =
+            Rules::compile(r#"if account.available < tx.amount { "too large" } else { true }"#)
+                .unwrap();
+        let mut account = Account::new(1);
+        account.deposit(DEFAULT_CURRENCY, amount!(10)).unwrap();
+        let mut tx = TxBuilder::deposit(1, 1, amount!(50)).build();
+
+        let result = rules.apply(&mut tx, Some(&account));
+
+        assert!(result.unwrap_err().to_string().contains("too large"));
+    }
+
+    #[cfg(feature = "rules")]
+    #[test]
+    fn test_apply_enriches_amount() {
+        let rules = Rules::compile("tx.amount = tx.amount.round(2);").unwrap();
+        let mut tx = TxBuilder::deposit(1, 1, amount!(1.2345)).build();
+
+        rules.apply(&mut tx, None).unwrap();
+
+        assert_eq!(tx.amount, Some(amount!(1.23)));
+    }
+
+    #[cfg(feature = "rules")]
+    #[test]
+    fn test_apply_failure_runaway_script() {
+        let rules = Rules::compile("loop {}").unwrap();
+        let mut tx = TxBuilder::deposit(1, 1, amount!(1)).build();
+
+        assert!(rules.apply(&mut tx, None).is_err());
+    }
+
+    #[cfg(feature = "rules")]
+    #[test]
+    fn test_compile_failure() {
+        assert!(Rules::compile("tx.amount <").is_err());
+    }
+
+    #[cfg(not(feature = "rules"))]
+    #[test]
+    fn test_from_file_failure_without_feature() {
+        let result = Rules::from_file(Path::new("rules.rhai"));
+
+        assert!(matches!(result, Err(Error::ConfigError(_))));
+    }
+}
@@ -0,0 +1,114 @@
+use std::io::Read;
+
+use serde::Deserialize;
+
+use payments_engine::{
+    AccountStatus, Amount, Balance, DEFAULT_CURRENCY, Error, ErrorContext, PaymentsEngine, Result,
+};
+
+// one row of an accounts csv, as written by a previous run (the currency and status columns are
+// optional; without a status, `locked` decides between active and locked)
+#[derive(Deserialize)]
+struct SeedRow {
+    client: u16,
+    #[serde(default)]
+    currency: Option<String>,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    #[serde(default)]
+    status: Option<AccountStatus>,
+}
+
+// seed opening balances from an accounts csv before any transactions are processed--any bad row
+// fails the run, since starting from wrong balances would silently corrupt the whole batch
+pub fn load<R: Read>(engine: &mut PaymentsEngine, reader: R) -> Result<()> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers = rdr.headers()?.clone();
+
+    for result in rdr.records() {
+        let record = result?;
+        let line = record.position().map(|pos| pos.line());
+        let seeded = record
+            .deserialize::<SeedRow>(Some(&headers))
+            .map_err(Error::Csv)
+            .and_then(|row| {
+                let balance = Balance {
+                    available: row.available,
+                    held: row.held,
+                    total: row.total,
+                };
+                let currency = row.currency.as_deref().unwrap_or(DEFAULT_CURRENCY);
+                let status = match (row.status, row.locked) {
+                    (Some(status), _) => status,
+                    (None, true) => AccountStatus::Locked,
+                    (None, false) => AccountStatus::Active,
+                };
+                engine
+                    .seed_balance(row.client, currency, balance, status)
+                    .map_err(|e| {
+                        e.with_context(ErrorContext {
+                            account_id: Some(row.client),
+                            ..ErrorContext::default()
+                        })
+                    })
+            });
+        seeded.map_err(|e| e.with_context(ErrorContext::for_line(line)))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::amount;
+
+    #[test]
+    fn test_load_success() {
+        let mut engine = PaymentsEngine::new();
+        let input = "client,available,held,total,locked\n\
+                     1,7.5,2.5,10,false\n\
+                     2,0,0,0,true\n";
+
+        load(&mut engine, input.as_bytes()).unwrap();
+
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).total,
+            amount!(10)
+        );
+        assert!(engine.account(2).unwrap().is_locked());
+    }
+
+    #[test]
+    fn test_load_multi_currency() {
+        let mut engine = PaymentsEngine::new();
+        let input = "client,currency,available,held,total,locked,status\n\
+                     1,EUR,5,0,5,false,active\n\
+                     1,USD,3,0,3,false,frozen\n";
+
+        load(&mut engine, input.as_bytes()).unwrap();
+
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.balance("EUR").total, amount!(5));
+        assert_eq!(account.balance("USD").total, amount!(3));
+        assert_eq!(account.status, AccountStatus::Frozen);
+    }
+
+    #[test]
+    fn test_load_failure_inconsistent_total() {
+        let mut engine = PaymentsEngine::new();
+        let input = "client,available,held,total,locked\n\
+                     1,7.5,2.5,10,false\n\
+                     2,1,1,3,false\n";
+
+        let error = load(&mut engine, input.as_bytes()).unwrap_err();
+
+        assert!(matches!(error.root(), Error::AccountError(_)));
+        let context = error.context().unwrap();
+        assert_eq!((context.line, context.account_id), (Some(3), Some(2)));
+    }
+}
@@ -0,0 +1,107 @@
+use std::io::Write;
+
+use payments_engine::{PaymentsEngine, Result};
+
+use crate::{
+    ingest::Ingest,
+    output::{OutputFormat, write_accounts},
+};
+
+// (name, input csv, expected output csv) bundled into the binary so a deployment can be
+// validated without the source tree
+const CASES: &[(&str, &str, &str)] = &[
+    (
+        "dispute-flows",
+        include_str!("../tests/fixtures/selftest/dispute-flows.csv"),
+        include_str!("../tests/fixtures/selftest/dispute-flows.expected.csv"),
+    ),
+    (
+        "malformed-rows",
+        include_str!("../tests/fixtures/selftest/malformed-rows.csv"),
+        include_str!("../tests/fixtures/selftest/malformed-rows.expected.csv"),
+    ),
+    (
+        "precision",
+        include_str!("../tests/fixtures/selftest/precision.csv"),
+        include_str!("../tests/fixtures/selftest/precision.expected.csv"),
+    ),
+];
+
+// runs every bundled case through the full pipeline, reporting each result to `out`, and returns
+// whether all of them matched
+pub fn run<W: Write>(mut out: W) -> Result<bool> {
+    let mut passed = true;
+
+    for (name, input, expected) in CASES {
+        let actual = run_case(input)?;
+        if normalize(&actual) == normalize(expected) {
+            writeln!(out, "ok       {}", name)?;
+        } else {
+            passed = false;
+            writeln!(out, "FAILED   {}", name)?;
+            writeln!(out, "expected:\n{}", expected.trim_end())?;
+            writeln!(out, "actual:\n{}", actual.trim_end())?;
+        }
+    }
+    out.flush()?;
+
+    Ok(passed)
+}
+
+fn run_case(input: &str) -> Result<String> {
+    let mut engine = PaymentsEngine::new();
+    Ingest::default().process(&mut engine, input.as_bytes())?;
+
+    let mut output = Vec::new();
+    write_accounts(&engine, &mut output, OutputFormat::Csv)?;
+
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+// account rows are written in map order, so compare the header and a sorted set of rows
+fn normalize(output: &str) -> (Option<&str>, Vec<&str>) {
+    let mut lines = output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty());
+    let header = lines.next();
+    let mut rows: Vec<&str> = lines.collect();
+    rows.sort_unstable();
+
+    (header, rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_cases_pass() {
+        for (name, input, expected) in CASES {
+            let actual = run_case(input).unwrap();
+
+            assert_eq!(normalize(&actual), normalize(expected), "case {}", name);
+        }
+    }
+
+    #[test]
+    fn test_run_reports_each_case() {
+        let mut out = Vec::new();
+
+        assert!(run(&mut out).unwrap());
+        let report = String::from_utf8(out).unwrap();
+        let expected: Vec<String> = CASES
+            .iter()
+            .map(|(name, _, _)| format!("ok       {}", name))
+            .collect();
+        assert_eq!(report.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_normalize_ignores_row_order() {
+        let a = "client,available\n2,1\n1,2\n";
+        let b = "client,available\n1,2\n2,1";
+
+        assert_eq!(normalize(a), normalize(b));
+    }
+}
@@ -0,0 +1,493 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::{FromRequestParts, Path, State},
+    http::{StatusCode, header::AUTHORIZATION, request::Parts},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio_stream::{
+    StreamExt,
+    wrappers::{BroadcastStream, errors::BroadcastStreamRecvError},
+};
+
+use payments_engine::{
+    Account, AsyncPaymentsEngine, Error, ErrorCategory, Event, EventSink, Result, Transaction,
+};
+
+use crate::{
+    metrics::Metrics,
+    roles::{Role, Tokens},
+};
+
+// events waiting for the slowest `GET /events` caller; one further behind skips the oldest
+const EVENTS_CAPACITY: usize = 1024;
+
+#[derive(Clone)]
+struct AppState {
+    engine: AsyncPaymentsEngine,
+    metrics: Arc<Metrics>,
+    // without tokens, every caller is an admin
+    tokens: Option<Arc<Tokens>>,
+    events: broadcast::Sender<Event>,
+}
+
+// the channel the server's engines emit their events to, for `GET /events` callers to follow
+pub fn event_channel() -> broadcast::Sender<Event> {
+    broadcast::channel(EVENTS_CAPACITY).0
+}
+
+// passes every event on to whoever follows `GET /events` at the time, and drops it otherwise
+pub struct EventBroadcast(pub broadcast::Sender<Event>);
+
+impl EventSink for EventBroadcast {
+    fn emit(&mut self, event: &Event) -> Result<()> {
+        // no one following isn't an error
+        let _ = self.0.send(event.clone());
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    category: String,
+    code: String,
+    error: String,
+}
+
+// errors as JSON responses: engine errors with the status derived from their category, and
+// callers without a known token or the role a request takes
+enum ApiError {
+    Engine(Error),
+    Unauthenticated,
+    Forbidden(Role),
+}
+
+impl From<Error> for ApiError {
+    fn from(error: Error) -> Self {
+        ApiError::Engine(error)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            ApiError::Engine(e) => {
+                let status = match (e.root(), e.category()) {
+                    (Error::EngineError(_), _) => StatusCode::SERVICE_UNAVAILABLE,
+                    (_, ErrorCategory::Parse) => StatusCode::BAD_REQUEST,
+                    (_, ErrorCategory::Duplicate) => StatusCode::CONFLICT,
+                    _ => StatusCode::UNPROCESSABLE_ENTITY,
+                };
+                let body = ErrorBody {
+                    category: e.category().to_string(),
+                    code: e.code().to_string(),
+                    error: e.to_string(),
+                };
+                (status, body)
+            }
+            ApiError::Unauthenticated => (
+                StatusCode::UNAUTHORIZED,
+                ErrorBody {
+                    category: "authorization".to_string(),
+                    code: "unauthenticated".to_string(),
+                    error: "a known bearer token is required".to_string(),
+                },
+            ),
+            ApiError::Forbidden(role) => (
+                StatusCode::FORBIDDEN,
+                ErrorBody {
+                    category: "authorization".to_string(),
+                    code: "forbidden".to_string(),
+                    error: format!("this takes the {:?} role", role).to_lowercase(),
+                },
+            ),
+        };
+
+        (status, Json(body)).into_response()
+    }
+}
+
+// the role of the caller, by the bearer token in its `Authorization` header
+struct Caller(Role);
+
+impl Caller {
+    fn require(&self, role: Role) -> std::result::Result<(), ApiError> {
+        match self.0 >= role {
+            true => Ok(()),
+            false => Err(ApiError::Forbidden(role)),
+        }
+    }
+}
+
+impl FromRequestParts<AppState> for Caller {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        let Some(tokens) = &state.tokens else {
+            return Ok(Caller(Role::Admin));
+        };
+        let authorization = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        tokens
+            .role(authorization)
+            .map(Caller)
+            .ok_or(ApiError::Unauthenticated)
+    }
+}
+
+// serve the engine `spawn` starts on the server's runtime over HTTP until the process is stopped,
+// to the callers `tokens` lists, or to anyone without them. `events` is where the engine emits
+// its events
+pub fn run(
+    addr: SocketAddr,
+    tokens: Option<Tokens>,
+    events: broadcast::Sender<Event>,
+    spawn: impl FnOnce() -> Result<AsyncPaymentsEngine>,
+) -> Result<()> {
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let engine = spawn()?;
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        tracing::info!(addr = %listener.local_addr()?, "listening");
+        axum::serve(listener, router(engine, tokens, events)).await?;
+
+        Ok(())
+    })
+}
+
+fn router(
+    engine: AsyncPaymentsEngine,
+    tokens: Option<Tokens>,
+    events: broadcast::Sender<Event>,
+) -> Router {
+    Router::new()
+        .route("/transactions", post(submit_transaction))
+        .route("/accounts", get(list_accounts))
+        .route("/accounts/{id}", get(get_account))
+        .route("/events", get(follow_events))
+        .route("/metrics", get(metrics))
+        .with_state(AppState {
+            engine,
+            metrics: Arc::new(Metrics::default()),
+            tokens: tokens.map(Arc::new),
+            events,
+        })
+}
+
+async fn submit_transaction(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(tx): Json<Transaction>,
+) -> std::result::Result<StatusCode, ApiError> {
+    caller.require(Role::to_submit(tx.tx_type))?;
+    let started = Instant::now();
+    let tx_type = tx.tx_type;
+    match state.engine.process(tx).await {
+        Ok(()) => state.metrics.record_applied(tx_type, started.elapsed()),
+        Err(e) => {
+            state.metrics.record_failure(&e, started.elapsed());
+            return Err(ApiError::Engine(e));
+        }
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_accounts(
+    State(state): State<AppState>,
+    caller: Caller,
+) -> std::result::Result<Json<Vec<Account>>, ApiError> {
+    caller.require(Role::Auditor)?;
+    Ok(Json(state.engine.accounts().await?))
+}
+
+async fn get_account(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<u16>,
+) -> std::result::Result<Response, ApiError> {
+    caller.require(Role::Auditor)?;
+    Ok(match state.engine.account(id).await? {
+        Some(account) => Json(account).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+// the events emitted from now on, as JSON lines, for as long as the caller keeps reading
+async fn follow_events(
+    State(state): State<AppState>,
+    caller: Caller,
+) -> std::result::Result<Response, ApiError> {
+    caller.require(Role::Auditor)?;
+    let lines = BroadcastStream::new(state.events.subscribe()).filter_map(|event| match event {
+        Ok(event) => {
+            let line = serde_json::to_string(&event).expect("events serialize to JSON") + "\n";
+            Some(Ok::<_, Infallible>(line))
+        }
+        Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+            tracing::warn!(skipped, "events caller fell behind, skipping events");
+            None
+        }
+    });
+
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response())
+}
+
+async fn metrics(
+    State(state): State<AppState>,
+    caller: Caller,
+) -> std::result::Result<Response, ApiError> {
+    caller.require(Role::Auditor)?;
+    let accounts = state.engine.accounts().await?;
+    let body = state.metrics.render(&accounts);
+
+    Ok((
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )],
+        body,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use payments_engine::PaymentsEngine;
+    use tower::ServiceExt;
+
+    async fn send(router: &Router, request: Request<Body>) -> (StatusCode, String) {
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn post_tx(body: &str) -> Request<Body> {
+        Request::post("/transactions")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_submit_and_query_success() {
+        let router = router(
+            AsyncPaymentsEngine::spawn(PaymentsEngine::new()),
+            None,
+            event_channel(),
+        );
+
+        let (status, _) = send(
+            &router,
+            post_tx(r#"{"type":"deposit","client":1,"tx":1,"amount":"10.5"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, body) = send(
+            &router,
+            Request::get("/accounts/1").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#""available":"10.5""#));
+
+        let (status, body) = send(
+            &router,
+            Request::get("/accounts").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.starts_with(r#"[{"id":1"#));
+    }
+
+    #[tokio::test]
+    async fn test_submit_duplicate_across_actors() {
+        let router = router(
+            AsyncPaymentsEngine::spawn_per_account(PaymentsEngine::new).unwrap(),
+            None,
+            event_channel(),
+        );
+        send(
+            &router,
+            post_tx(r#"{"type":"deposit","client":1,"tx":1,"amount":"10"}"#),
+        )
+        .await;
+
+        let (status, body) = send(
+            &router,
+            post_tx(r#"{"type":"deposit","client":2,"tx":1,"amount":"10"}"#),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(body.contains(r#""code":"duplicate-transaction""#));
+    }
+
+    #[tokio::test]
+    async fn test_submit_failure_insufficient_funds() {
+        let router = router(
+            AsyncPaymentsEngine::spawn(PaymentsEngine::new()),
+            None,
+            event_channel(),
+        );
+
+        let (status, body) = send(
+            &router,
+            post_tx(r#"{"type":"withdrawal","client":1,"tx":1,"amount":"10"}"#),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body.contains(r#""category":"insufficient-funds""#));
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let router = router(
+            AsyncPaymentsEngine::spawn(PaymentsEngine::new()),
+            None,
+            event_channel(),
+        );
+        send(
+            &router,
+            post_tx(r#"{"type":"deposit","client":1,"tx":1,"amount":"10"}"#),
+        )
+        .await;
+        send(
+            &router,
+            post_tx(r#"{"type":"withdrawal","client":1,"tx":2,"amount":"50"}"#),
+        )
+        .await;
+
+        let (status, body) = send(
+            &router,
+            Request::get("/metrics").body(Body::empty()).unwrap(),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("payments_transactions_total{type=\"deposit\"} 1"));
+        assert!(body.contains("payments_failures_total{code=\"insufficient-funds\"} 1"));
+        assert!(body.contains("payments_accounts 1"));
+    }
+
+    #[tokio::test]
+    async fn test_roles() {
+        let tokens = Tokens(
+            [
+                ("audit", Role::Auditor),
+                ("ops", Role::Operator),
+                ("root", Role::Admin),
+            ]
+            .into_iter()
+            .map(|(token, role)| (token.to_string(), role))
+            .collect(),
+        );
+        let router = router(
+            AsyncPaymentsEngine::spawn(PaymentsEngine::new()),
+            Some(tokens),
+            event_channel(),
+        );
+        let as_caller = |token: &str, mut request: Request<Body>| {
+            request
+                .headers_mut()
+                .insert(AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            request
+        };
+        let deposit = r#"{"type":"deposit","client":1,"tx":1,"amount":"10"}"#;
+        let unlock = r#"{"type":"unlock","client":1,"tx":2}"#;
+
+        let (status, body) = send(&router, post_tx(deposit)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(body.contains(r#""code":"unauthenticated""#));
+        let (status, _) = send(&router, as_caller("audit", post_tx(deposit))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&router, as_caller("ops", post_tx(deposit))).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, body) = send(&router, as_caller("ops", post_tx(unlock))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(body.contains("takes the admin role"));
+        let (status, _) = send(&router, as_caller("root", post_tx(unlock))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let get = || Request::get("/accounts/1").body(Body::empty()).unwrap();
+        assert_eq!(send(&router, get()).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            send(&router, as_caller("audit", get())).await.0,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_follow_events() {
+        let events = event_channel();
+        let engine = PaymentsEngine::builder()
+            .event_sink(Box::new(EventBroadcast(events.clone())))
+            .build();
+        let router = router(AsyncPaymentsEngine::spawn(engine), None, events);
+        let response = router
+            .clone()
+            .oneshot(Request::get("/events").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        send(
+            &router,
+            post_tx(r#"{"type":"deposit","client":1,"tx":1,"amount":"10"}"#),
+        )
+        .await;
+
+        let line = response
+            .into_body()
+            .into_data_stream()
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            line,
+            "{\"event\":\"deposited\",\"client\":1,\"tx\":1,\"amount\":\"10\"}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_account_failure_unknown_client() {
+        let router = router(
+            AsyncPaymentsEngine::spawn(PaymentsEngine::new()),
+            None,
+            event_channel(),
+        );
+
+        let (status, _) = send(
+            &router,
+            Request::get("/accounts/7").body(Body::empty()).unwrap(),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
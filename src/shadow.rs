@@ -0,0 +1,180 @@
+use std::io::Write;
+
+use payments_engine::{ErrorCode, PaymentsEngine, Result, Transaction, TransactionType};
+use serde::Serialize;
+
+use crate::diff::{self, DiffRow};
+
+// where the shadow engine parted ways with the primary one, as written to the report
+#[derive(Serialize)]
+#[serde(tag = "divergence", rename_all = "lowercase")]
+enum Divergence<'a> {
+    // one engine applied a transaction the other failed, or they failed it differently
+    Decision {
+        line: Option<u64>,
+        tx: u32,
+        client: u16,
+        #[serde(rename = "type")]
+        tx_type: TransactionType,
+        primary: Decision,
+        shadow: Decision,
+    },
+    // final balances or status of a client and currency, the primary's being the old side
+    Balance(&'a DiffRow),
+}
+
+// what an engine did with a transaction: `applied`, or the code of the error it failed with
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Decision(Option<ErrorCode>);
+
+impl Serialize for Decision {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        match self.0 {
+            Some(code) => serializer.collect_str(&code),
+            None => serializer.serialize_str("applied"),
+        }
+    }
+}
+
+// a second engine, configured differently, that every transaction the primary engine gets is
+// also applied to, to see what a change of policy would do to the same input before rolling it
+// out. Each transaction the two decide differently and, at the end, each client whose balances
+// differ is written to the report as a JSON line
+pub struct Shadow {
+    engine: PaymentsEngine,
+    report: Option<Box<dyn Write>>,
+    decisions: u64,
+}
+
+impl Shadow {
+    pub fn new(engine: PaymentsEngine, report: Option<Box<dyn Write>>) -> Self {
+        Shadow {
+            engine,
+            report,
+            decisions: 0,
+        }
+    }
+
+    // the shadow engine, for operations the primary engine goes through outside of ingestion
+    pub fn engine(&mut self) -> &mut PaymentsEngine {
+        &mut self.engine
+    }
+
+    // apply `tx` to the shadow engine too and report it if it isn't decided like the primary
+    // engine did (`primary` being its error code, if it failed). `line` gives the input line,
+    // only needed on divergence
+    pub fn apply(
+        &mut self,
+        tx: &Transaction,
+        primary: Option<ErrorCode>,
+        line: impl Fn() -> Option<u64>,
+    ) -> Result<()> {
+        let shadow = self.engine.process_tx(tx).err().map(|e| e.code());
+        // disputes the shadow gave up on waiting for are its own business
+        self.engine.take_dead_letters();
+        if shadow == primary {
+            return Ok(());
+        }
+        self.decisions += 1;
+        self.write(&Divergence::Decision {
+            line: line(),
+            tx: tx.tx_id,
+            client: tx.account_id,
+            tx_type: tx.tx_type,
+            primary: Decision(primary),
+            shadow: Decision(shadow),
+        })
+    }
+
+    // report every client whose final balances differ from `primary`'s and log how far the two
+    // engines diverged
+    pub fn finish(mut self, primary: &PaymentsEngine) -> Result<()> {
+        self.engine.flush_pending_disputes();
+        self.engine.take_dead_letters();
+        let balances = diff::changes(primary, &self.engine);
+        for row in &balances {
+            self.write(&Divergence::Balance(row))?;
+        }
+        if let Some(report) = &mut self.report {
+            report.flush()?;
+        }
+        if self.decisions > 0 || !balances.is_empty() {
+            tracing::warn!(
+                decisions = self.decisions,
+                balances = balances.len(),
+                "shadow engine diverged"
+            );
+        } else {
+            tracing::info!("shadow engine matched");
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, divergence: &Divergence) -> Result<()> {
+        if let Some(report) = &mut self.report {
+            serde_json::to_writer(&mut *report, divergence).map_err(std::io::Error::other)?;
+            writeln!(report)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::testing::TxBuilder;
+    use payments_engine::{DEFAULT_CURRENCY, NegativeAvailablePolicy, amount};
+
+    #[test]
+    fn test_shadow_reports_divergence() {
+        let mut primary = PaymentsEngine::new();
+        let report = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut shadow = Shadow::new(
+            PaymentsEngine::builder()
+                .negative_available_policy(NegativeAvailablePolicy::Reject)
+                .build(),
+            Some(Box::new(SharedWriter(report.clone()))),
+        );
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(10)).build(),
+            TxBuilder::withdrawal(1, 2, amount!(8)).build(),
+            TxBuilder::dispute(1, 1).build(),
+        ] {
+            let decision = primary.process_tx(&tx).err().map(|e| e.code());
+            shadow.apply(&tx, decision, || Some(7)).unwrap();
+        }
+        shadow.finish(&primary).unwrap();
+
+        assert_eq!(
+            primary.account(1).unwrap().balance(DEFAULT_CURRENCY).held,
+            amount!(10)
+        );
+        let report = String::from_utf8(report.borrow().clone()).unwrap();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"divergence":"decision","line":7,"tx":1,"client":1,"type":"dispute","primary":"applied","shadow":"insufficient-funds"}"#,
+                r#"{"divergence":"balance","client":1,"currency":"","change":"balance","available":"10.0000","held":"-10.0000","total":"0.0000","old_status":"active","new_status":"active"}"#,
+            ]
+        );
+    }
+
+    // a writer the test can read back after handing it to the shadow
+    struct SharedWriter(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
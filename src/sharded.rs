@@ -0,0 +1,325 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use dashmap::mapref::entry::Entry;
+
+use crate::{
+    account::Account,
+    async_engine::Command,
+    engine::{DuplicatePolicy, PaymentsEngine},
+    error::{Error, ErrorContext, Result},
+    hash::DashMap,
+    transaction::Transaction,
+};
+
+// one or more clients' engine, `None` once merged into another's. A caller that finds it empty
+// looks the client up again, as it now maps to the engine it was merged into
+type Slot = Arc<Mutex<Option<PaymentsEngine>>>;
+
+// an engine per client, like the actors, but kept in concurrent maps and applied to on the
+// caller's task: each map is split into shards locked on their own, and each engine has a lock
+// of its own, so requests for different clients only meet on a shard lock held for a lookup.
+// Clients merged into another share its engine from then on
+pub(crate) struct Shards {
+    factory: Box<dyn Fn() -> PaymentsEngine + Send + Sync>,
+    // the engine built to check the factory's configuration, handed to the first client
+    spare: Mutex<Option<PaymentsEngine>>,
+    duplicate_policy: DuplicatePolicy,
+    engines: DashMap<u16, Slot>,
+    // the client each deposit or withdrawal tx id belongs to, claimed under the claiming
+    // client's engine lock and given back if the tx isn't applied
+    owners: DashMap<u32, u16>,
+}
+
+impl Shards {
+    pub(crate) fn new<F>(factory: F) -> Result<Self>
+    where
+        F: Fn() -> PaymentsEngine + Send + Sync + 'static,
+    {
+        let engine = factory();
+        if engine.charges_fees() {
+            return Err(Error::ConfigError(
+                "an engine per account can't charge fees".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            factory: Box::new(factory),
+            duplicate_policy: engine.duplicate_policy(),
+            spare: Mutex::new(Some(engine)),
+            engines: DashMap::default(),
+            owners: DashMap::default(),
+        })
+    }
+
+    // a dropped reply just means the caller stopped waiting, so it's ignored
+    pub(crate) fn dispatch(&self, command: Command) {
+        match command {
+            Command::Process(tx, reply) => {
+                let _ = reply.send(self.process(&tx));
+            }
+            Command::Account(id, reply) => {
+                let _ = reply.send(self.account(id));
+            }
+            Command::Accounts(reply) => {
+                let _ = reply.send(self.accounts());
+            }
+            Command::Merge(source, target, reply) => {
+                let _ = reply.send(self.merge(source, target));
+            }
+            Command::EvictExpired(now, reply) => {
+                let _ = reply.send(self.evict_expired(now));
+            }
+        }
+    }
+
+    fn process(&self, tx: &Transaction) -> Result<()> {
+        let slot = self.slot(tx.account_id);
+        let mut guard = lock(&slot);
+        let Some(engine) = guard.as_mut() else {
+            drop(guard);
+            return self.process(tx);
+        };
+        let mut claimed = false;
+        if PaymentsEngine::creates_record(tx.tx_type) {
+            match self.owners.entry(tx.tx_id) {
+                // another engine holds the tx id, or is still applying it, so this one wouldn't
+                // see the duplicate
+                Entry::Occupied(_) if !engine.has_transaction(tx.tx_id)? => {
+                    return match self.duplicate_policy {
+                        DuplicatePolicy::Reject => Err(Error::DuplicateTransaction(tx.tx_id)
+                            .with_context(ErrorContext::for_tx(tx))),
+                        DuplicatePolicy::Skip => Ok(()),
+                    };
+                }
+                Entry::Occupied(_) => {}
+                Entry::Vacant(entry) => {
+                    entry.insert(tx.account_id);
+                    claimed = true;
+                }
+            }
+        }
+        let result = engine.process_tx(tx);
+        // give the tx id back for another client to use if it wasn't stored after all
+        if claimed && !engine.has_transaction(tx.tx_id).unwrap_or(true) {
+            self.owners.remove(&tx.tx_id);
+        }
+
+        result
+    }
+
+    fn account(&self, id: u16) -> Option<Account> {
+        let slot = self.engines.get(&id)?.clone();
+        let guard = lock(&slot);
+        match guard.as_ref() {
+            Some(engine) => engine.account(id).cloned(),
+            None => {
+                drop(guard);
+                self.account(id)
+            }
+        }
+    }
+
+    fn accounts(&self) -> Vec<Account> {
+        let mut accounts = Vec::new();
+        for slot in self.slots() {
+            if let Some(engine) = lock(&slot).as_ref() {
+                accounts.extend(engine.accounts().cloned());
+            }
+        }
+        accounts
+    }
+
+    fn evict_expired(&self, now: u64) -> Result<usize> {
+        let mut evicted = 0;
+        for slot in self.slots() {
+            if let Some(engine) = lock(&slot).as_mut() {
+                evicted += engine.evict_expired(now)?;
+            }
+        }
+        Ok(evicted)
+    }
+
+    // each engine's slot once; merged clients share a slot, and the one merged away is empty
+    fn slots(&self) -> Vec<Slot> {
+        let mut slots: Vec<Slot> = Vec::new();
+        for entry in self.engines.iter() {
+            let slot = entry.value();
+            if !slots.iter().any(|seen| Arc::ptr_eq(seen, slot)) {
+                slots.push(slot.clone());
+            }
+        }
+        slots
+    }
+
+    // merge `source` into `target`, moving the source's engine into the target's first if they
+    // have one each. Both engines are locked in address order, so two merges can't deadlock
+    fn merge(&self, source: u16, target: u16) -> Result<()> {
+        let target_slot = self.slot(target);
+        // cloned out, as holding a map entry while locking an engine could deadlock
+        let source_slot = self.engines.get(&source).map(|slot| slot.clone());
+        let source_slot = match source_slot {
+            Some(slot) if !Arc::ptr_eq(&slot, &target_slot) => slot,
+            // the target's engine refuses an unknown source or a merge into itself
+            _ => {
+                let mut guard = lock(&target_slot);
+                return match guard.as_mut() {
+                    Some(engine) => engine.merge_accounts(source, target),
+                    None => {
+                        drop(guard);
+                        self.merge(source, target)
+                    }
+                };
+            }
+        };
+        let (mut source_guard, mut target_guard) =
+            if Arc::as_ptr(&source_slot) < Arc::as_ptr(&target_slot) {
+                let source_guard = lock(&source_slot);
+                (source_guard, lock(&target_slot))
+            } else {
+                let target_guard = lock(&target_slot);
+                (lock(&source_slot), target_guard)
+            };
+        let (Some(other), Some(engine)) = (source_guard.as_ref(), target_guard.as_mut()) else {
+            drop((source_guard, target_guard));
+            return self.merge(source, target);
+        };
+        engine.check_merge(other)?;
+        let other = source_guard.take().expect("checked above");
+        engine.merge_state(other)?;
+        // every client of the source's engine now lives in the target's
+        for mut slot in self.engines.iter_mut() {
+            if Arc::ptr_eq(&slot, &source_slot) {
+                *slot = target_slot.clone();
+            }
+        }
+
+        engine.merge_accounts(source, target)
+    }
+
+    // the slot of `client`'s engine, built on first sight
+    fn slot(&self, client: u16) -> Slot {
+        if let Some(slot) = self.engines.get(&client) {
+            return slot.clone();
+        }
+        self.engines
+            .entry(client)
+            .or_insert_with(|| {
+                let spare = lock(&self.spare).take();
+                Arc::new(Mutex::new(Some(spare.unwrap_or_else(&self.factory))))
+            })
+            .clone()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().expect("no engine panics holding the lock")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::amount;
+    use crate::amount::Amount;
+    use crate::async_engine::AsyncPaymentsEngine;
+    use crate::engine::PaymentsEngine;
+    use crate::error::Error;
+    use crate::testing::TxBuilder;
+    use crate::transaction::DEFAULT_CURRENCY;
+
+    async fn total(engine: &AsyncPaymentsEngine, client: u16) -> Amount {
+        engine
+            .account(client)
+            .await
+            .unwrap()
+            .unwrap()
+            .balance(DEFAULT_CURRENCY)
+            .total
+    }
+
+    #[tokio::test]
+    async fn test_spawn_sharded_success() {
+        let engine = AsyncPaymentsEngine::spawn_sharded(PaymentsEngine::new).unwrap();
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(10)).build(),
+            TxBuilder::deposit(2, 2, amount!(20)).build(),
+            TxBuilder::withdrawal(1, 3, amount!(4)).build(),
+        ] {
+            engine.process(tx).await.unwrap();
+        }
+        // a failed withdrawal leaves its tx id free
+        assert!(
+            engine
+                .process(TxBuilder::withdrawal(1, 4, amount!(50)).build())
+                .await
+                .is_err()
+        );
+
+        let result = engine
+            .process(TxBuilder::deposit(2, 1, amount!(5)).build())
+            .await;
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::DuplicateTransaction(1)
+        ));
+        engine
+            .process(TxBuilder::deposit(2, 4, amount!(5)).build())
+            .await
+            .unwrap();
+        assert_eq!(total(&engine, 1).await, amount!(6));
+        assert_eq!(total(&engine, 2).await, amount!(25));
+        assert_eq!(engine.accounts().await.unwrap().len(), 2);
+        assert!(engine.account(3).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_merge_accounts_across_shards() {
+        let engine = AsyncPaymentsEngine::spawn_sharded(PaymentsEngine::new).unwrap();
+        for tx in [
+            TxBuilder::deposit(1, 1, amount!(10)).build(),
+            TxBuilder::deposit(2, 2, amount!(20)).build(),
+            TxBuilder::deposit(3, 3, amount!(5)).build(),
+        ] {
+            engine.process(tx).await.unwrap();
+        }
+
+        engine.merge_accounts(2, 1).await.unwrap();
+        engine.merge_accounts(1, 3).await.unwrap();
+
+        assert_eq!(total(&engine, 3).await, amount!(35));
+        assert!(engine.account(1).await.unwrap().is_none());
+        assert_eq!(engine.accounts().await.unwrap().len(), 1);
+        // the sources' transactions went with them
+        engine
+            .process(TxBuilder::dispute(3, 2).build())
+            .await
+            .unwrap();
+        assert!(engine.merge_accounts(4, 3).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_spawn_sharded_concurrent_clients() {
+        let engine = AsyncPaymentsEngine::spawn_sharded(PaymentsEngine::new).unwrap();
+        let tasks: Vec<_> = (0..8u16)
+            .map(|client| {
+                let engine = engine.clone();
+                tokio::spawn(async move {
+                    for i in 0..100 {
+                        // every client tries every tx id, so only one of each is applied
+                        let _ = engine
+                            .process(TxBuilder::deposit(client, i, amount!(1)).build())
+                            .await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let mut total = Amount::default();
+        for account in engine.accounts().await.unwrap() {
+            total += account.balance(DEFAULT_CURRENCY).total;
+        }
+        assert_eq!(total, amount!(100));
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::mpsc::{self, SyncSender};
+
+use crate::{
+    account::Account,
+    engine::PaymentsEngine,
+    error::{Error, Result},
+    transaction::Transaction,
+};
+
+// bounded so fast submitters get backpressure instead of queueing without limit
+const COMMAND_BUFFER: usize = 1024;
+
+// a call to run on the engine's thread, replying through a channel of its own
+type Command = Box<dyn FnOnce(&mut PaymentsEngine) + Send>;
+
+/// Cloneable, thread-safe handle to a [`PaymentsEngine`] running on a thread of its own, for
+/// embedding in host applications without the `tokio` feature.
+///
+/// Every handle feeds the same engine, from any number of threads, and calls are applied one at a
+/// time in the order the engine's thread receives them, so none of them needs a lock around the
+/// whole engine. Each call blocks until it has been applied. The thread stops once all handles
+/// are dropped.
+///
+/// ```
+/// use payments_engine::{DEFAULT_CURRENCY, PaymentsEngine, SharedPaymentsEngine, amount};
+/// use payments_engine::testing::TxBuilder;
+///
+/// let engine = SharedPaymentsEngine::spawn(PaymentsEngine::new()).unwrap();
+/// let workers: Vec<_> = (1..=4)
+///     .map(|client| {
+///         let engine = engine.clone();
+///         std::thread::spawn(move || {
+///             engine.process(TxBuilder::deposit(client, u32::from(client), amount!(10)).build())
+///         })
+///     })
+///     .collect();
+/// for worker in workers {
+///     worker.join().unwrap().unwrap();
+/// }
+///
+/// assert_eq!(engine.accounts().unwrap().len(), 4);
+/// let balance = engine.account(2).unwrap().unwrap().balance(DEFAULT_CURRENCY);
+/// assert_eq!(balance.available, amount!(10));
+/// ```
+#[derive(Clone)]
+pub struct SharedPaymentsEngine {
+    commands: SyncSender<Command>,
+}
+
+impl SharedPaymentsEngine {
+    /// Moves `engine` onto a new thread. Fails with [`Error::Io`] if the thread can't be started.
+    pub fn spawn(engine: PaymentsEngine) -> Result<Self> {
+        let (commands, receiver) = mpsc::sync_channel::<Command>(COMMAND_BUFFER);
+        std::thread::Builder::new()
+            .name("payments-engine".to_string())
+            .spawn(move || {
+                let mut engine = engine;
+                for command in receiver {
+                    command(&mut engine);
+                }
+            })?;
+
+        Ok(Self { commands })
+    }
+
+    /// Applies a single transaction, with the same semantics as [`PaymentsEngine::process_tx`].
+    pub fn process(&self, tx: Transaction) -> Result<()> {
+        self.with(move |engine| engine.process_tx(&tx))?
+    }
+
+    /// Returns a copy of a client's account, if it has been seen.
+    pub fn account(&self, id: u16) -> Result<Option<Account>> {
+        self.with(move |engine| engine.account(id).cloned())
+    }
+
+    /// Returns a copy of all client accounts, in no particular order.
+    pub fn accounts(&self) -> Result<Vec<Account>> {
+        self.with(|engine| engine.accounts().cloned().collect())
+    }
+
+    /// Merges client `source` into `target`, with the same semantics as
+    /// [`PaymentsEngine::merge_accounts`].
+    pub fn merge_accounts(&self, source: u16, target: u16) -> Result<()> {
+        self.with(move |engine| engine.merge_accounts(source, target))?
+    }
+
+    /// Runs `f` on the engine, between the calls of other handles, and returns what it returns,
+    /// e.g. to read a client's [`history`](PaymentsEngine::history) or take a
+    /// [`snapshot`](PaymentsEngine::snapshot). Fails with [`Error::EngineError`] if the engine's
+    /// thread has stopped, as it does if an earlier `f` panicked.
+    pub fn with<R, F>(&self, f: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut PaymentsEngine) -> R + Send + 'static,
+    {
+        let (reply, response) = mpsc::sync_channel(1);
+        let command: Command = Box::new(move |engine| {
+            // a dropped reply just means the caller stopped waiting
+            let _ = reply.send(f(engine));
+        });
+        self.commands.send(command).map_err(|_| Self::stopped())?;
+
+        response.recv().map_err(|_| Self::stopped())
+    }
+
+    fn stopped() -> Error {
+        Error::EngineError("Engine thread has stopped.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::testing::TxBuilder;
+    use crate::transaction::DEFAULT_CURRENCY;
+
+    #[test]
+    fn test_process_from_many_threads() {
+        let engine = SharedPaymentsEngine::spawn(PaymentsEngine::new()).unwrap();
+
+        let workers: Vec<_> = (0..8u32)
+            .map(|worker| {
+                let engine = engine.clone();
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        let tx_id = worker * 100 + i;
+                        engine
+                            .process(TxBuilder::deposit(1, tx_id, amount!(1)).build())
+                            .unwrap();
+                        // queries interleave with the other threads' deposits
+                        assert!(engine.account(1).unwrap().is_some());
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let account = engine.account(1).unwrap().unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).available, amount!(800));
+    }
+
+    #[test]
+    fn test_process_failure_insufficient_funds() {
+        let engine = SharedPaymentsEngine::spawn(PaymentsEngine::new()).unwrap();
+
+        let result = engine.process(TxBuilder::withdrawal(1, 1, amount!(10)).build());
+
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::InsufficientFunds(_)
+        ));
+    }
+
+    #[test]
+    fn test_with_failure_after_panic() {
+        let engine = SharedPaymentsEngine::spawn(PaymentsEngine::new()).unwrap();
+
+        assert!(engine.with(|_| -> u8 { panic!("query failed") }).is_err());
+
+        assert!(matches!(engine.accounts(), Err(Error::EngineError(_))));
+    }
+}
@@ -0,0 +1,172 @@
+use std::fs;
+use std::path::Path;
+
+use csv::StringRecord;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use payments_engine::{Error, Result};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SIGNATURE_COLUMN: &str = "signature";
+
+// verifies the per-row HMAC-SHA256 signature over the row's other fields (trimmed), each
+// preceded by its length in bytes as a big-endian u64, so no two rows sign the same bytes
+pub struct RowVerifier {
+    key: Vec<u8>,
+}
+
+impl RowVerifier {
+    pub fn new(key: Vec<u8>) -> Self {
+        Self { key }
+    }
+
+    // the shared key is read from a file rather than the command line so it stays out of the
+    // process list and shell history--a single trailing newline is ignored
+    pub fn from_key_file(path: &Path) -> Result<Self> {
+        let mut key = fs::read(path)?;
+        if key.ends_with(b"\n") {
+            key.pop();
+            if key.ends_with(b"\r") {
+                key.pop();
+            }
+        }
+        if key.is_empty() {
+            return Err(Error::SignatureError("HMAC key file is empty."));
+        }
+
+        Ok(Self::new(key))
+    }
+
+    pub fn verify(&self, record: &StringRecord, signature_idx: usize) -> Result<()> {
+        let signature = record
+            .get(signature_idx)
+            .filter(|signature| !signature.is_empty())
+            .ok_or(Error::SignatureError("Row signature is missing."))?;
+        let signature = hex::decode(signature)
+            .map_err(|_| Error::SignatureError("Row signature is not valid hex."))?;
+
+        self.mac_for(record, signature_idx)
+            .verify_slice(&signature)
+            .map_err(|_| Error::SignatureError("Row signature does not match row contents."))
+    }
+
+    fn mac_for(&self, record: &StringRecord, signature_idx: usize) -> HmacSha256 {
+        // HMAC accepts keys of any length, so this can't fail
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        let fields = record
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| *idx != signature_idx)
+            .map(|(_, field)| field);
+        for field in fields {
+            mac.update(&(field.len() as u64).to_be_bytes());
+            mac.update(field.as_bytes());
+        }
+
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(verifier: &RowVerifier, record: &StringRecord, signature_idx: usize) -> String {
+        hex::encode(
+            verifier
+                .mac_for(record, signature_idx)
+                .finalize()
+                .into_bytes(),
+        )
+    }
+
+    fn signed_record(verifier: &RowVerifier, fields: &[&str]) -> StringRecord {
+        let mut record = StringRecord::from(fields.to_vec());
+        record.push_field("");
+        let signature = sign(verifier, &record, fields.len());
+
+        let mut fields = fields.to_vec();
+        fields.push(&signature);
+        StringRecord::from(fields)
+    }
+
+    #[test]
+    fn test_verify_success() {
+        let verifier = RowVerifier::new(b"secret".to_vec());
+        let record = signed_record(&verifier, &["deposit", "1", "1", "100"]);
+
+        assert!(verifier.verify(&record, 4).is_ok());
+    }
+
+    #[test]
+    fn test_verify_signs_length_prefixed_fields() {
+        let verifier = RowVerifier::new(b"secret".to_vec());
+        let record = StringRecord::from(vec!["dispute", "1", "1", ""]);
+        let mut expected = HmacSha256::new_from_slice(b"secret").unwrap();
+        expected.update(b"\0\0\0\0\0\0\0\x07dispute");
+        expected.update(b"\0\0\0\0\0\0\0\x011");
+        expected.update(b"\0\0\0\0\0\0\0\x011");
+        expected.update(b"\0\0\0\0\0\0\0\0");
+
+        assert_eq!(
+            sign(&verifier, &record, 4),
+            hex::encode(expected.finalize().into_bytes())
+        );
+    }
+
+    #[test]
+    fn test_verify_failure_fields_shifted_across_a_comma() {
+        let verifier = RowVerifier::new(b"secret".to_vec());
+        // both would sign `deposit,1,1,10,USD,` with the fields joined by commas
+        let signed = signed_record(&verifier, &["deposit", "1", "1", "10,USD", ""]);
+        let mut fields: Vec<&str> = vec!["deposit", "1", "1,10", "USD", ""];
+        fields.push(signed.get(5).unwrap());
+        let record = StringRecord::from(fields);
+
+        assert!(verifier.verify(&signed, 5).is_ok());
+        assert!(matches!(
+            verifier.verify(&record, 5),
+            Err(Error::SignatureError(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_failure_tampered_row() {
+        let verifier = RowVerifier::new(b"secret".to_vec());
+        let mut fields: Vec<String> = signed_record(&verifier, &["deposit", "1", "1", "100"])
+            .iter()
+            .map(String::from)
+            .collect();
+        fields[3] = "1000".to_string();
+        let record = StringRecord::from(fields);
+
+        assert!(verifier.verify(&record, 4).is_err());
+    }
+
+    #[test]
+    fn test_verify_failure_wrong_key() {
+        let signer = RowVerifier::new(b"secret".to_vec());
+        let verifier = RowVerifier::new(b"other".to_vec());
+        let record = signed_record(&signer, &["deposit", "1", "1", "100"]);
+
+        assert!(verifier.verify(&record, 4).is_err());
+    }
+
+    #[test]
+    fn test_verify_failure_missing_signature() {
+        let verifier = RowVerifier::new(b"secret".to_vec());
+        let record = StringRecord::from(vec!["deposit", "1", "1", "100", ""]);
+
+        assert!(verifier.verify(&record, 4).is_err());
+    }
+
+    #[test]
+    fn test_verify_failure_invalid_hex() {
+        let verifier = RowVerifier::new(b"secret".to_vec());
+        let record = StringRecord::from(vec!["deposit", "1", "1", "100", "zz"]);
+
+        assert!(verifier.verify(&record, 4).is_err());
+    }
+}
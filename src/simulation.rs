@@ -0,0 +1,284 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
+use std::sync::Mutex;
+
+use crate::{
+    account::Account,
+    actors::{self, Claim},
+    engine::{DuplicatePolicy, PaymentsEngine},
+    error::{Error, ErrorCode, Result},
+    hash::HashMap,
+    transaction::Transaction,
+};
+
+/// Seeded, single-threaded model of how
+/// [`AsyncPaymentsEngine::spawn_per_account`](crate::AsyncPaymentsEngine::spawn_per_account)
+/// schedules transactions, for reproducing races between clients' engines.
+///
+/// Each transaction is routed to its client's engine, claiming its tx id, then applied by that
+/// engine in the order it was routed there, through the same steps the actors take. A run lets
+/// different clients' transactions be routed and applied in an order picked from its seed, while
+/// each client's own are routed in the order given, as if every client had a submitter of its
+/// own. The same seed always picks the same order.
+///
+/// [`explore`](Self::explore) looks for a seed whose outcome differs from applying the
+/// transactions one at a time, in the order given:
+///
+/// ```
+/// use payments_engine::{ErrorCode, PaymentsEngine, Simulation, amount};
+/// use payments_engine::testing::TxBuilder;
+///
+/// let simulation = Simulation::new(PaymentsEngine::new).unwrap();
+/// let txs = [
+///     // fails for lack of funds, giving tx id 5 back...
+///     TxBuilder::withdrawal(1, 5, amount!(50)).build(),
+///     // ...but client 2's deposit is refused if it's routed before that
+///     TxBuilder::deposit(2, 5, amount!(10)).build(),
+/// ];
+///
+/// let race = simulation.explore(&txs, 0..100).unwrap();
+///
+/// assert!(race.results.contains(&Err(ErrorCode::DuplicateTransaction)));
+/// assert_eq!(simulation.run(race.seed, &txs), race);
+/// ```
+pub struct Simulation {
+    factory: Box<dyn Fn() -> PaymentsEngine>,
+    duplicate_policy: DuplicatePolicy,
+}
+
+/// What one [`Simulation`] run made of the transactions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationRun {
+    /// The seed the run's order was picked from.
+    pub seed: u64,
+    /// Each transaction's outcome, in the order given.
+    pub results: Vec<std::result::Result<(), ErrorCode>>,
+    /// Every client's account afterwards, by client id.
+    pub accounts: Vec<Account>,
+    /// The order the engines applied the transactions in, as indexes into the ones given.
+    /// Transactions refused while being routed were never applied and aren't listed.
+    pub schedule: Vec<usize>,
+}
+
+// the next step a run takes
+enum Step {
+    // route a client's next transaction to its engine
+    Route(u16),
+    // apply the next transaction routed to an engine, by index
+    Apply(usize),
+}
+
+impl Simulation {
+    /// Simulates engines built by `factory`, one per client. Fails with [`Error::ConfigError`]
+    /// for engines with a fee schedule, as `spawn_per_account` does.
+    pub fn new<F>(factory: F) -> Result<Self>
+    where
+        F: Fn() -> PaymentsEngine + 'static,
+    {
+        let engine = factory();
+        if engine.charges_fees() {
+            return Err(Error::ConfigError(
+                "an engine per account can't charge fees".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            factory: Box::new(factory),
+            duplicate_policy: engine.duplicate_policy(),
+        })
+    }
+
+    /// Routes and applies `txs` in the order picked from `seed`.
+    pub fn run(&self, seed: u64, txs: &[Transaction]) -> SimulationRun {
+        self.simulate(seed, Some(Rng(seed)), txs)
+    }
+
+    /// Runs `txs` with every seed in `seeds`, returning the first run whose results or accounts
+    /// differ from routing and applying each transaction in turn, if any does.
+    pub fn explore(&self, txs: &[Transaction], seeds: Range<u64>) -> Option<SimulationRun> {
+        let expected = self.simulate(0, None, txs);
+        seeds
+            .map(|seed| self.run(seed, txs))
+            .find(|run| run.results != expected.results || run.accounts != expected.accounts)
+    }
+
+    // without a generator, each transaction is applied before the next is routed
+    fn simulate(&self, seed: u64, mut rng: Option<Rng>, txs: &[Transaction]) -> SimulationRun {
+        let owners = Mutex::new(HashMap::default());
+        let mut clients = HashMap::default();
+        let mut engines = Vec::new();
+        // each engine's routed transactions, by index, with whether they claimed their tx id
+        let mut mailboxes: Vec<VecDeque<(usize, bool)>> = Vec::new();
+        // each client's transactions not yet routed, by index; ordered so runs are repeatable
+        let mut unrouted: BTreeMap<u16, VecDeque<usize>> = BTreeMap::new();
+        for (i, tx) in txs.iter().enumerate() {
+            unrouted.entry(tx.account_id).or_default().push_back(i);
+        }
+        let mut results = vec![Ok(()); txs.len()];
+        let mut schedule = Vec::new();
+
+        loop {
+            let mut steps: Vec<Step> = mailboxes
+                .iter()
+                .enumerate()
+                .filter(|(_, mailbox)| !mailbox.is_empty())
+                .map(|(actor, _)| Step::Apply(actor))
+                .collect();
+            steps.extend(
+                unrouted
+                    .iter()
+                    .filter(|(_, pending)| !pending.is_empty())
+                    .map(|(&client, _)| Step::Route(client)),
+            );
+            if steps.is_empty() {
+                break;
+            }
+            let step = match &mut rng {
+                Some(rng) => steps.swap_remove(rng.below(steps.len())),
+                // at most one engine has anything routed, and it comes first
+                None => match steps.swap_remove(0) {
+                    Step::Apply(actor) => Step::Apply(actor),
+                    Step::Route(_) => {
+                        let (&client, _) = unrouted
+                            .iter()
+                            .filter_map(|(client, pending)| Some((client, pending.front()?)))
+                            .min_by_key(|&(_, &i)| i)
+                            .expect("a client has transactions left to route");
+                        Step::Route(client)
+                    }
+                },
+            };
+
+            match step {
+                Step::Route(client) => {
+                    let i = unrouted
+                        .get_mut(&client)
+                        .and_then(VecDeque::pop_front)
+                        .expect("only routed with transactions left");
+                    let actor = *clients.entry(client).or_insert_with(|| {
+                        engines.push((self.factory)());
+                        mailboxes.push(VecDeque::new());
+                        engines.len() - 1
+                    });
+                    match actors::claim(&owners, &clients, &txs[i]) {
+                        Claim::Claimed => mailboxes[actor].push_back((i, true)),
+                        Claim::Unclaimed => mailboxes[actor].push_back((i, false)),
+                        Claim::Taken => {
+                            results[i] =
+                                actors::taken(self.duplicate_policy, &txs[i]).map_err(|e| e.code());
+                        }
+                    }
+                }
+                Step::Apply(actor) => {
+                    let (i, claimed) = mailboxes[actor]
+                        .pop_front()
+                        .expect("only applied with transactions routed");
+                    results[i] = actors::apply(&mut engines[actor], &txs[i], claimed, &owners)
+                        .map_err(|e| e.code());
+                    schedule.push(i);
+                }
+            }
+        }
+
+        let mut accounts: Vec<Account> = engines
+            .iter()
+            .flat_map(|engine| engine.accounts().cloned())
+            .collect();
+        accounts.sort_by_key(|account| account.id);
+        SimulationRun {
+            seed,
+            results,
+            accounts,
+            schedule,
+        }
+    }
+}
+
+// splitmix64: small, and the same sequence from a seed on every platform
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        ((z ^ (z >> 31)) % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::testing::TxBuilder;
+    use crate::transaction::DEFAULT_CURRENCY;
+
+    #[test]
+    fn test_run_is_repeatable() {
+        let simulation = Simulation::new(PaymentsEngine::new).unwrap();
+        let txs: Vec<_> = (1..=30)
+            .map(|tx| TxBuilder::deposit((tx % 3 + 1) as u16, tx, amount!(1)).build())
+            .collect();
+
+        let runs: Vec<_> = (0..20).map(|seed| simulation.run(seed, &txs)).collect();
+
+        for run in &runs {
+            assert_eq!(simulation.run(run.seed, &txs), *run);
+            assert_eq!(run.accounts.len(), 3);
+            assert_eq!(run.accounts[0].balance(DEFAULT_CURRENCY).total, amount!(10));
+        }
+        // seeds pick different orders
+        assert!(runs.iter().any(|run| run.schedule != runs[0].schedule));
+    }
+
+    #[test]
+    fn test_explore_without_races() {
+        let simulation = Simulation::new(PaymentsEngine::new).unwrap();
+        let txs = [
+            TxBuilder::deposit(1, 1, amount!(10)).build(),
+            TxBuilder::deposit(2, 2, amount!(20)).build(),
+            TxBuilder::withdrawal(1, 3, amount!(4)).build(),
+            TxBuilder::dispute(2, 2).build(),
+            TxBuilder::withdrawal(1, 4, amount!(50)).build(),
+        ];
+
+        assert_eq!(simulation.explore(&txs, 0..200), None);
+    }
+
+    #[test]
+    fn test_explore_finds_released_tx_id_race() {
+        let simulation = Simulation::new(PaymentsEngine::new).unwrap();
+        let txs = [
+            TxBuilder::withdrawal(1, 5, amount!(50)).build(),
+            TxBuilder::deposit(2, 5, amount!(10)).build(),
+        ];
+
+        let race = simulation.explore(&txs, 0..100).unwrap();
+
+        // whichever client claims the id first, the other is refused before it's given back
+        let refused = match race.results[..] {
+            [
+                Err(ErrorCode::InsufficientFunds),
+                Err(ErrorCode::DuplicateTransaction),
+            ] => 1,
+            [Err(ErrorCode::DuplicateTransaction), Ok(())] => 0,
+            _ => panic!("unexpected results {:?}", race.results),
+        };
+        assert!(!race.schedule.contains(&refused));
+        assert_eq!(simulation.run(race.seed, &txs), race);
+    }
+
+    #[test]
+    fn test_new_failure_fee_schedule() {
+        let schedule = crate::FeeSchedule::from_toml("fee_account = 999\n").unwrap();
+
+        let result = Simulation::new(move || {
+            PaymentsEngine::builder()
+                .fee_schedule(schedule.clone())
+                .build()
+        });
+
+        assert!(matches!(result.err().unwrap(), Error::ConfigError(_)));
+    }
+}
@@ -0,0 +1,331 @@
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize, Serializer, ser::SerializeMap};
+
+use crate::{
+    account::Account,
+    error::{Error, Result},
+    hash::HashMap,
+    ledger::Ledger,
+    limits::RecentWithdrawals,
+    risk::Activity,
+    store::TxStore,
+    transaction::TxRecord,
+};
+
+// bump whenever the persisted layout changes so old snapshots are refused rather than misread
+pub(crate) const SNAPSHOT_VERSION: u32 = 13;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    accounts: &'a HashMap<u16, Account>,
+    transactions: StoreRef<'a>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    evicted: Vec<(u32, u32)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interest_accrued_to: Option<u64>,
+    ledger: &'a Ledger,
+    // what withdrawal limits and risk rules look back on, if configured
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    recent_withdrawals: Vec<RecentWithdrawals>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    risk_activity: Option<&'a HashMap<u16, Activity>>,
+}
+
+// streams the store's records as a JSON object, so a disk store isn't loaded into memory first
+struct StoreRef<'a>(&'a TxStore);
+
+impl Serialize for StoreRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        for record in self.0.records() {
+            let (tx_id, tx_info) = record.map_err(serde::ser::Error::custom)?;
+            map.serialize_entry(&tx_id, &tx_info)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    version: u32,
+    accounts: HashMap<u16, Account>,
+    transactions: HashMap<u32, TxRecord>,
+    #[serde(default)]
+    evicted: Vec<(u32, u32)>,
+    #[serde(default)]
+    interest_accrued_to: Option<u64>,
+    #[serde(default)]
+    ledger: Ledger,
+    #[serde(default)]
+    recent_withdrawals: Vec<RecentWithdrawals>,
+    #[serde(default)]
+    risk_activity: HashMap<u16, Activity>,
+}
+
+// what a snapshot restores
+pub(crate) struct Restored {
+    pub(crate) accounts: HashMap<u16, Account>,
+    pub(crate) transactions: HashMap<u32, TxRecord>,
+    // the ids of records evicted from the store, which still count as applied, as `(first, last)`
+    // runs of consecutive ids
+    pub(crate) evicted: Vec<(u32, u32)>,
+    pub(crate) interest_accrued_to: Option<u64>,
+    pub(crate) ledger: Ledger,
+    pub(crate) recent_withdrawals: Vec<RecentWithdrawals>,
+    pub(crate) risk_activity: HashMap<u16, Activity>,
+}
+
+pub(crate) fn write<W: Write>(
+    writer: W,
+    accounts: &HashMap<u16, Account>,
+    transactions: &TxStore,
+    interest_accrued_to: Option<u64>,
+    ledger: &Ledger,
+    recent_withdrawals: Vec<RecentWithdrawals>,
+    risk_activity: Option<&HashMap<u16, Activity>>,
+) -> Result<()> {
+    let snapshot = SnapshotRef {
+        version: SNAPSHOT_VERSION,
+        accounts,
+        transactions: StoreRef(transactions),
+        evicted: transactions.evicted(),
+        interest_accrued_to,
+        ledger,
+        recent_withdrawals,
+        risk_activity,
+    };
+
+    serde_json::to_writer(writer, &snapshot)
+        .map_err(|e| Error::SnapshotError(format!("failed to write snapshot: {}", e)))
+}
+
+pub(crate) fn read<R: Read>(reader: R) -> Result<Restored> {
+    let snapshot: Snapshot = serde_json::from_reader(reader)
+        .map_err(|e| Error::SnapshotError(format!("failed to read snapshot: {}", e)))?;
+
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(Error::SnapshotError(format!(
+            "unsupported snapshot version {} (expected {})",
+            snapshot.version, SNAPSHOT_VERSION
+        )));
+    }
+    validate(&snapshot)?;
+
+    Ok(Restored {
+        accounts: snapshot.accounts,
+        transactions: snapshot.transactions,
+        evicted: snapshot.evicted,
+        interest_accrued_to: snapshot.interest_accrued_to,
+        ledger: snapshot.ledger,
+        recent_withdrawals: snapshot.recent_withdrawals,
+        risk_activity: snapshot.risk_activity,
+    })
+}
+
+// refuse snapshots that couldn't have been produced by the engine
+fn validate(snapshot: &Snapshot) -> Result<()> {
+    for (id, account) in &snapshot.accounts {
+        if *id != account.id {
+            return Err(Error::SnapshotError(format!(
+                "account stored under client {} has id {}",
+                id, account.id
+            )));
+        }
+        for (currency, balance) in &account.balances {
+            if balance.available + balance.held != balance.total {
+                return Err(Error::SnapshotError(format!(
+                    "client {} {:?} total does not equal available + held",
+                    id, currency
+                )));
+            }
+        }
+    }
+    for (tx_id, tx_info) in &snapshot.transactions {
+        if !snapshot.accounts.contains_key(&tx_info.account_id) {
+            return Err(Error::SnapshotError(format!(
+                "tx {} references unknown client {}",
+                tx_id, tx_info.account_id
+            )));
+        }
+    }
+    if let Some((first, last)) = snapshot.evicted.iter().find(|(first, last)| first > last) {
+        return Err(Error::SnapshotError(format!(
+            "evicted ids {}..={} are out of order",
+            first, last
+        )));
+    }
+    if let Some(tx_id) = snapshot.transactions.keys().find(|tx_id| {
+        snapshot
+            .evicted
+            .iter()
+            .any(|(first, last)| (first..=last).contains(tx_id))
+    }) {
+        return Err(Error::SnapshotError(format!(
+            "tx {} is both stored and evicted",
+            tx_id
+        )));
+    }
+    snapshot
+        .ledger
+        .check(snapshot.accounts.values())
+        .map_err(|_| Error::SnapshotError("ledger does not balance".to_string()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::amount;
+    use crate::testing::TxBuilder;
+    use crate::{
+        DEFAULT_CURRENCY, Error, PaymentsEngine, RiskRules, TransactionType, WithdrawalLimits,
+    };
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut engine = PaymentsEngine::new();
+        engine
+            .process_tx(&TxBuilder::deposit(1, 1, amount!(100.1234)).build())
+            .unwrap();
+        engine
+            .process_tx(&TxBuilder::dispute(1, 1).build())
+            .unwrap();
+        let mut buf = Vec::new();
+        engine.snapshot(&mut buf).unwrap();
+
+        let mut restored = PaymentsEngine::restore(buf.as_slice()).unwrap();
+
+        assert_eq!(restored.ledger(), engine.ledger());
+        let account = restored.account(1).unwrap();
+        assert_eq!(account.balance(DEFAULT_CURRENCY).held, amount!(100.1234));
+        assert_eq!(account.balance(DEFAULT_CURRENCY).total, amount!(100.1234));
+        // dispute state survives, so the restored engine can resolve the open dispute
+        restored
+            .process_tx(&TxBuilder::resolve(1, 1).build())
+            .unwrap();
+        assert_eq!(
+            restored
+                .account(1)
+                .unwrap()
+                .balance(DEFAULT_CURRENCY)
+                .available,
+            amount!(100.1234)
+        );
+        // and stored tx ids still count as applied
+        assert!(
+            restored
+                .process_tx(&TxBuilder::deposit(1, 1, amount!(1)).build())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_snapshot_keeps_recent_activity() {
+        let builder = || {
+            PaymentsEngine::builder()
+                .withdrawal_limits(WithdrawalLimits::from_toml("daily = \"100\"").unwrap())
+                .risk_rules(
+                    RiskRules::from_toml(
+                        "[[rules]]\nrule = \"withdrawal_count\"\nmax = 1\nwindow = 100\n\
+                         action = \"reject\"",
+                    )
+                    .unwrap(),
+                )
+        };
+        let timed = |tx_type, tx_id, amount, timestamp| {
+            TxBuilder::new(tx_type, 1, tx_id)
+                .amount(amount)
+                .timestamp(timestamp)
+                .build()
+        };
+        let mut engine = builder().build();
+        engine
+            .process_tx(&timed(TransactionType::Deposit, 1, amount!(200), 0))
+            .unwrap();
+        engine
+            .process_tx(&timed(TransactionType::Withdrawal, 2, amount!(60), 10))
+            .unwrap();
+        let mut buf = Vec::new();
+        engine.snapshot(&mut buf).unwrap();
+
+        let mut restored = builder().restore(buf.as_slice()).unwrap();
+
+        // the withdrawal before the snapshot still counts towards both
+        let result = restored.process_tx(&timed(TransactionType::Withdrawal, 3, amount!(30), 20));
+        assert!(matches!(result.unwrap_err().root(), Error::RiskRejected(_)));
+        let result = restored.process_tx(&timed(TransactionType::Withdrawal, 4, amount!(50), 200));
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::LimitExceeded(_)
+        ));
+        // an engine without them ignores them
+        assert!(PaymentsEngine::restore(buf.as_slice()).is_ok());
+    }
+
+    #[test]
+    fn test_restore_failure_unsupported_version() {
+        let input = r#"{"version":999,"accounts":{},"transactions":{}}"#;
+
+        let result = PaymentsEngine::restore(input.as_bytes());
+
+        assert!(
+            result
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("unsupported snapshot version")
+        );
+    }
+
+    #[test]
+    fn test_restore_failure_inconsistent_totals() {
+        let input = r#"{"version":13,"accounts":{"1":{"id":1,"balances":{"":{"available":"1","held":"1","total":"5"}},"status":"active"}},"transactions":{}}"#;
+
+        let result = PaymentsEngine::restore(input.as_bytes());
+
+        assert!(
+            result
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("total does not equal")
+        );
+    }
+
+    #[test]
+    fn test_restore_failure_unknown_client_reference() {
+        let input = r#"{"version":13,"accounts":{},"transactions":{"1":{"tx_type":"deposit","account_id":7,"currency":"","dispute_status":"Undisputed","amount":"1","disputable":"1","disputed":"0","refunded":"0"}}}"#;
+
+        let result = PaymentsEngine::restore(input.as_bytes());
+
+        assert!(
+            result
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("unknown client 7")
+        );
+    }
+
+    #[test]
+    fn test_restore_failure_stored_and_evicted() {
+        let input = r#"{"version":13,"accounts":{"1":{"id":1,"balances":{},"status":"active"}},"transactions":{"5":{"tx_type":"deposit","account_id":1,"currency":"","dispute_status":"Undisputed","amount":"1","disputable":"1","disputed":"0","refunded":"0"}},"evicted":[[1,3],[4,9]]}"#;
+
+        let result = PaymentsEngine::restore(input.as_bytes());
+
+        assert!(
+            result
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("tx 5 is both stored and evicted")
+        );
+    }
+
+    #[test]
+    fn test_restore_failure_malformed() {
+        assert!(PaymentsEngine::restore("not json".as_bytes()).is_err());
+    }
+}
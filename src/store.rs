@@ -0,0 +1,760 @@
+#[cfg(feature = "disk-store")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "disk-store")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use std::collections::BTreeMap;
+
+use crate::amount::{AMOUNT_DP, Amount};
+#[cfg(feature = "disk-store")]
+use crate::bloom::TxFilter;
+#[cfg(not(feature = "fixed-point"))]
+use rust_decimal::Decimal;
+
+use crate::{
+    error::{Error, Result},
+    hash::HashMap,
+    transaction::{DisputeStatus, TransactionType, TxRecord},
+};
+
+// sled's page cache--the disk store's memory use stays around this no matter the input size
+#[cfg(feature = "disk-store")]
+const DISK_CACHE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Where the engine keeps the transactions that later disputes, resolves, chargebacks and clears
+/// reference.
+///
+/// The default keeps them in memory. With the `disk-store` feature, [`TxStore::disk`] keeps them
+/// in an on-disk database instead, so memory stays bounded on very large inputs.
+pub struct TxStore {
+    backend: Backend,
+    // the ids of evicted records, still counted as stored so they're caught as duplicates
+    evicted: IdRanges,
+}
+
+enum Backend {
+    Memory {
+        records: HashMap<u32, PackedTx>,
+        // the distinct currencies seen, which records refer to by index
+        currencies: Vec<String>,
+        // each currency's index in `currencies`
+        currency_ids: HashMap<String, u16>,
+        // the records with an amount that doesn't pack, kept whole
+        wide: HashMap<u32, TxRecord>,
+    },
+    #[cfg(feature = "disk-store")]
+    Disk {
+        db: sled::Db,
+        // the ids in `db`, so lookups of ids that aren't there (the duplicate check of every new
+        // deposit or withdrawal and, in dispute-light inputs, most dispute lookups) can skip it
+        filter: TxFilter,
+        // only held to be dropped after `db`, so the database is closed before its directory goes
+        _scratch: ScratchDir,
+    },
+}
+
+// a directory the disk store created for itself, removed with everything in it on drop. Only
+// ever one it made, never the one it was given, which may hold anything
+#[cfg(feature = "disk-store")]
+struct ScratchDir(PathBuf);
+
+#[cfg(feature = "disk-store")]
+impl ScratchDir {
+    // a new, empty directory under `parent`, which is created if missing
+    fn create(parent: &Path) -> Result<Self> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        std::fs::create_dir_all(parent)?;
+        let path = parent.join(format!(
+            "payments-engine-tx-store-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        // fails rather than taking over a directory that's already there
+        std::fs::create_dir(&path)?;
+
+        Ok(Self(path))
+    }
+}
+
+#[cfg(feature = "disk-store")]
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.0) {
+            tracing::warn!(dir = %self.0.display(), error = %e, "couldn't remove the tx store");
+        }
+    }
+}
+
+// a record as kept in memory: its amounts as counts of 1/10,000ths, its currency interned and the
+// rest in a bit field
+#[derive(Clone, Copy)]
+struct PackedTx {
+    amount: i64,
+    disputable: i64,
+    disputed: i64,
+    refunded: i64,
+    // `NO_TIMESTAMP` when unknown, sparing the 8 bytes an `Option` would add
+    timestamp: u64,
+    account_id: u16,
+    currency: u16,
+    // the tx type in bits 0-3, the dispute status in bits 4-6 and the scale each amount was
+    // written with in 3 bits apiece from bit 7, or `WIDE` for a record kept whole instead
+    bits: u32,
+}
+
+const NO_TIMESTAMP: u64 = u64::MAX;
+const WIDE: u32 = 1 << 31;
+
+// in the order they're declared, so a variant's discriminant is its index
+const TX_TYPES: [TransactionType; 16] = [
+    TransactionType::Adjustment,
+    TransactionType::Authorize,
+    TransactionType::Capture,
+    TransactionType::Chargeback,
+    TransactionType::Clear,
+    TransactionType::Close,
+    TransactionType::Deposit,
+    TransactionType::Dispute,
+    TransactionType::Freeze,
+    TransactionType::Provisional,
+    TransactionType::Refund,
+    TransactionType::Resolve,
+    TransactionType::Reversal,
+    TransactionType::Unlock,
+    TransactionType::Void,
+    TransactionType::Withdrawal,
+];
+const DISPUTE_STATUSES: [DisputeStatus; 5] = [
+    DisputeStatus::Undisputed,
+    DisputeStatus::Disputed,
+    DisputeStatus::Resolved,
+    DisputeStatus::ChargedBack,
+    DisputeStatus::Reversed,
+];
+
+// the same on every platform and with either amount type, well under half of a `TxRecord`'s 112
+// bytes on 64-bit platforms
+const _: () = assert!(size_of::<PackedTx>() == 48);
+
+impl Default for TxStore {
+    fn default() -> Self {
+        Self::memory()
+    }
+}
+
+impl TxStore {
+    /// Keeps stored transactions in memory.
+    pub fn memory() -> Self {
+        Self {
+            backend: Backend::Memory {
+                records: HashMap::default(),
+                currencies: Vec::new(),
+                currency_ids: HashMap::default(),
+                wide: HashMap::default(),
+            },
+            evicted: IdRanges::default(),
+        }
+    }
+
+    /// Keeps stored transactions in a scratch database in a new subdirectory of `dir`, which is
+    /// deleted again when the store is dropped. `dir` itself, and anything else in it, is left
+    /// alone.
+    #[cfg(feature = "disk-store")]
+    pub fn disk(dir: &Path) -> Result<Self> {
+        let scratch = ScratchDir::create(dir)?;
+        let db = sled::Config::new()
+            .path(&scratch.0)
+            .cache_capacity(DISK_CACHE_BYTES)
+            .open()
+            .map_err(store_error)?;
+
+        Ok(Self {
+            backend: Backend::Disk {
+                db,
+                filter: TxFilter::default(),
+                _scratch: scratch,
+            },
+            evicted: IdRanges::default(),
+        })
+    }
+
+    pub(crate) fn contains(&self, tx_id: u32) -> Result<bool> {
+        if self.evicted.contains(tx_id) {
+            return Ok(true);
+        }
+        match &self.backend {
+            Backend::Memory { records, .. } => Ok(records.contains_key(&tx_id)),
+            #[cfg(feature = "disk-store")]
+            Backend::Disk { db, filter, .. } => Ok(filter.may_contain(tx_id)
+                && db.contains_key(tx_id.to_be_bytes()).map_err(store_error)?),
+        }
+    }
+
+    pub(crate) fn get(&self, tx_id: u32) -> Result<Option<TxRecord>> {
+        match &self.backend {
+            Backend::Memory {
+                records,
+                currencies,
+                wide,
+                ..
+            } => Ok(records
+                .get(&tx_id)
+                .map(|packed| unpack(tx_id, packed, currencies, wide))),
+            #[cfg(feature = "disk-store")]
+            Backend::Disk { filter, .. } if !filter.may_contain(tx_id) => Ok(None),
+            #[cfg(feature = "disk-store")]
+            Backend::Disk { db, .. } => db
+                .get(tx_id.to_be_bytes())
+                .map_err(store_error)?
+                .map(|value| decode(&value))
+                .transpose(),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, tx_id: u32, record: TxRecord) -> Result<()> {
+        match &mut self.backend {
+            Backend::Memory {
+                records,
+                currencies,
+                currency_ids,
+                wide,
+            } => {
+                let packed = pack(&record, currencies, currency_ids)?;
+                match packed.bits & WIDE {
+                    0 => wide.remove(&tx_id),
+                    _ => wide.insert(tx_id, record),
+                };
+                records.insert(tx_id, packed);
+                Ok(())
+            }
+            #[cfg(feature = "disk-store")]
+            Backend::Disk { db, filter, .. } => {
+                db.insert(tx_id.to_be_bytes(), encode(&record)?)
+                    .map_err(store_error)?;
+                // records are rewritten as their dispute state changes; count each id once
+                if !filter.may_contain(tx_id) {
+                    filter.insert(tx_id);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // drop the record of `tx_id` for good, keeping only its id
+    pub(crate) fn evict(&mut self, tx_id: u32) -> Result<()> {
+        match &mut self.backend {
+            Backend::Memory { records, wide, .. } => {
+                records.remove(&tx_id);
+                wide.remove(&tx_id);
+            }
+            #[cfg(feature = "disk-store")]
+            Backend::Disk { db, .. } => {
+                db.remove(tx_id.to_be_bytes()).map_err(store_error)?;
+            }
+        }
+        self.evicted.insert(tx_id, tx_id);
+
+        Ok(())
+    }
+
+    // count the ids `first..=last` as evicted without touching the records, for ids that were
+    // never stored here (restored or merged in)
+    pub(crate) fn mark_evicted(&mut self, first: u32, last: u32) {
+        self.evicted.insert(first, last);
+    }
+
+    // the lowest id in `first..=last` that is stored or evicted, if any
+    pub(crate) fn first_in(&self, first: u32, last: u32) -> Result<Option<u32>> {
+        let evicted = self
+            .evicted
+            .0
+            .range(..=last)
+            .next_back()
+            .filter(|&(_, &end)| end >= first)
+            .map(|(&start, _)| start.max(first));
+        let stored = match &self.backend {
+            // whichever of the ids and the records is fewer to go through
+            Backend::Memory { records, .. } if ((last - first) as usize) < records.len() => {
+                (first..=last).find(|tx_id| records.contains_key(tx_id))
+            }
+            Backend::Memory { records, .. } => records
+                .keys()
+                .copied()
+                .filter(|tx_id| (first..=last).contains(tx_id))
+                .min(),
+            #[cfg(feature = "disk-store")]
+            Backend::Disk { db, .. } => db
+                .range(first.to_be_bytes()..=last.to_be_bytes())
+                .next()
+                .transpose()
+                .map_err(store_error)?
+                .map(|(key, _)| tx_id_of(&key))
+                .transpose()?,
+        };
+
+        Ok(evicted.into_iter().chain(stored).min())
+    }
+
+    pub(crate) fn is_evicted(&self, tx_id: u32) -> bool {
+        self.evicted.contains(tx_id)
+    }
+
+    // the ids of every evicted record, as ascending `(first, last)` runs of consecutive ids
+    pub(crate) fn evicted(&self) -> Vec<(u32, u32)> {
+        self.evicted.ranges().collect()
+    }
+
+    // point every record of one client at another, for account merges
+    pub(crate) fn reassign_account(&mut self, source_id: u16, target_id: u16) -> Result<()> {
+        match &mut self.backend {
+            Backend::Memory { records, wide, .. } => {
+                for record in records
+                    .values_mut()
+                    .filter(|record| record.account_id == source_id)
+                {
+                    record.account_id = target_id;
+                }
+                for record in wide
+                    .values_mut()
+                    .filter(|record| record.account_id == source_id)
+                {
+                    record.account_id = target_id;
+                }
+                Ok(())
+            }
+            #[cfg(feature = "disk-store")]
+            Backend::Disk { db, .. } => {
+                for entry in db.iter() {
+                    let (key, value) = entry.map_err(store_error)?;
+                    let mut record = decode(&value)?;
+                    if record.account_id == source_id {
+                        record.account_id = target_id;
+                        db.insert(key, encode(&record)?).map_err(store_error)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // every stored record, in no particular order
+    pub(crate) fn records(&self) -> Box<dyn Iterator<Item = Result<(u32, TxRecord)>> + '_> {
+        match &self.backend {
+            Backend::Memory {
+                records,
+                currencies,
+                wide,
+                ..
+            } => Box::new(
+                records
+                    .iter()
+                    .map(|(&tx_id, packed)| Ok((tx_id, unpack(tx_id, packed, currencies, wide)))),
+            ),
+            #[cfg(feature = "disk-store")]
+            Backend::Disk { db, .. } => Box::new(db.iter().map(|entry| {
+                let (key, value) = entry.map_err(store_error)?;
+                Ok((tx_id_of(&key)?, decode(&value)?))
+            })),
+        }
+    }
+}
+
+// a set of ids kept as runs of consecutive ids, each run's first id mapped to its last. Ids
+// evicted roughly in the order they were issued merge into a few runs, so the set grows with the
+// gaps between them rather than with their number
+#[derive(Default)]
+struct IdRanges(BTreeMap<u32, u32>);
+
+impl IdRanges {
+    fn contains(&self, id: u32) -> bool {
+        self.0
+            .range(..=id)
+            .next_back()
+            .is_some_and(|(_, &last)| id <= last)
+    }
+
+    // add `first..=last`, merging it with every run it overlaps or touches
+    fn insert(&mut self, mut first: u32, mut last: u32) {
+        if let Some((&start, &end)) = self.0.range(..=first).next_back()
+            && end.saturating_add(1) >= first
+        {
+            first = start;
+            last = last.max(end);
+        }
+        while let Some((&start, &end)) = self
+            .0
+            .range(first..)
+            .next()
+            .filter(|&(&start, _)| start <= last.saturating_add(1))
+        {
+            self.0.remove(&start);
+            last = last.max(end);
+        }
+        self.0.insert(first, last);
+    }
+
+    fn ranges(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.0.iter().map(|(&first, &last)| (first, last))
+    }
+}
+
+// a record packed, or marked `WIDE` if any of its amounts doesn't fit, for the caller to keep it
+// whole instead
+fn pack(
+    record: &TxRecord,
+    currencies: &mut Vec<String>,
+    currency_ids: &mut HashMap<String, u16>,
+) -> Result<PackedTx> {
+    let currency = match currency_ids.get(&record.currency) {
+        Some(&id) => id,
+        // checked before interning, so a refused currency isn't kept
+        None => {
+            let id = u16::try_from(currencies.len())
+                .map_err(|_| Error::StoreError("too many distinct currencies".to_string()))?;
+            currency_ids.insert(record.currency.clone(), id);
+            currencies.push(record.currency.clone());
+            id
+        }
+    };
+    let mut packed = PackedTx {
+        amount: 0,
+        disputable: 0,
+        disputed: 0,
+        refunded: 0,
+        timestamp: record.timestamp.unwrap_or(NO_TIMESTAMP),
+        account_id: record.account_id,
+        currency,
+        bits: WIDE,
+    };
+    let amounts = [
+        record.amount,
+        record.disputable,
+        record.disputed,
+        record.refunded,
+    ]
+    .map(to_units);
+    let [
+        Some((amount, a)),
+        Some((disputable, b)),
+        Some((disputed, c)),
+        Some((refunded, d)),
+    ] = amounts
+    else {
+        return Ok(packed);
+    };
+    packed.amount = amount;
+    packed.disputable = disputable;
+    packed.disputed = disputed;
+    packed.refunded = refunded;
+    packed.bits = record.tx_type as u32
+        | (record.dispute_status as u32) << 4
+        | a << 7
+        | b << 10
+        | c << 13
+        | d << 16;
+
+    Ok(packed)
+}
+
+fn unpack(
+    tx_id: u32,
+    packed: &PackedTx,
+    currencies: &[String],
+    wide: &HashMap<u32, TxRecord>,
+) -> TxRecord {
+    if packed.bits & WIDE != 0 {
+        return wide[&tx_id].clone();
+    }
+    let bits = packed.bits;
+    let scale = |shift: u32| (bits >> shift) & 0b111;
+    TxRecord {
+        tx_type: TX_TYPES[(bits & 0b1111) as usize],
+        account_id: packed.account_id,
+        currency: currencies[usize::from(packed.currency)].clone(),
+        dispute_status: DISPUTE_STATUSES[((bits >> 4) & 0b111) as usize],
+        amount: from_units(packed.amount, scale(7)),
+        disputable: from_units(packed.disputable, scale(10)),
+        disputed: from_units(packed.disputed, scale(13)),
+        refunded: from_units(packed.refunded, scale(16)),
+        timestamp: Some(packed.timestamp).filter(|&timestamp| timestamp != NO_TIMESTAMP),
+    }
+}
+
+// `amount` as a count of 1/10,000ths, with the scale it was written with so it comes back the
+// same, if it has no more places than that and the count fits
+#[cfg(not(feature = "fixed-point"))]
+fn to_units(amount: Amount) -> Option<(i64, u32)> {
+    let scale = amount.scale();
+    let units = amount
+        .mantissa()
+        .checked_mul(10i128.pow(AMOUNT_DP.checked_sub(scale)?))?;
+
+    Some((i64::try_from(units).ok()?, scale))
+}
+
+#[cfg(not(feature = "fixed-point"))]
+fn from_units(units: i64, scale: u32) -> Amount {
+    let mut amount = Decimal::new(units, AMOUNT_DP);
+    amount.rescale(scale);
+    amount
+}
+
+#[cfg(feature = "fixed-point")]
+fn to_units(amount: Amount) -> Option<(i64, u32)> {
+    Some((amount.minor_units(), AMOUNT_DP))
+}
+
+#[cfg(feature = "fixed-point")]
+fn from_units(units: i64, _scale: u32) -> Amount {
+    Amount::from_minor_units(units)
+}
+
+#[cfg(feature = "disk-store")]
+fn encode(record: &TxRecord) -> Result<Vec<u8>> {
+    serde_json::to_vec(record).map_err(|e| Error::StoreError(e.to_string()))
+}
+
+#[cfg(feature = "disk-store")]
+fn decode(value: &[u8]) -> Result<TxRecord> {
+    serde_json::from_slice(value).map_err(|e| Error::StoreError(e.to_string()))
+}
+
+#[cfg(feature = "disk-store")]
+fn tx_id_of(key: &[u8]) -> Result<u32> {
+    let key: [u8; 4] = key
+        .try_into()
+        .map_err(|_| Error::StoreError("malformed tx id key".to_string()))?;
+    Ok(u32::from_be_bytes(key))
+}
+
+#[cfg(feature = "disk-store")]
+fn store_error(error: sled::Error) -> Error {
+    Error::StoreError(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::transaction::{DisputeStatus, TransactionType};
+
+    fn record(account_id: u16) -> TxRecord {
+        TxRecord {
+            tx_type: TransactionType::Deposit,
+            account_id,
+            currency: String::new(),
+            dispute_status: DisputeStatus::Undisputed,
+            amount: amount!(10),
+            disputable: amount!(10),
+            disputed: amount!(0),
+            refunded: amount!(0),
+            timestamp: None,
+        }
+    }
+
+    fn check_store(mut store: TxStore) {
+        assert!(!store.contains(1).unwrap());
+        store.insert(1, record(1)).unwrap();
+        store.insert(2, record(2)).unwrap();
+
+        assert!(store.contains(1).unwrap());
+        assert_eq!(store.get(1).unwrap().unwrap().disputable, amount!(10));
+        assert!(store.get(3).unwrap().is_none());
+
+        store.reassign_account(2, 1).unwrap();
+        assert_eq!(store.get(2).unwrap().unwrap().account_id, 1);
+        assert_eq!(store.records().count(), 2);
+
+        store.evict(1).unwrap();
+        assert!(store.contains(1).unwrap());
+        assert!(store.get(1).unwrap().is_none());
+        assert!(store.is_evicted(1));
+        assert_eq!(store.evicted(), [(1, 1)]);
+        assert_eq!(store.records().count(), 1);
+    }
+
+    #[test]
+    fn test_memory_store() {
+        check_store(TxStore::memory());
+    }
+
+    #[test]
+    fn test_memory_store_packs_records() {
+        let mut store = TxStore::memory();
+        let mut usd = record(3);
+        usd.currency = "USD".to_string();
+        usd.timestamp = Some(1_700_000_000);
+        store.insert(1, record(1)).unwrap();
+        store.insert(2, usd).unwrap();
+        store.insert(3, record(2)).unwrap();
+
+        assert_eq!(store.get(2).unwrap().unwrap().currency, "USD");
+        assert_eq!(store.get(2).unwrap().unwrap().amount, amount!(10));
+        assert_eq!(store.get(3).unwrap().unwrap().currency, "");
+        assert_eq!(
+            store.get(2).unwrap().unwrap().timestamp,
+            Some(1_700_000_000)
+        );
+        assert_eq!(store.get(3).unwrap().unwrap().timestamp, None);
+        assert!(
+            matches!(&store.backend, Backend::Memory { currencies, .. } if currencies.len() == 2)
+        );
+    }
+
+    #[test]
+    fn test_memory_store_packs_every_field() {
+        let mut store = TxStore::memory();
+        for (tx_id, &tx_type) in TX_TYPES.iter().enumerate() {
+            assert_eq!(tx_type as usize, tx_id);
+            let dispute_status = DISPUTE_STATUSES[tx_id % DISPUTE_STATUSES.len()];
+            assert_eq!(dispute_status as usize, tx_id % DISPUTE_STATUSES.len());
+            let record = TxRecord {
+                tx_type,
+                dispute_status,
+                amount: -amount!(10.5),
+                disputable: amount!(0.0001),
+                disputed: amount!(3),
+                refunded: amount!(922337203685477.5807),
+                ..record(1)
+            };
+
+            store.insert(tx_id as u32, record.clone()).unwrap();
+
+            let stored = store.get(tx_id as u32).unwrap().unwrap();
+            assert_eq!(stored.tx_type, tx_type);
+            assert_eq!(stored.dispute_status, dispute_status);
+            // as written, down to the places
+            assert_eq!(stored.amount.to_string(), record.amount.to_string());
+            assert_eq!(stored.disputable, record.disputable);
+            assert_eq!(stored.disputed.to_string(), record.disputed.to_string());
+            assert_eq!(stored.refunded, record.refunded);
+        }
+        assert!(matches!(&store.backend, Backend::Memory { wide, .. } if wide.is_empty()));
+    }
+
+    #[cfg(not(feature = "fixed-point"))]
+    #[test]
+    fn test_memory_store_keeps_wide_records_whole() {
+        let mut store = TxStore::memory();
+        let mut wide = record(2);
+        wide.amount = amount!(1.00001);
+        wide.refunded = Amount::MAX;
+        store.insert(1, record(1)).unwrap();
+        store.insert(2, wide.clone()).unwrap();
+
+        store.reassign_account(2, 3).unwrap();
+
+        let stored = store.get(2).unwrap().unwrap();
+        assert_eq!(
+            (stored.amount, stored.refunded),
+            (wide.amount, wide.refunded)
+        );
+        assert_eq!(stored.account_id, 3);
+        assert_eq!(store.records().count(), 2);
+        // packed again once it fits
+        store.insert(2, record(2)).unwrap();
+        assert!(matches!(&store.backend, Backend::Memory { wide, .. } if wide.is_empty()));
+        store.insert(2, wide).unwrap();
+        store.evict(2).unwrap();
+        assert!(matches!(&store.backend, Backend::Memory { wide, .. } if wide.is_empty()));
+    }
+
+    #[cfg(all(target_pointer_width = "64", not(feature = "fixed-point")))]
+    #[test]
+    fn test_packed_record_size() {
+        assert_eq!(size_of::<TxRecord>(), 112);
+        assert_eq!(size_of::<PackedTx>(), 48);
+    }
+
+    #[test]
+    fn test_memory_store_failure_too_many_currencies() {
+        let mut store = TxStore::memory();
+        for tx_id in 0..=u32::from(u16::MAX) {
+            let mut record = record(1);
+            record.currency = tx_id.to_string();
+            store.insert(tx_id, record).unwrap();
+        }
+        let mut record = record(1);
+        record.currency = "one too many".to_string();
+
+        let result = store.insert(u32::MAX, record);
+
+        assert!(matches!(result, Err(Error::StoreError(_))));
+        assert!(!store.contains(u32::MAX).unwrap());
+        assert!(
+            matches!(&store.backend, Backend::Memory { currencies, currency_ids, .. }
+                if currencies.len() == 1 << 16 && currency_ids.len() == 1 << 16)
+        );
+    }
+
+    #[test]
+    fn test_evicted_ids_merge_into_runs() {
+        let mut store = TxStore::memory();
+        for tx_id in (1..=1000).chain([1002, u32::MAX]) {
+            store.evict(tx_id).unwrap();
+        }
+        // fills the gap, joining both runs
+        store.mark_evicted(999, 1001);
+
+        assert_eq!(store.evicted(), [(1, 1002), (u32::MAX, u32::MAX)]);
+        assert!(store.is_evicted(500));
+        assert!(store.contains(u32::MAX).unwrap());
+        assert!(!store.is_evicted(0));
+        assert!(!store.is_evicted(1003));
+    }
+
+    #[cfg(feature = "disk-store")]
+    #[test]
+    fn test_disk_store() {
+        let dir = std::env::temp_dir().join(format!("tx-store-{}", std::process::id()));
+
+        check_store(TxStore::disk(&dir).unwrap());
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[cfg(feature = "disk-store")]
+    #[test]
+    fn test_disk_store_keeps_given_dir() {
+        let dir = std::env::temp_dir().join(format!("tx-store-keep-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("keep.txt"), "mine").unwrap();
+
+        let store = TxStore::disk(&dir).unwrap();
+        let Backend::Disk {
+            _scratch: scratch, ..
+        } = &store.backend
+        else {
+            unreachable!()
+        };
+        let scratch = scratch.0.clone();
+        assert!(scratch.starts_with(&dir) && scratch.is_dir());
+        drop(store);
+
+        assert!(!scratch.exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("keep.txt")).unwrap(),
+            "mine"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "disk-store")]
+    #[test]
+    fn test_disk_store_filter() {
+        let dir = std::env::temp_dir().join(format!("tx-store-filter-{}", std::process::id()));
+        let mut store = TxStore::disk(&dir).unwrap();
+
+        // past the filter's first layer
+        for tx_id in 0..110_000 {
+            store.insert(tx_id * 2, record(1)).unwrap();
+        }
+        store.insert(0, record(2)).unwrap();
+
+        assert!((0..110_000).all(|tx_id| store.contains(tx_id * 2).unwrap()));
+        assert!(!store.contains(220_001).unwrap());
+        assert_eq!(store.get(0).unwrap().unwrap().account_id, 2);
+        assert!(store.get(1).unwrap().is_none());
+        drop(store);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
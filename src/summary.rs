@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use payments_engine::{Error, PaymentsEngine, Result, TransactionType};
+
+// run statistics for the end-of-run report: applied txs per type and failures per error code,
+// with the first input line each code failed on, as a starting point in large inputs. Runs over
+// several files also break applied and failed rows down per file
+pub struct Summary {
+    started: Instant,
+    applied: BTreeMap<String, u64>,
+    rejected: BTreeMap<String, (u64, Option<u64>)>,
+    // (file, applied, rejected) for every file finished so far
+    files: Vec<(String, u64, u64)>,
+    // the applied and rejected totals the current file started from
+    file_start: (u64, u64),
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            applied: BTreeMap::new(),
+            rejected: BTreeMap::new(),
+            files: Vec::new(),
+            file_start: (0, 0),
+        }
+    }
+}
+
+impl Summary {
+    pub fn record_applied(&mut self, tx_type: TransactionType) {
+        *self.applied.entry(tx_type.to_string()).or_default() += 1;
+    }
+
+    // a tx counted as applied that failed after all, like a parked dispute the engine gave up on
+    pub fn retract_applied(&mut self, tx_type: TransactionType) {
+        if let Some(count) = self.applied.get_mut(&tx_type.to_string()) {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    pub fn record_rejected(&mut self, error: &Error) {
+        let (count, first_line) = self.rejected.entry(error.code().to_string()).or_default();
+        *count += 1;
+        *first_line = first_line.or(error.context().and_then(|context| context.line));
+    }
+
+    // rows (and merges) that failed so far, however the policy handled them
+    pub fn failures(&self) -> u64 {
+        self.rejected.values().map(|(count, _)| count).sum()
+    }
+
+    fn applied(&self) -> u64 {
+        self.applied.values().sum()
+    }
+
+    pub fn start_file(&mut self) {
+        self.file_start = (self.applied(), self.failures());
+    }
+
+    // everything recorded since `start_file` is counted against `file`
+    pub fn end_file(&mut self, file: String) {
+        let (applied, rejected) = self.file_start;
+        self.files.push((
+            file,
+            self.applied().saturating_sub(applied),
+            self.failures().saturating_sub(rejected),
+        ));
+    }
+
+    // plain `name: value` lines; `accounts_before` is the account count the run started with
+    pub fn write<W: Write>(
+        &self,
+        mut writer: W,
+        engine: &PaymentsEngine,
+        accounts_before: usize,
+    ) -> Result<()> {
+        self.write_with_elapsed(&mut writer, engine, accounts_before, self.started.elapsed())
+    }
+
+    fn write_with_elapsed<W: Write>(
+        &self,
+        writer: &mut W,
+        engine: &PaymentsEngine,
+        accounts_before: usize,
+        elapsed: Duration,
+    ) -> Result<()> {
+        let applied = self.applied();
+        let processed = applied + self.failures();
+        let accounts = engine.accounts().count();
+        let locked = engine
+            .accounts()
+            .filter(|account| account.is_locked())
+            .count();
+
+        writeln!(writer, "processed: {}", processed)?;
+        writeln!(writer, "applied: {}", applied)?;
+        for (tx_type, count) in &self.applied {
+            writeln!(writer, "applied {}: {}", tx_type, count)?;
+        }
+        writeln!(writer, "rejected: {}", self.failures())?;
+        for (code, (count, first_line)) in &self.rejected {
+            match first_line {
+                Some(line) => writeln!(
+                    writer,
+                    "rejected {}: {} (first at line {})",
+                    code, count, line
+                )?,
+                None => writeln!(writer, "rejected {}: {}", code, count)?,
+            }
+        }
+        // a single file's counts are the run's
+        if self.files.len() > 1 {
+            for (file, applied, rejected) in &self.files {
+                writeln!(
+                    writer,
+                    "file {}: {} processed, {} applied, {} rejected",
+                    file,
+                    applied + rejected,
+                    applied,
+                    rejected
+                )?;
+            }
+        }
+        writeln!(
+            writer,
+            "accounts created: {}",
+            accounts.saturating_sub(accounts_before)
+        )?;
+        writeln!(writer, "accounts locked: {}", locked)?;
+        let secs = elapsed.as_secs_f64();
+        writeln!(writer, "elapsed: {:.3}s", secs)?;
+        if secs > 0.0 {
+            writeln!(writer, "throughput: {:.0} rows/s", processed as f64 / secs)?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payments_engine::ErrorContext;
+    use payments_engine::amount;
+    use payments_engine::testing::TxBuilder;
+
+    #[test]
+    fn test_write() {
+        let mut engine = PaymentsEngine::new();
+        let mut summary = Summary::default();
+        let deposit = TxBuilder::deposit(1, 1, amount!(10)).build();
+        engine.process_tx(&deposit).unwrap();
+        summary.record_applied(deposit.tx_type);
+        summary.record_rejected(&Error::InsufficientFunds(""));
+        summary.record_rejected(
+            &Error::InsufficientFunds("").with_context(ErrorContext::for_line(Some(3))),
+        );
+        summary.record_rejected(&Error::UnknownTransaction(9));
+
+        let mut out = Vec::new();
+        summary
+            .write_with_elapsed(&mut out, &engine, 0, Duration::from_secs(1))
+            .unwrap();
+
+        let report = String::from_utf8(out).unwrap();
+        assert_eq!(
+            report,
+            "processed: 4\n\
+             applied: 1\n\
+             applied deposit: 1\n\
+             rejected: 3\n\
+             rejected insufficient-funds: 2 (first at line 3)\n\
+             rejected unknown-transaction: 1\n\
+             accounts created: 1\n\
+             accounts locked: 0\n\
+             elapsed: 1.000s\n\
+             throughput: 4 rows/s\n"
+        );
+    }
+
+    #[test]
+    fn test_write_per_file() {
+        let engine = PaymentsEngine::new();
+        let mut summary = Summary::default();
+        summary.start_file();
+        summary.record_applied(TransactionType::Deposit);
+        summary.record_applied(TransactionType::Deposit);
+        summary.end_file("a.csv".to_string());
+        summary.start_file();
+        summary.record_applied(TransactionType::Withdrawal);
+        summary.record_rejected(&Error::InsufficientFunds(""));
+        summary.end_file("b.csv".to_string());
+
+        let mut out = Vec::new();
+        summary
+            .write_with_elapsed(&mut out, &engine, 0, Duration::from_secs(1))
+            .unwrap();
+
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains(
+            "file a.csv: 2 processed, 2 applied, 0 rejected\n\
+             file b.csv: 2 processed, 1 applied, 1 rejected\n\
+             accounts created: 0\n"
+        ));
+    }
+}
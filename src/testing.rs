@@ -0,0 +1,342 @@
+//! Builders for the transactions of a test, so tests against the engine can spell out a
+//! scenario instead of hand-writing CSV.
+//!
+//! ```
+//! use payments_engine::testing::ScenarioBuilder;
+//! use payments_engine::{DEFAULT_CURRENCY, PaymentsEngine, TransactionType, amount};
+//!
+//! let scenario = ScenarioBuilder::new()
+//!     .deposit(1, amount!(10))
+//!     .deposit_then(1, amount!(5), &[TransactionType::Dispute, TransactionType::Chargeback])
+//!     .deposit(2, amount!(3));
+//!
+//! let mut engine = PaymentsEngine::new();
+//! for result in scenario.run(&mut engine) {
+//!     result.unwrap();
+//! }
+//!
+//! let account = engine.account(1).unwrap();
+//! assert_eq!(account.balance(DEFAULT_CURRENCY).total, amount!(10));
+//! assert!(account.is_locked());
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{
+    amount::Amount,
+    engine::PaymentsEngine,
+    error::Result,
+    transaction::{Transaction, TransactionType},
+};
+
+/// Builds a single [`Transaction`], leaving every optional field unset until asked for.
+#[derive(Debug, Clone)]
+pub struct TxBuilder {
+    tx: Transaction,
+}
+
+impl TxBuilder {
+    /// A transaction of any type, without an amount.
+    pub fn new(tx_type: TransactionType, client: u16, tx: u32) -> Self {
+        Self {
+            tx: Transaction {
+                tx_type,
+                account_id: client,
+                tx_id: tx,
+                amount: None,
+                currency: None,
+                timestamp: None,
+                reason: None,
+                effective: None,
+            },
+        }
+    }
+
+    pub fn deposit(client: u16, tx: u32, amount: Amount) -> Self {
+        Self::new(TransactionType::Deposit, client, tx).amount(amount)
+    }
+
+    pub fn withdrawal(client: u16, tx: u32, amount: Amount) -> Self {
+        Self::new(TransactionType::Withdrawal, client, tx).amount(amount)
+    }
+
+    /// Disputes `client`'s tx `tx`.
+    pub fn dispute(client: u16, tx: u32) -> Self {
+        Self::new(TransactionType::Dispute, client, tx)
+    }
+
+    pub fn resolve(client: u16, tx: u32) -> Self {
+        Self::new(TransactionType::Resolve, client, tx)
+    }
+
+    pub fn chargeback(client: u16, tx: u32) -> Self {
+        Self::new(TransactionType::Chargeback, client, tx)
+    }
+
+    pub fn amount(mut self, amount: Amount) -> Self {
+        self.tx.amount = Some(amount);
+        self
+    }
+
+    pub fn currency(mut self, currency: &str) -> Self {
+        self.tx.currency = Some(currency.to_string());
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.tx.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn reason(mut self, reason: &str) -> Self {
+        self.tx.reason = Some(reason.to_string());
+        self
+    }
+
+    pub fn build(self) -> Transaction {
+        self.tx
+    }
+}
+
+impl From<TxBuilder> for Transaction {
+    fn from(builder: TxBuilder) -> Self {
+        builder.build()
+    }
+}
+
+/// Builds a sequence of transactions, handing out tx ids from 1 so that deposits and
+/// withdrawals never clash.
+///
+/// Follow-ups like disputes refer to the tx they act on, either explicitly or through
+/// [`last_tx`](Self::last_tx) and [`deposit_then`](Self::deposit_then).
+/// [`interleave_clients`](Self::interleave_clients) shuffles different clients' transactions
+/// into a random but repeatable order, keeping each client's own in order, as a test of
+/// behaviour that mustn't depend on how clients' transactions arrive relative to each other.
+#[derive(Debug, Clone)]
+pub struct ScenarioBuilder {
+    txs: Vec<Transaction>,
+    next_tx: u32,
+}
+
+impl Default for ScenarioBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScenarioBuilder {
+    pub fn new() -> Self {
+        Self {
+            txs: Vec::new(),
+            next_tx: 1,
+        }
+    }
+
+    /// Adds a transaction as is. Tx ids handed out later skip past its id.
+    pub fn tx(mut self, tx: impl Into<Transaction>) -> Self {
+        let tx = tx.into();
+        self.next_tx = self.next_tx.max(tx.tx_id.saturating_add(1));
+        self.txs.push(tx);
+        self
+    }
+
+    /// Adds a deposit under the next tx id.
+    pub fn deposit(self, client: u16, amount: Amount) -> Self {
+        let tx = self.next_tx;
+        self.tx(TxBuilder::deposit(client, tx, amount))
+    }
+
+    /// Adds a withdrawal under the next tx id.
+    pub fn withdrawal(self, client: u16, amount: Amount) -> Self {
+        let tx = self.next_tx;
+        self.tx(TxBuilder::withdrawal(client, tx, amount))
+    }
+
+    pub fn dispute(self, client: u16, tx: u32) -> Self {
+        self.tx(TxBuilder::dispute(client, tx))
+    }
+
+    pub fn resolve(self, client: u16, tx: u32) -> Self {
+        self.tx(TxBuilder::resolve(client, tx))
+    }
+
+    pub fn chargeback(self, client: u16, tx: u32) -> Self {
+        self.tx(TxBuilder::chargeback(client, tx))
+    }
+
+    /// Adds a deposit, then a transaction of each of `follow_ups` acting on it, such as a
+    /// dispute and a chargeback.
+    pub fn deposit_then(self, client: u16, amount: Amount, follow_ups: &[TransactionType]) -> Self {
+        let mut scenario = self.deposit(client, amount);
+        let tx = scenario.last_tx();
+        for tx_type in follow_ups {
+            scenario = scenario.tx(TxBuilder::new(*tx_type, client, tx));
+        }
+        scenario
+    }
+
+    /// The id of the last transaction added, or 0 before any.
+    pub fn last_tx(&self) -> u32 {
+        self.txs.last().map_or(0, |tx| tx.tx_id)
+    }
+
+    /// Shuffles the clients' transactions together in an order picked by `seed`, keeping each
+    /// client's transactions in the order they were added. The same seed always picks the same
+    /// order.
+    pub fn interleave_clients(mut self, seed: u64) -> Self {
+        let mut clients: BTreeMap<u16, VecDeque<Transaction>> = BTreeMap::new();
+        for tx in self.txs.drain(..) {
+            clients.entry(tx.account_id).or_default().push_back(tx);
+        }
+        let mut state = seed;
+        let mut queues: Vec<_> = clients.into_values().collect();
+        let mut left: usize = queues.iter().map(|queue| queue.len()).sum();
+        while left > 0 {
+            // picking a client in proportion to its transactions left makes every interleaving
+            // equally likely
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut pick = (mix(state) % left as u64) as usize;
+            let queue = queues
+                .iter_mut()
+                .find(|queue| {
+                    let found = pick < queue.len();
+                    pick = pick.saturating_sub(queue.len());
+                    found
+                })
+                .expect("picked within the transactions left");
+            self.txs.extend(queue.pop_front());
+            left -= 1;
+        }
+        self
+    }
+
+    pub fn build(self) -> Vec<Transaction> {
+        self.txs
+    }
+
+    /// Applies every transaction to `engine` in order, returning each one's result.
+    pub fn run(self, engine: &mut PaymentsEngine) -> Vec<Result<()>> {
+        self.txs.iter().map(|tx| engine.process_tx(tx)).collect()
+    }
+}
+
+// the splitmix64 finalizer
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+    use crate::error::Error;
+    use crate::transaction::DEFAULT_CURRENCY;
+
+    #[test]
+    fn test_tx_builder() {
+        let tx = TxBuilder::deposit(1, 7, amount!(2.5))
+            .currency("EUR")
+            .timestamp(60)
+            .build();
+
+        assert_eq!(tx.tx_type, TransactionType::Deposit);
+        assert_eq!((tx.account_id, tx.tx_id), (1, 7));
+        assert_eq!(tx.amount, Some(amount!(2.5)));
+        assert_eq!(tx.currency.as_deref(), Some("EUR"));
+        assert_eq!(tx.timestamp, Some(60));
+        assert_eq!(TxBuilder::dispute(1, 7).build().amount, None);
+    }
+
+    #[test]
+    fn test_scenario_hands_out_tx_ids() {
+        let txs = ScenarioBuilder::new()
+            .deposit(1, amount!(1))
+            .tx(TxBuilder::deposit(2, 10, amount!(1)))
+            .deposit_then(
+                1,
+                amount!(1),
+                &[TransactionType::Dispute, TransactionType::Resolve],
+            )
+            .build();
+
+        let ids: Vec<_> = txs.iter().map(|tx| (tx.tx_type, tx.tx_id)).collect();
+        assert_eq!(
+            ids,
+            [
+                (TransactionType::Deposit, 1),
+                (TransactionType::Deposit, 10),
+                (TransactionType::Deposit, 11),
+                (TransactionType::Dispute, 11),
+                (TransactionType::Resolve, 11),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scenario_run() {
+        let mut engine = PaymentsEngine::new();
+
+        let results = ScenarioBuilder::new()
+            .deposit(1, amount!(10))
+            .withdrawal(1, amount!(20))
+            .run(&mut engine);
+
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1].as_ref().unwrap_err().root(),
+            Error::InsufficientFunds(_)
+        ));
+        assert_eq!(
+            engine.account(1).unwrap().balance(DEFAULT_CURRENCY).total,
+            amount!(10)
+        );
+    }
+
+    #[test]
+    fn test_interleave_clients_keeps_each_clients_order() {
+        let scenario = (1..=4).fold(ScenarioBuilder::new(), |scenario, client| {
+            scenario.deposit_then(
+                client,
+                amount!(1),
+                &[TransactionType::Dispute, TransactionType::Chargeback],
+            )
+        });
+
+        let orders: Vec<_> = (0..20)
+            .map(|seed| {
+                let txs = scenario.clone().interleave_clients(seed).build();
+                for client in 1..=4 {
+                    let types: Vec<_> = txs
+                        .iter()
+                        .filter(|tx| tx.account_id == client)
+                        .map(|tx| tx.tx_type)
+                        .collect();
+                    assert_eq!(
+                        types,
+                        [
+                            TransactionType::Deposit,
+                            TransactionType::Dispute,
+                            TransactionType::Chargeback
+                        ]
+                    );
+                }
+                txs.iter().map(|tx| tx.account_id).collect::<Vec<_>>()
+            })
+            .collect();
+
+        // repeatable, and actually shuffled
+        assert_eq!(
+            orders[3],
+            scenario
+                .clone()
+                .interleave_clients(3)
+                .build()
+                .iter()
+                .map(|tx| tx.account_id)
+                .collect::<Vec<_>>()
+        );
+        assert!(orders.iter().any(|order| order != &orders[0]));
+    }
+}
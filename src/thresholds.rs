@@ -0,0 +1,246 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::Balance,
+    amount::Amount,
+    error::{Error, Result},
+    hash::HashMap,
+};
+
+/// Which side of a [`BalanceThreshold`] a balance crossed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThresholdKind {
+    /// `available` fell below the threshold.
+    AvailableBelow,
+    /// `held` rose above the threshold.
+    HeldAbove,
+}
+
+/// Levels a client's balance in any one currency is watched against. Unset levels don't apply.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceThreshold {
+    /// Alert once `available` falls below this.
+    pub available_below: Option<Amount>,
+    /// Alert once `held` rises above this.
+    pub held_above: Option<Amount>,
+}
+
+/// Balance levels, globally and per client, that raise an
+/// [`Event::BalanceThresholdCrossed`](crate::Event::BalanceThresholdCrossed) when a balance change
+/// crosses them. A balance already past a level doesn't raise it again until it has come back.
+///
+/// Written as TOML, with a client's own levels taking the place of the global ones they set:
+///
+/// ```toml
+/// available_below = "100"
+/// held_above = "5000"
+///
+/// [[clients]]
+/// client = 7
+/// available_below = "0"
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BalanceThresholds {
+    global: BalanceThreshold,
+    per_client: HashMap<u16, BalanceThreshold>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BalanceThresholdsFile {
+    #[serde(default)]
+    available_below: Option<Amount>,
+    #[serde(default)]
+    held_above: Option<Amount>,
+    #[serde(default)]
+    clients: Vec<ClientThreshold>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientThreshold {
+    client: u16,
+    #[serde(default)]
+    available_below: Option<Amount>,
+    #[serde(default)]
+    held_above: Option<Amount>,
+}
+
+impl BalanceThresholds {
+    /// Thresholds applying `global` to every client but those in `per_client`, whose levels take
+    /// the place of the global ones they set.
+    pub fn new(
+        global: BalanceThreshold,
+        per_client: impl IntoIterator<Item = (u16, BalanceThreshold)>,
+    ) -> Self {
+        Self {
+            global,
+            per_client: per_client.into_iter().collect(),
+        }
+    }
+
+    /// Parses thresholds from TOML, failing with [`Error::ConfigError`] if a client is listed
+    /// twice.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let file: BalanceThresholdsFile =
+            toml::from_str(text).map_err(|e| Error::ConfigError(e.to_string()))?;
+        let mut per_client = HashMap::default();
+        for entry in file.clients {
+            let threshold = BalanceThreshold {
+                available_below: entry.available_below,
+                held_above: entry.held_above,
+            };
+            if per_client.insert(entry.client, threshold).is_some() {
+                return Err(Error::ConfigError(format!(
+                    "client {} has more than one balance threshold",
+                    entry.client
+                )));
+            }
+        }
+
+        Ok(Self::new(
+            BalanceThreshold {
+                available_below: file.available_below,
+                held_above: file.held_above,
+            },
+            per_client,
+        ))
+    }
+
+    /// The levels that apply to `client`.
+    pub fn threshold(&self, client: u16) -> BalanceThreshold {
+        match self.per_client.get(&client) {
+            Some(own) => BalanceThreshold {
+                available_below: own.available_below.or(self.global.available_below),
+                held_above: own.held_above.or(self.global.held_above),
+            },
+            None => self.global,
+        }
+    }
+
+    // the levels `client`'s balance crossed going from `before` to `after`, with the level and
+    // the balance it was crossed by
+    pub(crate) fn crossed(
+        &self,
+        client: u16,
+        before: &Balance,
+        after: &Balance,
+    ) -> Vec<(ThresholdKind, Amount, Amount)> {
+        let threshold = self.threshold(client);
+        let mut crossed = Vec::new();
+        if let Some(level) = threshold.available_below
+            && before.available >= level
+            && after.available < level
+        {
+            crossed.push((ThresholdKind::AvailableBelow, level, after.available));
+        }
+        if let Some(level) = threshold.held_above
+            && before.held <= level
+            && after.held > level
+        {
+            crossed.push((ThresholdKind::HeldAbove, level, after.held));
+        }
+        crossed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount;
+
+    fn balance(available: Amount, held: Amount) -> Balance {
+        Balance {
+            available,
+            held,
+            total: available + held,
+        }
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let thresholds = BalanceThresholds::from_toml(
+            r#"
+            available_below = "100"
+            held_above = "500"
+            [[clients]]
+            client = 7
+            available_below = "0"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            thresholds.threshold(1),
+            BalanceThreshold {
+                available_below: Some(amount!(100)),
+                held_above: Some(amount!(500)),
+            }
+        );
+        assert_eq!(
+            thresholds.threshold(7),
+            BalanceThreshold {
+                available_below: Some(amount!(0)),
+                held_above: Some(amount!(500)),
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_toml_failure() {
+        for text in [
+            "[[clients]]\nclient = 1\nheld_above = \"5\"\n[[clients]]\nclient = 1",
+            "total_below = \"5\"",
+        ] {
+            assert!(
+                matches!(
+                    BalanceThresholds::from_toml(text),
+                    Err(Error::ConfigError(_))
+                ),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_crossed() {
+        let thresholds = BalanceThresholds::new(
+            BalanceThreshold {
+                available_below: Some(amount!(100)),
+                held_above: Some(amount!(50)),
+            },
+            [],
+        );
+
+        assert_eq!(
+            thresholds.crossed(
+                1,
+                &balance(amount!(150), amount!(0)),
+                &balance(amount!(90), amount!(60))
+            ),
+            [
+                (ThresholdKind::AvailableBelow, amount!(100), amount!(90)),
+                (ThresholdKind::HeldAbove, amount!(50), amount!(60)),
+            ]
+        );
+        // already past both, or landing right on them
+        assert_eq!(
+            thresholds.crossed(
+                1,
+                &balance(amount!(90), amount!(60)),
+                &balance(amount!(80), amount!(70))
+            ),
+            []
+        );
+        assert_eq!(
+            thresholds.crossed(
+                1,
+                &balance(amount!(150), amount!(0)),
+                &balance(amount!(100), amount!(50))
+            ),
+            []
+        );
+    }
+}
@@ -1,9 +1,23 @@
+use std::fmt;
+
+use crate::amount::{Amount, AmountExt, PrecisionPolicy};
 use rust_decimal::Decimal;
 use serde::{self, Deserialize, Serialize};
 
 use crate::error::{Error, Result};
 
+/// Currency of transactions that don't name one.
+pub const DEFAULT_CURRENCY: &str = "";
+
+/// One input row: the operation, the client it applies to, its tx id, the amount (absent for
+/// resolves, chargebacks, clears, voids and reversals, which reference an earlier tx id, optional
+/// for disputes, refunds and captures, where it covers only that part of the referenced tx, and
+/// signed for adjustments), and optionally the currency, when it happened and why.
+///
+/// Deserializing goes through [`TransactionRow`], rounding amounts with the default
+/// [`PrecisionPolicy`].
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(try_from = "TransactionRow")]
 pub struct Transaction {
     #[serde(rename = "type")]
     pub tx_type: TransactionType,
@@ -11,38 +25,208 @@ pub struct Transaction {
     pub account_id: u16,
     #[serde(rename = "tx")]
     pub tx_id: u32,
+    pub amount: Option<Amount>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// Seconds since the Unix epoch, checked against the engine's dispute window when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Why an adjustment or reversal was made, kept in its event for the audit trail.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Seconds since the Unix epoch the transaction takes effect from, for one backdated to
+    /// before its `timestamp`. Period reports place it by this date, and interest already paid
+    /// is corrected back to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effective: Option<u64>,
+}
+
+impl Transaction {
+    /// The tx's currency, or [`DEFAULT_CURRENCY`] when none is given.
+    pub fn currency_code(&self) -> &str {
+        self.currency.as_deref().unwrap_or(DEFAULT_CURRENCY)
+    }
+}
+
+/// A [`Transaction`] as read, with the amount exactly as written, to bring in line with a chosen
+/// [`PrecisionPolicy`].
+#[derive(Debug, Deserialize, Clone)]
+pub struct TransactionRow {
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    #[serde(rename = "client")]
+    pub account_id: u16,
+    #[serde(rename = "tx")]
+    pub tx_id: u32,
+    #[serde(default, deserialize_with = "deserialize_amount")]
     pub amount: Option<Decimal>,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub effective: Option<u64>,
+}
+
+// reads the amount from its text, as csv would otherwise read a plain number through a float and
+// lose digits beyond about 15 significant figures
+pub(crate) fn deserialize_amount<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<Decimal>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(amount) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let amount = amount.trim();
+    if amount.is_empty() {
+        return Ok(None);
+    }
+
+    amount
+        .parse()
+        .or_else(|_| Decimal::from_scientific(amount))
+        .map(Some)
+        .map_err(serde::de::Error::custom)
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+impl TransactionRow {
+    /// Fails if the policy rejects the amount, or it is out of range for [`Amount`].
+    pub fn into_transaction(self, policy: PrecisionPolicy) -> Result<Transaction> {
+        let amount = self
+            .amount
+            .map(|amount| policy.apply(amount).and_then(Amount::from_decimal))
+            .transpose()?;
+
+        Ok(Transaction {
+            tx_type: self.tx_type,
+            account_id: self.account_id,
+            tx_id: self.tx_id,
+            amount,
+            currency: self.currency,
+            timestamp: self.timestamp,
+            reason: self.reason,
+            effective: self.effective,
+        })
+    }
+}
+
+impl TryFrom<TransactionRow> for Transaction {
+    type Error = Error;
+
+    fn try_from(row: TransactionRow) -> Result<Self> {
+        row.into_transaction(PrecisionPolicy::default())
+    }
+}
+
+/// Transaction kinds, named in input as their lowercase variant name.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
+    Adjustment,
+    Authorize,
+    Capture,
     Chargeback,
+    Clear,
+    Close,
     Deposit,
     Dispute,
+    Freeze,
+    Provisional,
+    Refund,
     Resolve,
+    Reversal,
+    Unlock,
+    Void,
     Withdrawal,
 }
 
-// lightweight tx type for storage
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TransactionType::Adjustment => "adjustment",
+            TransactionType::Authorize => "authorize",
+            TransactionType::Capture => "capture",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Clear => "clear",
+            TransactionType::Close => "close",
+            TransactionType::Deposit => "deposit",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Freeze => "freeze",
+            TransactionType::Provisional => "provisional",
+            TransactionType::Refund => "refund",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Reversal => "reversal",
+            TransactionType::Unlock => "unlock",
+            TransactionType::Void => "void",
+            TransactionType::Withdrawal => "withdrawal",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// Where a stored tx is in the dispute flow: one dispute can be open at a time, a tx can be
+/// disputed again after a resolve while part of its amount is still disputable, and a chargeback
+/// is final.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeStatus {
+    Undisputed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+    /// Undone by a reversal, which is as final as a chargeback.
+    Reversed,
+}
+
+/// A stored transaction, holding only what later disputes, refunds, reversals, resolves,
+/// chargebacks, clears, captures and voids need.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct TxRecord {
-    // type not necessary here--keeping for sanity
+    /// Tells uncleared provisional deposits and open authorizations apart from settled funds.
     pub tx_type: TransactionType,
     pub account_id: u16,
-    pub amount: Decimal,
+    /// Disputes, resolves and chargebacks only ever move funds in this currency.
+    pub currency: String,
+    pub dispute_status: DisputeStatus,
+    /// The tx's original amount, whatever has been disputed or refunded since. For an
+    /// adjustment this is its signed amount.
+    pub amount: Amount,
+    /// How much of the tx's amount can still be disputed: all of it for a new record, then every
+    /// (partial) dispute draws it down for good, and it never exceeds what hasn't been refunded.
+    /// An adjustment can't be disputed, but this is its signed amount all the same.
+    pub disputable: Amount,
+    /// The amount held by the open dispute, if any.
+    pub disputed: Amount,
+    /// How much of a deposit has been refunded so far.
+    pub refunded: Amount,
+    /// When the tx happened, if the input said. Disputes of it must come within the dispute
+    /// window.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
 }
 
 impl TryFrom<&Transaction> for TxRecord {
     type Error = Error;
 
     fn try_from(tx: &Transaction) -> Result<Self> {
+        let amount = tx
+            .amount
+            .ok_or(Error::TransactionError("Invalid transaction amount."))?;
+
         Ok(TxRecord {
             tx_type: tx.tx_type,
             account_id: tx.account_id,
-            amount: tx
-                .amount
-                .ok_or(Error::TransactionError("Invalid transaction amount."))?,
+            currency: tx.currency_code().to_owned(),
+            dispute_status: DisputeStatus::Undisputed,
+            amount,
+            disputable: amount,
+            disputed: Amount::ZERO,
+            refunded: Amount::ZERO,
+            timestamp: tx.timestamp,
         })
     }
 }
@@ -0,0 +1,670 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use payments_engine::{Amount, Error, Event, EventSink, Result, ThresholdKind};
+use serde::{Deserialize, Serialize};
+
+// how many times an alert is posted before it is given up on
+const MAX_ATTEMPTS: u32 = 5;
+// wait before the first retry, doubling for each one after
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+// how long a single post may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// alerts waiting to be posted; once it's full, further alerts are dropped (and logged), or left
+// in the outbox, rather than holding up processing
+const QUEUE_CAPACITY: usize = 1024;
+
+// when to alert on top of every account lock, per client: once its chargebacks reach a count,
+// and once the amount charged back in a currency reaches a total
+#[derive(Default)]
+pub struct Thresholds {
+    pub chargebacks: Option<u32>,
+    pub charged_back: Option<Amount>,
+}
+
+// the JSON posted to the webhook
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "alert", rename_all = "snake_case")]
+enum Alert {
+    AccountLocked {
+        client: u16,
+        reason: String,
+        chargebacks: u32,
+    },
+    ChargebackCount {
+        client: u16,
+        chargebacks: u32,
+        threshold: u32,
+    },
+    ChargebackAmount {
+        client: u16,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        charged_back: Amount,
+        threshold: Amount,
+    },
+    BalanceThreshold {
+        client: u16,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        currency: String,
+        kind: ThresholdKind,
+        threshold: Amount,
+        balance: Amount,
+    },
+}
+
+// posts an alert to a webhook when an account is locked or crosses a chargeback or balance
+// threshold.
+// Alerts are posted, with retries and backoff, by a thread of their own, so a slow or unreachable
+// webhook doesn't hold up processing. An alert that can't be delivered, or doesn't fit the queue,
+// is logged rather than failing the run, as the state change behind it has been applied
+// regardless. With an outbox, such alerts are kept there and posted again by the next run.
+// Dropping the sink waits for the queued alerts to be posted, without retries
+pub struct WebhookSink {
+    thresholds: Thresholds,
+    // chargebacks per client
+    chargebacks: HashMap<u16, u32>,
+    // amount charged back per client and currency
+    charged_back: HashMap<(u16, String), Amount>,
+    // alerts for the delivery thread; `None` once the sink is dropped
+    queue: Option<SyncSender<Queued>>,
+    outbox: Option<Arc<Outbox>>,
+    // set once the sink is dropped, so the thread stops retrying
+    closing: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl WebhookSink {
+    // a sink recording its alerts in the outbox at `outbox`, if any, which first queues the
+    // alerts an earlier run left undelivered there
+    pub fn new(url: &str, thresholds: Thresholds, outbox: Option<&Path>) -> Result<Self> {
+        Self::with_backoff(url, thresholds, outbox, RETRY_BACKOFF)
+    }
+
+    fn with_backoff(
+        url: &str,
+        thresholds: Thresholds,
+        outbox: Option<&Path>,
+        backoff: Duration,
+    ) -> Result<Self> {
+        let (outbox, undelivered) = match outbox {
+            Some(path) => {
+                let (outbox, undelivered) = Outbox::open(path)?;
+                (Some(Arc::new(outbox)), undelivered)
+            }
+            None => (None, Vec::new()),
+        };
+        let (queue, alerts) = mpsc::sync_channel(QUEUE_CAPACITY);
+        for (id, alert) in undelivered {
+            // the rest stay in the outbox for the run after
+            if queue.try_send(Queued::new(Some(id), &alert)).is_err() {
+                tracing::warn!("webhook queue is full, leaving undelivered alerts in the outbox");
+                break;
+            }
+        }
+        let closing = Arc::new(AtomicBool::new(false));
+        let delivery = Delivery {
+            url: url.to_string(),
+            agent: ureq::Agent::config_builder()
+                .timeout_global(Some(REQUEST_TIMEOUT))
+                .build()
+                .into(),
+            backoff,
+            closing: closing.clone(),
+            outbox: outbox.clone(),
+        };
+        let worker = std::thread::Builder::new()
+            .name("webhook".to_string())
+            .spawn(move || delivery.run(alerts))?;
+
+        Ok(WebhookSink {
+            thresholds,
+            chargebacks: HashMap::new(),
+            charged_back: HashMap::new(),
+            queue: Some(queue),
+            outbox,
+            closing,
+            worker: Some(worker),
+        })
+    }
+
+    // the alerts `event` raises. A threshold alerts only on the event that reaches it, so each
+    // is sent once per client (and currency)
+    fn alerts(&mut self, event: &Event) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        match event {
+            Event::ChargebackApplied {
+                client,
+                currency,
+                amount,
+                ..
+            } => {
+                let count = self.chargebacks.entry(*client).or_default();
+                *count = count.saturating_add(1);
+                if self.thresholds.chargebacks == Some(*count) {
+                    alerts.push(Alert::ChargebackCount {
+                        client: *client,
+                        chargebacks: *count,
+                        threshold: *count,
+                    });
+                }
+                let total = self
+                    .charged_back
+                    .entry((*client, currency.clone()))
+                    .or_default();
+                let before = *total;
+                // past any threshold by then, so capping it changes no alert
+                *total = total.checked_add(*amount).unwrap_or(Amount::MAX);
+                if let Some(threshold) = self.thresholds.charged_back
+                    && before < threshold
+                    && *total >= threshold
+                {
+                    alerts.push(Alert::ChargebackAmount {
+                        client: *client,
+                        currency: currency.clone(),
+                        charged_back: *total,
+                        threshold,
+                    });
+                }
+            }
+            Event::AccountLocked { client, reason } => alerts.push(Alert::AccountLocked {
+                client: *client,
+                reason: reason.clone(),
+                chargebacks: self.chargebacks.get(client).copied().unwrap_or_default(),
+            }),
+            Event::BalanceThresholdCrossed {
+                client,
+                currency,
+                kind,
+                threshold,
+                balance,
+                ..
+            } => alerts.push(Alert::BalanceThreshold {
+                client: *client,
+                currency: currency.clone(),
+                kind: *kind,
+                threshold: *threshold,
+                balance: *balance,
+            }),
+            _ => {}
+        }
+        alerts
+    }
+}
+
+impl EventSink for WebhookSink {
+    fn emit(&mut self, event: &Event) -> Result<()> {
+        let alerts = self.alerts(event);
+        let Some(queue) = &self.queue else {
+            return Ok(());
+        };
+        for alert in alerts {
+            let id = match &self.outbox {
+                Some(outbox) => Some(outbox.push(&alert)?),
+                None => None,
+            };
+            let dropped = match queue.try_send(Queued::new(id, &alert)) {
+                Ok(()) => continue,
+                Err(TrySendError::Full(queued)) => (queued, "webhook queue is full"),
+                Err(TrySendError::Disconnected(queued)) => (queued, "webhook delivery has stopped"),
+            };
+            match dropped {
+                (Queued { id: Some(_), body }, why) => {
+                    tracing::warn!(alert = %body, "{}, leaving alert in the outbox", why);
+                }
+                (Queued { id: None, body }, why) => {
+                    tracing::error!(alert = %body, "{}, dropping alert", why);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for WebhookSink {
+    fn drop(&mut self) {
+        self.closing.store(true, Ordering::Relaxed);
+        // closing the queue ends the thread once it has gone through what's left
+        self.queue = None;
+        if let Some(worker) = self.worker.take()
+            && worker.join().is_err()
+        {
+            tracing::error!("webhook delivery thread panicked");
+        }
+    }
+}
+
+// an alert for the delivery thread, as JSON, with its id in the outbox if there is one
+struct Queued {
+    id: Option<u64>,
+    body: String,
+}
+
+impl Queued {
+    fn new(id: Option<u64>, alert: &Alert) -> Self {
+        Queued {
+            id,
+            body: serde_json::to_string(alert).expect("alerts serialize to JSON"),
+        }
+    }
+}
+
+// what the delivery thread posts alerts with
+struct Delivery {
+    url: String,
+    agent: ureq::Agent,
+    backoff: Duration,
+    closing: Arc<AtomicBool>,
+    outbox: Option<Arc<Outbox>>,
+}
+
+impl Delivery {
+    fn run(self, alerts: Receiver<Queued>) {
+        for Queued { id, body } in alerts {
+            let entry = match self.deliver(&body) {
+                Ok(attempts) => id.map(|id| OutboxEntry::Delivered { id, attempts }),
+                Err((attempts, error)) => id.map(|id| OutboxEntry::Failed {
+                    id,
+                    attempts,
+                    error,
+                }),
+            };
+            if let (Some(outbox), Some(entry)) = (&self.outbox, entry)
+                && let Err(e) = outbox.record(&entry)
+            {
+                tracing::error!(error = %e, "failed to record webhook delivery in the outbox");
+            }
+        }
+    }
+
+    // posts `body`, returning the attempts it took, or the attempts made and the last error
+    fn deliver(&self, body: &str) -> std::result::Result<u32, (u32, String)> {
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .agent
+                .post(&self.url)
+                .content_type("application/json")
+                .send(body);
+            match result {
+                Ok(_) => return Ok(attempt),
+                Err(e) if attempt == MAX_ATTEMPTS || self.closing.load(Ordering::Relaxed) => {
+                    tracing::error!(error = %e, alert = %body, "webhook delivery failed, giving up");
+                    return Err((attempt, e.to_string()));
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, attempt, "webhook delivery failed, retrying");
+                    std::thread::sleep(self.backoff * 2u32.pow(attempt - 1));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+// a line of the outbox: an alert as it is queued, or how a delivery of it ended
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum OutboxEntry {
+    Pending {
+        id: u64,
+        alert: Alert,
+    },
+    Delivered {
+        id: u64,
+        attempts: u32,
+    },
+    Failed {
+        id: u64,
+        attempts: u32,
+        error: String,
+    },
+}
+
+// the alerts a sink queues and how their deliveries went, as JSON lines appended to a file, so
+// an alert without a `delivered` line--the webhook was down, the queue was full or the run
+// ended first--is posted again by the next run rather than lost
+struct Outbox {
+    file: File,
+    next_id: AtomicU64,
+}
+
+impl Outbox {
+    // opens (or creates) the outbox at `path`, along with its undelivered alerts in the order
+    // they were queued
+    fn open(path: &Path) -> Result<(Self, Vec<(u64, Alert)>)> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        // a crash mid-append can leave a partial last line; drop it so appends start on a line of
+        // their own
+        let keep = contents.rfind('\n').map_or(0, |pos| pos + 1);
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        if keep < contents.len() {
+            file.set_len(keep as u64)?;
+        }
+
+        let mut undelivered = BTreeMap::new();
+        let mut next_id = 0;
+        for (idx, line) in contents[..keep].lines().enumerate() {
+            let entry = serde_json::from_str(line).map_err(|e| {
+                Error::EventError(format!(
+                    "corrupt webhook outbox entry on line {}: {}",
+                    idx + 1,
+                    e
+                ))
+            })?;
+            match entry {
+                OutboxEntry::Pending { id, alert } => {
+                    next_id = next_id.max(id + 1);
+                    undelivered.insert(id, alert);
+                }
+                OutboxEntry::Delivered { id, .. } => {
+                    undelivered.remove(&id);
+                }
+                OutboxEntry::Failed { .. } => {}
+            }
+        }
+
+        Ok((
+            Outbox {
+                file,
+                next_id: next_id.into(),
+            },
+            undelivered.into_iter().collect(),
+        ))
+    }
+
+    // records `alert` as pending, returning its id
+    fn push(&self, alert: &Alert) -> Result<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.record(&OutboxEntry::Pending {
+            id,
+            alert: alert.clone(),
+        })?;
+
+        Ok(id)
+    }
+
+    // each entry is a single write to the file opened for appending, so the sink and the delivery
+    // thread can record entries at once without interleaving them
+    fn record(&self, entry: &OutboxEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry).expect("outbox entries serialize to JSON");
+        line.push(b'\n');
+        (&self.file)
+            .write_all(&line)
+            .map_err(|e| Error::EventError(format!("failed to write webhook outbox: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+    use payments_engine::{TransactionType, amount};
+
+    fn chargeback(client: u16, tx: u32, amount: Amount) -> Event {
+        Event::ChargebackApplied {
+            client,
+            tx,
+            tx_type: TransactionType::Deposit,
+            currency: String::new(),
+            amount,
+        }
+    }
+
+    // the body of the HTTP request on `stream`, going by its content-length
+    fn read_body(stream: &mut impl Read) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        line.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(str::to_string)
+                    })
+                    .and_then(|length| length.trim().parse().ok())
+                    .unwrap_or(0);
+                if body.len() >= length || n == 0 {
+                    return body.to_string();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_alerts() {
+        let mut sink = WebhookSink::new(
+            "http://localhost",
+            Thresholds {
+                chargebacks: Some(2),
+                charged_back: Some(amount!(15)),
+            },
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(sink.alerts(&chargeback(1, 1, amount!(10))), []);
+        assert_eq!(
+            sink.alerts(&Event::AccountLocked {
+                client: 1,
+                reason: "chargeback of tx 1".to_string(),
+            }),
+            [Alert::AccountLocked {
+                client: 1,
+                reason: "chargeback of tx 1".to_string(),
+                chargebacks: 1,
+            }]
+        );
+        assert_eq!(
+            sink.alerts(&chargeback(1, 2, amount!(10))),
+            [
+                Alert::ChargebackCount {
+                    client: 1,
+                    chargebacks: 2,
+                    threshold: 2,
+                },
+                Alert::ChargebackAmount {
+                    client: 1,
+                    currency: String::new(),
+                    charged_back: amount!(20),
+                    threshold: amount!(15),
+                },
+            ]
+        );
+        // already past both thresholds, and other clients count separately
+        assert_eq!(sink.alerts(&chargeback(1, 3, amount!(10))), []);
+        assert_eq!(sink.alerts(&chargeback(2, 4, amount!(10))), []);
+    }
+
+    #[test]
+    fn test_alerts_total_saturates() {
+        let mut sink = WebhookSink::new(
+            "http://localhost",
+            Thresholds {
+                chargebacks: None,
+                charged_back: Some(Amount::MAX),
+            },
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(sink.alerts(&chargeback(1, 1, amount!(10))), []);
+        assert_eq!(
+            sink.alerts(&chargeback(1, 2, Amount::MAX)),
+            [Alert::ChargebackAmount {
+                client: 1,
+                currency: String::new(),
+                charged_back: Amount::MAX,
+                threshold: Amount::MAX,
+            }]
+        );
+        assert_eq!(sink.alerts(&chargeback(1, 3, amount!(10))), []);
+    }
+
+    #[test]
+    fn test_emit_doesnt_wait_for_delivery() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let (respond, respond_now) = mpsc::channel::<()>();
+        // only answers once emit has returned
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let body = read_body(&mut stream);
+            respond_now.recv().unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 204 No Content\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+            )
+            .unwrap();
+            body
+        });
+        let mut sink = WebhookSink::new(&url, Thresholds::default(), None).unwrap();
+
+        sink.emit(&Event::AccountLocked {
+            client: 7,
+            reason: "chargeback of tx 9".to_string(),
+        })
+        .unwrap();
+        respond.send(()).unwrap();
+        // waits for the queued alert to be delivered
+        drop(sink);
+
+        assert!(server.join().unwrap().contains(r#""client":7"#));
+    }
+
+    #[test]
+    fn test_deliver_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        // fail the first post, accept the second
+        let server = std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for status in ["500 Internal Server Error", "204 No Content"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                bodies.push(read_body(&mut stream));
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+            bodies
+        });
+        let mut sink =
+            WebhookSink::with_backoff(&url, Thresholds::default(), None, Duration::from_millis(1))
+                .unwrap();
+
+        sink.emit(&Event::AccountLocked {
+            client: 7,
+            reason: "chargeback of tx 9".to_string(),
+        })
+        .unwrap();
+
+        let expected = r#"{"alert":"account_locked","client":7,"reason":"chargeback of tx 9","chargebacks":0}"#;
+        assert_eq!(server.join().unwrap(), [expected, expected]);
+    }
+
+    #[test]
+    fn test_outbox_open_returns_undelivered() {
+        let path = std::env::temp_dir().join(format!(
+            "payments-engine-outbox-{}.jsonl",
+            std::process::id()
+        ));
+        let locked = |client| Alert::AccountLocked {
+            client,
+            reason: "chargeback of tx 1".to_string(),
+            chargebacks: 1,
+        };
+        let (outbox, undelivered) = Outbox::open(&path).unwrap();
+        assert_eq!(undelivered, []);
+        for client in 1..=3 {
+            outbox.push(&locked(client)).unwrap();
+        }
+        outbox
+            .record(&OutboxEntry::Delivered { id: 0, attempts: 1 })
+            .unwrap();
+        outbox
+            .record(&OutboxEntry::Failed {
+                id: 1,
+                attempts: 5,
+                error: "connection refused".to_string(),
+            })
+            .unwrap();
+        drop(outbox);
+        // torn by a crash mid-append
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(br#"{"status":"deliv"#)
+            .unwrap();
+
+        let (outbox, undelivered) = Outbox::open(&path).unwrap();
+
+        assert_eq!(undelivered, [(1, locked(2)), (2, locked(3))]);
+        assert_eq!(outbox.push(&locked(4)).unwrap(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_outbox_records_delivery() {
+        let path = std::env::temp_dir().join(format!(
+            "payments-engine-outbox-delivery-{}.jsonl",
+            std::process::id()
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let body = read_body(&mut stream);
+            write!(
+                stream,
+                "HTTP/1.1 204 No Content\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+            )
+            .unwrap();
+            body
+        });
+        // left undelivered by an earlier run
+        let alert = Alert::AccountLocked {
+            client: 7,
+            reason: "chargeback of tx 9".to_string(),
+            chargebacks: 1,
+        };
+        let (outbox, _) = Outbox::open(&path).unwrap();
+        outbox.push(&alert).unwrap();
+        drop(outbox);
+
+        let sink = WebhookSink::new(&url, Thresholds::default(), Some(&path)).unwrap();
+        drop(sink);
+
+        assert!(server.join().unwrap().contains(r#""client":7"#));
+        let (_, undelivered) = Outbox::open(&path).unwrap();
+        assert_eq!(undelivered, []);
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .ends_with("{\"status\":\"delivered\",\"id\":0,\"attempts\":1}\n")
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+//! Runs the CLI over the fixtures in `tests/fixtures/` to check the options that carry state from
+//! one run to the next (seeding, checkpoints, the write-ahead log and the list of processed
+//! inputs), the holds `--as-of` releases and when `--events -` may share stdout.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+// a fresh directory for a test's own files
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("payments-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(args: &[&Path]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+// the account rows a successful run wrote, in client order, as accounts come out in no particular
+// order
+fn accounts(output: &Output) -> Vec<String> {
+    assert!(
+        output.status.success(),
+        "run failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mut lines: Vec<String> = String::from_utf8(output.stdout.clone())
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(str::to_owned)
+        .collect();
+    lines.sort();
+    lines
+}
+
+#[test]
+fn test_accounts_in_seeds_opening_balances() {
+    let dir = scratch("seed");
+    let seed = dir.join("accounts.csv");
+    fs::write(&seed, run(&[&fixture("txs-clean.csv")]).stdout).unwrap();
+
+    let seeded = run(&[Path::new("--accounts-in"), &seed, &fixture("txs-more.csv")]);
+
+    assert_eq!(
+        accounts(&seeded),
+        [
+            "1,260.2222,0.0000,260.2222,false,active",
+            "2,170.1111,0.0000,170.1111,true,locked",
+            "3,0.0000,0.0000,0.0000,false,active",
+            "4,40.0000,0.0000,40.0000,false,active",
+        ]
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_resume_passes_over_inputs_read_to_the_end() {
+    let dir = scratch("resume-done");
+    let checkpoint = dir.join("checkpoint.json");
+    let (clean, more) = (fixture("txs-clean.csv"), fixture("txs-more.csv"));
+    let first = run(&[Path::new("--checkpoint"), &checkpoint, &clean]);
+    assert!(first.status.success());
+
+    // the inputs come in a different order the second time
+    let resumed = run(&[
+        Path::new("--checkpoint"),
+        &checkpoint,
+        Path::new("--resume"),
+        &more,
+        &clean,
+    ]);
+
+    assert_eq!(accounts(&resumed), accounts(&run(&[&clean, &more])));
+    assert!(!String::from_utf8_lossy(&resumed.stderr).contains("code=duplicate"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_resume_picks_up_part_way_through_an_input() {
+    let dir = scratch("resume-part");
+    let (input, checkpoint) = (dir.join("in.csv"), dir.join("checkpoint.json"));
+    let full = fs::read_to_string(fixture("txs-clean.csv")).unwrap();
+    // the header and the first six rows
+    let read: String = full.split_inclusive('\n').take(7).collect();
+    fs::write(&input, &read).unwrap();
+    let first = run(&[Path::new("--checkpoint"), &checkpoint, &input]);
+    assert!(first.status.success());
+    // leave the checkpoint as a run interrupted after those rows of the whole input would
+    fs::write(&input, &full).unwrap();
+    let saved = fs::read_to_string(&checkpoint).unwrap();
+    let (position, state) = saved.split_once('\n').unwrap();
+    let mut position: serde_json::Value = serde_json::from_str(position).unwrap();
+    position["inputs"][input.display().to_string()] = serde_json::json!({
+        "records": 6,
+        "byte": read.len(),
+        "line": 8,
+        "done": false,
+    });
+    fs::write(&checkpoint, format!("{position}\n{state}")).unwrap();
+
+    let resumed = run(&[
+        Path::new("--checkpoint"),
+        &checkpoint,
+        Path::new("--resume"),
+        &input,
+    ]);
+
+    assert_eq!(
+        accounts(&resumed),
+        accounts(&run(&[&fixture("txs-clean.csv")]))
+    );
+    assert!(!String::from_utf8_lossy(&resumed.stderr).contains("code=duplicate"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_wal_dir_carries_state_over_with_checkpoints() {
+    let dir = scratch("wal");
+    let (wal, checkpoint) = (dir.join("wal"), dir.join("checkpoint.json"));
+    let (clean, more) = (fixture("txs-clean.csv"), fixture("txs-more.csv"));
+    let options = [
+        Path::new("--wal-dir"),
+        &wal,
+        Path::new("--checkpoint"),
+        &checkpoint,
+    ];
+    let first = run(&[&options[..], &[clean.as_path()]].concat());
+    assert!(first.status.success());
+
+    let second = run(&[&options[..], &[more.as_path()]].concat());
+
+    assert_eq!(accounts(&second), accounts(&run(&[&clean, &more])));
+    assert!(checkpoint.exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_processed_passes_over_inputs_seen_before() {
+    let dir = scratch("processed");
+    let (wal, processed) = (dir.join("wal"), dir.join("processed.csv"));
+    let (clean, more) = (fixture("txs-clean.csv"), fixture("txs-more.csv"));
+    let options = [
+        Path::new("--wal-dir"),
+        &wal,
+        Path::new("--processed"),
+        &processed,
+    ];
+    let first = run(&[&options[..], &[clean.as_path()]].concat());
+    assert!(first.status.success());
+
+    let refused = run(&[
+        &options[..],
+        &[Path::new("--reprocess"), Path::new("refuse"), &clean],
+    ]
+    .concat());
+    let second = run(&[&options[..], &[clean.as_path(), more.as_path()]].concat());
+
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&second.stderr).contains("skipping input processed before"));
+    assert_eq!(accounts(&second), accounts(&run(&[&clean, &more])));
+    assert_eq!(fs::read_to_string(&processed).unwrap().lines().count(), 3);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_as_of_releases_expired_holds() {
+    let holds = fixture("txs-holds.csv");
+    let expiry = [Path::new("--hold-expiry"), Path::new("7")];
+
+    let open = run(&[&expiry[..], &[holds.as_path()]].concat());
+    let released = run(&[
+        &expiry[..],
+        &[Path::new("--as-of"), Path::new("604800"), &holds],
+    ]
+    .concat());
+
+    assert_eq!(accounts(&open), ["1,70.0000,30.0000,100.0000,false,active"]);
+    // only the hold authorized on day 0 has expired by day 7
+    assert_eq!(
+        accounts(&released),
+        ["1,80.0000,20.0000,100.0000,false,active"]
+    );
+}
+
+#[test]
+fn test_as_of_failure_invalid_timestamp() {
+    let output = run(&[
+        Path::new("--hold-expiry"),
+        Path::new("7"),
+        Path::new("--as-of"),
+        Path::new("yesterday"),
+        &fixture("txs-holds.csv"),
+    ]);
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--as-of"));
+}
+
+#[test]
+fn test_events_to_stdout_needs_output() {
+    let dir = scratch("events");
+    let output = dir.join("accounts.csv");
+    let clean = fixture("txs-clean.csv");
+
+    let refused = run(&[Path::new("--events"), Path::new("-"), &clean]);
+    let events = run(&[
+        Path::new("--events"),
+        Path::new("-"),
+        Path::new("--output"),
+        &output,
+        &clean,
+    ]);
+
+    assert!(!refused.status.success());
+    assert!(refused.stdout.is_empty());
+    assert!(events.status.success());
+    let stdout = String::from_utf8(events.stdout).unwrap();
+    assert!(stdout.lines().all(|line| line.starts_with("{\"event\":")));
+    // the header and a row per client
+    assert_eq!(fs::read_to_string(&output).unwrap().lines().count(), 4);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(not(feature = "disk-store"))]
+#[test]
+fn test_tx_store_disk_failure_without_feature() {
+    let output = run(&[
+        Path::new("--tx-store"),
+        Path::new("disk"),
+        &fixture("txs-clean.csv"),
+    ]);
+
+    // refused as a configuration error, like the other options behind a feature
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("ConfigError"));
+}